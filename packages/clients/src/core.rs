@@ -0,0 +1,1166 @@
+//! Core types shared by Courier and its clients.
+//!
+//! These describe cached content (CAS keys, the hashes they're derived with,
+//! and the manifests of saved units) without anything needed to talk to
+//! Courier. They're available with default features disabled, for tooling
+//! that reads or produces them without needing an HTTP stack.
+//!
+//! Apart from [`KeyHasher`] implementing `std::io::Write` and errors being
+//! reported with `color-eyre`, these types only need `core` and `alloc`, so the
+//! crate isn't `no_std` yet.
+//!
+//! # Intrinsics
+//!
+//! This module attempts to push as much context as reasonably possible out of
+//! the shared layer, which means it makes heavy use of opaque values and
+//! generics. It also tries to use the semantically least broad data types
+//! possible.
+//!
+//! ## Data type broadness
+//!
+//! Collections or container types attempt to use the "least broad"
+//! implementation when reasonable. For example, a `HashSet` is "less broad"
+//! than a `Vec` because the latter implies ordering while the former does not.
+//! The concept of "ordering" is considered "more broad" than the concept of
+//! "equality", even though both require explicit opt-in, because equality
+//! can nearly always be derived: it's rarely a matter of business logic. In
+//! contrast, ordering is nearly always inherently dependant on business logic.
+//!
+//! However, sometimes this isn't possible: if the contents aren't able to be
+//! compared for equality or cannot be hashed, then obviously a `HashSet` won't
+//! work. It's also sometimes not _desired_ to imply that order doesn't matter.
+//!
+//! As such, if you see a `Vec` over a `HashSet` in this module, you can be sure
+//! that Courier treats order like it matters and therefore so should the
+//! client.
+//!
+//! ## Opaque values
+//!
+//! The types in this module make relatively heavy use of "opaque values", such
+//! as but not limited to `Key`, `DiskPath`, and `Fingerprint`. The intention
+//! with these types is to allow applications like Hurry to encode arbitrary
+//! types into these values while freeing Courier to treat them as opaque types.
+//!
+//! For example, Hurry might parse a `DiskPath` as a `QualifiedPath` or as a
+//! `TypedPath` depending on the data structure and use case involved, but
+//! collapsing both into the opaque value of `DiskPath` frees Courier from
+//! needing to know or care about the difference: it just stores and returns
+//! what Courier provides.
+
+use std::{cmp::Ordering, fmt::Display, str::FromStr};
+
+use bon::Builder;
+use color_eyre::eyre::{self, Context, bail, eyre};
+use derive_more::{Debug, Display};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::{instrument, trace};
+
+mod chunk;
+mod hash;
+
+pub use chunk::ChunkList;
+pub use hash::{HashAlgorithm, KeyHasher};
+
+/// Opaque value signifying a CAS key.
+///
+/// A key is the hash of its content. Keys hashed with BLAKE3 (the default) are
+/// the 32 byte hash; keys hashed with any other [`HashAlgorithm`] are the hash
+/// followed by a byte identifying the algorithm, so that keys from different
+/// algorithms can never be confused for one another.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+#[display("{}", self.to_hex())]
+pub struct Key(#[debug("{:?}", self.to_hex())] Vec<u8>);
+
+impl Key {
+    /// The length of the hash, which is the same for every algorithm.
+    const DIGEST_LEN: usize = 32;
+
+    /// View the key as a hex string.
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.0)
+    }
+
+    /// Attempt to parse the key from a hex string.
+    #[instrument(fields(hex = hex.as_ref()))]
+    pub fn from_hex(hex: impl AsRef<str>) -> color_eyre::Result<Self> {
+        let bytes = hex::decode(hex.as_ref()).context("decode hex")?;
+        let len = bytes.len();
+        trace!(?bytes, ?len, "decoded hex");
+        Self::from_bytes(bytes)
+    }
+
+    /// View the key as bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Parse a key from raw bytes (the inverse of `as_bytes`).
+    ///
+    /// This is used when deserializing keys from the database or other binary
+    /// formats.
+    pub fn from_bytes(bytes: impl AsRef<[u8]>) -> color_eyre::Result<Self> {
+        let bytes = bytes.as_ref();
+        match bytes.len() {
+            Self::DIGEST_LEN => {}
+            len if len == Self::DIGEST_LEN + 1 => {
+                let tag = bytes[Self::DIGEST_LEN];
+                if HashAlgorithm::from_tag(tag).is_none() {
+                    bail!("unknown hash algorithm tag: {tag}");
+                }
+            }
+            len => bail!(
+                "invalid hash length: expected {} or {} bytes, got {len}",
+                Self::DIGEST_LEN,
+                Self::DIGEST_LEN + 1
+            ),
+        }
+        Ok(Self(bytes.to_vec()))
+    }
+
+    /// Create a key from a blake3 hash.
+    pub fn from_blake3(hash: blake3::Hash) -> Self {
+        Self(hash.as_bytes().to_vec())
+    }
+
+    pub(crate) fn from_digest(algorithm: HashAlgorithm, digest: [u8; 32]) -> Self {
+        let mut bytes = digest.to_vec();
+        bytes.extend(algorithm.tag());
+        Self(bytes)
+    }
+
+    /// The algorithm that produced the key.
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.0
+            .get(Self::DIGEST_LEN)
+            .and_then(|tag| HashAlgorithm::from_tag(*tag))
+            .unwrap_or_default()
+    }
+
+    /// Hash the contents of a buffer to create a key.
+    ///
+    /// This computes the blake3 hash of the provided buffer and returns the
+    /// resulting key. Use this when you have file contents or other data
+    /// that you want to content-address. This is NOT for parsing keys that
+    /// are already in binary format: use `from_bytes` for that.
+    pub fn from_buffer(buffer: impl AsRef<[u8]>) -> Self {
+        Self::from_buffer_with(HashAlgorithm::Blake3, buffer)
+    }
+
+    /// Hash the contents of a buffer with the algorithm to create a key.
+    pub fn from_buffer_with(algorithm: HashAlgorithm, buffer: impl AsRef<[u8]>) -> Self {
+        let mut hasher = KeyHasher::new(algorithm);
+        hasher.update(buffer);
+        hasher.finalize()
+    }
+
+    /// Hash the contents of the iterator in order.
+    pub fn from_fields(fields: impl IntoIterator<Item = impl AsRef<[u8]>>) -> Self {
+        let mut hasher = KeyHasher::new(HashAlgorithm::Blake3);
+        for field in fields {
+            hasher.update(field);
+        }
+        hasher.finalize()
+    }
+
+    /// Check whether the content hashes to this key, using the algorithm that
+    /// produced the key.
+    pub fn verify(&self, content: impl AsRef<[u8]>) -> bool {
+        self.verify_with(self.algorithm(), content)
+    }
+
+    /// Check whether the content hashes to this key using the algorithm.
+    pub fn verify_with(&self, algorithm: HashAlgorithm, content: impl AsRef<[u8]>) -> bool {
+        Self::from_buffer_with(algorithm, content) == *self
+    }
+}
+
+impl From<&Key> for Key {
+    fn from(key: &Key) -> Self {
+        key.clone()
+    }
+}
+
+impl PartialEq<blake3::Hash> for Key {
+    fn eq(&self, other: &blake3::Hash) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<blake3::Hash> for &Key {
+    fn eq(&self, other: &blake3::Hash) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let hex = String::deserialize(deserializer)?;
+        Self::from_hex(&hex).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Opaque value signifying a path on disk.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Serialize, Deserialize)]
+#[display("{}", self.0)]
+pub struct DiskPath(String);
+
+impl DiskPath {
+    /// Create a new instance from a string.
+    pub fn new(path: impl Into<String>) -> Self {
+        Self(path.into())
+    }
+
+    /// View the underlying data as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<S: Into<String>> From<S> for DiskPath {
+    fn from(path: S) -> Self {
+        Self::new(path)
+    }
+}
+
+impl AsRef<str> for DiskPath {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&DiskPath> for DiskPath {
+    fn from(path: &DiskPath) -> Self {
+        path.clone()
+    }
+}
+
+/// Opaque value signifying a fingerprint that uniquely identifies a library.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Serialize, Deserialize)]
+#[display("{}", self.0)]
+pub struct Fingerprint(String);
+
+impl Fingerprint {
+    /// Create a new instance from a string.
+    pub fn new(path: impl Into<String>) -> Self {
+        Self(path.into())
+    }
+
+    /// View the underlying data as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<S: Into<String>> From<S> for Fingerprint {
+    fn from(path: S) -> Self {
+        Self::new(path)
+    }
+}
+
+impl AsRef<str> for Fingerprint {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&Fingerprint> for Fingerprint {
+    fn from(fingerprint: &Fingerprint) -> Self {
+        fingerprint.clone()
+    }
+}
+
+/// Opaque value signifying a unit hash that uniquely identifies a `SavedUnit`.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Serialize, Deserialize)]
+#[display("{}", self.0)]
+pub struct SavedUnitHash(String);
+
+impl SavedUnitHash {
+    /// Create a new instance from a string.
+    pub fn new(hash: impl Into<String>) -> Self {
+        Self(hash.into())
+    }
+
+    /// View the underlying data as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<S: Into<String>> From<S> for SavedUnitHash {
+    fn from(path: S) -> Self {
+        Self::new(path)
+    }
+}
+
+impl AsRef<str> for SavedUnitHash {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&SavedUnitHash> for SavedUnitHash {
+    fn from(hash: &SavedUnitHash) -> Self {
+        hash.clone()
+    }
+}
+
+/// The name of a cache scope, which isolates the units saved in it from the
+/// rest of the organization's cache.
+///
+/// The scope is mixed into the hash under which units are saved (see
+/// [`UnitHashVersion::derive_scoped`]), so builds only restore units saved in
+/// the same scope, and Courier records it alongside each saved unit so that a
+/// single scope can be reset. Builds without a scope share the organization's
+/// unscoped cache, as they did before scopes were introduced.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Serialize, Deserialize)]
+#[display("{}", self.0)]
+#[serde(try_from = "String", into = "String")]
+pub struct CacheScope(String);
+
+impl CacheScope {
+    /// The longest allowed scope name.
+    pub const MAX_LEN: usize = 64;
+
+    /// Create a new instance from a scope name.
+    ///
+    /// Names are made of ASCII letters, digits, and `-`, `_`, `.`, or `/`, so
+    /// that they can name a repository (`org/repo`) or a family of branches
+    /// (`release/1.x`) and still be used in URLs.
+    pub fn new(name: impl Into<String>) -> Result<Self, eyre::Report> {
+        let name = name.into();
+        if name.is_empty() {
+            bail!("cache scope is empty");
+        }
+        if name.len() > Self::MAX_LEN {
+            bail!(
+                "cache scope is longer than {} characters: {name:?}",
+                Self::MAX_LEN
+            );
+        }
+        if let Some(c) = name
+            .chars()
+            .find(|c| !c.is_ascii_alphanumeric() && !matches!(c, '-' | '_' | '.' | '/'))
+        {
+            bail!("cache scope contains invalid character {c:?}: {name:?}");
+        }
+        Ok(Self(name))
+    }
+
+    /// View the scope name as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for CacheScope {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<String> for CacheScope {
+    type Error = eyre::Report;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        Self::new(name)
+    }
+}
+
+impl From<CacheScope> for String {
+    fn from(scope: CacheScope) -> Self {
+        scope.0
+    }
+}
+
+impl AsRef<str> for CacheScope {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The version of the algorithm used to derive the `SavedUnitHash` under which
+/// a unit is saved.
+///
+/// Changing how saved unit hashes are derived would otherwise invalidate every
+/// unit saved by previous versions of Hurry. Instead, the algorithm is
+/// versioned explicitly: units are saved using `CURRENT`, Courier records the
+/// version alongside each saved unit, and clients request units under every
+/// version in `RESTORABLE` so that units saved by older clients remain usable
+/// for a deprecation window.
+///
+/// Versions are serialized as integers so that they can be stored and compared
+/// cheaply.
+#[derive(
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Debug,
+    Display,
+    Default,
+    Serialize,
+    Deserialize,
+)]
+#[serde(into = "i32", try_from = "i32")]
+pub enum UnitHashVersion {
+    /// The Cargo unit hash, used verbatim.
+    ///
+    /// This is the default because it is what all clients that predate
+    /// versioning used, so requests that don't specify a version use it.
+    #[default]
+    #[display("v1")]
+    V1 = 1,
+
+    /// A blake3 hash over a domain separator, the Cargo unit hash, and the
+    /// identifying fields of the unit.
+    ///
+    /// Cargo unit hashes are only 64 bits and are shared across every project
+    /// in an organization, so mixing in the package name, crate name, and
+    /// target makes accidental collisions between unrelated units far less
+    /// likely. The domain separator means that any future version can never
+    /// produce a hash that collides with this one.
+    #[display("v2")]
+    V2 = 2,
+}
+
+impl UnitHashVersion {
+    /// The version used when saving units.
+    pub const CURRENT: Self = Self::V2;
+
+    /// The versions requested when restoring units, in order of preference.
+    ///
+    /// When a version is deprecated, it stays in this list until the
+    /// deprecation window has passed so that units saved with it can still be
+    /// restored.
+    pub const RESTORABLE: [Self; 2] = [Self::V2, Self::V1];
+
+    /// View the version as its integer representation.
+    pub const fn as_i32(self) -> i32 {
+        self as i32
+    }
+
+    /// The versions requested when restoring units in the given cache
+    /// generation, in order of preference.
+    ///
+    /// Versions that can't include the generation in their hashes are only
+    /// restorable in the initial generation, since otherwise bumping the
+    /// generation wouldn't invalidate the units saved with them.
+    pub fn restorable_in(generation: u64) -> impl Iterator<Item = Self> {
+        Self::RESTORABLE
+            .into_iter()
+            .filter(move |version| generation == 0 || version.includes_generation())
+    }
+
+    /// The versions requested when restoring the unit described by `info` in
+    /// the given cache generation, in order of preference.
+    ///
+    /// Units with a source hash are only restorable with versions that
+    /// include it, since otherwise units built from different sources would
+    /// be indistinguishable.
+    pub fn restorable_for(info: &UnitPlanInfo, generation: u64) -> impl Iterator<Item = Self> {
+        Self::restorable_scoped(info, generation, None)
+    }
+
+    /// The versions requested when restoring the unit described by `info` in
+    /// the given cache generation and scope, in order of preference.
+    ///
+    /// Scoped units are only restorable with versions that include the scope,
+    /// since otherwise scopes wouldn't isolate anything.
+    pub fn restorable_scoped(
+        info: &UnitPlanInfo,
+        generation: u64,
+        scope: Option<&CacheScope>,
+    ) -> impl Iterator<Item = Self> {
+        let first_party = info.source_hash.is_some();
+        let scoped = scope.is_some();
+        Self::restorable_in(generation)
+            .filter(move |version| !first_party || version.includes_source_hash())
+            .filter(move |version| !scoped || version.includes_scope())
+    }
+
+    /// Whether hashes derived with this version depend on the cache
+    /// generation.
+    pub const fn includes_generation(self) -> bool {
+        match self {
+            UnitHashVersion::V1 => false,
+            UnitHashVersion::V2 => true,
+        }
+    }
+
+    /// Whether hashes derived with this version depend on the unit's source
+    /// hash.
+    pub const fn includes_source_hash(self) -> bool {
+        match self {
+            UnitHashVersion::V1 => false,
+            UnitHashVersion::V2 => true,
+        }
+    }
+
+    /// Whether hashes derived with this version depend on the cache scope.
+    pub const fn includes_scope(self) -> bool {
+        match self {
+            UnitHashVersion::V1 => false,
+            UnitHashVersion::V2 => true,
+        }
+    }
+
+    /// Derive the hash under which the unit described by `info` is saved in
+    /// the initial cache generation.
+    pub fn derive(self, info: &UnitPlanInfo) -> SavedUnitHash {
+        self.derive_in(info, 0)
+    }
+
+    /// Derive the hash under which the unit described by `info` is saved in
+    /// the given cache generation.
+    ///
+    /// The initial generation (zero) adds nothing to the hash, so that units
+    /// saved before generations were introduced remain restorable until the
+    /// generation is first bumped. Versions that don't include the generation
+    /// (see [`UnitHashVersion::includes_generation`]) ignore it. Likewise,
+    /// units without a source hash hash the same as before source hashes were
+    /// introduced.
+    pub fn derive_in(self, info: &UnitPlanInfo, generation: u64) -> SavedUnitHash {
+        self.derive_scoped(info, generation, None)
+    }
+
+    /// Derive the hash under which the unit described by `info` is saved in
+    /// the given cache generation and scope.
+    ///
+    /// Units without a scope hash the same as before scopes were introduced,
+    /// and versions that don't include the scope (see
+    /// [`UnitHashVersion::includes_scope`]) ignore it.
+    pub fn derive_scoped(
+        self,
+        info: &UnitPlanInfo,
+        generation: u64,
+        scope: Option<&CacheScope>,
+    ) -> SavedUnitHash {
+        match self {
+            UnitHashVersion::V1 => info.unit_hash.clone(),
+            UnitHashVersion::V2 => {
+                let target_arch = info.target_arch.as_deref().unwrap_or_default();
+                let generation = (generation != 0).then(|| generation.to_string());
+                let mut fields = vec![
+                    "hurry-unit-hash-v2",
+                    info.unit_hash.as_str(),
+                    info.package_name.as_str(),
+                    info.crate_name.as_str(),
+                    target_arch,
+                ];
+                fields.extend(generation.as_deref());
+                if let Some(scope) = scope {
+                    fields.extend(["scope", scope.as_str()]);
+                }
+                if let Some(source_hash) = &info.source_hash {
+                    fields.extend(["source", source_hash.as_str()]);
+                }
+
+                // Length-prefix each field so that moving bytes between
+                // adjacent fields can't produce the same hash.
+                //
+                // Unit hashes are identifiers rather than content keys, so they
+                // are always BLAKE3 regardless of the organization's
+                // configured algorithm: otherwise switching algorithms would
+                // make every saved unit unrestorable.
+                let mut hasher = KeyHasher::new(HashAlgorithm::Blake3);
+                for field in fields {
+                    hasher.update((field.len() as u64).to_le_bytes());
+                    hasher.update(field);
+                }
+                SavedUnitHash::new(hasher.finalize().to_hex())
+            }
+        }
+    }
+}
+
+impl From<UnitHashVersion> for i32 {
+    fn from(version: UnitHashVersion) -> Self {
+        version.as_i32()
+    }
+}
+
+impl TryFrom<i32> for UnitHashVersion {
+    type Error = eyre::Report;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(UnitHashVersion::V1),
+            2 => Ok(UnitHashVersion::V2),
+            _ => bail!("unknown unit hash version: {value}"),
+        }
+    }
+}
+
+/// Common metadata fields present in all unit plan types.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Builder)]
+#[non_exhaustive]
+pub struct UnitPlanInfo {
+    /// The directory hash of the unit, which is used to construct the unit's
+    /// file directories.
+    ///
+    /// See the `*_dir` methods on `CompilationFiles`[^1] for details.
+    ///
+    /// [^1]: https://github.com/attunehq/cargo/blob/7a93b36f1ae2f524d93efd16cd42864675f3e15b/src/cargo/core/compiler/build_runner/compilation_files.rs#L117
+    #[builder(into)]
+    pub unit_hash: SavedUnitHash,
+
+    /// The package name of this unit.
+    ///
+    /// This is used to reconstruct expected output directories. See the `*_dir`
+    /// methods on `CompilationFiles`[^1] for details.
+    ///
+    /// [^1]: https://github.com/attunehq/cargo/blob/7a93b36f1ae2f524d93efd16cd42864675f3e15b/src/cargo/core/compiler/build_runner/compilation_files.rs#L117
+    #[builder(into)]
+    pub package_name: String,
+
+    /// The crate name of this unit.
+    ///
+    /// Note that this is not necessarily the _extern_ crate name, which can be
+    /// affected by directives like `replace` and `patch`, and that the crate
+    /// name used in fingerprints is the extern crate name[^1], not the
+    /// canonical crate name.
+    ///
+    /// [^1]: https://github.com/attunehq/cargo/blob/7a93b36f1ae2f524d93efd16cd42864675f3e15b/src/cargo/core/compiler/fingerprint/mod.rs#L1366
+    // FIXME: To properly support `replace` and `patch` directives, we need to
+    // also calculate an extern_crate_name for each edge in the dependency
+    // graph. Note that this is a per-edge value, not a per-unit value. Perhaps
+    // we can derive this from the unit graph?
+    #[builder(into)]
+    pub crate_name: String,
+
+    /// The unit's target architecture, if set.
+    ///
+    /// When None, this unit is not being compiled with a specific `--target` in
+    /// mind, and therefore is being compiled for the host architecture.
+    ///
+    /// Note that some units (e.g. proc macros, build script compilations, and
+    /// dependencies thereof) are compiled for the host architecture even when
+    /// `--target` is set to a different architecture. This field already takes
+    /// that into account.
+    #[builder(into)]
+    pub target_arch: Option<String>,
+
+    /// A hash of the source files of the unit's package, for first-party
+    /// units.
+    ///
+    /// Cargo tracks changes to first-party sources by their modification
+    /// times rather than in the unit hash, so units built from different
+    /// sources can share a unit hash; the source hash tells them apart.
+    /// Third-party units are identified by their unit hash alone.
+    #[builder(into)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_hash: Option<String>,
+}
+
+impl From<&UnitPlanInfo> for UnitPlanInfo {
+    fn from(info: &UnitPlanInfo) -> Self {
+        info.clone()
+    }
+}
+
+/// A saved file in the cargo cache.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize, Builder)]
+#[non_exhaustive]
+pub struct SavedFile {
+    pub executable: bool,
+
+    #[builder(into)]
+    pub object_key: Key,
+
+    #[builder(into)]
+    pub path: DiskPath,
+}
+
+impl From<&SavedFile> for SavedFile {
+    fn from(file: &SavedFile) -> Self {
+        file.clone()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum SavedUnit {
+    LibraryCrate(LibraryFiles, LibraryCrateUnitPlan),
+    BuildScriptCompilation(BuildScriptCompiledFiles, BuildScriptCompilationUnitPlan),
+    BuildScriptExecution(BuildScriptOutputFiles, BuildScriptExecutionUnitPlan),
+}
+
+impl SavedUnit {
+    /// Read the common unit plan metadata from this saved unit.
+    pub fn info(&self) -> &UnitPlanInfo {
+        match self {
+            SavedUnit::LibraryCrate(_, plan) => &plan.info,
+            SavedUnit::BuildScriptCompilation(_, plan) => &plan.info,
+            SavedUnit::BuildScriptExecution(_, plan) => &plan.info,
+        }
+    }
+
+    /// Read the unit hash from this saved unit.
+    pub fn unit_hash(&self) -> &SavedUnitHash {
+        match self {
+            SavedUnit::LibraryCrate(_, plan) => &plan.info.unit_hash,
+            SavedUnit::BuildScriptCompilation(_, plan) => &plan.info.unit_hash,
+            SavedUnit::BuildScriptExecution(_, plan) => &plan.info.unit_hash,
+        }
+    }
+
+    /// Read the fingerprint from this saved unit.
+    pub fn fingerprint(&self) -> &Fingerprint {
+        match self {
+            SavedUnit::LibraryCrate(files, _) => &files.fingerprint,
+            SavedUnit::BuildScriptCompilation(files, _) => &files.fingerprint,
+            SavedUnit::BuildScriptExecution(files, _) => &files.fingerprint,
+        }
+    }
+
+    /// List the CAS keys of every file stored for this saved unit.
+    ///
+    /// Keys may repeat if the unit has multiple files with the same contents.
+    pub fn object_keys(&self) -> Vec<&Key> {
+        match self {
+            SavedUnit::LibraryCrate(files, _) => files
+                .output_files
+                .iter()
+                .map(|file| &file.object_key)
+                .chain([&files.dep_info_file, &files.encoded_dep_info_file])
+                .collect(),
+            SavedUnit::BuildScriptCompilation(files, _) => vec![
+                &files.compiled_program,
+                &files.dep_info_file,
+                &files.encoded_dep_info_file,
+            ],
+            SavedUnit::BuildScriptExecution(files, _) => files
+                .out_dir_files
+                .iter()
+                .map(|file| &file.object_key)
+                .chain([&files.stdout, &files.stderr])
+                .collect(),
+        }
+    }
+}
+
+impl From<&SavedUnit> for SavedUnit {
+    fn from(unit: &SavedUnit) -> Self {
+        unit.clone()
+    }
+}
+
+/// Libraries are usually associated with 7 files:
+///
+/// - 2 output files (an `.rmeta` and an `.rlib`)
+/// - 1 rustc dep-info (`.d`) file in the `deps` folder
+/// - 4 files in the fingerprint directory
+///   - An `EncodedDepInfo` file
+///   - A fingerprint hash
+///   - A fingerprint JSON
+///   - An invoked timestamp
+///
+/// Of these files, the fingerprint hash, fingerprint JSON, and invoked
+/// timestamp are all reconstructed from fingerprint information during
+/// restoration.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Builder)]
+#[non_exhaustive]
+pub struct LibraryFiles {
+    /// These files come from the build plan's `outputs` field.
+    // TODO: Can we specify this even more narrowly (e.g. with an `rmeta` and
+    // `rlib` field)? I know there are other possible output files (e.g. `.so`
+    // for proc macros on Linux and `.dylib` for something on macOS), but I
+    // don't know what the enumerated list is.
+    pub output_files: Vec<SavedFile>,
+
+    /// This information is parsed from the initial fingerprint created after
+    /// the build, and is used to dynamically reconstruct fingerprints on
+    /// restoration.
+    pub fingerprint: Fingerprint,
+
+    /// This file is always at a known path in
+    /// `deps/{package_name}-{unit_hash}.d`.
+    pub dep_info_file: Key,
+
+    /// This file is always at a known path in
+    /// `.fingerprint/{package_name}-{unit_hash}/dep-lib-{crate_name}`. It can
+    /// be safely relocatably copied because the `EncodedDepInfo` struct only
+    /// ever contains relative file path information (note that deps always have
+    /// a `DepInfoPathType`, which is either `PackageRootRelative` or
+    /// `BuildRootRelative`)[^1].
+    ///
+    /// [^1]: https://github.com/rust-lang/cargo/blob/df07b394850b07348c918703054712e3427715cf/src/cargo/core/compiler/fingerprint/dep_info.rs#L112
+    pub encoded_dep_info_file: Key,
+}
+
+impl From<&LibraryFiles> for LibraryFiles {
+    fn from(files: &LibraryFiles) -> Self {
+        files.clone()
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Builder)]
+#[non_exhaustive]
+pub struct LibraryCrateUnitPlan {
+    /// Common metadata fields present in all unit plan variants.
+    #[serde(flatten)]
+    #[builder(into)]
+    pub info: UnitPlanInfo,
+
+    /// The path to the source file on disk.
+    #[builder(into)]
+    pub src_path: DiskPath,
+
+    /// The paths to output files on disk.
+    #[builder(into)]
+    pub outputs: Vec<DiskPath>,
+
+    /// The hash in the name of the unit's fingerprint directory, if it isn't
+    /// the unit hash. Cargo names the fingerprint directories of `cdylib`
+    /// and `dylib` workspace members after the package instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(into)]
+    pub fingerprint_hash: Option<String>,
+}
+
+impl From<&LibraryCrateUnitPlan> for LibraryCrateUnitPlan {
+    fn from(plan: &LibraryCrateUnitPlan) -> Self {
+        plan.clone()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Builder)]
+#[non_exhaustive]
+pub struct BuildScriptCompiledFiles {
+    /// This field contains the contents of the compiled build script program at
+    /// `build_script_{build_script_entrypoint}-{build_script_compilation_unit_hash}`
+    /// and hard linked at `build-script-{build_script_entrypoint}`.
+    ///
+    /// We need both of these files: the hard link is the file that's actually
+    /// executed in the build plan, but the full path with the unit hash is the
+    /// file that's tracked by the fingerprint.
+    #[builder(into)]
+    pub compiled_program: Key,
+
+    /// The rustc dep-info file in the build directory.
+    #[builder(into)]
+    pub dep_info_file: Key,
+
+    /// This fingerprint is stored in `.fingerprint`, and is used to derive the
+    /// timestamp, fingerprint hash file, and fingerprint JSON file.
+    #[builder(into)]
+    pub fingerprint: Fingerprint,
+
+    /// This `EncodedDepInfo` (i.e. Cargo dep-info) file is stored in
+    /// `.fingerprint`, and is directly saved and restored.
+    #[builder(into)]
+    pub encoded_dep_info_file: Key,
+}
+
+impl From<&BuildScriptCompiledFiles> for BuildScriptCompiledFiles {
+    fn from(files: &BuildScriptCompiledFiles) -> Self {
+        files.clone()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Builder)]
+#[non_exhaustive]
+pub struct BuildScriptCompilationUnitPlan {
+    /// Common metadata fields present in all unit plan variants.
+    #[serde(flatten)]
+    #[builder(into)]
+    pub info: UnitPlanInfo,
+
+    /// The path to the build script's main entrypoint source file. This is
+    /// usually `build.rs` within the package's source code, but can vary if
+    /// the package author sets `package.build` in the package's
+    /// `Cargo.toml`, which changes the build script's name[^1].
+    ///
+    /// This is parsed from the rustc invocation arguments in the unit's
+    /// build plan invocation.
+    ///
+    /// This is used to rewrite the build script compilation's fingerprint
+    /// on restore.
+    ///
+    /// [^1]: https://doc.rust-lang.org/cargo/reference/manifest.html#the-build-field
+    #[builder(into)]
+    pub src_path: DiskPath,
+}
+
+impl From<&BuildScriptCompilationUnitPlan> for BuildScriptCompilationUnitPlan {
+    fn from(plan: &BuildScriptCompilationUnitPlan) -> Self {
+        plan.clone()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Builder)]
+#[non_exhaustive]
+pub struct BuildScriptOutputFiles {
+    #[builder(default, with = |i: impl IntoIterator<Item = impl Into<SavedFile>>| i.into_iter().map(Into::into).collect())]
+    pub out_dir_files: Vec<SavedFile>,
+
+    #[builder(into)]
+    pub stdout: Key,
+
+    #[builder(into)]
+    pub stderr: Key,
+
+    #[builder(into)]
+    pub fingerprint: Fingerprint,
+}
+
+impl From<&BuildScriptOutputFiles> for BuildScriptOutputFiles {
+    fn from(files: &BuildScriptOutputFiles) -> Self {
+        files.clone()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Builder)]
+#[non_exhaustive]
+pub struct BuildScriptExecutionUnitPlan {
+    /// Common metadata fields present in all unit plan variants.
+    #[serde(flatten)]
+    #[builder(into)]
+    pub info: UnitPlanInfo,
+
+    /// The entrypoint module name of the compiled build script program after
+    /// linkage (i.e. using the original build script name, which is what Cargo
+    /// uses to name the execution unit files).
+    #[builder(into)]
+    pub build_script_program_name: String,
+}
+
+impl From<&BuildScriptExecutionUnitPlan> for BuildScriptExecutionUnitPlan {
+    fn from(plan: &BuildScriptExecutionUnitPlan) -> Self {
+        plan.clone()
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct GlibcVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Display for GlibcVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for GlibcVersion {
+    type Err = eyre::Report;
+
+    // For reference, see the full list of glibc versions[^1].
+    //
+    // [^1]: https://sourceware.org/glibc/wiki/Glibc%20Timeline
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('.');
+        let major = parts
+            .next()
+            .ok_or(eyre!("could not parse major version"))?
+            .parse()?;
+        let minor = parts
+            .next()
+            .ok_or(eyre!("could not parse minor version"))?
+            .parse()?;
+        // Patch versions are optional, and default to zero for comparison
+        // purposes.
+        let patch = parts
+            .next()
+            .map(|s| {
+                s.parse::<u32>()
+                    .map_err(|e| eyre!("could not parse patch version: {e}"))
+            })
+            .unwrap_or(Ok(0))?;
+        // Make sure there are no remaining parts.
+        if parts.next().is_some() {
+            bail!("expected end of string");
+        }
+        Ok(Self {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+impl Ord for GlibcVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.major
+            .cmp(&other.major)
+            .then_with(|| self.minor.cmp(&other.minor))
+            .then_with(|| self.patch.cmp(&other.patch))
+    }
+}
+
+impl PartialOrd for GlibcVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    fn info(target_arch: Option<&str>) -> UnitPlanInfo {
+        UnitPlanInfo::builder()
+            .unit_hash("0123456789abcdef")
+            .package_name("serde")
+            .crate_name("serde")
+            .maybe_target_arch(target_arch.map(String::from))
+            .build()
+    }
+
+    // These hashes are pinned: if any of these tests fail, the derivation for
+    // an existing version has changed, which invalidates every unit saved with
+    // it. Add a new version instead.
+    #[test]
+    fn unit_hash_v1_is_cargo_unit_hash() {
+        let hash = UnitHashVersion::V1.derive(&info(None));
+        pretty_assert_eq!(hash, SavedUnitHash::from("0123456789abcdef"));
+    }
+
+    #[test]
+    fn unit_hash_v2_host() {
+        let hash = UnitHashVersion::V2.derive(&info(None));
+        pretty_assert_eq!(
+            hash,
+            SavedUnitHash::from("0adcfa6558a0e59b7f286e368cf285c6b79d2d2163aedad1f1ea2fc84c2438eb")
+        );
+    }
+
+    #[test]
+    fn unit_hash_v2_target() {
+        let hash = UnitHashVersion::V2.derive(&info(Some("x86_64-unknown-linux-gnu")));
+        pretty_assert_eq!(
+            hash,
+            SavedUnitHash::from("cb2ed27afb835ee97d3e81ea1f9cd066fc564f298cfa5497e0e6016a43ea6354")
+        );
+    }
+
+    #[test]
+    fn unit_hash_source_hash() {
+        let third_party = info(None);
+        let first_party = UnitPlanInfo {
+            source_hash: Some(String::from("a1b2c3")),
+            ..info(None)
+        };
+        let changed = UnitPlanInfo {
+            source_hash: Some(String::from("d4e5f6")),
+            ..info(None)
+        };
+        assert_ne!(
+            UnitHashVersion::V2.derive(&first_party),
+            UnitHashVersion::V2.derive(&third_party)
+        );
+        assert_ne!(
+            UnitHashVersion::V2.derive(&first_party),
+            UnitHashVersion::V2.derive(&changed)
+        );
+        pretty_assert_eq!(
+            UnitHashVersion::restorable_for(&first_party, 0).collect::<Vec<_>>(),
+            vec![UnitHashVersion::V2]
+        );
+        pretty_assert_eq!(
+            UnitHashVersion::restorable_for(&third_party, 0).collect::<Vec<_>>(),
+            UnitHashVersion::RESTORABLE.to_vec()
+        );
+    }
+
+    #[test]
+    fn unit_hash_generation() {
+        let info = info(None);
+        pretty_assert_eq!(
+            UnitHashVersion::V2.derive_in(&info, 0),
+            UnitHashVersion::V2.derive(&info)
+        );
+        assert_ne!(
+            UnitHashVersion::V2.derive_in(&info, 1),
+            UnitHashVersion::V2.derive(&info)
+        );
+        assert_ne!(
+            UnitHashVersion::V2.derive_in(&info, 1),
+            UnitHashVersion::V2.derive_in(&info, 2)
+        );
+    }
+
+    #[test]
+    fn unit_hash_scope() {
+        let info = info(None);
+        let scope = |name: &str| CacheScope::new(name).unwrap();
+        pretty_assert_eq!(
+            UnitHashVersion::V2.derive_scoped(&info, 0, None),
+            UnitHashVersion::V2.derive(&info)
+        );
+        assert_ne!(
+            UnitHashVersion::V2.derive_scoped(&info, 0, Some(&scope("acme/api"))),
+            UnitHashVersion::V2.derive(&info)
+        );
+        assert_ne!(
+            UnitHashVersion::V2.derive_scoped(&info, 0, Some(&scope("acme/api"))),
+            UnitHashVersion::V2.derive_scoped(&info, 0, Some(&scope("acme/web")))
+        );
+        pretty_assert_eq!(
+            UnitHashVersion::restorable_scoped(&info, 0, Some(&scope("acme/api")))
+                .collect::<Vec<_>>(),
+            vec![UnitHashVersion::V2]
+        );
+    }
+
+    #[test]
+    fn cache_scope_names() {
+        for name in ["acme", "acme/api", "release-1.x", "feature_flags"] {
+            pretty_assert_eq!(CacheScope::new(name).unwrap().as_str(), name);
+        }
+        for name in [
+            "",
+            "has space",
+            "tab\t",
+            "caf\u{e9}",
+            "a".repeat(65).as_str(),
+        ] {
+            assert!(CacheScope::new(name).is_err(), "{name:?} should be invalid");
+        }
+        let json = serde_json::to_string(&CacheScope::new("acme/api").unwrap()).unwrap();
+        pretty_assert_eq!(json, "\"acme/api\"");
+        assert!(serde_json::from_str::<CacheScope>("\"has space\"").is_err());
+    }
+
+    #[test]
+    fn unit_hash_restorable_in_generation() {
+        pretty_assert_eq!(
+            UnitHashVersion::restorable_in(0).collect::<Vec<_>>(),
+            UnitHashVersion::RESTORABLE.to_vec()
+        );
+        pretty_assert_eq!(
+            UnitHashVersion::restorable_in(1).collect::<Vec<_>>(),
+            vec![UnitHashVersion::V2]
+        );
+    }
+
+    #[test]
+    fn unit_hash_version_serialization() {
+        let json = serde_json::to_string(&UnitHashVersion::RESTORABLE).unwrap();
+        pretty_assert_eq!(json, "[2,1]");
+
+        let versions = serde_json::from_str::<Vec<UnitHashVersion>>(&json).unwrap();
+        pretty_assert_eq!(versions, UnitHashVersion::RESTORABLE.to_vec());
+
+        assert!(serde_json::from_str::<UnitHashVersion>("0").is_err());
+    }
+}
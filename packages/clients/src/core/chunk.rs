@@ -0,0 +1,157 @@
+//! Lists of the chunks that large objects are split into.
+//!
+//! Objects that differ only slightly (e.g. rlibs built with different feature
+//! sets) share most of their content. When they're split at content-defined
+//! boundaries, the shared content ends up in identical chunks, so a CAS that
+//! stores each object as a list of chunks stores the shared content once.
+//!
+//! A chunk list is an ordinary CAS object, stored under the key of its
+//! encoding; that key is what units reference instead of the key of the
+//! object's content. Readers tell chunk lists apart from other objects by
+//! [`ChunkList::MAGIC`].
+
+use color_eyre::eyre::{Context, bail};
+
+use super::Key;
+
+/// The chunks that an object is split into, in order.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[non_exhaustive]
+pub struct ChunkList {
+    /// The key of the object's content.
+    pub content: Key,
+
+    /// The size of the object's content, in bytes.
+    pub size: u64,
+
+    /// The keys of the chunks, in the order their content is concatenated.
+    /// A chunk that occurs more than once in the object is listed each time.
+    pub chunks: Vec<Key>,
+}
+
+impl ChunkList {
+    /// The bytes every encoded chunk list starts with.
+    pub const MAGIC: &[u8] = b"hurry-chunk-list-v1\n";
+
+    /// Create a list of the chunks of the object with the given content key.
+    pub fn new(content: Key, size: u64, chunks: Vec<Key>) -> Self {
+        Self {
+            content,
+            size,
+            chunks,
+        }
+    }
+
+    /// The key the list is stored under, hashed with the algorithm of the
+    /// object's content key.
+    pub fn key(&self) -> Key {
+        Key::from_buffer_with(self.content.algorithm(), self.encode())
+    }
+
+    /// Encode the list to be stored in the CAS.
+    ///
+    /// The encoding is the magic bytes, the size of the object as a
+    /// little-endian `u64`, and then the content key and each chunk key,
+    /// each prefixed with its length as a single byte.
+    pub fn encode(&self) -> Vec<u8> {
+        let keys = std::iter::once(&self.content).chain(&self.chunks);
+        let mut encoded = Vec::with_capacity(Self::MAGIC.len() + 8 + 34 * (self.chunks.len() + 1));
+        encoded.extend_from_slice(Self::MAGIC);
+        encoded.extend_from_slice(&self.size.to_le_bytes());
+        for key in keys {
+            let bytes = key.as_bytes();
+            encoded.push(bytes.len() as u8);
+            encoded.extend_from_slice(bytes);
+        }
+        encoded
+    }
+
+    /// Decode a CAS object as a chunk list.
+    ///
+    /// Returns `None` if the object isn't a chunk list.
+    pub fn decode(content: &[u8]) -> Option<Self> {
+        let rest = content.strip_prefix(Self::MAGIC)?;
+        let (size, mut rest) = rest.split_first_chunk::<8>()?;
+        let mut keys = Vec::new();
+        while let Some((&len, after)) = rest.split_first() {
+            let (key, after) = after.split_at_checked(usize::from(len))?;
+            keys.push(Key::from_bytes(key).ok()?);
+            rest = after;
+        }
+        let mut keys = keys.into_iter();
+        let content = keys.next()?;
+        Some(Self::new(
+            content,
+            u64::from_le_bytes(*size),
+            keys.collect(),
+        ))
+    }
+
+    /// Concatenate the content of the chunks, in the order they're listed,
+    /// into the content of the object.
+    ///
+    /// Errors if the result isn't the object's content.
+    pub fn assemble(
+        &self,
+        chunks: impl IntoIterator<Item = impl AsRef<[u8]>>,
+    ) -> color_eyre::Result<Vec<u8>> {
+        let size = usize::try_from(self.size).context("object size")?;
+        let mut content = Vec::with_capacity(size);
+        for chunk in chunks {
+            content.extend_from_slice(chunk.as_ref());
+        }
+        if content.len() != size || !self.content.verify(&content) {
+            bail!("chunks do not assemble into object {}", self.content);
+        }
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::ChunkList;
+    use crate::core::{HashAlgorithm, Key};
+
+    fn list(algorithm: HashAlgorithm) -> (ChunkList, Vec<&'static [u8]>) {
+        let chunks: Vec<&[u8]> = vec![b"hello ", b"world", b"hello "];
+        let list = ChunkList::new(
+            Key::from_buffer_with(algorithm, b"hello worldhello "),
+            17,
+            chunks
+                .iter()
+                .map(|chunk| Key::from_buffer_with(algorithm, chunk))
+                .collect(),
+        );
+        (list, chunks)
+    }
+
+    #[test]
+    fn round_trips() {
+        for algorithm in HashAlgorithm::ALL {
+            let (list, _) = list(algorithm);
+            pretty_assert_eq!(ChunkList::decode(&list.encode()), Some(list.clone()));
+            pretty_assert_eq!(list.key().algorithm(), algorithm);
+        }
+    }
+
+    #[test]
+    fn other_objects_are_not_lists() {
+        assert!(ChunkList::decode(b"").is_none());
+        assert!(ChunkList::decode(b"hello world").is_none());
+        assert!(ChunkList::decode(ChunkList::MAGIC).is_none());
+
+        let (list, _) = list(HashAlgorithm::Blake3);
+        let encoded = list.encode();
+        assert!(ChunkList::decode(&encoded[..encoded.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn assembles() {
+        let (list, chunks) = list(HashAlgorithm::Blake3);
+        pretty_assert_eq!(list.assemble(&chunks).unwrap(), b"hello worldhello ");
+        assert!(list.assemble(&chunks[..2]).is_err());
+        assert!(list.assemble([b"hello worldhello!"]).is_err());
+    }
+}
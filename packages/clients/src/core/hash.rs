@@ -0,0 +1,211 @@
+//! Hash algorithms used to derive CAS keys.
+//!
+//! BLAKE3 is the default and what every Courier instance supports. SHA-256 is
+//! available for organizations whose compliance requirements mandate it.
+//!
+//! Keys record the algorithm that produced them, so a single CAS can hold
+//! content hashed with different algorithms at the same time: this is what
+//! allows an organization to switch algorithms without discarding its cache,
+//! since units saved before the switch reference keys under the old algorithm
+//! and remain restorable.
+
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+use super::Key;
+
+/// The algorithm used to hash content into a [`Key`].
+#[derive(
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Debug,
+    Display,
+    Default,
+    Serialize,
+    Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashAlgorithm {
+    #[default]
+    #[display("blake3")]
+    Blake3,
+
+    #[display("sha256")]
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Every supported algorithm.
+    pub const ALL: [Self; 2] = [Self::Blake3, Self::Sha256];
+
+    /// The byte appended to keys hashed with this algorithm.
+    ///
+    /// BLAKE3 keys have no tag so that keys created before algorithms were
+    /// configurable keep their meaning.
+    pub(crate) const fn tag(self) -> Option<u8> {
+        match self {
+            Self::Blake3 => None,
+            Self::Sha256 => Some(1),
+        }
+    }
+
+    /// The algorithm with the given tag.
+    pub(crate) const fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+}
+
+/// Incrementally hashes content into a [`Key`].
+///
+/// Besides plain hashing, the hasher supports BLAKE3's key derivation mode
+/// (with a length-prefixed context for SHA-256). That's for deriving
+/// identifiers rather than content keys: Courier verifies content against its
+/// key with a plain hash, so derived keys can't be used to store content.
+#[derive(Clone, Debug)]
+pub struct KeyHasher(Inner);
+
+#[derive(Clone, Debug)]
+enum Inner {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(Sha256),
+}
+
+impl KeyHasher {
+    /// Create a hasher for plain content hashing.
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Blake3 => Self(Inner::Blake3(Box::new(blake3::Hasher::new()))),
+            HashAlgorithm::Sha256 => Self(Inner::Sha256(Sha256::new())),
+        }
+    }
+
+    /// Create a hasher whose output is specific to `context`, so that hashes
+    /// derived for different purposes can never collide.
+    ///
+    /// The context should be a hardcoded, globally unique string describing
+    /// the purpose, for example `"hurry 2025-01-01 unit hash"`.
+    pub fn derive(algorithm: HashAlgorithm, context: &str) -> Self {
+        match algorithm {
+            HashAlgorithm::Blake3 => Self(Inner::Blake3(Box::new(blake3::Hasher::new_derive_key(
+                context,
+            )))),
+            HashAlgorithm::Sha256 => {
+                let mut hasher = Sha256::new();
+                hasher.update((context.len() as u64).to_le_bytes());
+                hasher.update(context.as_bytes());
+                Self(Inner::Sha256(hasher))
+            }
+        }
+    }
+
+    /// The algorithm the hasher uses.
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match &self.0 {
+            Inner::Blake3(_) => HashAlgorithm::Blake3,
+            Inner::Sha256(_) => HashAlgorithm::Sha256,
+        }
+    }
+
+    /// Add content to the hash.
+    pub fn update(&mut self, content: impl AsRef<[u8]>) -> &mut Self {
+        let content = content.as_ref();
+        match &mut self.0 {
+            Inner::Blake3(hasher) => {
+                hasher.update(content);
+            }
+            Inner::Sha256(hasher) => {
+                hasher.update(content);
+            }
+        }
+        self
+    }
+
+    /// Finish hashing and produce the key.
+    pub fn finalize(self) -> Key {
+        let algorithm = self.algorithm();
+        let digest = match self.0 {
+            Inner::Blake3(hasher) => *hasher.finalize().as_bytes(),
+            Inner::Sha256(hasher) => hasher.finalize().into(),
+        };
+        Key::from_digest(algorithm, digest)
+    }
+}
+
+impl std::io::Write for KeyHasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::{HashAlgorithm, KeyHasher};
+    use crate::core::Key;
+
+    #[test]
+    fn blake3_keys_are_unchanged() {
+        let key = Key::from_buffer_with(HashAlgorithm::Blake3, b"hello");
+        pretty_assert_eq!(key, blake3::hash(b"hello"));
+        pretty_assert_eq!(key.algorithm(), HashAlgorithm::Blake3);
+        pretty_assert_eq!(key.to_hex().len(), 64);
+    }
+
+    #[test]
+    fn sha256_keys_round_trip() {
+        let key = Key::from_buffer_with(HashAlgorithm::Sha256, b"hello");
+        pretty_assert_eq!(key.algorithm(), HashAlgorithm::Sha256);
+        pretty_assert_eq!(
+            &key.to_hex()[..64],
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        pretty_assert_eq!(Key::from_hex(key.to_hex()).unwrap(), key);
+        pretty_assert_eq!(Key::from_bytes(key.as_bytes()).unwrap(), key);
+        assert!(key.verify(b"hello"));
+        assert!(!key.verify(b"goodbye"));
+    }
+
+    #[test]
+    fn algorithms_never_collide() {
+        let blake3 = Key::from_buffer_with(HashAlgorithm::Blake3, b"hello");
+        let sha256 = Key::from_buffer_with(HashAlgorithm::Sha256, b"hello");
+        assert_ne!(blake3, sha256);
+        assert!(!sha256.verify_with(HashAlgorithm::Blake3, b"hello"));
+    }
+
+    #[test]
+    fn rejects_unknown_tags() {
+        let mut bytes = Key::from_buffer_with(HashAlgorithm::Sha256, b"hello")
+            .as_bytes()
+            .to_vec();
+        *bytes.last_mut().unwrap() = 0xff;
+        assert!(Key::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn derived_hashes_depend_on_context() {
+        for algorithm in HashAlgorithm::ALL {
+            let hash = |context: &str| {
+                let mut hasher = KeyHasher::derive(algorithm, context);
+                hasher.update(b"content");
+                hasher.finalize()
+            };
+            assert_ne!(hash("context a"), hash("context b"), "{algorithm}");
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Access token API types.
+//!
+//! Authenticating a request with an API key costs Courier a database lookup,
+//! which adds latency to every request a build makes. Instead, clients can
+//! exchange their API key for a short-lived access token that Courier signs,
+//! and that it verifies without the database. Access tokens expire after a
+//! few minutes, so a revoked API key stops working within that time; clients
+//! exchange their API key again for a new access token before it expires.
+//!
+//! Access tokens can't be exchanged for new access tokens, so that they can't
+//! outlive their API key.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Token;
+
+/// An access token issued for an API key.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AccessTokenResponse {
+    /// The access token, which authenticates requests the same way as the
+    /// API key it was issued for.
+    pub token: Token,
+
+    /// Seconds until the access token expires.
+    pub expires_in: u64,
+}
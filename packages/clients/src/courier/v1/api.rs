@@ -0,0 +1,72 @@
+//! The Courier cache API as a trait, so that it can be mocked.
+
+use std::future::Future;
+
+use color_eyre::Result;
+
+use crate::courier::v1::{
+    Key,
+    cache::{CargoGenerationResponse, CargoRestoreRequest, CargoRestoreResponse, CargoSaveRequest},
+};
+
+/// The cache and CAS operations of the Courier API.
+///
+/// `Client` (behind the `client` feature) implements this over HTTP; code
+/// that only needs these operations can accept an implementation of this
+/// trait instead, so that its tests can use `FakeCourier` (behind the `fake`
+/// feature) or their own mock.
+///
+/// Bulk and streaming CAS operations are deliberately left out: they're
+/// transport optimizations over the operations here, and a mock would
+/// implement them the same way.
+pub trait CourierApi: Send + Sync {
+    /// Save units to the cache.
+    fn cargo_cache_save(&self, body: CargoSaveRequest) -> impl Future<Output = Result<()>> + Send;
+
+    /// Look up saved units, returning the ones that are in the cache.
+    fn cargo_cache_restore(
+        &self,
+        body: CargoRestoreRequest,
+    ) -> impl Future<Output = Result<CargoRestoreResponse>> + Send;
+
+    /// Get the organization's cache generation.
+    fn cargo_cache_generation(
+        &self,
+    ) -> impl Future<Output = Result<CargoGenerationResponse>> + Send;
+
+    /// Check if a CAS object exists.
+    fn cas_exists(&self, key: &Key) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Read a CAS object, if it exists.
+    fn cas_read_bytes(&self, key: &Key) -> impl Future<Output = Result<Option<Vec<u8>>>> + Send;
+
+    /// Write a CAS object.
+    fn cas_write_bytes(&self, key: &Key, body: Vec<u8>) -> impl Future<Output = Result<()>> + Send;
+}
+
+#[cfg(feature = "client")]
+impl CourierApi for crate::courier::v1::Client {
+    async fn cargo_cache_save(&self, body: CargoSaveRequest) -> Result<()> {
+        Self::cargo_cache_save(self, body).await
+    }
+
+    async fn cargo_cache_restore(&self, body: CargoRestoreRequest) -> Result<CargoRestoreResponse> {
+        Self::cargo_cache_restore(self, body).await
+    }
+
+    async fn cargo_cache_generation(&self) -> Result<CargoGenerationResponse> {
+        Self::cargo_cache_generation(self).await
+    }
+
+    async fn cas_exists(&self, key: &Key) -> Result<bool> {
+        Self::cas_exists(self, key).await
+    }
+
+    async fn cas_read_bytes(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        Self::cas_read_bytes(self, key).await
+    }
+
+    async fn cas_write_bytes(&self, key: &Key, body: Vec<u8>) -> Result<()> {
+        Self::cas_write_bytes(self, key, body).await
+    }
+}
@@ -1,12 +1,19 @@
 //! Cargo cache API types.
 
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read as _,
+};
 
 use bon::Builder;
-use derive_more::From;
-use serde::{Deserialize, Serialize};
+use color_eyre::{
+    Result,
+    eyre::{Context as _, bail},
+};
+use derive_more::Display;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
-use crate::courier::v1::{GlibcVersion, SavedUnit, SavedUnitHash};
+use crate::courier::v1::{CacheScope, GlibcVersion, SavedUnit, SavedUnitHash, UnitHashVersion};
 
 /// A single `SavedUnit` and its associated cache key in a save request.
 #[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize, Builder)]
@@ -17,6 +24,162 @@ pub struct CargoSaveUnitRequest {
     pub unit: SavedUnit,
     pub resolved_target: String,
     pub linux_glibc_version: Option<GlibcVersion>,
+
+    /// The version of the algorithm used to derive the hash under which the
+    /// unit is saved.
+    ///
+    /// Requests from clients that predate versioning omit this field, and are
+    /// treated as using `UnitHashVersion::V1`.
+    #[serde(default)]
+    #[builder(default)]
+    pub unit_hash_version: UnitHashVersion,
+
+    /// The key under which the unit is offered as a near match for units that
+    /// differ from it only in ways the restoring client may accept.
+    ///
+    /// Units that can't be near matches (e.g. build scripts) omit this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(into)]
+    pub near_match_key: Option<String>,
+
+    /// The organization's cache generation when the unit was saved, which is
+    /// mixed into the hash under which the unit is saved.
+    ///
+    /// Requests from clients that predate generations omit this field, and
+    /// are treated as saving in the initial generation.
+    #[serde(default)]
+    #[builder(default)]
+    pub cache_generation: u64,
+
+    /// The cache scope the unit was saved in, which is mixed into the hash
+    /// under which the unit is saved.
+    ///
+    /// Units saved without a scope are in the organization's unscoped cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_scope: Option<CacheScope>,
+
+    /// Whether the unit should replace the unit already saved under the same
+    /// hash, because objects that the saved unit references were missing
+    /// from the CAS when the client tried to restore it.
+    ///
+    /// Courier only replaces the saved unit if its objects are in fact
+    /// missing, so that units which restore fine aren't churned.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    #[builder(default)]
+    pub repair: bool,
+}
+
+impl CargoSaveUnitRequest {
+    /// The hash under which the unit is saved.
+    pub fn saved_unit_hash(&self) -> SavedUnitHash {
+        self.unit_hash_version.derive_scoped(
+            self.unit.info(),
+            self.cache_generation,
+            self.cache_scope.as_ref(),
+        )
+    }
+}
+
+/// The serialized size above which save requests are streamed as
+/// newline-delimited JSON instead of sent as a single JSON document.
+///
+/// Courier limits JSON bodies to 100 MiB; this leaves plenty of headroom so
+/// that requests approaching the limit never fail outright.
+pub const SAVE_STREAM_THRESHOLD: usize = 32 * 1024 * 1024;
+
+/// Encode a cache message as [`ContentType::MsgPackZstd`].
+///
+/// Save requests and restore responses are dominated by repeated field names,
+/// keys, and paths, which MessagePack and zstd shrink considerably compared to
+/// JSON. Structs are encoded as maps keyed by field name, like they are in
+/// JSON, so fields can be added and defaulted the same way.
+///
+/// [`ContentType::MsgPackZstd`]: crate::ContentType::MsgPackZstd
+pub fn encode_binary(message: &impl Serialize) -> Result<Vec<u8>> {
+    let encoded = rmp_serde::to_vec_named(message).context("encode MessagePack")?;
+    zstd::encode_all(encoded.as_slice(), zstd::DEFAULT_COMPRESSION_LEVEL)
+        .context("compress message")
+}
+
+/// Decode a cache message encoded with [`encode_binary`], failing if it
+/// decompresses to more than `max_size` bytes.
+pub fn decode_binary<T: DeserializeOwned>(body: &[u8], max_size: u64) -> Result<T> {
+    let decoder = zstd::stream::read::Decoder::new(body).context("create zstd decoder")?;
+    let mut decoded = Vec::new();
+    decoder
+        .take(max_size + 1)
+        .read_to_end(&mut decoded)
+        .context("decompress message")?;
+    if decoded.len() as u64 > max_size {
+        bail!("message decompresses to more than {max_size} bytes");
+    }
+    rmp_serde::from_slice(&decoded).context("decode MessagePack")
+}
+
+/// Information about the environment that saved a request's units.
+///
+/// This is recorded alongside each saved unit to help debug cache behavior
+/// (e.g. "which builder uploaded this?") and to support retention policies.
+/// Every field is optional: clients report what they know, and clients that
+/// predate this report nothing.
+#[derive(Clone, Default, PartialEq, Eq, Hash, Debug, Serialize, Deserialize, Builder)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct SavedUnitMetadata {
+    /// The version of `rustc` that compiled the units, as reported by
+    /// `rustc -V`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(into)]
+    pub rustc_version: Option<String>,
+
+    /// The version of Hurry that saved the units.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(into)]
+    pub hurry_version: Option<String>,
+
+    /// The CI provider the units were built on, e.g. `github-actions`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(into)]
+    pub ci_provider: Option<String>,
+
+    /// The commit the units were built from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(into)]
+    pub commit_sha: Option<String>,
+
+    /// A hash of the hostname of the machine that built the units.
+    ///
+    /// This groups units by builder without recording the hostname itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(into)]
+    pub builder_hostname_hash: Option<String>,
+
+    /// The branch the units were built from.
+    ///
+    /// Courier checks this against the organization's [`CargoWritePolicy`]
+    /// before saving the units.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(into)]
+    pub branch: Option<String>,
+
+    /// Identifies the build that saved the units, grouping the units saved
+    /// by one build across requests.
+    ///
+    /// Courier keeps units saved with a build ID provisional, and doesn't
+    /// restore them, until the build's save is finalized with a
+    /// [`CargoFinalizeRequest`]. This way an interrupted save can't leave
+    /// units in the cache whose dependencies were never saved. Units saved
+    /// without a build ID are restorable immediately.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(into)]
+    pub build_id: Option<String>,
+}
+
+impl SavedUnitMetadata {
+    /// Whether no metadata is set.
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
 }
 
 /// Request to save cargo cache metadata.
@@ -24,13 +187,31 @@ pub struct CargoSaveUnitRequest {
 #[non_exhaustive]
 pub struct CargoSaveRequest {
     units: HashSet<CargoSaveUnitRequest>,
+
+    /// Metadata recorded for every unit in the request.
+    #[serde(default, skip_serializing_if = "SavedUnitMetadata::is_empty")]
+    metadata: SavedUnitMetadata,
 }
 
 impl CargoSaveRequest {
     /// Create a new instance from the provided units.
     pub fn new(units: impl IntoIterator<Item = impl Into<CargoSaveUnitRequest>>) -> Self {
         let units = units.into_iter().map(Into::into).collect::<HashSet<_>>();
-        Self { units }
+        Self {
+            units,
+            metadata: SavedUnitMetadata::default(),
+        }
+    }
+
+    /// Record the provided metadata for every unit in the request.
+    pub fn with_metadata(mut self, metadata: SavedUnitMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// The metadata recorded for every unit in the request.
+    pub fn metadata(&self) -> &SavedUnitMetadata {
+        &self.metadata
     }
 
     /// Iterate over the units in the request.
@@ -55,11 +236,30 @@ impl From<&CargoSaveRequest> for CargoSaveRequest {
 }
 
 /// Request to restore cargo cache metadata.
-#[derive(Debug, Clone, Serialize, Deserialize, From)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[non_exhaustive]
 pub struct CargoRestoreRequest {
     pub units: HashSet<SavedUnitHash>,
     pub host_glibc_version: Option<GlibcVersion>,
+
+    /// Near-match keys to return candidate units for.
+    ///
+    /// Candidates are only near matches: the client is responsible for
+    /// validating that a candidate is compatible before restoring it.
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub near_match_keys: HashSet<String>,
+
+    /// Only restore units that were saved at or before this point, to
+    /// rebuild the workspace the way it was built then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub as_of: Option<CacheAsOf>,
+
+    /// The cache scope to find near-match candidates in.
+    ///
+    /// Units are requested by hashes that already include the scope, so this
+    /// only applies to near matches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_scope: Option<CacheScope>,
 }
 
 impl CargoRestoreRequest {
@@ -72,15 +272,46 @@ impl CargoRestoreRequest {
         Self {
             units,
             host_glibc_version,
+            near_match_keys: HashSet::new(),
+            as_of: None,
+            cache_scope: None,
         }
     }
 
+    /// Only find near-match candidates saved in the provided cache scope.
+    pub fn with_cache_scope(mut self, scope: impl Into<Option<CacheScope>>) -> Self {
+        self.cache_scope = scope.into();
+        self
+    }
+
+    /// Only restore units that were saved at or before the provided point.
+    pub fn with_as_of(mut self, as_of: impl Into<Option<CacheAsOf>>) -> Self {
+        self.as_of = as_of.into();
+        self
+    }
+
+    /// Also request near-match candidates for the provided keys.
+    pub fn with_near_match_keys(
+        mut self,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.near_match_keys
+            .extend(keys.into_iter().map(Into::into));
+        self
+    }
+
     /// Iterate over the hashes in the request.
     pub fn iter(&self) -> impl Iterator<Item = &SavedUnitHash> {
         self.units.iter()
     }
 }
 
+impl From<(HashSet<SavedUnitHash>, Option<GlibcVersion>)> for CargoRestoreRequest {
+    fn from((units, host_glibc_version): (HashSet<SavedUnitHash>, Option<GlibcVersion>)) -> Self {
+        Self::new(units, host_glibc_version)
+    }
+}
+
 impl IntoIterator for CargoRestoreRequest {
     type Item = SavedUnitHash;
     type IntoIter = std::collections::hash_set::IntoIter<SavedUnitHash>;
@@ -90,6 +321,26 @@ impl IntoIterator for CargoRestoreRequest {
     }
 }
 
+/// A point in the history of the cache to restore units as of.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheAsOf {
+    /// An RFC 3339 timestamp.
+    Time(String),
+
+    /// A commit, by its SHA or a prefix of it at least
+    /// [`CacheAsOf::MIN_COMMIT_PREFIX`] characters long.
+    ///
+    /// This is the last time a unit built from the commit was uploaded, so
+    /// that everything the commit's builds saved is restored.
+    Commit(String),
+}
+
+impl CacheAsOf {
+    /// The shortest commit SHA prefix that's accepted.
+    pub const MIN_COMMIT_PREFIX: usize = 7;
+}
+
 impl From<&CargoRestoreRequest> for CargoRestoreRequest {
     fn from(req: &CargoRestoreRequest) -> Self {
         req.clone()
@@ -97,9 +348,14 @@ impl From<&CargoRestoreRequest> for CargoRestoreRequest {
 }
 
 /// Response from restoring cargo cache metadata.
-#[derive(Debug, Clone, Serialize, Deserialize, From, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct CargoRestoreResponse {
     units: HashMap<SavedUnitHash, SavedUnit>,
+
+    /// Candidate units for each requested near-match key, most recently saved
+    /// first.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    near_matches: HashMap<String, Vec<SavedUnit>>,
 }
 
 impl CargoRestoreResponse {
@@ -114,7 +370,16 @@ impl CargoRestoreResponse {
             .into_iter()
             .map(|(hash, unit)| (hash.into(), unit.into()))
             .collect::<HashMap<_, _>>();
-        Self { units }
+        Self {
+            units,
+            near_matches: HashMap::new(),
+        }
+    }
+
+    /// Add near-match candidates to the response.
+    pub fn with_near_matches(mut self, near_matches: HashMap<String, Vec<SavedUnit>>) -> Self {
+        self.near_matches = near_matches;
+        self
     }
 
     /// Iterate over the units in the response.
@@ -141,6 +406,23 @@ impl CargoRestoreResponse {
     pub fn take(&mut self, key: &SavedUnitHash) -> Option<SavedUnit> {
         self.units.remove(key)
     }
+
+    /// Consume the near-match candidates for a key, removing them from the
+    /// response.
+    pub fn take_near_matches(&mut self, key: &str) -> Vec<SavedUnit> {
+        self.near_matches.remove(key).unwrap_or_default()
+    }
+
+    /// Get the number of near-match candidates in the response.
+    pub fn near_match_count(&self) -> usize {
+        self.near_matches.values().map(Vec::len).sum()
+    }
+}
+
+impl From<HashMap<SavedUnitHash, SavedUnit>> for CargoRestoreResponse {
+    fn from(units: HashMap<SavedUnitHash, SavedUnit>) -> Self {
+        Self::new(units)
+    }
 }
 
 impl IntoIterator for CargoRestoreResponse {
@@ -157,9 +439,7 @@ impl FromIterator<(SavedUnitHash, SavedUnit)> for CargoRestoreResponse {
     where
         I: IntoIterator<Item = (SavedUnitHash, SavedUnit)>,
     {
-        Self {
-            units: iter.into_iter().collect(),
-        }
+        Self::new(iter)
     }
 }
 
@@ -168,3 +448,446 @@ impl From<&CargoRestoreResponse> for CargoRestoreResponse {
         resp.clone()
     }
 }
+
+/// A record of a unit being uploaded to the cache.
+///
+/// A unit can be uploaded more than once, e.g. by builders racing to save the
+/// same unit; each upload is recorded separately even though only the first
+/// one's content is kept.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize, Builder)]
+#[non_exhaustive]
+pub struct CargoUnitOrigin {
+    /// The account whose token uploaded the unit.
+    pub account_id: i64,
+
+    /// The name of the account, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(into)]
+    pub account_name: Option<String>,
+
+    /// The email of the account. For bots, this is the contact for the
+    /// person or team that owns the bot.
+    #[builder(into)]
+    pub account_email: String,
+
+    /// Whether the account is a bot rather than a human.
+    pub bot: bool,
+
+    /// When the unit was uploaded, as an RFC 3339 timestamp.
+    #[builder(into)]
+    pub uploaded_at: String,
+
+    /// Metadata about the environment that uploaded the unit.
+    #[serde(default, skip_serializing_if = "SavedUnitMetadata::is_empty")]
+    #[builder(default)]
+    pub metadata: SavedUnitMetadata,
+}
+
+/// Response listing the uploads of a unit, most recent first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CargoUnitOriginsResponse {
+    pub origins: Vec<CargoUnitOrigin>,
+}
+
+impl CargoUnitOriginsResponse {
+    /// Create a new instance from the provided origins.
+    pub fn new(origins: impl IntoIterator<Item = CargoUnitOrigin>) -> Self {
+        Self {
+            origins: origins.into_iter().collect(),
+        }
+    }
+}
+
+/// Request to list the units saved in the cache, in the order they were
+/// saved.
+///
+/// Listing is paginated: pass the [`CargoListResponse::next`] cursor of one
+/// page as `after` to list the next.
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize, Builder)]
+#[non_exhaustive]
+pub struct CargoListRequest {
+    /// Only list units saved after the one this cursor points to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<i64>,
+
+    /// The maximum number of units to list. Courier caps this, and uses its
+    /// own default if it's omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+/// A saved unit, as listed by a [`CargoListRequest`].
+///
+/// This carries everything needed to save the unit again, e.g. to copy it to
+/// another Courier instance.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CargoListedUnit {
+    /// The unit, as it was saved.
+    pub unit: CargoSaveUnitRequest,
+
+    /// Metadata about the environment that saved the unit.
+    #[serde(default, skip_serializing_if = "SavedUnitMetadata::is_empty")]
+    pub metadata: SavedUnitMetadata,
+}
+
+impl CargoListedUnit {
+    /// Create a new instance from the provided unit and metadata.
+    pub fn new(unit: CargoSaveUnitRequest, metadata: SavedUnitMetadata) -> Self {
+        Self { unit, metadata }
+    }
+}
+
+/// Response listing a page of saved units.
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CargoListResponse {
+    pub units: Vec<CargoListedUnit>,
+
+    /// The cursor to list the next page after, or `None` if the page is
+    /// empty (i.e. every unit has been listed).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<i64>,
+}
+
+impl CargoListResponse {
+    /// Create a new instance from the provided units and cursor.
+    pub fn new(units: impl IntoIterator<Item = CargoListedUnit>, next: Option<i64>) -> Self {
+        Self {
+            units: units.into_iter().collect(),
+            next,
+        }
+    }
+}
+
+/// Request to finalize the units saved by a build.
+///
+/// Once finalized, the units saved with the build's
+/// [`SavedUnitMetadata::build_id`] can be restored. Finalizing a build more
+/// than once, or a build that saved no units, has no effect.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CargoFinalizeRequest {
+    pub build_id: String,
+}
+
+impl CargoFinalizeRequest {
+    /// Create a new instance for the provided build ID.
+    pub fn new(build_id: impl Into<String>) -> Self {
+        Self {
+            build_id: build_id.into(),
+        }
+    }
+}
+
+/// The organization's cache generation.
+///
+/// Clients mix the generation into the hashes under which units are saved, so
+/// bumping it invalidates every unit saved before the bump without deleting
+/// anything.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CargoGenerationResponse {
+    pub generation: u64,
+}
+
+impl CargoGenerationResponse {
+    /// Create a new instance from the provided generation.
+    pub fn new(generation: u64) -> Self {
+        Self { generation }
+    }
+}
+
+/// Request to reset a single cache scope, deleting the units saved in it.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CargoScopeResetRequest {
+    pub scope: CacheScope,
+}
+
+impl CargoScopeResetRequest {
+    /// Create a new instance for the provided scope.
+    pub fn new(scope: CacheScope) -> Self {
+        Self { scope }
+    }
+}
+
+/// The result of resetting a single cache scope.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CargoScopeResetResponse {
+    /// How many saved units were deleted.
+    pub units_deleted: u64,
+}
+
+impl CargoScopeResetResponse {
+    /// Create a new instance from the number of deleted units.
+    pub fn new(units_deleted: u64) -> Self {
+        Self { units_deleted }
+    }
+}
+
+/// Which branches can save units to the organization's cache.
+///
+/// Builds of other branches can still restore from the cache, but their saves
+/// are rejected, so that e.g. feature branches can't pollute the cache that
+/// `main` and release builds rely on.
+///
+/// The branch is reported by the client, so this guards against accidental
+/// pollution rather than against a client that lies about its branch.
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CargoWritePolicy {
+    /// Patterns for the branches that can save units, where `*` matches any
+    /// sequence of characters (e.g. `main` or `release/*`).
+    ///
+    /// If unset, every build can save units, including builds whose branch
+    /// is unknown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branches: Option<Vec<String>>,
+}
+
+impl CargoWritePolicy {
+    /// Create a policy that only lets the branches matching the patterns save
+    /// units.
+    pub fn protected(branches: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            branches: Some(branches.into_iter().map(Into::into).collect()),
+        }
+    }
+
+    /// Create a policy that lets every build save units.
+    pub fn unrestricted() -> Self {
+        Self::default()
+    }
+
+    /// Whether a build of `branch` can save units.
+    ///
+    /// Once branches are protected, builds whose branch is unknown can't save
+    /// units.
+    pub fn allows(&self, branch: Option<&str>) -> bool {
+        let Some(patterns) = &self.branches else {
+            return true;
+        };
+        branch.is_some_and(|branch| {
+            patterns
+                .iter()
+                .any(|pattern| branch_matches(pattern, branch))
+        })
+    }
+}
+
+/// The header that Courier sets on `503 Service Unavailable` responses to
+/// writes it rejected because the cache is read-only, to tell them apart from
+/// an outage.
+pub const READ_ONLY_HEADER: &str = "x-courier-read-only";
+
+/// Whether the organization's cache is read-only.
+///
+/// Courier is made read-only during maintenance like migrations: it keeps
+/// serving restores, but rejects saves and CAS writes with
+/// `503 Service Unavailable`, the [`READ_ONLY_HEADER`] header, and this as the
+/// body. Either the whole instance or a single organization can be read-only.
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CargoReadOnly {
+    /// Why the cache is read-only. If unset, the cache accepts writes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl CargoReadOnly {
+    /// Make the cache read-only for the provided reason.
+    pub fn read_only(reason: impl Into<String>) -> Self {
+        Self {
+            reason: Some(reason.into()),
+        }
+    }
+
+    /// Let the cache accept writes.
+    pub fn writable() -> Self {
+        Self::default()
+    }
+}
+
+/// The error for a write that Courier rejected because the cache is
+/// read-only.
+///
+/// Clients that find this in an error's chain can skip saving instead of
+/// failing, since the cache accepts writes again once maintenance is over.
+#[derive(Clone, PartialEq, Eq, Debug, Display)]
+#[display("the cache is read-only: {reason}")]
+#[non_exhaustive]
+pub struct CacheReadOnly {
+    /// Why the cache is read-only.
+    pub reason: String,
+}
+
+impl CacheReadOnly {
+    /// Create the error for the provided reason.
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+        }
+    }
+}
+
+impl std::error::Error for CacheReadOnly {}
+
+/// The header that Courier sets on `413 Payload Too Large` responses to
+/// writes it rejected because the organization's storage quota is used up.
+pub const QUOTA_EXCEEDED_HEADER: &str = "x-courier-quota-exceeded";
+
+/// How much the organization stores in the cache.
+///
+/// Courier rejects saves and CAS writes once an organization stores as much
+/// as its quota, with `413 Payload Too Large`, the [`QUOTA_EXCEEDED_HEADER`]
+/// header, and this as the body. Objects shared with other organizations
+/// count towards each of their usage.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CargoStorageUsage {
+    /// Compressed bytes of the objects the organization stores.
+    pub bytes_stored: u64,
+
+    /// The most the organization may store. If unset, there's no limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_bytes: Option<u64>,
+}
+
+impl CargoStorageUsage {
+    /// Create the usage for the provided bytes and quota.
+    pub fn new(bytes_stored: u64, quota_bytes: Option<u64>) -> Self {
+        Self {
+            bytes_stored,
+            quota_bytes,
+        }
+    }
+
+    /// Whether the organization stores as much as its quota allows.
+    pub fn exceeded(&self) -> bool {
+        self.quota_bytes
+            .is_some_and(|quota| self.bytes_stored >= quota)
+    }
+}
+
+/// The error for a write that Courier rejected because the organization's
+/// storage quota is used up.
+///
+/// Unlike [`CacheReadOnly`], this doesn't go away on its own: an admin has to
+/// reset the cache or have the quota raised.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display)]
+#[display(
+    "the organization's storage quota is used up ({} of {} bytes stored)",
+    usage.bytes_stored,
+    usage.quota_bytes.unwrap_or_default()
+)]
+#[non_exhaustive]
+pub struct StorageQuotaExceeded {
+    /// How much the organization stores.
+    pub usage: CargoStorageUsage,
+}
+
+impl StorageQuotaExceeded {
+    /// Create the error for the provided usage.
+    pub fn new(usage: CargoStorageUsage) -> Self {
+        Self { usage }
+    }
+}
+
+impl std::error::Error for StorageQuotaExceeded {}
+
+/// Whether `branch` matches `pattern`, where `*` in the pattern matches any
+/// sequence of characters.
+fn branch_matches(pattern: &str, branch: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = branch.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.collect::<Vec<_>>();
+    let Some(last) = parts.pop() else {
+        // No wildcards: the pattern must match exactly.
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::*;
+    use crate::courier::v1::{
+        BuildScriptExecutionUnitPlan, BuildScriptOutputFiles, Key, UnitPlanInfo,
+    };
+
+    #[test]
+    fn binary_encoding_round_trips() {
+        let info = UnitPlanInfo::builder()
+            .unit_hash("0123456789abcdef")
+            .package_name("serde")
+            .crate_name("build_script_build")
+            .build();
+        let unit = SavedUnit::BuildScriptExecution(
+            BuildScriptOutputFiles::builder()
+                .stdout(Key::from_buffer(b"cargo::rerun-if-changed=build.rs\n"))
+                .stderr(Key::from_buffer(b""))
+                .fingerprint("0123456789abcdef")
+                .build(),
+            BuildScriptExecutionUnitPlan::builder()
+                .info(info)
+                .build_script_program_name("build-script-build")
+                .build(),
+        );
+        let response = CargoRestoreResponse::new([(SavedUnitHash::from("0123456789abcdef"), unit)]);
+
+        let encoded = encode_binary(&response).unwrap();
+        let decoded = decode_binary::<CargoRestoreResponse>(&encoded, 1024 * 1024).unwrap();
+        pretty_assert_eq!(
+            decoded.into_iter().collect::<Vec<_>>(),
+            response.into_iter().collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn binary_decoding_limits_size() {
+        let encoded = encode_binary(&vec![0u8; 4096]).unwrap();
+        assert!(decode_binary::<Vec<u8>>(&encoded, 1024).is_err());
+    }
+
+    #[test]
+    fn unrestricted_policy_allows_every_branch() {
+        let policy = CargoWritePolicy::unrestricted();
+        assert!(policy.allows(Some("feature/foo")));
+        assert!(policy.allows(None));
+    }
+
+    #[test]
+    fn protected_policy_allows_matching_branches() {
+        let policy = CargoWritePolicy::protected(["main", "release/*", "*-stable"]);
+        assert!(policy.allows(Some("main")));
+        assert!(policy.allows(Some("release/1.2")));
+        assert!(policy.allows(Some("1.2-stable")));
+        assert!(!policy.allows(Some("main2")));
+        assert!(!policy.allows(Some("feature/main")));
+        assert!(!policy.allows(Some("release")));
+        assert!(!policy.allows(None));
+    }
+
+    #[test]
+    fn wildcards_match_in_order() {
+        assert!(branch_matches("a*b*c", "a-b-c"));
+        assert!(branch_matches("a*b*c", "abc"));
+        assert!(!branch_matches("a*b*c", "a-c-b"));
+        assert!(!branch_matches("ab*ba", "aba"));
+        assert!(branch_matches("*", "anything"));
+    }
+}
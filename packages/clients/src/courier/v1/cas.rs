@@ -1,11 +1,40 @@
 //! CAS-specific API types.
 
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, sync::Arc};
 
 use bon::Builder;
+use color_eyre::{
+    Result,
+    eyre::{Context, OptionExt, bail},
+};
+use derive_more::Debug;
 use serde::{Deserialize, Serialize};
 
-use super::Key;
+use super::{HashAlgorithm, Key};
+
+/// The largest object, in bytes, that is compressed with a dictionary.
+///
+/// Dictionaries mostly help small objects (fingerprints, dep-info files, build
+/// script output) which don't contain enough data for zstd to learn from on its
+/// own; larger objects compress just as well without one, and skipping them
+/// keeps the dictionary from being trained on or applied to large artifacts.
+pub const DICTIONARY_MAX_OBJECT_SIZE: u64 = 64 * 1024;
+
+/// Objects larger than this, in bytes, are uploaded in resumable chunks
+/// rather than in a single request, so that a dropped connection doesn't
+/// lose the whole upload.
+pub const RESUMABLE_UPLOAD_THRESHOLD: u64 = 32 * 1024 * 1024;
+
+/// The header that carries the offset a chunk of a resumable upload starts
+/// at, in bytes of uncompressed content.
+pub const UPLOAD_OFFSET_HEADER: &str = "upload-offset";
+
+/// The progress of a resumable CAS upload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct CasUploadStatus {
+    /// The number of bytes of uncompressed content received so far.
+    pub offset: u64,
+}
 
 /// Response from bulk CAS write operation.
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default, Builder)]
@@ -50,6 +79,17 @@ impl From<&CasBulkWriteKeyError> for CasBulkWriteKeyError {
 pub struct CasBulkReadRequest {
     #[builder(default, with = |i: impl IntoIterator<Item = impl Into<Key>>| i.into_iter().map(Into::into).collect())]
     pub keys: Vec<Key>,
+
+    /// The key of a dictionary (see [`CasDictionary`]) that the client has
+    /// available locally.
+    ///
+    /// When set, Courier may compress small objects with this dictionary. Each
+    /// compressed object records the ID of the dictionary it was compressed
+    /// with in its zstd frame header, so clients can tell which objects need
+    /// the dictionary to decompress.
+    #[builder(into)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dictionary: Option<Key>,
 }
 
 impl From<&CasBulkReadRequest> for CasBulkReadRequest {
@@ -57,3 +97,260 @@ impl From<&CasBulkReadRequest> for CasBulkReadRequest {
         request.clone()
     }
 }
+
+/// A zstd dictionary that Courier trained from an organization's small CAS
+/// objects.
+///
+/// The dictionary content is itself stored in the CAS under `key`.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize, Serialize, Builder)]
+#[non_exhaustive]
+pub struct CasDictionary {
+    /// The CAS key of the dictionary content.
+    #[builder(into)]
+    pub key: Key,
+
+    /// The package whose objects were used to train the dictionary.
+    ///
+    /// `None` for the global dictionary, which is trained on objects from all
+    /// packages.
+    #[builder(into)]
+    pub package_name: Option<String>,
+
+    /// The dictionary ID that zstd records in the header of frames compressed
+    /// with this dictionary.
+    pub zstd_id: u32,
+}
+
+impl From<&CasDictionary> for CasDictionary {
+    fn from(dictionary: &CasDictionary) -> Self {
+        dictionary.clone()
+    }
+}
+
+/// Response body for listing CAS dictionaries.
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, Default, Builder)]
+#[non_exhaustive]
+pub struct CasDictionaryListResponse {
+    #[builder(default, with = |i: impl IntoIterator<Item = impl Into<CasDictionary>>| i.into_iter().map(Into::into).collect())]
+    pub dictionaries: Vec<CasDictionary>,
+}
+
+impl CasDictionaryListResponse {
+    /// The dictionary trained on objects from all packages, if any.
+    pub fn global(&self) -> Option<&CasDictionary> {
+        self.dictionaries
+            .iter()
+            .find(|dictionary| dictionary.package_name.is_none())
+    }
+}
+
+/// Response body for listing the hash algorithms Courier accepts for CAS
+/// keys.
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, Default, Builder)]
+#[non_exhaustive]
+pub struct CasAlgorithmsResponse {
+    #[builder(default, with = |i: impl IntoIterator<Item = HashAlgorithm>| i.into_iter().collect())]
+    pub algorithms: BTreeSet<HashAlgorithm>,
+}
+
+/// Request body for URLs that transfer CAS objects directly to and from the
+/// object storage behind Courier, bypassing its API.
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, Default, Builder)]
+#[non_exhaustive]
+pub struct CasDirectRequest {
+    /// Objects to read.
+    #[builder(default, with = |i: impl IntoIterator<Item = impl Into<Key>>| i.into_iter().map(Into::into).collect())]
+    #[serde(default)]
+    pub reads: Vec<Key>,
+
+    /// Objects to write.
+    #[builder(default, with = |i: impl IntoIterator<Item = impl Into<Key>>| i.into_iter().map(Into::into).collect())]
+    #[serde(default)]
+    pub writes: Vec<Key>,
+}
+
+/// Response body with URLs that transfer CAS objects directly to and from
+/// object storage.
+///
+/// URLs are presigned: they're used without Courier's authentication, and
+/// expire after a while, so they should be used right away.
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, Default, Builder)]
+#[non_exhaustive]
+pub struct CasDirectResponse {
+    /// `GET` URLs for the requested objects, whose responses are the content
+    /// compressed with zstd (without a dictionary).
+    ///
+    /// Objects that don't exist, that the organization can't access, or that
+    /// are small enough to be read more efficiently in bulk are left out;
+    /// read those through the API instead.
+    #[builder(default, with = |i: impl IntoIterator<Item = impl Into<CasDirectUrl>>| i.into_iter().map(Into::into).collect())]
+    #[serde(default)]
+    pub reads: Vec<CasDirectUrl>,
+
+    /// `PUT` URLs for the requested objects, whose bodies are the
+    /// uncompressed content.
+    ///
+    /// A direct upload works like a resumable upload that's been sent in one
+    /// piece: once the content is uploaded, complete it with
+    /// `POST /api/v1/cas/uploads/{key}/complete` so that Courier checks it
+    /// and moves it into the CAS. Objects that already exist are left out.
+    #[builder(default, with = |i: impl IntoIterator<Item = impl Into<CasDirectUrl>>| i.into_iter().map(Into::into).collect())]
+    #[serde(default)]
+    pub writes: Vec<CasDirectUrl>,
+}
+
+impl CasDirectResponse {
+    /// The read URL for the key, if any.
+    pub fn read_url(&self, key: &Key) -> Option<&str> {
+        self.reads
+            .iter()
+            .find(|direct| &direct.key == key)
+            .map(|direct| direct.url.as_str())
+    }
+
+    /// The write URL for the key, if any.
+    pub fn write_url(&self, key: &Key) -> Option<&str> {
+        self.writes
+            .iter()
+            .find(|direct| &direct.key == key)
+            .map(|direct| direct.url.as_str())
+    }
+}
+
+/// A presigned URL that transfers a CAS object directly.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize, Serialize, Builder)]
+#[non_exhaustive]
+pub struct CasDirectUrl {
+    #[builder(into)]
+    pub key: Key,
+
+    #[builder(into)]
+    pub url: String,
+}
+
+impl From<&CasDirectUrl> for CasDirectUrl {
+    fn from(direct: &CasDirectUrl) -> Self {
+        direct.clone()
+    }
+}
+
+/// A loaded zstd dictionary, used to compress and decompress small objects.
+///
+/// ## Cloning
+///
+/// This type is cheaply cloneable; clones share the dictionary content.
+#[derive(Clone, Debug)]
+pub struct Dictionary {
+    /// The CAS key of the dictionary content.
+    pub key: Key,
+
+    /// The dictionary ID that zstd records in frames compressed with it.
+    pub zstd_id: u32,
+
+    #[debug(skip)]
+    content: Arc<[u8]>,
+}
+
+impl Dictionary {
+    /// Load a dictionary from its content.
+    ///
+    /// Errors if the content isn't a zstd dictionary: raw content
+    /// dictionaries don't have an ID, so frames compressed with them can't be
+    /// told apart from frames compressed without a dictionary.
+    pub fn new(content: impl Into<Arc<[u8]>>) -> Result<Self> {
+        let content = content.into();
+        let zstd_id = zstd::zstd_safe::get_dict_id_from_dict(&content)
+            .ok_or_eyre("content is not a zstd dictionary")?
+            .get();
+        Ok(Self {
+            key: Key::from_buffer(&content),
+            zstd_id,
+            content,
+        })
+    }
+
+    /// Compress the content with this dictionary.
+    pub fn compress(&self, content: &[u8]) -> Result<Vec<u8>> {
+        zstd::bulk::Compressor::with_dictionary(0, &self.content)
+            .context("load dictionary")?
+            .compress(content)
+            .context("compress with dictionary")
+    }
+
+    /// Decompress a zstd frame, using this dictionary if the frame was
+    /// compressed with it.
+    ///
+    /// Frames compressed without a dictionary are decompressed normally.
+    pub fn decompress(&self, compressed: &[u8], capacity: usize) -> Result<Vec<u8>> {
+        decompress(compressed, Some(self), capacity)
+    }
+}
+
+/// Decompress a zstd frame with the dictionary named in its frame header, if
+/// any.
+///
+/// Errors if the frame was compressed with a dictionary other than the one
+/// provided.
+pub fn decompress(
+    compressed: &[u8],
+    dictionary: Option<&Dictionary>,
+    capacity: usize,
+) -> Result<Vec<u8>> {
+    let Some(id) = zstd::zstd_safe::get_dict_id_from_frame(compressed) else {
+        return zstd::bulk::decompress(compressed, capacity).context("decompress");
+    };
+    let Some(dictionary) = dictionary.filter(|d| d.zstd_id == id.get()) else {
+        bail!("frame was compressed with unknown dictionary {id}");
+    };
+    zstd::bulk::Decompressor::with_dictionary(&dictionary.content)
+        .context("load dictionary")?
+        .decompress(compressed, capacity)
+        .context("decompress with dictionary")
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::*;
+
+    fn train(samples: usize) -> Dictionary {
+        let samples = (0..samples)
+            .map(|rustc| {
+                let target = rustc * 7;
+                format!(
+                    r#"{{"rustc":{rustc},"features":"[\"default\", \"std\"]","target":{target}}}"#
+                )
+                .into_bytes()
+            })
+            .collect::<Vec<_>>();
+        let content = zstd::dict::from_samples(&samples, 2048).expect("train dictionary");
+        Dictionary::new(content).expect("load dictionary")
+    }
+
+    #[test]
+    fn dictionary_roundtrip() {
+        let dictionary = train(1024);
+        let content = br#"{"rustc":1234,"features":"[\"default\", \"std\"]","target":5678}"#;
+
+        let compressed = dictionary.compress(content).expect("compress");
+        let decompressed = dictionary
+            .decompress(&compressed, 1024)
+            .expect("decompress");
+        pretty_assert_eq!(decompressed, content.to_vec());
+    }
+
+    #[test]
+    fn decompress_without_dictionary() {
+        let dictionary = train(1024);
+        let content = b"plain frame";
+
+        let compressed = zstd::bulk::compress(content, 0).expect("compress");
+        let plain = decompress(&compressed, None, 1024).expect("decompress plain");
+        let with = decompress(&compressed, Some(&dictionary), 1024).expect("decompress with");
+        pretty_assert_eq!((plain, with), (content.to_vec(), content.to_vec()));
+
+        let compressed = dictionary.compress(content).expect("compress");
+        assert!(decompress(&compressed, None, 1024).is_err());
+    }
+}
@@ -1,6 +1,12 @@
 //! HTTP client for the Courier v1 API.
 
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
 use async_compression::{
     Level,
@@ -9,26 +15,45 @@ use async_compression::{
 use async_tar::Archive;
 use color_eyre::{
     Result, Section, SectionExt,
-    eyre::{Context, eyre},
+    eyre::{Context, Report, bail, eyre},
 };
 use derive_more::{Debug, Display};
 use futures::{AsyncWriteExt, Stream, StreamExt, TryStreamExt};
-use reqwest::{Response, StatusCode};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::{Serialize, de::DeserializeOwned};
 use tap::Pipe;
 use tokio::io::{AsyncRead, BufReader};
 use tokio_util::{
     compat::{FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt},
     io::{ReaderStream, StreamReader},
 };
-use tracing::{Instrument, instrument, warn};
+use tracing::{Instrument, debug, instrument, warn};
 use url::Url;
 
 use crate::{
-    ContentType, NETWORK_BUFFER_SIZE, Token,
+    BufferSizes, ContentType, Token,
     courier::v1::{
-        Key,
-        cache::{CargoRestoreRequest, CargoRestoreResponse, CargoSaveRequest},
-        cas::{CasBulkReadRequest, CasBulkWriteResponse},
+        CacheScope, ConnectionPool, ConnectionStats, HashAlgorithm, Key, RetryPolicy,
+        SavedUnitHash, TimeoutClass, Timeouts,
+        access::AccessTokenResponse,
+        cache::{
+            CacheReadOnly, CargoFinalizeRequest, CargoGenerationResponse, CargoListRequest,
+            CargoListResponse, CargoReadOnly, CargoRestoreRequest, CargoRestoreResponse,
+            CargoSaveRequest, CargoScopeResetRequest, CargoScopeResetResponse, CargoStorageUsage,
+            CargoUnitOriginsResponse, CargoWritePolicy, QUOTA_EXCEEDED_HEADER, READ_ONLY_HEADER,
+            SAVE_STREAM_THRESHOLD, StorageQuotaExceeded, decode_binary, encode_binary,
+        },
+        cas::{
+            self, CasAlgorithmsResponse, CasBulkReadRequest, CasBulkWriteResponse, CasDictionary,
+            CasDictionaryListResponse, CasDirectRequest, CasDirectResponse, CasUploadStatus,
+            DICTIONARY_MAX_OBJECT_SIZE, Dictionary, UPLOAD_OFFSET_HEADER,
+        },
+        credentials::AccessTokens,
+        device::{
+            DeviceAuthorizationRequest, DeviceAuthorizationResponse, DeviceTokenPoll,
+            DeviceTokenRequest, DeviceTokenResponse,
+        },
+        traffic::{TRAFFIC_CLASS_HEADER, TrafficClass},
     },
 };
 
@@ -40,12 +65,28 @@ use crate::{
 /// decompressed blob.
 const MAX_DECOMPRESSED_SIZE: usize = 1024 * 1024 * 1024;
 
+/// The size of each chunk of a resumable upload, before compression.
+const UPLOAD_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// The most consecutive times a chunk of a resumable upload can fail before
+/// the upload gives up.
+const UPLOAD_CHUNK_ATTEMPTS: u32 = 5;
+
+/// The delay before retrying the first failed chunk of a resumable upload.
+/// The delay doubles after each consecutive failure.
+const UPLOAD_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// The most objects read directly from object storage at once during a bulk
+/// read.
+const DIRECT_READ_CONCURRENCY: usize = 8;
+
 /// Client for the Courier API.
 ///
 /// ## Cloning
 ///
 /// This type is cheaply cloneable, and clones share the underlying HTTP
-/// connection pool.
+/// connection pool. To share connections between clients with different base
+/// URLs or tokens, create them from the same [`ConnectionPool`].
 #[derive(Clone, Debug, Display)]
 #[display("{base}")]
 pub struct Client {
@@ -55,29 +96,192 @@ pub struct Client {
     #[debug(skip)]
     http: reqwest::Client,
 
+    #[debug(skip)]
+    pool: ConnectionPool,
+
     token: Token,
+
+    buffers: BufferSizes,
+
+    traffic_class: TrafficClass,
+
+    /// The cache scope the client's builds save and restore units in.
+    cache_scope: Option<CacheScope>,
+
+    /// How idempotent requests are retried.
+    retry: RetryPolicy,
+
+    timeouts: Timeouts,
+
+    /// Whether cache messages are sent as [`ContentType::MsgPackZstd`]. This
+    /// is cleared when Courier turns out not to support it, and shared
+    /// between clones so that they don't each find out separately.
+    #[debug(skip)]
+    binary_messages: Arc<AtomicBool>,
+
+    /// Whether to ask Courier for URLs that transfer CAS objects directly to
+    /// and from its object storage. This is cleared when Courier turns out not
+    /// to have object storage, and shared between clones like
+    /// `binary_messages`.
+    #[debug(skip)]
+    direct_transfers: Arc<AtomicBool>,
+
+    /// The access token issued for `token`, shared between clones; see
+    /// [`Client::access_token_exchange`].
+    #[debug(skip)]
+    access: AccessTokens,
 }
 impl Client {
     /// Create a new client with the given base URL and authentication token.
+    ///
+    /// The client uses its own connection pool with the default options; to
+    /// change them, create the client from a pool made with
+    /// [`ConnectionPool::with_options`].
     pub fn new(base: Url, token: Token) -> Result<Self> {
-        let http = reqwest::Client::builder()
-            .gzip(true)
-            .brotli(true)
-            .build()
-            .context("build http client")?;
+        ConnectionPool::new()?.client(base, token).pipe(Ok)
+    }
 
-        Ok(Self {
+    pub(crate) fn from_pool(pool: ConnectionPool, base: Url, token: Token) -> Self {
+        Self {
             base: Arc::new(base),
-            http,
+            http: pool.http().clone(),
+            pool,
             token,
-        })
+            buffers: BufferSizes::default(),
+            traffic_class: TrafficClass::default(),
+            cache_scope: None,
+            retry: RetryPolicy::default(),
+            timeouts: Timeouts::default(),
+            binary_messages: Arc::new(AtomicBool::new(true)),
+            direct_transfers: Arc::new(AtomicBool::new(true)),
+            access: AccessTokens::default(),
+        }
+    }
+
+    /// Send cache messages as JSON rather than in the compact binary encoding,
+    /// e.g. to inspect them while debugging.
+    pub fn with_json_messages(mut self) -> Self {
+        self.binary_messages = Arc::new(AtomicBool::new(false));
+        self
+    }
+
+    /// Authenticate every request with the API key, rather than with access
+    /// tokens issued for it.
+    pub fn without_access_tokens(mut self) -> Self {
+        self.access = AccessTokens::disabled();
+        self
+    }
+
+    /// Use the provided buffer sizes for streaming requests and responses.
+    pub fn with_buffer_sizes(mut self, buffers: BufferSizes) -> Self {
+        self.buffers = buffers;
+        self
+    }
+
+    /// Declare the class of traffic the client's requests belong to, so that
+    /// Courier can prioritize them; requests are interactive by default.
+    pub fn with_traffic_class(mut self, class: TrafficClass) -> Self {
+        self.traffic_class = class;
+        self
+    }
+
+    /// Save and restore units in the provided cache scope, or in the
+    /// organization's unscoped cache if there's no scope.
+    ///
+    /// The scope is part of the hash under which units are saved, which
+    /// callers derive themselves; see [`Client::cache_scope`].
+    pub fn with_cache_scope(mut self, scope: Option<CacheScope>) -> Self {
+        self.cache_scope = scope;
+        self
+    }
+
+    /// The cache scope the client saves and restores units in.
+    pub fn cache_scope(&self) -> Option<&CacheScope> {
+        self.cache_scope.as_ref()
+    }
+
+    /// Retry idempotent requests (restores, CAS reads, and existence checks)
+    /// that fail with the provided policy; see [`RetryPolicy`].
+    ///
+    /// Requests that write are never retried, since Courier may have acted on
+    /// a request whose response was lost.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Use the provided timeouts for each class of request; see [`Timeouts`].
+    ///
+    /// The connect timeout and pool size are shared by every client of a
+    /// pool, so they're set on the pool with [`ConnectionPool::with_options`].
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Connection statistics for the client's connection pool.
+    pub fn stats(&self) -> ConnectionStats {
+        self.pool.stats()
+    }
+
+    /// Open a connection to Courier ahead of time, so that later requests
+    /// don't pay for the TCP and TLS handshakes.
+    #[instrument(skip(self))]
+    pub async fn warm(&self) -> Result<()> {
+        self.pool.record_warmup();
+        self.ping().await.context("warm connection")
+    }
+
+    /// Exchange the client's API key for a short-lived access token, or
+    /// `None` if Courier doesn't issue them.
+    ///
+    /// The client does this on its own and authenticates with the access
+    /// token while it's valid, so this is only needed to inspect the token.
+    /// See [`crate::courier::v1::access`].
+    #[instrument(skip(self))]
+    pub async fn access_token_exchange(&self) -> Result<Option<AccessTokenResponse>> {
+        let url = self.base.join("api/v1/access/exchange")?;
+        let response = self
+            .send_idempotent(|| {
+                // Access tokens can't be exchanged, so this always uses the
+                // API key.
+                self.http
+                    .post(url.clone())
+                    .timeout(self.timeouts.get(TimeoutClass::Api))
+                    .bearer_auth(self.token.expose())
+                    .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            })
+            .await?;
+        match response.status() {
+            StatusCode::OK => response
+                .json::<AccessTokenResponse>()
+                .await
+                .context("parse JSON response")
+                .map(Some),
+            StatusCode::NOT_FOUND => Ok(None),
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
     }
 
     /// Check that the service is reachable.
     #[instrument(skip(self))]
     pub async fn ping(&self) -> Result<()> {
         let url = self.base.join("api/v1/health")?;
-        let response = self.http.get(url).send().await.context("request")?;
+        let response = self
+            .http
+            .get(url)
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .send()
+            .await
+            .context("request")?;
         match response.status() {
             StatusCode::OK => Ok(()),
             status => {
@@ -92,21 +296,684 @@ impl Client {
         }
     }
 
+    /// Start a device authorization for a device with the given name.
+    ///
+    /// This doesn't need the client's token, so the client can be created
+    /// with any token while it doesn't have one yet.
+    #[instrument(skip(self))]
+    pub async fn device_authorization_start(
+        &self,
+        client_name: &str,
+    ) -> Result<DeviceAuthorizationResponse> {
+        let url = self.base.join("api/v1/oauth/device")?;
+        let response = self
+            .http
+            .post(url)
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .json(&DeviceAuthorizationRequest {
+                client_name: client_name.to_string(),
+            })
+            .send()
+            .await
+            .context("send")?;
+
+        match response.status() {
+            StatusCode::CREATED => response
+                .json::<DeviceAuthorizationResponse>()
+                .await
+                .context("parse JSON response"),
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Poll a device authorization started with
+    /// [`Client::device_authorization_start`].
+    ///
+    /// Like starting the authorization, this doesn't need the client's token.
+    #[instrument(skip(self, device_code))]
+    pub async fn device_authorization_poll(&self, device_code: &Token) -> Result<DeviceTokenPoll> {
+        let url = self.base.join("api/v1/oauth/device/token")?;
+        let response = self
+            .http
+            .post(url)
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .json(&DeviceTokenRequest {
+                device_code: device_code.clone(),
+            })
+            .send()
+            .await
+            .context("send")?;
+
+        match response.status() {
+            StatusCode::OK => response
+                .json::<DeviceTokenResponse>()
+                .await
+                .context("parse JSON response")
+                .map(DeviceTokenPoll::Approved),
+            StatusCode::ACCEPTED => Ok(DeviceTokenPoll::Pending),
+            StatusCode::TOO_MANY_REQUESTS => Ok(DeviceTokenPoll::SlowDown),
+            StatusCode::GONE | StatusCode::NOT_FOUND => Ok(DeviceTokenPoll::Expired),
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
     /// Save cargo cache metadata.
+    ///
+    /// Requests larger than [`SAVE_STREAM_THRESHOLD`] are sent with
+    /// [`Client::cargo_cache_save_stream`], unless Courier doesn't support
+    /// streaming saves.
     #[instrument(skip(self))]
     pub async fn cargo_cache_save(&self, body: CargoSaveRequest) -> Result<()> {
+        let json = serde_json::to_vec(&body).context("serialize request")?;
+        if json.len() > SAVE_STREAM_THRESHOLD {
+            if self.send_cargo_cache_save_stream(&body).await? {
+                return Ok(());
+            }
+            warn!(
+                size = json.len(),
+                "courier does not support streaming saves, falling back to JSON"
+            );
+        }
+
         let url = self.base.join("api/v1/cache/cargo/save")?;
+        let response = self
+            .post_message(url, &body, TimeoutClass::Transfer, &RetryPolicy::none())
+            .await?;
+
+        match response.status() {
+            StatusCode::CREATED => Ok(()),
+            StatusCode::FORBIDDEN => {
+                bail!("this branch can't save units to the organization's cache")
+            }
+            StatusCode::SERVICE_UNAVAILABLE if is_read_only(&response) => {
+                Err(read_only_error(response).await)
+            }
+            StatusCode::PAYLOAD_TOO_LARGE if is_quota_exceeded(&response) => {
+                Err(quota_exceeded_error(response).await)
+            }
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Save cargo cache metadata, streaming the units as newline-delimited
+    /// JSON.
+    ///
+    /// Courier inserts the units as they arrive, so this isn't subject to the
+    /// size limit on JSON requests. The request's metadata is sent in the query
+    /// string, since it applies to every unit in the stream. Prefer
+    /// [`Client::cargo_cache_save`], which uses this automatically for large
+    /// requests.
+    #[instrument(skip(self))]
+    pub async fn cargo_cache_save_stream(&self, body: CargoSaveRequest) -> Result<()> {
+        if !self.send_cargo_cache_save_stream(&body).await? {
+            bail!("courier does not support streaming saves");
+        }
+        Ok(())
+    }
+
+    /// Finalize the units saved by a build, so that they can be restored.
+    ///
+    /// Call this once every unit the build saves has been saved with the
+    /// build's [`SavedUnitMetadata::build_id`]. Finalizing is idempotent, so
+    /// it's retried like a read. Courier instances that predate build IDs
+    /// restore units as soon as they're saved, so there's nothing to finalize.
+    ///
+    /// [`SavedUnitMetadata::build_id`]: crate::courier::v1::cache::SavedUnitMetadata::build_id
+    #[instrument(skip(self))]
+    pub async fn cargo_cache_finalize(&self, build_id: &str) -> Result<()> {
+        let url = self.base.join("api/v1/cache/cargo/save/finalize")?;
+        let body = CargoFinalizeRequest::new(build_id);
+        let response = self
+            .send_idempotent(|| {
+                self.http
+                    .post(url.clone())
+                    .timeout(self.timeouts.get(TimeoutClass::Api))
+                    .bearer_auth(self.bearer())
+                    .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+                    .json(&body)
+            })
+            .await?;
+
+        match response.status() {
+            StatusCode::NO_CONTENT => Ok(()),
+            StatusCode::NOT_FOUND => {
+                debug!("courier does not support build IDs, nothing to finalize");
+                Ok(())
+            }
+            StatusCode::SERVICE_UNAVAILABLE if is_read_only(&response) => {
+                Err(read_only_error(response).await)
+            }
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Post a cache message, asking for a response in the same encoding.
+    ///
+    /// Messages are sent as [`ContentType::MsgPackZstd`] unless Courier
+    /// rejects it, in which case they're sent as JSON from then on. Failed
+    /// requests are retried with the provided policy.
+    async fn post_message(
+        &self,
+        url: Url,
+        message: &impl Serialize,
+        class: TimeoutClass,
+        retry: &RetryPolicy,
+    ) -> Result<Response> {
+        if self.binary_messages.load(Ordering::Relaxed) {
+            let body = encode_binary(message).context("encode request")?;
+            let response = self
+                .send_with_retries(retry, || {
+                    self.http
+                        .post(url.clone())
+                        .timeout(self.timeouts.get(class))
+                        .bearer_auth(self.bearer())
+                        .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+                        .header(ContentType::HEADER, ContentType::MsgPackZstd.value())
+                        .header(ContentType::ACCEPT, ContentType::MsgPackZstd.value())
+                        .body(body.clone())
+                })
+                .await?;
+            if response.status() != StatusCode::UNSUPPORTED_MEDIA_TYPE {
+                return Ok(response);
+            }
+            warn!("courier does not support binary messages, falling back to JSON");
+            self.binary_messages.store(false, Ordering::Relaxed);
+        }
+
+        let json = serde_json::to_vec(message).context("serialize request")?;
+        self.send_with_retries(retry, || {
+            self.http
+                .post(url.clone())
+                .timeout(self.timeouts.get(class))
+                .bearer_auth(self.bearer())
+                .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+                .header(ContentType::HEADER, ContentType::Json.value())
+                .body(json.clone())
+        })
+        .await
+    }
+
+    /// Send an idempotent request, retrying it with the client's retry policy.
+    async fn send_idempotent(&self, request: impl Fn() -> RequestBuilder) -> Result<Response> {
+        self.send_with_retries(&self.retry, request).await
+    }
+
+    /// Send a request, building it again for each attempt, until it succeeds,
+    /// fails in a way that isn't retryable, or runs out of attempts.
+    ///
+    /// Responses with a status that isn't retryable are returned as they are,
+    /// so that callers handle them as usual.
+    async fn send_with_retries(
+        &self,
+        retry: &RetryPolicy,
+        request: impl Fn() -> RequestBuilder,
+    ) -> Result<Response> {
+        let mut attempt = 1;
+        loop {
+            let delay = match request().send().await {
+                Ok(response)
+                    if retry.can_retry(attempt) && retry.is_retryable_status(response.status()) =>
+                {
+                    let delay = retry.delay_for(attempt, &response);
+                    warn!(
+                        status = %response.status(),
+                        url = %response.url(),
+                        request_id = %request_id(&response),
+                        attempt,
+                        ?delay,
+                        "request failed, retrying"
+                    );
+                    delay
+                }
+                Ok(response)
+                    if response.status() == StatusCode::UNAUTHORIZED && self.access.rejected() =>
+                {
+                    debug!(url = %response.url(), "access token rejected, using the API key");
+                    continue;
+                }
+                Ok(response) => return Ok(response),
+                Err(err) if retry.can_retry(attempt) && RetryPolicy::is_retryable_error(&err) => {
+                    let delay = retry.delay(attempt);
+                    warn!(?err, attempt, ?delay, "request failed, retrying");
+                    delay
+                }
+                Err(err) => {
+                    return Err(err)
+                        .context("send")
+                        .with_section(|| attempt.to_string().header("Attempts:"));
+                }
+            };
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// The credential that authenticates requests: the current access token
+    /// if there is one, and otherwise the API key.
+    ///
+    /// If the access token is due to be exchanged again, this starts the
+    /// exchange in the background, so that requests don't wait for it.
+    fn bearer(&self) -> String {
+        if self.access.claim_exchange() {
+            match tokio::runtime::Handle::try_current() {
+                Ok(runtime) => {
+                    runtime.spawn(self.clone().refresh_access_token());
+                }
+                Err(_) => self.access.failed(),
+            }
+        }
+        match self.access.current() {
+            Some(token) => token.expose().to_string(),
+            None => self.token.expose().to_string(),
+        }
+    }
+
+    /// Exchange the API key for a new access token and cache it.
+    async fn refresh_access_token(self) {
+        match self.access_token_exchange().await {
+            Ok(Some(issued)) => {
+                debug!(
+                    expires_in = issued.expires_in,
+                    "exchanged API key for access token"
+                );
+                self.access.issued(
+                    issued.token,
+                    std::time::Duration::from_secs(issued.expires_in),
+                );
+            }
+            Ok(None) => {
+                debug!("courier does not issue access tokens, using the API key");
+                self.access.disable();
+            }
+            Err(err) => {
+                warn!(?err, "failed to exchange API key for access token");
+                self.access.failed();
+            }
+        }
+    }
+
+    /// Send a streaming save request, returning `false` if Courier doesn't
+    /// support them.
+    async fn send_cargo_cache_save_stream(&self, body: &CargoSaveRequest) -> Result<bool> {
+        // Serializing each unit separately means the request is never
+        // buffered as one contiguous allocation, which matters at the sizes
+        // where streaming is used.
+        let lines = body
+            .iter()
+            .map(|unit| {
+                let mut line = serde_json::to_vec(unit).context("serialize unit")?;
+                line.push(b'\n');
+                Ok(line)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let stream = futures::stream::iter(lines.into_iter().map(Ok::<_, std::io::Error>))
+            .pipe(reqwest::Body::wrap_stream);
+
+        let url = self.base.join("api/v1/cache/cargo/save/stream")?;
         let response = self
             .http
             .post(url)
-            .bearer_auth(self.token.expose())
+            .timeout(self.timeouts.get(TimeoutClass::Transfer))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            .query(body.metadata())
+            .header(ContentType::HEADER, ContentType::NdJson.value())
+            .body(stream)
+            .send()
+            .await
+            .context("send")?;
+
+        match response.status() {
+            StatusCode::CREATED => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            StatusCode::FORBIDDEN => {
+                bail!("this branch can't save units to the organization's cache")
+            }
+            StatusCode::SERVICE_UNAVAILABLE if is_read_only(&response) => {
+                Err(read_only_error(response).await)
+            }
+            StatusCode::PAYLOAD_TOO_LARGE if is_quota_exceeded(&response) => {
+                Err(quota_exceeded_error(response).await)
+            }
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Restore cargo cache metadata.
+    #[instrument(skip_all)]
+    pub async fn cargo_cache_restore(
+        &self,
+        body: CargoRestoreRequest,
+    ) -> Result<CargoRestoreResponse> {
+        let url = self.base.join("api/v1/cache/cargo/restore")?;
+        let response = self
+            .post_message(url, &body, TimeoutClass::Api, &self.retry)
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => read_message::<CargoRestoreResponse>(response).await,
+            StatusCode::NOT_FOUND => Ok(CargoRestoreResponse::default()),
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// List a page of the units saved in the cache, in the order they were
+    /// saved.
+    #[instrument(skip(self))]
+    pub async fn cargo_cache_list(&self, body: CargoListRequest) -> Result<CargoListResponse> {
+        let url = self.base.join("api/v1/cache/cargo/list")?;
+        let response = self
+            .http
+            .post(url)
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
             .json(&body)
             .send()
             .await
             .context("send")?;
 
         match response.status() {
-            StatusCode::CREATED => Ok(()),
+            StatusCode::OK => response
+                .json::<CargoListResponse>()
+                .await
+                .context("parse JSON response")?
+                .pipe(Ok),
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Get the organization's cache generation.
+    ///
+    /// Courier instances that predate generations don't have this endpoint,
+    /// and only have the initial generation.
+    #[instrument(skip(self))]
+    pub async fn cargo_cache_generation(&self) -> Result<CargoGenerationResponse> {
+        let url = self.base.join("api/v1/cache/cargo/generation")?;
+        let response = self
+            .send_idempotent(|| {
+                self.http
+                    .get(url.clone())
+                    .timeout(self.timeouts.get(TimeoutClass::Api))
+                    .bearer_auth(self.bearer())
+                    .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            })
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => response
+                .json::<CargoGenerationResponse>()
+                .await
+                .context("parse JSON response"),
+            StatusCode::NOT_FOUND => Ok(CargoGenerationResponse::default()),
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Bump the organization's cache generation, invalidating every unit
+    /// saved before the bump. Only organization admins can do this.
+    ///
+    /// Returns the new generation.
+    #[instrument(skip(self))]
+    pub async fn cargo_cache_bump_generation(&self) -> Result<CargoGenerationResponse> {
+        let url = self.base.join("api/v1/cache/cargo/generation/bump")?;
+        let response = self
+            .http
+            .post(url)
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            .send()
+            .await
+            .context("send")?;
+
+        match response.status() {
+            StatusCode::OK => response
+                .json::<CargoGenerationResponse>()
+                .await
+                .context("parse JSON response"),
+            StatusCode::FORBIDDEN => {
+                bail!("only organization admins can bump the cache generation")
+            }
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Get the organization's cache write policy.
+    ///
+    /// Courier instances that predate write policies don't have this
+    /// endpoint, and let every build save units.
+    #[instrument(skip(self))]
+    pub async fn cargo_cache_write_policy(&self) -> Result<CargoWritePolicy> {
+        let url = self.base.join("api/v1/cache/cargo/write-policy")?;
+        let response = self
+            .http
+            .get(url)
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            .send()
+            .await
+            .context("send")?;
+
+        match response.status() {
+            StatusCode::OK => response
+                .json::<CargoWritePolicy>()
+                .await
+                .context("parse JSON response"),
+            StatusCode::NOT_FOUND => Ok(CargoWritePolicy::unrestricted()),
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Replace the organization's cache write policy. Only organization
+    /// admins can do this.
+    #[instrument(skip(self))]
+    pub async fn cargo_cache_set_write_policy(&self, policy: &CargoWritePolicy) -> Result<()> {
+        let url = self.base.join("api/v1/cache/cargo/write-policy")?;
+        let response = self
+            .http
+            .put(url)
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            .json(policy)
+            .send()
+            .await
+            .context("send")?;
+
+        match response.status() {
+            StatusCode::OK => Ok(()),
+            StatusCode::FORBIDDEN => {
+                bail!("only organization admins can change the cache write policy")
+            }
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Get whether the organization's cache is read-only, because either the
+    /// organization or the whole Courier instance is under maintenance.
+    ///
+    /// Courier instances that predate read-only mode don't have this
+    /// endpoint, and always accept writes.
+    #[instrument(skip(self))]
+    pub async fn cargo_cache_read_only(&self) -> Result<CargoReadOnly> {
+        let url = self.base.join("api/v1/cache/cargo/read-only")?;
+        let response = self
+            .http
+            .get(url)
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            .send()
+            .await
+            .context("send")?;
+
+        match response.status() {
+            StatusCode::OK => response
+                .json::<CargoReadOnly>()
+                .await
+                .context("parse JSON response"),
+            StatusCode::NOT_FOUND => Ok(CargoReadOnly::writable()),
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Get how much the organization stores in the cache, and its quota.
+    ///
+    /// Courier instances that predate storage quotas don't have this
+    /// endpoint.
+    #[instrument(skip(self))]
+    pub async fn cargo_cache_usage(&self) -> Result<Option<CargoStorageUsage>> {
+        let url = self.base.join("api/v1/cache/cargo/usage")?;
+        let response = self
+            .http
+            .get(url)
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            .send()
+            .await
+            .context("send")?;
+
+        match response.status() {
+            StatusCode::OK => response
+                .json::<CargoStorageUsage>()
+                .await
+                .context("parse JSON response")
+                .map(Some),
+            StatusCode::NOT_FOUND => Ok(None),
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Make the organization's cache read-only, or let it accept writes
+    /// again. Only organization admins can do this.
+    ///
+    /// This doesn't affect an instance that's read-only as a whole.
+    #[instrument(skip(self))]
+    pub async fn cargo_cache_set_read_only(&self, read_only: &CargoReadOnly) -> Result<()> {
+        let url = self.base.join("api/v1/cache/cargo/read-only")?;
+        let response = self
+            .http
+            .put(url)
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            .json(read_only)
+            .send()
+            .await
+            .context("send")?;
+
+        match response.status() {
+            StatusCode::OK => Ok(()),
+            StatusCode::FORBIDDEN => {
+                bail!("only organization admins can make the cache read-only")
+            }
             status => {
                 let url = response.url().to_string();
                 let request_id = request_id(&response);
@@ -119,29 +986,31 @@ impl Client {
         }
     }
 
-    /// Restore cargo cache metadata.
-    #[instrument(skip_all)]
-    pub async fn cargo_cache_restore(
+    /// List the uploads of a saved unit, most recent first.
+    #[instrument(skip(self))]
+    pub async fn cargo_unit_origins(
         &self,
-        body: CargoRestoreRequest,
-    ) -> Result<CargoRestoreResponse> {
-        let url = self.base.join("api/v1/cache/cargo/restore")?;
+        unit_hash: &SavedUnitHash,
+    ) -> Result<CargoUnitOriginsResponse> {
+        let url = self
+            .base
+            .join(&format!("api/v1/cargo/unit/{unit_hash}/origins"))?;
         let response = self
             .http
-            .post(url)
-            .bearer_auth(self.token.expose())
-            .json(&body)
+            .get(url)
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
             .send()
             .await
             .context("send")?;
 
         match response.status() {
             StatusCode::OK => response
-                .json::<CargoRestoreResponse>()
+                .json::<CargoUnitOriginsResponse>()
                 .await
                 .context("parse JSON response")?
                 .pipe(Ok),
-            StatusCode::NOT_FOUND => Ok(CargoRestoreResponse::default()),
             status => {
                 let url = response.url().to_string();
                 let request_id = request_id(&response);
@@ -159,12 +1028,14 @@ impl Client {
     pub async fn cas_exists(&self, key: &Key) -> Result<bool> {
         let url = self.base.join(&format!("api/v1/cas/{key}"))?;
         let response = self
-            .http
-            .head(url)
-            .bearer_auth(self.token.expose())
-            .send()
-            .await
-            .context("send")?;
+            .send_idempotent(|| {
+                self.http
+                    .head(url.clone())
+                    .timeout(self.timeouts.get(TimeoutClass::Api))
+                    .bearer_auth(self.bearer())
+                    .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            })
+            .await?;
         match response.status() {
             StatusCode::OK => Ok(true),
             StatusCode::NOT_FOUND => Ok(false),
@@ -185,13 +1056,15 @@ impl Client {
     pub async fn cas_read(&self, key: &Key) -> Result<Option<impl AsyncRead + Unpin>> {
         let url = self.base.join(&format!("api/v1/cas/{key}"))?;
         let response = self
-            .http
-            .get(url)
-            .bearer_auth(self.token.expose())
-            .header(ContentType::ACCEPT, ContentType::BytesZstd.value())
-            .send()
-            .await
-            .context("send")?;
+            .send_idempotent(|| {
+                self.http
+                    .get(url.clone())
+                    .timeout(self.timeouts.get(TimeoutClass::Transfer))
+                    .bearer_auth(self.bearer())
+                    .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+                    .header(ContentType::ACCEPT, ContentType::BytesZstd.value())
+            })
+            .await?;
         match response.status() {
             StatusCode::OK => response
                 .bytes_stream()
@@ -224,13 +1097,15 @@ impl Client {
         let url = self.base.join(&format!("api/v1/cas/{key}"))?;
         let content = BufReader::new(content);
         let encoder = ZstdEncoder::with_quality(content, Level::Default);
-        let stream = ReaderStream::with_capacity(encoder, NETWORK_BUFFER_SIZE);
+        let stream = ReaderStream::with_capacity(encoder, self.buffers.network);
         let body = reqwest::Body::wrap_stream(stream);
 
         let response = self
             .http
             .put(url)
-            .bearer_auth(self.token.expose())
+            .timeout(self.timeouts.get(TimeoutClass::Transfer))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
             .header(ContentType::HEADER, ContentType::BytesZstd.value())
             .body(body)
             .send()
@@ -238,6 +1113,12 @@ impl Client {
             .context("send")?;
         match response.status() {
             StatusCode::CREATED => Ok(()),
+            StatusCode::SERVICE_UNAVAILABLE if is_read_only(&response) => {
+                Err(read_only_error(response).await)
+            }
+            StatusCode::PAYLOAD_TOO_LARGE if is_quota_exceeded(&response) => {
+                Err(quota_exceeded_error(response).await)
+            }
             status => {
                 let url = response.url().to_string();
                 let request_id = request_id(&response);
@@ -258,7 +1139,9 @@ impl Client {
         let response = self
             .http
             .put(url)
-            .bearer_auth(self.token.expose())
+            .timeout(self.timeouts.get(TimeoutClass::Transfer))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
             .header(ContentType::HEADER, ContentType::BytesZstd.value())
             .body(compressed)
             .send()
@@ -266,6 +1149,12 @@ impl Client {
             .context("send")?;
         match response.status() {
             StatusCode::CREATED => Ok(()),
+            StatusCode::SERVICE_UNAVAILABLE if is_read_only(&response) => {
+                Err(read_only_error(response).await)
+            }
+            StatusCode::PAYLOAD_TOO_LARGE if is_quota_exceeded(&response) => {
+                Err(quota_exceeded_error(response).await)
+            }
             status => {
                 let url = response.url().to_string();
                 let request_id = request_id(&response);
@@ -278,17 +1167,323 @@ impl Client {
         }
     }
 
-    /// Read a CAS object into a byte vector.
-    pub async fn cas_read_bytes(&self, key: &Key) -> Result<Option<Vec<u8>>> {
-        let url = self.base.join(&format!("api/v1/cas/{key}"))?;
+    /// Write a CAS object in chunks, resuming an earlier upload of it that
+    /// didn't finish.
+    ///
+    /// When a chunk fails, the upload continues from wherever Courier says
+    /// it is, so a dropped connection only costs the part of the chunk that
+    /// didn't arrive. Chunks that Courier rejects because the cache is
+    /// read-only aren't retried. Courier instances that don't support
+    /// resumable uploads are sent the object with [`Client::cas_write_bytes`]
+    /// instead.
+    ///
+    /// If Courier has object storage, the object is first uploaded directly
+    /// to it in one piece, and only sent in chunks if that fails.
+    #[instrument(name = "Client::cas_write_resumable", skip(content), fields(content = content.len()))]
+    pub async fn cas_write_resumable(&self, key: &Key, content: Vec<u8>) -> Result<()> {
+        if self.cas_write_direct(key, &content).await? {
+            return Ok(());
+        }
+
+        let Some(mut offset) = self.cas_upload_offset(key).await? else {
+            debug!("resumable uploads aren't supported");
+            return self.cas_write_bytes(key, content).await;
+        };
+        if offset > 0 {
+            debug!(offset, "resuming upload");
+        }
+
+        let len = content.len() as u64;
+        let mut failures = 0;
+        while offset < len {
+            let start = offset as usize;
+            let end = (start + UPLOAD_CHUNK_SIZE).min(content.len());
+            let appended = match self
+                .cas_upload_chunk(key, offset, &content[start..end])
+                .await
+            {
+                Ok(appended) if appended > offset => Ok(appended),
+                Ok(appended) => Err(eyre!("upload didn't advance past offset {appended}")),
+                Err(err) if err.downcast_ref::<CacheReadOnly>().is_some() => return Err(err),
+                Err(err) if err.downcast_ref::<StorageQuotaExceeded>().is_some() => {
+                    return Err(err);
+                }
+                Err(err) => match self.cas_upload_offset(key).await {
+                    Ok(Some(resumed)) if resumed > offset => Ok(resumed),
+                    _ => Err(err),
+                },
+            };
+            match appended {
+                Ok(appended) => {
+                    offset = appended;
+                    failures = 0;
+                }
+                Err(err) => {
+                    failures += 1;
+                    if failures >= UPLOAD_CHUNK_ATTEMPTS {
+                        return Err(err).context(format!("upload chunk at offset {offset}"));
+                    }
+                    let delay = UPLOAD_RETRY_DELAY * 2u32.pow(failures - 1);
+                    warn!(?err, offset, ?delay, "upload chunk failed, retrying");
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+
+        self.cas_upload_complete(key).await
+    }
+
+    /// Ask Courier for URLs that transfer CAS objects directly to and from its
+    /// object storage, or `None` if it doesn't have object storage.
+    ///
+    /// Objects whose write URLs are left out already exist, and the
+    /// organization has been granted access to them; see
+    /// [`CasDirectResponse`] for which read URLs are left out.
+    #[instrument(skip(self))]
+    pub async fn cas_direct(
+        &self,
+        request: &CasDirectRequest,
+    ) -> Result<Option<CasDirectResponse>> {
+        if !self.direct_transfers.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        if request.reads.is_empty() && request.writes.is_empty() {
+            return Ok(Some(CasDirectResponse::default()));
+        }
+
+        let url = self.base.join("api/v1/cas/direct")?;
+        let response = self
+            .send_idempotent(|| {
+                self.http
+                    .post(url.clone())
+                    .timeout(self.timeouts.get(TimeoutClass::Api))
+                    .bearer_auth(self.bearer())
+                    .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+                    .json(request)
+            })
+            .await?;
+        match response.status() {
+            StatusCode::OK => response
+                .json::<CasDirectResponse>()
+                .await
+                .context("parse JSON response")
+                .map(Some),
+            StatusCode::NOT_FOUND => {
+                debug!("courier does not have object storage, transferring through it");
+                self.direct_transfers.store(false, Ordering::Relaxed);
+                Ok(None)
+            }
+            StatusCode::SERVICE_UNAVAILABLE if is_read_only(&response) => {
+                Err(read_only_error(response).await)
+            }
+            StatusCode::PAYLOAD_TOO_LARGE if is_quota_exceeded(&response) => {
+                Err(quota_exceeded_error(response).await)
+            }
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Upload a CAS object directly to Courier's object storage, returning
+    /// `false` if it can't be, in which case it should be sent through
+    /// Courier instead.
+    ///
+    /// Returns `true` without uploading anything if the object already
+    /// exists.
+    #[instrument(skip(self, content), fields(content = content.len()))]
+    async fn cas_write_direct(&self, key: &Key, content: &[u8]) -> Result<bool> {
+        let request = CasDirectRequest::builder().writes([key]).build();
+        let Some(direct) = self.cas_direct(&request).await? else {
+            return Ok(false);
+        };
+        let Some(url) = direct.write_url(key) else {
+            debug!("object already exists");
+            return Ok(true);
+        };
+
+        let response = self
+            .http
+            .put(url)
+            .timeout(self.timeouts.get(TimeoutClass::Transfer))
+            .body(content.to_vec())
+            .send()
+            .await;
+        match response {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                warn!(%status, ?body, "direct upload failed, uploading through courier");
+                return Ok(false);
+            }
+            Err(err) => {
+                warn!(?err, "direct upload failed, uploading through courier");
+                return Ok(false);
+            }
+        }
+
+        self.cas_upload_complete(key).await.map(|()| true)
+    }
+
+    /// Read a CAS object directly from object storage with a URL from
+    /// [`Client::cas_direct`].
+    #[instrument(skip(self, url))]
+    async fn cas_read_direct(&self, key: &Key, url: &str) -> Result<Vec<u8>> {
+        let response = self
+            .send_idempotent(|| {
+                self.http
+                    .get(url)
+                    .timeout(self.timeouts.get(TimeoutClass::Transfer))
+            })
+            .await?;
+        match response.status() {
+            StatusCode::OK => {
+                let compressed = response.bytes().await.context("read body")?;
+                zstd::bulk::decompress(&compressed, MAX_DECOMPRESSED_SIZE)
+                    .with_context(|| format!("decompress object: {key}"))
+            }
+            status => {
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| key.to_string().header("Key:"))
+                    .with_section(|| body.header("Body:"))
+            }
+        }
+    }
+
+    /// How many bytes of the object Courier has received, or `None` if it
+    /// doesn't support resumable uploads.
+    #[instrument(skip(self))]
+    async fn cas_upload_offset(&self, key: &Key) -> Result<Option<u64>> {
+        let url = self.base.join(&format!("api/v1/cas/uploads/{key}"))?;
         let response = self
             .http
             .get(url)
-            .bearer_auth(self.token.expose())
-            .header(ContentType::ACCEPT, ContentType::BytesZstd.value())
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            .send()
+            .await
+            .context("send")?;
+        match response.status() {
+            StatusCode::OK => response
+                .json::<CasUploadStatus>()
+                .await
+                .context("parse")
+                .map(|status| Some(status.offset)),
+            StatusCode::NOT_FOUND => Ok(None),
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Send a chunk of a resumable upload that starts at `offset`, returning
+    /// how many bytes of the object Courier has received afterwards.
+    ///
+    /// If Courier isn't at `offset`, the chunk isn't appended and Courier's
+    /// actual offset is returned.
+    #[instrument(skip(self, chunk), fields(chunk = chunk.len()))]
+    async fn cas_upload_chunk(&self, key: &Key, offset: u64, chunk: &[u8]) -> Result<u64> {
+        let url = self.base.join(&format!("api/v1/cas/uploads/{key}"))?;
+        let compressed = zstd::bulk::compress(chunk, 0).context("compress chunk")?;
+        let response = self
+            .http
+            .patch(url)
+            .timeout(self.timeouts.get(TimeoutClass::Transfer))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            .header(ContentType::HEADER, ContentType::BytesZstd.value())
+            .header(UPLOAD_OFFSET_HEADER, offset.to_string())
+            .body(compressed)
+            .send()
+            .await
+            .context("send")?;
+        match response.status() {
+            StatusCode::OK | StatusCode::CONFLICT => response
+                .json::<CasUploadStatus>()
+                .await
+                .context("parse")
+                .map(|status| status.offset),
+            StatusCode::SERVICE_UNAVAILABLE if is_read_only(&response) => {
+                Err(read_only_error(response).await)
+            }
+            StatusCode::PAYLOAD_TOO_LARGE if is_quota_exceeded(&response) => {
+                Err(quota_exceeded_error(response).await)
+            }
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Finish a resumable upload, moving the received content into the CAS.
+    #[instrument(skip(self))]
+    async fn cas_upload_complete(&self, key: &Key) -> Result<()> {
+        let url = self
+            .base
+            .join(&format!("api/v1/cas/uploads/{key}/complete"))?;
+        let response = self
+            .http
+            .post(url)
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
             .send()
             .await
             .context("send")?;
+        match response.status() {
+            StatusCode::CREATED => Ok(()),
+            StatusCode::SERVICE_UNAVAILABLE if is_read_only(&response) => {
+                Err(read_only_error(response).await)
+            }
+            StatusCode::PAYLOAD_TOO_LARGE if is_quota_exceeded(&response) => {
+                Err(quota_exceeded_error(response).await)
+            }
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Read a CAS object into a byte vector.
+    pub async fn cas_read_bytes(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        let url = self.base.join(&format!("api/v1/cas/{key}"))?;
+        let response = self
+            .send_idempotent(|| {
+                self.http
+                    .get(url.clone())
+                    .timeout(self.timeouts.get(TimeoutClass::Transfer))
+                    .bearer_auth(self.bearer())
+                    .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+                    .header(ContentType::ACCEPT, ContentType::BytesZstd.value())
+            })
+            .await?;
         match response.status() {
             StatusCode::OK => {
                 let compressed = response.bytes().await.context("read body")?;
@@ -309,21 +1504,143 @@ impl Client {
         }
     }
 
+    /// List the compression dictionaries available to the organization.
+    #[instrument(skip(self))]
+    pub async fn cas_dictionaries(&self) -> Result<CasDictionaryListResponse> {
+        let url = self.base.join("api/v1/cas/dictionaries")?;
+        let response = self
+            .send_idempotent(|| {
+                self.http
+                    .get(url.clone())
+                    .timeout(self.timeouts.get(TimeoutClass::Api))
+                    .bearer_auth(self.bearer())
+                    .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            })
+            .await?;
+        match response.status() {
+            StatusCode::OK => response
+                .json::<CasDictionaryListResponse>()
+                .await
+                .context("parse"),
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// List the hash algorithms Courier accepts for CAS keys.
+    ///
+    /// Courier instances that predate configurable algorithms don't have this
+    /// endpoint, and only accept BLAKE3.
+    #[instrument(skip(self))]
+    pub async fn cas_algorithms(&self) -> Result<CasAlgorithmsResponse> {
+        let url = self.base.join("api/v1/cas/algorithms")?;
+        let response = self
+            .send_idempotent(|| {
+                self.http
+                    .get(url.clone())
+                    .timeout(self.timeouts.get(TimeoutClass::Api))
+                    .bearer_auth(self.bearer())
+                    .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            })
+            .await?;
+        match response.status() {
+            StatusCode::OK => response
+                .json::<CasAlgorithmsResponse>()
+                .await
+                .context("parse"),
+            StatusCode::NOT_FOUND => CasAlgorithmsResponse::builder()
+                .algorithms([HashAlgorithm::Blake3])
+                .build()
+                .pipe(Ok),
+            status => {
+                let url = response.url().to_string();
+                let request_id = request_id(&response);
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+                    .with_section(|| request_id.header("Request ID:"))
+            }
+        }
+    }
+
+    /// Check that Courier accepts keys hashed with the algorithm, returning
+    /// the algorithm if it does.
+    ///
+    /// Every Courier instance accepts BLAKE3, so that doesn't need a request.
+    /// Other algorithms are usually mandated by policy, so this fails rather
+    /// than falling back to BLAKE3 if Courier doesn't accept them.
+    #[instrument(skip(self))]
+    pub async fn negotiate_hash_algorithm(
+        &self,
+        algorithm: HashAlgorithm,
+    ) -> Result<HashAlgorithm> {
+        if algorithm == HashAlgorithm::Blake3 {
+            return Ok(algorithm);
+        }
+        let supported = self.cas_algorithms().await?;
+        if !supported.algorithms.contains(&algorithm) {
+            bail!("Courier does not accept {algorithm} keys");
+        }
+        Ok(algorithm)
+    }
+
+    /// Download and load a compression dictionary.
+    #[instrument(skip(self))]
+    pub async fn cas_dictionary(&self, dictionary: &CasDictionary) -> Result<Option<Dictionary>> {
+        let Some(content) = self.cas_read_bytes(&dictionary.key).await? else {
+            return Ok(None);
+        };
+        let loaded = Dictionary::new(content).context("load dictionary")?;
+        if loaded.key != dictionary.key || loaded.zstd_id != dictionary.zstd_id {
+            return Err(eyre!("dictionary does not match its listing"))
+                .with_section(|| format!("{dictionary:?}").header("Listed:"))
+                .with_section(|| format!("{loaded:?}").header("Loaded:"));
+        }
+        Ok(Some(loaded))
+    }
+
     /// Write multiple CAS objects from a tar archive.
     #[instrument(name = "Client::cas_write_bulk", skip(entries))]
     pub async fn cas_write_bulk(
+        &self,
+        entries: impl Stream<Item = (Key, Vec<u8>)> + Unpin + Send + 'static,
+    ) -> Result<CasBulkWriteResponse> {
+        self.cas_write_bulk_with_dictionary(entries, None).await
+    }
+
+    /// Write multiple CAS objects from a tar archive, compressing small
+    /// objects with the provided dictionary.
+    ///
+    /// Objects larger than [`DICTIONARY_MAX_OBJECT_SIZE`] are compressed
+    /// without the dictionary.
+    #[instrument(name = "Client::cas_write_bulk_with_dictionary", skip(entries))]
+    pub async fn cas_write_bulk_with_dictionary(
         &self,
         mut entries: impl Stream<Item = (Key, Vec<u8>)> + Unpin + Send + 'static,
+        dictionary: Option<Dictionary>,
     ) -> Result<CasBulkWriteResponse> {
         let url = self.base.join("api/v1/cas/bulk/write")?;
-        let (reader, writer) = piper::pipe(NETWORK_BUFFER_SIZE);
+        let (reader, writer) = piper::pipe(self.buffers.network);
         let span = tracing::info_span!("cas_bulk_write_worker");
         let writer = tokio::task::spawn(
             async move {
                 let mut tar = async_tar::Builder::new(writer);
                 while let Some((key, content)) = entries.next().await {
-                    let compressed = zstd::bulk::compress(&content, 0)
-                        .with_context(|| format!("compress entry: {key}"))?;
+                    let compressed = match &dictionary {
+                        Some(dictionary) if content.len() as u64 <= DICTIONARY_MAX_OBJECT_SIZE => {
+                            dictionary.compress(&content)
+                        }
+                        _ => zstd::bulk::compress(&content, 0).context("compress"),
+                    }
+                    .with_context(|| format!("compress entry: {key}"))?;
                     let mut header = async_tar::Header::new_gnu();
                     header.set_size(compressed.len() as u64);
                     header.set_mode(0o644);
@@ -339,12 +1656,14 @@ impl Client {
             .instrument(span),
         );
 
-        let stream = ReaderStream::with_capacity(reader.compat(), NETWORK_BUFFER_SIZE);
+        let stream = ReaderStream::with_capacity(reader.compat(), self.buffers.network);
         let body = reqwest::Body::wrap_stream(stream);
         let response = self
             .http
             .post(url)
-            .bearer_auth(self.token.expose())
+            .timeout(self.timeouts.get(TimeoutClass::Transfer))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
             .header(ContentType::HEADER, ContentType::TarZstd.value())
             .body(body)
             .send()
@@ -361,6 +1680,10 @@ impl Client {
                 .json::<CasBulkWriteResponse>()
                 .await
                 .context("parse")
+        } else if is_read_only(&response) {
+            Err(read_only_error(response).await)
+        } else if is_quota_exceeded(&response) {
+            Err(quota_exceeded_error(response).await)
         } else {
             let url = response.url().to_string();
             let request_id = request_id(&response);
@@ -378,17 +1701,69 @@ impl Client {
         &self,
         keys: impl IntoIterator<Item = impl Into<Key>>,
     ) -> Result<impl Stream<Item = Result<(Key, Vec<u8>)>> + Unpin> {
+        self.cas_read_bulk_with_dictionary(keys, None).await
+    }
+
+    /// Read multiple CAS objects as tar archive bytes, allowing Courier to
+    /// compress small objects with the provided dictionary.
+    ///
+    /// If Courier has object storage, large objects are read directly from it
+    /// instead of through Courier.
+    #[instrument(name = "Client::cas_read_bulk_with_dictionary", skip(keys))]
+    pub async fn cas_read_bulk_with_dictionary(
+        &self,
+        keys: impl IntoIterator<Item = impl Into<Key>>,
+        dictionary: Option<Dictionary>,
+    ) -> Result<impl Stream<Item = Result<(Key, Vec<u8>)>> + Unpin> {
+        let keys = keys.into_iter().map(Into::into).collect::<Vec<Key>>();
+        let request = CasDirectRequest::builder().reads(&keys).build();
+        let direct = match self.cas_direct(&request).await {
+            Ok(direct) => direct.map(|direct| direct.reads).unwrap_or_default(),
+            Err(err) => {
+                warn!(
+                    ?err,
+                    "failed to get direct read URLs, reading through courier"
+                );
+                Vec::new()
+            }
+        };
+        let direct_keys = direct
+            .iter()
+            .map(|direct| &direct.key)
+            .collect::<HashSet<_>>();
+        let keys = keys
+            .iter()
+            .filter(|key| !direct_keys.contains(key))
+            .cloned()
+            .collect::<Vec<_>>();
+        let client = self.clone();
+        let direct = futures::stream::iter(direct)
+            .map(move |direct| {
+                let client = client.clone();
+                async move {
+                    let content = client.cas_read_direct(&direct.key, &direct.url).await;
+                    content.map(|content| (direct.key, content))
+                }
+            })
+            .buffer_unordered(DIRECT_READ_CONCURRENCY)
+            .boxed();
+
         let url = self.base.join("api/v1/cas/bulk/read")?;
-        let request = CasBulkReadRequest::builder().keys(keys).build();
+        let request = CasBulkReadRequest::builder()
+            .keys(keys)
+            .maybe_dictionary(dictionary.as_ref().map(|d| &d.key))
+            .build();
         let response = self
-            .http
-            .post(url)
-            .bearer_auth(self.token.expose())
-            .header(ContentType::ACCEPT, ContentType::TarZstd.value())
-            .json(&request)
-            .send()
-            .await
-            .context("send")?;
+            .send_idempotent(|| {
+                self.http
+                    .post(url.clone())
+                    .timeout(self.timeouts.get(TimeoutClass::Transfer))
+                    .bearer_auth(self.bearer())
+                    .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+                    .header(ContentType::ACCEPT, ContentType::TarZstd.value())
+                    .json(&request)
+            })
+            .await?;
 
         let archive = response
             .bytes_stream()
@@ -421,9 +1796,12 @@ impl Client {
                             .await
                             .context("read compressed content")?;
 
-                        let decompressed =
-                            zstd::bulk::decompress(&compressed, MAX_DECOMPRESSED_SIZE)
-                                .with_context(|| format!("decompress entry: {key}"))?;
+                        let decompressed = cas::decompress(
+                            &compressed,
+                            dictionary.as_ref(),
+                            MAX_DECOMPRESSED_SIZE,
+                        )
+                        .with_context(|| format!("decompress entry: {key}"))?;
 
                         tx.send_async(Ok((key, decompressed)))
                             .await
@@ -442,7 +1820,43 @@ impl Client {
             .instrument(span),
         );
 
-        rx.into_stream().pipe(Ok)
+        rx.into_stream().chain(direct).pipe(Ok)
+    }
+
+    /// Reset a single cache scope, deleting the units saved in it.
+    ///
+    /// The objects the units reference are left for garbage collection, since
+    /// other scopes may share them. Courier instances that predate scopes
+    /// don't have this endpoint, and this fails rather than resetting the
+    /// whole cache.
+    #[instrument(skip(self))]
+    pub async fn cache_reset_scope(&self, scope: &CacheScope) -> Result<CargoScopeResetResponse> {
+        let url = self.base.join("api/v1/cache/cargo/reset/scope")?;
+        let response = self
+            .http
+            .post(url)
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
+            .json(&CargoScopeResetRequest::new(scope.clone()))
+            .send()
+            .await
+            .context("send")?;
+        match response.status() {
+            StatusCode::OK => response
+                .json::<CargoScopeResetResponse>()
+                .await
+                .context("parse JSON response"),
+            StatusCode::FORBIDDEN => bail!("only organization admins can reset a cache scope"),
+            StatusCode::NOT_FOUND => bail!("this Hurry API doesn't support cache scopes"),
+            status => {
+                let url = response.url().to_string();
+                let body = response.text().await.unwrap_or_default();
+                Err(eyre!("unexpected status code: {status}"))
+                    .with_section(|| url.header("Url:"))
+                    .with_section(|| body.header("Body:"))
+            }
+        }
     }
 
     /// Reset all cache data: delete all database records and CAS blobs.
@@ -452,7 +1866,9 @@ impl Client {
         let response = self
             .http
             .post(url)
-            .bearer_auth(self.token.expose())
+            .timeout(self.timeouts.get(TimeoutClass::Api))
+            .bearer_auth(self.bearer())
+            .header(TRAFFIC_CLASS_HEADER, self.traffic_class.as_str())
             .send()
             .await
             .context("send")?;
@@ -469,6 +1885,55 @@ impl Client {
     }
 }
 
+/// Read a cache message from the response, in whichever encoding Courier
+/// responded with.
+async fn read_message<T: DeserializeOwned>(response: Response) -> Result<T> {
+    let binary = response
+        .headers()
+        .get(ContentType::HEADER)
+        .is_some_and(|value| value == ContentType::MsgPackZstd);
+    if !binary {
+        return response.json::<T>().await.context("parse JSON response");
+    }
+    let body = response.bytes().await.context("read response")?;
+    decode_binary(&body, MAX_DECOMPRESSED_SIZE as u64).context("parse binary response")
+}
+
+/// Whether Courier rejected a write because the cache is read-only.
+fn is_read_only(response: &Response) -> bool {
+    response.status() == StatusCode::SERVICE_UNAVAILABLE
+        && response.headers().contains_key(READ_ONLY_HEADER)
+}
+
+/// The error for a write that Courier rejected because the cache is
+/// read-only.
+async fn read_only_error(response: Response) -> Report {
+    let reason = response
+        .json::<CargoReadOnly>()
+        .await
+        .ok()
+        .and_then(|body| body.reason)
+        .unwrap_or_else(|| String::from("no reason given"));
+    Report::new(CacheReadOnly::new(reason))
+}
+
+/// Whether Courier rejected a write because the organization's storage quota
+/// is used up.
+fn is_quota_exceeded(response: &Response) -> bool {
+    response.status() == StatusCode::PAYLOAD_TOO_LARGE
+        && response.headers().contains_key(QUOTA_EXCEEDED_HEADER)
+}
+
+/// The error for a write that Courier rejected because the organization's
+/// storage quota is used up.
+async fn quota_exceeded_error(response: Response) -> Report {
+    let usage = response
+        .json::<CargoStorageUsage>()
+        .await
+        .unwrap_or_default();
+    Report::new(StorageQuotaExceeded::new(usage))
+}
+
 /// Extract the request ID from a response header.
 fn request_id(response: &Response) -> String {
     response
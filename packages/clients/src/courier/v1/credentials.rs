@@ -0,0 +1,184 @@
+//! Caching access tokens issued for the client's API key.
+//!
+//! See [`crate::courier::v1::access`] for how access tokens work. The client
+//! authenticates with its API key until it has an access token, and exchanges
+//! the API key for a new access token in the background once the current one
+//! is close to expiring, so that requests never wait on the exchange.
+
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::Token;
+
+/// Access tokens are exchanged again once this share of their lifetime has
+/// passed, so that there's time to exchange them before they expire.
+const REFRESH_AFTER: f64 = 0.75;
+
+/// How long to wait before exchanging the API key again after an exchange
+/// fails.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// The client's cached access token, shared between clones.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AccessTokens {
+    state: Arc<Mutex<State>>,
+
+    /// Whether an exchange is running, so that only one runs at a time.
+    exchanging: Arc<AtomicBool>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Set once Courier turns out not to issue access tokens, or if the client
+    /// is configured not to use them.
+    disabled: bool,
+
+    /// The current access token, if any.
+    current: Option<Cached>,
+
+    /// When to exchange the API key next, if not right away.
+    next_exchange: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Cached {
+    token: Token,
+    expires_at: Instant,
+}
+
+impl AccessTokens {
+    /// A cache that never has an access token, so the client always uses its
+    /// API key.
+    pub(crate) fn disabled() -> Self {
+        let tokens = Self::default();
+        tokens.disable();
+        tokens
+    }
+
+    /// The current access token, if there is one that hasn't expired.
+    pub(crate) fn current(&self) -> Option<Token> {
+        let state = self.state.lock().expect("access token lock poisoned");
+        state
+            .current
+            .as_ref()
+            .filter(|cached| cached.expires_at > Instant::now())
+            .map(|cached| cached.token.clone())
+    }
+
+    /// Claim the next exchange if it's due, returning whether the caller
+    /// should exchange the API key; the caller must then report the outcome
+    /// with [`AccessTokens::issued`], [`AccessTokens::failed`], or
+    /// [`AccessTokens::disable`].
+    pub(crate) fn claim_exchange(&self) -> bool {
+        {
+            let state = self.state.lock().expect("access token lock poisoned");
+            let due = state
+                .next_exchange
+                .is_none_or(|next| next <= Instant::now());
+            if state.disabled || !due {
+                return false;
+            }
+        }
+        self.exchanging
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    /// Cache an access token that expires after the provided time.
+    pub(crate) fn issued(&self, token: Token, expires_in: Duration) {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("access token lock poisoned");
+        state.current = Some(Cached {
+            token,
+            expires_at: now + expires_in,
+        });
+        state.next_exchange = Some(now + expires_in.mul_f64(REFRESH_AFTER));
+        self.exchanging.store(false, Ordering::Release);
+    }
+
+    /// Record that an exchange failed, so that it's tried again later.
+    pub(crate) fn failed(&self) {
+        let mut state = self.state.lock().expect("access token lock poisoned");
+        state.next_exchange = Some(Instant::now() + RETRY_DELAY);
+        self.exchanging.store(false, Ordering::Release);
+    }
+
+    /// Stop using access tokens.
+    pub(crate) fn disable(&self) {
+        let mut state = self.state.lock().expect("access token lock poisoned");
+        state.disabled = true;
+        state.current = None;
+        self.exchanging.store(false, Ordering::Release);
+    }
+
+    /// Drop the current access token after Courier rejected it, e.g. because
+    /// the key it's signed with changed, returning whether there was one.
+    ///
+    /// The client uses its API key until the next exchange.
+    pub(crate) fn rejected(&self) -> bool {
+        let mut state = self.state.lock().expect("access token lock poisoned");
+        let had_token = state.current.take().is_some();
+        if had_token {
+            state.next_exchange = Some(Instant::now() + RETRY_DELAY);
+        }
+        had_token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::AccessTokens;
+    use crate::Token;
+
+    #[test]
+    fn exchanges_once_until_outcome() {
+        let tokens = AccessTokens::default();
+        assert!(tokens.claim_exchange());
+        assert!(!tokens.claim_exchange(), "exchange already running");
+
+        tokens.issued(Token::from("access"), Duration::from_secs(300));
+        pretty_assert_eq!(tokens.current(), Some(Token::from("access")));
+        assert!(!tokens.claim_exchange(), "token is fresh");
+    }
+
+    #[test]
+    fn expired_token_is_not_used() {
+        let tokens = AccessTokens::default();
+        assert!(tokens.claim_exchange());
+        tokens.issued(Token::from("access"), Duration::ZERO);
+        pretty_assert_eq!(tokens.current(), None);
+        assert!(tokens.claim_exchange(), "expired token is exchanged again");
+    }
+
+    #[test]
+    fn failures_and_rejections_back_off() {
+        let tokens = AccessTokens::default();
+        assert!(tokens.claim_exchange());
+        tokens.failed();
+        assert!(!tokens.claim_exchange());
+
+        let tokens = AccessTokens::default();
+        assert!(tokens.claim_exchange());
+        tokens.issued(Token::from("access"), Duration::from_secs(300));
+        assert!(tokens.rejected());
+        assert!(!tokens.rejected());
+        pretty_assert_eq!(tokens.current(), None);
+        assert!(!tokens.claim_exchange());
+    }
+
+    #[test]
+    fn disabled_never_exchanges() {
+        let tokens = AccessTokens::disabled();
+        assert!(!tokens.claim_exchange());
+        pretty_assert_eq!(tokens.current(), None);
+    }
+}
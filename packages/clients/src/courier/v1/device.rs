@@ -0,0 +1,74 @@
+//! Device authorization API types.
+//!
+//! Device authorization lets a CLI get an API token without the user pasting
+//! one in: the CLI starts an authorization, the user approves it in the
+//! console (where they're already signed in), and the CLI polls until the
+//! approval comes through and it receives a token.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Token;
+
+/// Request to start a device authorization.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DeviceAuthorizationRequest {
+    /// The name of the device, shown to the user when they approve the
+    /// authorization and used as the name of the API key it creates.
+    pub client_name: String,
+}
+
+/// A device authorization that is waiting for the user to approve it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DeviceAuthorizationResponse {
+    /// The code the device polls with. Keep this secret: whoever has it
+    /// receives the token once the user approves the authorization.
+    pub device_code: Token,
+
+    /// The code the user confirms when approving the authorization.
+    pub user_code: String,
+
+    /// The path of the console page where the user approves the
+    /// authorization, with the user code filled in. The console is served by
+    /// Courier, so this is relative to Courier's URL.
+    pub verification_path: String,
+
+    /// Seconds until the authorization expires.
+    pub expires_in: u64,
+
+    /// Seconds the device should wait between polls.
+    pub interval: u64,
+}
+
+/// Request to poll a device authorization.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DeviceTokenRequest {
+    /// The device code of the authorization.
+    pub device_code: Token,
+}
+
+/// The token issued for an approved device authorization.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DeviceTokenResponse {
+    /// The API token, which belongs to the organization the user chose.
+    pub token: Token,
+
+    /// The ID of the organization the token belongs to.
+    pub org_id: i64,
+}
+
+/// The outcome of polling a device authorization.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceTokenPoll {
+    /// The user approved the authorization.
+    Approved(DeviceTokenResponse),
+
+    /// The user hasn't approved the authorization yet; poll again after the
+    /// interval.
+    Pending,
+
+    /// The device is polling too often; poll again after a longer interval.
+    SlowDown,
+
+    /// The authorization expired, or was already claimed; start a new one.
+    Expired,
+}
@@ -0,0 +1,196 @@
+//! An in-memory fake of the Courier API for tests.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use color_eyre::{Result, eyre::bail};
+
+use crate::courier::v1::{
+    CourierApi, GlibcVersion, Key, SavedUnit, SavedUnitHash,
+    cache::{
+        CargoGenerationResponse, CargoRestoreRequest, CargoRestoreResponse, CargoSaveRequest,
+        CargoSaveUnitRequest,
+    },
+};
+
+/// An in-memory implementation of [`CourierApi`].
+///
+/// It behaves like a single organization's cache in Courier: saved units are
+/// restored under the hash they were saved with, filtered for glibc
+/// compatibility, and CAS writes are rejected if the content doesn't match
+/// its key.
+///
+/// ## Cloning
+///
+/// Clones share the same cache, so a test can keep a clone to inspect what
+/// the code under test saved.
+#[derive(Clone, Debug, Default)]
+pub struct FakeCourier {
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    units: HashMap<SavedUnitHash, CargoSaveUnitRequest>,
+
+    /// Units offered as near matches under each key, most recently saved
+    /// first.
+    near_matches: HashMap<String, Vec<CargoSaveUnitRequest>>,
+
+    objects: HashMap<Key, Vec<u8>>,
+    generation: u64,
+}
+
+impl FakeCourier {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the cache generation.
+    pub fn with_generation(self, generation: u64) -> Self {
+        self.state().generation = generation;
+        self
+    }
+
+    /// Bump the cache generation, returning the new generation.
+    pub fn bump_generation(&self) -> u64 {
+        let mut state = self.state();
+        state.generation += 1;
+        state.generation
+    }
+
+    /// The units saved in the cache, in no particular order.
+    pub fn saved_units(&self) -> Vec<CargoSaveUnitRequest> {
+        self.state().units.values().cloned().collect()
+    }
+
+    /// The content of a CAS object, if it exists.
+    pub fn object(&self, key: &Key) -> Option<Vec<u8>> {
+        self.state().objects.get(key).cloned()
+    }
+
+    fn state(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().expect("fake courier state poisoned")
+    }
+}
+
+impl CourierApi for FakeCourier {
+    async fn cargo_cache_save(&self, body: CargoSaveRequest) -> Result<()> {
+        let mut state = self.state();
+        for unit in body {
+            if let Some(key) = &unit.near_match_key {
+                state
+                    .near_matches
+                    .entry(key.clone())
+                    .or_default()
+                    .insert(0, unit.clone());
+            }
+            state.units.insert(unit.saved_unit_hash(), unit);
+        }
+        Ok(())
+    }
+
+    async fn cargo_cache_restore(&self, body: CargoRestoreRequest) -> Result<CargoRestoreResponse> {
+        let state = self.state();
+        let host = body.host_glibc_version.as_ref();
+        let units = body
+            .units
+            .iter()
+            .filter_map(|hash| state.units.get(hash).map(|unit| (hash, unit)))
+            .filter(|(_, unit)| glibc_compatible(host, unit.linux_glibc_version.as_ref()))
+            .map(|(hash, unit)| (hash.clone(), unit.unit.clone()))
+            .collect::<Vec<_>>();
+        let near_matches = body
+            .near_match_keys
+            .iter()
+            .filter_map(|key| {
+                let candidates = state
+                    .near_matches
+                    .get(key)?
+                    .iter()
+                    .filter(|unit| unit.cache_scope == body.cache_scope)
+                    .filter(|unit| unit.cache_generation == state.generation)
+                    .filter(|unit| glibc_compatible(host, unit.linux_glibc_version.as_ref()))
+                    .map(|unit| unit.unit.clone())
+                    .collect::<Vec<SavedUnit>>();
+                (!candidates.is_empty()).then(|| (key.clone(), candidates))
+            })
+            .collect::<HashMap<_, _>>();
+        Ok(CargoRestoreResponse::new(units).with_near_matches(near_matches))
+    }
+
+    async fn cargo_cache_generation(&self) -> Result<CargoGenerationResponse> {
+        Ok(CargoGenerationResponse::new(self.state().generation))
+    }
+
+    async fn cas_exists(&self, key: &Key) -> Result<bool> {
+        Ok(self.state().objects.contains_key(key))
+    }
+
+    async fn cas_read_bytes(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        Ok(self.object(key))
+    }
+
+    async fn cas_write_bytes(&self, key: &Key, body: Vec<u8>) -> Result<()> {
+        if !key.verify(&body) {
+            bail!("content does not match key: {key}");
+        }
+        self.state().objects.insert(key.clone(), body);
+        Ok(())
+    }
+}
+
+/// Whether a unit saved against `saved` glibc can be restored on a host with
+/// `host` glibc, mirroring Courier's check.
+fn glibc_compatible(host: Option<&GlibcVersion>, saved: Option<&GlibcVersion>) -> bool {
+    match (host, saved) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(host), Some(saved)) => host >= saved,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn cas_round_trip() {
+        let courier = FakeCourier::new();
+        let content = b"hello world".to_vec();
+        let key = Key::from_buffer(&content);
+
+        assert!(!courier.cas_exists(&key).await.unwrap());
+        courier
+            .cas_write_bytes(&key, content.clone())
+            .await
+            .unwrap();
+        assert!(courier.cas_exists(&key).await.unwrap());
+        pretty_assert_eq!(courier.cas_read_bytes(&key).await.unwrap(), Some(content));
+    }
+
+    #[tokio::test]
+    async fn cas_rejects_mismatched_content() {
+        let courier = FakeCourier::new();
+        let key = Key::from_buffer(b"hello world");
+
+        let written = courier.cas_write_bytes(&key, b"goodbye".to_vec()).await;
+        assert!(written.is_err());
+        assert!(!courier.cas_exists(&key).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn generation_is_shared_between_clones() {
+        let courier = FakeCourier::new().with_generation(3);
+        let clone = courier.clone();
+
+        pretty_assert_eq!(clone.bump_generation(), 4);
+        let generation = courier.cargo_cache_generation().await.unwrap();
+        pretty_assert_eq!(generation.generation, 4);
+    }
+}
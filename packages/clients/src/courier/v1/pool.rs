@@ -0,0 +1,262 @@
+//! Shared HTTP connection pool for Courier clients.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bon::Builder;
+use color_eyre::{Result, eyre::Context as _};
+use derive_more::Debug;
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service};
+use url::Url;
+
+use crate::{ProxyConfig, Token, courier::v1::Client};
+
+/// How long idle connections are kept in the pool by default.
+///
+/// Builds can take several minutes between the restore and the upload, so
+/// this is longer than the default to keep the connection warm across them.
+/// Courier or an intermediate proxy may still close idle connections sooner,
+/// in which case the next request just opens a new one.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How long opening a connection can take by default.
+///
+/// This only covers the TCP and TLS handshakes, so it can be much shorter than
+/// the timeouts of requests (see [`Timeouts`](crate::courier::v1::Timeouts)):
+/// a Courier instance that doesn't accept a connection within it is almost
+/// certainly unreachable.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often to send TCP keepalive probes on idle connections.
+const TCP_KEEPALIVE: Duration = Duration::from_secs(30);
+
+/// Options for the connections of a [`ConnectionPool`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Builder)]
+pub struct PoolOptions {
+    /// How long opening a connection can take.
+    #[builder(default = CONNECT_TIMEOUT)]
+    pub connect_timeout: Duration,
+
+    /// How long idle connections are kept in the pool.
+    #[builder(default = POOL_IDLE_TIMEOUT)]
+    pub idle_timeout: Duration,
+
+    /// The most idle connections kept in the pool for each host. Connections
+    /// beyond this are closed once their request completes, rather than
+    /// reused. Unlimited if not set.
+    pub max_idle_per_host: Option<usize>,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// A pool of HTTP connections shared by Courier clients.
+///
+/// Every client created from the same pool reuses the same connections,
+/// regardless of its base URL or token. This avoids repeating the TCP and TLS
+/// handshakes for each request, which dominates the latency of small requests.
+///
+/// ## Cloning
+///
+/// This type is cheaply cloneable, and clones share the same connections.
+#[derive(Clone, Debug)]
+pub struct ConnectionPool {
+    #[debug(skip)]
+    http: reqwest::Client,
+    counters: Arc<Counters>,
+}
+
+impl ConnectionPool {
+    /// Create a new, empty connection pool that uses the proxies configured in
+    /// the environment.
+    pub fn new() -> Result<Self> {
+        Self::with_proxy(&ProxyConfig::default())
+    }
+
+    /// Create a new, empty connection pool that uses the provided proxy
+    /// configuration.
+    pub fn with_proxy(proxy: &ProxyConfig) -> Result<Self> {
+        Self::with_options(proxy, &PoolOptions::default())
+    }
+
+    /// Create a new, empty connection pool that uses the provided proxy
+    /// configuration and connection options.
+    pub fn with_options(proxy: &ProxyConfig, options: &PoolOptions) -> Result<Self> {
+        let counters = Arc::new(Counters::default());
+        let mut builder = reqwest::Client::builder()
+            .gzip(true)
+            .brotli(true)
+            .connect_timeout(options.connect_timeout)
+            .pool_idle_timeout(options.idle_timeout)
+            .tcp_keepalive(TCP_KEEPALIVE)
+            .connector_layer(CountConnections(counters.clone()));
+        if let Some(max) = options.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        let http = proxy.apply(builder)?.build().context("build http client")?;
+        Ok(Self { http, counters })
+    }
+
+    /// Create a client for the Courier instance at `base` that uses this pool.
+    pub fn client(&self, base: Url, token: Token) -> Client {
+        Client::from_pool(self.clone(), base, token)
+    }
+
+    /// Connection statistics for the pool.
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            connections_opened: self.counters.connections.load(Ordering::Relaxed),
+            warmups: self.counters.warmups.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+
+    pub(crate) fn record_warmup(&self) {
+        self.counters.warmups.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Connection statistics for a [`ConnectionPool`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    /// The number of connections the pool has opened. Each one is a new TCP
+    /// handshake and, for HTTPS, a new TLS handshake; if this grows with every
+    /// request, connections aren't being reused.
+    pub connections_opened: u64,
+
+    /// The number of times the pool was pre-warmed with
+    /// [`Client::warm`](crate::courier::v1::Client::warm).
+    pub warmups: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    connections: AtomicU64,
+    warmups: AtomicU64,
+}
+
+/// Connector layer that counts the connections opened by the pool.
+#[derive(Clone, Debug)]
+struct CountConnections(Arc<Counters>);
+
+impl<S> Layer<S> for CountConnections {
+    type Service = CountedConnector<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CountedConnector {
+            inner,
+            counters: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CountedConnector<S> {
+    #[debug(skip)]
+    inner: S,
+    counters: Arc<Counters>,
+}
+
+impl<S, R> Service<R> for CountedConnector<S>
+where
+    S: Service<R>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        self.counters.connections.fetch_add(1, Ordering::Relaxed);
+        self.inner.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use tokio::{
+        io::{AsyncReadExt as _, AsyncWriteExt as _},
+        net::TcpListener,
+    };
+    use url::Url;
+
+    use super::{ConnectionPool, PoolOptions};
+    use crate::{ProxyConfig, Token};
+
+    /// Start a server that answers every request on a connection with an
+    /// empty `200 OK`, keeping the connection open for the next request.
+    async fn ok_server() -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = vec![0; 8192];
+                    while let Ok(read) = stream.read(&mut buf).await
+                        && read > 0
+                    {
+                        let response = b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n";
+                        if stream.write_all(response).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    async fn connections_for_two_pings(options: &PoolOptions) -> u64 {
+        let url = ok_server().await;
+        let pool = ConnectionPool::with_options(&ProxyConfig::default(), options).unwrap();
+        let client = pool.client(url, Token::from("test-token"));
+        client.ping().await.unwrap();
+        client.ping().await.unwrap();
+        pool.stats().connections_opened
+    }
+
+    #[test]
+    fn defaults() {
+        pretty_assert_eq!(
+            PoolOptions::default(),
+            PoolOptions {
+                connect_timeout: Duration::from_secs(10),
+                idle_timeout: Duration::from_secs(5 * 60),
+                max_idle_per_host: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn reuses_idle_connections() {
+        let opened = connections_for_two_pings(&PoolOptions::default()).await;
+        pretty_assert_eq!(opened, 1);
+    }
+
+    #[tokio::test]
+    async fn options_reach_the_http_client() {
+        // Without any idle connections kept, every request opens a new one.
+        let options = PoolOptions::builder().max_idle_per_host(0).build();
+        let opened = connections_for_two_pings(&options).await;
+        pretty_assert_eq!(opened, 2);
+    }
+}
@@ -0,0 +1,198 @@
+//! Retrying idempotent requests.
+//!
+//! Builds send thousands of requests to Courier, so on a flaky network it's
+//! likely that at least one of them hits a dropped connection or a load
+//! balancer that's briefly unavailable. Requests that only read (restoring
+//! units, reading and checking CAS objects) are safe to send again, so the
+//! client retries them with exponential backoff instead of failing the build.
+//!
+//! Backoff uses "full jitter": each delay is picked at random between zero and
+//! the exponential delay, so that clients that failed at the same time don't
+//! retry at the same time too.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher as _, Hasher as _},
+    time::Duration,
+};
+
+use bon::Builder;
+use reqwest::{Response, StatusCode, header::RETRY_AFTER};
+
+/// When and how often the client retries idempotent requests.
+#[derive(Clone, Debug, Eq, PartialEq, Builder)]
+pub struct RetryPolicy {
+    /// The most times a request is sent, including the first. One disables
+    /// retries.
+    #[builder(default = RetryPolicy::DEFAULT_MAX_ATTEMPTS)]
+    pub max_attempts: u32,
+
+    /// The longest delay before the first retry. The longest delay doubles
+    /// after each retry.
+    #[builder(default = RetryPolicy::DEFAULT_INITIAL_BACKOFF)]
+    pub initial_backoff: Duration,
+
+    /// The longest delay before any retry, including delays that Courier asks
+    /// for with `Retry-After`.
+    #[builder(default = RetryPolicy::DEFAULT_MAX_BACKOFF)]
+    pub max_backoff: Duration,
+
+    /// The response statuses that are retried. Errors sending the request,
+    /// like refused connections and timeouts, are always retried.
+    #[builder(default = RetryPolicy::DEFAULT_RETRYABLE_STATUSES.to_vec())]
+    pub retryable_statuses: Vec<StatusCode>,
+}
+
+impl RetryPolicy {
+    /// The most times a request is sent by default.
+    pub const DEFAULT_MAX_ATTEMPTS: u32 = 4;
+
+    /// The longest delay before the first retry by default.
+    pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+
+    /// The longest delay before any retry by default.
+    pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+    /// The response statuses that are retried by default: those that proxies
+    /// and load balancers return when the service behind them is briefly
+    /// unavailable, and rate limiting.
+    pub const DEFAULT_RETRYABLE_STATUSES: [StatusCode; 6] = [
+        StatusCode::REQUEST_TIMEOUT,
+        StatusCode::TOO_MANY_REQUESTS,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        StatusCode::BAD_GATEWAY,
+        StatusCode::SERVICE_UNAVAILABLE,
+        StatusCode::GATEWAY_TIMEOUT,
+    ];
+
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self::builder().max_attempts(1).build()
+    }
+
+    /// Whether a request that has been sent `attempt` times (starting at one)
+    /// can be sent again.
+    pub fn can_retry(&self, attempt: u32) -> bool {
+        attempt < self.max_attempts
+    }
+
+    /// Whether a request that got a response with this status is retried.
+    pub fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retryable_statuses.contains(&status)
+    }
+
+    /// Whether a request that failed to send with this error is retried.
+    ///
+    /// Errors building the request, or following redirects, fail the same way
+    /// every time.
+    pub fn is_retryable_error(err: &reqwest::Error) -> bool {
+        !err.is_builder() && !err.is_redirect()
+    }
+
+    /// The longest delay before retrying a request that has been sent
+    /// `attempt` times (starting at one).
+    pub fn max_delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        self.initial_backoff
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_backoff)
+    }
+
+    /// The delay before retrying a request that has been sent `attempt` times
+    /// (starting at one): a random delay up to [`RetryPolicy::max_delay`].
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.max_delay(attempt).mul_f64(jitter())
+    }
+
+    /// The delay before retrying a request that got `response`.
+    ///
+    /// When Courier says how long to wait with `Retry-After` (in seconds),
+    /// that's used instead of the backoff, up to the maximum backoff.
+    pub fn delay_for(&self, attempt: u32, response: &Response) -> Duration {
+        response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(|secs| Duration::from_secs(secs).min(self.max_backoff))
+            .unwrap_or_else(|| self.delay(attempt))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// A random fraction between zero and one.
+///
+/// This doesn't need to be unpredictable, only different between clients and
+/// between retries, so the hasher's random keys are enough.
+fn jitter() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use reqwest::StatusCode;
+
+    use super::RetryPolicy;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = RetryPolicy::builder()
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(500))
+            .build();
+        let delays = (1..=5)
+            .map(|attempt| policy.max_delay(attempt))
+            .collect::<Vec<_>>();
+        pretty_assert_eq!(
+            delays,
+            vec![
+                Duration::from_millis(100),
+                Duration::from_millis(200),
+                Duration::from_millis(400),
+                Duration::from_millis(500),
+                Duration::from_millis(500),
+            ]
+        );
+        for attempt in 1..=5 {
+            let delay = policy.delay(attempt);
+            assert!(
+                delay <= policy.max_delay(attempt),
+                "attempt {attempt}: {delay:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn attempts() {
+        let policy = RetryPolicy::builder().max_attempts(3).build();
+        assert!(policy.can_retry(1));
+        assert!(policy.can_retry(2));
+        assert!(!policy.can_retry(3));
+        assert!(!RetryPolicy::none().can_retry(1));
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        let policy = RetryPolicy::default();
+        assert!(policy.is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(policy.is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!policy.is_retryable_status(StatusCode::NOT_FOUND));
+        assert!(!policy.is_retryable_status(StatusCode::UNAUTHORIZED));
+
+        let policy = RetryPolicy::builder()
+            .retryable_statuses(vec![StatusCode::CONFLICT])
+            .build();
+        assert!(policy.is_retryable_status(StatusCode::CONFLICT));
+        assert!(!policy.is_retryable_status(StatusCode::BAD_GATEWAY));
+    }
+}
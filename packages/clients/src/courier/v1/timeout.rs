@@ -0,0 +1,137 @@
+//! Timeouts for Courier requests.
+//!
+//! Requests to Courier vary in size by orders of magnitude: a health check or
+//! a restore message is a few kilobytes, while a bulk CAS read or a large
+//! upload can take minutes on a slow link. A single timeout is either too
+//! short for transfers or too long to notice a hung API call, so requests are
+//! grouped into classes with their own timeouts.
+
+use std::time::Duration;
+
+use bon::Builder;
+use derive_more::Display;
+
+/// The class of a request, which decides its timeout.
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq, Hash)]
+pub enum TimeoutClass {
+    /// Small API calls: health checks, cache messages, existence checks, and
+    /// administration.
+    #[display("api")]
+    Api,
+
+    /// Requests that transfer CAS content or saved units, whose duration
+    /// depends on how much is being transferred.
+    #[display("transfer")]
+    Transfer,
+}
+
+/// Timeouts for each [`TimeoutClass`] of request.
+///
+/// A timeout covers the whole request, from connecting to reading the end of
+/// the response body.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Builder)]
+pub struct Timeouts {
+    /// The timeout for [`TimeoutClass::Api`] requests.
+    #[builder(default = Timeouts::DEFAULT_API)]
+    pub api: Duration,
+
+    /// The timeout for [`TimeoutClass::Transfer`] requests.
+    #[builder(default = Timeouts::DEFAULT_TRANSFER)]
+    pub transfer: Duration,
+}
+
+impl Timeouts {
+    /// The timeout for API requests by default.
+    pub const DEFAULT_API: Duration = Duration::from_secs(2 * 60);
+
+    /// The timeout for transfers by default.
+    pub const DEFAULT_TRANSFER: Duration = Duration::from_secs(30 * 60);
+
+    /// The timeout for requests of the class.
+    pub fn get(&self, class: TimeoutClass) -> Duration {
+        match class {
+            TimeoutClass::Api => self.api,
+            TimeoutClass::Transfer => self.transfer,
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use tokio::net::TcpListener;
+    use url::Url;
+
+    use super::{TimeoutClass, Timeouts};
+    use crate::{
+        Token,
+        courier::v1::{ConnectionPool, Key, RetryPolicy},
+    };
+
+    /// Start a server that accepts connections and never responds, so that
+    /// every request to it runs until it times out.
+    async fn silent_server() -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn builder_overrides_defaults() {
+        let timeouts = Timeouts::builder().api(Duration::from_secs(5)).build();
+        pretty_assert_eq!(timeouts.get(TimeoutClass::Api), Duration::from_secs(5));
+        pretty_assert_eq!(
+            timeouts.get(TimeoutClass::Transfer),
+            Timeouts::DEFAULT_TRANSFER
+        );
+
+        let timeouts = Timeouts::builder()
+            .transfer(Duration::from_secs(60))
+            .build();
+        pretty_assert_eq!(timeouts.get(TimeoutClass::Api), Timeouts::DEFAULT_API);
+        pretty_assert_eq!(
+            timeouts.get(TimeoutClass::Transfer),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_use_their_class_timeout() {
+        let url = silent_server().await;
+        let client = ConnectionPool::new()
+            .unwrap()
+            .client(url, Token::from("test-token"))
+            .without_access_tokens()
+            .with_retry_policy(RetryPolicy::none())
+            .with_timeouts(
+                Timeouts::builder()
+                    .api(Duration::from_millis(100))
+                    .transfer(Duration::from_secs(60))
+                    .build(),
+            );
+
+        // JSON endpoints are API requests, so they give up after the short
+        // timeout.
+        let ping = tokio::time::timeout(Duration::from_secs(10), client.ping()).await;
+        assert!(matches!(ping, Ok(Err(_))), "{ping:?}");
+
+        // Transfers are still waiting well after the API timeout has passed.
+        let key = Key::from_buffer(b"content");
+        let read = tokio::time::timeout(Duration::from_secs(1), client.cas_read_bytes(&key)).await;
+        assert!(read.is_err(), "{read:?}");
+    }
+}
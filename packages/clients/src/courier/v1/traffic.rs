@@ -0,0 +1,92 @@
+//! Traffic classes.
+//!
+//! Clients declare whether their requests come from a developer waiting on a
+//! build or from CI, so that Courier can serve interactive requests ahead of
+//! bulk CI traffic when it's busy.
+
+use std::{fmt, str::FromStr};
+
+use color_eyre::eyre::bail;
+use serde::{Deserialize, Serialize};
+
+/// The header that carries the [`TrafficClass`] of a request. Requests
+/// without it are treated as [`TrafficClass::Interactive`].
+pub const TRAFFIC_CLASS_HEADER: &str = "x-hurry-traffic-class";
+
+/// The class of traffic a request belongs to.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Deserialize, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrafficClass {
+    /// A developer is waiting on the request.
+    #[default]
+    Interactive,
+
+    /// The request comes from a CI job, which is usually larger and less
+    /// sensitive to latency.
+    Ci,
+}
+
+impl TrafficClass {
+    /// All traffic classes, from highest to lowest priority.
+    pub const ALL: [Self; 2] = [Self::Interactive, Self::Ci];
+
+    /// The value of the class in the [`TRAFFIC_CLASS_HEADER`] header.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Interactive => "interactive",
+            Self::Ci => "ci",
+        }
+    }
+
+    /// The class declared by a header value.
+    ///
+    /// Missing or unrecognized values are treated as interactive, so that
+    /// clients which predate traffic classes (or declare classes this version
+    /// doesn't know) aren't deprioritized.
+    pub fn from_header(value: Option<&str>) -> Self {
+        value
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for TrafficClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TrafficClass {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "interactive" => Ok(Self::Interactive),
+            "ci" => Ok(Self::Ci),
+            other => bail!("unknown traffic class: {other:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::TrafficClass;
+
+    #[test]
+    fn from_header() {
+        pretty_assert_eq!(TrafficClass::from_header(None), TrafficClass::Interactive);
+        pretty_assert_eq!(TrafficClass::from_header(Some("ci")), TrafficClass::Ci);
+        pretty_assert_eq!(TrafficClass::from_header(Some(" CI ")), TrafficClass::Ci);
+        pretty_assert_eq!(
+            TrafficClass::from_header(Some("batch")),
+            TrafficClass::Interactive
+        );
+        for class in TrafficClass::ALL {
+            pretty_assert_eq!(TrafficClass::from_header(Some(class.as_str())), class);
+        }
+    }
+}
@@ -1,8 +1,17 @@
 //! Shared client library for API types and HTTP clients.
 //!
 //! This library provides type definitions and HTTP client implementations
-//! for various APIs. Types are always available, while HTTP client code
-//! is gated behind feature flags.
+//! for various APIs, split up by feature flags:
+//! - [`core`] holds the types that describe cached content (keys, hashes, and
+//!   saved unit manifests), and is always available.
+//! - `api` (enabled by default) adds the Courier API request and response
+//!   types, which need `http` and the message encodings.
+//! - `client` adds the HTTP client.
+//! - `fake` adds an in-memory fake of the client for tests.
+//!
+//! The types in [`core`] are also re-exported from [`courier::v1`], so code
+//! that uses them through the API module doesn't need to change when the
+//! `api` feature is disabled.
 //!
 //! ## Use of `#[non_exhaustive]`
 //!
@@ -21,13 +30,22 @@
 use std::{fmt, str::FromStr};
 
 use color_eyre::eyre::bail;
+#[cfg(feature = "api")]
 use derive_more::Display;
+#[cfg(feature = "api")]
 use enum_assoc::Assoc;
+#[cfg(feature = "api")]
 use http::header::{self, HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
 use tap::Pipe;
 
+pub mod core;
 pub mod courier;
+#[cfg(feature = "client")]
+pub mod proxy;
+
+#[cfg(feature = "client")]
+pub use proxy::ProxyConfig;
 
 /// An authentication token for API access.
 ///
@@ -94,6 +112,31 @@ pub const NETWORK_BUFFER_SIZE: usize = 1024 * 1024;
 /// testing with different sizes.
 pub const LOCAL_BUFFER_SIZE: usize = 16 * 1024;
 
+/// Buffer sizes to use in place of [`NETWORK_BUFFER_SIZE`] and
+/// [`LOCAL_BUFFER_SIZE`].
+///
+/// The best sizes depend on the machine, so callers that have measured them
+/// (or been configured with them) can provide them here; otherwise the
+/// defaults are the constants.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct BufferSizes {
+    /// Used in place of [`NETWORK_BUFFER_SIZE`].
+    pub network: usize,
+
+    /// Used in place of [`LOCAL_BUFFER_SIZE`].
+    pub local: usize,
+}
+
+impl Default for BufferSizes {
+    fn default() -> Self {
+        Self {
+            network: NETWORK_BUFFER_SIZE,
+            local: LOCAL_BUFFER_SIZE,
+        }
+    }
+}
+
 /// The latest Courier client version.
 #[cfg(feature = "client")]
 pub type Courier = courier::v1::Client;
@@ -102,7 +145,16 @@ pub type Courier = courier::v1::Client;
 #[cfg(feature = "client")]
 pub type CourierV1 = courier::v1::Client;
 
+/// The Courier cache API, implemented by the Courier client and by test fakes.
+#[cfg(feature = "api")]
+pub use courier::v1::CourierApi;
+
+/// An in-memory fake of the latest Courier client, for tests.
+#[cfg(feature = "fake")]
+pub type FakeCourier = courier::v1::FakeCourier;
+
 /// Content types used by the library.
+#[cfg(feature = "api")]
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display, Assoc)]
 #[func(pub const fn value(&self) -> HeaderValue)]
 #[func(pub const fn to_str(&self) -> &'static str)]
@@ -127,31 +179,46 @@ pub enum ContentType {
     #[assoc(to_str = "application/json")]
     #[assoc(value = HeaderValue::from_static(self.to_str()))]
     Json,
+
+    #[assoc(to_str = "application/x-ndjson")]
+    #[assoc(value = HeaderValue::from_static(self.to_str()))]
+    NdJson,
+
+    /// Zstd-compressed MessagePack, a compact alternative to JSON for cache
+    /// messages; see [`courier::v1::cache::encode_binary`].
+    #[assoc(to_str = "application/msgpack+zstd")]
+    #[assoc(value = HeaderValue::from_static(self.to_str()))]
+    MsgPackZstd,
 }
 
+#[cfg(feature = "api")]
 impl ContentType {
     pub const HEADER: HeaderName = header::CONTENT_TYPE;
     pub const ACCEPT: HeaderName = header::ACCEPT;
 }
 
+#[cfg(feature = "api")]
 impl PartialEq<ContentType> for HeaderValue {
     fn eq(&self, other: &ContentType) -> bool {
         self == other.value()
     }
 }
 
+#[cfg(feature = "api")]
 impl PartialEq<ContentType> for &HeaderValue {
     fn eq(&self, other: &ContentType) -> bool {
         *self == other.value()
     }
 }
 
+#[cfg(feature = "api")]
 impl PartialEq<HeaderValue> for ContentType {
     fn eq(&self, other: &HeaderValue) -> bool {
         self.value() == other
     }
 }
 
+#[cfg(feature = "api")]
 impl PartialEq<&HeaderValue> for ContentType {
     fn eq(&self, other: &&HeaderValue) -> bool {
         self.value() == other
@@ -0,0 +1,2 @@
+ALTER TABLE cargo_saved_unit
+  DROP COLUMN unit_hash_version;
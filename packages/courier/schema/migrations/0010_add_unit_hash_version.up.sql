@@ -0,0 +1,4 @@
+-- Units saved before versioning were all keyed by the Cargo unit hash, which
+-- is version 1.
+ALTER TABLE cargo_saved_unit
+  ADD COLUMN unit_hash_version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1 @@
+DROP TABLE cas_dictionary;
@@ -0,0 +1,22 @@
+-- Zstd dictionaries trained from the small CAS objects of an organization.
+--
+-- The dictionary content itself is stored in the CAS like any other blob, so
+-- the dictionary is identified by its CAS key. Dictionaries are trained per
+-- organization so that one organization's content never influences another
+-- organization's compressed objects.
+CREATE TABLE cas_dictionary (
+  id BIGSERIAL PRIMARY KEY,
+  organization_id BIGINT NOT NULL REFERENCES organization(id),
+  -- The package whose objects were used to train the dictionary, or NULL for
+  -- the global dictionary trained on objects from all packages.
+  package_name TEXT,
+  cas_key_id BIGINT NOT NULL REFERENCES cas_key(id),
+  -- The dictionary ID that zstd embeds in frames compressed with this
+  -- dictionary; used to find the dictionary when decompressing.
+  zstd_id BIGINT NOT NULL,
+  sample_count BIGINT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE NULLS NOT DISTINCT (organization_id, package_name)
+);
+
+CREATE INDEX idx_cas_dictionary_zstd_id ON cas_dictionary(organization_id, zstd_id);
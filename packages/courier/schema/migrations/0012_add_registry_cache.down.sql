@@ -0,0 +1,2 @@
+DROP TABLE registry_crate;
+DROP TABLE registry_index_file;
@@ -0,0 +1,31 @@
+-- Crate registry proxy cache.
+--
+-- Courier can act as a caching proxy for the crates.io registry. Registry
+-- content is public, so unlike the rest of the CAS it's shared by every
+-- organization instead of being scoped by `cas_access`.
+
+-- Sparse index files fetched from the upstream registry.
+--
+-- Index files change whenever a new version of the crate is published, so
+-- they're refreshed from upstream once they're older than the configured TTL.
+CREATE TABLE registry_index_file (
+  id BIGSERIAL PRIMARY KEY,
+  -- The path of the file relative to the index root, e.g. `se/rd/serde`.
+  path TEXT NOT NULL UNIQUE,
+  cas_key_id BIGINT NOT NULL REFERENCES cas_key(id),
+  -- The upstream ETag, used to revalidate the file when it's stale.
+  etag TEXT,
+  fetched_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- `.crate` files downloaded from the upstream registry.
+--
+-- Published crate versions are immutable, so these never need revalidation.
+CREATE TABLE registry_crate (
+  id BIGSERIAL PRIMARY KEY,
+  name TEXT NOT NULL,
+  version TEXT NOT NULL,
+  cas_key_id BIGINT NOT NULL REFERENCES cas_key(id),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE (name, version)
+);
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_cargo_saved_unit_org_near_match_key;
+
+ALTER TABLE cargo_saved_unit
+  DROP COLUMN near_match_key;
@@ -0,0 +1,6 @@
+-- Units saved before near matching was introduced have no key, so they are
+-- only ever restored as exact matches.
+ALTER TABLE cargo_saved_unit
+  ADD COLUMN near_match_key TEXT;
+
+CREATE INDEX idx_cargo_saved_unit_org_near_match_key ON cargo_saved_unit(organization_id, near_match_key);
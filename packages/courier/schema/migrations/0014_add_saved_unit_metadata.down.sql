@@ -0,0 +1,6 @@
+ALTER TABLE cargo_saved_unit
+  DROP COLUMN rustc_version,
+  DROP COLUMN hurry_version,
+  DROP COLUMN ci_provider,
+  DROP COLUMN commit_sha,
+  DROP COLUMN builder_hostname_hash;
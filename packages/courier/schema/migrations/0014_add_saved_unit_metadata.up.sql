@@ -0,0 +1,7 @@
+-- Units saved before metadata was recorded have NULL for all of these.
+ALTER TABLE cargo_saved_unit
+  ADD COLUMN rustc_version TEXT,
+  ADD COLUMN hurry_version TEXT,
+  ADD COLUMN ci_provider TEXT,
+  ADD COLUMN commit_sha TEXT,
+  ADD COLUMN builder_hostname_hash TEXT;
@@ -0,0 +1,14 @@
+CREATE TABLE cargo_unit_upload (
+  id BIGSERIAL PRIMARY KEY,
+  organization_id BIGINT NOT NULL REFERENCES organization(id),
+  account_id BIGINT NOT NULL REFERENCES account(id),
+  unit_hash TEXT NOT NULL,
+  rustc_version TEXT,
+  hurry_version TEXT,
+  ci_provider TEXT,
+  commit_sha TEXT,
+  builder_hostname_hash TEXT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_cargo_unit_upload_org_unit_hash ON cargo_unit_upload(organization_id, unit_hash, created_at);
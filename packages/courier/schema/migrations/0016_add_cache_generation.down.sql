@@ -0,0 +1,5 @@
+ALTER TABLE cargo_saved_unit
+  DROP COLUMN cache_generation;
+
+ALTER TABLE organization
+  DROP COLUMN cache_generation;
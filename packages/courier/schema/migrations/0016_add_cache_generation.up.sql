@@ -0,0 +1,7 @@
+-- Organizations and units that predate generations are in the initial
+-- generation, whose hashes are the same as before generations existed.
+ALTER TABLE organization
+  ADD COLUMN cache_generation BIGINT NOT NULL DEFAULT 0;
+
+ALTER TABLE cargo_saved_unit
+  ADD COLUMN cache_generation BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,8 @@
+ALTER TABLE cargo_unit_upload
+  DROP COLUMN branch;
+
+ALTER TABLE cargo_saved_unit
+  DROP COLUMN branch;
+
+ALTER TABLE organization
+  DROP COLUMN cache_write_branches;
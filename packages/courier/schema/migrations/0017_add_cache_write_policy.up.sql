@@ -0,0 +1,10 @@
+-- Organizations that predate write policies let every branch write.
+ALTER TABLE organization
+  ADD COLUMN cache_write_branches TEXT[];
+
+-- Units saved before branches were reported have NULL for these.
+ALTER TABLE cargo_saved_unit
+  ADD COLUMN branch TEXT;
+
+ALTER TABLE cargo_unit_upload
+  ADD COLUMN branch TEXT;
@@ -0,0 +1 @@
+DROP INDEX idx_cargo_unit_upload_org_commit_sha;
@@ -0,0 +1,3 @@
+-- Restoring the cache as of a commit looks up when the commit's units were
+-- uploaded, by SHA prefix.
+CREATE INDEX idx_cargo_unit_upload_org_commit_sha ON cargo_unit_upload(organization_id, commit_sha text_pattern_ops);
@@ -0,0 +1,20 @@
+-- Device authorizations let command line tools sign in without handling a
+-- browser redirect: the tool shows a user code, the user approves it in the
+-- console, and the tool polls with its device code until it's approved.
+CREATE TABLE device_authorization (
+  id BIGSERIAL PRIMARY KEY,
+  -- Store only a hash of the device code (like exchange codes), so DB leaks
+  -- don't allow claiming pending authorizations.
+  device_code_hash BYTEA NOT NULL UNIQUE,
+  user_code TEXT NOT NULL UNIQUE,
+  -- Names the API key created for the tool once it's approved.
+  client_name TEXT NOT NULL,
+  -- Set once the user approves the authorization.
+  account_id BIGINT REFERENCES account(id),
+  organization_id BIGINT REFERENCES organization(id),
+  approved_at TIMESTAMPTZ,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_device_authorization_expires ON device_authorization(expires_at);
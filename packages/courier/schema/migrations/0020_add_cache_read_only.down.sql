@@ -0,0 +1,2 @@
+ALTER TABLE organization
+  DROP COLUMN cache_read_only_reason;
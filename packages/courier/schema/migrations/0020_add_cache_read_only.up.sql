@@ -0,0 +1,3 @@
+-- Organizations that predate read-only mode accept saves.
+ALTER TABLE organization
+  ADD COLUMN cache_read_only_reason TEXT;
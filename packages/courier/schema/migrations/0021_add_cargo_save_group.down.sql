@@ -0,0 +1,3 @@
+ALTER TABLE cargo_saved_unit
+  DROP COLUMN save_group_id;
+DROP TABLE cargo_save_group;
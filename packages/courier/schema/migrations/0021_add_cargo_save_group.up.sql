@@ -0,0 +1,16 @@
+-- Groups the units saved by one build, so that an interrupted save can't leave
+-- units in the cache whose dependencies were never saved. Units in a group are
+-- provisional, and aren't restored, until the group is finalized.
+CREATE TABLE cargo_save_group (
+  id BIGSERIAL PRIMARY KEY,
+  organization_id BIGINT NOT NULL REFERENCES organization(id),
+  build_id TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  finalized_at TIMESTAMPTZ,
+  UNIQUE(organization_id, build_id)
+);
+
+-- Units saved before groups, or by clients that don't send a build ID, aren't
+-- in a group and are restorable as soon as they're saved.
+ALTER TABLE cargo_saved_unit
+  ADD COLUMN save_group_id BIGINT REFERENCES cargo_save_group(id) ON DELETE SET NULL;
@@ -0,0 +1,3 @@
+DROP INDEX idx_cas_key_last_accessed_at;
+ALTER TABLE cas_key
+  DROP COLUMN last_accessed_at;
@@ -0,0 +1,6 @@
+-- Objects that predate access tracking count as accessed when the migration
+-- runs, so garbage collection keeps them for at least one retention window.
+ALTER TABLE cas_key
+  ADD COLUMN last_accessed_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+CREATE INDEX idx_cas_key_last_accessed_at ON cas_key(last_accessed_at);
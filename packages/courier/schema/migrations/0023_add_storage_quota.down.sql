@@ -0,0 +1,5 @@
+ALTER TABLE organization
+  DROP COLUMN storage_quota_bytes,
+  DROP COLUMN storage_bytes;
+ALTER TABLE cas_key
+  DROP COLUMN size_bytes;
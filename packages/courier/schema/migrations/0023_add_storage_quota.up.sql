@@ -0,0 +1,8 @@
+-- Objects written before sizes were recorded have no size, and don't count
+-- towards any organization's usage.
+ALTER TABLE cas_key
+  ADD COLUMN size_bytes BIGINT;
+
+ALTER TABLE organization
+  ADD COLUMN storage_bytes BIGINT NOT NULL DEFAULT 0,
+  ADD COLUMN storage_quota_bytes BIGINT;
@@ -0,0 +1,4 @@
+DROP INDEX idx_cargo_saved_unit_org_scope;
+
+ALTER TABLE cargo_saved_unit
+  DROP COLUMN cache_scope;
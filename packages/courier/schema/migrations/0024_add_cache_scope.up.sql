@@ -0,0 +1,6 @@
+-- Units that predate scopes, and units saved without one, are in the
+-- organization's unscoped cache.
+ALTER TABLE cargo_saved_unit
+  ADD COLUMN cache_scope TEXT;
+
+CREATE INDEX idx_cargo_saved_unit_org_scope ON cargo_saved_unit(organization_id, cache_scope);
@@ -0,0 +1,4 @@
+-- Read-only API keys can restore from the organization's cache but not save
+-- to it, e.g. for builds of pull requests from forks.
+ALTER TABLE api_key
+  ADD COLUMN read_only BOOLEAN NOT NULL DEFAULT FALSE;
@@ -7,6 +7,21 @@
 CREATE TABLE organization (
   id BIGSERIAL PRIMARY KEY,
   name TEXT NOT NULL,
+  -- Clients mix this into the hashes under which units are saved, so bumping
+  -- it invalidates the organization's saved units without deleting them.
+  cache_generation BIGINT NOT NULL DEFAULT 0,
+  -- Patterns for the branches whose builds can save units, where `*` matches
+  -- any sequence of characters. NULL lets every build save units.
+  cache_write_branches TEXT[],
+  -- Why the organization's cache is read-only, e.g. during maintenance. While
+  -- set, saves are rejected but restores are still served. NULL accepts saves.
+  cache_read_only_reason TEXT,
+  -- Compressed bytes of the CAS objects the organization has access to, kept
+  -- up to date as access is granted and revoked.
+  storage_bytes BIGINT NOT NULL DEFAULT 0,
+  -- The most the organization may store before writes are rejected. NULL uses
+  -- the instance's default quota (see `COURIER_STORAGE_QUOTA`).
+  storage_quota_bytes BIGINT,
   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
 );
 
@@ -33,7 +48,11 @@ CREATE TABLE api_key (
   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
   accessed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
   revoked_at TIMESTAMPTZ,
-  organization_id BIGINT NOT NULL REFERENCES organization(id)
+  organization_id BIGINT NOT NULL REFERENCES organization(id),
+
+  -- Read-only keys can restore from the organization's cache but not save to
+  -- it.
+  read_only BOOLEAN NOT NULL DEFAULT FALSE
 );
 
 -- Lists CAS keys known about by the database.
@@ -44,9 +63,20 @@ CREATE TABLE api_key (
 CREATE TABLE cas_key (
   id BIGSERIAL PRIMARY KEY,
   content BYTEA NOT NULL UNIQUE,
-  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  -- When the object was last read, written, or checked for. Garbage collection
+  -- only deletes objects that haven't been accessed within its retention
+  -- window. This is updated at most once an hour per object, so it can lag
+  -- behind the actual last access by that much.
+  last_accessed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  -- Compressed size of the object, which counts towards the storage usage of
+  -- every organization with access to it. NULL for objects written before
+  -- sizes were recorded.
+  size_bytes BIGINT
 );
 
+CREATE INDEX idx_cas_key_last_accessed_at ON cas_key(last_accessed_at);
+
 -- Controls what organizations have access to a given CAS key.
 --
 -- We deduplicate CAS keys: if two organizations both save the same content,
@@ -61,6 +91,75 @@ CREATE TABLE cas_access (
   PRIMARY KEY (organization_id, cas_key_id)
 );
 
+-- Zstd dictionaries trained from the small CAS objects of an organization.
+--
+-- The dictionary content itself is stored in the CAS like any other blob, so
+-- the dictionary is identified by its CAS key. Dictionaries are trained per
+-- organization so that one organization's content never influences another
+-- organization's compressed objects.
+CREATE TABLE cas_dictionary (
+  id BIGSERIAL PRIMARY KEY,
+  organization_id BIGINT NOT NULL REFERENCES organization(id),
+  -- The package whose objects were used to train the dictionary, or NULL for
+  -- the global dictionary trained on objects from all packages.
+  package_name TEXT,
+  cas_key_id BIGINT NOT NULL REFERENCES cas_key(id),
+  -- The dictionary ID that zstd embeds in frames compressed with this
+  -- dictionary; used to find the dictionary when decompressing.
+  zstd_id BIGINT NOT NULL,
+  sample_count BIGINT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE NULLS NOT DISTINCT (organization_id, package_name)
+);
+
+CREATE INDEX idx_cas_dictionary_zstd_id ON cas_dictionary(organization_id, zstd_id);
+
+-- Crate registry proxy cache.
+--
+-- Courier can act as a caching proxy for the crates.io registry. Registry
+-- content is public, so unlike the rest of the CAS it's shared by every
+-- organization instead of being scoped by `cas_access`.
+
+-- Sparse index files fetched from the upstream registry.
+--
+-- Index files change whenever a new version of the crate is published, so
+-- they're refreshed from upstream once they're older than the configured TTL.
+CREATE TABLE registry_index_file (
+  id BIGSERIAL PRIMARY KEY,
+  -- The path of the file relative to the index root, e.g. `se/rd/serde`.
+  path TEXT NOT NULL UNIQUE,
+  cas_key_id BIGINT NOT NULL REFERENCES cas_key(id),
+  -- The upstream ETag, used to revalidate the file when it's stale.
+  etag TEXT,
+  fetched_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- `.crate` files downloaded from the upstream registry.
+--
+-- Published crate versions are immutable, so these never need revalidation.
+CREATE TABLE registry_crate (
+  id BIGSERIAL PRIMARY KEY,
+  name TEXT NOT NULL,
+  version TEXT NOT NULL,
+  cas_key_id BIGINT NOT NULL REFERENCES cas_key(id),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE (name, version)
+);
+
+-- Groups the units saved by one build, so that an interrupted save can't leave
+-- units in the cache whose dependencies were never saved.
+CREATE TABLE cargo_save_group (
+  id BIGSERIAL PRIMARY KEY,
+  organization_id BIGINT NOT NULL REFERENCES organization(id),
+  -- Identifies the build that saved the units, as reported by the client.
+  build_id TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  -- Set once the build has saved all of its units. Until then the units in
+  -- the group are provisional and aren't restored.
+  finalized_at TIMESTAMPTZ,
+  UNIQUE(organization_id, build_id)
+);
+
 -- Cargo cache: stores SavedUnit instances as JSONB.
 --
 -- This table uses a JSONB-based approach for simplicity and flexibility:
@@ -90,7 +189,21 @@ CREATE TABLE cargo_saved_unit (
   -- their build scripts used different files as inputs or produced different
   -- files as outputs, or they were linked against different native libraries
   -- (this list is non-exhaustive).
+  --
+  -- Depending on `unit_hash_version`, this is either the Cargo unit hash itself
+  -- or a hash derived from it.
   unit_hash TEXT NOT NULL,
+  -- The version of the algorithm used to derive `unit_hash`. Versioning this
+  -- lets clients keep restoring units saved by older clients while a derivation
+  -- is being deprecated, instead of invalidating the whole cache at once.
+  unit_hash_version INTEGER NOT NULL DEFAULT 1,
+  -- The organization's cache generation when the unit was saved, which is
+  -- part of the hash for versions that include it.
+  cache_generation BIGINT NOT NULL DEFAULT 0,
+  -- The cache scope the unit was saved in, which is part of the hash for
+  -- versions that include it. This is NULL for units in the organization's
+  -- unscoped cache.
+  cache_scope TEXT,
   -- The resolved architecture target triple of the unit. Note that this is
   -- subtly different from "the value of the `--target` flag", because it
   -- defaults to the host architecture when `--target` is unset.
@@ -113,6 +226,34 @@ CREATE TABLE cargo_saved_unit (
   --
   -- For other compilation targets, this field is NULL.
   linux_glibc_version TEXT,
+  -- Groups units that differ only in ways a restoring client may accept, such
+  -- as the enabled features, so that a client whose exact unit isn't cached
+  -- can ask for compatible candidates instead. Clients validate candidates
+  -- themselves; this key only narrows the search.
+  --
+  -- This is NULL for units that can't be near matches (e.g. build scripts)
+  -- and for units saved by clients that predate near matching.
+  near_match_key TEXT,
+  -- Information about the environment that saved the unit, as reported by the
+  -- client. These are for debugging and retention policies only, and are NULL
+  -- when the client didn't report them.
+  --
+  -- The version of `rustc` that compiled the unit, as reported by `rustc -V`.
+  rustc_version TEXT,
+  -- The version of Hurry that saved the unit.
+  hurry_version TEXT,
+  -- The CI provider the unit was built on, e.g. `github-actions`.
+  ci_provider TEXT,
+  -- The commit the unit was built from.
+  commit_sha TEXT,
+  -- A hash of the hostname of the machine that built the unit.
+  builder_hostname_hash TEXT,
+  -- The branch the unit was built from.
+  branch TEXT,
+  -- The build that saved the unit. The unit isn't restored until the group is
+  -- finalized; units saved without a build ID aren't in a group and are
+  -- restorable as soon as they're saved.
+  save_group_id BIGINT REFERENCES cargo_save_group(id) ON DELETE SET NULL,
   -- Note that elements in this JSONB blob reference CAS keys.
   --
   -- TODO: Normalize this JSONB blob into tables? Or at least add a version
@@ -123,6 +264,34 @@ CREATE TABLE cargo_saved_unit (
 );
 
 CREATE INDEX idx_cargo_saved_unit_org_key ON cargo_saved_unit(organization_id, unit_hash);
+CREATE INDEX idx_cargo_saved_unit_org_near_match_key ON cargo_saved_unit(organization_id, near_match_key);
+CREATE INDEX idx_cargo_saved_unit_org_scope ON cargo_saved_unit(organization_id, cache_scope);
+
+-- Records each time a unit is uploaded, including uploads of units that were
+-- already saved, so that the origin of a bad unit can be investigated.
+--
+-- Unlike `cargo_saved_unit`, this is an append-only history: it isn't cleared
+-- when the organization's cache is reset.
+CREATE TABLE cargo_unit_upload (
+  id BIGSERIAL PRIMARY KEY,
+  organization_id BIGINT NOT NULL REFERENCES organization(id),
+  -- The account (human or bot) whose token uploaded the unit.
+  account_id BIGINT NOT NULL REFERENCES account(id),
+  -- The unit hash as stored in `cargo_saved_unit.unit_hash`.
+  unit_hash TEXT NOT NULL,
+  -- Information about the environment that uploaded the unit, as reported by
+  -- the client; see the columns of the same names in `cargo_saved_unit`.
+  rustc_version TEXT,
+  hurry_version TEXT,
+  ci_provider TEXT,
+  commit_sha TEXT,
+  builder_hostname_hash TEXT,
+  branch TEXT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_cargo_unit_upload_org_unit_hash ON cargo_unit_upload(organization_id, unit_hash, created_at);
+CREATE INDEX idx_cargo_unit_upload_org_commit_sha ON cargo_unit_upload(organization_id, commit_sha text_pattern_ops);
 
 -- Links a GitHub user to their Courier account (1:1)
 CREATE TABLE github_identity (
@@ -215,6 +384,27 @@ CREATE TABLE oauth_exchange_code (
 
 CREATE INDEX idx_oauth_exchange_code_expires ON oauth_exchange_code(expires_at);
 
+-- Device authorizations let command line tools sign in without handling a
+-- browser redirect: the tool shows a user code, the user approves it in the
+-- console, and the tool polls with its device code until it's approved.
+CREATE TABLE device_authorization (
+  id BIGSERIAL PRIMARY KEY,
+  -- Store only a hash of the device code (like exchange codes), so DB leaks
+  -- don't allow claiming pending authorizations.
+  device_code_hash BYTEA NOT NULL UNIQUE,
+  user_code TEXT NOT NULL UNIQUE,
+  -- Names the API key created for the tool once it's approved.
+  client_name TEXT NOT NULL,
+  -- Set once the user approves the authorization.
+  account_id BIGINT REFERENCES account(id),
+  organization_id BIGINT REFERENCES organization(id),
+  approved_at TIMESTAMPTZ,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_device_authorization_expires ON device_authorization(expires_at);
+
 -- Active user sessions (for web UI authentication)
 CREATE TABLE user_session (
   id BIGSERIAL PRIMARY KEY,
@@ -0,0 +1,234 @@
+//! Short-lived access tokens issued for API keys.
+//!
+//! Validating an API key costs a database lookup on every request. Clients
+//! can instead exchange their API key for an access token (see
+//! [`crate::api::v1::access`]), which Courier signs and then verifies without
+//! the database. Access tokens expire after a few minutes, which bounds how
+//! long a revoked API key keeps working through them.
+//!
+//! An access token is the account, organization, and expiry it was issued
+//! for, followed by an HMAC-SHA256 signature of them:
+//!
+//! ```not_rust
+//! hat1.<account id>.<organization id>.<expires at, unix seconds>.<signature>
+//! ```
+//!
+//! Access tokens for read-only API keys carry an `ro` claim before the
+//! signature, so that they stay read-only without the database:
+//!
+//! ```not_rust
+//! hat1.<account id>.<organization id>.<expires at, unix seconds>.ro.<signature>
+//! ```
+//!
+//! API keys and session tokens are hex, so they're never mistaken for access
+//! tokens. Every instance serving the same clients must be configured with the
+//! same secret; tokens signed with another secret are rejected, and clients
+//! fall back to their API key.
+
+use std::time::Duration;
+
+use color_eyre::{Result, eyre::bail};
+use derive_more::Debug;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use time::OffsetDateTime;
+
+use crate::auth::{AccountId, AuthenticatedToken, OrgId, RawToken};
+
+/// Issues and verifies access tokens.
+#[derive(Clone, Debug)]
+pub struct AccessTokens {
+    #[debug(skip)]
+    secret: Vec<u8>,
+    ttl: Duration,
+}
+
+impl AccessTokens {
+    /// How long access tokens are valid for by default.
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(5 * 60);
+
+    /// The shortest secret that access tokens can be signed with.
+    pub const MIN_SECRET_LEN: usize = 32;
+
+    /// The prefix of every access token, which also versions the format.
+    const PREFIX: &str = "hat1";
+
+    /// The claim that marks access tokens issued for read-only API keys.
+    const READ_ONLY_CLAIM: &str = ".ro";
+
+    /// Sign access tokens with the provided secret, valid for `ttl`.
+    pub fn new(secret: impl Into<Vec<u8>>, ttl: Duration) -> Result<Self> {
+        let secret = secret.into();
+        if secret.len() < Self::MIN_SECRET_LEN {
+            bail!(
+                "access token secret must be at least {} bytes",
+                Self::MIN_SECRET_LEN
+            );
+        }
+        if ttl.is_zero() {
+            bail!("access tokens must be valid for at least 1 second");
+        }
+        Ok(Self { secret, ttl })
+    }
+
+    /// How long access tokens are valid for.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Whether the token is formatted as an access token, whether or not it's
+    /// valid.
+    pub fn is_access_token(token: &RawToken) -> bool {
+        token
+            .expose()
+            .strip_prefix(Self::PREFIX)
+            .is_some_and(|rest| rest.starts_with('.'))
+    }
+
+    /// Issue an access token for the authenticated API key.
+    pub fn issue(&self, auth: &AuthenticatedToken) -> RawToken {
+        self.issue_at(auth, OffsetDateTime::now_utc())
+    }
+
+    fn issue_at(&self, auth: &AuthenticatedToken, now: OffsetDateTime) -> RawToken {
+        let expires_at = now.unix_timestamp() + self.ttl.as_secs() as i64;
+        let mut claims = format!(
+            "{}.{}.{}.{expires_at}",
+            Self::PREFIX,
+            auth.account_id.as_i64(),
+            auth.org_id.as_i64(),
+        );
+        if auth.read_only {
+            claims.push_str(Self::READ_ONLY_CLAIM);
+        }
+        let signature = hex::encode(self.mac(&claims).finalize().into_bytes());
+        RawToken::new(format!("{claims}.{signature}"))
+    }
+
+    /// The MAC of the claims, keyed with the secret.
+    fn mac(&self, claims: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(claims.as_bytes());
+        mac
+    }
+
+    /// Verify an access token, returning who it was issued for if it was
+    /// signed with this secret and hasn't expired.
+    pub fn verify(&self, token: &RawToken) -> Option<AuthenticatedToken> {
+        self.verify_at(token, OffsetDateTime::now_utc())
+    }
+
+    fn verify_at(&self, token: &RawToken, now: OffsetDateTime) -> Option<AuthenticatedToken> {
+        let (claims, signature) = token.expose().rsplit_once('.')?;
+        let signature = hex::decode(signature).ok()?;
+        self.mac(claims).verify_slice(&signature).ok()?;
+
+        let (claims, read_only) = match claims.strip_suffix(Self::READ_ONLY_CLAIM) {
+            Some(claims) => (claims, true),
+            None => (claims, false),
+        };
+        let mut parts = claims.split('.');
+        let (Some(Self::PREFIX), Some(account_id), Some(org_id), Some(expires_at), None) = (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) else {
+            return None;
+        };
+        if expires_at.parse::<i64>().ok()? <= now.unix_timestamp() {
+            return None;
+        }
+        Some(AuthenticatedToken {
+            account_id: AccountId::from_i64(account_id.parse().ok()?),
+            org_id: OrgId::from_i64(org_id.parse().ok()?),
+            read_only,
+            plaintext: token.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use time::OffsetDateTime;
+
+    use super::AccessTokens;
+    use crate::auth::{AccountId, AuthenticatedToken, OrgId, RawToken};
+
+    fn tokens(secret: &str) -> AccessTokens {
+        AccessTokens::new(secret.repeat(32), Duration::from_secs(300)).unwrap()
+    }
+
+    fn api_key() -> AuthenticatedToken {
+        AuthenticatedToken {
+            account_id: AccountId::from_i64(12),
+            org_id: OrgId::from_i64(34),
+            read_only: false,
+            plaintext: RawToken::new("0123456789abcdef0123456789abcdef"),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let tokens = tokens("a");
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let token = tokens.issue_at(&api_key(), now);
+        assert!(AccessTokens::is_access_token(&token));
+        assert!(!AccessTokens::is_access_token(&api_key().plaintext));
+
+        let verified = tokens.verify_at(&token, now).expect("token should verify");
+        pretty_assert_eq!(verified.account_id, AccountId::from_i64(12));
+        pretty_assert_eq!(verified.org_id, OrgId::from_i64(34));
+        pretty_assert_eq!(verified.plaintext, token);
+    }
+
+    #[test]
+    fn expired() {
+        let tokens = tokens("a");
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let token = tokens.issue_at(&api_key(), now);
+        let later = now + Duration::from_secs(299);
+        assert!(tokens.verify_at(&token, later).is_some());
+        let later = now + Duration::from_secs(300);
+        assert!(tokens.verify_at(&token, later).is_none());
+    }
+
+    #[test]
+    fn rejects_tampering_and_other_secrets() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let token = tokens("a").issue_at(&api_key(), now);
+        assert!(tokens("b").verify_at(&token, now).is_none());
+
+        let tampered = RawToken::new(token.expose().replacen(".34.", ".35.", 1));
+        assert!(tokens("a").verify_at(&tampered, now).is_none());
+    }
+
+    #[test]
+    fn read_only() {
+        let tokens = tokens("a");
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let key = AuthenticatedToken {
+            read_only: true,
+            ..api_key()
+        };
+        let token = tokens.issue_at(&key, now);
+        let verified = tokens.verify_at(&token, now).expect("token should verify");
+        assert!(verified.read_only);
+        let writable = tokens.issue_at(&api_key(), now);
+        assert!(!tokens.verify_at(&writable, now).unwrap().read_only);
+
+        // Dropping the claim invalidates the signature.
+        let stripped = RawToken::new(token.expose().replacen(".ro.", ".", 1));
+        assert!(tokens.verify_at(&stripped, now).is_none());
+    }
+
+    #[test]
+    fn rejects_short_secrets() {
+        assert!(AccessTokens::new("short", Duration::from_secs(300)).is_err());
+    }
+}
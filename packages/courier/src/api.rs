@@ -70,16 +70,21 @@ const MAX_BODY_SIZE: usize = 10 * 1024 * 1024 * 1024; // 10GB
 
 /// Body size limit for JSON deserialization. Set to accommodate bulk metadata
 /// operations like bulk restore requests.
-const MAX_JSON_BODY_SIZE: usize = 100 * 1024 * 1024; // 100MB
+pub(crate) const MAX_JSON_BODY_SIZE: usize = 100 * 1024 * 1024; // 100MB
 
 pub type State = Aero![
     crate::db::Postgres,
     crate::storage::Disk,
     Option<crate::oauth::GitHub>,
+    crate::registry::Registry,
+    crate::maintenance::ReadOnly,
+    Option<crate::access::AccessTokens>,
+    crate::quota::StorageQuota,
 ];
 
 pub fn router(
     state: State,
+    lanes: crate::lanes::Lanes,
     allowed_origins: Vec<HeaderValue>,
     console_dir: Option<&Path>,
 ) -> Router {
@@ -121,6 +126,10 @@ pub fn router(
         .layer(DefaultBodyLimit::max(MAX_JSON_BODY_SIZE))
         .layer(middleware)
         .layer(cors)
+        .layer(axum::middleware::from_fn_with_state(
+            lanes,
+            crate::lanes::prioritize,
+        ))
         .layer(axum::middleware::from_fn(trace_request))
         .with_state(state)
 }
@@ -0,0 +1,11 @@
+//! Access token endpoints.
+
+use axum::{Router, routing::post};
+
+use crate::api::State;
+
+pub mod exchange;
+
+pub fn router() -> Router<State> {
+    Router::new().route("/exchange", post(exchange::handle))
+}
@@ -0,0 +1,57 @@
+//! Exchange an API key for an access token.
+
+use aerosol::axum::Dep;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::access::AccessTokenResponse;
+use tracing::info;
+
+use crate::{access::AccessTokens, auth::AuthenticatedToken};
+
+/// Issue a short-lived access token for the API key that authenticated the
+/// request; see [`crate::access`].
+///
+/// Access tokens themselves can't be exchanged, since that would let a client
+/// keep using a revoked API key indefinitely. Responds with `404 Not Found`
+/// if Courier isn't configured to issue access tokens.
+#[tracing::instrument(skip(auth))]
+pub async fn handle(
+    auth: AuthenticatedToken,
+    Dep(tokens): Dep<Option<AccessTokens>>,
+) -> ExchangeResponse {
+    let Some(tokens) = tokens else {
+        info!("access.exchange.not_configured");
+        return ExchangeResponse::NotConfigured;
+    };
+    if AccessTokens::is_access_token(&auth.plaintext) {
+        info!("access.exchange.access_token");
+        return ExchangeResponse::AccessToken;
+    }
+
+    let token = tokens.issue(&auth);
+    info!(org_id = %auth.org_id, ttl = ?tokens.ttl(), "access.exchange");
+    ExchangeResponse::Success(AccessTokenResponse {
+        token: token.expose().into(),
+        expires_in: tokens.ttl().as_secs(),
+    })
+}
+
+#[derive(Debug)]
+pub enum ExchangeResponse {
+    Success(AccessTokenResponse),
+    AccessToken,
+    NotConfigured,
+}
+
+impl IntoResponse for ExchangeResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            ExchangeResponse::Success(body) => (StatusCode::OK, Json(body)).into_response(),
+            ExchangeResponse::AccessToken => (
+                StatusCode::FORBIDDEN,
+                "access tokens can't be exchanged; use an API key",
+            )
+                .into_response(),
+            ExchangeResponse::NotConfigured => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+}
@@ -1,14 +1,41 @@
-use axum::{Router, routing::post};
+use axum::{
+    Router,
+    routing::{get, post},
+};
 
 use crate::api::State;
 
+pub mod encoding;
+pub mod finalize;
+pub mod generation;
+pub mod list;
+pub mod read_only;
 pub mod reset;
+pub mod reset_scope;
 pub mod restore;
 pub mod save;
+pub mod save_stream;
+pub mod usage;
+pub mod write_policy;
 
 pub fn router() -> Router<State> {
     Router::new()
         .route("/save", post(save::handle))
+        .route("/save/stream", post(save_stream::handle))
+        .route("/save/finalize", post(finalize::handle))
         .route("/restore", post(restore::handle))
+        .route("/list", post(list::handle))
         .route("/reset", post(reset::handle))
+        .route("/reset/scope", post(reset_scope::handle))
+        .route("/generation", get(generation::get::handle))
+        .route("/generation/bump", post(generation::bump::handle))
+        .route(
+            "/read-only",
+            get(read_only::get::handle).put(read_only::set::handle),
+        )
+        .route("/usage", get(usage::handle))
+        .route(
+            "/write-policy",
+            get(write_policy::get::handle).put(write_policy::set::handle),
+        )
 }
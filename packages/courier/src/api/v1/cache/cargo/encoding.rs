@@ -0,0 +1,106 @@
+//! Negotiated encoding of cache messages.
+//!
+//! Save requests and restore responses are sent as JSON by default, which is
+//! easy to inspect while debugging. Clients that send them as
+//! [`ContentType::MsgPackZstd`] instead save a lot of bandwidth on
+//! metadata-heavy projects; they're answered in the same encoding if they
+//! list it in `Accept`.
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Request},
+    http::{StatusCode, request::Parts},
+    response::{IntoResponse, Response},
+};
+use clients::{
+    ContentType,
+    courier::v1::cache::{decode_binary, encode_binary},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use tracing::error;
+
+use crate::api::MAX_JSON_BODY_SIZE;
+
+/// A cache message in the request body, encoded as JSON or as
+/// [`ContentType::MsgPackZstd`] according to its `Content-Type`.
+#[derive(Debug)]
+pub struct Message<T>(pub T);
+
+impl<T, S> FromRequest<S> for Message<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let binary = req
+            .headers()
+            .get(ContentType::HEADER)
+            .is_some_and(|value| value == ContentType::MsgPackZstd);
+        if !binary {
+            return Json::<T>::from_request(req, state)
+                .await
+                .map(|Json(message)| Self(message))
+                .map_err(IntoResponse::into_response);
+        }
+
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        decode_binary(&body, MAX_JSON_BODY_SIZE as u64)
+            .map(Self)
+            .map_err(|err| (StatusCode::BAD_REQUEST, format!("{err:?}")).into_response())
+    }
+}
+
+/// The encoding the client accepts cache messages in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    Binary,
+}
+
+impl Encoding {
+    /// Respond with the message in this encoding.
+    pub fn respond(self, status: StatusCode, message: &impl Serialize) -> Response {
+        match self {
+            Encoding::Json => (status, Json(message)).into_response(),
+            Encoding::Binary => match encode_binary(message) {
+                Ok(body) => (
+                    status,
+                    [(ContentType::HEADER, ContentType::MsgPackZstd.value())],
+                    body,
+                )
+                    .into_response(),
+                Err(err) => {
+                    error!(error = ?err, "encode response");
+                    (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:?}")).into_response()
+                }
+            },
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Encoding {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let binary = parts
+            .headers
+            .get_all(ContentType::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|accepted| {
+                accepted.split(';').next().map(str::trim) == Some(ContentType::MsgPackZstd.to_str())
+            });
+        Ok(if binary {
+            Encoding::Binary
+        } else {
+            Encoding::Json
+        })
+    }
+}
@@ -0,0 +1,66 @@
+use aerosol::axum::Dep;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::CargoFinalizeRequest;
+use color_eyre::eyre::Report;
+use tracing::{error, info, warn};
+
+use crate::{
+    auth::AuthenticatedToken,
+    db::Postgres,
+    maintenance::{self, ReadOnly},
+};
+
+/// Finalize the units saved by a build, so that they can be restored.
+///
+/// Units saved with a build ID are provisional until their build is
+/// finalized: a build that's interrupted partway through saving never
+/// finalizes, so units whose dependencies weren't saved are never restored.
+#[tracing::instrument(skip(auth))]
+pub async fn handle(
+    auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Dep(read_only): Dep<ReadOnly>,
+    Json(request): Json<CargoFinalizeRequest>,
+) -> CacheFinalizeResponse {
+    match read_only.check(&db, &auth).await {
+        Ok(None) => {}
+        Ok(Some(reason)) => {
+            warn!(?reason, "cache.finalize.read_only");
+            return CacheFinalizeResponse::ReadOnly(reason);
+        }
+        Err(err) => {
+            error!(error = ?err, "cache.finalize.read_only_error");
+            return CacheFinalizeResponse::Error(err);
+        }
+    }
+
+    match db.cargo_cache_finalize(&auth, &request.build_id).await {
+        Ok(()) => {
+            info!("cache.finalize.success");
+            CacheFinalizeResponse::Success
+        }
+        Err(err) => {
+            error!(error = ?err, "cache.finalize.error");
+            CacheFinalizeResponse::Error(err)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CacheFinalizeResponse {
+    Success,
+    ReadOnly(String),
+    Error(Report),
+}
+
+impl IntoResponse for CacheFinalizeResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            CacheFinalizeResponse::Success => StatusCode::NO_CONTENT.into_response(),
+            CacheFinalizeResponse::ReadOnly(reason) => maintenance::rejected(reason),
+            CacheFinalizeResponse::Error(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod bump;
+pub mod get;
@@ -0,0 +1,76 @@
+use aerosol::axum::Dep;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::CargoGenerationResponse;
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::{auth::AuthenticatedToken, db::Postgres};
+
+/// Bump the organization's cache generation, invalidating every unit saved
+/// before the bump. Only admins can perform this action.
+///
+/// This is meant for recovering from a bad cache (for example, after a broken
+/// toolchain rollout) without deleting it: units saved in earlier generations
+/// stay in the database until they're cleaned up, but clients stop restoring
+/// them.
+#[tracing::instrument(skip(auth))]
+pub async fn handle(auth: AuthenticatedToken, Dep(db): Dep<Postgres>) -> BumpResponse {
+    match db.get_member_role(auth.org_id, auth.account_id).await {
+        Ok(Some(role)) if role.is_admin() && !auth.read_only => {}
+        Ok(_) => {
+            warn!(
+                account_id = %auth.account_id,
+                org_id = %auth.org_id,
+                "cache.generation.bump.not_admin"
+            );
+            return BumpResponse::Forbidden;
+        }
+        Err(error) => {
+            error!(?error, "cache.generation.bump.role_check_error");
+            return BumpResponse::Error(error.to_string());
+        }
+    }
+
+    match db.cargo_cache_bump_generation(&auth).await {
+        Ok(generation) => {
+            let _ = db
+                .log_audit_event(
+                    Some(auth.account_id),
+                    Some(auth.org_id),
+                    "cache.generation.bumped",
+                    Some(json!({
+                        "generation": generation,
+                    })),
+                )
+                .await;
+
+            info!(generation, "cache.generation.bump.success");
+            BumpResponse::Success(CargoGenerationResponse::new(generation))
+        }
+        Err(error) => {
+            error!(?error, "cache.generation.bump.error");
+            BumpResponse::Error(error.to_string())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BumpResponse {
+    Success(CargoGenerationResponse),
+    Forbidden,
+    Error(String),
+}
+
+impl IntoResponse for BumpResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            BumpResponse::Success(body) => (StatusCode::OK, Json(body)).into_response(),
+            BumpResponse::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "Only admins can bump the cache generation",
+            )
+                .into_response(),
+            BumpResponse::Error(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response(),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use aerosol::axum::Dep;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::CargoGenerationResponse;
+use color_eyre::eyre::Report;
+use tracing::{error, info};
+
+use crate::{auth::AuthenticatedToken, db::Postgres};
+
+/// Get the organization's cache generation.
+///
+/// Clients mix the generation into the hashes under which they save and
+/// restore units, so they request it before planning a build.
+#[tracing::instrument(skip(auth))]
+pub async fn handle(auth: AuthenticatedToken, Dep(db): Dep<Postgres>) -> GenerationResponse {
+    match db.cargo_cache_generation(&auth).await {
+        Ok(generation) => {
+            info!(generation, "cache.generation.get.success");
+            GenerationResponse::Success(CargoGenerationResponse::new(generation))
+        }
+        Err(err) => {
+            error!(error = ?err, "cache.generation.get.error");
+            GenerationResponse::Error(err)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GenerationResponse {
+    Success(CargoGenerationResponse),
+    Error(Report),
+}
+
+impl IntoResponse for GenerationResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            GenerationResponse::Success(body) => (StatusCode::OK, Json(body)).into_response(),
+            GenerationResponse::Error(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use aerosol::axum::Dep;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::{CargoListRequest, CargoListResponse};
+use color_eyre::eyre::Report;
+use tracing::{error, info};
+
+use crate::{auth::AuthenticatedToken, db::Postgres};
+
+/// The number of units listed per page if the request doesn't say.
+const DEFAULT_LIMIT: u32 = 500;
+
+/// The maximum number of units listed per page.
+///
+/// Saved units are stored as JSON that can run to hundreds of kilobytes for
+/// crates with many outputs, so this keeps pages to a reasonable size.
+const MAX_LIMIT: u32 = 1000;
+
+#[tracing::instrument(skip(auth))]
+pub async fn handle(
+    auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Json(request): Json<CargoListRequest>,
+) -> CacheListResponse {
+    let limit = request.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    match db
+        .cargo_cache_list(&auth, request.after, i64::from(limit))
+        .await
+    {
+        Ok(page) => {
+            info!(count = page.units.len(), "cache.list.success");
+            CacheListResponse::Ok(page)
+        }
+        Err(err) => {
+            error!(error = ?err, "cache.list.error");
+            CacheListResponse::Error(err)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CacheListResponse {
+    Ok(CargoListResponse),
+    Error(Report),
+}
+
+impl IntoResponse for CacheListResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            CacheListResponse::Ok(body) => (StatusCode::OK, Json(body)).into_response(),
+            CacheListResponse::Error(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use aerosol::axum::Dep;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::CargoReadOnly;
+use color_eyre::eyre::Report;
+use tracing::{error, info};
+
+use crate::{auth::AuthenticatedToken, db::Postgres, maintenance::ReadOnly};
+
+/// Get whether the organization's cache is read-only, either because the
+/// instance is or because the organization was made read-only.
+///
+/// Clients request it before uploading, so that builds skip the upload
+/// instead of having their writes rejected.
+#[tracing::instrument(skip(auth))]
+pub async fn handle(
+    auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Dep(read_only): Dep<ReadOnly>,
+) -> ReadOnlyResponse {
+    match read_only.check(&db, &auth).await {
+        Ok(reason) => {
+            info!(?reason, "cache.read_only.get.success");
+            ReadOnlyResponse::Success(reason.map(CargoReadOnly::read_only).unwrap_or_default())
+        }
+        Err(err) => {
+            error!(error = ?err, "cache.read_only.get.error");
+            ReadOnlyResponse::Error(err)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReadOnlyResponse {
+    Success(CargoReadOnly),
+    Error(Report),
+}
+
+impl IntoResponse for ReadOnlyResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            ReadOnlyResponse::Success(body) => (StatusCode::OK, Json(body)).into_response(),
+            ReadOnlyResponse::Error(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use aerosol::axum::Dep;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::CargoReadOnly;
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::{auth::AuthenticatedToken, db::Postgres};
+
+/// Make the organization's cache read-only, or let it accept writes again.
+/// Only admins can perform this action.
+#[tracing::instrument(skip(auth))]
+pub async fn handle(
+    auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Json(read_only): Json<CargoReadOnly>,
+) -> SetReadOnlyResponse {
+    match db.get_member_role(auth.org_id, auth.account_id).await {
+        Ok(Some(role)) if role.is_admin() && !auth.read_only => {}
+        Ok(_) => {
+            warn!(
+                account_id = %auth.account_id,
+                org_id = %auth.org_id,
+                "cache.read_only.set.not_admin"
+            );
+            return SetReadOnlyResponse::Forbidden;
+        }
+        Err(error) => {
+            error!(?error, "cache.read_only.set.role_check_error");
+            return SetReadOnlyResponse::Error(error.to_string());
+        }
+    }
+
+    match db
+        .cargo_cache_set_read_only(&auth, read_only.reason.as_deref())
+        .await
+    {
+        Ok(()) => {
+            let _ = db
+                .log_audit_event(
+                    Some(auth.account_id),
+                    Some(auth.org_id),
+                    "cache.read_only.updated",
+                    Some(json!({
+                        "reason": read_only.reason,
+                    })),
+                )
+                .await;
+
+            info!(?read_only, "cache.read_only.set.success");
+            SetReadOnlyResponse::Success
+        }
+        Err(error) => {
+            error!(?error, "cache.read_only.set.error");
+            SetReadOnlyResponse::Error(error.to_string())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SetReadOnlyResponse {
+    Success,
+    Forbidden,
+    Error(String),
+}
+
+impl IntoResponse for SetReadOnlyResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            SetReadOnlyResponse::Success => StatusCode::OK.into_response(),
+            SetReadOnlyResponse::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "Only admins can make the cache read-only",
+            )
+                .into_response(),
+            SetReadOnlyResponse::Error(msg) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response()
+            }
+        }
+    }
+}
@@ -2,12 +2,23 @@ use aerosol::axum::Dep;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use color_eyre::eyre::Report;
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, warn};
 
 use crate::{auth::AuthenticatedToken, db::Postgres};
 
+/// Reset the organization's cache. Read-only API keys can't reset the cache,
+/// since they can't write to it either.
 #[instrument(skip(auth))]
 pub async fn handle(auth: AuthenticatedToken, Dep(db): Dep<Postgres>) -> CacheResetResponse {
+    if auth.read_only {
+        warn!(
+            account_id = %auth.account_id,
+            org_id = %auth.org_id,
+            "cache.reset.read_only_key"
+        );
+        return CacheResetResponse::Forbidden;
+    }
+
     match db.cargo_cache_reset(&auth).await {
         Ok(()) => {
             info!("cache.reset.success");
@@ -23,6 +34,7 @@ pub async fn handle(auth: AuthenticatedToken, Dep(db): Dep<Postgres>) -> CacheRe
 #[derive(Debug)]
 pub enum CacheResetResponse {
     Success,
+    Forbidden,
     Error(Report),
 }
 
@@ -30,6 +42,11 @@ impl IntoResponse for CacheResetResponse {
     fn into_response(self) -> axum::response::Response {
         match self {
             CacheResetResponse::Success => StatusCode::NO_CONTENT.into_response(),
+            CacheResetResponse::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "Read-only API keys can't reset the cache",
+            )
+                .into_response(),
             CacheResetResponse::Error(error) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
             }
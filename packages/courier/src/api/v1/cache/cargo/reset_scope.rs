@@ -0,0 +1,81 @@
+use aerosol::axum::Dep;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::{CargoScopeResetRequest, CargoScopeResetResponse};
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::{auth::AuthenticatedToken, db::Postgres};
+
+/// Reset a single cache scope, deleting the units saved in it. Only admins
+/// can perform this action.
+///
+/// Unlike resetting the whole cache, this leaves the organization's access to
+/// CAS objects alone, since units in other scopes may reference the same
+/// objects; objects that no unit references anymore are left for garbage
+/// collection.
+#[tracing::instrument(skip(auth))]
+pub async fn handle(
+    auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Json(request): Json<CargoScopeResetRequest>,
+) -> ResetScopeResponse {
+    match db.get_member_role(auth.org_id, auth.account_id).await {
+        Ok(Some(role)) if role.is_admin() && !auth.read_only => {}
+        Ok(_) => {
+            warn!(
+                account_id = %auth.account_id,
+                org_id = %auth.org_id,
+                "cache.reset_scope.not_admin"
+            );
+            return ResetScopeResponse::Forbidden;
+        }
+        Err(error) => {
+            error!(?error, "cache.reset_scope.role_check_error");
+            return ResetScopeResponse::Error(error.to_string());
+        }
+    }
+
+    match db.cargo_cache_reset_scope(&auth, &request.scope).await {
+        Ok(units_deleted) => {
+            let _ = db
+                .log_audit_event(
+                    Some(auth.account_id),
+                    Some(auth.org_id),
+                    "cache.scope.reset",
+                    Some(json!({
+                        "scope": request.scope,
+                        "units_deleted": units_deleted,
+                    })),
+                )
+                .await;
+
+            info!(scope = %request.scope, units_deleted, "cache.reset_scope.success");
+            ResetScopeResponse::Success(CargoScopeResetResponse::new(units_deleted))
+        }
+        Err(error) => {
+            error!(?error, "cache.reset_scope.error");
+            ResetScopeResponse::Error(error.to_string())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ResetScopeResponse {
+    Success(CargoScopeResetResponse),
+    Forbidden,
+    Error(String),
+}
+
+impl IntoResponse for ResetScopeResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            ResetScopeResponse::Success(body) => (StatusCode::OK, Json(body)).into_response(),
+            ResetScopeResponse::Forbidden => {
+                (StatusCode::FORBIDDEN, "Only admins can reset a cache scope").into_response()
+            }
+            ResetScopeResponse::Error(msg) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response()
+            }
+        }
+    }
+}
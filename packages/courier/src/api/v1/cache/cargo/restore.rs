@@ -1,25 +1,42 @@
 use aerosol::axum::Dep;
-use axum::{Json, http::StatusCode, response::IntoResponse};
-use clients::courier::v1::cache::{CargoRestoreRequest, CargoRestoreResponse};
+use axum::{http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::{CacheAsOf, CargoRestoreRequest, CargoRestoreResponse};
 use color_eyre::eyre::Report;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
 use tracing::{error, info};
 
+use super::encoding::{Encoding, Message};
 use crate::{auth::AuthenticatedToken, db::Postgres};
 
 #[tracing::instrument(skip_all)]
 pub async fn handle(
     auth: AuthenticatedToken,
     Dep(db): Dep<Postgres>,
-    Json(request): Json<CargoRestoreRequest>,
+    encoding: Encoding,
+    Message(request): Message<CargoRestoreRequest>,
 ) -> CacheRestoreResponse {
-    match db.cargo_cache_restore(&auth, request).await {
-        Ok(artifacts) if artifacts.is_empty() => {
+    let as_of = match resolve_as_of(&db, &auth, request.as_of.as_ref()).await {
+        Ok(as_of) => as_of,
+        Err(response) => return response,
+    };
+    let near_matches = match db.cargo_cache_near_matches(&auth, &request, as_of).await {
+        Ok(near_matches) => near_matches,
+        Err(err) => {
+            error!(error = ?err, "cache.restore.error");
+            return CacheRestoreResponse::Error(err);
+        }
+    };
+    match db.cargo_cache_restore(&auth, request, as_of).await {
+        Ok(artifacts) if artifacts.is_empty() && near_matches.is_empty() => {
             info!("cache.restore.miss");
             CacheRestoreResponse::NotFound
         }
         Ok(artifacts) => {
-            info!("cache.restore.hit");
-            CacheRestoreResponse::Ok(CargoRestoreResponse::new(artifacts))
+            info!(near_match_keys = near_matches.len(), "cache.restore.hit");
+            CacheRestoreResponse::Ok(
+                CargoRestoreResponse::new(artifacts).with_near_matches(near_matches),
+                encoding,
+            )
         }
         Err(err) => {
             error!(error = ?err, "cache.restore.error");
@@ -28,18 +45,75 @@ pub async fn handle(
     }
 }
 
+/// Resolve the point in time that the request restores units as of, if any.
+async fn resolve_as_of(
+    db: &Postgres,
+    auth: &AuthenticatedToken,
+    as_of: Option<&CacheAsOf>,
+) -> Result<Option<OffsetDateTime>, CacheRestoreResponse> {
+    let commit = match as_of {
+        None => return Ok(None),
+        Some(CacheAsOf::Time(time)) => {
+            return match OffsetDateTime::parse(time, &Rfc3339) {
+                Ok(time) => Ok(Some(time)),
+                Err(err) => {
+                    info!(error = ?err, "cache.restore.invalid_as_of");
+                    Err(CacheRestoreResponse::InvalidAsOf(format!(
+                        "invalid RFC 3339 timestamp: {time}"
+                    )))
+                }
+            };
+        }
+        Some(CacheAsOf::Commit(commit)) => commit,
+    };
+
+    if commit.len() < CacheAsOf::MIN_COMMIT_PREFIX || !commit.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        info!("cache.restore.invalid_as_of");
+        return Err(CacheRestoreResponse::InvalidAsOf(format!(
+            "invalid commit SHA, expected at least {} hex digits: {commit}",
+            CacheAsOf::MIN_COMMIT_PREFIX
+        )));
+    }
+    match db.cargo_cache_commit_times(auth, commit).await {
+        Ok(times) => match times.as_slice() {
+            [(_, time)] => Ok(Some(*time)),
+            [] => {
+                info!("cache.restore.unknown_commit");
+                Err(CacheRestoreResponse::InvalidAsOf(format!(
+                    "no units built from commit {commit} were uploaded"
+                )))
+            }
+            _ => {
+                info!("cache.restore.ambiguous_commit");
+                Err(CacheRestoreResponse::InvalidAsOf(format!(
+                    "commit SHA prefix is ambiguous: {commit}"
+                )))
+            }
+        },
+        Err(err) => {
+            error!(error = ?err, "cache.restore.error");
+            Err(CacheRestoreResponse::Error(err))
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum CacheRestoreResponse {
-    Ok(CargoRestoreResponse),
+    Ok(CargoRestoreResponse, Encoding),
     NotFound,
+    InvalidAsOf(String),
     Error(Report),
 }
 
 impl IntoResponse for CacheRestoreResponse {
     fn into_response(self) -> axum::response::Response {
         match self {
-            CacheRestoreResponse::Ok(body) => (StatusCode::OK, Json(body)).into_response(),
+            CacheRestoreResponse::Ok(body, encoding) => encoding.respond(StatusCode::OK, &body),
             CacheRestoreResponse::NotFound => StatusCode::NOT_FOUND.into_response(),
+            CacheRestoreResponse::InvalidAsOf(message) => {
+                (StatusCode::BAD_REQUEST, message).into_response()
+            }
             CacheRestoreResponse::Error(error) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
             }
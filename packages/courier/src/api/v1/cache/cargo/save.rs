@@ -1,17 +1,71 @@
+use std::collections::HashSet;
+
 use aerosol::axum::Dep;
-use axum::{Json, http::StatusCode, response::IntoResponse};
-use clients::courier::v1::cache::CargoSaveRequest;
-use color_eyre::eyre::Report;
-use tracing::{error, info};
+use axum::{http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::{CargoSaveRequest, CargoSaveUnitRequest, CargoStorageUsage};
+use color_eyre::{Result, eyre::Report};
+use tracing::{error, info, warn};
 
-use crate::{auth::AuthenticatedToken, db::Postgres};
+use super::encoding::Message;
+use crate::{
+    auth::AuthenticatedToken,
+    db::Postgres,
+    maintenance::{self, ReadOnly},
+    quota::{self, StorageQuota},
+    storage::Disk,
+};
 
 #[tracing::instrument(skip(auth))]
 pub async fn handle(
     auth: AuthenticatedToken,
     Dep(db): Dep<Postgres>,
-    Json(request): Json<CargoSaveRequest>,
+    Dep(cas): Dep<Disk>,
+    Dep(read_only): Dep<ReadOnly>,
+    Dep(quota): Dep<StorageQuota>,
+    Message(request): Message<CargoSaveRequest>,
 ) -> CacheSaveResponse {
+    match read_only.check(&db, &auth).await {
+        Ok(None) => {}
+        Ok(Some(reason)) => {
+            warn!(?reason, "cache.save.read_only");
+            return CacheSaveResponse::ReadOnly(reason);
+        }
+        Err(err) => {
+            error!(error = ?err, "cache.save.read_only_error");
+            return CacheSaveResponse::Error(err);
+        }
+    }
+
+    match quota.check(&db, &auth).await {
+        Ok(None) => {}
+        Ok(Some(usage)) => {
+            warn!(?usage, "cache.save.quota_exceeded");
+            return CacheSaveResponse::QuotaExceeded(usage);
+        }
+        Err(err) => {
+            error!(error = ?err, "cache.save.quota_error");
+            return CacheSaveResponse::Error(err);
+        }
+    }
+
+    let branch = request.metadata().branch.as_deref();
+    match db.cargo_cache_write_policy(&auth).await {
+        Ok(policy) if policy.allows(branch) => {}
+        Ok(_) => {
+            warn!(?branch, "cache.save.branch_not_allowed");
+            return CacheSaveResponse::Forbidden;
+        }
+        Err(err) => {
+            error!(error = ?err, "cache.save.write_policy_error");
+            return CacheSaveResponse::Error(err);
+        }
+    }
+
+    if let Err(err) = remove_broken_units(&db, &cas, &auth, request.iter()).await {
+        error!(error = ?err, "cache.save.repair_error");
+        return CacheSaveResponse::Error(err);
+    }
+
     match db.cargo_cache_save(&auth, request).await {
         Ok(()) => {
             info!("cache.save.created");
@@ -24,9 +78,56 @@ pub async fn handle(
     }
 }
 
+/// Remove the saved units that units in a save request repair, if they're
+/// actually broken, so that the request's units are saved in their place.
+///
+/// Clients ask to repair a unit when objects it references were missing from
+/// the CAS when they tried to restore it. Saved units whose objects are all
+/// present are kept: the repair was probably a transient failure, and
+/// replacing the unit would only churn the cache.
+pub(super) async fn remove_broken_units(
+    db: &Postgres,
+    cas: &Disk,
+    auth: &AuthenticatedToken,
+    units: impl IntoIterator<Item = &CargoSaveUnitRequest>,
+) -> Result<()> {
+    let repairs = units
+        .into_iter()
+        .filter(|unit| unit.repair)
+        .map(|unit| unit.saved_unit_hash())
+        .collect::<HashSet<_>>();
+    if repairs.is_empty() {
+        return Ok(());
+    }
+
+    let mut broken = HashSet::new();
+    for (hash, unit) in db.cargo_cache_load(auth, &repairs).await? {
+        for key in unit.object_keys() {
+            if !cas.exists(key).await? {
+                broken.insert(hash);
+                break;
+            }
+        }
+    }
+    if broken.is_empty() {
+        info!(requested = repairs.len(), "cache.save.repair.intact");
+        return Ok(());
+    }
+
+    let removed = db.cargo_cache_remove(auth, &broken).await?;
+    info!(
+        requested = repairs.len(),
+        removed, "cache.save.repair.removed"
+    );
+    Ok(())
+}
+
 #[derive(Debug)]
 pub enum CacheSaveResponse {
     Created,
+    Forbidden,
+    ReadOnly(String),
+    QuotaExceeded(CargoStorageUsage),
     Error(Report),
 }
 
@@ -34,6 +135,13 @@ impl IntoResponse for CacheSaveResponse {
     fn into_response(self) -> axum::response::Response {
         match self {
             CacheSaveResponse::Created => StatusCode::CREATED.into_response(),
+            CacheSaveResponse::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "This branch can't save units to the cache",
+            )
+                .into_response(),
+            CacheSaveResponse::ReadOnly(reason) => maintenance::rejected(reason),
+            CacheSaveResponse::QuotaExceeded(usage) => quota::rejected(usage),
             CacheSaveResponse::Error(error) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
             }
@@ -0,0 +1,202 @@
+use aerosol::axum::Dep;
+use axum::{body::Body, extract::Query, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::{
+    CargoSaveRequest, CargoSaveUnitRequest, CargoStorageUsage, SavedUnitMetadata,
+};
+use color_eyre::{
+    Report, Result,
+    eyre::{Context, eyre},
+};
+use futures::{StreamExt, TryStreamExt};
+use tokio::io::AsyncBufReadExt;
+use tokio_util::io::StreamReader;
+use tracing::{error, info, warn};
+
+use super::save::remove_broken_units;
+use crate::{
+    auth::AuthenticatedToken,
+    db::Postgres,
+    maintenance::{self, ReadOnly},
+    quota::{self, StorageQuota},
+    storage::Disk,
+};
+
+/// The number of units inserted per transaction.
+///
+/// Batching keeps memory bounded regardless of how many units the request
+/// contains, while still amortizing the cost of each transaction.
+const BATCH_SIZE: usize = 100;
+
+/// Save cargo cache metadata streamed as newline-delimited JSON.
+///
+/// This is equivalent to the `/save` endpoint, but instead of a single JSON
+/// document the body contains one [`CargoSaveUnitRequest`] per line. Units are
+/// inserted in batches as they arrive, so the request isn't subject to the
+/// JSON body size limit and Courier never holds the whole request in memory.
+///
+/// Since [`SavedUnitMetadata`] applies to every unit in the request, it's
+/// provided in the query string rather than repeated on every line.
+///
+/// Like `/save`, the request is rejected if the organization's write policy
+/// doesn't let the branch in the metadata save units, if the cache is
+/// read-only, or if the organization's storage quota is used up.
+///
+/// Batches are committed independently: if the request fails partway through,
+/// units from earlier batches remain saved. This is safe because saves are
+/// idempotent, so clients can simply retry the whole request. Clients that
+/// send a build ID in the metadata also keep those units from being restored
+/// until the build is finalized with `/save/finalize`.
+#[tracing::instrument(skip(auth, body))]
+pub async fn handle(
+    auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Dep(cas): Dep<Disk>,
+    Dep(read_only): Dep<ReadOnly>,
+    Dep(quota): Dep<StorageQuota>,
+    Query(metadata): Query<SavedUnitMetadata>,
+    body: Body,
+) -> CacheSaveStreamResponse {
+    match read_only.check(&db, &auth).await {
+        Ok(None) => {}
+        Ok(Some(reason)) => {
+            // Drain the body so that the client sees the response instead of
+            // a connection reset.
+            body.into_data_stream().for_each(|_| async {}).await;
+            warn!(?reason, "cache.save.stream.read_only");
+            return CacheSaveStreamResponse::ReadOnly(reason);
+        }
+        Err(err) => {
+            error!(error = ?err, "cache.save.stream.read_only_error");
+            return CacheSaveStreamResponse::Error(err);
+        }
+    }
+
+    match quota.check(&db, &auth).await {
+        Ok(None) => {}
+        Ok(Some(usage)) => {
+            // Drain the body, as above.
+            body.into_data_stream().for_each(|_| async {}).await;
+            warn!(?usage, "cache.save.stream.quota_exceeded");
+            return CacheSaveStreamResponse::QuotaExceeded(usage);
+        }
+        Err(err) => {
+            error!(error = ?err, "cache.save.stream.quota_error");
+            return CacheSaveStreamResponse::Error(err);
+        }
+    }
+
+    let branch = metadata.branch.as_deref();
+    match db.cargo_cache_write_policy(&auth).await {
+        Ok(policy) if policy.allows(branch) => {}
+        Ok(_) => {
+            warn!(?branch, "cache.save.stream.branch_not_allowed");
+            return CacheSaveStreamResponse::Forbidden;
+        }
+        Err(err) => {
+            error!(error = ?err, "cache.save.stream.write_policy_error");
+            return CacheSaveStreamResponse::Error(err);
+        }
+    }
+
+    let stream = body.into_data_stream().map_err(std::io::Error::other);
+    let mut lines = StreamReader::new(stream).lines();
+
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut saved = 0usize;
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                let err = eyre!(err).wrap_err("read request body");
+                error!(error = ?err, "cache.save.stream.invalid");
+                return CacheSaveStreamResponse::InvalidRequest(err);
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let unit = match serde_json::from_str::<CargoSaveUnitRequest>(&line)
+            .with_context(|| format!("parse unit on line {}", saved + batch.len() + 1))
+        {
+            Ok(unit) => unit,
+            Err(err) => {
+                error!(error = ?err, "cache.save.stream.invalid");
+                return CacheSaveStreamResponse::InvalidRequest(err);
+            }
+        };
+        batch.push(unit);
+
+        if batch.len() >= BATCH_SIZE {
+            if let Err(err) = save_batch(&db, &cas, &auth, &metadata, &mut batch, &mut saved).await
+            {
+                error!(error = ?err, saved, "cache.save.stream.error");
+                return CacheSaveStreamResponse::Error(err);
+            }
+        }
+    }
+
+    if let Err(err) = save_batch(&db, &cas, &auth, &metadata, &mut batch, &mut saved).await {
+        error!(error = ?err, saved, "cache.save.stream.error");
+        return CacheSaveStreamResponse::Error(err);
+    }
+
+    info!(saved, "cache.save.stream.created");
+    CacheSaveStreamResponse::Created
+}
+
+async fn save_batch(
+    db: &Postgres,
+    cas: &Disk,
+    auth: &AuthenticatedToken,
+    metadata: &SavedUnitMetadata,
+    batch: &mut Vec<CargoSaveUnitRequest>,
+    saved: &mut usize,
+) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    remove_broken_units(db, cas, auth, batch.iter())
+        .await
+        .context("repair batch")?;
+    let count = batch.len();
+    let request = CargoSaveRequest::new(batch.drain(..)).with_metadata(metadata.clone());
+    db.cargo_cache_save(auth, request)
+        .await
+        .context("save batch")?;
+    *saved += count;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum CacheSaveStreamResponse {
+    Created,
+    InvalidRequest(Report),
+    Forbidden,
+    ReadOnly(String),
+    QuotaExceeded(CargoStorageUsage),
+    Error(Report),
+}
+
+impl IntoResponse for CacheSaveStreamResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            CacheSaveStreamResponse::Created => StatusCode::CREATED.into_response(),
+            CacheSaveStreamResponse::InvalidRequest(error) => {
+                (StatusCode::BAD_REQUEST, format!("{error:?}")).into_response()
+            }
+            CacheSaveStreamResponse::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "This branch can't save units to the cache",
+            )
+                .into_response(),
+            CacheSaveStreamResponse::ReadOnly(reason) => maintenance::rejected(reason),
+            CacheSaveStreamResponse::QuotaExceeded(usage) => quota::rejected(usage),
+            CacheSaveStreamResponse::Error(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use aerosol::axum::Dep;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::CargoStorageUsage;
+use color_eyre::eyre::Report;
+use tracing::{error, info};
+
+use crate::{auth::AuthenticatedToken, db::Postgres, quota::StorageQuota};
+
+/// Get how much the organization stores in the cache, and its quota.
+///
+/// This is the same as `GET /api/v1/organizations/{org_id}/usage`, for the
+/// organization the API key is scoped to.
+#[tracing::instrument(skip(auth))]
+pub async fn handle(
+    auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Dep(quota): Dep<StorageQuota>,
+) -> UsageResponse {
+    match quota.usage(&db, auth.org_id).await {
+        Ok(usage) => {
+            info!(?usage, "cache.usage.success");
+            UsageResponse::Success(usage)
+        }
+        Err(err) => {
+            error!(error = ?err, "cache.usage.error");
+            UsageResponse::Error(err)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UsageResponse {
+    Success(CargoStorageUsage),
+    Error(Report),
+}
+
+impl IntoResponse for UsageResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            UsageResponse::Success(body) => (StatusCode::OK, Json(body)).into_response(),
+            UsageResponse::Error(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use aerosol::axum::Dep;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::CargoWritePolicy;
+use color_eyre::eyre::Report;
+use tracing::{error, info};
+
+use crate::{auth::AuthenticatedToken, db::Postgres};
+
+/// Get the organization's cache write policy.
+///
+/// Clients request it before uploading, so that builds of branches that can't
+/// save units skip the upload instead of having their save rejected.
+#[tracing::instrument(skip(auth))]
+pub async fn handle(auth: AuthenticatedToken, Dep(db): Dep<Postgres>) -> WritePolicyResponse {
+    match db.cargo_cache_write_policy(&auth).await {
+        Ok(policy) => {
+            info!(?policy, "cache.write_policy.get.success");
+            WritePolicyResponse::Success(policy)
+        }
+        Err(err) => {
+            error!(error = ?err, "cache.write_policy.get.error");
+            WritePolicyResponse::Error(err)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WritePolicyResponse {
+    Success(CargoWritePolicy),
+    Error(Report),
+}
+
+impl IntoResponse for WritePolicyResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            WritePolicyResponse::Success(body) => (StatusCode::OK, Json(body)).into_response(),
+            WritePolicyResponse::Error(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
+            }
+        }
+    }
+}
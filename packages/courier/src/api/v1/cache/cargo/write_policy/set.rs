@@ -0,0 +1,77 @@
+use aerosol::axum::Dep;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::CargoWritePolicy;
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::{auth::AuthenticatedToken, db::Postgres};
+
+/// Replace the organization's cache write policy. Only admins can perform
+/// this action.
+#[tracing::instrument(skip(auth))]
+pub async fn handle(
+    auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Json(policy): Json<CargoWritePolicy>,
+) -> SetWritePolicyResponse {
+    match db.get_member_role(auth.org_id, auth.account_id).await {
+        Ok(Some(role)) if role.is_admin() && !auth.read_only => {}
+        Ok(_) => {
+            warn!(
+                account_id = %auth.account_id,
+                org_id = %auth.org_id,
+                "cache.write_policy.set.not_admin"
+            );
+            return SetWritePolicyResponse::Forbidden;
+        }
+        Err(error) => {
+            error!(?error, "cache.write_policy.set.role_check_error");
+            return SetWritePolicyResponse::Error(error.to_string());
+        }
+    }
+
+    match db.cargo_cache_set_write_policy(&auth, &policy).await {
+        Ok(()) => {
+            let _ = db
+                .log_audit_event(
+                    Some(auth.account_id),
+                    Some(auth.org_id),
+                    "cache.write_policy.updated",
+                    Some(json!({
+                        "branches": policy.branches,
+                    })),
+                )
+                .await;
+
+            info!(?policy, "cache.write_policy.set.success");
+            SetWritePolicyResponse::Success
+        }
+        Err(error) => {
+            error!(?error, "cache.write_policy.set.error");
+            SetWritePolicyResponse::Error(error.to_string())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SetWritePolicyResponse {
+    Success,
+    Forbidden,
+    Error(String),
+}
+
+impl IntoResponse for SetWritePolicyResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            SetWritePolicyResponse::Success => StatusCode::OK.into_response(),
+            SetWritePolicyResponse::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "Only admins can change the cache write policy",
+            )
+                .into_response(),
+            SetWritePolicyResponse::Error(msg) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,11 @@
+//! Cargo unit endpoints that aren't part of the cache protocol itself.
+
+use axum::{Router, routing::get};
+
+use crate::api::State;
+
+pub mod origins;
+
+pub fn router() -> Router<State> {
+    Router::new().route("/unit/{unit_hash}/origins", get(origins::handle))
+}
@@ -0,0 +1,81 @@
+//! Unit provenance endpoint.
+
+use aerosol::axum::Dep;
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use clients::courier::v1::{SavedUnitHash, cache::CargoUnitOriginsResponse};
+use color_eyre::eyre::Report;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{error, info};
+
+use crate::{auth::AuthenticatedToken, db::Postgres};
+
+#[derive(Debug, Deserialize)]
+pub struct OriginsParams {
+    /// Maximum number of uploads to return. Defaults to 25.
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+fn default_limit() -> i64 {
+    25
+}
+
+/// List the uploads of a saved unit, most recent first.
+///
+/// This is for investigating a bad unit, so each query is recorded in the
+/// organization's audit log: knowing who looked into a unit matters when
+/// reconstructing an incident.
+#[tracing::instrument(skip(auth))]
+pub async fn handle(
+    auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Path(unit_hash): Path<String>,
+    Query(params): Query<OriginsParams>,
+) -> CargoUnitOriginsResult {
+    let unit_hash = SavedUnitHash::new(unit_hash);
+    let limit = params.limit.clamp(1, 100);
+    let origins = match db.cargo_unit_origins(&auth, &unit_hash, limit).await {
+        Ok(origins) => origins,
+        Err(err) => {
+            error!(error = ?err, "cargo.unit.origins.error");
+            return CargoUnitOriginsResult::Error(err);
+        }
+    };
+
+    let _ = db
+        .log_audit_event(
+            Some(auth.account_id),
+            Some(auth.org_id),
+            "cargo.unit.origins_viewed",
+            Some(json!({ "unit_hash": unit_hash.as_str() })),
+        )
+        .await;
+
+    info!(%unit_hash, count = origins.origins.len(), "cargo.unit.origins.success");
+    CargoUnitOriginsResult::Success(origins)
+}
+
+#[derive(Debug)]
+pub enum CargoUnitOriginsResult {
+    Success(CargoUnitOriginsResponse),
+    Error(Report),
+}
+
+impl IntoResponse for CargoUnitOriginsResult {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            CargoUnitOriginsResult::Success(origins) => {
+                (StatusCode::OK, Json(origins)).into_response()
+            }
+            CargoUnitOriginsResult::Error(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
+            }
+        }
+    }
+}
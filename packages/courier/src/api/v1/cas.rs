@@ -1,13 +1,24 @@
 use axum::{
     Router,
-    routing::{get, head, post, put},
+    routing::{get, head, patch, post, put},
 };
 
-use crate::api::State;
+use color_eyre::Result;
 
+use crate::{
+    api::State,
+    auth::AuthenticatedToken,
+    db::Postgres,
+    storage::{Disk, Key},
+};
+
+pub mod algorithms;
 pub mod bulk;
 pub mod check;
+pub mod dictionaries;
+pub mod direct;
 pub mod read;
+pub mod upload;
 pub mod write;
 
 pub fn router() -> Router<State> {
@@ -15,6 +26,26 @@ pub fn router() -> Router<State> {
         .route("/{key}", head(check::handle))
         .route("/{key}", get(read::handle))
         .route("/{key}", put(write::handle))
+        .route("/uploads/{key}", get(upload::status))
+        .route("/uploads/{key}", patch(upload::append))
+        .route("/uploads/{key}/complete", post(upload::complete))
         .route("/bulk/read", post(bulk::read::handle))
         .route("/bulk/write", post(bulk::write::handle))
+        .route("/direct", post(direct::handle))
+        .route("/dictionaries", get(dictionaries::handle))
+        .route("/algorithms", get(algorithms::handle))
+}
+
+/// Grant the organization access to an object in the CAS, counting its
+/// compressed size towards the organization's storage usage.
+///
+/// Returns `true` if access was newly granted.
+pub(crate) async fn grant_access(
+    db: &Postgres,
+    cas: &Disk,
+    auth: &AuthenticatedToken,
+    key: &Key,
+) -> Result<bool> {
+    let size = cas.size_compressed(key).await?.unwrap_or_default();
+    db.grant_cas_access(auth, key, size).await
 }
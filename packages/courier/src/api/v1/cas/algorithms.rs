@@ -0,0 +1,34 @@
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::{HashAlgorithm, cas::CasAlgorithmsResponse};
+use tap::Pipe;
+use tracing::info;
+
+use crate::auth::AuthenticatedToken;
+
+/// List the hash algorithms accepted for CAS keys.
+///
+/// Every algorithm is accepted for both reads and writes, so organizations can
+/// switch algorithms while still restoring content saved under the previous
+/// one. Clients use this to check that Courier supports the algorithm they're
+/// configured to use before uploading with it.
+#[tracing::instrument(skip(_auth))]
+pub async fn handle(_auth: AuthenticatedToken) -> AlgorithmsResponse {
+    info!("cas.algorithms.list");
+    CasAlgorithmsResponse::builder()
+        .algorithms(HashAlgorithm::ALL)
+        .build()
+        .pipe(AlgorithmsResponse::Success)
+}
+
+#[derive(Debug)]
+pub enum AlgorithmsResponse {
+    Success(CasAlgorithmsResponse),
+}
+
+impl IntoResponse for AlgorithmsResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            AlgorithmsResponse::Success(body) => (StatusCode::OK, Json(body)).into_response(),
+        }
+    }
+}
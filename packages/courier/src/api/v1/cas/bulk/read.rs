@@ -10,17 +10,21 @@ use axum::{
 };
 use clients::{
     ContentType, NETWORK_BUFFER_SIZE,
-    courier::v1::{Key, cas::CasBulkReadRequest},
+    courier::v1::{
+        Key,
+        cas::{CasBulkReadRequest, DICTIONARY_MAX_OBJECT_SIZE, Dictionary},
+    },
 };
-use color_eyre::Report;
+use color_eyre::{Report, Result, eyre::bail};
 use futures::AsyncWriteExt;
+use tokio::io::AsyncReadExt;
 use tokio_util::{
     compat::{FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt},
     io::ReaderStream,
 };
-use tracing::{Instrument, error, info};
+use tracing::{Instrument, error, info, warn};
 
-use crate::{auth::AuthenticatedToken, db::Postgres, storage::Disk};
+use crate::{auth::AuthenticatedToken, db::Postgres, dictionary, storage::Disk};
 
 /// Read multiple blobs from the CAS and return them as a tar archive.
 ///
@@ -51,6 +55,14 @@ use crate::{auth::AuthenticatedToken, db::Postgres, storage::Disk};
 /// indicates whether the individual blobs inside the tar should be compressed.
 /// Missing keys are silently skipped.
 ///
+/// ## Dictionaries
+///
+/// If the request names one of the organization's compression dictionaries,
+/// compressed responses use it for blobs no larger than
+/// [`DICTIONARY_MAX_OBJECT_SIZE`]. The zstd frame header of each blob records
+/// whether it was compressed with the dictionary. If the dictionary can't be
+/// loaded, blobs are compressed without it.
+///
 /// ## Streaming
 ///
 /// The tar archive is streamed directly to the client without buffering the
@@ -79,17 +91,40 @@ pub async fn handle(
         .is_some_and(|accept| accept == ContentType::TarZstd);
 
     if want_compressed {
-        handle_compressed(cas, accessible_keys, req).await
+        let dictionary = match &req.dictionary {
+            Some(key) => load_dictionary(&auth, &db, &cas, key)
+                .await
+                .inspect_err(|error| warn!(%key, ?error, "cas.bulk.read.dictionary.error"))
+                .ok(),
+            None => None,
+        };
+        handle_compressed(cas, accessible_keys, req, dictionary).await
     } else {
         handle_plain(cas, accessible_keys, req).await
     }
 }
 
+/// Load the dictionary requested by the client, if it belongs to the
+/// organization.
+#[tracing::instrument(skip(auth))]
+async fn load_dictionary(
+    auth: &AuthenticatedToken,
+    db: &Postgres,
+    cas: &Disk,
+    key: &Key,
+) -> Result<Dictionary> {
+    if !db.is_cas_dictionary(auth, key).await? {
+        bail!("not a dictionary for this organization");
+    }
+    dictionary::load(cas, key).await
+}
+
 #[tracing::instrument(skip(accessible_keys))]
 async fn handle_compressed(
     cas: Disk,
     accessible_keys: HashSet<Key>,
     req: CasBulkReadRequest,
+    dictionary: Option<Dictionary>,
 ) -> BulkReadResponse {
     info!("cas.bulk.read.compressed");
 
@@ -105,6 +140,35 @@ async fn handle_compressed(
                     continue;
                 }
 
+                if let Some(dictionary) = &dictionary {
+                    match compress_with_dictionary(&cas, &key, dictionary).await {
+                        Ok(Some(compressed)) => {
+                            let bytes = compressed.len() as u64;
+                            let header = {
+                                let mut header = Header::new_gnu();
+                                if let Err(error) = header.set_path(key.to_hex()) {
+                                    error!(%key, ?error, "cas.bulk.read.header.set_path.error");
+                                    continue;
+                                }
+                                header.set_size(bytes);
+                                header.set_mode(0o644);
+                                header.set_cksum();
+                                header
+                            };
+                            match builder.append(&header, compressed.as_slice()).await {
+                                Ok(_) => info!(%key, bytes, "cas.bulk.read.append.dictionary.success"),
+                                Err(error) => error!(%key, ?error, "cas.bulk.read.append.error"),
+                            }
+                            continue;
+                        }
+                        Ok(None) => {}
+                        Err(error) => {
+                            error!(%key, ?error, "cas.bulk.read.dictionary.compress.error");
+                            continue;
+                        }
+                    }
+                }
+
                 let reader = match cas.read_compressed(&key).await {
                     Ok(reader) => reader,
                     Err(error) => {
@@ -237,6 +301,28 @@ async fn handle_plain(
     BulkReadResponse::Success(body, ContentType::Tar)
 }
 
+/// Compress the blob with the dictionary if it's small enough to benefit.
+///
+/// Returns `None` for larger blobs, which are sent as stored.
+async fn compress_with_dictionary(
+    cas: &Disk,
+    key: &Key,
+    dictionary: &Dictionary,
+) -> Result<Option<Vec<u8>>> {
+    match cas.size(key).await? {
+        Some(size) if size <= DICTIONARY_MAX_OBJECT_SIZE => {}
+        _ => return Ok(None),
+    }
+
+    let mut content = Vec::new();
+    cas.read(key)
+        .await?
+        .take(DICTIONARY_MAX_OBJECT_SIZE)
+        .read_to_end(&mut content)
+        .await?;
+    dictionary.compress(&content).map(Some)
+}
+
 #[derive(Debug)]
 pub enum BulkReadResponse {
     Success(Body, ContentType),
@@ -1,4 +1,5 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap, hash_map::Entry};
+use std::io::Cursor;
 
 use aerosol::axum::Dep;
 use async_tar::Archive;
@@ -10,20 +11,30 @@ use axum::{
 };
 use clients::{
     ContentType,
-    courier::v1::cas::{CasBulkWriteKeyError, CasBulkWriteResponse},
+    courier::v1::{
+        cache::CargoStorageUsage,
+        cas::{CasBulkWriteKeyError, CasBulkWriteResponse, DICTIONARY_MAX_OBJECT_SIZE, Dictionary},
+    },
 };
-use color_eyre::{Report, eyre::Context};
-use futures::StreamExt;
+use color_eyre::{
+    Report, Result,
+    eyre::{Context, bail},
+};
+use futures::{AsyncRead, AsyncReadExt, StreamExt};
 use tap::Pipe;
 use tokio_util::{
     compat::{FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt},
     io::StreamReader,
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::{
+    api::v1::cas::grant_access,
     auth::AuthenticatedToken,
     db::Postgres,
+    dictionary,
+    maintenance::{self, ReadOnly},
+    quota::{self, StorageQuota},
     storage::{Disk, Key},
 };
 
@@ -32,6 +43,8 @@ pub enum BulkWriteResponse {
     Success(CasBulkWriteResponse),
     PartialSuccess(CasBulkWriteResponse),
     InvalidRequest(Report),
+    ReadOnly(String),
+    QuotaExceeded(CargoStorageUsage),
     Error(Report),
 }
 
@@ -51,6 +64,11 @@ pub enum BulkWriteResponse {
 /// Note: The tar archive itself is always uncompressed. The Content-Type only
 /// indicates whether the individual blobs inside the tar are compressed.
 ///
+/// Compressed blobs no larger than [`DICTIONARY_MAX_OBJECT_SIZE`] may be
+/// compressed with one of the organization's dictionaries, named by the
+/// dictionary ID in the zstd frame header; such blobs are decompressed with
+/// that dictionary before being stored.
+///
 /// ## Response format
 ///
 /// ```json
@@ -84,11 +102,42 @@ pub async fn handle(
     auth: AuthenticatedToken,
     Dep(db): Dep<Postgres>,
     Dep(cas): Dep<Disk>,
+    Dep(read_only): Dep<ReadOnly>,
+    Dep(quota): Dep<StorageQuota>,
     headers: HeaderMap,
     body: Body,
 ) -> BulkWriteResponse {
     info!("cas.bulk.write.start");
 
+    match read_only.check(&db, &auth).await {
+        Ok(None) => {}
+        Ok(Some(reason)) => {
+            // Drain the body so that the client sees the response instead of
+            // a connection reset.
+            body.into_data_stream().for_each(|_| async {}).await;
+            warn!(?reason, "cas.bulk.write.read_only");
+            return BulkWriteResponse::ReadOnly(reason);
+        }
+        Err(err) => {
+            error!(error = ?err, "cas.bulk.write.read_only_error");
+            return BulkWriteResponse::Error(err);
+        }
+    }
+
+    match quota.check(&db, &auth).await {
+        Ok(None) => {}
+        Ok(Some(usage)) => {
+            // Drain the body, as above.
+            body.into_data_stream().for_each(|_| async {}).await;
+            warn!(?usage, "cas.bulk.write.quota_exceeded");
+            return BulkWriteResponse::QuotaExceeded(usage);
+        }
+        Err(err) => {
+            error!(error = ?err, "cas.bulk.write.quota_error");
+            return BulkWriteResponse::Error(err);
+        }
+    }
+
     // Check Content-Type to determine if entries are pre-compressed
     let entries_compressed = headers
         .get(ContentType::HEADER)
@@ -144,6 +193,7 @@ async fn process_archive(
     let mut written = BTreeSet::new();
     let mut skipped = BTreeSet::new();
     let mut errors = BTreeSet::new();
+    let mut dictionaries = HashMap::new();
     while let Some(entry) = entries.next().await {
         let entry = match entry.context("read archive entry") {
             Ok(entry) => entry,
@@ -172,7 +222,7 @@ async fn process_archive(
 
         // We still need to grant access, even if the CAS item exists.
         if let Ok(true) = cas.exists(&key).await {
-            match db.grant_cas_access(auth, &key).await {
+            match grant_access(&db, &cas, auth, &key).await {
                 Ok(granted) => {
                     if granted {
                         // Org didn't have access, to them this was "written"
@@ -198,13 +248,14 @@ async fn process_archive(
         }
 
         let result = if entries_compressed {
-            cas.write_compressed(&key, entry.compat()).await
+            let size = entry.header().size().ok();
+            write_compressed_entry(auth, &db, &cas, &mut dictionaries, &key, size, entry).await
         } else {
             cas.write(&key, entry.compat()).await
         };
 
         match result {
-            Ok(()) => match db.grant_cas_access(auth, &key).await {
+            Ok(()) => match grant_access(&db, &cas, auth, &key).await {
                 Ok(granted) => {
                     info!(%key, ?granted, "cas.bulk.write.success");
                     written.insert(key);
@@ -253,6 +304,47 @@ async fn process_archive(
     }
 }
 
+/// Write a compressed entry to the CAS.
+///
+/// Small entries are checked for a dictionary ID in their zstd frame header;
+/// if present, the entry is decompressed with the organization's dictionary
+/// (loaded once per request and cached in `dictionaries`) and stored through
+/// the uncompressed path, since [`Disk`] stores blobs without dictionaries.
+async fn write_compressed_entry(
+    auth: &AuthenticatedToken,
+    db: &Postgres,
+    cas: &Disk,
+    dictionaries: &mut HashMap<u32, Dictionary>,
+    key: &Key,
+    size: Option<u64>,
+    mut entry: impl AsyncRead + Unpin,
+) -> Result<()> {
+    if size.is_none_or(|size| size > DICTIONARY_MAX_OBJECT_SIZE) {
+        return cas.write_compressed(key, entry.compat()).await;
+    }
+
+    let mut compressed = Vec::new();
+    entry
+        .read_to_end(&mut compressed)
+        .await
+        .context("read entry")?;
+    let Some(id) = zstd::zstd_safe::get_dict_id_from_frame(&compressed) else {
+        return cas.write_compressed(key, Cursor::new(compressed)).await;
+    };
+
+    let dictionary = match dictionaries.entry(id.get()) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let Some(dictionary_key) = db.cas_dictionary_by_zstd_id(auth, id.get()).await? else {
+                bail!("entry was compressed with unknown dictionary {id}");
+            };
+            entry.insert(dictionary::load(cas, &dictionary_key).await?)
+        }
+    };
+    let content = dictionary.decompress(&compressed, DICTIONARY_MAX_OBJECT_SIZE as usize)?;
+    cas.write(key, Cursor::new(content)).await
+}
+
 impl IntoResponse for BulkWriteResponse {
     fn into_response(self) -> axum::response::Response {
         match self {
@@ -266,6 +358,8 @@ impl IntoResponse for BulkWriteResponse {
             BulkWriteResponse::InvalidRequest(error) => {
                 (StatusCode::BAD_REQUEST, format!("{error:?}")).into_response()
             }
+            BulkWriteResponse::ReadOnly(reason) => maintenance::rejected(reason),
+            BulkWriteResponse::QuotaExceeded(usage) => quota::rejected(usage),
         }
     }
 }
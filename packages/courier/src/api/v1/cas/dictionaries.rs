@@ -0,0 +1,53 @@
+use aerosol::axum::Dep;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cas::{CasDictionary, CasDictionaryListResponse};
+use color_eyre::eyre::Report;
+use tap::Pipe;
+use tracing::{error, info};
+
+use crate::{auth::AuthenticatedToken, db::Postgres};
+
+/// List the compression dictionaries trained for the organization.
+///
+/// Dictionary content is stored in the CAS, so clients download each
+/// dictionary with the usual CAS read endpoints using the listed key.
+#[tracing::instrument(skip(auth))]
+pub async fn handle(auth: AuthenticatedToken, Dep(db): Dep<Postgres>) -> DictionaryListResponse {
+    match db.list_cas_dictionaries(&auth).await {
+        Ok(dictionaries) => {
+            info!(count = dictionaries.len(), "cas.dictionaries.list.success");
+            let dictionaries = dictionaries.into_iter().map(|dictionary| {
+                CasDictionary::builder()
+                    .key(dictionary.key)
+                    .maybe_package_name(dictionary.package_name)
+                    .zstd_id(dictionary.zstd_id)
+                    .build()
+            });
+            CasDictionaryListResponse::builder()
+                .dictionaries(dictionaries)
+                .build()
+                .pipe(DictionaryListResponse::Success)
+        }
+        Err(err) => {
+            error!(error = ?err, "cas.dictionaries.list.error");
+            DictionaryListResponse::Error(err)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DictionaryListResponse {
+    Success(CasDictionaryListResponse),
+    Error(Report),
+}
+
+impl IntoResponse for DictionaryListResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            DictionaryListResponse::Success(body) => (StatusCode::OK, Json(body)).into_response(),
+            DictionaryListResponse::Error(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
+            }
+        }
+    }
+}
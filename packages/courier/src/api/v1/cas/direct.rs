@@ -0,0 +1,170 @@
+//! Direct transfers of CAS objects to and from object storage.
+//!
+//! When the CAS is stored in an object storage bucket (see
+//! [`crate::storage::ObjectStore`]), clients can transfer large objects
+//! directly with presigned URLs instead of through the API. The API then only
+//! handles metadata: which objects the organization can access, and
+//! completing uploads so that their content is checked before it's moved into
+//! the CAS.
+//!
+//! Without an object store, this responds with `404 Not Found`, and clients
+//! transfer everything through the API.
+
+use aerosol::axum::Dep;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::{
+    cache::CargoStorageUsage,
+    cas::{CasDirectRequest, CasDirectResponse, CasDirectUrl},
+};
+use color_eyre::{Report, Result};
+use tracing::{error, info, warn};
+
+use super::{grant_access, upload};
+use crate::{
+    auth::AuthenticatedToken,
+    db::Postgres,
+    maintenance::{self, ReadOnly},
+    quota::{self, StorageQuota},
+    storage::Disk,
+};
+
+/// Presign URLs for the requested reads and writes.
+///
+/// Reads are only presigned for objects the organization can access that are
+/// at least the object store's minimum size. Writes are only presigned for
+/// objects that don't exist yet; the organization is granted access to those
+/// that do, the same as when it writes an object that already exists through
+/// the API.
+#[tracing::instrument(skip(auth, req))]
+pub async fn handle(
+    auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Dep(cas): Dep<Disk>,
+    Dep(read_only): Dep<ReadOnly>,
+    Dep(quota): Dep<StorageQuota>,
+    Json(req): Json<CasDirectRequest>,
+) -> DirectResponse {
+    let Some(store) = cas.object_store() else {
+        info!("cas.direct.not_configured");
+        return DirectResponse::NotConfigured;
+    };
+
+    if !req.writes.is_empty() {
+        match read_only.check(&db, &auth).await {
+            Ok(None) => {}
+            Ok(Some(reason)) => {
+                warn!(?reason, "cas.direct.read_only");
+                return DirectResponse::ReadOnly(reason);
+            }
+            Err(err) => {
+                error!(error = ?err, "cas.direct.read_only_error");
+                return DirectResponse::Error(err);
+            }
+        }
+
+        match quota.check(&db, &auth).await {
+            Ok(None) => {}
+            Ok(Some(usage)) => {
+                warn!(?usage, "cas.direct.quota_exceeded");
+                return DirectResponse::QuotaExceeded(usage);
+            }
+            Err(err) => {
+                error!(error = ?err, "cas.direct.quota_error");
+                return DirectResponse::Error(err);
+            }
+        }
+    }
+
+    let reads = match presign_reads(&auth, &db, &cas, store.min_read_bytes(), &req).await {
+        Ok(reads) => reads,
+        Err(err) => {
+            error!(error = ?err, "cas.direct.reads.error");
+            return DirectResponse::Error(err);
+        }
+    };
+    let writes = match presign_writes(&auth, &db, &cas, &req).await {
+        Ok(writes) => writes,
+        Err(err) => {
+            error!(error = ?err, "cas.direct.writes.error");
+            return DirectResponse::Error(err);
+        }
+    };
+
+    info!(
+        reads = reads.len(),
+        writes = writes.len(),
+        requested_reads = req.reads.len(),
+        requested_writes = req.writes.len(),
+        "cas.direct"
+    );
+    DirectResponse::Success(
+        CasDirectResponse::builder()
+            .reads(reads)
+            .writes(writes)
+            .build(),
+    )
+}
+
+async fn presign_reads(
+    auth: &AuthenticatedToken,
+    db: &Postgres,
+    cas: &Disk,
+    min_read_bytes: u64,
+    req: &CasDirectRequest,
+) -> Result<Vec<CasDirectUrl>> {
+    let accessible = db.check_cas_access_bulk(auth, &req.reads).await?;
+    let mut reads = Vec::new();
+    for key in req.reads.iter().filter(|key| accessible.contains(*key)) {
+        match cas.size_compressed(key).await? {
+            Some(size) if size >= min_read_bytes => {}
+            _ => continue,
+        }
+        if let Some(url) = cas.presign_read(key)? {
+            reads.push(CasDirectUrl::builder().key(key).url(url).build());
+        }
+    }
+    Ok(reads)
+}
+
+async fn presign_writes(
+    auth: &AuthenticatedToken,
+    db: &Postgres,
+    cas: &Disk,
+    req: &CasDirectRequest,
+) -> Result<Vec<CasDirectUrl>> {
+    let namespace = upload::namespace(auth);
+    let mut writes = Vec::new();
+    for key in &req.writes {
+        if cas.exists(key).await? {
+            grant_access(db, cas, auth, key).await?;
+            continue;
+        }
+        if let Some(url) = cas.presign_upload(&namespace, key)? {
+            writes.push(CasDirectUrl::builder().key(key).url(url).build());
+        }
+    }
+    Ok(writes)
+}
+
+#[derive(Debug)]
+pub enum DirectResponse {
+    Success(CasDirectResponse),
+    NotConfigured,
+    ReadOnly(String),
+    QuotaExceeded(CargoStorageUsage),
+    Error(Report),
+}
+
+impl IntoResponse for DirectResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            DirectResponse::Success(body) => (StatusCode::OK, Json(body)).into_response(),
+            DirectResponse::NotConfigured => StatusCode::NOT_FOUND.into_response(),
+            DirectResponse::ReadOnly(reason) => maintenance::rejected(reason),
+            DirectResponse::QuotaExceeded(usage) => quota::rejected(usage),
+            DirectResponse::Error(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
+            }
+        }
+    }
+}
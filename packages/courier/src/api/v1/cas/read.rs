@@ -2,7 +2,7 @@ use aerosol::axum::Dep;
 use axum::{
     body::Body,
     extract::Path,
-    http::{HeaderMap, StatusCode},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
     response::IntoResponse,
 };
 use clients::{ContentType, NETWORK_BUFFER_SIZE};
@@ -30,6 +30,13 @@ use crate::{
 /// The response sets `Content-Type`:
 /// - `application/octet-stream+zstd`: The body is compressed with `zstd`.
 /// - `application/octet-stream`: The body is uncompressed.
+///
+/// ## Caching
+///
+/// Content never changes for a given key, so found content is served with a
+/// `Cache-Control` header that allows clients to cache it indefinitely. It's
+/// `private` because access to keys is checked per organization; shared
+/// caches must not serve it to other clients.
 #[tracing::instrument(skip(auth))]
 pub async fn handle(
     auth: AuthenticatedToken,
@@ -106,6 +113,9 @@ async fn handle_plain(cas: Disk, key: Key) -> Result<Body> {
         .map(Body::from_stream)
 }
 
+/// The `Cache-Control` header of found content.
+const CACHE_CONTROL: &str = "private, max-age=31536000, immutable";
+
 #[derive(Debug)]
 pub enum CasReadResponse {
     Found(Body, ContentType),
@@ -116,9 +126,18 @@ pub enum CasReadResponse {
 impl IntoResponse for CasReadResponse {
     fn into_response(self) -> axum::response::Response {
         match self {
-            CasReadResponse::Found(body, ct) => {
-                (StatusCode::OK, [(ContentType::HEADER, ct.value())], body).into_response()
-            }
+            CasReadResponse::Found(body, ct) => (
+                StatusCode::OK,
+                [
+                    (ContentType::HEADER, ct.value()),
+                    (
+                        header::CACHE_CONTROL,
+                        HeaderValue::from_static(CACHE_CONTROL),
+                    ),
+                ],
+                body,
+            )
+                .into_response(),
             CasReadResponse::NotFound => StatusCode::NOT_FOUND.into_response(),
             CasReadResponse::Error(error) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
@@ -0,0 +1,248 @@
+//! Resumable uploads of large CAS objects.
+//!
+//! A single `PUT` of a large object has to start over from the beginning if
+//! the connection drops partway through. Resumable uploads instead send the
+//! object in chunks:
+//! 1. `GET /uploads/{key}` reports how many bytes of the object have been
+//!    received so far (zero for a new upload).
+//! 2. `PATCH /uploads/{key}` appends a chunk, with the `upload-offset` header
+//!    set to the offset the chunk starts at. If the offset isn't where the
+//!    upload is, the chunk is rejected with `409 Conflict` and the actual
+//!    offset, so that the client can pick up from there.
+//! 3. `POST /uploads/{key}/complete` validates that the received content
+//!    hashes to the key and moves it into the CAS.
+//!
+//! Offsets count bytes of uncompressed content, so chunks can be compressed
+//! independently. Whatever part of a chunk arrives before its connection
+//! drops is kept, so a retry only resends the rest.
+//!
+//! Appending and completing uploads are rejected while the cache is
+//! read-only; see [`crate::maintenance`]. Appending is also rejected once the
+//! organization's storage quota is used up; see [`crate::quota`].
+//!
+//! Uploads are kept per organization, so that one organization can't append
+//! to another's upload. Uploads that stop receiving content are removed after
+//! a while (see [`STALE_UPLOAD_AGE`]).
+
+use std::time::Duration;
+
+use aerosol::axum::Dep;
+use async_compression::tokio::bufread::ZstdDecoder;
+use axum::{
+    Json,
+    body::Body,
+    extract::Path,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use clients::{
+    ContentType,
+    courier::v1::{
+        cache::CargoStorageUsage,
+        cas::{CasUploadStatus, UPLOAD_OFFSET_HEADER},
+    },
+};
+use color_eyre::eyre::Report;
+use futures::{StreamExt, TryStreamExt};
+use tap::Pipe;
+use tokio::io::BufReader;
+use tokio_util::{either::Either, io::StreamReader};
+use tracing::{error, info, warn};
+
+use super::grant_access;
+use crate::{
+    auth::AuthenticatedToken,
+    db::Postgres,
+    maintenance::{self, ReadOnly},
+    quota::{self, StorageQuota},
+    storage::{Disk, Key, UploadAppend},
+};
+
+/// How long an upload can go without receiving content before it's removed.
+pub const STALE_UPLOAD_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Report how much of the object has been uploaded.
+#[tracing::instrument(skip(auth))]
+pub async fn status(
+    auth: AuthenticatedToken,
+    Dep(cas): Dep<Disk>,
+    Path(key): Path<Key>,
+) -> CasUploadResponse {
+    match cas.upload_offset(&namespace(&auth), &key).await {
+        Ok(offset) => {
+            info!(offset, "cas.upload.status");
+            CasUploadResponse::Status(CasUploadStatus { offset })
+        }
+        Err(err) => {
+            error!(error = ?err, "cas.upload.status.error");
+            CasUploadResponse::Error(err)
+        }
+    }
+}
+
+/// Append a chunk to the upload.
+#[tracing::instrument(skip(auth, body))]
+pub async fn append(
+    auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Dep(cas): Dep<Disk>,
+    Dep(read_only): Dep<ReadOnly>,
+    Dep(quota): Dep<StorageQuota>,
+    Path(key): Path<Key>,
+    headers: HeaderMap,
+    body: Body,
+) -> CasUploadResponse {
+    match read_only.check(&db, &auth).await {
+        Ok(None) => {}
+        Ok(Some(reason)) => {
+            // Drain the body so that the client sees the response instead of
+            // a connection reset.
+            body.into_data_stream().for_each(|_| async {}).await;
+            warn!(?reason, "cas.upload.append.read_only");
+            return CasUploadResponse::ReadOnly(reason);
+        }
+        Err(err) => {
+            error!(error = ?err, "cas.upload.append.read_only_error");
+            return CasUploadResponse::Error(err);
+        }
+    }
+
+    match quota.check(&db, &auth).await {
+        Ok(None) => {}
+        Ok(Some(usage)) => {
+            // Drain the body, as above.
+            body.into_data_stream().for_each(|_| async {}).await;
+            warn!(?usage, "cas.upload.append.quota_exceeded");
+            return CasUploadResponse::QuotaExceeded(usage);
+        }
+        Err(err) => {
+            error!(error = ?err, "cas.upload.append.quota_error");
+            return CasUploadResponse::Error(err);
+        }
+    }
+
+    let Some(offset) = headers
+        .get(UPLOAD_OFFSET_HEADER)
+        .and_then(|offset| offset.to_str().ok())
+        .and_then(|offset| offset.parse::<u64>().ok())
+    else {
+        info!("cas.upload.append.missing_offset");
+        return CasUploadResponse::MissingOffset;
+    };
+
+    let is_compressed = headers
+        .get(ContentType::HEADER)
+        .is_some_and(|v| v == ContentType::BytesZstd);
+    let content = body
+        .into_data_stream()
+        .map_err(std::io::Error::other)
+        .pipe(StreamReader::new);
+    let content = if is_compressed {
+        Either::Left(ZstdDecoder::new(BufReader::new(content)))
+    } else {
+        Either::Right(content)
+    };
+
+    match cas
+        .append_upload(&namespace(&auth), &key, offset, content)
+        .await
+    {
+        Ok(UploadAppend::Appended(offset)) => {
+            info!(offset, "cas.upload.append");
+            CasUploadResponse::Status(CasUploadStatus { offset })
+        }
+        Ok(UploadAppend::Conflict(offset)) => {
+            info!(offset, "cas.upload.append.conflict");
+            CasUploadResponse::Conflict(CasUploadStatus { offset })
+        }
+        Err(err) => {
+            error!(error = ?err, "cas.upload.append.error");
+            CasUploadResponse::Error(err)
+        }
+    }
+}
+
+/// Move the uploaded content into the CAS.
+#[tracing::instrument(skip(auth))]
+pub async fn complete(
+    auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Dep(cas): Dep<Disk>,
+    Dep(read_only): Dep<ReadOnly>,
+    Path(key): Path<Key>,
+) -> CasUploadResponse {
+    match read_only.check(&db, &auth).await {
+        Ok(None) => {}
+        Ok(Some(reason)) => {
+            warn!(?reason, "cas.upload.complete.read_only");
+            return CasUploadResponse::ReadOnly(reason);
+        }
+        Err(err) => {
+            error!(error = ?err, "cas.upload.complete.read_only_error");
+            return CasUploadResponse::Error(err);
+        }
+    }
+
+    match cas.complete_upload(&namespace(&auth), &key).await {
+        Ok(true) => {}
+        Ok(false) => {
+            info!("cas.upload.complete.not_found");
+            return CasUploadResponse::NotFound;
+        }
+        Err(err) => {
+            error!(error = ?err, "cas.upload.complete.error");
+            return CasUploadResponse::Error(err);
+        }
+    }
+
+    match grant_access(&db, &cas, &auth, &key).await {
+        Ok(granted) => {
+            info!(?granted, "cas.upload.complete");
+            CasUploadResponse::Created
+        }
+        Err(err) => {
+            error!(error = ?err, "cas.upload.complete.grant_access_error");
+            CasUploadResponse::Error(err)
+        }
+    }
+}
+
+/// The namespace that keeps the organization's uploads apart from others'.
+pub(super) fn namespace(auth: &AuthenticatedToken) -> String {
+    auth.org_id.as_i64().to_string()
+}
+
+#[derive(Debug)]
+pub enum CasUploadResponse {
+    Status(CasUploadStatus),
+    Conflict(CasUploadStatus),
+    Created,
+    MissingOffset,
+    NotFound,
+    ReadOnly(String),
+    QuotaExceeded(CargoStorageUsage),
+    Error(Report),
+}
+
+impl IntoResponse for CasUploadResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            CasUploadResponse::Status(status) => (StatusCode::OK, Json(status)).into_response(),
+            CasUploadResponse::Conflict(status) => {
+                (StatusCode::CONFLICT, Json(status)).into_response()
+            }
+            CasUploadResponse::Created => StatusCode::CREATED.into_response(),
+            CasUploadResponse::MissingOffset => (
+                StatusCode::BAD_REQUEST,
+                format!("missing or invalid `{UPLOAD_OFFSET_HEADER}` header"),
+            )
+                .into_response(),
+            CasUploadResponse::NotFound => StatusCode::NOT_FOUND.into_response(),
+            CasUploadResponse::ReadOnly(reason) => maintenance::rejected(reason),
+            CasUploadResponse::QuotaExceeded(usage) => quota::rejected(usage),
+            CasUploadResponse::Error(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
+            }
+        }
+    }
+}
@@ -5,16 +5,19 @@ use axum::{
     http::{HeaderMap, StatusCode},
     response::IntoResponse,
 };
-use clients::ContentType;
+use clients::{ContentType, courier::v1::cache::CargoStorageUsage};
 use color_eyre::{Result, eyre::Report};
 use futures::{StreamExt, TryStreamExt};
 use tap::Pipe;
 use tokio_util::io::StreamReader;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
+use super::grant_access;
 use crate::{
     auth::AuthenticatedToken,
     db::Postgres,
+    maintenance::{self, ReadOnly},
+    quota::{self, StorageQuota},
     storage::{Disk, Key},
 };
 
@@ -63,10 +66,41 @@ pub async fn handle(
     auth: AuthenticatedToken,
     Dep(db): Dep<Postgres>,
     Dep(cas): Dep<Disk>,
+    Dep(read_only): Dep<ReadOnly>,
+    Dep(quota): Dep<StorageQuota>,
     Path(key): Path<Key>,
     headers: HeaderMap,
     body: Body,
 ) -> CasWriteResponse {
+    match read_only.check(&db, &auth).await {
+        Ok(None) => {}
+        Ok(Some(reason)) => {
+            // Drain the body so that the client sees the response instead of
+            // a connection reset.
+            body.into_data_stream().for_each(|_| async {}).await;
+            warn!(?reason, "cas.write.read_only");
+            return CasWriteResponse::ReadOnly(reason);
+        }
+        Err(err) => {
+            error!(error = ?err, "cas.write.read_only_error");
+            return CasWriteResponse::Error(err);
+        }
+    }
+
+    match quota.check(&db, &auth).await {
+        Ok(None) => {}
+        Ok(Some(usage)) => {
+            // Drain the body, as above.
+            body.into_data_stream().for_each(|_| async {}).await;
+            warn!(?usage, "cas.write.quota_exceeded");
+            return CasWriteResponse::QuotaExceeded(usage);
+        }
+        Err(err) => {
+            error!(error = ?err, "cas.write.quota_error");
+            return CasWriteResponse::Error(err);
+        }
+    }
+
     // Check if the key already exists before consuming the body
     // If it exists, we still need to consume the entire body; if we return early
     // instead then clients see a "connection reset by peer" error.
@@ -85,7 +119,7 @@ pub async fn handle(
 
         // Grant access even though it already exists (idempotent, in case org didn't
         // have access)
-        match db.grant_cas_access(&auth, &key).await {
+        match grant_access(&db, &cas, &auth, &key).await {
             Ok(granted) => {
                 info!(?granted, "cas.write.exists");
                 return CasWriteResponse::Created;
@@ -103,15 +137,15 @@ pub async fn handle(
         .is_some_and(|v| v == ContentType::BytesZstd);
 
     let result = if is_compressed {
-        handle_compressed(cas, key.clone(), body).await
+        handle_compressed(cas.clone(), key.clone(), body).await
     } else {
-        handle_plain(cas, key.clone(), body).await
+        handle_plain(cas.clone(), key.clone(), body).await
     };
 
     match result {
         Ok(()) => {
             // Grant org access to the CAS key after successful write
-            match db.grant_cas_access(&auth, &key).await {
+            match grant_access(&db, &cas, &auth, &key).await {
                 Ok(granted) => {
                     info!(?granted, "cas.write.success");
                     CasWriteResponse::Created
@@ -152,6 +186,8 @@ async fn handle_plain(cas: Disk, key: Key, body: Body) -> Result<()> {
 #[derive(Debug)]
 pub enum CasWriteResponse {
     Created,
+    ReadOnly(String),
+    QuotaExceeded(CargoStorageUsage),
     Error(Report),
 }
 
@@ -159,6 +195,8 @@ impl IntoResponse for CasWriteResponse {
     fn into_response(self) -> axum::response::Response {
         match self {
             CasWriteResponse::Created => StatusCode::CREATED.into_response(),
+            CasWriteResponse::ReadOnly(reason) => maintenance::rejected(reason),
+            CasWriteResponse::QuotaExceeded(usage) => quota::rejected(usage),
             CasWriteResponse::Error(error) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
             }
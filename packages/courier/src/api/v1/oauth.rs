@@ -9,6 +9,7 @@ use time::Duration;
 use crate::api::State;
 
 pub mod callback;
+pub mod device;
 pub mod exchange;
 pub mod logout;
 pub mod start;
@@ -16,6 +17,7 @@ pub mod start;
 pub const SESSION_DURATION: Duration = Duration::hours(24);
 pub const OAUTH_STATE_DURATION: Duration = Duration::minutes(10);
 pub const EXCHANGE_CODE_DURATION: Duration = Duration::seconds(60);
+pub const DEVICE_CODE_DURATION: Duration = Duration::minutes(10);
 
 pub fn router() -> Router<State> {
     Router::new()
@@ -23,4 +25,7 @@ pub fn router() -> Router<State> {
         .route("/github/callback", get(callback::handle))
         .route("/exchange", post(exchange::handle))
         .route("/logout", post(logout::handle))
+        .route("/device", post(device::start::handle))
+        .route("/device/approve", post(device::approve::handle))
+        .route("/device/token", post(device::token::handle))
 }
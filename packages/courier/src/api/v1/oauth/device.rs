@@ -0,0 +1,18 @@
+//! Device authorization endpoints.
+//!
+//! These let the CLI get an API token without the user pasting one in:
+//! 1. The CLI starts an authorization with `POST /device`, receiving a
+//!    device code that it keeps secret and a user code that it shows.
+//! 2. The user opens the console's device page, checks that the user code
+//!    matches, picks an organization, and approves the authorization with
+//!    `POST /device/approve`.
+//! 3. The CLI polls `POST /device/token` with the device code until the
+//!    authorization is approved, at which point it receives an API key for
+//!    the organization.
+
+pub mod approve;
+pub mod start;
+pub mod token;
+
+/// Seconds the CLI should wait between polls.
+pub const POLL_INTERVAL_SECS: u64 = 5;
@@ -0,0 +1,120 @@
+//! Approve device authorization endpoint.
+
+use aerosol::axum::Dep;
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::{
+    auth::{OrgId, SessionContext},
+    db::Postgres,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ApproveRequest {
+    /// The user code shown by the device.
+    pub user_code: String,
+
+    /// The organization the device's API key belongs to.
+    pub org_id: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApproveResponseBody {
+    /// The name of the device that was approved.
+    pub client_name: String,
+}
+
+/// Approve a device authorization, issuing the device an API key for the
+/// organization the next time it polls.
+#[tracing::instrument(skip(db, session))]
+pub async fn handle(
+    Dep(db): Dep<Postgres>,
+    session: SessionContext,
+    Json(request): Json<ApproveRequest>,
+) -> ApproveResponse {
+    let org_id = OrgId::from_i64(request.org_id);
+
+    match db.get_member_role(org_id, session.account_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            warn!(
+                account_id = %session.account_id,
+                org_id = %org_id,
+                "oauth.device.approve.not_member"
+            );
+            return ApproveResponse::Forbidden;
+        }
+        Err(error) => {
+            error!(?error, "oauth.device.approve.role_check_error");
+            return ApproveResponse::Error(error.to_string());
+        }
+    }
+
+    // Users type the code in by hand if they didn't follow the link.
+    let user_code = request.user_code.trim().to_uppercase();
+    match db
+        .approve_device_authorization(&user_code, session.account_id, org_id)
+        .await
+    {
+        Ok(Some(client_name)) => {
+            let _ = db
+                .log_audit_event(
+                    Some(session.account_id),
+                    Some(org_id),
+                    "device.approved",
+                    Some(json!({ "client_name": client_name })),
+                )
+                .await;
+
+            info!(
+                account_id = %session.account_id,
+                org_id = %org_id,
+                "oauth.device.approve.success"
+            );
+            ApproveResponse::Approved(ApproveResponseBody { client_name })
+        }
+        Ok(None) => {
+            warn!("oauth.device.approve.not_found");
+            ApproveResponse::NotFound
+        }
+        Err(error) => {
+            error!(?error, "oauth.device.approve.error");
+            ApproveResponse::Error(error.to_string())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ApproveResponse {
+    Approved(ApproveResponseBody),
+    Forbidden,
+    NotFound,
+    Error(String),
+}
+
+impl IntoResponse for ApproveResponse {
+    fn into_response(self) -> Response {
+        match self {
+            ApproveResponse::Approved(body) => (StatusCode::OK, Json(body)).into_response(),
+            ApproveResponse::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "You must be a member of this organization to authorize devices for it",
+            )
+                .into_response(),
+            ApproveResponse::NotFound => (
+                StatusCode::NOT_FOUND,
+                "No pending authorization with this code. It may have expired; start again from your device.",
+            )
+                .into_response(),
+            ApproveResponse::Error(msg) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response()
+            }
+        }
+    }
+}
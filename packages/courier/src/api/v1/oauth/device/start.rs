@@ -0,0 +1,92 @@
+//! Start device authorization endpoint.
+
+use aerosol::axum::Dep;
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use clients::courier::v1::device::{DeviceAuthorizationRequest, DeviceAuthorizationResponse};
+use rand::Rng;
+use time::OffsetDateTime;
+use tracing::{error, info};
+
+use crate::db::Postgres;
+
+use super::{super::DEVICE_CODE_DURATION, POLL_INTERVAL_SECS};
+
+/// Characters user codes are made of. Vowels are left out so that codes
+/// don't spell words, and so are letters that are easily confused.
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+
+/// The longest device name that's accepted, in characters.
+const MAX_CLIENT_NAME_LEN: usize = 100;
+
+/// Start a device authorization.
+#[tracing::instrument(skip(db))]
+pub async fn handle(
+    Dep(db): Dep<Postgres>,
+    Json(request): Json<DeviceAuthorizationRequest>,
+) -> StartResponse {
+    let client_name = request.client_name.trim();
+    if client_name.is_empty() || client_name.chars().count() > MAX_CLIENT_NAME_LEN {
+        return StartResponse::InvalidName;
+    }
+
+    let user_code = generate_user_code();
+    let expires_at = OffsetDateTime::now_utc() + DEVICE_CODE_DURATION;
+    match db
+        .create_device_authorization(&user_code, client_name, expires_at)
+        .await
+    {
+        Ok(device_code) => {
+            info!(%user_code, "oauth.device.start");
+            StartResponse::Started(DeviceAuthorizationResponse {
+                device_code: device_code.expose().into(),
+                verification_path: format!("/device?code={user_code}"),
+                user_code,
+                expires_in: DEVICE_CODE_DURATION.whole_seconds().unsigned_abs(),
+                interval: POLL_INTERVAL_SECS,
+            })
+        }
+        Err(error) => {
+            error!(?error, "oauth.device.start.error");
+            StartResponse::Error(error.to_string())
+        }
+    }
+}
+
+/// Generate a user code, formatted like `BCDF-GHJK`.
+fn generate_user_code() -> String {
+    let mut rng = rand::thread_rng();
+    let mut code = String::with_capacity(9);
+    for i in 0..8 {
+        if i == 4 {
+            code.push('-');
+        }
+        let c = USER_CODE_ALPHABET[rng.gen_range(0..USER_CODE_ALPHABET.len())];
+        code.push(char::from(c));
+    }
+    code
+}
+
+#[derive(Debug)]
+pub enum StartResponse {
+    Started(DeviceAuthorizationResponse),
+    InvalidName,
+    Error(String),
+}
+
+impl IntoResponse for StartResponse {
+    fn into_response(self) -> Response {
+        match self {
+            StartResponse::Started(body) => (StatusCode::CREATED, Json(body)).into_response(),
+            StartResponse::InvalidName => (
+                StatusCode::BAD_REQUEST,
+                format!("Device name must be 1 to {MAX_CLIENT_NAME_LEN} characters"),
+            )
+                .into_response(),
+            StartResponse::Error(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response(),
+        }
+    }
+}
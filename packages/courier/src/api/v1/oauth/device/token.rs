@@ -0,0 +1,125 @@
+//! Poll device authorization endpoint.
+
+use aerosol::axum::Dep;
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use clients::courier::v1::device::{DeviceTokenRequest, DeviceTokenResponse};
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::{
+    auth::AuthCode,
+    db::{DeviceAuthorizationClaim, Postgres},
+};
+
+/// Poll a device authorization, issuing an API key once it's approved.
+#[tracing::instrument(skip(db, request))]
+pub async fn handle(
+    Dep(db): Dep<Postgres>,
+    Json(request): Json<DeviceTokenRequest>,
+) -> TokenResponse {
+    let device_code = AuthCode::new(request.device_code.expose());
+
+    let (account_id, org_id, client_name) = match db.claim_device_authorization(&device_code).await
+    {
+        Ok(DeviceAuthorizationClaim::Approved {
+            account_id,
+            org_id,
+            client_name,
+        }) => (account_id, org_id, client_name),
+        Ok(DeviceAuthorizationClaim::Pending) => return TokenResponse::Pending,
+        Ok(DeviceAuthorizationClaim::Expired) => {
+            warn!("oauth.device.token.expired");
+            return TokenResponse::Expired;
+        }
+        Ok(DeviceAuthorizationClaim::NotFound) => {
+            warn!("oauth.device.token.not_found");
+            return TokenResponse::NotFound;
+        }
+        Err(error) => {
+            error!(?error, "oauth.device.token.claim_error");
+            return TokenResponse::Error(error.to_string());
+        }
+    };
+
+    // Expired authorizations are only useful for telling a device that its
+    // authorization expired, which it only needs until it starts a new one.
+    let db_cleanup = db.clone();
+    tokio::spawn(async move {
+        if let Err(error) = db_cleanup.cleanup_expired_device_authorizations().await {
+            error!(?error, "oauth.cleanup.device_authorization_error");
+        }
+    });
+
+    match db
+        .create_api_key(account_id, &client_name, org_id, false)
+        .await
+    {
+        Ok((key_id, token)) => {
+            let _ = db
+                .log_audit_event(
+                    Some(account_id),
+                    Some(org_id),
+                    "api_key.created",
+                    Some(json!({
+                        "key_id": key_id.as_i64(),
+                        "name": client_name,
+                        "type": "organization",
+                        "via": "device_authorization",
+                    })),
+                )
+                .await;
+
+            info!(
+                account_id = %account_id,
+                org_id = %org_id,
+                key_id = %key_id,
+                "oauth.device.token.success"
+            );
+            TokenResponse::Approved(DeviceTokenResponse {
+                token: token.expose().into(),
+                org_id: org_id.as_i64(),
+            })
+        }
+        Err(error) => {
+            error!(?error, "oauth.device.token.create_key_error");
+            TokenResponse::Error(error.to_string())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TokenResponse {
+    Approved(DeviceTokenResponse),
+    Pending,
+    Expired,
+    NotFound,
+    Error(String),
+}
+
+impl IntoResponse for TokenResponse {
+    fn into_response(self) -> Response {
+        match self {
+            TokenResponse::Approved(body) => (StatusCode::OK, Json(body)).into_response(),
+            TokenResponse::Pending => (
+                StatusCode::ACCEPTED,
+                "Waiting for the authorization to be approved",
+            )
+                .into_response(),
+            TokenResponse::Expired => (
+                StatusCode::GONE,
+                "The authorization has expired. Please start again.",
+            )
+                .into_response(),
+            TokenResponse::NotFound => (
+                StatusCode::NOT_FOUND,
+                "Unknown or already used device code. Please start again.",
+            )
+                .into_response(),
+            TokenResponse::Error(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response(),
+        }
+    }
+}
@@ -14,6 +14,7 @@ pub mod create;
 pub mod leave;
 pub mod members;
 pub mod rename;
+pub mod usage;
 
 pub fn router() -> Router<State> {
     let sensitive = Router::new()
@@ -41,6 +42,7 @@ pub fn router() -> Router<State> {
         )
         .route("/{org_id}/bots", get(bots::list::handle))
         .route("/{org_id}/audit-log", get(audit_log::list::handle))
+        .route("/{org_id}/usage", get(usage::handle))
         .merge(invitations::organization_router())
         .merge(sensitive)
 }
@@ -16,6 +16,11 @@ use crate::{
 pub struct CreateOrgApiKeyRequest {
     /// The API key name.
     pub name: String,
+
+    /// Whether the key can only restore from the cache, not save to it, e.g.
+    /// for builds of pull requests from forks.
+    #[serde(default)]
+    pub read_only: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -29,6 +34,9 @@ pub struct CreateOrgApiKeyResponse {
     /// The API key token. Only returned once at creation.
     pub token: String,
 
+    /// Whether the key can only restore from the cache.
+    pub read_only: bool,
+
     /// The creation timestamp.
     #[serde(with = "time::serde::rfc3339")]
     pub created_at: OffsetDateTime,
@@ -65,7 +73,10 @@ pub async fn handle(
         return Response::EmptyName;
     }
 
-    match db.create_api_key(session.account_id, name, org_id).await {
+    match db
+        .create_api_key(session.account_id, name, org_id, request.read_only)
+        .await
+    {
         Ok((key_id, token)) => {
             let _ = db
                 .log_audit_event(
@@ -76,6 +87,7 @@ pub async fn handle(
                         "key_id": key_id.as_i64(),
                         "name": name,
                         "type": "organization",
+                        "read_only": request.read_only,
                     })),
                 )
                 .await;
@@ -92,6 +104,7 @@ pub async fn handle(
                     id: key.id.as_i64(),
                     name: key.name,
                     token: token.expose().to_string(),
+                    read_only: key.read_only,
                     created_at: key.created_at,
                 }),
                 Ok(None) => {
@@ -35,6 +35,9 @@ pub struct OrgApiKeyEntry {
     /// Whether the key owner is a bot (i.e., does not have a GitHub identity).
     pub bot: bool,
 
+    /// Whether the key can only restore from the cache, not save to it.
+    pub read_only: bool,
+
     /// The creation timestamp.
     #[serde(with = "time::serde::rfc3339")]
     pub created_at: OffsetDateTime,
@@ -83,6 +86,7 @@ pub async fn handle(
                     account_id: key.account_id.as_i64(),
                     account_email: key.account_email,
                     bot: !key.has_github_identity,
+                    read_only: key.read_only,
                     created_at: key.created_at,
                     accessed_at: key.accessed_at,
                 })
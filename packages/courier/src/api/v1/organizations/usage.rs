@@ -0,0 +1,73 @@
+//! Organization storage usage endpoint.
+
+use aerosol::axum::Dep;
+use axum::{Json, extract::Path, http::StatusCode, response::IntoResponse};
+use clients::courier::v1::cache::CargoStorageUsage;
+use tracing::{error, info, warn};
+
+use crate::{
+    auth::{OrgId, SessionContext},
+    db::Postgres,
+    quota::StorageQuota,
+};
+
+/// Get how much an organization stores in the cache, and its quota.
+///
+/// Any member can view this.
+#[tracing::instrument(skip(db, session))]
+pub async fn handle(
+    Dep(db): Dep<Postgres>,
+    Dep(quota): Dep<StorageQuota>,
+    session: SessionContext,
+    Path(org_id): Path<i64>,
+) -> Response {
+    let org_id = OrgId::from_i64(org_id);
+
+    match db.get_member_role(org_id, session.account_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            warn!(
+                account_id = %session.account_id,
+                org_id = %org_id,
+                "organizations.usage.not_member"
+            );
+            return Response::Forbidden;
+        }
+        Err(error) => {
+            error!(?error, "organizations.usage.role_check_error");
+            return Response::Error(error.to_string());
+        }
+    }
+
+    match quota.usage(&db, org_id).await {
+        Ok(usage) => {
+            info!(org_id = %org_id, ?usage, "organizations.usage.success");
+            Response::Success(usage)
+        }
+        Err(error) => {
+            error!(?error, "organizations.usage.error");
+            Response::Error(error.to_string())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Response {
+    Success(CargoStorageUsage),
+    Forbidden,
+    Error(String),
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Response::Success(usage) => (StatusCode::OK, Json(usage)).into_response(),
+            Response::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "You must be a member of this organization to view its usage",
+            )
+                .into_response(),
+            Response::Error(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response(),
+        }
+    }
+}
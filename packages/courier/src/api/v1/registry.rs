@@ -0,0 +1,23 @@
+//! Caching proxy for the crates.io registry.
+//!
+//! Cargo uses these endpoints through source replacement; `hurry init`
+//! generates the configuration that points Cargo at them. See
+//! [`crate::registry`] for how content is cached.
+
+use axum::{Router, routing::get};
+
+use crate::api::State;
+
+pub mod config;
+pub mod download;
+pub mod index;
+
+pub fn router() -> Router<State> {
+    Router::new()
+        .route("/crates-io/index/config.json", get(config::handle))
+        .route("/crates-io/index/{*path}", get(index::handle))
+        .route(
+            "/crates-io/crates/{name}/{version}/download",
+            get(download::handle),
+        )
+}
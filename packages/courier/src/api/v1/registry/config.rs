@@ -0,0 +1,73 @@
+use aerosol::axum::Dep;
+use axum::{
+    Json,
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+};
+use serde::Serialize;
+use tracing::info;
+
+use crate::registry::Registry;
+
+/// The sparse registry configuration.
+///
+/// Cargo reads this before anything else in the index to learn where to
+/// download crates from. It doesn't contain anything sensitive, so it's served
+/// without authentication; Cargo then authenticates the rest of its requests
+/// because the configuration says that authentication is required.
+#[tracing::instrument(skip(registry, headers))]
+pub async fn handle(Dep(registry): Dep<Registry>, headers: HeaderMap) -> RegistryConfigResponse {
+    let base = match registry.public_url() {
+        Some(url) => String::from(url.as_str().trim_end_matches('/')),
+        None => {
+            // Without a configured public URL, assume Courier is reachable at
+            // the address the client used; this is the case unless it's
+            // behind a proxy that rewrites the host.
+            let Some(host) = headers
+                .get(header::HOST)
+                .and_then(|host| host.to_str().ok())
+            else {
+                info!("registry.config.no_host");
+                return RegistryConfigResponse::NoHost;
+            };
+            let scheme = headers
+                .get("x-forwarded-proto")
+                .and_then(|proto| proto.to_str().ok())
+                .unwrap_or("http");
+            format!("{scheme}://{host}")
+        }
+    };
+
+    info!("registry.config.success");
+    RegistryConfigResponse::Success(RegistryConfig {
+        dl: format!("{base}/api/v1/registry/crates-io/crates/{{crate}}/{{version}}/download"),
+        auth_required: true,
+    })
+}
+
+/// The `config.json` file of a sparse registry.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RegistryConfig {
+    pub dl: String,
+    pub auth_required: bool,
+}
+
+#[derive(Debug)]
+pub enum RegistryConfigResponse {
+    Success(RegistryConfig),
+    NoHost,
+}
+
+impl IntoResponse for RegistryConfigResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            RegistryConfigResponse::Success(config) => {
+                (StatusCode::OK, Json(config)).into_response()
+            }
+            RegistryConfigResponse::NoHost => {
+                (StatusCode::BAD_REQUEST, "missing Host header").into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use aerosol::axum::Dep;
+use axum::{
+    body::Body,
+    extract::Path,
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use clients::NETWORK_BUFFER_SIZE;
+use color_eyre::eyre::Report;
+use tokio_util::io::ReaderStream;
+use tracing::{error, info};
+
+use crate::{
+    auth::AuthenticatedToken,
+    db::Postgres,
+    registry::{self, Registry},
+    storage::Disk,
+};
+
+/// Download the `.crate` file for a crate version.
+///
+/// The file is downloaded from upstream the first time it's requested and
+/// served from the CAS afterwards.
+#[tracing::instrument(skip(_auth, db, cas, registry))]
+pub async fn handle(
+    _auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Dep(cas): Dep<Disk>,
+    Dep(registry): Dep<Registry>,
+    Path((name, version)): Path<(String, String)>,
+) -> RegistryDownloadResponse {
+    if !registry::is_valid_crate_version(&name, &version) {
+        info!("registry.download.invalid_crate");
+        return RegistryDownloadResponse::NotFound;
+    }
+
+    let key = match registry.crate_file(&db, &cas, &name, &version).await {
+        Ok(Some(key)) => key,
+        Ok(None) => return RegistryDownloadResponse::NotFound,
+        Err(err) => {
+            error!(error = ?err, "registry.download.error");
+            return RegistryDownloadResponse::Error(err);
+        }
+    };
+
+    match cas.read(&key).await {
+        Ok(reader) => {
+            info!("registry.download.success");
+            let body = Body::from_stream(ReaderStream::with_capacity(reader, NETWORK_BUFFER_SIZE));
+            RegistryDownloadResponse::Found(body)
+        }
+        Err(err) => {
+            error!(error = ?err, "registry.download.read_error");
+            RegistryDownloadResponse::Error(err)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RegistryDownloadResponse {
+    Found(Body),
+    NotFound,
+    Error(Report),
+}
+
+impl IntoResponse for RegistryDownloadResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            RegistryDownloadResponse::Found(body) => (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "application/x-tar")],
+                body,
+            )
+                .into_response(),
+            RegistryDownloadResponse::NotFound => StatusCode::NOT_FOUND.into_response(),
+            RegistryDownloadResponse::Error(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use aerosol::axum::Dep;
+use axum::{
+    body::Body,
+    extract::Path,
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+};
+use clients::NETWORK_BUFFER_SIZE;
+use color_eyre::{Result, eyre::Report};
+use tokio_util::io::ReaderStream;
+use tracing::{error, info};
+
+use crate::{
+    auth::AuthenticatedToken,
+    db::Postgres,
+    registry::{self, Registry},
+    storage::{Disk, Key},
+};
+
+/// Read a file from the sparse index.
+///
+/// The file is fetched from upstream if it isn't cached or its cached copy is
+/// stale. The CAS key of the content is used as the `ETag`, so Cargo's
+/// conditional requests are answered without reading the content.
+#[tracing::instrument(skip(_auth, db, cas, registry, headers))]
+pub async fn handle(
+    _auth: AuthenticatedToken,
+    Dep(db): Dep<Postgres>,
+    Dep(cas): Dep<Disk>,
+    Dep(registry): Dep<Registry>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+) -> RegistryIndexResponse {
+    if !registry::is_valid_index_path(&path) {
+        info!("registry.index.invalid_path");
+        return RegistryIndexResponse::NotFound;
+    }
+
+    let key = match registry.index_file(&db, &cas, &path).await {
+        Ok(Some(key)) => key,
+        Ok(None) => return RegistryIndexResponse::NotFound,
+        Err(err) => {
+            error!(error = ?err, "registry.index.error");
+            return RegistryIndexResponse::Error(err);
+        }
+    };
+
+    let etag = format!("\"{key}\"");
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|value| value.as_bytes() == etag.as_bytes())
+    {
+        info!("registry.index.not_modified");
+        return RegistryIndexResponse::NotModified(etag);
+    }
+
+    match read(&cas, &key).await {
+        Ok(body) => {
+            info!("registry.index.success");
+            RegistryIndexResponse::Found(body, etag)
+        }
+        Err(err) => {
+            error!(error = ?err, "registry.index.read_error");
+            RegistryIndexResponse::Error(err)
+        }
+    }
+}
+
+async fn read(cas: &Disk, key: &Key) -> Result<Body> {
+    cas.read(key)
+        .await
+        .map(|s| ReaderStream::with_capacity(s, NETWORK_BUFFER_SIZE))
+        .map(Body::from_stream)
+}
+
+#[derive(Debug)]
+pub enum RegistryIndexResponse {
+    Found(Body, String),
+    NotModified(String),
+    NotFound,
+    Error(Report),
+}
+
+impl IntoResponse for RegistryIndexResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            RegistryIndexResponse::Found(body, etag) => {
+                (StatusCode::OK, [(header::ETAG, etag)], body).into_response()
+            }
+            RegistryIndexResponse::NotModified(etag) => {
+                (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response()
+            }
+            RegistryIndexResponse::NotFound => StatusCode::NOT_FOUND.into_response(),
+            RegistryIndexResponse::Error(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:?}")).into_response()
+            }
+        }
+    }
+}
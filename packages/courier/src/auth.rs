@@ -7,7 +7,7 @@ use derive_more::{Debug, Display};
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 
-use crate::{api, db};
+use crate::{access::AccessTokens, api, db};
 
 /// Organization role for membership.
 ///
@@ -256,6 +256,9 @@ impl AsRef<AuthCode> for AuthCode {
 /// This type can be extracted directly from a request using Axum's extractor
 /// system. It will automatically validate the bearer token from the
 /// Authorization header against the database before the handler is called.
+///
+/// The bearer token can also be an access token issued for an API key (see
+/// [`crate::access`]), which is verified by its signature instead.
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize, Serialize)]
 pub struct AuthenticatedToken {
     /// The account ID in the database.
@@ -264,6 +267,10 @@ pub struct AuthenticatedToken {
     /// The organization ID this API key is scoped to.
     pub org_id: OrgId,
 
+    /// Whether this API key can only restore from the cache, not save to it.
+    #[serde(default)]
+    pub read_only: bool,
+
     /// The plaintext value of the token for the user.
     pub plaintext: RawToken,
 }
@@ -315,6 +322,21 @@ impl FromRequestParts<api::State> for AuthenticatedToken {
             RawToken::new(header)
         };
 
+        // Access tokens are verified by their signature, without the database.
+        if AccessTokens::is_access_token(&token) {
+            let Dep(tokens) = Dep::<Option<AccessTokens>>::from_request_parts(parts, state)
+                .await
+                .map_err(|_| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Check out access token configuration",
+                    )
+                })?;
+            return tokens
+                .and_then(|tokens| tokens.verify(&token))
+                .ok_or((StatusCode::UNAUTHORIZED, "Invalid or expired access token"));
+        }
+
         let Dep(db) = Dep::<db::Postgres>::from_request_parts(parts, state)
             .await
             .map_err(|_| {
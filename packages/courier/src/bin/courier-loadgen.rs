@@ -0,0 +1,143 @@
+//! Generates load against a Courier instance to validate it before
+//! production bursts.
+//!
+//! See [`courier::loadgen`] for what the simulated clients do.
+
+use std::time::Duration;
+
+use clap::Parser;
+use color_eyre::{
+    Result,
+    eyre::{Context, bail},
+};
+use courier::loadgen::{LoadgenConfig, OperationMix, SizeDistribution};
+use derive_more::Debug;
+use tracing::level_filters::LevelFilter;
+use tracing_error::ErrorLayer;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use url::{Host, Url};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// URL of the Courier to generate load against
+    #[arg(long, env = "COURIER_LOADGEN_URL")]
+    #[debug("{:?}", url.as_str())]
+    url: Url,
+
+    /// API token for the Courier; generated units and objects belong to the
+    /// token's organization
+    #[arg(long, env = "COURIER_LOADGEN_TOKEN")]
+    #[debug(ignore)]
+    token: String,
+
+    /// Host name of the Courier, required to confirm that load should be
+    /// generated against a Courier that isn't running locally
+    #[arg(long)]
+    confirm_host: Option<String>,
+
+    /// Number of simulated clients
+    #[arg(long, default_value_t = 16)]
+    clients: usize,
+
+    /// Seconds to generate load for
+    #[arg(long, default_value_t = 60)]
+    duration: u64,
+
+    /// Milliseconds each client waits between operations
+    #[arg(long, default_value_t = 0)]
+    think_time: u64,
+
+    /// Relative frequency of saves
+    #[arg(long, default_value_t = 1)]
+    save_weight: u32,
+
+    /// Relative frequency of restores
+    #[arg(long, default_value_t = 4)]
+    restore_weight: u32,
+
+    /// Relative frequency of CAS reads and writes
+    #[arg(long, default_value_t = 2)]
+    cas_weight: u32,
+
+    /// Smallest generated object, in bytes
+    #[arg(long, default_value_t = 256)]
+    min_object_size: u64,
+
+    /// Largest generated object, in bytes
+    #[arg(long, default_value_t = 32 * 1024 * 1024)]
+    max_object_size: u64,
+
+    /// Most output files in a generated unit
+    #[arg(long, default_value_t = 4)]
+    max_files_per_unit: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    color_eyre::install()?;
+
+    tracing_subscriber::registry()
+        .with(ErrorLayer::default())
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_level(true)
+                .with_target(true),
+        )
+        .with(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
+
+    // Load against the wrong Courier is an outage, so anything that isn't
+    // local has to be named twice.
+    if !is_local(&cli.url) && cli.confirm_host.as_deref() != cli.url.host_str() {
+        bail!(
+            "{} isn't local; pass `--confirm-host {}` to generate load against it",
+            cli.url,
+            cli.url.host_str().unwrap_or_default()
+        );
+    }
+    if cli.min_object_size > cli.max_object_size {
+        bail!("--min-object-size must be at most --max-object-size");
+    }
+
+    let client =
+        clients::courier::v1::Client::new(cli.url, cli.token.into()).context("create client")?;
+    client.ping().await.context("ping courier")?;
+
+    let config = LoadgenConfig {
+        client,
+        clients: cli.clients,
+        duration: Duration::from_secs(cli.duration),
+        think_time: Duration::from_millis(cli.think_time),
+        mix: OperationMix {
+            save: cli.save_weight,
+            restore: cli.restore_weight,
+            cas: cli.cas_weight,
+        },
+        sizes: SizeDistribution {
+            min: cli.min_object_size,
+            max: cli.max_object_size,
+        },
+        max_files_per_unit: cli.max_files_per_unit,
+    };
+    let report = courier::loadgen::run(config)
+        .await
+        .context("generate load")?;
+    print!("{}", report.table());
+    Ok(())
+}
+
+/// Whether the URL points at this machine.
+fn is_local(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(domain)) => domain == "localhost",
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
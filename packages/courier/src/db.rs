@@ -12,11 +12,16 @@ mod api_key;
 pub mod audit;
 mod bot_account;
 mod cargo_cache;
+mod cas_dictionary;
+mod device_authorization;
+mod gc;
 mod github_identity;
 mod invitation;
 mod member;
 mod oauth;
 mod organization;
+mod quota;
+mod registry;
 mod session;
 
 use std::collections::HashMap;
@@ -33,11 +38,16 @@ use sqlx::{PgPool, migrate::Migrator};
 pub use account::{Account, SignupResult};
 pub use api_key::{ApiKey, OrgApiKey};
 pub use bot_account::BotAccount;
+pub use cas_dictionary::CasDictionary;
+pub use device_authorization::DeviceAuthorizationClaim;
+pub use gc::{CasKeyRow, GcDeletion};
 pub use github_identity::GitHubIdentity;
 pub use invitation::{AcceptInvitationResult, Invitation, InvitationPreview};
 pub use member::OrganizationMember;
 pub use oauth::{ExchangeCodeRedemption, OAuthState, RedeemExchangeCodeError};
 pub use organization::{Organization, OrganizationWithRole};
+pub use quota::OrganizationStorage;
+pub use registry::RegistryIndexFile;
 pub use session::UserSession;
 
 /// A connected Postgres database instance.
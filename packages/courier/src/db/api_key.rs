@@ -21,6 +21,7 @@ pub struct ApiKey {
     pub created_at: OffsetDateTime,
     pub accessed_at: OffsetDateTime,
     pub revoked_at: Option<OffsetDateTime>,
+    pub read_only: bool,
 }
 
 /// An API key with account email (for org listing).
@@ -33,10 +34,12 @@ pub struct OrgApiKey {
     pub created_at: OffsetDateTime,
     pub accessed_at: OffsetDateTime,
     pub has_github_identity: bool,
+    pub read_only: bool,
 }
 
 impl Postgres {
-    /// Lookup account and org for a raw token by direct hash comparison.
+    /// Lookup account and org for a raw token by direct hash comparison,
+    /// along with whether the token is read-only.
     ///
     /// Returns `None` if the token is invalid, revoked, or the owning account
     /// is disabled.
@@ -44,13 +47,14 @@ impl Postgres {
     async fn token_lookup(
         &self,
         token: impl AsRef<RawToken>,
-    ) -> Result<Option<(AccountId, OrgId)>> {
+    ) -> Result<Option<(AccountId, OrgId, bool)>> {
         let hash = TokenHash::new(token.as_ref().expose());
         let row = sqlx::query!(
             r#"
             SELECT
                 api_key.account_id,
-                api_key.organization_id
+                api_key.organization_id,
+                api_key.read_only
             FROM api_key
             JOIN account ON api_key.account_id = account.id
             WHERE api_key.hash = $1
@@ -67,6 +71,7 @@ impl Postgres {
             (
                 AccountId::from_i64(r.account_id),
                 OrgId::from_i64(r.organization_id),
+                r.read_only,
             )
         }))
     }
@@ -82,9 +87,10 @@ impl Postgres {
         Ok(self
             .token_lookup(&token)
             .await?
-            .map(|(account_id, org_id)| AuthenticatedToken {
+            .map(|(account_id, org_id, read_only)| AuthenticatedToken {
                 account_id,
                 org_id,
+                read_only,
                 plaintext: token,
             }))
     }
@@ -155,27 +161,30 @@ impl Postgres {
 
     /// Create a new API key scoped to an organization.
     ///
-    /// This is the only time the token is available in plaintext.
+    /// Read-only keys can restore from the organization's cache but not save
+    /// to it. This is the only time the token is available in plaintext.
     #[tracing::instrument(name = "Postgres::create_api_key")]
     pub async fn create_api_key(
         &self,
         account_id: AccountId,
         name: &str,
         organization_id: OrgId,
+        read_only: bool,
     ) -> Result<(ApiKeyId, RawToken)> {
         let token = RawToken::generate();
         let hash = TokenHash::new(token.expose());
 
         let row = sqlx::query!(
             r#"
-            INSERT INTO api_key (account_id, name, hash, organization_id)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO api_key (account_id, name, hash, organization_id, read_only)
+            VALUES ($1, $2, $3, $4, $5)
             RETURNING id
             "#,
             account_id.as_i64(),
             name,
             hash.as_bytes(),
             organization_id.as_i64(),
+            read_only,
         )
         .fetch_one(&self.pool)
         .await
@@ -193,7 +202,7 @@ impl Postgres {
     ) -> Result<Vec<ApiKey>> {
         let rows = sqlx::query!(
             r#"
-            SELECT id, account_id, organization_id, name, created_at, accessed_at, revoked_at
+            SELECT id, account_id, organization_id, name, created_at, accessed_at, revoked_at, read_only
             FROM api_key
             WHERE account_id = $1 AND organization_id = $2 AND revoked_at IS NULL
             ORDER BY created_at DESC
@@ -215,6 +224,7 @@ impl Postgres {
                 created_at: r.created_at,
                 accessed_at: r.accessed_at,
                 revoked_at: r.revoked_at,
+                read_only: r.read_only,
             })
             .collect())
     }
@@ -267,7 +277,7 @@ impl Postgres {
     pub async fn get_api_key(&self, key_id: ApiKeyId) -> Result<Option<ApiKey>> {
         let row = sqlx::query!(
             r#"
-            SELECT id, account_id, organization_id, name, created_at, accessed_at, revoked_at
+            SELECT id, account_id, organization_id, name, created_at, accessed_at, revoked_at, read_only
             FROM api_key
             WHERE id = $1
             "#,
@@ -285,6 +295,7 @@ impl Postgres {
             created_at: r.created_at,
             accessed_at: r.accessed_at,
             revoked_at: r.revoked_at,
+            read_only: r.read_only,
         }))
     }
 
@@ -301,6 +312,7 @@ impl Postgres {
                 api_key.name,
                 api_key.created_at,
                 api_key.accessed_at,
+                api_key.read_only,
                 account.email as account_email,
                 gi.id IS NOT NULL as "has_github_identity!"
             FROM api_key
@@ -325,6 +337,7 @@ impl Postgres {
                 created_at: r.created_at,
                 accessed_at: r.accessed_at,
                 has_github_identity: r.has_github_identity,
+                read_only: r.read_only,
             })
             .collect())
     }
@@ -3,16 +3,28 @@
 use std::collections::{HashMap, HashSet};
 
 use clients::courier::v1::{
-    GlibcVersion, Key, SavedUnit, SavedUnitHash,
-    cache::{CargoRestoreRequest, CargoSaveRequest},
+    CacheScope, GlibcVersion, Key, SavedUnit, SavedUnitHash, UnitHashVersion,
+    cache::{
+        CargoListResponse, CargoListedUnit, CargoRestoreRequest, CargoSaveRequest,
+        CargoSaveUnitRequest, CargoUnitOrigin, CargoUnitOriginsResponse, CargoWritePolicy,
+        SavedUnitMetadata,
+    },
 };
 use color_eyre::{Result, eyre::Context};
 use futures::StreamExt;
+use tap::Pipe as _;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
 use tracing::{debug, trace};
 
 use super::Postgres;
 use crate::auth::AuthenticatedToken;
 
+/// The maximum number of near-match candidates returned for each key.
+///
+/// Clients try candidates in order until one is compatible, so this bounds
+/// how much a client downloads for a unit it may end up building anyway.
+const NEAR_MATCH_CANDIDATE_LIMIT: i64 = 8;
+
 impl Postgres {
     #[tracing::instrument(name = "Postgres::save_cargo_cache", skip(auth))]
     pub async fn cargo_cache_save(
@@ -20,41 +32,187 @@ impl Postgres {
         auth: &AuthenticatedToken,
         request: CargoSaveRequest,
     ) -> Result<()> {
+        // Saving a unit counts as accessing the objects it references, so that
+        // garbage collection doesn't delete them as they become referenced.
+        let keys = request
+            .iter()
+            .flat_map(|item| item.unit.object_keys())
+            .cloned()
+            .collect::<Vec<_>>();
+        self.touch_cas_keys(&keys).await?;
+
         let mut tx = self.pool.begin().await?;
 
+        let metadata = request.metadata().clone();
+
+        // Units saved with a build ID are provisional until the build is
+        // finalized, so that an interrupted save doesn't leave units in the
+        // cache whose dependencies were never saved.
+        let save_group_id = match metadata.build_id.as_deref() {
+            Some(build_id) => sqlx::query!(
+                r#"INSERT INTO cargo_save_group (organization_id, build_id)
+                VALUES ($1, $2)
+                ON CONFLICT (organization_id, build_id) DO UPDATE SET build_id = EXCLUDED.build_id
+                RETURNING id"#,
+                auth.org_id.as_i64(),
+                build_id,
+            )
+            .fetch_one(tx.as_mut())
+            .await
+            .context("record save group")?
+            .id
+            .pipe(Some),
+            None => None,
+        };
+
         // TODO: bulk insert
         for item in request {
             let data = serde_json::to_value(&item.unit)
                 .with_context(|| format!("serialize data to json: {:?}", item.unit))?;
+
+            // A unit that's already saved is kept as it is, unless it's
+            // provisional: then it moves to this save's group (or out of any
+            // group), so that a unit left behind by an interrupted save
+            // becomes restorable once a later save of it completes.
             sqlx::query!(
-                r#"INSERT INTO cargo_saved_unit (organization_id, unit_hash, unit_resolved_target, linux_glibc_version, data)
-                VALUES ($1, $2, $3, $4, $5)
-                ON CONFLICT DO NOTHING"#,
+                r#"INSERT INTO cargo_saved_unit (organization_id, unit_hash, unit_hash_version, cache_generation, cache_scope, unit_resolved_target, linux_glibc_version, near_match_key, rustc_version, hurry_version, ci_provider, commit_sha, builder_hostname_hash, branch, save_group_id, data)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+                ON CONFLICT (organization_id, unit_hash) DO UPDATE SET save_group_id = EXCLUDED.save_group_id
+                WHERE cargo_saved_unit.save_group_id IN (SELECT id FROM cargo_save_group WHERE finalized_at IS NULL)"#,
                 auth.org_id.as_i64(),
-                item.unit.unit_hash().as_str(),
+                item.saved_unit_hash().as_str(),
+                item.unit_hash_version.as_i32(),
+                i64::try_from(item.cache_generation).context("cache generation out of range")?,
+                item.cache_scope.as_ref().map(|scope| scope.as_str()),
                 item.resolved_target,
                 item.linux_glibc_version.map(|v| v.to_string()),
+                item.near_match_key,
+                metadata.rustc_version.as_deref(),
+                metadata.hurry_version.as_deref(),
+                metadata.ci_provider.as_deref(),
+                metadata.commit_sha.as_deref(),
+                metadata.builder_hostname_hash.as_deref(),
+                metadata.branch.as_deref(),
+                save_group_id,
                 data,
             )
             .execute(tx.as_mut())
             .await
             .context("insert serialized cache data")?;
+
+            // Uploads are recorded even when the unit was already saved, since
+            // which builders produced a unit is exactly what's in question
+            // when two of them disagree about its content.
+            sqlx::query!(
+                r#"INSERT INTO cargo_unit_upload (organization_id, account_id, unit_hash, rustc_version, hurry_version, ci_provider, commit_sha, builder_hostname_hash, branch)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+                auth.org_id.as_i64(),
+                auth.account_id.as_i64(),
+                item.saved_unit_hash().as_str(),
+                metadata.rustc_version.as_deref(),
+                metadata.hurry_version.as_deref(),
+                metadata.ci_provider.as_deref(),
+                metadata.commit_sha.as_deref(),
+                metadata.builder_hostname_hash.as_deref(),
+                metadata.branch.as_deref(),
+            )
+            .execute(tx.as_mut())
+            .await
+            .context("record upload")?;
         }
 
         tx.commit().await.context("commit transaction")
     }
 
+    /// Finalize the units saved by a build, so that they can be restored.
+    ///
+    /// Finalizing is idempotent, and finalizing a build that hasn't saved any
+    /// units yet finalizes the units it saves later.
+    #[tracing::instrument(name = "Postgres::cargo_cache_finalize", skip(auth))]
+    pub async fn cargo_cache_finalize(
+        &self,
+        auth: &AuthenticatedToken,
+        build_id: &str,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO cargo_save_group (organization_id, build_id, finalized_at)
+            VALUES ($1, $2, NOW())
+            ON CONFLICT (organization_id, build_id) DO UPDATE SET finalized_at = COALESCE(cargo_save_group.finalized_at, EXCLUDED.finalized_at)"#,
+            auth.org_id.as_i64(),
+            build_id,
+        )
+        .execute(&self.pool)
+        .await
+        .context("finalize save group")?;
+        Ok(())
+    }
+
+    /// Load saved units by hash, regardless of the glibc version they were
+    /// saved against.
+    #[tracing::instrument(name = "Postgres::cargo_cache_load", skip(auth))]
+    pub async fn cargo_cache_load(
+        &self,
+        auth: &AuthenticatedToken,
+        hashes: &HashSet<SavedUnitHash>,
+    ) -> Result<HashMap<SavedUnitHash, SavedUnit>> {
+        let rows = sqlx::query!(
+            r#"SELECT unit_hash, data
+            FROM cargo_saved_unit
+            WHERE organization_id = $1
+            AND unit_hash = ANY($2)"#,
+            auth.org_id.as_i64(),
+            &hashes.iter().map(|h| h.to_string()).collect::<Vec<_>>(),
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("load saved units")?;
+
+        let mut units = HashMap::with_capacity(rows.len());
+        for row in rows {
+            let unit = serde_json::from_value::<SavedUnit>(row.data)
+                .with_context(|| format!("deserialize value for cache key: {}", row.unit_hash))?;
+            units.insert(row.unit_hash.into(), unit);
+        }
+        Ok(units)
+    }
+
+    /// Remove saved units, so that they can be saved again. Returns the
+    /// number of units removed.
+    #[tracing::instrument(name = "Postgres::cargo_cache_remove", skip(auth))]
+    pub async fn cargo_cache_remove(
+        &self,
+        auth: &AuthenticatedToken,
+        hashes: &HashSet<SavedUnitHash>,
+    ) -> Result<u64> {
+        let result = sqlx::query!(
+            "delete from cargo_saved_unit where organization_id = $1 and unit_hash = any($2)",
+            auth.org_id.as_i64(),
+            &hashes.iter().map(|h| h.to_string()).collect::<Vec<_>>(),
+        )
+        .execute(&self.pool)
+        .await
+        .context("delete saved units")?;
+        Ok(result.rows_affected())
+    }
+
+    /// Load the saved units in the request.
+    ///
+    /// Units from a save that hasn't been finalized aren't loaded. If `as_of`
+    /// is set, only units saved at or before then are loaded.
     #[tracing::instrument(name = "Postgres::cargo_cache_restore", skip(auth))]
     pub async fn cargo_cache_restore(
         &self,
         auth: &AuthenticatedToken,
         request: CargoRestoreRequest,
+        as_of: Option<OffsetDateTime>,
     ) -> Result<HashMap<SavedUnitHash, SavedUnit>> {
         let mut rows = sqlx::query!(
             r#"SELECT unit_hash, linux_glibc_version, data
             FROM cargo_saved_unit
             WHERE organization_id = $1
-            AND unit_hash = ANY($2)"#,
+            AND unit_hash = ANY($2)
+            AND ($3::timestamptz IS NULL OR created_at <= $3)
+            AND NOT EXISTS (SELECT 1 FROM cargo_save_group WHERE id = cargo_saved_unit.save_group_id AND finalized_at IS NULL)"#,
             auth.org_id.as_i64(),
             &request
                 .units
@@ -62,6 +220,7 @@ impl Postgres {
                 .cloned()
                 .map(|h| h.to_string())
                 .collect::<Vec<_>>(),
+            as_of,
         )
         .fetch(&self.pool);
 
@@ -74,45 +233,11 @@ impl Postgres {
             let unit = serde_json::from_value::<SavedUnit>(row.data)
                 .with_context(|| format!("deserialize value for cache key: {key}"))?;
 
-            // Check for glibc version compatibility for units that compile
-            // against glibc.
-            trace!(
-                %unit_hash,
-                host_glibc = ?request.host_glibc_version,
-                saved_glibc = ?row.linux_glibc_version,
-                "checking glibc compatibility"
-            );
-            if let Some(ref host_glibc) = request.host_glibc_version {
-                let Some(saved_glibc_string) = row.linux_glibc_version else {
-                    // Skip units without glibc version info. Note that this
-                    // should never happen, since all units with a matching unit
-                    // hash will all be on the same target, and all units of a
-                    // target either do or do not have glibc version info.
-                    debug!(
-                        %unit_hash,
-                        "skipping unit: no saved glibc version"
-                    );
-                    continue;
-                };
-                let saved_glibc = saved_glibc_string.as_str().parse::<GlibcVersion>()?;
-                if *host_glibc < saved_glibc {
-                    // Skip units with incompatible glibc versions.
-                    debug!(
-                        %unit_hash,
-                        %host_glibc,
-                        %saved_glibc,
-                        "skipping unit: host glibc too old"
-                    );
-                    continue;
-                }
-            } else if let Some(_) = row.linux_glibc_version {
-                // Skip units that have glibc version info when host doesn't
-                // have glibc version info (i.e., non-linux targets). This is
-                // another thing that should never happen.
-                debug!(
-                    %unit_hash,
-                    "skipping unit: host has no glibc but saved unit does"
-                );
+            if !glibc_compatible(
+                unit_hash,
+                request.host_glibc_version.as_ref(),
+                row.linux_glibc_version.as_deref(),
+            )? {
                 continue;
             }
 
@@ -122,31 +247,208 @@ impl Postgres {
         Ok(artifacts)
     }
 
+    /// Load near-match candidates for the keys in the request.
+    ///
+    /// At most [`NEAR_MATCH_CANDIDATE_LIMIT`] candidates are returned per key,
+    /// most recently saved first. Candidates are filtered for glibc
+    /// compatibility the same way exact matches are, but are otherwise not
+    /// validated: that's up to the client, which knows what it's willing to
+    /// accept. Units from a save that hasn't been finalized aren't candidates,
+    /// and neither are units saved in an earlier cache generation than the
+    /// organization's current one, since bumping the generation is meant to
+    /// stop them from being restored. If `as_of` is set, only units saved at
+    /// or before then are candidates.
+    #[tracing::instrument(name = "Postgres::cargo_cache_near_matches", skip(auth))]
+    pub async fn cargo_cache_near_matches(
+        &self,
+        auth: &AuthenticatedToken,
+        request: &CargoRestoreRequest,
+        as_of: Option<OffsetDateTime>,
+    ) -> Result<HashMap<String, Vec<SavedUnit>>> {
+        if request.near_match_keys.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut rows = sqlx::query!(
+            r#"SELECT unit_hash as "unit_hash!", near_match_key as "near_match_key!", linux_glibc_version as "linux_glibc_version?", data as "data!"
+            FROM (
+                SELECT unit_hash, near_match_key, linux_glibc_version, data,
+                    ROW_NUMBER() OVER (PARTITION BY near_match_key ORDER BY created_at DESC, id DESC) AS rank
+                FROM cargo_saved_unit
+                WHERE organization_id = $1
+                AND near_match_key = ANY($2)
+                AND ($4::timestamptz IS NULL OR created_at <= $4)
+                AND cache_scope IS NOT DISTINCT FROM $5
+                AND cache_generation = (SELECT cache_generation FROM organization WHERE id = $1)
+                AND NOT EXISTS (SELECT 1 FROM cargo_save_group WHERE id = cargo_saved_unit.save_group_id AND finalized_at IS NULL)
+            ) AS candidates
+            WHERE rank <= $3
+            ORDER BY near_match_key, rank"#,
+            auth.org_id.as_i64(),
+            &request
+                .near_match_keys
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>(),
+            NEAR_MATCH_CANDIDATE_LIMIT,
+            as_of,
+            request.cache_scope.as_ref().map(|scope| scope.as_str()),
+        )
+        .fetch(&self.pool);
+
+        let mut candidates = HashMap::<String, Vec<SavedUnit>>::new();
+        while let Some(row) = rows.next().await {
+            let row = row.context("read rows")?;
+
+            let unit_hash = &row.unit_hash;
+            let unit = serde_json::from_value::<SavedUnit>(row.data)
+                .with_context(|| format!("deserialize value for cache key: {unit_hash}"))?;
+            if !glibc_compatible(
+                unit_hash,
+                request.host_glibc_version.as_ref(),
+                row.linux_glibc_version.as_deref(),
+            )? {
+                continue;
+            }
+
+            candidates.entry(row.near_match_key).or_default().push(unit);
+        }
+
+        Ok(candidates)
+    }
+
+    /// Find the last time a unit built from each commit that starts with the
+    /// SHA prefix was uploaded.
+    ///
+    /// At most two commits are returned, which is enough to tell whether the
+    /// prefix is ambiguous. The caller is responsible for making sure that the
+    /// prefix only contains hex digits.
+    #[tracing::instrument(name = "Postgres::cargo_cache_commit_times", skip(auth))]
+    pub async fn cargo_cache_commit_times(
+        &self,
+        auth: &AuthenticatedToken,
+        commit_prefix: &str,
+    ) -> Result<Vec<(String, OffsetDateTime)>> {
+        let rows = sqlx::query!(
+            r#"SELECT commit_sha AS "commit_sha!", MAX(created_at) AS "uploaded_at!"
+            FROM cargo_unit_upload
+            WHERE organization_id = $1
+            AND commit_sha LIKE $2 || '%'
+            GROUP BY commit_sha
+            LIMIT 2"#,
+            auth.org_id.as_i64(),
+            commit_prefix.to_ascii_lowercase(),
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("find commit upload times")?;
+        rows.into_iter()
+            .map(|row| (row.commit_sha, row.uploaded_at))
+            .collect::<Vec<_>>()
+            .pipe(Ok)
+    }
+
+    /// List a page of the organization's saved units, in the order they were
+    /// saved.
+    ///
+    /// The cursor is the row ID of the last unit in the page, so units saved
+    /// after a page was listed are listed on a later page. There's no cursor
+    /// for an empty page; listing again from the same cursor later lists any
+    /// units saved in the meantime.
+    #[tracing::instrument(name = "Postgres::cargo_cache_list", skip(auth))]
+    pub async fn cargo_cache_list(
+        &self,
+        auth: &AuthenticatedToken,
+        after: Option<i64>,
+        limit: i64,
+    ) -> Result<CargoListResponse> {
+        let rows = sqlx::query!(
+            r#"SELECT id, unit_hash_version, cache_generation, cache_scope, unit_resolved_target, linux_glibc_version, near_match_key, rustc_version, hurry_version, ci_provider, commit_sha, builder_hostname_hash, branch, data
+            FROM cargo_saved_unit
+            WHERE organization_id = $1
+            AND id > $2
+            ORDER BY id
+            LIMIT $3"#,
+            auth.org_id.as_i64(),
+            after.unwrap_or_default(),
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("list saved units")?;
+
+        let next = rows.last().map(|row| row.id);
+        let units = rows
+            .into_iter()
+            .map(|row| {
+                let unit = serde_json::from_value::<SavedUnit>(row.data)
+                    .with_context(|| format!("deserialize saved unit: {}", row.id))?;
+                let linux_glibc_version = row
+                    .linux_glibc_version
+                    .map(|version| version.parse::<GlibcVersion>())
+                    .transpose()?;
+                let request = CargoSaveUnitRequest::builder()
+                    .unit(unit)
+                    .resolved_target(row.unit_resolved_target)
+                    .maybe_linux_glibc_version(linux_glibc_version)
+                    .unit_hash_version(UnitHashVersion::try_from(row.unit_hash_version)?)
+                    .maybe_near_match_key(row.near_match_key)
+                    .cache_generation(u64::try_from(row.cache_generation)?)
+                    .maybe_cache_scope(row.cache_scope.map(CacheScope::new).transpose()?)
+                    .build();
+                let metadata = SavedUnitMetadata::builder()
+                    .maybe_rustc_version(row.rustc_version)
+                    .maybe_hurry_version(row.hurry_version)
+                    .maybe_ci_provider(row.ci_provider)
+                    .maybe_commit_sha(row.commit_sha)
+                    .maybe_builder_hostname_hash(row.builder_hostname_hash)
+                    .maybe_branch(row.branch)
+                    .build();
+                Ok(CargoListedUnit::new(request, metadata))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(CargoListResponse::new(units, next))
+    }
+
     /// Grant an organization access to a CAS key.
     ///
     /// This is idempotent: if the organization already has access, this is a
     /// no-op. The operation atomically upserts the CAS key and grants access.
+    /// It also records that the key was accessed, so that garbage collection
+    /// doesn't delete an object that was just written again.
+    ///
+    /// `size` is the compressed size of the object, which is added to the
+    /// organization's storage usage if access is newly granted.
     ///
     /// Returns `true` if access was newly granted, `false` if the org already
     /// had access.
     #[tracing::instrument(name = "Postgres::grant_cas_access", skip(auth))]
-    pub async fn grant_cas_access(&self, auth: &AuthenticatedToken, key: &Key) -> Result<bool> {
+    pub async fn grant_cas_access(
+        &self,
+        auth: &AuthenticatedToken,
+        key: &Key,
+        size: u64,
+    ) -> Result<bool> {
         let mut tx = self.pool.begin().await?;
 
         // First, ensure the CAS key exists
-        let key_id = sqlx::query!(
+        let cas_key = sqlx::query!(
             r#"
-            INSERT INTO cas_key (content)
-            VALUES ($1)
-            ON CONFLICT (content) DO UPDATE SET content = EXCLUDED.content
-            RETURNING id
+            INSERT INTO cas_key (content, size_bytes)
+            VALUES ($1, $2)
+            ON CONFLICT (content) DO UPDATE SET
+              last_accessed_at = NOW(),
+              size_bytes = COALESCE(cas_key.size_bytes, EXCLUDED.size_bytes)
+            RETURNING id, size_bytes
             "#,
             key.as_bytes(),
+            i64::try_from(size).context("convert size")?,
         )
         .fetch_one(tx.as_mut())
         .await
-        .context("upsert cas key")?
-        .id;
+        .context("upsert cas key")?;
+        let key_id = cas_key.id;
 
         // Then grant access to the organization
         let result = sqlx::query!(
@@ -162,6 +464,21 @@ impl Postgres {
         .await
         .context("grant org access to cas key")?;
 
+        if result.rows_affected() == 1 {
+            sqlx::query!(
+                r#"
+                UPDATE organization
+                SET storage_bytes = storage_bytes + $2
+                WHERE id = $1
+                "#,
+                auth.org_id.as_i64(),
+                cas_key.size_bytes.unwrap_or_default(),
+            )
+            .execute(tx.as_mut())
+            .await
+            .context("update organization storage")?;
+        }
+
         tx.commit().await?;
 
         // If rows_affected is 1, we inserted a new row (newly granted access)
@@ -169,7 +486,8 @@ impl Postgres {
         Ok(result.rows_affected() == 1)
     }
 
-    /// Check if an organization has access to a CAS key.
+    /// Check if an organization has access to a CAS key, recording that the
+    /// key was accessed if it does.
     #[tracing::instrument(name = "Postgres::check_cas_access", skip(auth))]
     pub async fn check_cas_access(&self, auth: &AuthenticatedToken, key: &Key) -> Result<bool> {
         let result = sqlx::query!(
@@ -187,11 +505,15 @@ impl Postgres {
         .await
         .context("check cas access")?;
 
+        if result.exists {
+            self.touch_cas_keys(std::slice::from_ref(key)).await?;
+        }
         Ok(result.exists)
     }
 
     /// Check which keys from a set the organization has access to.
-    /// Returns a HashSet of keys that the organization can access.
+    /// Returns a HashSet of keys that the organization can access, and records
+    /// that they were accessed.
     #[tracing::instrument(name = "Postgres::check_cas_access_bulk", skip(auth, keys))]
     pub async fn check_cas_access_bulk(
         &self,
@@ -222,12 +544,186 @@ impl Postgres {
         .await
         .context("check cas access bulk")?;
 
-        rows.into_iter()
+        let accessible = rows
+            .into_iter()
             .map(|row| {
                 Key::from_bytes(&row.content)
                     .with_context(|| format!("parse key: {:x?}", &row.content))
             })
-            .collect()
+            .collect::<Result<HashSet<_>>>()?;
+        self.touch_cas_keys(&accessible.iter().cloned().collect::<Vec<_>>())
+            .await?;
+        Ok(accessible)
+    }
+
+    /// List the uploads of a saved unit, most recent first.
+    ///
+    /// Bots are accounts without a GitHub identity.
+    #[tracing::instrument(name = "Postgres::cargo_unit_origins", skip(auth))]
+    pub async fn cargo_unit_origins(
+        &self,
+        auth: &AuthenticatedToken,
+        unit_hash: &SavedUnitHash,
+        limit: i64,
+    ) -> Result<CargoUnitOriginsResponse> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                u.account_id,
+                a.name AS "account_name?",
+                a.email AS account_email,
+                gi.id IS NULL AS "bot!",
+                u.rustc_version,
+                u.hurry_version,
+                u.ci_provider,
+                u.commit_sha,
+                u.builder_hostname_hash,
+                u.branch,
+                u.created_at
+            FROM cargo_unit_upload u
+            JOIN account a ON a.id = u.account_id
+            LEFT JOIN github_identity gi ON gi.account_id = u.account_id
+            WHERE u.organization_id = $1
+              AND u.unit_hash = $2
+            ORDER BY u.created_at DESC, u.id DESC
+            LIMIT $3
+            "#,
+            auth.org_id.as_i64(),
+            unit_hash.as_str(),
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("list unit uploads")?;
+
+        rows.into_iter()
+            .map(|row| {
+                let uploaded_at = row
+                    .created_at
+                    .format(&Rfc3339)
+                    .context("format upload time")?;
+                let metadata = SavedUnitMetadata::builder()
+                    .maybe_rustc_version(row.rustc_version)
+                    .maybe_hurry_version(row.hurry_version)
+                    .maybe_ci_provider(row.ci_provider)
+                    .maybe_commit_sha(row.commit_sha)
+                    .maybe_builder_hostname_hash(row.builder_hostname_hash)
+                    .maybe_branch(row.branch)
+                    .build();
+                CargoUnitOrigin::builder()
+                    .account_id(row.account_id)
+                    .maybe_account_name(row.account_name)
+                    .account_email(row.account_email)
+                    .bot(row.bot)
+                    .uploaded_at(uploaded_at)
+                    .metadata(metadata)
+                    .build()
+                    .pipe(Ok)
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(CargoUnitOriginsResponse::new)
+    }
+
+    /// Get the organization's cache generation.
+    #[tracing::instrument(name = "Postgres::cargo_cache_generation", skip(auth))]
+    pub async fn cargo_cache_generation(&self, auth: &AuthenticatedToken) -> Result<u64> {
+        let row = sqlx::query!(
+            "SELECT cache_generation FROM organization WHERE id = $1",
+            auth.org_id.as_i64()
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("get cache generation")?;
+        u64::try_from(row.cache_generation).context("cache generation out of range")
+    }
+
+    /// Bump the organization's cache generation, returning the new
+    /// generation.
+    ///
+    /// Saved units aren't deleted: units saved in earlier generations just stop
+    /// being restored, since clients derive different hashes for them.
+    #[tracing::instrument(name = "Postgres::cargo_cache_bump_generation", skip(auth))]
+    pub async fn cargo_cache_bump_generation(&self, auth: &AuthenticatedToken) -> Result<u64> {
+        let row = sqlx::query!(
+            r#"UPDATE organization
+            SET cache_generation = cache_generation + 1
+            WHERE id = $1
+            RETURNING cache_generation"#,
+            auth.org_id.as_i64()
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("bump cache generation")?;
+        u64::try_from(row.cache_generation).context("cache generation out of range")
+    }
+
+    /// Get the organization's cache write policy.
+    #[tracing::instrument(name = "Postgres::cargo_cache_write_policy", skip(auth))]
+    pub async fn cargo_cache_write_policy(
+        &self,
+        auth: &AuthenticatedToken,
+    ) -> Result<CargoWritePolicy> {
+        let row = sqlx::query!(
+            "SELECT cache_write_branches FROM organization WHERE id = $1",
+            auth.org_id.as_i64()
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("get cache write policy")?;
+        Ok(match row.cache_write_branches {
+            Some(branches) => CargoWritePolicy::protected(branches),
+            None => CargoWritePolicy::unrestricted(),
+        })
+    }
+
+    /// Replace the organization's cache write policy.
+    #[tracing::instrument(name = "Postgres::cargo_cache_set_write_policy", skip(auth))]
+    pub async fn cargo_cache_set_write_policy(
+        &self,
+        auth: &AuthenticatedToken,
+        policy: &CargoWritePolicy,
+    ) -> Result<()> {
+        sqlx::query!(
+            "UPDATE organization SET cache_write_branches = $2 WHERE id = $1",
+            auth.org_id.as_i64(),
+            policy.branches.as_deref(),
+        )
+        .execute(&self.pool)
+        .await
+        .context("set cache write policy")?;
+        Ok(())
+    }
+
+    /// Get the reason the organization's cache is read-only, if it is.
+    #[tracing::instrument(name = "Postgres::cargo_cache_read_only", skip(auth))]
+    pub async fn cargo_cache_read_only(&self, auth: &AuthenticatedToken) -> Result<Option<String>> {
+        let row = sqlx::query!(
+            "SELECT cache_read_only_reason FROM organization WHERE id = $1",
+            auth.org_id.as_i64()
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("get cache read-only reason")?;
+        Ok(row.cache_read_only_reason)
+    }
+
+    /// Make the organization's cache read-only for the provided reason, or
+    /// accept saves again if there's no reason.
+    #[tracing::instrument(name = "Postgres::cargo_cache_set_read_only", skip(auth))]
+    pub async fn cargo_cache_set_read_only(
+        &self,
+        auth: &AuthenticatedToken,
+        reason: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query!(
+            "UPDATE organization SET cache_read_only_reason = $2 WHERE id = $1",
+            auth.org_id.as_i64(),
+            reason,
+        )
+        .execute(&self.pool)
+        .await
+        .context("set cache read-only reason")?;
+        Ok(())
     }
 
     #[tracing::instrument(name = "Postgres::cargo_cache_reset", skip(auth))]
@@ -242,6 +738,22 @@ impl Postgres {
         .await
         .context("delete saved units")?;
 
+        sqlx::query!(
+            "delete from cargo_save_group where organization_id = $1",
+            auth.org_id.as_i64()
+        )
+        .execute(tx.as_mut())
+        .await
+        .context("delete save groups")?;
+
+        sqlx::query!(
+            "delete from cas_dictionary where organization_id = $1",
+            auth.org_id.as_i64()
+        )
+        .execute(tx.as_mut())
+        .await
+        .context("delete cas dictionaries")?;
+
         sqlx::query!(
             "delete from cas_access where organization_id = $1",
             auth.org_id.as_i64()
@@ -250,7 +762,85 @@ impl Postgres {
         .await
         .context("delete cas access")?;
 
+        sqlx::query!(
+            "update organization set storage_bytes = 0 where id = $1",
+            auth.org_id.as_i64()
+        )
+        .execute(tx.as_mut())
+        .await
+        .context("reset organization storage")?;
+
         tx.commit().await?;
         Ok(())
     }
+
+    /// Delete the units saved in a single cache scope, returning how many
+    /// were deleted.
+    ///
+    /// The organization keeps access to the objects the units reference,
+    /// since units in other scopes may reference them too.
+    #[tracing::instrument(name = "Postgres::cargo_cache_reset_scope", skip(auth))]
+    pub async fn cargo_cache_reset_scope(
+        &self,
+        auth: &AuthenticatedToken,
+        scope: &CacheScope,
+    ) -> Result<u64> {
+        let result = sqlx::query!(
+            "delete from cargo_saved_unit where organization_id = $1 and cache_scope = $2",
+            auth.org_id.as_i64(),
+            scope.as_str(),
+        )
+        .execute(&self.pool)
+        .await
+        .context("delete saved units in scope")?;
+        Ok(result.rows_affected())
+    }
+}
+
+/// Check whether a unit saved against `saved_glibc` can be restored on a host
+/// with `host_glibc`.
+fn glibc_compatible(
+    unit_hash: &str,
+    host_glibc: Option<&GlibcVersion>,
+    saved_glibc: Option<&str>,
+) -> Result<bool> {
+    // Check for glibc version compatibility for units that compile against
+    // glibc.
+    trace!(
+        %unit_hash,
+        host_glibc = ?host_glibc,
+        saved_glibc = ?saved_glibc,
+        "checking glibc compatibility"
+    );
+    if let Some(host_glibc) = host_glibc {
+        let Some(saved_glibc) = saved_glibc else {
+            // Skip units without glibc version info. Note that this should
+            // never happen, since all units with a matching unit hash will all
+            // be on the same target, and all units of a target either do or do
+            // not have glibc version info.
+            debug!(%unit_hash, "skipping unit: no saved glibc version");
+            return Ok(false);
+        };
+        let saved_glibc = saved_glibc.parse::<GlibcVersion>()?;
+        if *host_glibc < saved_glibc {
+            // Skip units with incompatible glibc versions.
+            debug!(
+                %unit_hash,
+                %host_glibc,
+                %saved_glibc,
+                "skipping unit: host glibc too old"
+            );
+            return Ok(false);
+        }
+    } else if saved_glibc.is_some() {
+        // Skip units that have glibc version info when host doesn't have glibc
+        // version info (i.e., non-linux targets). This is another thing that
+        // should never happen.
+        debug!(
+            %unit_hash,
+            "skipping unit: host has no glibc but saved unit does"
+        );
+        return Ok(false);
+    }
+    Ok(true)
 }
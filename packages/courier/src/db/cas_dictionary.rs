@@ -0,0 +1,227 @@
+//! CAS compression dictionary database operations.
+
+use clients::courier::v1::{Key, SavedUnit};
+use color_eyre::{Result, eyre::Context};
+use time::OffsetDateTime;
+
+use super::Postgres;
+use crate::auth::{AuthenticatedToken, OrgId};
+
+/// A compression dictionary record from the database.
+#[derive(Clone, Debug)]
+pub struct CasDictionary {
+    pub key: Key,
+    pub package_name: Option<String>,
+    pub zstd_id: u32,
+    pub sample_count: u64,
+    pub created_at: OffsetDateTime,
+}
+
+impl Postgres {
+    /// List the compression dictionaries trained for the organization.
+    #[tracing::instrument(name = "Postgres::list_cas_dictionaries", skip(auth))]
+    pub async fn list_cas_dictionaries(
+        &self,
+        auth: &AuthenticatedToken,
+    ) -> Result<Vec<CasDictionary>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT cas_key.content, cas_dictionary.package_name, cas_dictionary.zstd_id,
+                cas_dictionary.sample_count, cas_dictionary.created_at
+            FROM cas_dictionary
+            JOIN cas_key ON cas_key.id = cas_dictionary.cas_key_id
+            WHERE cas_dictionary.organization_id = $1
+            ORDER BY cas_dictionary.package_name NULLS FIRST
+            "#,
+            auth.org_id.as_i64(),
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("list cas dictionaries")?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(CasDictionary {
+                    key: Key::from_bytes(&row.content)
+                        .with_context(|| format!("parse key: {:x?}", &row.content))?,
+                    package_name: row.package_name,
+                    zstd_id: u32::try_from(row.zstd_id).context("parse zstd dictionary id")?,
+                    sample_count: u64::try_from(row.sample_count).context("parse sample count")?,
+                    created_at: row.created_at,
+                })
+            })
+            .collect()
+    }
+
+    /// Find the organization's compression dictionary with the given zstd
+    /// dictionary ID.
+    ///
+    /// Zstd dictionary IDs are randomly generated during training, so in the
+    /// unlikely event that two of an organization's dictionaries share an ID
+    /// the most recently trained one is returned.
+    #[tracing::instrument(name = "Postgres::cas_dictionary_by_zstd_id", skip(auth))]
+    pub async fn cas_dictionary_by_zstd_id(
+        &self,
+        auth: &AuthenticatedToken,
+        zstd_id: u32,
+    ) -> Result<Option<Key>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT cas_key.content
+            FROM cas_dictionary
+            JOIN cas_key ON cas_key.id = cas_dictionary.cas_key_id
+            WHERE cas_dictionary.organization_id = $1
+            AND cas_dictionary.zstd_id = $2
+            ORDER BY cas_dictionary.created_at DESC
+            LIMIT 1
+            "#,
+            auth.org_id.as_i64(),
+            i64::from(zstd_id),
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("find cas dictionary")?;
+
+        row.map(|row| {
+            Key::from_bytes(&row.content).with_context(|| format!("parse key: {:x?}", &row.content))
+        })
+        .transpose()
+    }
+
+    /// Check whether the key is one of the organization's compression
+    /// dictionaries.
+    #[tracing::instrument(name = "Postgres::is_cas_dictionary", skip(auth))]
+    pub async fn is_cas_dictionary(&self, auth: &AuthenticatedToken, key: &Key) -> Result<bool> {
+        let result = sqlx::query!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM cas_dictionary
+                WHERE organization_id = $1
+                AND cas_key_id = (SELECT id FROM cas_key WHERE content = $2)
+            ) as "exists!"
+            "#,
+            auth.org_id.as_i64(),
+            key.as_bytes(),
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("check cas dictionary")?;
+
+        Ok(result.exists)
+    }
+
+    /// Record a newly trained compression dictionary for the organization,
+    /// replacing any existing dictionary for the same package.
+    ///
+    /// The dictionary content must already be written to the CAS; this also
+    /// grants the organization access to it so that clients can download it.
+    #[tracing::instrument(name = "Postgres::save_cas_dictionary")]
+    pub async fn save_cas_dictionary(
+        &self,
+        org_id: OrgId,
+        package_name: Option<&str>,
+        key: &Key,
+        zstd_id: u32,
+        sample_count: u64,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        let key_id = sqlx::query!(
+            r#"
+            INSERT INTO cas_key (content)
+            VALUES ($1)
+            ON CONFLICT (content) DO UPDATE SET content = EXCLUDED.content
+            RETURNING id
+            "#,
+            key.as_bytes(),
+        )
+        .fetch_one(tx.as_mut())
+        .await
+        .context("upsert cas key")?
+        .id;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO cas_access (organization_id, cas_key_id)
+            VALUES ($1, $2)
+            ON CONFLICT (organization_id, cas_key_id) DO NOTHING
+            "#,
+            org_id.as_i64(),
+            key_id,
+        )
+        .execute(tx.as_mut())
+        .await
+        .context("grant org access to dictionary")?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO cas_dictionary (organization_id, package_name, cas_key_id, zstd_id, sample_count)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (organization_id, package_name) DO UPDATE SET
+                cas_key_id = EXCLUDED.cas_key_id,
+                zstd_id = EXCLUDED.zstd_id,
+                sample_count = EXCLUDED.sample_count,
+                created_at = NOW()
+            "#,
+            org_id.as_i64(),
+            package_name,
+            key_id,
+            i64::from(zstd_id),
+            i64::try_from(sample_count).context("convert sample count")?,
+        )
+        .execute(tx.as_mut())
+        .await
+        .context("upsert cas dictionary")?;
+
+        tx.commit().await.context("commit transaction")
+    }
+
+    /// List the organizations that have saved units, and therefore objects
+    /// that dictionaries can be trained on.
+    #[tracing::instrument(name = "Postgres::cas_dictionary_organizations")]
+    pub async fn cas_dictionary_organizations(&self) -> Result<Vec<OrgId>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT DISTINCT organization_id
+            FROM cargo_saved_unit
+            ORDER BY organization_id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("list organizations with saved units")?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| OrgId::from_i64(row.organization_id))
+            .collect())
+    }
+
+    /// Read the organization's most recently saved units, which are used to
+    /// associate CAS objects with the package that produced them.
+    #[tracing::instrument(name = "Postgres::cas_dictionary_saved_units")]
+    pub async fn cas_dictionary_saved_units(
+        &self,
+        org_id: OrgId,
+        limit: u64,
+    ) -> Result<Vec<SavedUnit>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT data
+            FROM cargo_saved_unit
+            WHERE organization_id = $1
+            ORDER BY created_at DESC
+            LIMIT $2
+            "#,
+            org_id.as_i64(),
+            i64::try_from(limit).context("convert limit")?,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("read saved units")?;
+
+        rows.into_iter()
+            .map(|row| serde_json::from_value::<SavedUnit>(row.data).context("deserialize unit"))
+            .collect()
+    }
+}
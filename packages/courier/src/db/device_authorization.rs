@@ -0,0 +1,156 @@
+//! Device authorization database operations.
+
+use color_eyre::{Result, eyre::Context};
+use time::OffsetDateTime;
+
+use super::Postgres;
+use crate::auth::{AccountId, AuthCode, OrgId};
+use crate::crypto::TokenHash;
+
+/// The state of a device authorization, as seen by the device polling it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceAuthorizationClaim {
+    /// The user approved the authorization, which has now been consumed.
+    Approved {
+        account_id: AccountId,
+        org_id: OrgId,
+        client_name: String,
+    },
+
+    /// The user hasn't approved the authorization yet.
+    Pending,
+
+    /// The authorization expired before the user approved it.
+    Expired,
+
+    /// There's no such authorization, or it was already claimed.
+    NotFound,
+}
+
+impl Postgres {
+    /// Create a device authorization that the user approves with the user
+    /// code, returning the device code the device polls with.
+    ///
+    /// Only a SHA-256 hash of the device code is stored.
+    #[tracing::instrument(name = "Postgres::create_device_authorization")]
+    pub async fn create_device_authorization(
+        &self,
+        user_code: &str,
+        client_name: &str,
+        expires_at: OffsetDateTime,
+    ) -> Result<AuthCode> {
+        let code = AuthCode::generate();
+        let hash = TokenHash::new(code.expose());
+        sqlx::query!(
+            r#"
+            INSERT INTO device_authorization (device_code_hash, user_code, client_name, expires_at)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            hash.as_bytes(),
+            user_code,
+            client_name,
+            expires_at,
+        )
+        .execute(&self.pool)
+        .await
+        .context("create device authorization")?;
+
+        Ok(code)
+    }
+
+    /// Approve the pending device authorization with the user code on behalf
+    /// of the account, for the organization.
+    ///
+    /// Returns the name of the client that requested the authorization, or
+    /// `None` if there's no pending authorization with the user code.
+    #[tracing::instrument(name = "Postgres::approve_device_authorization")]
+    pub async fn approve_device_authorization(
+        &self,
+        user_code: &str,
+        account_id: AccountId,
+        org_id: OrgId,
+    ) -> Result<Option<String>> {
+        let row = sqlx::query!(
+            r#"
+            UPDATE device_authorization
+            SET account_id = $2, organization_id = $3, approved_at = NOW()
+            WHERE user_code = $1 AND approved_at IS NULL AND expires_at > NOW()
+            RETURNING client_name
+            "#,
+            user_code,
+            account_id.as_i64(),
+            org_id.as_i64(),
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("approve device authorization")?;
+
+        Ok(row.map(|r| r.client_name))
+    }
+
+    /// Claim the device authorization with the device code.
+    ///
+    /// Approved authorizations are deleted as they're claimed, so each one
+    /// can only be claimed once.
+    #[tracing::instrument(name = "Postgres::claim_device_authorization", skip(device_code))]
+    pub async fn claim_device_authorization(
+        &self,
+        device_code: &AuthCode,
+    ) -> Result<DeviceAuthorizationClaim> {
+        let hash = TokenHash::new(device_code.expose());
+        let approved = sqlx::query!(
+            r#"
+            DELETE FROM device_authorization
+            WHERE device_code_hash = $1 AND approved_at IS NOT NULL AND expires_at > NOW()
+            RETURNING account_id AS "account_id!", organization_id AS "organization_id!", client_name
+            "#,
+            hash.as_bytes(),
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("claim device authorization")?;
+        if let Some(row) = approved {
+            return Ok(DeviceAuthorizationClaim::Approved {
+                account_id: AccountId::from_i64(row.account_id),
+                org_id: OrgId::from_i64(row.organization_id),
+                client_name: row.client_name,
+            });
+        }
+
+        let row = sqlx::query!(
+            r#"
+            SELECT expires_at > NOW() AS "live!"
+            FROM device_authorization
+            WHERE device_code_hash = $1
+            "#,
+            hash.as_bytes(),
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("fetch device authorization")?;
+
+        Ok(match row {
+            Some(row) if row.live => DeviceAuthorizationClaim::Pending,
+            Some(_) => DeviceAuthorizationClaim::Expired,
+            None => DeviceAuthorizationClaim::NotFound,
+        })
+    }
+
+    /// Clean up expired device authorizations.
+    ///
+    /// Returns the number of authorizations deleted.
+    #[tracing::instrument(name = "Postgres::cleanup_expired_device_authorizations")]
+    pub async fn cleanup_expired_device_authorizations(&self) -> Result<u64> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM device_authorization
+            WHERE expires_at < NOW()
+            "#,
+        )
+        .execute(&self.pool)
+        .await
+        .context("cleanup expired device authorizations")?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,242 @@
+//! Garbage collection database operations.
+//!
+//! See [`crate::gc`] for how garbage collection works.
+
+use std::collections::HashSet;
+
+use clients::courier::v1::{Key, SavedUnit};
+use color_eyre::{Result, eyre::Context};
+use time::OffsetDateTime;
+
+use super::Postgres;
+
+/// A CAS key known to the database.
+#[derive(Clone, Debug)]
+pub struct CasKeyRow {
+    pub id: i64,
+    pub key: Key,
+}
+
+/// A CAS key deleted by garbage collection, whose deletion isn't committed
+/// yet.
+///
+/// The key's row stays locked until the deletion is committed, so nothing can
+/// touch or record the key in the meantime. Dropping it without committing
+/// rolls the deletion back.
+pub struct GcDeletion {
+    tx: sqlx::Transaction<'static, sqlx::Postgres>,
+}
+
+impl GcDeletion {
+    /// Commit the deletion.
+    pub async fn commit(self) -> Result<()> {
+        self.tx.commit().await.context("commit cas key deletion")
+    }
+}
+
+impl Postgres {
+    /// Record that the CAS keys were accessed.
+    ///
+    /// Keys are only updated if they weren't already accessed in the last
+    /// hour, so that reads of popular objects don't turn into a write each.
+    #[tracing::instrument(name = "Postgres::touch_cas_keys", skip(keys))]
+    pub async fn touch_cas_keys(&self, keys: &[Key]) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let key_bytes = keys
+            .iter()
+            .map(|k| k.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        sqlx::query!(
+            r#"
+            UPDATE cas_key
+            SET last_accessed_at = NOW()
+            WHERE content = ANY($1)
+            AND last_accessed_at < NOW() - INTERVAL '1 hour'
+            "#,
+            &key_bytes,
+        )
+        .execute(&self.pool)
+        .await
+        .context("touch cas keys")?;
+        Ok(())
+    }
+
+    /// Read a page of saved units with IDs greater than `after`, ordered by
+    /// ID, returning each unit with its ID.
+    #[tracing::instrument(name = "Postgres::gc_saved_units")]
+    pub async fn gc_saved_units(&self, after: i64, limit: u64) -> Result<Vec<(i64, SavedUnit)>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, data
+            FROM cargo_saved_unit
+            WHERE id > $1
+            ORDER BY id
+            LIMIT $2
+            "#,
+            after,
+            i64::try_from(limit).context("convert limit")?,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("read saved units")?;
+
+        rows.into_iter()
+            .map(|row| {
+                serde_json::from_value::<SavedUnit>(row.data)
+                    .with_context(|| format!("deserialize unit {}", row.id))
+                    .map(|unit| (row.id, unit))
+            })
+            .collect()
+    }
+
+    /// Read a page of CAS keys with IDs greater than `after` that haven't been
+    /// created or accessed since `cutoff`, ordered by ID.
+    ///
+    /// Keys of dictionaries and registry files are never returned, since
+    /// they're referenced outside of saved units.
+    #[tracing::instrument(name = "Postgres::gc_candidates")]
+    pub async fn gc_candidates(
+        &self,
+        cutoff: OffsetDateTime,
+        after: i64,
+        limit: u64,
+    ) -> Result<Vec<CasKeyRow>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, content
+            FROM cas_key
+            WHERE id > $1
+            AND created_at < $2
+            AND last_accessed_at < $2
+            AND NOT EXISTS (SELECT 1 FROM cas_dictionary WHERE cas_key_id = cas_key.id)
+            AND NOT EXISTS (SELECT 1 FROM registry_index_file WHERE cas_key_id = cas_key.id)
+            AND NOT EXISTS (SELECT 1 FROM registry_crate WHERE cas_key_id = cas_key.id)
+            ORDER BY id
+            LIMIT $3
+            "#,
+            after,
+            cutoff,
+            i64::try_from(limit).context("convert limit")?,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("read gc candidates")?;
+
+        rows.into_iter()
+            .map(|row| {
+                Key::from_bytes(&row.content)
+                    .with_context(|| format!("parse key: {:x?}", &row.content))
+                    .map(|key| CasKeyRow { id: row.id, key })
+            })
+            .collect()
+    }
+
+    /// Delete a CAS key and every organization's access to it, unless it was
+    /// accessed since `cutoff`. The object no longer counts towards those
+    /// organizations' storage usage.
+    ///
+    /// Returns the uncommitted deletion if the key was deleted, which the
+    /// caller commits once the object's file is removed. Checking the access
+    /// time again here means that an object a client starts using again while
+    /// garbage collection runs is kept.
+    #[tracing::instrument(name = "Postgres::gc_delete_cas_key")]
+    pub async fn gc_delete_cas_key(
+        &self,
+        id: i64,
+        cutoff: OffsetDateTime,
+    ) -> Result<Option<GcDeletion>> {
+        let mut tx = self.pool.begin().await?;
+
+        let locked = sqlx::query!(
+            r#"
+            SELECT id
+            FROM cas_key
+            WHERE id = $1
+            AND last_accessed_at < $2
+            FOR UPDATE
+            "#,
+            id,
+            cutoff,
+        )
+        .fetch_optional(tx.as_mut())
+        .await
+        .context("lock cas key")?;
+        if locked.is_none() {
+            return Ok(None);
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE organization
+            SET storage_bytes = GREATEST(
+              storage_bytes - COALESCE((SELECT size_bytes FROM cas_key WHERE id = $1), 0),
+              0
+            )
+            WHERE id IN (SELECT organization_id FROM cas_access WHERE cas_key_id = $1)
+            "#,
+            id,
+        )
+        .execute(tx.as_mut())
+        .await
+        .context("update organization storage")?;
+        sqlx::query!(
+            r#"
+            DELETE FROM cas_access
+            WHERE cas_key_id = $1
+            "#,
+            id,
+        )
+        .execute(tx.as_mut())
+        .await
+        .context("delete cas access")?;
+        sqlx::query!(
+            r#"
+            DELETE FROM cas_key
+            WHERE id = $1
+            "#,
+            id,
+        )
+        .execute(tx.as_mut())
+        .await
+        .context("delete cas key")?;
+
+        Ok(Some(GcDeletion { tx }))
+    }
+
+    /// Filter the keys down to those the database doesn't know about.
+    #[tracing::instrument(name = "Postgres::gc_unknown_keys", skip(keys))]
+    pub async fn gc_unknown_keys(&self, keys: &[Key]) -> Result<Vec<Key>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let key_bytes = keys
+            .iter()
+            .map(|k| k.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let rows = sqlx::query!(
+            r#"
+            SELECT content
+            FROM cas_key
+            WHERE content = ANY($1)
+            "#,
+            &key_bytes,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("read known cas keys")?;
+
+        let known = rows
+            .into_iter()
+            .map(|row| row.content)
+            .collect::<HashSet<_>>();
+        Ok(keys
+            .iter()
+            .filter(|key| !known.contains(key.as_bytes()))
+            .cloned()
+            .collect())
+    }
+}
@@ -0,0 +1,73 @@
+//! Storage quota database operations.
+//!
+//! See [`crate::quota`] for how quotas are enforced.
+
+use color_eyre::{Result, eyre::Context};
+
+use super::Postgres;
+use crate::auth::OrgId;
+
+/// How much an organization stores, and the most it may store.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OrganizationStorage {
+    /// Compressed bytes of the CAS objects the organization has access to.
+    pub bytes: u64,
+
+    /// The organization's own quota, if it has one instead of the instance's
+    /// default.
+    pub quota: Option<u64>,
+}
+
+impl Postgres {
+    /// Read how much the organization stores.
+    ///
+    /// Returns `None` if the organization doesn't exist.
+    #[tracing::instrument(name = "Postgres::organization_storage")]
+    pub async fn organization_storage(&self, org_id: OrgId) -> Result<Option<OrganizationStorage>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT storage_bytes, storage_quota_bytes
+            FROM organization
+            WHERE id = $1
+            "#,
+            org_id.as_i64(),
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("read organization storage")?;
+
+        Ok(row.map(|row| OrganizationStorage {
+            bytes: u64::try_from(row.storage_bytes).unwrap_or_default(),
+            quota: row
+                .storage_quota_bytes
+                .map(|quota| u64::try_from(quota).unwrap_or_default()),
+        }))
+    }
+
+    /// Set the organization's own quota, or make it use the instance's
+    /// default quota again.
+    #[tracing::instrument(name = "Postgres::set_organization_storage_quota")]
+    pub async fn set_organization_storage_quota(
+        &self,
+        org_id: OrgId,
+        quota: Option<u64>,
+    ) -> Result<()> {
+        let quota = quota
+            .map(i64::try_from)
+            .transpose()
+            .context("convert quota")?;
+        sqlx::query!(
+            r#"
+            UPDATE organization
+            SET storage_quota_bytes = $2
+            WHERE id = $1
+            "#,
+            org_id.as_i64(),
+            quota,
+        )
+        .execute(&self.pool)
+        .await
+        .context("set organization storage quota")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+//! Crate registry proxy cache database operations.
+
+use clients::courier::v1::Key;
+use color_eyre::{Result, eyre::Context};
+use sqlx::PgConnection;
+use time::OffsetDateTime;
+
+use super::Postgres;
+
+/// A cached sparse index file.
+#[derive(Clone, Debug)]
+pub struct RegistryIndexFile {
+    pub key: Key,
+    pub etag: Option<String>,
+    pub fetched_at: OffsetDateTime,
+}
+
+impl Postgres {
+    /// Find the cached index file at the path.
+    #[tracing::instrument(name = "Postgres::registry_index_file")]
+    pub async fn registry_index_file(&self, path: &str) -> Result<Option<RegistryIndexFile>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT cas_key.content, registry_index_file.etag, registry_index_file.fetched_at
+            FROM registry_index_file
+            JOIN cas_key ON cas_key.id = registry_index_file.cas_key_id
+            WHERE registry_index_file.path = $1
+            "#,
+            path,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("find registry index file")?;
+
+        row.map(|row| {
+            Ok(RegistryIndexFile {
+                key: Key::from_bytes(&row.content)
+                    .with_context(|| format!("parse key: {:x?}", &row.content))?,
+                etag: row.etag,
+                fetched_at: row.fetched_at,
+            })
+        })
+        .transpose()
+    }
+
+    /// Record the index file at the path, replacing any previous version.
+    ///
+    /// The content must already be written to the CAS.
+    #[tracing::instrument(name = "Postgres::save_registry_index_file")]
+    pub async fn save_registry_index_file(
+        &self,
+        path: &str,
+        key: &Key,
+        etag: Option<&str>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let key_id = upsert_cas_key(tx.as_mut(), key).await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO registry_index_file (path, cas_key_id, etag)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (path) DO UPDATE SET
+                cas_key_id = EXCLUDED.cas_key_id,
+                etag = EXCLUDED.etag,
+                fetched_at = NOW()
+            "#,
+            path,
+            key_id,
+            etag,
+        )
+        .execute(tx.as_mut())
+        .await
+        .context("upsert registry index file")?;
+        tx.commit().await.context("commit transaction")
+    }
+
+    /// Mark the index file at the path as freshly fetched, after upstream
+    /// confirmed that it hasn't changed.
+    #[tracing::instrument(name = "Postgres::touch_registry_index_file")]
+    pub async fn touch_registry_index_file(&self, path: &str) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE registry_index_file
+            SET fetched_at = NOW()
+            WHERE path = $1
+            "#,
+            path,
+        )
+        .execute(&self.pool)
+        .await
+        .context("touch registry index file")?;
+        Ok(())
+    }
+
+    /// Forget the index file at the path, after upstream reported that it no
+    /// longer exists.
+    #[tracing::instrument(name = "Postgres::delete_registry_index_file")]
+    pub async fn delete_registry_index_file(&self, path: &str) -> Result<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM registry_index_file
+            WHERE path = $1
+            "#,
+            path,
+        )
+        .execute(&self.pool)
+        .await
+        .context("delete registry index file")?;
+        Ok(())
+    }
+
+    /// Find the CAS key of the cached `.crate` file for the crate version.
+    #[tracing::instrument(name = "Postgres::registry_crate")]
+    pub async fn registry_crate(&self, name: &str, version: &str) -> Result<Option<Key>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT cas_key.content
+            FROM registry_crate
+            JOIN cas_key ON cas_key.id = registry_crate.cas_key_id
+            WHERE registry_crate.name = $1
+            AND registry_crate.version = $2
+            "#,
+            name,
+            version,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("find registry crate")?;
+
+        row.map(|row| {
+            Key::from_bytes(&row.content).with_context(|| format!("parse key: {:x?}", &row.content))
+        })
+        .transpose()
+    }
+
+    /// Record the `.crate` file for the crate version.
+    ///
+    /// The content must already be written to the CAS.
+    #[tracing::instrument(name = "Postgres::save_registry_crate")]
+    pub async fn save_registry_crate(&self, name: &str, version: &str, key: &Key) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let key_id = upsert_cas_key(tx.as_mut(), key).await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO registry_crate (name, version, cas_key_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (name, version) DO NOTHING
+            "#,
+            name,
+            version,
+            key_id,
+        )
+        .execute(tx.as_mut())
+        .await
+        .context("insert registry crate")?;
+        tx.commit().await.context("commit transaction")
+    }
+}
+
+/// Insert the CAS key if it doesn't exist, returning its ID.
+async fn upsert_cas_key(conn: &mut PgConnection, key: &Key) -> Result<i64> {
+    sqlx::query!(
+        r#"
+        INSERT INTO cas_key (content)
+        VALUES ($1)
+        ON CONFLICT (content) DO UPDATE SET content = EXCLUDED.content
+        RETURNING id
+        "#,
+        key.as_bytes(),
+    )
+    .fetch_one(conn)
+    .await
+    .context("upsert cas key")
+    .map(|row| row.id)
+}
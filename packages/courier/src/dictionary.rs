@@ -0,0 +1,220 @@
+//! Zstd compression dictionaries for small CAS objects.
+//!
+//! Small objects like fingerprints, dep-info files, and build script output
+//! compress poorly on their own because each one is too small for zstd to
+//! learn much from. Courier trains dictionaries offline from an organization's
+//! existing objects (see [`train`]); clients download them and use them to
+//! compress small objects in bulk transfers.
+//!
+//! Dictionaries are trained per organization, both per package and globally
+//! across all packages. The dictionary content is stored in the CAS itself, so
+//! dictionaries are identified by their CAS key.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Cursor;
+
+use clients::courier::v1::{
+    Key,
+    cas::{DICTIONARY_MAX_OBJECT_SIZE, Dictionary},
+};
+use color_eyre::{
+    Result,
+    eyre::{Context, bail},
+};
+use tokio::io::AsyncReadExt;
+use tracing::{info, warn};
+
+use crate::{auth::OrgId, db::Postgres, storage::Disk};
+
+/// The largest dictionary Courier will load.
+///
+/// Dictionaries are trained to [`TrainConfig::dictionary_size`], so anything
+/// larger than this was not produced by [`train`].
+const MAX_DICTIONARY_SIZE: u64 = 1024 * 1024;
+
+/// Configuration for training dictionaries.
+#[derive(Clone, Debug)]
+pub struct TrainConfig {
+    /// The largest object to use as a training sample.
+    pub max_object_size: u64,
+
+    /// The most samples to train each dictionary on.
+    pub max_samples: usize,
+
+    /// The fewest samples needed to train a dictionary; packages with fewer
+    /// small objects than this only use the global dictionary.
+    pub min_samples: usize,
+
+    /// The maximum size of each dictionary in bytes.
+    pub dictionary_size: usize,
+
+    /// The most recently saved units to read per organization when looking
+    /// for samples.
+    pub max_units: u64,
+}
+
+impl Default for TrainConfig {
+    fn default() -> Self {
+        Self {
+            max_object_size: DICTIONARY_MAX_OBJECT_SIZE,
+            max_samples: 4096,
+            min_samples: 64,
+            // This is the default used by the `zstd` CLI.
+            dictionary_size: 110 * 1024,
+            max_units: 10_000,
+        }
+    }
+}
+
+/// Summary of a training run.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct TrainSummary {
+    /// The number of organizations whose objects were sampled.
+    pub organizations: usize,
+
+    /// The number of dictionaries trained.
+    pub dictionaries: usize,
+}
+
+/// Load the dictionary with the provided key from the CAS.
+#[tracing::instrument(name = "dictionary::load")]
+pub async fn load(cas: &Disk, key: &Key) -> Result<Dictionary> {
+    let content = read_limited(cas, key, MAX_DICTIONARY_SIZE).await?;
+    let Some(content) = content else {
+        bail!("dictionary does not exist or is too large");
+    };
+    Dictionary::new(content).context("load dictionary")
+}
+
+/// Train dictionaries for every organization that has saved units.
+///
+/// Dictionaries are replaced each time they're trained, so this is intended to
+/// be run periodically as objects accumulate.
+#[tracing::instrument(name = "dictionary::train", skip(db, cas))]
+pub async fn train(db: &Postgres, cas: &Disk, config: &TrainConfig) -> Result<TrainSummary> {
+    let mut summary = TrainSummary::default();
+    for org_id in db.cas_dictionary_organizations().await? {
+        summary.organizations += 1;
+        summary.dictionaries += train_organization(db, cas, config, org_id)
+            .await
+            .with_context(|| format!("train dictionaries for organization {org_id}"))?;
+    }
+    Ok(summary)
+}
+
+/// Train the global and per-package dictionaries for an organization.
+///
+/// Returns the number of dictionaries trained.
+#[tracing::instrument(skip(db, cas))]
+async fn train_organization(
+    db: &Postgres,
+    cas: &Disk,
+    config: &TrainConfig,
+    org_id: OrgId,
+) -> Result<usize> {
+    let units = db
+        .cas_dictionary_saved_units(org_id, config.max_units)
+        .await?;
+
+    let mut packages = BTreeMap::<String, BTreeSet<Key>>::new();
+    for unit in &units {
+        packages
+            .entry(unit.info().package_name.clone())
+            .or_default()
+            .extend(unit.object_keys().into_iter().cloned());
+    }
+
+    let mut trained = 0;
+    let mut global = Vec::new();
+    for (package_name, keys) in packages {
+        let mut samples = Vec::new();
+        for key in keys {
+            if samples.len() >= config.max_samples {
+                break;
+            }
+            if let Some(sample) = read_limited(cas, &key, config.max_object_size).await? {
+                samples.push(sample);
+            }
+        }
+
+        if global.len() < config.max_samples {
+            let remaining = config.max_samples - global.len();
+            global.extend(samples.iter().take(remaining).cloned());
+        }
+        if samples.len() < config.min_samples {
+            continue;
+        }
+        if train_dictionary(db, cas, config, org_id, Some(&package_name), samples).await? {
+            trained += 1;
+        }
+    }
+
+    if global.len() >= config.min_samples
+        && train_dictionary(db, cas, config, org_id, None, global).await?
+    {
+        trained += 1;
+    }
+
+    Ok(trained)
+}
+
+/// Train a dictionary from the samples, store it in the CAS, and record it for
+/// the organization.
+///
+/// Returns whether a dictionary was recorded: training fails for sample sets
+/// that zstd can't find useful patterns in, which is logged and skipped rather
+/// than treated as an error.
+#[tracing::instrument(skip(db, cas, samples), fields(samples = samples.len()))]
+async fn train_dictionary(
+    db: &Postgres,
+    cas: &Disk,
+    config: &TrainConfig,
+    org_id: OrgId,
+    package_name: Option<&str>,
+    samples: Vec<Vec<u8>>,
+) -> Result<bool> {
+    let sample_count = samples.len() as u64;
+    let size = config.dictionary_size;
+    let content =
+        tokio::task::spawn_blocking(move || zstd::dict::from_samples(&samples, size)).await?;
+    let content = match content {
+        Ok(content) => content,
+        Err(error) => {
+            warn!(?error, "dictionary.train.skipped");
+            return Ok(false);
+        }
+    };
+
+    let dictionary = Dictionary::new(content.as_slice()).context("load trained dictionary")?;
+    cas.write(&dictionary.key, Cursor::new(content))
+        .await
+        .context("store dictionary")?;
+    db.save_cas_dictionary(
+        org_id,
+        package_name,
+        &dictionary.key,
+        dictionary.zstd_id,
+        sample_count,
+    )
+    .await?;
+
+    info!(key = %dictionary.key, zstd_id = dictionary.zstd_id, "dictionary.train.saved");
+    Ok(true)
+}
+
+/// Read the content for the key if it exists and is no larger than `limit`.
+async fn read_limited(cas: &Disk, key: &Key, limit: u64) -> Result<Option<Vec<u8>>> {
+    match cas.size(key).await? {
+        Some(size) if size <= limit => {}
+        _ => return Ok(None),
+    }
+
+    let mut content = Vec::new();
+    cas.read(key)
+        .await?
+        .take(limit)
+        .read_to_end(&mut content)
+        .await
+        .with_context(|| format!("read content for {key:?}"))?;
+    Ok(Some(content))
+}
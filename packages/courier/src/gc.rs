@@ -0,0 +1,280 @@
+//! Garbage collection of unreferenced CAS objects.
+//!
+//! CAS objects are deduplicated across organizations and never deleted when
+//! they're written, so storage only grows. Garbage collection (see
+//! [`collect`]) reclaims it by deleting objects that:
+//! - No saved unit references, in any organization, either directly or as a
+//!   chunk of a chunk list (see [`ChunkList`]) that a unit references.
+//! - Aren't a dictionary or a registry file, which are referenced outside of
+//!   saved units.
+//! - Nobody has created, read, written, or checked for within the retention
+//!   window.
+//!
+//! Deleting an object also revokes every organization's access to it, so
+//! clients that still have the key upload the object again the next time they
+//! need it, the same as for an object that was never stored.
+//!
+//! Courier records when each object was last accessed (see
+//! [`Postgres::touch_cas_keys`]), at most once an hour, so the retention
+//! window must be comfortably longer than that. Each deletion checks the
+//! access time again and locks the object's key until its file is removed, so
+//! an object that a client starts using again while garbage collection runs is
+//! kept, and a key is only deleted once its file is gone.
+//!
+//! Objects on disk that the database doesn't know about at all, e.g. because a
+//! previous run was interrupted between deleting an object's key and its file,
+//! are deleted too, once their file is older than the retention window.
+//!
+//! Garbage collection runs with `courier gc`, or periodically in the server
+//! (see `COURIER_GC_INTERVAL`). Concurrent runs are safe, but only waste work.
+
+use std::{
+    collections::HashSet,
+    time::{Duration, SystemTime},
+};
+
+use clients::courier::v1::{ChunkList, Key};
+use color_eyre::{Result, eyre::bail};
+use time::OffsetDateTime;
+use tokio::io::AsyncReadExt;
+use tracing::{debug, info};
+
+use crate::{db::Postgres, storage::Disk};
+
+/// Configuration for garbage collection.
+#[derive(Clone, Debug)]
+pub struct GcConfig {
+    /// How long objects are kept after they were last accessed.
+    pub retention: Duration,
+
+    /// The number of saved units or objects to read from the database at a
+    /// time.
+    pub batch_size: u64,
+
+    /// Report what would be deleted without deleting anything.
+    pub dry_run: bool,
+}
+
+impl GcConfig {
+    /// The default retention window.
+    pub const DEFAULT_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+    /// The shortest retention window, which keeps it well above how often
+    /// access times are updated.
+    pub const MIN_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            retention: Self::DEFAULT_RETENTION,
+            batch_size: 1000,
+            dry_run: false,
+        }
+    }
+}
+
+/// Summary of a garbage collection run.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct GcSummary {
+    /// Saved units whose references were walked.
+    pub units_checked: u64,
+
+    /// Distinct objects referenced by saved units.
+    pub objects_referenced: u64,
+
+    /// Objects deleted, or that would be deleted in a dry run.
+    pub objects_deleted: u64,
+
+    /// Objects on disk that the database doesn't know about that were deleted,
+    /// or that would be deleted in a dry run.
+    pub orphans_deleted: u64,
+
+    /// Compressed bytes of the deleted objects and orphans.
+    pub bytes_deleted: u64,
+}
+
+/// Delete CAS objects that no saved unit references and that haven't been
+/// accessed within the retention window.
+#[tracing::instrument(name = "gc::collect", skip(db, cas))]
+pub async fn collect(db: &Postgres, cas: &Disk, config: &GcConfig) -> Result<GcSummary> {
+    if config.retention < GcConfig::MIN_RETENTION {
+        bail!(
+            "garbage collection retention must be at least {:?}",
+            GcConfig::MIN_RETENTION
+        );
+    }
+    if config.batch_size == 0 {
+        bail!("garbage collection batch size must be at least 1");
+    }
+
+    // Saving a unit touches the objects it references, so units saved after
+    // the references are walked can't make a candidate referenced without
+    // also keeping it from being deleted.
+    let cutoff = OffsetDateTime::now_utc() - config.retention;
+    info!(%cutoff, dry_run = config.dry_run, "gc.start");
+
+    let mut summary = GcSummary::default();
+    let referenced = referenced_keys(db, cas, config, &mut summary).await?;
+    delete_unreferenced(db, cas, config, cutoff, &referenced, &mut summary).await?;
+    delete_orphans(db, cas, config, &referenced, &mut summary).await?;
+
+    info!(
+        dry_run = config.dry_run,
+        units_checked = summary.units_checked,
+        objects_referenced = summary.objects_referenced,
+        objects_deleted = summary.objects_deleted,
+        orphans_deleted = summary.orphans_deleted,
+        bytes_deleted = summary.bytes_deleted,
+        "gc.complete"
+    );
+    Ok(summary)
+}
+
+/// Collect the keys of every object referenced by a saved unit, including the
+/// chunks of referenced chunk lists.
+async fn referenced_keys(
+    db: &Postgres,
+    cas: &Disk,
+    config: &GcConfig,
+    summary: &mut GcSummary,
+) -> Result<HashSet<Key>> {
+    let mut referenced = HashSet::new();
+    let mut after = 0;
+    loop {
+        let units = db.gc_saved_units(after, config.batch_size).await?;
+        let Some((last, _)) = units.last() else {
+            break;
+        };
+        after = *last;
+        summary.units_checked += units.len() as u64;
+        for (_, unit) in &units {
+            referenced.extend(unit.object_keys().into_iter().cloned());
+        }
+    }
+
+    // Units reference chunked objects by the key of their chunk list, so the
+    // chunks are only reachable through the list's content.
+    let mut chunks = Vec::new();
+    for key in &referenced {
+        if let Some(list) = read_chunk_list(cas, key).await? {
+            chunks.extend(list.chunks);
+        }
+    }
+    referenced.extend(chunks);
+
+    summary.objects_referenced = referenced.len() as u64;
+    Ok(referenced)
+}
+
+/// Read the object as a chunk list, if it's stored and is one.
+///
+/// Only the start of other objects is read.
+async fn read_chunk_list(cas: &Disk, key: &Key) -> Result<Option<ChunkList>> {
+    if !cas.exists(key).await? {
+        return Ok(None);
+    }
+    let mut reader = cas.read(key).await?;
+    let mut content = Vec::new();
+    (&mut reader)
+        .take(ChunkList::MAGIC.len() as u64)
+        .read_to_end(&mut content)
+        .await?;
+    if content != ChunkList::MAGIC {
+        return Ok(None);
+    }
+    reader.read_to_end(&mut content).await?;
+    Ok(ChunkList::decode(&content))
+}
+
+/// Delete the objects the database knows about that aren't referenced and
+/// haven't been accessed since the cutoff.
+async fn delete_unreferenced(
+    db: &Postgres,
+    cas: &Disk,
+    config: &GcConfig,
+    cutoff: OffsetDateTime,
+    referenced: &HashSet<Key>,
+    summary: &mut GcSummary,
+) -> Result<()> {
+    let mut after = 0;
+    loop {
+        let candidates = db.gc_candidates(cutoff, after, config.batch_size).await?;
+        let Some(last) = candidates.last() else {
+            break;
+        };
+        after = last.id;
+        for candidate in candidates
+            .iter()
+            .filter(|candidate| !referenced.contains(&candidate.key))
+        {
+            if config.dry_run {
+                summary.objects_deleted += 1;
+                summary.bytes_deleted += cas.size_compressed(&candidate.key).await?.unwrap_or(0);
+                continue;
+            }
+            let Some(deletion) = db.gc_delete_cas_key(candidate.id, cutoff).await? else {
+                debug!(key = %candidate.key, "gc.accessed");
+                continue;
+            };
+
+            // The key stays locked until the deletion is committed, so the
+            // file is removed before any client can use the key again. If
+            // removing it fails, the deletion is rolled back and a later run
+            // tries again.
+            let size = cas.remove(&candidate.key).await?;
+            deletion.commit().await?;
+            summary.objects_deleted += 1;
+            summary.bytes_deleted += size.unwrap_or(0);
+        }
+    }
+    Ok(())
+}
+
+/// Delete objects on disk that the database doesn't know about, once they're
+/// older than the retention window.
+async fn delete_orphans(
+    db: &Postgres,
+    cas: &Disk,
+    config: &GcConfig,
+    referenced: &HashSet<Key>,
+    summary: &mut GcSummary,
+) -> Result<()> {
+    let keys = cas.list().await?;
+    for chunk in keys.chunks(config.batch_size as usize) {
+        for key in db.gc_unknown_keys(chunk).await? {
+            if referenced.contains(&key) {
+                continue;
+            }
+            // Objects are written to disk before their key is recorded, so
+            // recent files may just be in the middle of a write.
+            let old = cas.modified(&key).await?.is_some_and(|modified| {
+                SystemTime::now()
+                    .duration_since(modified)
+                    .is_ok_and(|age| age > config.retention)
+            });
+            if !old {
+                continue;
+            }
+            if config.dry_run {
+                summary.orphans_deleted += 1;
+                summary.bytes_deleted += cas.size_compressed(&key).await?.unwrap_or(0);
+                continue;
+            }
+            // An old orphan is recorded again if a client writes the same
+            // content, which makes it an object like any other.
+            if db
+                .gc_unknown_keys(std::slice::from_ref(&key))
+                .await?
+                .is_empty()
+            {
+                continue;
+            }
+            if let Some(size) = cas.remove(&key).await? {
+                summary.orphans_deleted += 1;
+                summary.bytes_deleted += size;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,546 @@
+//! Priority lanes for interactive and CI traffic.
+//!
+//! Clients declare the [`TrafficClass`] of their requests in the
+//! [`TRAFFIC_CLASS_HEADER`] header. Courier limits how many requests it serves
+//! at once, and when it's at that limit, requests wait in a lane for their
+//! class. Lanes are served with weighted fair queuing: each class gets a share
+//! of the requests that are let through in proportion to its weight, so a
+//! developer's restore isn't stuck behind a queue of CI uploads, while CI still
+//! makes progress when developers are busy.
+//!
+//! A request holds its place until its response body has been sent, since
+//! for restores that's where most of the work is.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use clients::courier::v1::traffic::{TRAFFIC_CLASS_HEADER, TrafficClass};
+use derive_more::Debug;
+use futures::StreamExt;
+use tokio::sync::oneshot;
+
+/// Paths that are never queued, so that health checks keep answering while
+/// Courier is busy.
+const UNQUEUED_PATHS: [&str; 1] = ["/api/v1/health"];
+
+/// The virtual time a class advances by for each request it's granted, when
+/// its weight is 1. Heavier classes advance proportionally less.
+const VIRTUAL_TIME_STEP: u64 = 1_000_000;
+
+/// Configuration for [`Lanes`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LanesConfig {
+    /// The most requests served at once; further requests wait in their lane.
+    /// Zero disables queuing, though statistics are still recorded.
+    pub max_in_flight: usize,
+
+    /// The share of requests that interactive traffic gets when both lanes
+    /// are waiting, relative to `ci_weight`.
+    pub interactive_weight: u32,
+
+    /// The share of requests that CI traffic gets when both lanes are
+    /// waiting, relative to `interactive_weight`.
+    pub ci_weight: u32,
+}
+
+impl LanesConfig {
+    /// The default limit on requests served at once.
+    pub const DEFAULT_MAX_IN_FLIGHT: usize = 256;
+
+    /// The default weight of interactive traffic.
+    pub const DEFAULT_INTERACTIVE_WEIGHT: u32 = 4;
+
+    /// The default weight of CI traffic.
+    pub const DEFAULT_CI_WEIGHT: u32 = 1;
+
+    fn weight(&self, class: TrafficClass) -> u64 {
+        let weight = match class {
+            TrafficClass::Interactive => self.interactive_weight,
+            TrafficClass::Ci => self.ci_weight,
+        };
+        u64::from(weight.max(1))
+    }
+}
+
+impl Default for LanesConfig {
+    fn default() -> Self {
+        Self {
+            max_in_flight: Self::DEFAULT_MAX_IN_FLIGHT,
+            interactive_weight: Self::DEFAULT_INTERACTIVE_WEIGHT,
+            ci_weight: Self::DEFAULT_CI_WEIGHT,
+        }
+    }
+}
+
+/// Limits how many requests are served at once, queuing the rest in a lane
+/// per traffic class.
+///
+/// Lanes are cheap to clone; clones share the same limit.
+#[derive(Clone, Debug)]
+pub struct Lanes {
+    config: LanesConfig,
+    #[debug(skip)]
+    queue: Arc<Mutex<Queue>>,
+    #[debug(skip)]
+    stats: Arc<[Counters; 2]>,
+}
+
+impl Lanes {
+    /// Create lanes with the provided configuration.
+    pub fn new(config: LanesConfig) -> Self {
+        Self {
+            config,
+            queue: Arc::default(),
+            stats: Arc::default(),
+        }
+    }
+
+    /// Wait for a turn to serve a request of the provided class.
+    ///
+    /// The turn lasts until the returned permit is dropped.
+    pub async fn acquire(&self, class: TrafficClass) -> Permit {
+        let receiver = {
+            let mut queue = self.queue.lock().expect("lanes lock poisoned");
+            let limited = self.config.max_in_flight > 0;
+            if !limited || (queue.in_flight < self.config.max_in_flight && queue.is_empty()) {
+                if limited {
+                    queue.in_flight += 1;
+                    queue.grant(&self.config, class);
+                }
+                return Permit {
+                    lanes: limited.then(|| self.clone()),
+                };
+            }
+            let (sender, receiver) = oneshot::channel();
+            queue.push(class, sender);
+            receiver
+        };
+
+        self.stats[index(class)]
+            .queued
+            .fetch_add(1, Ordering::Relaxed);
+        let mut waiting = Waiting {
+            lanes: self.clone(),
+            receiver,
+        };
+        // The sender is only dropped when the lanes are, which can't happen
+        // while this holds a clone of them.
+        let _ = (&mut waiting.receiver).await;
+        waiting.receiver.close();
+        Permit {
+            lanes: Some(self.clone()),
+        }
+    }
+
+    /// Give the turn of a finished request to the next waiting request, if
+    /// any.
+    fn release(&self) {
+        let mut queue = self.queue.lock().expect("lanes lock poisoned");
+        while let Some(sender) = queue.next(&self.config) {
+            // A waiter that gave up has dropped its receiver; its turn goes
+            // to the next one.
+            if sender.send(()).is_ok() {
+                return;
+            }
+        }
+        queue.in_flight -= 1;
+    }
+
+    /// Record a finished request.
+    fn record(&self, class: TrafficClass, waited: Duration, latency: Duration) {
+        let counters = &self.stats[index(class)];
+        counters.requests.fetch_add(1, Ordering::Relaxed);
+        let waited = waited.as_millis() as u64;
+        counters.wait_ms.fetch_add(waited, Ordering::Relaxed);
+        counters.max_wait_ms.fetch_max(waited, Ordering::Relaxed);
+        let latency = latency.as_millis() as u64;
+        counters.latency_ms.fetch_add(latency, Ordering::Relaxed);
+        counters
+            .max_latency_ms
+            .fetch_max(latency, Ordering::Relaxed);
+    }
+
+    /// Take the statistics of the traffic class recorded since they were last
+    /// taken.
+    pub fn take_stats(&self, class: TrafficClass) -> LaneStats {
+        let counters = &self.stats[index(class)];
+        let waiting = self
+            .queue
+            .lock()
+            .expect("lanes lock poisoned")
+            .waiting(class);
+        LaneStats {
+            requests: counters.requests.swap(0, Ordering::Relaxed),
+            queued: counters.queued.swap(0, Ordering::Relaxed),
+            waiting,
+            wait_ms: counters.wait_ms.swap(0, Ordering::Relaxed),
+            max_wait_ms: counters.max_wait_ms.swap(0, Ordering::Relaxed),
+            latency_ms: counters.latency_ms.swap(0, Ordering::Relaxed),
+            max_latency_ms: counters.max_latency_ms.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+/// A turn to serve a request; the turn passes to the next waiting request
+/// when this is dropped.
+#[derive(Debug)]
+pub struct Permit {
+    lanes: Option<Lanes>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(lanes) = &self.lanes {
+            lanes.release();
+        }
+    }
+}
+
+/// A request waiting in its lane. If the request is dropped after it was
+/// given a turn but before it noticed, the turn is passed on.
+struct Waiting {
+    lanes: Lanes,
+    receiver: oneshot::Receiver<()>,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        self.receiver.close();
+        if self.receiver.try_recv().is_ok() {
+            self.lanes.release();
+        }
+    }
+}
+
+/// The statistics of a traffic class over a period.
+#[derive(Clone, Copy, Eq, PartialEq, Default, Debug)]
+pub struct LaneStats {
+    /// Requests that finished.
+    pub requests: u64,
+
+    /// Requests that had to wait in the lane.
+    pub queued: u64,
+
+    /// Requests waiting in the lane right now.
+    pub waiting: usize,
+
+    /// Total time finished requests spent waiting, in milliseconds.
+    pub wait_ms: u64,
+
+    /// The longest time a finished request spent waiting, in milliseconds.
+    pub max_wait_ms: u64,
+
+    /// Total time from arrival to the end of the response of finished
+    /// requests, in milliseconds.
+    pub latency_ms: u64,
+
+    /// The longest latency of a finished request, in milliseconds.
+    pub max_latency_ms: u64,
+}
+
+impl LaneStats {
+    /// The mean latency of finished requests, in milliseconds.
+    pub fn mean_latency_ms(&self) -> u64 {
+        self.latency_ms
+            .checked_div(self.requests)
+            .unwrap_or_default()
+    }
+
+    /// The mean time finished requests spent waiting, in milliseconds.
+    pub fn mean_wait_ms(&self) -> u64 {
+        self.wait_ms.checked_div(self.requests).unwrap_or_default()
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: AtomicU64,
+    queued: AtomicU64,
+    wait_ms: AtomicU64,
+    max_wait_ms: AtomicU64,
+    latency_ms: AtomicU64,
+    max_latency_ms: AtomicU64,
+}
+
+/// The waiting requests of each lane, and the virtual time of each lane for
+/// weighted fair queuing.
+///
+/// Each time a lane is granted a request, its virtual time advances by
+/// [`VIRTUAL_TIME_STEP`] divided by its weight; the next request granted is
+/// from the waiting lane with the earliest virtual time. A lane that was idle
+/// catches up to the current virtual time when a request arrives in it, so
+/// that it can't save up turns while idle and then starve the other lane.
+#[derive(Default)]
+struct Queue {
+    in_flight: usize,
+    now: u64,
+    virtual_time: [u64; 2],
+    waiting: [VecDeque<oneshot::Sender<()>>; 2],
+}
+
+impl Queue {
+    fn is_empty(&self) -> bool {
+        self.waiting.iter().all(VecDeque::is_empty)
+    }
+
+    fn waiting(&self, class: TrafficClass) -> usize {
+        self.waiting[index(class)].len()
+    }
+
+    fn push(&mut self, class: TrafficClass, sender: oneshot::Sender<()>) {
+        let lane = index(class);
+        if self.waiting[lane].is_empty() {
+            self.virtual_time[lane] = self.virtual_time[lane].max(self.now);
+        }
+        self.waiting[lane].push_back(sender);
+    }
+
+    /// Advance the virtual time of the class for a request it was granted.
+    fn grant(&mut self, config: &LanesConfig, class: TrafficClass) {
+        let lane = index(class);
+        self.virtual_time[lane] = self.virtual_time[lane].max(self.now);
+        self.now = self.virtual_time[lane];
+        self.virtual_time[lane] += VIRTUAL_TIME_STEP / config.weight(class);
+    }
+
+    /// Take the next waiting request to grant, if any.
+    fn next(&mut self, config: &LanesConfig) -> Option<oneshot::Sender<()>> {
+        let class = TrafficClass::ALL
+            .into_iter()
+            .filter(|&class| !self.waiting[index(class)].is_empty())
+            .min_by_key(|&class| self.virtual_time[index(class)])?;
+        self.grant(config, class);
+        self.waiting[index(class)].pop_front()
+    }
+}
+
+fn index(class: TrafficClass) -> usize {
+    match class {
+        TrafficClass::Interactive => 0,
+        TrafficClass::Ci => 1,
+    }
+}
+
+/// Middleware that serves each request in the lane for its traffic class.
+pub async fn prioritize(State(lanes): State<Lanes>, request: Request, next: Next) -> Response {
+    if UNQUEUED_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let class = TrafficClass::from_header(
+        request
+            .headers()
+            .get(TRAFFIC_CLASS_HEADER)
+            .and_then(|value| value.to_str().ok()),
+    );
+    let start = Instant::now();
+    let permit = lanes.acquire(class).await;
+    let turn = Turn {
+        lanes: lanes.clone(),
+        class,
+        start,
+        waited: start.elapsed(),
+        _permit: permit,
+    };
+
+    // The turn is held by the response body, so that it lasts until the body
+    // has been sent (or the client hangs up).
+    let (parts, body) = next.run(request).await.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _turn = &turn;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// A request being served; records the request's statistics when dropped.
+struct Turn {
+    lanes: Lanes,
+    class: TrafficClass,
+    start: Instant,
+    waited: Duration,
+    _permit: Permit,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        self.lanes
+            .record(self.class, self.waited, self.start.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::*;
+
+    fn config(max_in_flight: usize) -> LanesConfig {
+        LanesConfig {
+            max_in_flight,
+            interactive_weight: 3,
+            ci_weight: 1,
+        }
+    }
+
+    /// Queue requests of the classes in order, then grant every waiting
+    /// request, returning the classes in the order they were granted.
+    fn drain(
+        queue: &mut Queue,
+        config: &LanesConfig,
+        classes: &[TrafficClass],
+    ) -> Vec<TrafficClass> {
+        let mut receivers = Vec::new();
+        for &class in classes {
+            let (sender, receiver) = oneshot::channel();
+            queue.push(class, sender);
+            receivers.push((class, receiver));
+        }
+
+        let mut order = Vec::new();
+        while let Some(sender) = queue.next(config) {
+            sender.send(()).unwrap();
+            let granted = receivers
+                .iter_mut()
+                .position(|(_, receiver)| receiver.try_recv().is_ok())
+                .unwrap();
+            order.push(receivers[granted].0);
+        }
+        order
+    }
+
+    #[test]
+    fn queue_shares_turns_by_weight() {
+        use TrafficClass::{Ci, Interactive};
+        let mut queue = Queue::default();
+        let order = drain(
+            &mut queue,
+            &config(1),
+            &[
+                Ci,
+                Ci,
+                Ci,
+                Ci,
+                Interactive,
+                Interactive,
+                Interactive,
+                Interactive,
+                Interactive,
+                Interactive,
+            ],
+        );
+        pretty_assert_eq!(
+            order,
+            vec![
+                Interactive,
+                Ci,
+                Interactive,
+                Interactive,
+                Interactive,
+                Ci,
+                Interactive,
+                Interactive,
+                Ci,
+                Ci
+            ]
+        );
+    }
+
+    #[test]
+    fn idle_lane_does_not_save_turns() {
+        use TrafficClass::{Ci, Interactive};
+        let config = config(1);
+        let mut queue = Queue::default();
+        for _ in 0..30 {
+            queue.grant(&config, Ci);
+        }
+
+        // Interactive was idle while CI was served, so it doesn't get the 90
+        // turns in a row that its weight would have earned it.
+        let order = drain(
+            &mut queue,
+            &config,
+            &[
+                Ci,
+                Interactive,
+                Interactive,
+                Interactive,
+                Interactive,
+                Interactive,
+                Interactive,
+            ],
+        );
+        pretty_assert_eq!(
+            order,
+            vec![
+                Interactive,
+                Interactive,
+                Interactive,
+                Interactive,
+                Ci,
+                Interactive,
+                Interactive
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_turn() {
+        let lanes = Lanes::new(config(1));
+        let first = lanes.acquire(TrafficClass::Ci).await;
+
+        let waiting = tokio::spawn({
+            let lanes = lanes.clone();
+            async move { lanes.acquire(TrafficClass::Interactive).await }
+        });
+        tokio::task::yield_now().await;
+        pretty_assert_eq!(lanes.take_stats(TrafficClass::Interactive).waiting, 1);
+
+        drop(first);
+        let second = waiting.await.unwrap();
+        pretty_assert_eq!(lanes.queue.lock().unwrap().in_flight, 1);
+        drop(second);
+        pretty_assert_eq!(lanes.queue.lock().unwrap().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn abandoned_waiters_pass_their_turn_on() {
+        let lanes = Lanes::new(config(1));
+        let first = lanes.acquire(TrafficClass::Ci).await;
+
+        let abandoned = tokio::spawn({
+            let lanes = lanes.clone();
+            async move { lanes.acquire(TrafficClass::Interactive).await }
+        });
+        tokio::task::yield_now().await;
+        abandoned.abort();
+        let _ = abandoned.await;
+
+        drop(first);
+        pretty_assert_eq!(lanes.queue.lock().unwrap().in_flight, 0);
+        let _second = lanes.acquire(TrafficClass::Ci).await;
+    }
+
+    #[tokio::test]
+    async fn unlimited_lanes_do_not_queue() {
+        let lanes = Lanes::new(config(0));
+        let permits = [
+            lanes.acquire(TrafficClass::Ci).await,
+            lanes.acquire(TrafficClass::Ci).await,
+        ];
+        pretty_assert_eq!(lanes.queue.lock().unwrap().in_flight, 0);
+        drop(permits);
+    }
+}
@@ -1,9 +1,18 @@
 //! Courier library exports for integration tests.
 
+pub mod access;
 pub mod api;
 pub mod auth;
 pub mod crypto;
 pub mod db;
+pub mod dictionary;
+pub mod gc;
+pub mod lanes;
+pub mod loadgen;
+pub mod maintenance;
 pub mod oauth;
+pub mod quota;
 pub mod rate_limit;
+pub mod registry;
 pub mod storage;
+pub mod sync;
@@ -0,0 +1,510 @@
+//! Load generation against a Courier instance.
+//!
+//! [`run`] simulates concurrent Hurry clients, each repeatedly picking an
+//! operation from a weighted mix:
+//! - Save: upload a unit's objects, then save the unit.
+//! - Restore: restore a batch of previously saved units, then read their
+//!   objects in bulk.
+//! - CAS: write an object, then read back a previously written one.
+//!
+//! Object sizes are drawn log-uniformly between a minimum and maximum size,
+//! so that most objects are small and a few are large, as with real build
+//! artifacts. Object contents are random, so they don't compress; this is the
+//! worst case for Courier.
+//!
+//! Everything generated belongs to the organization of the API token, and is
+//! named after the run (`courier-loadgen-<run id>`) so that it's easy to tell
+//! apart from real units.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use clients::courier::v1::{
+    Client, DiskPath, Fingerprint, Key, LibraryCrateUnitPlan, LibraryFiles, SavedFile, SavedUnit,
+    SavedUnitHash, UnitPlanInfo,
+    cache::{CargoRestoreRequest, CargoSaveRequest, CargoSaveUnitRequest},
+};
+use color_eyre::{Result, eyre::Context};
+use derive_more::Display;
+use futures::TryStreamExt;
+use rand::{Rng, RngCore, SeedableRng, rngs::StdRng, seq::SliceRandom};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// The most saved units remembered for later restores and reads.
+const MAX_REMEMBERED_UNITS: usize = 4096;
+
+/// The most units restored in a single restore.
+const MAX_RESTORE_BATCH: usize = 32;
+
+/// Configuration for a load generation run.
+#[derive(Clone, Debug)]
+pub struct LoadgenConfig {
+    /// The instance to generate load against.
+    pub client: Client,
+
+    /// The number of simulated clients.
+    pub clients: usize,
+
+    /// How long to generate load for.
+    pub duration: Duration,
+
+    /// How long each client waits between operations.
+    pub think_time: Duration,
+
+    /// The relative frequency of each operation.
+    pub mix: OperationMix,
+
+    /// The sizes of generated objects.
+    pub sizes: SizeDistribution,
+
+    /// The largest number of output files in a saved unit.
+    pub max_files_per_unit: usize,
+}
+
+/// The relative frequency of each operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OperationMix {
+    pub save: u32,
+    pub restore: u32,
+    pub cas: u32,
+}
+
+impl OperationMix {
+    /// Pick an operation with probability proportional to its weight.
+    fn pick(&self, rng: &mut impl Rng) -> Operation {
+        let total = self.save + self.restore + self.cas;
+        if total == 0 {
+            return Operation::Save;
+        }
+        let roll = rng.gen_range(0..total);
+        if roll < self.save {
+            Operation::Save
+        } else if roll < self.save + self.restore {
+            Operation::Restore
+        } else {
+            Operation::Cas
+        }
+    }
+}
+
+/// Object sizes, drawn log-uniformly between `min` and `max` bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeDistribution {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl SizeDistribution {
+    /// Draw an object size.
+    fn sample(&self, rng: &mut impl Rng) -> u64 {
+        let min = self.min.max(1) as f64;
+        let max = (self.max as f64).max(min);
+        let size = min * (max / min).powf(rng.gen_range(0.0..=1.0));
+        (size.round() as u64).clamp(min as u64, max as u64)
+    }
+}
+
+/// An operation performed by a simulated client.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Operation {
+    #[display("save")]
+    Save,
+
+    #[display("restore")]
+    Restore,
+
+    #[display("cas")]
+    Cas,
+}
+
+/// The outcomes of one kind of operation.
+#[derive(Clone, Debug, Default)]
+pub struct OperationStats {
+    /// How long each successful operation took.
+    pub latencies: Vec<Duration>,
+
+    /// Operations that failed.
+    pub errors: u64,
+
+    /// Bytes of object content written and read.
+    pub bytes: u64,
+}
+
+impl OperationStats {
+    /// The latency that `quantile` (between 0 and 1) of operations were at
+    /// least as fast as.
+    pub fn percentile(&self, quantile: f64) -> Option<Duration> {
+        let mut latencies = self.latencies.clone();
+        latencies.sort_unstable();
+        percentile(&latencies, quantile)
+    }
+
+    fn merge(&mut self, other: OperationStats) {
+        self.latencies.extend(other.latencies);
+        self.errors += other.errors;
+        self.bytes += other.bytes;
+    }
+}
+
+/// What a load generation run did.
+#[derive(Clone, Debug, Default)]
+pub struct LoadgenReport {
+    /// The stats of each operation.
+    pub operations: HashMap<Operation, OperationStats>,
+
+    /// How long the run took.
+    pub elapsed: Duration,
+}
+
+impl LoadgenReport {
+    /// Render the report as a table.
+    pub fn table(&self) -> String {
+        let mut table = format!(
+            "{:<10} {:>8} {:>8} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10}\n",
+            "operation", "count", "errors", "ops/s", "p50", "p90", "p99", "max", "MiB"
+        );
+        let mut operations = self.operations.iter().collect::<Vec<_>>();
+        operations.sort_by_key(|(operation, _)| **operation);
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        for (operation, stats) in operations {
+            let latency = |quantile| {
+                stats
+                    .percentile(quantile)
+                    .map(|latency| format!("{:.1}ms", latency.as_secs_f64() * 1000.0))
+                    .unwrap_or_else(|| String::from("-"))
+            };
+            table.push_str(&format!(
+                "{:<10} {:>8} {:>8} {:>8.1} {:>10} {:>10} {:>10} {:>10} {:>10.1}\n",
+                operation.to_string(),
+                stats.latencies.len(),
+                stats.errors,
+                stats.latencies.len() as f64 / secs,
+                latency(0.5),
+                latency(0.9),
+                latency(0.99),
+                latency(1.0),
+                stats.bytes as f64 / (1024.0 * 1024.0),
+            ));
+        }
+        table
+    }
+}
+
+/// A saved unit, remembered so that later operations can restore it and read
+/// its objects.
+#[derive(Clone, Debug)]
+struct RememberedUnit {
+    hash: SavedUnitHash,
+    keys: Vec<Key>,
+}
+
+/// State shared by the simulated clients.
+#[derive(Debug)]
+struct Shared {
+    config: LoadgenConfig,
+    run: String,
+    units: Mutex<Vec<RememberedUnit>>,
+}
+
+impl Shared {
+    fn remember(&self, unit: RememberedUnit, rng: &mut impl Rng) {
+        let mut units = self.units.lock().expect("units lock poisoned");
+        if units.len() < MAX_REMEMBERED_UNITS {
+            units.push(unit);
+        } else {
+            let index = rng.gen_range(0..units.len());
+            units[index] = unit;
+        }
+    }
+
+    fn sample(&self, count: usize, rng: &mut impl Rng) -> Vec<RememberedUnit> {
+        let units = self.units.lock().expect("units lock poisoned");
+        units.choose_multiple(rng, count).cloned().collect()
+    }
+}
+
+/// Generate load until the configured duration has passed.
+pub async fn run(config: LoadgenConfig) -> Result<LoadgenReport> {
+    let run = Uuid::new_v4().simple().to_string();
+    info!(%run, clients = config.clients, duration = ?config.duration, "loadgen.start");
+
+    let start = Instant::now();
+    let deadline = start + config.duration;
+    let shared = Arc::new(Shared {
+        config,
+        run,
+        units: Mutex::new(Vec::new()),
+    });
+    let workers = (0..shared.config.clients.max(1))
+        .map(|worker| tokio::spawn(simulate_client(shared.clone(), worker, deadline)))
+        .collect::<Vec<_>>();
+
+    let mut report = LoadgenReport::default();
+    for worker in workers {
+        let stats = worker.await.context("join simulated client")?;
+        for (operation, stats) in stats {
+            report.operations.entry(operation).or_default().merge(stats);
+        }
+    }
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
+
+/// Perform operations as a single client until the deadline.
+async fn simulate_client(
+    shared: Arc<Shared>,
+    worker: usize,
+    deadline: Instant,
+) -> HashMap<Operation, OperationStats> {
+    let mut rng = StdRng::from_entropy();
+    let mut stats = HashMap::<Operation, OperationStats>::new();
+    let mut sequence = 0u64;
+    while Instant::now() < deadline {
+        let mut operation = shared.config.mix.pick(&mut rng);
+        if operation != Operation::Save
+            && shared.units.lock().expect("units lock poisoned").is_empty()
+        {
+            // Nothing has been saved to restore or read yet.
+            operation = Operation::Save;
+        }
+
+        let start = Instant::now();
+        let result = match operation {
+            Operation::Save => {
+                sequence += 1;
+                save(&shared, &mut rng, worker, sequence).await
+            }
+            Operation::Restore => restore(&shared, &mut rng).await,
+            Operation::Cas => cas(&shared, &mut rng).await,
+        };
+        let elapsed = start.elapsed();
+
+        let stats = stats.entry(operation).or_default();
+        match result {
+            Ok(bytes) => {
+                debug!(%operation, ?elapsed, bytes, "loadgen.operation");
+                stats.latencies.push(elapsed);
+                stats.bytes += bytes;
+            }
+            Err(err) => {
+                warn!(%operation, ?elapsed, ?err, "loadgen.operation.failed");
+                stats.errors += 1;
+            }
+        }
+
+        if !shared.config.think_time.is_zero() {
+            tokio::time::sleep(shared.config.think_time).await;
+        }
+    }
+    stats
+}
+
+/// Upload a new unit's objects and save it, returning the bytes uploaded.
+async fn save(shared: &Shared, rng: &mut StdRng, worker: usize, sequence: u64) -> Result<u64> {
+    let client = &shared.config.client;
+    let files = rng.gen_range(1..=shared.config.max_files_per_unit.max(1));
+    let mut keys = Vec::with_capacity(files + 2);
+    let mut bytes = 0;
+    for _ in 0..files + 2 {
+        let content = random_object(&shared.config.sizes, rng);
+        let key = Key::from_buffer(&content);
+        bytes += content.len() as u64;
+        client
+            .cas_write_bytes(&key, content)
+            .await
+            .with_context(|| format!("write object: {key}"))?;
+        keys.push(key);
+    }
+
+    let unit = generated_unit(&shared.run, worker, sequence, &keys);
+    let request = CargoSaveUnitRequest::builder()
+        .unit(unit)
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .maybe_linux_glibc_version(None)
+        .build();
+    let hash = request.saved_unit_hash();
+    client
+        .cargo_cache_save(CargoSaveRequest::new([request]))
+        .await
+        .context("save unit")?;
+
+    shared.remember(RememberedUnit { hash, keys }, rng);
+    Ok(bytes)
+}
+
+/// Restore a batch of saved units and read their objects, returning the
+/// bytes read.
+async fn restore(shared: &Shared, rng: &mut StdRng) -> Result<u64> {
+    let client = &shared.config.client;
+    let count = rng.gen_range(1..=MAX_RESTORE_BATCH);
+    let units = shared.sample(count, rng);
+    let response = client
+        .cargo_cache_restore(CargoRestoreRequest::new(
+            units.iter().map(|unit| unit.hash.clone()),
+            None,
+        ))
+        .await
+        .context("restore units")?;
+
+    let keys = response
+        .into_iter()
+        .flat_map(|(_, unit)| unit.object_keys().into_iter().cloned().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    if keys.is_empty() {
+        return Ok(0);
+    }
+    client
+        .cas_read_bulk(keys)
+        .await
+        .context("read objects")?
+        .try_fold(0, |bytes, (_, content)| async move {
+            Ok(bytes + content.len() as u64)
+        })
+        .await
+        .context("read object")
+}
+
+/// Write a new object and read a previously written one, returning the bytes
+/// written and read.
+async fn cas(shared: &Shared, rng: &mut StdRng) -> Result<u64> {
+    let client = &shared.config.client;
+    let content = random_object(&shared.config.sizes, rng);
+    let key = Key::from_buffer(&content);
+    let mut bytes = content.len() as u64;
+    client
+        .cas_write_bytes(&key, content)
+        .await
+        .with_context(|| format!("write object: {key}"))?;
+
+    let Some(key) = shared
+        .sample(1, rng)
+        .into_iter()
+        .flat_map(|unit| unit.keys)
+        .collect::<Vec<_>>()
+        .choose(rng)
+        .cloned()
+    else {
+        return Ok(bytes);
+    };
+    let content = client
+        .cas_read_bytes(&key)
+        .await
+        .with_context(|| format!("read object: {key}"))?;
+    bytes += content
+        .map(|content| content.len() as u64)
+        .unwrap_or_default();
+    Ok(bytes)
+}
+
+/// Generate an object with random content.
+fn random_object(sizes: &SizeDistribution, rng: &mut impl Rng) -> Vec<u8> {
+    let mut content = vec![0; sizes.sample(rng) as usize];
+    rng.fill_bytes(&mut content);
+    content
+}
+
+/// A library unit whose files are the given objects: the dep-info files
+/// first, then the output files.
+fn generated_unit(run: &str, worker: usize, sequence: u64, keys: &[Key]) -> SavedUnit {
+    let unit_hash = SavedUnitHash::new(format!("loadgen-{run}-{worker}-{sequence}"));
+    let info = UnitPlanInfo::builder()
+        .unit_hash(&unit_hash)
+        .package_name(format!("courier-loadgen-{run}"))
+        .crate_name("courier_loadgen")
+        .maybe_target_arch(Some("x86_64-unknown-linux-gnu"))
+        .build();
+    let output_files = keys
+        .iter()
+        .skip(2)
+        .enumerate()
+        .map(|(index, key)| {
+            SavedFile::builder()
+                .executable(false)
+                .object_key(key)
+                .path(DiskPath::new(format!("deps/courier_loadgen-{index}.rlib")))
+                .build()
+        })
+        .collect();
+    let files = LibraryFiles::builder()
+        .output_files(output_files)
+        .fingerprint(Fingerprint::from("courier-loadgen"))
+        .dep_info_file(keys[0].clone())
+        .encoded_dep_info_file(keys[1].clone())
+        .build();
+    let plan = LibraryCrateUnitPlan::builder()
+        .info(info)
+        .src_path("src/lib.rs")
+        .outputs(vec![])
+        .build();
+    SavedUnit::LibraryCrate(files, plan)
+}
+
+/// The latency that `quantile` (between 0 and 1) of the sorted latencies
+/// were at least as fast as, using the nearest-rank method.
+fn percentile(sorted: &[Duration], quantile: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (quantile.clamp(0.0, 1.0) * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.saturating_sub(1)).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use rand::{SeedableRng, rngs::StdRng};
+    use simple_test_case::test_case;
+
+    use super::{Operation, OperationMix, SizeDistribution, percentile};
+
+    #[test_case(0.0, Some(1); "min")]
+    #[test_case(0.5, Some(5); "median")]
+    #[test_case(0.9, Some(9); "p90")]
+    #[test_case(0.99, Some(10); "p99")]
+    #[test_case(1.0, Some(10); "max")]
+    #[test]
+    fn percentiles(quantile: f64, expected: Option<u64>) {
+        let latencies = (1..=10).map(Duration::from_millis).collect::<Vec<_>>();
+        pretty_assert_eq!(
+            percentile(&latencies, quantile),
+            expected.map(Duration::from_millis)
+        );
+    }
+
+    #[test]
+    fn percentile_of_nothing() {
+        pretty_assert_eq!(percentile(&[], 0.5), None);
+    }
+
+    #[test]
+    fn sizes_stay_in_range() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let sizes = SizeDistribution {
+            min: 1024,
+            max: 64 * 1024 * 1024,
+        };
+        for _ in 0..1000 {
+            let size = sizes.sample(&mut rng);
+            assert!((sizes.min..=sizes.max).contains(&size), "size: {size}");
+        }
+    }
+
+    #[test]
+    fn mix_skips_unweighted_operations() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mix = OperationMix {
+            save: 0,
+            restore: 1,
+            cas: 0,
+        };
+        for _ in 0..100 {
+            pretty_assert_eq!(mix.pick(&mut rng), Operation::Restore);
+        }
+    }
+}
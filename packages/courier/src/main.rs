@@ -1,8 +1,11 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use aerosol::Aero;
 use clap::Parser;
-use color_eyre::{Result, eyre::Context};
+use color_eyre::{
+    Result,
+    eyre::{Context, OptionExt},
+};
 use derive_more::Debug;
 use tracing::level_filters::LevelFilter;
 use tracing_error::ErrorLayer;
@@ -22,6 +25,20 @@ enum Command {
 
     /// Apply database migrations
     Migrate(MigrateConfig),
+
+    /// Train zstd compression dictionaries from stored CAS objects
+    TrainDictionaries(TrainDictionariesConfig),
+
+    /// Copy cached units and the CAS objects they reference from one Courier
+    /// to another
+    Sync(SyncConfig),
+
+    /// Check that every stored CAS object still matches its key
+    VerifyStorage(VerifyStorageConfig),
+
+    /// Delete CAS objects that no saved unit references and that haven't been
+    /// accessed recently
+    Gc(GcCommandConfig),
 }
 
 #[derive(Parser, Debug)]
@@ -39,9 +56,8 @@ struct ServeConfig {
     #[arg(long, env = "HOST", default_value = "0.0.0.0")]
     host: String,
 
-    /// Root path to store CAS blobs
-    #[arg(long, env = "CAS_ROOT")]
-    cas_root: PathBuf,
+    #[command(flatten)]
+    storage: StorageArgs,
 
     /// Directory containing the console static files (optional)
     #[arg(long, env = "CONSOLE_DIR")]
@@ -59,6 +75,238 @@ struct ServeConfig {
     /// Allowed redirect URIs for OAuth (comma-separated)
     #[arg(long, env = "OAUTH_REDIRECT_ALLOWLIST", value_delimiter = ',')]
     oauth_redirect_allowlist: Vec<String>,
+
+    /// Public URL of the server, used in the crate registry proxy's
+    /// configuration (inferred from each request if not set)
+    #[arg(long, env = "COURIER_PUBLIC_URL")]
+    #[debug("{:?}", public_url.as_ref().map(|url| url.as_str()))]
+    public_url: Option<url::Url>,
+
+    /// Upstream sparse index for the crate registry proxy
+    #[arg(
+        long,
+        env = "COURIER_REGISTRY_INDEX_UPSTREAM",
+        default_value = courier::registry::RegistryConfig::CRATES_IO_INDEX
+    )]
+    #[debug("{:?}", registry_index_upstream.as_str())]
+    registry_index_upstream: url::Url,
+
+    /// Upstream crate downloads for the crate registry proxy
+    #[arg(
+        long,
+        env = "COURIER_REGISTRY_DOWNLOAD_UPSTREAM",
+        default_value = courier::registry::RegistryConfig::CRATES_IO_DOWNLOAD
+    )]
+    #[debug("{:?}", registry_download_upstream.as_str())]
+    registry_download_upstream: url::Url,
+
+    /// Seconds to serve cached registry index files before revalidating them
+    #[arg(long, env = "COURIER_REGISTRY_INDEX_TTL", default_value_t = 300)]
+    registry_index_ttl: u64,
+
+    /// Maximum size in bytes of the in-memory CAS read cache (0 disables it)
+    #[arg(
+        long,
+        env = "COURIER_READ_CACHE_MEMORY_BYTES",
+        default_value_t = courier::storage::ReadCacheConfig::DEFAULT_MEMORY_BYTES
+    )]
+    read_cache_memory_bytes: u64,
+
+    /// Local directory for the on-disk CAS read cache (optional, enables the
+    /// disk tier if provided; cleared on startup)
+    #[arg(long, env = "COURIER_READ_CACHE_DIR")]
+    read_cache_dir: Option<PathBuf>,
+
+    /// Maximum size in bytes of the on-disk CAS read cache
+    #[arg(
+        long,
+        env = "COURIER_READ_CACHE_DISK_BYTES",
+        default_value_t = courier::storage::ReadCacheConfig::DEFAULT_DISK_BYTES
+    )]
+    read_cache_disk_bytes: u64,
+
+    /// Maximum compressed size in bytes of objects in the CAS read cache
+    #[arg(
+        long,
+        env = "COURIER_READ_CACHE_MAX_OBJECT_BYTES",
+        default_value_t = courier::storage::ReadCacheConfig::DEFAULT_MAX_OBJECT_BYTES
+    )]
+    read_cache_max_object_bytes: u64,
+
+    /// Secret that signs access tokens issued for API keys (optional, at least
+    /// 32 bytes, enables access tokens if provided; must be the same on every
+    /// instance)
+    #[arg(long, env = "COURIER_ACCESS_TOKEN_SECRET")]
+    #[debug(ignore)]
+    access_token_secret: Option<String>,
+
+    /// Seconds that access tokens are valid for, which bounds how long a
+    /// revoked API key keeps working through them
+    #[arg(
+        long,
+        env = "COURIER_ACCESS_TOKEN_TTL",
+        default_value_t = courier::access::AccessTokens::DEFAULT_TTL.as_secs()
+    )]
+    access_token_ttl: u64,
+
+    /// Maximum number of requests served at once; further requests wait in a
+    /// lane for their traffic class (0 disables queuing)
+    #[arg(
+        long,
+        env = "COURIER_MAX_IN_FLIGHT",
+        default_value_t = courier::lanes::LanesConfig::DEFAULT_MAX_IN_FLIGHT
+    )]
+    max_in_flight: usize,
+
+    /// Share of queued requests given to interactive traffic, relative to the
+    /// CI weight
+    #[arg(
+        long,
+        env = "COURIER_INTERACTIVE_WEIGHT",
+        default_value_t = courier::lanes::LanesConfig::DEFAULT_INTERACTIVE_WEIGHT
+    )]
+    interactive_weight: u32,
+
+    /// Share of queued requests given to CI traffic, relative to the
+    /// interactive weight
+    #[arg(
+        long,
+        env = "COURIER_CI_WEIGHT",
+        default_value_t = courier::lanes::LanesConfig::DEFAULT_CI_WEIGHT
+    )]
+    ci_weight: u32,
+
+    /// Reject saves and CAS writes with this reason while still serving
+    /// restores, e.g. during maintenance (optional)
+    #[arg(long, env = "COURIER_READ_ONLY")]
+    read_only: Option<String>,
+
+    /// Compressed bytes of CAS objects that each organization may store before
+    /// saves and CAS writes are rejected, unless the organization has its own
+    /// quota (optional, unlimited if not provided)
+    #[arg(long, env = "COURIER_STORAGE_QUOTA")]
+    storage_quota: Option<u64>,
+
+    /// Seconds between garbage collection runs in the background (optional,
+    /// enables background garbage collection if provided)
+    #[arg(long, env = "COURIER_GC_INTERVAL")]
+    gc_interval: Option<u64>,
+
+    /// Seconds that unreferenced CAS objects are kept after they were last
+    /// accessed
+    #[arg(
+        long,
+        env = "COURIER_GC_RETENTION",
+        default_value_t = courier::gc::GcConfig::DEFAULT_RETENTION.as_secs()
+    )]
+    gc_retention: u64,
+}
+
+/// Where CAS objects are stored.
+#[derive(clap::Args, Debug)]
+struct StorageArgs {
+    /// Storage for CAS objects, e.g. `fs:/var/lib/courier` for a directory on
+    /// a local filesystem whose writes are synced to the disk, or
+    /// `s3:<bucket>/<prefix>` for a prefix in an object storage bucket
+    #[arg(
+        long,
+        env = "COURIER_STORAGE",
+        required_unless_present = "cas_root",
+        conflicts_with = "cas_root"
+    )]
+    storage: Option<courier::storage::StorageConfig>,
+
+    /// Root path to store CAS blobs, without syncing writes (e.g. for a
+    /// mounted object storage bucket)
+    #[arg(long, env = "CAS_ROOT")]
+    cas_root: Option<PathBuf>,
+
+    /// S3-compatible endpoint of the object storage bucket that holds the CAS
+    /// (required for `s3:` storage; with `--cas-root`, enables direct
+    /// transfers with presigned URLs for the bucket mounted as the root)
+    #[arg(long, env = "COURIER_OBJECT_STORE_ENDPOINT")]
+    #[debug("{:?}", object_store_endpoint.as_ref().map(|url| url.as_str()))]
+    object_store_endpoint: Option<url::Url>,
+
+    /// Object storage bucket mounted as the CAS root (for `--cas-root`)
+    #[arg(long, env = "COURIER_OBJECT_STORE_BUCKET")]
+    object_store_bucket: Option<String>,
+
+    /// Prefix within the bucket that's mounted as the CAS root (optional, for
+    /// `--cas-root`)
+    #[arg(long, env = "COURIER_OBJECT_STORE_PREFIX")]
+    object_store_prefix: Option<String>,
+
+    /// Region of the object storage bucket (`auto` for Google Cloud Storage)
+    #[arg(long, env = "COURIER_OBJECT_STORE_REGION", default_value = "us-east-1")]
+    object_store_region: String,
+
+    /// Access key ID that accesses object storage and signs its URLs
+    #[arg(long, env = "COURIER_OBJECT_STORE_ACCESS_KEY_ID")]
+    object_store_access_key_id: Option<String>,
+
+    /// Secret access key that accesses object storage and signs its URLs
+    #[arg(long, env = "COURIER_OBJECT_STORE_SECRET_ACCESS_KEY")]
+    #[debug(ignore)]
+    object_store_secret_access_key: Option<String>,
+
+    /// Seconds that presigned object storage URLs are valid for
+    #[arg(
+        long,
+        env = "COURIER_OBJECT_STORE_URL_TTL",
+        default_value_t = courier::storage::ObjectStoreConfig::DEFAULT_URL_TTL.as_secs()
+    )]
+    object_store_url_ttl: u64,
+
+    /// Minimum compressed size in bytes of objects that clients read directly
+    /// from object storage
+    #[arg(
+        long,
+        env = "COURIER_OBJECT_STORE_MIN_READ_BYTES",
+        default_value_t = courier::storage::ObjectStoreConfig::DEFAULT_MIN_READ_BYTES
+    )]
+    object_store_min_read_bytes: u64,
+}
+
+impl StorageArgs {
+    fn open(&self) -> Result<courier::storage::Disk> {
+        let object_store = self.object_store()?;
+        match (&self.storage, &self.cas_root) {
+            (Some(storage), _) => storage.open(object_store.as_ref()),
+            (None, Some(root)) => {
+                let disk = courier::storage::Disk::new(root);
+                match object_store {
+                    Some(config) => courier::storage::ObjectStore::new(config)
+                        .context("configure object storage")
+                        .map(|store| disk.with_object_store(store)),
+                    None => Ok(disk),
+                }
+            }
+            (None, None) => unreachable!("clap requires --storage or --cas-root"),
+        }
+    }
+
+    /// The object storage configuration, if an endpoint is configured.
+    fn object_store(&self) -> Result<Option<courier::storage::ObjectStoreConfig>> {
+        let Some(endpoint) = self.object_store_endpoint.clone() else {
+            return Ok(None);
+        };
+        Ok(Some(courier::storage::ObjectStoreConfig {
+            endpoint,
+            bucket: self.object_store_bucket.clone().unwrap_or_default(),
+            prefix: self.object_store_prefix.clone(),
+            region: self.object_store_region.clone(),
+            access_key_id: self
+                .object_store_access_key_id
+                .clone()
+                .ok_or_eyre("COURIER_OBJECT_STORE_ACCESS_KEY_ID is required for object storage")?,
+            secret_access_key: self.object_store_secret_access_key.clone().ok_or_eyre(
+                "COURIER_OBJECT_STORE_SECRET_ACCESS_KEY is required for object storage",
+            )?,
+            url_ttl: Duration::from_secs(self.object_store_url_ttl),
+            min_read_bytes: self.object_store_min_read_bytes,
+        }))
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -69,6 +317,110 @@ struct MigrateConfig {
     database_url: String,
 }
 
+#[derive(Parser, Debug)]
+struct TrainDictionariesConfig {
+    /// Database URL
+    #[arg(long, env = "COURIER_DATABASE_URL")]
+    #[debug(ignore)]
+    database_url: String,
+
+    #[command(flatten)]
+    storage: StorageArgs,
+
+    /// Maximum number of samples to train each dictionary on
+    #[arg(long, default_value_t = courier::dictionary::TrainConfig::default().max_samples)]
+    max_samples: usize,
+
+    /// Minimum number of samples needed to train a dictionary
+    #[arg(long, default_value_t = courier::dictionary::TrainConfig::default().min_samples)]
+    min_samples: usize,
+
+    /// Maximum size of each dictionary in bytes
+    #[arg(long, default_value_t = courier::dictionary::TrainConfig::default().dictionary_size)]
+    dictionary_size: usize,
+}
+
+#[derive(Parser, Debug)]
+struct VerifyStorageConfig {
+    #[command(flatten)]
+    storage: StorageArgs,
+
+    /// Move objects that fail verification out of the CAS, so that clients
+    /// upload them again
+    #[arg(long)]
+    quarantine: bool,
+}
+
+#[derive(Parser, Debug)]
+struct GcCommandConfig {
+    /// Database URL
+    #[arg(long, env = "COURIER_DATABASE_URL")]
+    #[debug(ignore)]
+    database_url: String,
+
+    #[command(flatten)]
+    storage: StorageArgs,
+
+    /// Seconds that unreferenced CAS objects are kept after they were last
+    /// accessed
+    #[arg(
+        long,
+        env = "COURIER_GC_RETENTION",
+        default_value_t = courier::gc::GcConfig::DEFAULT_RETENTION.as_secs()
+    )]
+    retention: u64,
+
+    /// Number of saved units or objects to read from the database at a time
+    #[arg(long, default_value_t = courier::gc::GcConfig::default().batch_size)]
+    batch_size: u64,
+
+    /// Report what would be deleted without deleting anything
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+struct SyncConfig {
+    /// URL of the Courier to copy units from
+    #[arg(long, env = "COURIER_SYNC_FROM")]
+    #[debug("{:?}", from.as_str())]
+    from: url::Url,
+
+    /// API token for the Courier to copy units from; units are copied from
+    /// the token's organization
+    #[arg(long, env = "COURIER_SYNC_FROM_TOKEN")]
+    #[debug(ignore)]
+    from_token: String,
+
+    /// URL of the Courier to copy units to
+    #[arg(long, env = "COURIER_SYNC_TO")]
+    #[debug("{:?}", to.as_str())]
+    to: url::Url,
+
+    /// API token for the Courier to copy units to; units are copied to the
+    /// token's organization
+    #[arg(long, env = "COURIER_SYNC_TO_TOKEN")]
+    #[debug(ignore)]
+    to_token: String,
+
+    /// File recording how far previous syncs got (optional, enables resuming
+    /// from where the last sync left off)
+    #[arg(long, env = "COURIER_SYNC_STATE")]
+    state: Option<PathBuf>,
+
+    /// Report what would be copied without copying anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Number of units to list from the source at a time
+    #[arg(long, default_value_t = 500)]
+    page_size: u32,
+
+    /// Number of objects to check for on the destination at a time
+    #[arg(long, default_value_t = 16)]
+    concurrency: usize,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -96,6 +448,10 @@ async fn main() -> Result<()> {
     match cli.command {
         Command::Serve(config) => serve(config).await,
         Command::Migrate(config) => migrate(config).await,
+        Command::TrainDictionaries(config) => train_dictionaries(config).await,
+        Command::Sync(config) => sync(config).await,
+        Command::VerifyStorage(config) => verify_storage(config).await,
+        Command::Gc(config) => gc(config).await,
     }
 }
 
@@ -104,7 +460,24 @@ async fn serve(config: ServeConfig) -> Result<()> {
     use oauth2::url::Url;
 
     tracing::info!("constructing application router...");
-    let storage = courier::storage::Disk::new(&config.cas_root);
+    let mut storage = config.storage.open().context("open storage")?;
+    tracing::info!(%storage, "opened storage");
+    if config.read_cache_memory_bytes > 0 || config.read_cache_dir.is_some() {
+        let cache = courier::storage::ReadCache::new(courier::storage::ReadCacheConfig {
+            memory_bytes: config.read_cache_memory_bytes,
+            disk_dir: config.read_cache_dir,
+            disk_bytes: config.read_cache_disk_bytes,
+            max_object_bytes: config.read_cache_max_object_bytes,
+        })
+        .await
+        .context("create CAS read cache")?;
+        tokio::spawn(report_read_cache_stats(cache.clone()));
+        storage = storage.with_read_cache(cache);
+    }
+    if let Some(store) = storage.object_store() {
+        tracing::info!(?store, "object storage direct transfers enabled");
+    }
+    tokio::spawn(remove_stale_uploads(storage.clone()));
     let db = courier::db::Postgres::connect(&config.database_url)
         .await
         .context("connect to database")?;
@@ -116,6 +489,20 @@ async fn serve(config: ServeConfig) -> Result<()> {
         .await
         .context("validate database migrations")?;
 
+    if let Some(interval) = config.gc_interval {
+        let gc = courier::gc::GcConfig {
+            retention: Duration::from_secs(config.gc_retention),
+            ..Default::default()
+        };
+        tracing::info!(interval, ?gc, "garbage collecting in the background");
+        tokio::spawn(collect_garbage(
+            db.clone(),
+            storage.clone(),
+            Duration::from_secs(interval),
+            gc,
+        ));
+    }
+
     // Extract CORS allowed origins from the OAuth redirect allowlist.
     // We use the origin (scheme + host + port) of each allowed redirect URI.
     let cors_origins = config
@@ -159,8 +546,59 @@ async fn serve(config: ServeConfig) -> Result<()> {
         }
     };
 
+    let registry = courier::registry::Registry::new(courier::registry::RegistryConfig {
+        index_upstream: config.registry_index_upstream,
+        download_upstream: config.registry_download_upstream,
+        index_ttl: Duration::from_secs(config.registry_index_ttl),
+        public_url: config.public_url,
+    })?;
+
+    let lanes = courier::lanes::Lanes::new(courier::lanes::LanesConfig {
+        max_in_flight: config.max_in_flight,
+        interactive_weight: config.interactive_weight,
+        ci_weight: config.ci_weight,
+    });
+    tokio::spawn(report_lane_stats(lanes.clone()));
+
+    let read_only = match config.read_only {
+        Some(reason) => {
+            tracing::warn!(?reason, "serving read-only");
+            courier::maintenance::ReadOnly::new(reason)
+        }
+        None => courier::maintenance::ReadOnly::writable(),
+    };
+
+    let storage_quota = match config.storage_quota {
+        Some(bytes) => {
+            tracing::info!(bytes, "limiting organization storage");
+            courier::quota::StorageQuota::new(bytes)
+        }
+        None => courier::quota::StorageQuota::unlimited(),
+    };
+
+    let access_tokens = match config.access_token_secret {
+        Some(secret) => {
+            let tokens = courier::access::AccessTokens::new(
+                secret,
+                Duration::from_secs(config.access_token_ttl),
+            )
+            .context("configure access tokens")?;
+            tracing::info!(ttl = ?tokens.ttl(), "issuing access tokens");
+            Some(tokens)
+        }
+        None => None,
+    };
+
     let router = courier::api::router(
-        Aero::new().with(github).with(storage).with(db),
+        Aero::new()
+            .with(storage_quota)
+            .with(access_tokens)
+            .with(read_only)
+            .with(registry)
+            .with(github)
+            .with(storage)
+            .with(db),
+        lanes,
         cors_origins,
         config.console_dir.as_deref(),
     );
@@ -179,6 +617,75 @@ async fn serve(config: ServeConfig) -> Result<()> {
     Ok(())
 }
 
+/// Periodically log the statistics of the CAS read cache.
+async fn report_read_cache_stats(cache: courier::storage::ReadCache) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        let stats = cache.stats().await;
+        tracing::info!(
+            memory_hits = stats.memory_hits,
+            disk_hits = stats.disk_hits,
+            misses = stats.misses,
+            evictions = stats.evictions,
+            memory_bytes = stats.memory_bytes,
+            disk_bytes = stats.disk_bytes,
+            "cas.cache.stats"
+        );
+    }
+}
+
+/// Periodically log the latency of each traffic class.
+async fn report_lane_stats(lanes: courier::lanes::Lanes) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        for class in clients::courier::v1::traffic::TrafficClass::ALL {
+            let stats = lanes.take_stats(class);
+            tracing::info!(
+                %class,
+                requests = stats.requests,
+                queued = stats.queued,
+                waiting = stats.waiting,
+                mean_wait_ms = stats.mean_wait_ms(),
+                max_wait_ms = stats.max_wait_ms,
+                mean_latency_ms = stats.mean_latency_ms(),
+                max_latency_ms = stats.max_latency_ms,
+                "lanes.stats"
+            );
+        }
+    }
+}
+
+/// Remove resumable uploads that clients have abandoned.
+async fn remove_stale_uploads(storage: courier::storage::Disk) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        let max_age = courier::api::v1::cas::upload::STALE_UPLOAD_AGE;
+        match storage.remove_stale_uploads(max_age).await {
+            Ok(removed) => tracing::info!(removed, "cas.upload.stale.removed"),
+            Err(err) => tracing::warn!(error = ?err, "cas.upload.stale.error"),
+        }
+    }
+}
+
+/// Periodically delete CAS objects that are no longer referenced.
+async fn collect_garbage(
+    db: courier::db::Postgres,
+    storage: courier::storage::Disk,
+    interval: Duration,
+    config: courier::gc::GcConfig,
+) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        if let Err(err) = courier::gc::collect(&db, &storage, &config).await {
+            tracing::warn!(error = ?err, "gc.error");
+        }
+    }
+}
+
 /// Wait for a shutdown signal (SIGTERM or SIGINT).
 async fn shutdown_signal() {
     use tokio::signal;
@@ -225,3 +732,122 @@ async fn migrate(config: MigrateConfig) -> Result<()> {
     tracing::info!("migrations applied successfully");
     Ok(())
 }
+
+async fn train_dictionaries(config: TrainDictionariesConfig) -> Result<()> {
+    tracing::info!("training dictionaries...");
+
+    let storage = config.storage.open().context("open storage")?;
+    let db = courier::db::Postgres::connect(&config.database_url)
+        .await
+        .context("connect to database")?;
+    db.validate_migrations()
+        .await
+        .context("validate database migrations")?;
+
+    let train = courier::dictionary::TrainConfig {
+        max_samples: config.max_samples,
+        min_samples: config.min_samples,
+        dictionary_size: config.dictionary_size,
+        ..Default::default()
+    };
+    let summary = courier::dictionary::train(&db, &storage, &train)
+        .await
+        .context("train dictionaries")?;
+
+    tracing::info!(
+        organizations = summary.organizations,
+        dictionaries = summary.dictionaries,
+        "dictionaries trained successfully"
+    );
+    Ok(())
+}
+
+async fn verify_storage(config: VerifyStorageConfig) -> Result<()> {
+    let storage = config.storage.open().context("open storage")?;
+    tracing::info!(%storage, "verifying storage...");
+
+    let summary = storage
+        .verify_all(config.quarantine)
+        .await
+        .context("verify storage")?;
+    for key in &summary.corrupt {
+        tracing::warn!(%key, "corrupt object");
+    }
+    if !summary.corrupt.is_empty() && !config.quarantine {
+        color_eyre::eyre::bail!(
+            "{} of {} objects are corrupt; run again with --quarantine to move them aside",
+            summary.corrupt.len(),
+            summary.checked
+        );
+    }
+
+    tracing::info!(
+        checked = summary.checked,
+        quarantined = summary.quarantined,
+        "storage verified"
+    );
+    Ok(())
+}
+
+async fn gc(config: GcCommandConfig) -> Result<()> {
+    let storage = config.storage.open().context("open storage")?;
+    tracing::info!(%storage, "collecting garbage...");
+
+    let db = courier::db::Postgres::connect(&config.database_url)
+        .await
+        .context("connect to database")?;
+    db.validate_migrations()
+        .await
+        .context("validate database migrations")?;
+
+    let gc = courier::gc::GcConfig {
+        retention: Duration::from_secs(config.retention),
+        batch_size: config.batch_size,
+        dry_run: config.dry_run,
+    };
+    let summary = courier::gc::collect(&db, &storage, &gc)
+        .await
+        .context("collect garbage")?;
+
+    tracing::info!(
+        dry_run = gc.dry_run,
+        units_checked = summary.units_checked,
+        objects_referenced = summary.objects_referenced,
+        objects_deleted = summary.objects_deleted,
+        orphans_deleted = summary.orphans_deleted,
+        bytes_deleted = summary.bytes_deleted,
+        "garbage collected successfully"
+    );
+    Ok(())
+}
+
+async fn sync(config: SyncConfig) -> Result<()> {
+    let from = clients::courier::v1::Client::new(config.from, config.from_token.into())
+        .context("create source client")?;
+    let to = clients::courier::v1::Client::new(config.to, config.to_token.into())
+        .context("create destination client")?;
+    from.ping().await.context("ping source")?;
+    to.ping().await.context("ping destination")?;
+
+    let sync = courier::sync::SyncConfig {
+        from,
+        to,
+        state: config.state,
+        dry_run: config.dry_run,
+        page_size: config.page_size,
+        concurrency: config.concurrency,
+    };
+    let summary = courier::sync::sync(&sync).await.context("sync")?;
+
+    tracing::info!(
+        dry_run = sync.dry_run,
+        units_listed = summary.units_listed,
+        units_present = summary.units_present,
+        units_copied = summary.units_copied,
+        units_incomplete = summary.units_incomplete,
+        objects_copied = summary.objects_copied,
+        bytes_copied = summary.bytes_copied,
+        "sync completed successfully"
+    );
+    Ok(())
+}
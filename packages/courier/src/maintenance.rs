@@ -0,0 +1,66 @@
+//! Read-only mode for maintenance.
+//!
+//! During maintenance like migrations, Courier keeps serving restores but
+//! rejects writes: saves and CAS writes answer `503 Service Unavailable` with
+//! the [`READ_ONLY_HEADER`] header and a [`CargoReadOnly`] body giving the
+//! reason, which clients treat as "skip the save" rather than as a failure.
+//!
+//! The whole instance is read-only if it's started with a reason (see
+//! [`ReadOnly`]), and an organization's admins can make their organization's
+//! cache read-only on its own. Read-only API keys (e.g. for builds of pull
+//! requests from forks) are rejected the same way, whatever the cache's mode.
+//! The instance's reason takes precedence.
+
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use clients::courier::v1::cache::{CargoReadOnly, READ_ONLY_HEADER};
+use color_eyre::Result;
+
+use crate::{auth::AuthenticatedToken, db::Postgres};
+
+/// Whether the whole instance is read-only, and why.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReadOnly {
+    reason: Option<String>,
+}
+
+impl ReadOnly {
+    /// Why writes with a read-only API key are rejected.
+    pub const READ_ONLY_KEY: &str = "this API key can only restore from the cache";
+
+    /// Make the instance read-only for the provided reason.
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: Some(reason.into()),
+        }
+    }
+
+    /// Let the instance accept writes.
+    pub fn writable() -> Self {
+        Self::default()
+    }
+
+    /// Why the organization's cache is read-only for this token, if it is:
+    /// because the instance is, because the token is a read-only API key, or
+    /// because the organization was made read-only.
+    pub async fn check(&self, db: &Postgres, auth: &AuthenticatedToken) -> Result<Option<String>> {
+        match &self.reason {
+            Some(reason) => Ok(Some(reason.clone())),
+            None if auth.read_only => Ok(Some(String::from(Self::READ_ONLY_KEY))),
+            None => db.cargo_cache_read_only(auth).await,
+        }
+    }
+}
+
+/// The response to a write that's rejected because the cache is read-only.
+pub fn rejected(reason: String) -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(READ_ONLY_HEADER, "true")],
+        Json(CargoReadOnly::read_only(reason)),
+    )
+        .into_response()
+}
@@ -0,0 +1,80 @@
+//! Per-organization storage quotas.
+//!
+//! Courier records how many compressed bytes of CAS objects each organization
+//! has access to: an object counts towards every organization with access to
+//! it, from when access is granted until garbage collection deletes the
+//! object or the organization resets its cache. Objects written before sizes
+//! were recorded don't count.
+//!
+//! Once an organization stores as much as its quota, saves and CAS writes
+//! answer `413 Payload Too Large` with the [`QUOTA_EXCEEDED_HEADER`] header and
+//! a [`CargoStorageUsage`] body, while restores are still served. The quota is
+//! checked before a write rather than enforced byte for byte, so concurrent
+//! writes can take an organization somewhat over it.
+//!
+//! The instance has a default quota (see [`StorageQuota`]), which an
+//! organization's own quota replaces if it has one.
+
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use clients::courier::v1::cache::{CargoStorageUsage, QUOTA_EXCEEDED_HEADER};
+use color_eyre::Result;
+
+use crate::{
+    auth::{AuthenticatedToken, OrgId},
+    db::Postgres,
+};
+
+/// The instance's default storage quota.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StorageQuota {
+    default: Option<u64>,
+}
+
+impl StorageQuota {
+    /// Limit each organization without its own quota to `bytes`.
+    pub fn new(bytes: u64) -> Self {
+        Self {
+            default: Some(bytes),
+        }
+    }
+
+    /// Let organizations without their own quota store without limit.
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// How much the organization stores, and its quota.
+    pub async fn usage(&self, db: &Postgres, org_id: OrgId) -> Result<CargoStorageUsage> {
+        let storage = db.organization_storage(org_id).await?.unwrap_or_default();
+        Ok(CargoStorageUsage::new(
+            storage.bytes,
+            storage.quota.or(self.default),
+        ))
+    }
+
+    /// The organization's usage if it's used up its quota, in which case
+    /// writes are rejected.
+    pub async fn check(
+        &self,
+        db: &Postgres,
+        auth: &AuthenticatedToken,
+    ) -> Result<Option<CargoStorageUsage>> {
+        let usage = self.usage(db, auth.org_id).await?;
+        Ok(usage.exceeded().then_some(usage))
+    }
+}
+
+/// The response to a write that's rejected because the organization's quota
+/// is used up.
+pub fn rejected(usage: CargoStorageUsage) -> Response {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        [(QUOTA_EXCEEDED_HEADER, "true")],
+        Json(usage),
+    )
+        .into_response()
+}
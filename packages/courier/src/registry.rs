@@ -0,0 +1,286 @@
+//! Caching proxy for the crates.io registry.
+//!
+//! Downloading crates is a significant part of a cold CI build, and crates.io
+//! is often much further away than Courier. Courier can serve as a sparse
+//! registry[^1] that Cargo uses in place of crates.io via source
+//! replacement[^2]: index files and `.crate` files are fetched from upstream on
+//! first use and served from the CAS afterwards.
+//!
+//! Registry content is public, so it's shared by every organization rather
+//! than being scoped by CAS access like build artifacts.
+//!
+//! [^1]: https://doc.rust-lang.org/cargo/reference/registry-index.html#sparse-protocol
+//! [^2]: https://doc.rust-lang.org/cargo/reference/source-replacement.html
+
+use std::{io::Cursor, sync::Arc, time::Duration};
+
+use clients::courier::v1::Key;
+use color_eyre::{
+    Report, Result,
+    eyre::{Context, eyre},
+};
+use derive_more::Debug;
+use reqwest::{StatusCode, header};
+use time::OffsetDateTime;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{db::Postgres, storage::Disk};
+
+/// Configuration for the registry proxy.
+#[derive(Clone, Debug)]
+pub struct RegistryConfig {
+    /// The root of the upstream sparse index.
+    #[debug("{:?}", index_upstream.as_str())]
+    pub index_upstream: Url,
+
+    /// The root of the upstream `.crate` file downloads; files are fetched
+    /// from `{name}/{name}-{version}.crate` relative to this URL.
+    #[debug("{:?}", download_upstream.as_str())]
+    pub download_upstream: Url,
+
+    /// How long a cached index file is served before it's revalidated with
+    /// upstream. New crate versions aren't visible through the proxy until
+    /// this has elapsed.
+    pub index_ttl: Duration,
+
+    /// The URL that clients use to reach Courier, used to tell Cargo where to
+    /// download crates from. If unset, this is inferred from the request.
+    #[debug("{:?}", public_url.as_ref().map(Url::as_str))]
+    pub public_url: Option<Url>,
+}
+
+impl RegistryConfig {
+    /// The crates.io sparse index.
+    pub const CRATES_IO_INDEX: &str = "https://index.crates.io/";
+
+    /// The crates.io `.crate` file downloads.
+    pub const CRATES_IO_DOWNLOAD: &str = "https://static.crates.io/crates/";
+
+    /// The default index TTL.
+    pub const DEFAULT_INDEX_TTL: Duration = Duration::from_secs(5 * 60);
+}
+
+impl Default for RegistryConfig {
+    fn default() -> Self {
+        Self {
+            index_upstream: Url::parse(Self::CRATES_IO_INDEX).expect("valid index URL"),
+            download_upstream: Url::parse(Self::CRATES_IO_DOWNLOAD).expect("valid download URL"),
+            index_ttl: Self::DEFAULT_INDEX_TTL,
+            public_url: None,
+        }
+    }
+}
+
+/// The registry proxy.
+#[derive(Clone, Debug)]
+pub struct Registry {
+    #[debug(skip)]
+    http: reqwest::Client,
+    config: Arc<RegistryConfig>,
+}
+
+impl Registry {
+    /// Create a new registry proxy.
+    pub fn new(mut config: RegistryConfig) -> Result<Self> {
+        // Upstream URLs are joined with relative paths, which replaces the last
+        // path segment unless the URL ends with a slash.
+        for url in [&mut config.index_upstream, &mut config.download_upstream] {
+            if !url.path().ends_with('/') {
+                url.set_path(&format!("{}/", url.path()));
+            }
+        }
+
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("courier/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .context("build http client")?;
+        Ok(Self {
+            http,
+            config: Arc::new(config),
+        })
+    }
+
+    /// The configured public URL of Courier, if any.
+    pub fn public_url(&self) -> Option<&Url> {
+        self.config.public_url.as_ref()
+    }
+
+    /// Get the CAS key of the index file at `path`, fetching it from upstream
+    /// if it isn't cached or is stale.
+    ///
+    /// Returns `None` if the file doesn't exist upstream. If upstream can't be
+    /// reached, a stale cached copy is served rather than failing the build.
+    #[tracing::instrument(name = "Registry::index_file", skip(db, cas))]
+    pub async fn index_file(&self, db: &Postgres, cas: &Disk, path: &str) -> Result<Option<Key>> {
+        let cached = db.registry_index_file(path).await?;
+        if let Some(cached) = &cached
+            && cached.fetched_at + self.config.index_ttl > OffsetDateTime::now_utc()
+        {
+            info!("registry.index.hit");
+            return Ok(Some(cached.key.clone()));
+        }
+
+        let url = self.config.index_upstream.join(path).context("build url")?;
+        let mut request = self.http.get(url);
+        if let Some(etag) = cached.as_ref().and_then(|cached| cached.etag.as_deref()) {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        let stale_key = cached.map(|cached| cached.key);
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(err) => return serve_stale(stale_key, Report::new(err)),
+        };
+        match response.status() {
+            StatusCode::NOT_MODIFIED if stale_key.is_some() => {
+                info!("registry.index.revalidated");
+                db.touch_registry_index_file(path).await?;
+                Ok(stale_key)
+            }
+            StatusCode::OK => {
+                let etag = response
+                    .headers()
+                    .get(header::ETAG)
+                    .and_then(|etag| etag.to_str().ok())
+                    .map(String::from);
+                let content = response.bytes().await.context("read index file")?;
+                let key = Key::from_buffer(&content);
+                cas.write(&key, Cursor::new(content))
+                    .await
+                    .context("store index file")?;
+                db.save_registry_index_file(path, &key, etag.as_deref())
+                    .await?;
+                info!("registry.index.fetched");
+                Ok(Some(key))
+            }
+            // crates.io serves missing index files as 404, but its CDN can
+            // also respond with 403 for paths that were never populated.
+            StatusCode::NOT_FOUND | StatusCode::FORBIDDEN | StatusCode::GONE => {
+                info!("registry.index.not_found");
+                if stale_key.is_some() {
+                    db.delete_registry_index_file(path).await?;
+                }
+                Ok(None)
+            }
+            status => serve_stale(stale_key, eyre!("unexpected upstream status: {status}")),
+        }
+    }
+
+    /// Get the CAS key of the `.crate` file for the crate version, downloading
+    /// it from upstream if it isn't cached.
+    ///
+    /// Returns `None` if the crate version doesn't exist upstream. Published
+    /// crate versions never change, so cached files are never revalidated;
+    /// Cargo verifies each file against the checksum in the index anyway.
+    #[tracing::instrument(name = "Registry::crate_file", skip(db, cas))]
+    pub async fn crate_file(
+        &self,
+        db: &Postgres,
+        cas: &Disk,
+        name: &str,
+        version: &str,
+    ) -> Result<Option<Key>> {
+        if let Some(key) = db.registry_crate(name, version).await? {
+            info!("registry.crate.hit");
+            return Ok(Some(key));
+        }
+
+        let url = self
+            .config
+            .download_upstream
+            .join(&format!("{name}/{name}-{version}.crate"))
+            .context("build url")?;
+        let response = self.http.get(url).send().await.context("request crate")?;
+        match response.status() {
+            StatusCode::OK => {
+                let content = response.bytes().await.context("read crate")?;
+                let key = Key::from_buffer(&content);
+                cas.write(&key, Cursor::new(content))
+                    .await
+                    .context("store crate")?;
+                db.save_registry_crate(name, version, &key).await?;
+                info!("registry.crate.fetched");
+                Ok(Some(key))
+            }
+            StatusCode::NOT_FOUND | StatusCode::FORBIDDEN | StatusCode::GONE => {
+                info!("registry.crate.not_found");
+                Ok(None)
+            }
+            status => Err(eyre!("unexpected upstream status: {status}")),
+        }
+    }
+}
+
+/// Serve the stale cached index file if there is one, otherwise fail with
+/// the upstream error.
+fn serve_stale(stale_key: Option<Key>, error: Report) -> Result<Option<Key>> {
+    match stale_key {
+        Some(key) => {
+            warn!(?error, "registry.index.stale");
+            Ok(Some(key))
+        }
+        None => Err(error).context("fetch index file from upstream"),
+    }
+}
+
+/// Check that a sparse index path is a plain relative path, so that it can't
+/// be used to reach anything outside the upstream index.
+pub fn is_valid_index_path(path: &str) -> bool {
+    !path.is_empty()
+        && path.split('/').all(|component| {
+            !component.is_empty()
+                && component != "."
+                && component != ".."
+                && component
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        })
+}
+
+/// Check that a crate name and version are safe to use in an upstream path.
+pub fn is_valid_crate_version(name: &str, version: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && !version.is_empty()
+        && !version.starts_with('.')
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '+')
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+
+    use super::{is_valid_crate_version, is_valid_index_path};
+
+    #[test_case("se/rd/serde", true; "crate")]
+    #[test_case("1/a", true; "short crate")]
+    #[test_case("3/s/syn", true; "three letter crate")]
+    #[test_case("config.json", true; "config")]
+    #[test_case("", false; "empty")]
+    #[test_case("se/../serde", false; "parent")]
+    #[test_case("/se/rd/serde", false; "absolute")]
+    #[test_case("se//serde", false; "empty component")]
+    #[test_case("se/rd/serde?x=1", false; "query")]
+    #[test]
+    fn index_path(path: &str, expected: bool) {
+        pretty_assert_eq!(is_valid_index_path(path), expected);
+    }
+
+    #[test_case("serde", "1.0.228", true; "release")]
+    #[test_case("windows_x86_64_gnu", "0.52.6", true; "underscores")]
+    #[test_case("foo", "1.0.0-alpha.1+build.5", true; "prerelease and build")]
+    #[test_case("foo/bar", "1.0.0", false; "slash in name")]
+    #[test_case("foo", "../1.0.0", false; "slash in version")]
+    #[test_case("foo", "..", false; "parent version")]
+    #[test_case("", "1.0.0", false; "empty name")]
+    #[test]
+    fn crate_version(name: &str, version: &str, expected: bool) {
+        pretty_assert_eq!(is_valid_crate_version(name, version), expected);
+    }
+}
@@ -1,10 +1,12 @@
 use std::io::Cursor;
 use std::path::{Path, PathBuf};
-use std::sync::LazyLock;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock};
 
 use async_compression::Level;
 use async_compression::tokio::bufread::ZstdDecoder;
 use async_compression::tokio::write::ZstdEncoder;
+use clients::courier::v1::{HashAlgorithm, KeyHasher};
 use clients::{LOCAL_BUFFER_SIZE, NETWORK_BUFFER_SIZE};
 use color_eyre::eyre::bail;
 use color_eyre::{Result, eyre::Context};
@@ -13,10 +15,17 @@ use tap::Pipe;
 use tokio::fs::{File, create_dir_all, metadata, remove_file, rename};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio_util::compat::{FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt};
-use tracing::warn;
+use tokio_util::either::Either;
+use tracing::{info, warn};
+use url::Url;
 use uuid::Uuid;
 
+pub use cache::{ReadCache, ReadCacheConfig, ReadCacheStats};
 pub use clients::courier::v1::Key;
+pub use object::{Method, ObjectStore, ObjectStoreConfig};
+
+mod cache;
+mod object;
 
 /// Implements the CAS storage interface on disk.
 ///
@@ -44,15 +53,51 @@ pub use clients::courier::v1::Key;
 ///
 /// ## Atomic writes
 ///
-/// The CAS uses write-then-rename to ensure that writes are atomic. If a file
-/// already exists, it is not written again. This is safe because the key is
-/// computed from the content of the file, so if the file already exists it must
-/// have the same content.
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
-#[debug("Disk(root = {})", self.root.display())]
-#[display("{}", root.display())]
+/// On a filesystem, the CAS uses write-then-rename to ensure that writes are
+/// atomic. In object storage, an object is only visible once its upload is
+/// complete, so objects are uploaded in place and the upload is only completed
+/// once the content is checked. If an object already exists, it is not
+/// written again. This is safe because the key is computed from the content of
+/// the object, so if the object already exists it must have the same content.
+///
+/// ## Read cache
+///
+/// Reads can be served from a [`ReadCache`] in front of the disk, attached
+/// with [`Disk::with_read_cache`]. Writes always go to the disk.
+///
+/// ## Durability
+///
+/// By default, writes are only as durable as the filesystem makes them without
+/// being asked; object storage (see below) doesn't need more. On a local
+/// filesystem, [`Disk::with_fsync`] makes each write sync the object's file and
+/// directory before it's reported as written, so that a power loss can't leave
+/// an object that's missing or truncated.
+///
+/// ## Integrity
+///
+/// Objects are checked against their key when they're written, and
+/// [`Disk::verify_all`] checks them again at rest, moving objects that don't
+/// match aside so that they're uploaded again.
+///
+/// ## Object storage
+///
+/// [`Disk::in_bucket`] stores the CAS in an object storage bucket instead of a
+/// directory, with the same layout, reading and writing objects through the
+/// bucket's API. The [`ObjectStore`] also presigns URLs so that clients can
+/// transfer objects directly to and from the bucket.
+///
+/// A bucket that's mounted as the root can also presign URLs when it's
+/// attached with [`Disk::with_object_store`], but only mounts that support
+/// renames (like `gcsfuse`, unlike `mountpoint-s3`) can be written to.
+#[derive(Clone, Debug, Display)]
+#[debug("Disk({})", self.location())]
+#[display("{}", self.location())]
 pub struct Disk {
     root: PathBuf,
+    cache: Option<ReadCache>,
+    object_store: Option<ObjectStore>,
+    in_bucket: bool,
+    fsync: bool,
 }
 
 impl Disk {
@@ -69,12 +114,83 @@ impl Disk {
     /// serving many clients at once.
     const DEFAULT_BUF_SIZE: usize = 64 * 1024;
 
+    /// The directory that resumable uploads are kept in until they complete.
+    ///
+    /// Object files are in two-character prefix directories, so this can't
+    /// collide with them.
+    const UPLOADS_DIR: &str = "uploads";
+
+    /// The directory that objects which fail verification are moved to.
+    ///
+    /// Like [`Disk::UPLOADS_DIR`], this can't collide with object files.
+    const QUARANTINE_DIR: &str = "quarantine";
+
     /// Create a new instance in the provided directory.
     ///
     /// If the directory does not already exist, it is created when the first
     /// file is written to the CAS instance.
     pub fn new(root: impl Into<PathBuf>) -> Self {
-        Self { root: root.into() }
+        Self {
+            root: root.into(),
+            cache: None,
+            object_store: None,
+            in_bucket: false,
+            fsync: false,
+        }
+    }
+
+    /// Create a new instance that stores objects in the object store's bucket.
+    pub fn in_bucket(store: ObjectStore) -> Self {
+        Self {
+            root: PathBuf::new(),
+            cache: None,
+            object_store: Some(store),
+            in_bucket: true,
+            fsync: false,
+        }
+    }
+
+    /// Serve reads through the provided cache.
+    pub fn with_read_cache(mut self, cache: ReadCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// The read cache in front of the disk, if any.
+    pub fn read_cache(&self) -> Option<&ReadCache> {
+        self.cache.as_ref()
+    }
+
+    /// Presign URLs for direct transfers with the provided object store, which
+    /// must be the bucket that's mounted as the root.
+    pub fn with_object_store(mut self, store: ObjectStore) -> Self {
+        self.object_store = Some(store);
+        self
+    }
+
+    /// The object store that holds the objects, if any.
+    pub fn object_store(&self) -> Option<&ObjectStore> {
+        self.object_store.as_ref()
+    }
+
+    /// The object store that objects are read and written through, if they're
+    /// not on the filesystem.
+    fn bucket(&self) -> Option<&ObjectStore> {
+        self.object_store.as_ref().filter(|_| self.in_bucket)
+    }
+
+    /// Where the objects are stored.
+    fn location(&self) -> String {
+        match self.bucket() {
+            Some(bucket) => bucket.to_string(),
+            None => self.root.display().to_string(),
+        }
+    }
+
+    /// Sync each written object to the disk before reporting it as written.
+    pub fn with_fsync(mut self) -> Self {
+        self.fsync = true;
+        self
     }
 
     /// Create a new instance in a temporary directory.
@@ -121,10 +237,13 @@ impl Disk {
         //
         // This also allows us to add new volumes at different levels in the
         // future if we need to do so for storage or other reasons.
+        self.root.join(Self::key_name(key))
+    }
+
+    /// The name of the key's file relative to the root.
+    fn key_name(key: &Key) -> String {
         let hex = key.to_hex();
-        let prefix1 = hex.chars().take(2).collect::<String>();
-        let prefix2 = hex.chars().skip(2).take(2).collect::<String>();
-        self.root.join(prefix1).join(prefix2).join(&hex)
+        format!("{}/{}/{hex}", &hex[..2], &hex[2..4])
     }
 
     /// Check if a blob exists in storage.
@@ -135,14 +254,19 @@ impl Disk {
     /// - Blobs are always stored by their key, and their key is derived from
     ///   their content; this means that once a blob is written there's never a
     ///   reason to write it again or otherwise modify it.
-    /// - In the current design we don't ever delete blobs. In the future we may
-    ///   do so and in that world we may no longer be able to trust "exists"
-    ///   checks, but that's not the world we're in today.
+    /// - Blobs are only deleted by garbage collection (see [`crate::gc`]), and
+    ///   only once nobody has accessed them for its retention window; checking
+    ///   for a blob through the API counts as accessing it.
     ///
     /// Returns `Ok(true)` if the key exists, `Ok(false)` if it does not exist,
     /// and `Err` if there was an error checking (e.g., permission denied).
     #[tracing::instrument(name = "Disk::exists")]
     pub async fn exists(&self, key: &Key) -> Result<bool> {
+        if let Some(bucket) = self.bucket() {
+            let meta = bucket.head(&Self::key_name(key)).await?;
+            return Ok(meta.is_some());
+        }
+
         let path = self.key_path(key);
         match metadata(&path).await {
             Ok(_) => Ok(true),
@@ -184,6 +308,11 @@ impl Disk {
     /// in contrast, reports the compressed size of the data.
     #[tracing::instrument(name = "Disk::size_compressed")]
     pub async fn size_compressed(&self, key: &Key) -> Result<Option<u64>> {
+        if let Some(bucket) = self.bucket() {
+            let meta = bucket.head(&Self::key_name(key)).await?;
+            return Ok(meta.map(|meta| meta.size));
+        }
+
         let path = self.key_path(key);
         match tokio::fs::metadata(&path).await {
             Ok(metadata) => Ok(Some(metadata.len())),
@@ -196,14 +325,18 @@ impl Disk {
     #[tracing::instrument(name = "Disk::read_size")]
     async fn read_size(&self, key: &Key) -> Result<Option<u64>> {
         let path = self.key_path(key);
-        let size_path = path.with_extension("size");
-        match tokio::fs::read(&size_path).await {
-            Ok(bytes) => match bytes.try_into().map(u64::from_be_bytes) {
-                Ok(size) => Ok(Some(size)),
-                Err(buf) => bail!("invalid big-endian u64: {buf:?}"),
+        let bytes = match self.bucket() {
+            Some(bucket) => bucket.get_bytes(&Self::size_name(key)).await?,
+            None => match tokio::fs::read(path.with_extension("size")).await {
+                Ok(bytes) => Some(bytes),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+                Err(error) => return Err(error).context(format!("read size of blob at {path:?}")),
             },
-            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
-            Err(error) => Err(error).context(format!("read size of blob at {path:?}")),
+        };
+        match bytes.map(|bytes| bytes.try_into().map(u64::from_be_bytes)) {
+            Some(Ok(size)) => Ok(Some(size)),
+            Some(Err(buf)) => bail!("invalid big-endian u64: {buf:?}"),
+            None => Ok(None),
         }
     }
 
@@ -216,6 +349,12 @@ impl Disk {
     /// If this method fails, it's logged as a warning.
     #[tracing::instrument]
     async fn write_size(&self, key: &Key, size: u64) -> Result<()> {
+        if let Some(bucket) = self.bucket() {
+            return bucket
+                .put(&Self::size_name(key), size.to_be_bytes().to_vec())
+                .await;
+        }
+
         let path = self.key_path(key);
         let size_path = path.with_extension("size");
         tokio::fs::write(&size_path, &size.to_be_bytes())
@@ -229,8 +368,17 @@ impl Disk {
     /// [`Disk::DEFAULT_BUF_SIZE`]; callers should probably not buffer further.
     #[tracing::instrument(name = "Disk::read")]
     pub async fn read(&self, key: &Key) -> Result<impl AsyncRead + Unpin + 'static> {
+        if let Some(content) = self.read_cached(key).await? {
+            return Cursor::new(content)
+                .pipe(ZstdDecoder::new)
+                .pipe(|reader| BufReader::with_capacity(Self::DEFAULT_BUF_SIZE, reader))
+                .pipe(Either::Left)
+                .pipe(Ok);
+        }
+
         self.read_inner(key)
             .await
+            .map(Either::Right)
             .with_context(|| format!("open blob file {:?}", self.key_path(key)))
     }
 
@@ -244,11 +392,57 @@ impl Disk {
     /// [`Disk::DEFAULT_BUF_SIZE`]; callers should probably not buffer further.
     #[tracing::instrument(name = "Disk::read_compressed")]
     pub async fn read_compressed(&self, key: &Key) -> Result<impl AsyncRead + Unpin + 'static> {
+        if let Some(content) = self.read_cached(key).await? {
+            return Ok(Either::Left(Cursor::new(content)));
+        }
+
         let path = self.key_path(key);
-        File::open(&path)
-            .await
-            .map(|reader| BufReader::with_capacity(Self::DEFAULT_BUF_SIZE, reader))
-            .with_context(|| format!("open blob file {:?}", self.key_path(key)))
+        match self.bucket() {
+            Some(bucket) => bucket
+                .get(&Self::key_name(key))
+                .await
+                .map(Either::Right)
+                .map(Either::Right),
+            None => File::open(&path)
+                .await
+                .map(|reader| BufReader::with_capacity(Self::DEFAULT_BUF_SIZE, reader))
+                .map(Either::Left)
+                .map(Either::Right),
+        }
+        .with_context(|| format!("open blob file {path:?}"))
+    }
+
+    /// Read the compressed content for the provided key through the read
+    /// cache, populating the cache on a miss.
+    ///
+    /// Returns `None` if there is no cache, the object doesn't exist, or the
+    /// object is too large to cache; callers should then read from the disk.
+    async fn read_cached(&self, key: &Key) -> Result<Option<Arc<[u8]>>> {
+        let Some(cache) = &self.cache else {
+            return Ok(None);
+        };
+        if let Some(content) = cache.get(key).await {
+            return Ok(Some(content));
+        }
+
+        match self.size_compressed(key).await? {
+            Some(size) if cache.admits(size) => {}
+            _ => return Ok(None),
+        }
+        let path = self.key_path(key);
+        let content = match self.bucket() {
+            Some(bucket) => match bucket.get_bytes(&Self::key_name(key)).await? {
+                Some(content) => Arc::<[u8]>::from(content),
+                None => return Ok(None),
+            },
+            None => match tokio::fs::read(&path).await {
+                Ok(content) => Arc::<[u8]>::from(content),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(err) => return Err(err).context(format!("read blob file {path:?}")),
+            },
+        };
+        cache.insert(key, content.clone()).await;
+        Ok(Some(content))
     }
 
     /// Write the content to storage for the provided key.
@@ -259,6 +453,10 @@ impl Disk {
     /// during the final rename operation.
     #[tracing::instrument(name = "Disk::write", skip(content))]
     pub async fn write(&self, key: &Key, content: impl AsyncRead + Unpin) -> Result<()> {
+        if let Some(bucket) = self.bucket() {
+            return self.write_object(bucket, key, content, false).await;
+        }
+
         let path = self.key_path(key);
 
         if let Some(parent) = path.parent() {
@@ -286,7 +484,7 @@ impl Disk {
 
         // While we're writing we also need to compute the hash of the content
         // to make sure that it actually matches the key we were provided.
-        let (hash, size) = hashed_copy(&mut content, &mut encoder)
+        let (hash, size) = hashed_copy(key.algorithm(), &mut content, &mut encoder)
             .await
             .with_context(|| format!("write content to {temp:?}"))?;
 
@@ -295,9 +493,10 @@ impl Disk {
         encoder.shutdown().await.context("flush zstd encoder")?;
         let mut file = encoder.into_inner();
         file.flush().await.context("flush file")?;
+        self.sync_file(&file).await?;
         drop(file);
 
-        if key != hash {
+        if *key != hash {
             if let Err(err) = remove_file(&temp).await {
                 warn!("failed to remove temp file {temp:?}: {err}");
             }
@@ -308,10 +507,12 @@ impl Disk {
         // If the file already exists, we can just abort: file contents never
         // change and are always named by their content hash.
         match rename(&temp, &path).await {
-            Ok(()) => self
-                .write_size(key, size)
-                .await
-                .with_context(|| format!("write size for {key:?}")),
+            Ok(()) => {
+                self.sync_parent(&path).await?;
+                self.write_size(key, size)
+                    .await
+                    .with_context(|| format!("write size for {key:?}"))
+            }
             Err(err) => {
                 if let Err(err) = remove_file(&temp).await {
                     warn!("failed to remove temp file {temp:?}: {err}");
@@ -344,6 +545,10 @@ impl Disk {
     /// during the final rename operation.
     #[tracing::instrument(name = "Disk::write_compressed", skip(content))]
     pub async fn write_compressed(&self, key: &Key, content: impl AsyncRead + Unpin) -> Result<()> {
+        if let Some(bucket) = self.bucket() {
+            return self.write_object(bucket, key, content, true).await;
+        }
+
         let path = self.key_path(key);
 
         if let Some(parent) = path.parent() {
@@ -367,16 +572,17 @@ impl Disk {
 
         // While we're writing we also need to compute the hash of the content
         // to make sure that it actually matches the key we were provided.
-        let (hash, size) = hashed_copy_compressed(&mut content, &mut file)
+        let (hash, size) = hashed_copy_compressed(key.algorithm(), &mut content, &mut file)
             .await
             .with_context(|| format!("write content to {temp:?}"))?;
 
         // Even if the hash didn't match we still need to finalize the write so
         // that we can delete the temp file before returning.
         file.flush().await.context("flush file")?;
+        self.sync_file(&file).await?;
         drop(file);
 
-        if key != hash {
+        if *key != hash {
             if let Err(err) = remove_file(&temp).await {
                 warn!("failed to remove temp file {temp:?}: {err}");
             }
@@ -387,10 +593,12 @@ impl Disk {
         // If the file already exists, we can just abort: file contents never
         // change and are always named by their content hash.
         match rename(&temp, &path).await {
-            Ok(()) => self
-                .write_size(key, size)
-                .await
-                .with_context(|| format!("write uncompressed size for {key:?}")),
+            Ok(()) => {
+                self.sync_parent(&path).await?;
+                self.write_size(key, size)
+                    .await
+                    .with_context(|| format!("write uncompressed size for {key:?}"))
+            }
             Err(err) => {
                 if let Err(err) = remove_file(&temp).await {
                     warn!("failed to remove temp file {temp:?}: {err}");
@@ -404,9 +612,507 @@ impl Disk {
         }
     }
 
+    /// Write the content to the bucket for the provided key; the content is
+    /// already compressed if `compressed` is set.
+    ///
+    /// There's no temporary object to rename: the object only becomes visible
+    /// once its upload is completed, which only happens if the content matches
+    /// the key. Concurrent writes of the same key upload the same content, so
+    /// whichever completes last wins without changing anything.
+    async fn write_object(
+        &self,
+        bucket: &ObjectStore,
+        key: &Key,
+        content: impl AsyncRead + Unpin,
+        compressed: bool,
+    ) -> Result<()> {
+        let name = Self::key_name(key);
+        let mut content = BufReader::with_capacity(Self::DEFAULT_BUF_SIZE, content);
+        let mut writer = bucket.writer(&name);
+        let copied = if compressed {
+            hashed_copy_compressed(key.algorithm(), &mut content, &mut writer).await
+        } else {
+            let mut encoder = ZstdEncoder::with_quality(&mut writer, Level::Default);
+            match hashed_copy(key.algorithm(), &mut content, &mut encoder).await {
+                Ok(copied) => encoder
+                    .shutdown()
+                    .await
+                    .context("flush zstd encoder")
+                    .map(|()| copied),
+                Err(err) => Err(err),
+            }
+        };
+
+        let size = match copied {
+            Ok((hash, size)) if hash == *key => size,
+            copied => {
+                if let Err(err) = writer.abort().await {
+                    warn!("failed to abort object {name:?}: {err:?}");
+                }
+                let (hash, _) = copied.with_context(|| format!("write content to {name:?}"))?;
+                bail!("hash mismatch: {hash:?} != {key:?}");
+            }
+        };
+        writer.complete().await?;
+        self.write_size(key, size)
+            .await
+            .with_context(|| format!("write size for {key:?}"))
+    }
+
+    /// The number of bytes received so far by the resumable upload of the
+    /// key in the namespace, or zero if there's no upload in progress.
+    #[tracing::instrument(name = "Disk::upload_offset")]
+    pub async fn upload_offset(&self, namespace: &str, key: &Key) -> Result<u64> {
+        if let Some(bucket) = self.bucket() {
+            let parts = bucket.list(&Self::upload_name(namespace, key)).await?;
+            return Ok(parts.iter().map(|part| part.size).sum());
+        }
+
+        let path = self.upload_path(namespace, key);
+        match metadata(&path).await {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(err).with_context(|| format!("read metadata of {path:?}")),
+        }
+    }
+
+    /// Append uncompressed content to the resumable upload of the key in the
+    /// namespace, starting a new upload if there's none in progress.
+    ///
+    /// The content is only appended if `offset` is the number of bytes the
+    /// upload has received so far; otherwise the client has lost track of
+    /// the upload, and is told where it actually is.
+    #[tracing::instrument(name = "Disk::append_upload", skip(content))]
+    pub async fn append_upload(
+        &self,
+        namespace: &str,
+        key: &Key,
+        offset: u64,
+        mut content: impl AsyncRead + Unpin,
+    ) -> Result<UploadAppend> {
+        if let Some(bucket) = self.bucket() {
+            // Objects can't be appended to, so each append is its own part of
+            // the upload, named for the offset that it starts at.
+            let current = self.upload_offset(namespace, key).await?;
+            if current != offset {
+                return Ok(UploadAppend::Conflict(current));
+            }
+            let mut part = bucket.writer(&Self::upload_part_name(namespace, key, offset));
+            let copied = tokio::io::copy(&mut content, &mut part).await;
+
+            // Keep whatever was received even if the connection dropped
+            // partway through, so that the client can resume after it.
+            part.complete().await?;
+            copied.with_context(|| format!("append to upload of {key:?}"))?;
+            return self
+                .upload_offset(namespace, key)
+                .await
+                .map(UploadAppend::Appended);
+        }
+
+        let path = self.upload_path(namespace, key);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)
+                .await
+                .with_context(|| format!("create parent directory {parent:?} for {path:?}"))?;
+        }
+
+        let current = self.upload_offset(namespace, key).await?;
+        if current != offset {
+            return Ok(UploadAppend::Conflict(current));
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("open upload file {path:?}"))?;
+        let copied = tokio::io::copy(&mut content, &mut file).await;
+
+        // Flush whatever was received even if the connection dropped partway
+        // through, so that the client can resume after it.
+        file.flush().await.context("flush upload file")?;
+        drop(file);
+        copied.with_context(|| format!("append to upload file {path:?}"))?;
+        self.upload_offset(namespace, key)
+            .await
+            .map(UploadAppend::Appended)
+    }
+
+    /// Write the content of the resumable upload of the key in the namespace
+    /// to storage, then discard the upload.
+    ///
+    /// Returns `false` if there's no upload in progress. Like [`Disk::write`],
+    /// this fails if the content doesn't hash to the key; the upload is
+    /// discarded in that case too, since resuming it can't fix it.
+    #[tracing::instrument(name = "Disk::complete_upload")]
+    pub async fn complete_upload(&self, namespace: &str, key: &Key) -> Result<bool> {
+        if let Some(bucket) = self.bucket() {
+            let mut parts = bucket.list(&Self::upload_name(namespace, key)).await?;
+            if parts.is_empty() {
+                return Ok(false);
+            }
+            // Part names are zero-padded offsets, so they sort in order.
+            parts.sort_by(|a, b| a.path.cmp(&b.path));
+            let content = bucket.get_all(parts.iter().map(|part| part.path.clone()));
+            let written = self.write(key, content).await;
+            for part in &parts {
+                if let Err(err) = bucket.delete(&part.path).await {
+                    warn!("failed to remove upload part {:?}: {err:?}", part.path);
+                }
+            }
+            return written.map(|()| true);
+        }
+
+        let path = self.upload_path(namespace, key);
+        let file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err).with_context(|| format!("open upload file {path:?}")),
+        };
+        let written = self.write(key, file).await;
+        if let Err(err) = remove_file(&path).await {
+            warn!("failed to remove upload file {path:?}: {err}");
+        }
+        written.map(|()| true)
+    }
+
+    /// Remove resumable uploads that haven't received content for longer than
+    /// `max_age`, returning how many were removed.
+    #[tracing::instrument(name = "Disk::remove_stale_uploads")]
+    pub async fn remove_stale_uploads(&self, max_age: std::time::Duration) -> Result<u64> {
+        if let Some(bucket) = self.bucket() {
+            // An upload is stale if its latest part is.
+            let mut uploads = std::collections::BTreeMap::<String, Vec<_>>::new();
+            for part in bucket.list(Self::UPLOADS_DIR).await? {
+                if let Some((upload, _)) = part.path.rsplit_once('/') {
+                    uploads.entry(upload.to_string()).or_default().push(part);
+                }
+            }
+            let mut removed = 0;
+            for parts in uploads.values() {
+                let modified = parts.iter().map(|part| part.modified).max();
+                if modified
+                    .is_some_and(|modified| modified.elapsed().is_ok_and(|age| age > max_age))
+                {
+                    for part in parts {
+                        bucket.delete(&part.path).await?;
+                    }
+                    removed += 1;
+                }
+            }
+            return Ok(removed);
+        }
+
+        let root = self.root.join(Self::UPLOADS_DIR);
+        let mut namespaces = match tokio::fs::read_dir(&root).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err).with_context(|| format!("read directory {root:?}")),
+        };
+
+        let mut removed = 0;
+        while let Some(namespace) = namespaces.next_entry().await? {
+            let mut uploads = tokio::fs::read_dir(namespace.path()).await?;
+            while let Some(upload) = uploads.next_entry().await? {
+                let modified = upload.metadata().await?.modified()?;
+                if modified.elapsed().is_ok_and(|age| age > max_age) {
+                    remove_file(upload.path()).await?;
+                    removed += 1;
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Check that the stored content of the key still hashes to the key,
+    /// returning `false` if it doesn't or can't be decompressed.
+    ///
+    /// This reads from the disk, bypassing the read cache.
+    #[tracing::instrument(name = "Disk::verify")]
+    pub async fn verify(&self, key: &Key) -> Result<bool> {
+        let content = self
+            .read_inner(key)
+            .await
+            .with_context(|| format!("open blob file {:?}", self.key_path(key)))?;
+        match hashed_copy(key.algorithm(), content, tokio::io::sink()).await {
+            Ok((hash, _)) => Ok(hash == *key),
+            Err(err) => {
+                warn!(%key, ?err, "storage.verify.unreadable");
+                Ok(false)
+            }
+        }
+    }
+
+    /// Verify every object in storage (see [`Disk::verify`]).
+    ///
+    /// If `quarantine` is set, objects that fail verification are moved out of
+    /// the CAS into the quarantine directory, so that they no longer exist
+    /// and clients upload them again; otherwise they're only reported.
+    #[tracing::instrument(name = "Disk::verify_all")]
+    pub async fn verify_all(&self, quarantine: bool) -> Result<VerifySummary> {
+        let mut summary = VerifySummary::default();
+        for key in self.list().await? {
+            summary.checked += 1;
+            if self.verify(&key).await? {
+                continue;
+            }
+            warn!(%key, "storage.verify.corrupt");
+            if quarantine {
+                self.quarantine(&key).await?;
+                summary.quarantined += 1;
+            }
+            summary.corrupt.push(key);
+        }
+        info!(
+            checked = summary.checked,
+            corrupt = summary.corrupt.len(),
+            quarantined = summary.quarantined,
+            "storage.verify"
+        );
+        Ok(summary)
+    }
+
+    /// List the keys of every object in storage.
+    pub async fn list(&self) -> Result<Vec<Key>> {
+        let is_prefix = |name: &str| name.len() == 2 && hex::decode(name).is_ok();
+        let mut keys = Vec::new();
+        if let Some(bucket) = self.bucket() {
+            for object in bucket.list("").await? {
+                // Objects are named `ab/cd/{key}`; anything else, like size
+                // files, uploads and quarantined objects, isn't a key.
+                let mut parts = object.path.split('/');
+                let name = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                    (Some(prefix1), Some(prefix2), Some(name), None)
+                        if is_prefix(prefix1) && is_prefix(prefix2) && !name.contains('.') =>
+                    {
+                        name
+                    }
+                    _ => continue,
+                };
+                match Key::from_hex(name) {
+                    Ok(key) => keys.push(key),
+                    Err(err) => warn!(?name, ?err, "storage.list.invalid_name"),
+                }
+            }
+            return Ok(keys);
+        }
+
+        let mut prefixes1 = match tokio::fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(keys),
+            Err(err) => return Err(err).with_context(|| format!("read directory {:?}", self.root)),
+        };
+        while let Some(prefix1) = prefixes1.next_entry().await? {
+            if !prefix1.file_name().to_str().is_some_and(is_prefix) {
+                continue;
+            }
+            let mut prefixes2 = tokio::fs::read_dir(prefix1.path()).await?;
+            while let Some(prefix2) = prefixes2.next_entry().await? {
+                if !prefix2.file_name().to_str().is_some_and(is_prefix) {
+                    continue;
+                }
+                let mut objects = tokio::fs::read_dir(prefix2.path()).await?;
+                while let Some(object) = objects.next_entry().await? {
+                    // Size files and temporary files have extensions, which
+                    // keys never do.
+                    let name = object.file_name();
+                    let Some(name) = name.to_str().filter(|name| !name.contains('.')) else {
+                        continue;
+                    };
+                    match Key::from_hex(name) {
+                        Ok(key) => keys.push(key),
+                        Err(err) => warn!(?name, ?err, "storage.list.invalid_name"),
+                    }
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    /// When the key's object was last modified, if it exists.
+    #[tracing::instrument(name = "Disk::modified")]
+    pub async fn modified(&self, key: &Key) -> Result<Option<std::time::SystemTime>> {
+        if let Some(bucket) = self.bucket() {
+            let meta = bucket.head(&Self::key_name(key)).await?;
+            return Ok(meta.map(|meta| meta.modified));
+        }
+
+        let path = self.key_path(key);
+        match metadata(&path).await {
+            Ok(metadata) => metadata
+                .modified()
+                .with_context(|| format!("read modification time of {path:?}"))
+                .map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).with_context(|| format!("read metadata of {path:?}")),
+        }
+    }
+
+    /// Delete the key's object, returning its compressed size if it existed.
+    ///
+    /// Objects are only deleted by garbage collection; the read cache isn't
+    /// updated, so callers must make sure the object is no longer accessible
+    /// through the database first.
+    #[tracing::instrument(name = "Disk::remove")]
+    pub async fn remove(&self, key: &Key) -> Result<Option<u64>> {
+        if let Some(bucket) = self.bucket() {
+            let name = Self::key_name(key);
+            let Some(meta) = bucket.head(&name).await? else {
+                return Ok(None);
+            };
+            if !bucket.delete(&name).await? {
+                return Ok(None);
+            }
+            if let Err(err) = bucket.delete(&Self::size_name(key)).await {
+                warn!(%key, ?err, "failed to remove size file of deleted object");
+            }
+            return Ok(Some(meta.size));
+        }
+
+        let path = self.key_path(key);
+        let size = match metadata(&path).await {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).with_context(|| format!("read metadata of {path:?}")),
+        };
+        match remove_file(&path).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).with_context(|| format!("remove {path:?}")),
+        }
+        match remove_file(path.with_extension("size")).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => warn!(%key, ?err, "failed to remove size file of deleted object"),
+        }
+        self.sync_parent(&path).await?;
+        Ok(Some(size))
+    }
+
+    /// Move the key's object into the quarantine directory.
+    async fn quarantine(&self, key: &Key) -> Result<()> {
+        if let Some(bucket) = self.bucket() {
+            let name = Self::key_name(key);
+            let target = format!("{}/{}", Self::QUARANTINE_DIR, key.to_hex());
+            bucket.copy(&name, &target).await?;
+            bucket.delete(&name).await?;
+            if let Err(err) = bucket.delete(&Self::size_name(key)).await {
+                warn!(%key, ?err, "failed to remove size file of quarantined object");
+            }
+            return Ok(());
+        }
+
+        let path = self.key_path(key);
+        let dir = self.root.join(Self::QUARANTINE_DIR);
+        create_dir_all(&dir)
+            .await
+            .with_context(|| format!("create quarantine directory {dir:?}"))?;
+        let target = dir.join(key.to_hex());
+        rename(&path, &target)
+            .await
+            .with_context(|| format!("rename {path:?} to {target:?}"))?;
+        match remove_file(path.with_extension("size")).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => warn!(%key, ?err, "failed to remove size file of quarantined object"),
+        }
+        self.sync_parent(&path).await
+    }
+
+    /// Sync the file's content to the disk, if writes are synced.
+    async fn sync_file(&self, file: &File) -> Result<()> {
+        if self.fsync {
+            file.sync_all().await.context("sync file")?;
+        }
+        Ok(())
+    }
+
+    /// Sync the directory containing the path to the disk, if writes are
+    /// synced, so that a file renamed into it is durable.
+    async fn sync_parent(&self, path: &Path) -> Result<()> {
+        if !self.fsync {
+            return Ok(());
+        }
+        let Some(parent) = path.parent() else {
+            return Ok(());
+        };
+        // Directories can only be opened as files to sync them on Unix; other
+        // platforms make renames durable on their own.
+        #[cfg(unix)]
+        {
+            let dir = File::open(parent)
+                .await
+                .with_context(|| format!("open directory {parent:?}"))?;
+            dir.sync_all()
+                .await
+                .with_context(|| format!("sync directory {parent:?}"))?;
+        }
+        Ok(())
+    }
+
+    /// The path of the resumable upload of the key in the namespace.
+    fn upload_path(&self, namespace: &str, key: &Key) -> PathBuf {
+        self.root.join(Self::upload_name(namespace, key))
+    }
+
+    /// The name of the resumable upload's file relative to the root.
+    ///
+    /// In a bucket, this is the directory of the upload's parts instead.
+    fn upload_name(namespace: &str, key: &Key) -> String {
+        format!("{}/{namespace}/{}", Self::UPLOADS_DIR, key.to_hex())
+    }
+
+    /// The name of the part of the resumable upload that starts at the offset,
+    /// relative to the root of a bucket.
+    fn upload_part_name(namespace: &str, key: &Key, offset: u64) -> String {
+        format!("{}/{offset:020}", Self::upload_name(namespace, key))
+    }
+
+    /// The name of the key's size file relative to the root.
+    fn size_name(key: &Key) -> String {
+        format!("{}.size", Self::key_name(key))
+    }
+
+    /// Presign a URL to read the key's compressed content directly from
+    /// object storage.
+    ///
+    /// Returns `None` if there's no object store; callers must check that the
+    /// key exists.
+    pub fn presign_read(&self, key: &Key) -> Result<Option<Url>> {
+        self.object_store
+            .as_ref()
+            .map(|store| store.presign(Method::Get, &Self::key_name(key)))
+            .transpose()
+    }
+
+    /// Presign a URL to write the key's uncompressed content directly to
+    /// object storage, as the resumable upload of the key in the namespace.
+    ///
+    /// The upload is completed with [`Disk::complete_upload`] once the client
+    /// has written it. Returns `None` if there's no object store.
+    pub fn presign_upload(&self, namespace: &str, key: &Key) -> Result<Option<Url>> {
+        // In a bucket, the client writes the first part of the upload.
+        let name = match self.in_bucket {
+            true => Self::upload_part_name(namespace, key, 0),
+            false => Self::upload_name(namespace, key),
+        };
+        self.object_store
+            .as_ref()
+            .map(|store| store.presign(Method::Put, &name))
+            .transpose()
+    }
+
     /// Read and buffer the entire content from storage.
+    ///
+    /// This bypasses the read cache, so that [`Disk::ping`] actually reads
+    /// from the disk.
     async fn read_buffered(&self, key: &Key) -> Result<Vec<u8>> {
-        let mut content = self.read(key).await?;
+        let mut content = self
+            .read_inner(key)
+            .await
+            .with_context(|| format!("open blob file {:?}", self.key_path(key)))?;
         let mut buffer = Vec::new();
         tokio::io::copy(&mut content, &mut buffer)
             .await
@@ -424,12 +1130,115 @@ impl Disk {
         &self,
         key: &Key,
     ) -> Result<impl AsyncRead + Unpin + 'static, std::io::Error> {
-        let path = self.key_path(key);
-        File::open(&path)
-            .await
-            .map(BufReader::new)
-            .map(ZstdDecoder::new)
-            .map(|reader| BufReader::with_capacity(Self::DEFAULT_BUF_SIZE, reader))
+        let content = match self.bucket() {
+            Some(bucket) => bucket.get(&Self::key_name(key)).await.map(Either::Right)?,
+            None => File::open(self.key_path(key))
+                .await
+                .map(BufReader::new)
+                .map(Either::Left)?,
+        };
+        Ok(BufReader::with_capacity(
+            Self::DEFAULT_BUF_SIZE,
+            ZstdDecoder::new(content),
+        ))
+    }
+}
+
+/// The outcome of appending to a resumable upload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UploadAppend {
+    /// The content was appended; the upload has now received this many bytes.
+    Appended(u64),
+
+    /// The content wasn't appended because the upload has received this many
+    /// bytes, not the number the client expected.
+    Conflict(u64),
+}
+
+/// The outcome of [`Disk::verify_all`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifySummary {
+    /// How many objects were checked.
+    pub checked: u64,
+
+    /// The objects that failed verification.
+    pub corrupt: Vec<Key>,
+
+    /// How many of the corrupt objects were moved into quarantine.
+    pub quarantined: u64,
+}
+
+/// Where Courier stores CAS objects, parsed from a `<backend>:<location>`
+/// specification.
+///
+/// The backends are:
+/// - `fs:<path>`: a directory on a local filesystem, whose writes are synced
+///   to the disk.
+/// - `s3:<bucket>[/<prefix>]`: a prefix in an S3-compatible bucket, accessed
+///   through its API (see [`ObjectStore`]).
+#[derive(Clone, Debug, Display, PartialEq, Eq)]
+pub enum StorageConfig {
+    /// A directory on a local filesystem.
+    #[display("fs:{}", _0.display())]
+    Filesystem(PathBuf),
+
+    /// A prefix in an S3-compatible bucket.
+    #[display("s3:{bucket}{}", prefix.as_ref().map(|prefix| format!("/{prefix}")).unwrap_or_default())]
+    Bucket {
+        bucket: String,
+        prefix: Option<String>,
+    },
+}
+
+impl StorageConfig {
+    /// Open the storage.
+    ///
+    /// Buckets are accessed with the endpoint, region and credentials of the
+    /// object store configuration, which they require; its bucket and prefix
+    /// are replaced with the storage's.
+    pub fn open(&self, object_store: Option<&ObjectStoreConfig>) -> Result<Disk> {
+        match self {
+            StorageConfig::Filesystem(root) => Ok(Disk::new(root).with_fsync()),
+            StorageConfig::Bucket { bucket, prefix } => {
+                let Some(config) = object_store else {
+                    bail!("storage in {self} needs an object storage endpoint and credentials");
+                };
+                let config = ObjectStoreConfig {
+                    bucket: bucket.clone(),
+                    prefix: prefix.clone(),
+                    ..config.clone()
+                };
+                ObjectStore::new(config).map(Disk::in_bucket)
+            }
+        }
+    }
+}
+
+impl FromStr for StorageConfig {
+    type Err = color_eyre::Report;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let Some((backend, location)) = spec.split_once(':') else {
+            bail!("storage must be given as `<backend>:<location>`, e.g. `fs:/var/lib/courier`");
+        };
+        match backend {
+            "fs" if location.is_empty() => bail!("filesystem storage needs a path"),
+            "fs" => Ok(StorageConfig::Filesystem(PathBuf::from(location))),
+            "s3" => match location.trim_matches('/') {
+                "" => bail!("bucket storage needs a bucket"),
+                location => Ok(match location.split_once('/') {
+                    Some((bucket, prefix)) => StorageConfig::Bucket {
+                        bucket: bucket.to_string(),
+                        prefix: Some(prefix.to_string()),
+                    },
+                    None => StorageConfig::Bucket {
+                        bucket: location.to_string(),
+                        prefix: None,
+                    },
+                }),
+            },
+            other => bail!("unsupported storage backend {other:?}; supported backends: fs, s3"),
+        }
     }
 }
 
@@ -457,13 +1266,14 @@ fn temp_path(target: &Path) -> PathBuf {
 }
 
 /// Copy the content from the source reader into the target writer while
-/// computing the hash of the copied content.
+/// computing the hash of the copied content with the algorithm.
 ///
-/// Returns the hash of the content and the number of bytes copied.
+/// Returns the key of the content and the number of bytes copied.
 async fn hashed_copy(
+    algorithm: HashAlgorithm,
     mut source: impl AsyncRead + Unpin,
     mut target: impl AsyncWrite + Unpin,
-) -> Result<(blake3::Hash, u64)> {
+) -> Result<(Key, u64)> {
     // We set the buffer size to this value because it's called out by the
     // `blake3` docs on the `update_reader` method:
     // https://docs.rs/blake3/1.8.2/blake3/struct.Hasher.html#method.update_reader
@@ -473,7 +1283,7 @@ async fn hashed_copy(
     // the runtime, and the Blake3 docs imply that it won't benefit from a
     // buffer larger than 16KB.
     let mut buffer = vec![0; 16 * 1024];
-    let mut hasher = blake3::Hasher::new();
+    let mut hasher = KeyHasher::new(algorithm);
     let mut copied = 0;
     loop {
         let n = source.read(&mut buffer).await.context("read source")?;
@@ -497,7 +1307,7 @@ async fn hashed_copy(
 /// The decompressed content is only used for calculating the hash; the
 /// compressed content is what's written to the destination.
 ///
-/// Returns the hash of the _uncompressed_ content and the number of
+/// Returns the key of the _uncompressed_ content and the number of
 /// _uncompressed_ bytes copied. The intention of this is to enable the
 /// compressed and uncompressed disk APIs to smoothly interoperate: for example
 /// [`Disk::write_compressed`] needs to know the uncompressed size so that it
@@ -505,9 +1315,10 @@ async fn hashed_copy(
 /// [`Disk::size_compressed`] doesn't need to know the size ahead of time, as it
 /// can just check the metadata of the actual file on disk.
 async fn hashed_copy_compressed(
+    algorithm: HashAlgorithm,
     mut source: impl AsyncRead + Unpin,
     mut target: impl AsyncWrite + Unpin,
-) -> Result<(blake3::Hash, u64)> {
+) -> Result<(Key, u64)> {
     // We set the buffer size to this value because it's called out by the
     // `blake3` docs on the `update_reader` method:
     // https://docs.rs/blake3/1.8.2/blake3/struct.Hasher.html#method.update_reader
@@ -529,13 +1340,13 @@ async fn hashed_copy_compressed(
         Ok(())
     };
 
-    let hash = async move || -> Result<(blake3::Hash, u64)> {
+    let hash = async move || -> Result<(Key, u64)> {
         let mut tee = tee_reader
             .compat()
             .pipe(BufReader::new)
             .pipe(ZstdDecoder::new);
         let mut buffer = vec![0; LOCAL_BUFFER_SIZE];
-        let mut hasher = blake3::Hasher::new();
+        let mut hasher = KeyHasher::new(algorithm);
         let mut copied = 0;
         loop {
             let n = tee.read(&mut buffer).await.context("read tee")?;
@@ -552,3 +1363,239 @@ async fn hashed_copy_compressed(
     let (metrics, _) = tokio::try_join!(hash(), copy())?;
     Ok(metrics)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use object_store::memory::InMemory;
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use tokio::io::AsyncReadExt;
+    use url::Url;
+
+    use super::{Disk, Key, ObjectStore, ObjectStoreConfig, StorageConfig, UploadAppend};
+
+    /// A disk that stores objects in an in-memory bucket.
+    fn in_memory_bucket() -> Disk {
+        let config = ObjectStoreConfig {
+            endpoint: Url::parse("http://localhost:9000").unwrap(),
+            bucket: String::from("courier"),
+            prefix: Some(String::from("cas")),
+            region: String::from("us-east-1"),
+            access_key_id: String::from("courier"),
+            secret_access_key: String::from("secret"),
+            url_ttl: ObjectStoreConfig::DEFAULT_URL_TTL,
+            min_read_bytes: ObjectStoreConfig::DEFAULT_MIN_READ_BYTES,
+        };
+        Disk::in_bucket(ObjectStore::with_client(config, Arc::new(InMemory::new())))
+    }
+
+    async fn read_all(disk: &Disk, key: &Key) -> Vec<u8> {
+        let mut content = Vec::new();
+        disk.read(key)
+            .await
+            .unwrap()
+            .read_to_end(&mut content)
+            .await
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn parse_storage_config() {
+        let config = "fs:/var/lib/courier".parse::<StorageConfig>().unwrap();
+        pretty_assert_eq!(
+            config,
+            StorageConfig::Filesystem(PathBuf::from("/var/lib/courier"))
+        );
+        pretty_assert_eq!(config.to_string(), "fs:/var/lib/courier");
+
+        assert!("fs:".parse::<StorageConfig>().is_err());
+        assert!("/var/lib/courier".parse::<StorageConfig>().is_err());
+        assert!("s3:".parse::<StorageConfig>().is_err());
+        assert!("gcs:bucket".parse::<StorageConfig>().is_err());
+    }
+
+    #[test]
+    fn parse_bucket_storage_config() {
+        let config = "s3:hurry-cas".parse::<StorageConfig>().unwrap();
+        pretty_assert_eq!(
+            config,
+            StorageConfig::Bucket {
+                bucket: String::from("hurry-cas"),
+                prefix: None,
+            }
+        );
+        pretty_assert_eq!(config.to_string(), "s3:hurry-cas");
+
+        let config = "s3:hurry-cas/courier/cas/"
+            .parse::<StorageConfig>()
+            .unwrap();
+        pretty_assert_eq!(
+            config,
+            StorageConfig::Bucket {
+                bucket: String::from("hurry-cas"),
+                prefix: Some(String::from("courier/cas")),
+            }
+        );
+        pretty_assert_eq!(config.to_string(), "s3:hurry-cas/courier/cas");
+        assert!(config.open(None).is_err());
+    }
+
+    #[tokio::test]
+    async fn bucket_reads_and_writes_objects() {
+        let disk = in_memory_bucket();
+        pretty_assert_eq!(disk.to_string(), "s3:courier/cas");
+        let key = Key::from_buffer(b"content");
+        assert!(!disk.exists(&key).await.unwrap());
+
+        disk.write_buffered(&key, b"content").await.unwrap();
+        assert!(disk.exists(&key).await.unwrap());
+        pretty_assert_eq!(disk.size(&key).await.unwrap(), Some(7));
+        pretty_assert_eq!(read_all(&disk, &key).await, b"content");
+
+        // Compressed content written as-is reads back the same.
+        let mut compressed = Vec::new();
+        disk.read_compressed(&key)
+            .await
+            .unwrap()
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+        pretty_assert_eq!(
+            disk.size_compressed(&key).await.unwrap(),
+            Some(compressed.len() as u64)
+        );
+        let other = Key::from_buffer(b"other");
+        let other_compressed = zstd::bulk::compress(b"other", 3).unwrap();
+        disk.write_compressed(&other, other_compressed.as_slice())
+            .await
+            .unwrap();
+        pretty_assert_eq!(read_all(&disk, &other).await, b"other");
+        pretty_assert_eq!(disk.size(&other).await.unwrap(), Some(5));
+
+        let mut keys = disk.list().await.unwrap();
+        keys.sort_by_key(|key| key.to_hex());
+        let mut expected = vec![key.clone(), other.clone()];
+        expected.sort_by_key(|key| key.to_hex());
+        pretty_assert_eq!(keys, expected);
+
+        pretty_assert_eq!(
+            disk.remove(&key).await.unwrap(),
+            Some(compressed.len() as u64)
+        );
+        assert!(!disk.exists(&key).await.unwrap());
+        pretty_assert_eq!(disk.remove(&key).await.unwrap(), None);
+        pretty_assert_eq!(disk.list().await.unwrap(), vec![other]);
+    }
+
+    #[tokio::test]
+    async fn bucket_discards_mismatched_content() {
+        let disk = in_memory_bucket();
+        let key = Key::from_buffer(b"content");
+        assert!(disk.write_buffered(&key, b"tampered").await.is_err());
+        assert!(!disk.exists(&key).await.unwrap());
+        pretty_assert_eq!(disk.list().await.unwrap(), Vec::<Key>::new());
+    }
+
+    #[tokio::test]
+    async fn bucket_resumes_uploads() {
+        let disk = in_memory_bucket();
+        let key = Key::from_buffer(b"resumable content");
+        pretty_assert_eq!(disk.upload_offset("1", &key).await.unwrap(), 0);
+
+        let appended = disk
+            .append_upload("1", &key, 0, b"resumable ".as_slice())
+            .await
+            .unwrap();
+        pretty_assert_eq!(appended, UploadAppend::Appended(10));
+        let conflict = disk
+            .append_upload("1", &key, 0, b"content".as_slice())
+            .await
+            .unwrap();
+        pretty_assert_eq!(conflict, UploadAppend::Conflict(10));
+        let appended = disk
+            .append_upload("1", &key, 10, b"content".as_slice())
+            .await
+            .unwrap();
+        pretty_assert_eq!(appended, UploadAppend::Appended(17));
+
+        assert!(disk.complete_upload("1", &key).await.unwrap());
+        pretty_assert_eq!(read_all(&disk, &key).await, b"resumable content");
+        pretty_assert_eq!(disk.upload_offset("1", &key).await.unwrap(), 0);
+        assert!(!disk.complete_upload("1", &key).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn bucket_removes_stale_uploads() {
+        let disk = in_memory_bucket();
+        let key = Key::from_buffer(b"content");
+        disk.append_upload("1", &key, 0, b"cont".as_slice())
+            .await
+            .unwrap();
+        disk.append_upload("1", &key, 4, b"ent".as_slice())
+            .await
+            .unwrap();
+
+        let removed = disk
+            .remove_stale_uploads(std::time::Duration::from_secs(60))
+            .await
+            .unwrap();
+        pretty_assert_eq!(removed, 0);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let removed = disk
+            .remove_stale_uploads(std::time::Duration::ZERO)
+            .await
+            .unwrap();
+        pretty_assert_eq!(removed, 1);
+        pretty_assert_eq!(disk.upload_offset("1", &key).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn bucket_quarantines_corrupt_objects() {
+        let disk = in_memory_bucket();
+        let intact = Key::from_buffer(b"intact");
+        let corrupt = Key::from_buffer(b"corrupt");
+        disk.write_buffered(&intact, b"intact").await.unwrap();
+
+        // Store valid zstd of other content under the key.
+        let bucket = disk.bucket().unwrap();
+        let other = zstd::bulk::compress(b"tampered", 3).unwrap();
+        bucket.put(&Disk::key_name(&corrupt), other).await.unwrap();
+
+        let summary = disk.verify_all(true).await.unwrap();
+        pretty_assert_eq!(summary.checked, 2);
+        pretty_assert_eq!(summary.corrupt, vec![corrupt.clone()]);
+        pretty_assert_eq!(summary.quarantined, 1);
+        assert!(!disk.exists(&corrupt).await.unwrap());
+        assert!(disk.verify(&intact).await.unwrap());
+        pretty_assert_eq!(disk.list().await.unwrap(), vec![intact]);
+    }
+
+    #[tokio::test]
+    async fn verify_quarantines_corrupt_objects() {
+        let (disk, _temp) = Disk::new_temp().await.unwrap();
+        let disk = disk.with_fsync();
+        let intact = Key::from_buffer(b"intact");
+        let corrupt = Key::from_buffer(b"corrupt");
+        disk.write_buffered(&intact, b"intact").await.unwrap();
+        disk.write_buffered(&corrupt, b"corrupt").await.unwrap();
+
+        // Replace the object with valid zstd of other content.
+        let other = zstd::bulk::compress(b"tampered", 3).unwrap();
+        tokio::fs::write(disk.key_path(&corrupt), other)
+            .await
+            .unwrap();
+
+        let summary = disk.verify_all(false).await.unwrap();
+        pretty_assert_eq!(summary.checked, 2);
+        pretty_assert_eq!(summary.corrupt, vec![corrupt.clone()]);
+        assert!(disk.exists(&corrupt).await.unwrap());
+
+        let summary = disk.verify_all(true).await.unwrap();
+        pretty_assert_eq!(summary.quarantined, 1);
+        assert!(!disk.exists(&corrupt).await.unwrap());
+        assert!(disk.verify(&intact).await.unwrap());
+    }
+}
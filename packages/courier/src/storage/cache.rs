@@ -0,0 +1,494 @@
+//! Read cache in front of CAS storage.
+//!
+//! The CAS is mounted on a network file system, and the same popular objects
+//! (e.g. the rlibs of `serde` or `syn`) are read over and over as different
+//! builds restore them. The read cache keeps recently read objects closer to
+//! the server: a memory tier first, and optionally a local disk tier behind it
+//! for objects that were evicted from memory. Each tier is a separate LRU
+//! bounded by its total size in bytes.
+//!
+//! Objects are cached in their compressed form, which is how they're stored,
+//! so a single cached copy serves both compressed and uncompressed reads.
+//!
+//! Since objects are immutable and named by their content, cached objects
+//! never need to be invalidated; they're only ever evicted.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use color_eyre::{Result, eyre::Context};
+use derive_more::Debug;
+use tokio::fs::{create_dir_all, read, remove_dir_all, remove_file, rename, write};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use super::{Disk, Key, temp_path};
+
+/// Configuration for a [`ReadCache`].
+#[derive(Clone, Debug)]
+pub struct ReadCacheConfig {
+    /// The maximum total size of the objects in the memory tier, in bytes.
+    pub memory_bytes: u64,
+
+    /// The directory of the disk tier; the disk tier is disabled if unset.
+    ///
+    /// This should be on local storage: there's no point in caching objects
+    /// on the same network file system as the CAS itself.
+    pub disk_dir: Option<PathBuf>,
+
+    /// The maximum total size of the objects in the disk tier, in bytes.
+    pub disk_bytes: u64,
+
+    /// The maximum compressed size of an object to cache, in bytes. Larger
+    /// objects are always streamed from storage so that a few huge objects
+    /// can't evict everything else.
+    pub max_object_bytes: u64,
+}
+
+impl ReadCacheConfig {
+    /// The default size of the memory tier.
+    pub const DEFAULT_MEMORY_BYTES: u64 = 512 * 1024 * 1024;
+
+    /// The default size of the disk tier.
+    pub const DEFAULT_DISK_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+
+    /// The default maximum size of a cached object.
+    pub const DEFAULT_MAX_OBJECT_BYTES: u64 = 16 * 1024 * 1024;
+}
+
+impl Default for ReadCacheConfig {
+    fn default() -> Self {
+        Self {
+            memory_bytes: Self::DEFAULT_MEMORY_BYTES,
+            disk_dir: None,
+            disk_bytes: Self::DEFAULT_DISK_BYTES,
+            max_object_bytes: Self::DEFAULT_MAX_OBJECT_BYTES,
+        }
+    }
+}
+
+/// Caches the compressed content of recently read CAS objects.
+///
+/// The cache is cheap to clone; clones share the same cached objects.
+#[derive(Clone, Debug)]
+pub struct ReadCache {
+    max_object_bytes: u64,
+    #[debug(skip)]
+    memory: Arc<Mutex<Lru<Arc<[u8]>>>>,
+    #[debug(skip)]
+    disk: Option<Arc<DiskTier>>,
+    #[debug(skip)]
+    stats: Arc<Counters>,
+}
+
+impl ReadCache {
+    /// Create a new cache.
+    ///
+    /// The disk tier's index is only kept in memory, so anything already in
+    /// its directory is removed.
+    pub async fn new(config: ReadCacheConfig) -> Result<Self> {
+        let disk = match config.disk_dir {
+            Some(dir) => {
+                match remove_dir_all(&dir).await {
+                    Ok(()) => {}
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err).context(format!("clear cache directory {dir:?}")),
+                }
+                create_dir_all(&dir)
+                    .await
+                    .with_context(|| format!("create cache directory {dir:?}"))?;
+                Some(Arc::new(DiskTier {
+                    disk: Disk::new(dir),
+                    index: Mutex::new(Lru::new(config.disk_bytes)),
+                }))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            max_object_bytes: config.max_object_bytes,
+            memory: Arc::new(Mutex::new(Lru::new(config.memory_bytes))),
+            disk,
+            stats: Arc::default(),
+        })
+    }
+
+    /// Whether an object with the provided compressed size is cached.
+    pub fn admits(&self, size: u64) -> bool {
+        size <= self.max_object_bytes
+    }
+
+    /// Get the compressed content of the object from the cache.
+    ///
+    /// Objects found in the disk tier are promoted to the memory tier.
+    #[tracing::instrument(name = "ReadCache::get", skip(self))]
+    pub async fn get(&self, key: &Key) -> Option<Arc<[u8]>> {
+        if let Some(content) = self.memory.lock().await.get(key) {
+            self.stats.memory_hits.fetch_add(1, Ordering::Relaxed);
+            debug!(tier = "memory", "cas.cache.hit");
+            return Some(content);
+        }
+
+        if let Some(tier) = &self.disk {
+            let indexed = tier.index.lock().await.get(key).is_some();
+            if indexed {
+                match read(tier.disk.key_path(key)).await {
+                    Ok(content) => {
+                        self.stats.disk_hits.fetch_add(1, Ordering::Relaxed);
+                        debug!(tier = "disk", "cas.cache.hit");
+                        let content = Arc::<[u8]>::from(content);
+                        self.insert_memory(key, &content).await;
+                        return Some(content);
+                    }
+                    Err(err) => {
+                        // The cache is best-effort: if the file can't be read
+                        // we forget about it and read from storage instead.
+                        warn!(?err, "cas.cache.disk_read_error");
+                        tier.index.lock().await.remove(key);
+                    }
+                }
+            }
+        }
+
+        self.stats.misses.fetch_add(1, Ordering::Relaxed);
+        debug!("cas.cache.miss");
+        None
+    }
+
+    /// Add the compressed content of the object to the cache.
+    ///
+    /// Objects evicted from the memory tier move to the disk tier, if it's
+    /// enabled. Failures to write to the disk tier are logged and otherwise
+    /// ignored.
+    #[tracing::instrument(name = "ReadCache::insert", skip(self, content))]
+    pub async fn insert(&self, key: &Key, content: Arc<[u8]>) {
+        if !self.admits(content.len() as u64) {
+            return;
+        }
+        self.insert_memory(key, &content).await;
+    }
+
+    /// Get a snapshot of the cache's statistics.
+    pub async fn stats(&self) -> ReadCacheStats {
+        let (memory_objects, memory_bytes) = {
+            let memory = self.memory.lock().await;
+            (memory.len(), memory.size())
+        };
+        let (disk_objects, disk_bytes) = match &self.disk {
+            Some(tier) => {
+                let index = tier.index.lock().await;
+                (index.len(), index.size())
+            }
+            None => (0, 0),
+        };
+        ReadCacheStats {
+            memory_hits: self.stats.memory_hits.load(Ordering::Relaxed),
+            disk_hits: self.stats.disk_hits.load(Ordering::Relaxed),
+            misses: self.stats.misses.load(Ordering::Relaxed),
+            evictions: self.stats.evictions.load(Ordering::Relaxed),
+            memory_objects,
+            memory_bytes,
+            disk_objects,
+            disk_bytes,
+        }
+    }
+
+    async fn insert_memory(&self, key: &Key, content: &Arc<[u8]>) {
+        let size = content.len() as u64;
+        let evicted = self
+            .memory
+            .lock()
+            .await
+            .insert(key.clone(), content.clone(), size);
+        for (key, content) in evicted {
+            self.insert_disk(&key, &content).await;
+        }
+    }
+
+    async fn insert_disk(&self, key: &Key, content: &[u8]) {
+        let Some(tier) = &self.disk else {
+            self.stats.evictions.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        if tier.index.lock().await.contains(key) {
+            return;
+        }
+
+        let path = tier.disk.key_path(key);
+        let written = async {
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent).await?;
+            }
+            let temp = temp_path(&path);
+            write(&temp, content).await?;
+            rename(&temp, &path).await
+        };
+        if let Err(err) = written.await {
+            warn!(?err, ?path, "cas.cache.disk_write_error");
+            self.stats.evictions.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let evicted = tier
+            .index
+            .lock()
+            .await
+            .insert(key.clone(), (), content.len() as u64);
+        for (key, ()) in evicted {
+            self.stats.evictions.fetch_add(1, Ordering::Relaxed);
+            let path = tier.disk.key_path(&key);
+            if let Err(err) = remove_file(&path).await {
+                warn!(?err, ?path, "cas.cache.disk_remove_error");
+            }
+        }
+    }
+}
+
+/// A snapshot of the statistics of a [`ReadCache`].
+#[derive(Clone, Copy, Eq, PartialEq, Default, Debug)]
+pub struct ReadCacheStats {
+    /// Reads served from the memory tier.
+    pub memory_hits: u64,
+
+    /// Reads served from the disk tier.
+    pub disk_hits: u64,
+
+    /// Reads that weren't in the cache.
+    pub misses: u64,
+
+    /// Objects evicted from the cache entirely.
+    pub evictions: u64,
+
+    /// Objects currently in the memory tier.
+    pub memory_objects: usize,
+
+    /// Total size of the objects currently in the memory tier.
+    pub memory_bytes: u64,
+
+    /// Objects currently in the disk tier.
+    pub disk_objects: usize,
+
+    /// Total size of the objects currently in the disk tier.
+    pub disk_bytes: u64,
+}
+
+/// Objects evicted from memory, stored on local disk.
+struct DiskTier {
+    disk: Disk,
+    index: Mutex<Lru<()>>,
+}
+
+#[derive(Default)]
+struct Counters {
+    memory_hits: AtomicU64,
+    disk_hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// A least-recently-used map bounded by the total size of its values.
+struct Lru<V> {
+    capacity: u64,
+    size: u64,
+    tick: u64,
+    entries: HashMap<Key, Entry<V>>,
+
+    /// Keys ordered by when they were last used, least recent first.
+    recency: BTreeMap<u64, Key>,
+}
+
+struct Entry<V> {
+    value: V,
+    size: u64,
+    tick: u64,
+}
+
+impl<V: Clone> Lru<V> {
+    fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            size: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn contains(&self, key: &Key) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Get the value for the key, marking it as the most recently used.
+    fn get(&mut self, key: &Key) -> Option<V> {
+        let tick = self.next_tick();
+        let entry = self.entries.get_mut(key)?;
+        self.recency.remove(&entry.tick);
+        self.recency.insert(tick, key.clone());
+        entry.tick = tick;
+        Some(entry.value.clone())
+    }
+
+    /// Insert the value for the key, returning the entries evicted to make
+    /// room for it.
+    ///
+    /// Values larger than the capacity are not inserted; they're returned as
+    /// if they were evicted immediately.
+    fn insert(&mut self, key: Key, value: V, size: u64) -> Vec<(Key, V)> {
+        if size > self.capacity {
+            return vec![(key, value)];
+        }
+        if self.get(&key).is_some() {
+            return Vec::new();
+        }
+
+        let mut evicted = Vec::new();
+        while self.size + size > self.capacity
+            && let Some((_, oldest)) = self.recency.pop_first()
+            && let Some(entry) = self.entries.remove(&oldest)
+        {
+            self.size -= entry.size;
+            evicted.push((oldest, entry.value));
+        }
+
+        let tick = self.next_tick();
+        self.recency.insert(tick, key.clone());
+        self.entries.insert(key, Entry { value, size, tick });
+        self.size += size;
+        evicted
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.tick);
+        self.size -= entry.size;
+        Some(entry.value)
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::*;
+
+    fn key(content: &[u8]) -> Key {
+        Key::from_blake3(blake3::hash(content))
+    }
+
+    async fn read_all(cas: &Disk, key: &Key) -> Result<Vec<u8>> {
+        let mut content = Vec::new();
+        tokio::io::copy(&mut cas.read(key).await?, &mut content).await?;
+        Ok(content)
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let (a, b, c) = (key(b"a"), key(b"b"), key(b"c"));
+        let mut lru = Lru::new(10);
+        pretty_assert_eq!(lru.insert(a.clone(), 'a', 4), vec![]);
+        pretty_assert_eq!(lru.insert(b.clone(), 'b', 4), vec![]);
+
+        // Using `a` makes `b` the least recently used.
+        pretty_assert_eq!(lru.get(&a), Some('a'));
+        pretty_assert_eq!(lru.insert(c.clone(), 'c', 4), vec![(b.clone(), 'b')]);
+        pretty_assert_eq!(lru.get(&b), None);
+        pretty_assert_eq!(lru.size(), 8);
+        pretty_assert_eq!(lru.len(), 2);
+    }
+
+    #[test]
+    fn lru_rejects_values_larger_than_capacity() {
+        let (a, b) = (key(b"a"), key(b"b"));
+        let mut lru = Lru::new(10);
+        lru.insert(a.clone(), 'a', 4);
+        pretty_assert_eq!(lru.insert(b.clone(), 'b', 11), vec![(b.clone(), 'b')]);
+        pretty_assert_eq!(lru.get(&a), Some('a'));
+        pretty_assert_eq!(lru.get(&b), None);
+    }
+
+    #[test]
+    fn lru_remove() {
+        let a = key(b"a");
+        let mut lru = Lru::new(10);
+        lru.insert(a.clone(), 'a', 4);
+        pretty_assert_eq!(lru.remove(&a), Some('a'));
+        pretty_assert_eq!(lru.size(), 0);
+        pretty_assert_eq!(lru.get(&a), None);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn serves_reads_from_memory() -> Result<()> {
+        let (cas, _temp) = Disk::new_temp().await?;
+        let cache = ReadCache::new(ReadCacheConfig::default()).await?;
+        let cas = cas.with_read_cache(cache.clone());
+
+        let content = b"popular rlib";
+        let key = key(content);
+        cas.write_buffered(&key, content).await?;
+
+        pretty_assert_eq!(read_all(&cas, &key).await?, content);
+        pretty_assert_eq!(read_all(&cas, &key).await?, content);
+
+        let stats = cache.stats().await;
+        pretty_assert_eq!(stats.misses, 1);
+        pretty_assert_eq!(stats.memory_hits, 1);
+        pretty_assert_eq!(stats.memory_objects, 1);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn evicted_objects_move_to_disk() -> Result<()> {
+        let (cas, _temp) = Disk::new_temp().await?;
+        let disk_dir = async_tempfile::TempDir::new().await?;
+        let first = b"first object".as_slice();
+        let second = b"second object".as_slice();
+        let (first_key, second_key) = (key(first), key(second));
+        cas.write_buffered(&first_key, first).await?;
+        cas.write_buffered(&second_key, second).await?;
+
+        // The memory tier only has room for one of the objects.
+        let first_size = cas
+            .size_compressed(&first_key)
+            .await?
+            .expect("object exists");
+        let second_size = cas
+            .size_compressed(&second_key)
+            .await?
+            .expect("object exists");
+        let cache = ReadCache::new(ReadCacheConfig {
+            memory_bytes: first_size.max(second_size),
+            disk_dir: Some(disk_dir.dir_path().join("cache")),
+            ..Default::default()
+        })
+        .await?;
+        let cas = cas.with_read_cache(cache.clone());
+
+        pretty_assert_eq!(read_all(&cas, &first_key).await?, first);
+        pretty_assert_eq!(read_all(&cas, &second_key).await?, second);
+        pretty_assert_eq!(read_all(&cas, &first_key).await?, first);
+
+        let stats = cache.stats().await;
+        pretty_assert_eq!(stats.misses, 2);
+        pretty_assert_eq!(stats.disk_hits, 1);
+        pretty_assert_eq!(stats.memory_objects, 1);
+        pretty_assert_eq!(stats.disk_objects, 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,420 @@
+//! Copying cached units from one Courier instance to another.
+//!
+//! Promoting a cache (e.g. from staging to production) means copying an
+//! organization's saved units to an organization on another instance, along
+//! with the CAS objects they reference. [`sync`] does this through each
+//! instance's API, so it only needs an API token for each side; since tokens
+//! belong to a single organization, they also select which organizations are
+//! synced.
+//!
+//! Objects stored as chunk lists (see [`ChunkList`]) are copied along with
+//! their chunks; a list is only written to the destination once all of its
+//! chunks are there.
+//!
+//! Sync is incremental: units and objects the destination already has aren't
+//! copied again. It's also resumable: with a state file, the cursor of each
+//! page of units is recorded once the page has been copied, and the next sync
+//! starts after it.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use clients::courier::v1::{
+    ChunkList, Client, Key, SavedUnitHash,
+    cache::{
+        CargoListRequest, CargoListedUnit, CargoRestoreRequest, CargoSaveRequest,
+        CargoSaveUnitRequest, SavedUnitMetadata,
+    },
+};
+use color_eyre::{
+    Result,
+    eyre::{Context, bail},
+};
+use futures::{SinkExt, StreamExt, TryStreamExt, stream};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// The most objects copied in a single bulk transfer.
+const OBJECT_BATCH_SIZE: usize = 256;
+
+/// Configuration for syncing.
+#[derive(Clone, Debug)]
+pub struct SyncConfig {
+    /// The instance to copy units from.
+    pub from: Client,
+
+    /// The instance to copy units to.
+    pub to: Client,
+
+    /// The file recording how far previous syncs got, if sync should resume
+    /// from where it left off.
+    pub state: Option<PathBuf>,
+
+    /// Report what would be copied without copying anything.
+    pub dry_run: bool,
+
+    /// The number of units to list from the source at a time.
+    pub page_size: u32,
+
+    /// The number of objects to check for on the destination at a time.
+    pub concurrency: usize,
+}
+
+/// What a sync copied.
+///
+/// In a dry run, the counts are of what would have been copied. Object
+/// sizes aren't known without reading the objects, so `bytes_copied` is
+/// always zero in a dry run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyncSummary {
+    /// Units listed from the source.
+    pub units_listed: u64,
+
+    /// Units the destination already had.
+    pub units_present: u64,
+
+    /// Units copied to the destination.
+    pub units_copied: u64,
+
+    /// Units that weren't copied because the source is missing some of
+    /// their objects.
+    pub units_incomplete: u64,
+
+    /// Objects copied to the destination.
+    pub objects_copied: u64,
+
+    /// Bytes of object content copied to the destination.
+    pub bytes_copied: u64,
+}
+
+/// How far previous syncs got, as recorded in the state file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct SyncState {
+    /// The source the cursor belongs to.
+    from: String,
+
+    /// The destination the units were copied to.
+    to: String,
+
+    /// The cursor of the last page that was copied.
+    after: i64,
+}
+
+/// Copy the units saved on the source to the destination.
+pub async fn sync(config: &SyncConfig) -> Result<SyncSummary> {
+    let mut after = match &config.state {
+        Some(path) => read_state(path, config).await?,
+        None => None,
+    };
+    info!(?after, dry_run = config.dry_run, "sync.start");
+
+    let mut summary = SyncSummary::default();
+    loop {
+        let request = CargoListRequest::builder()
+            .maybe_after(after)
+            .limit(config.page_size)
+            .build();
+        let page = config
+            .from
+            .cargo_cache_list(request)
+            .await
+            .context("list units on source")?;
+        let Some(next) = page.next else {
+            break;
+        };
+
+        summary.units_listed += page.units.len() as u64;
+        sync_page(config, page.units, &mut summary).await?;
+        after = Some(next);
+        if !config.dry_run
+            && let Some(path) = &config.state
+        {
+            write_state(path, config, next).await?;
+        }
+        info!(
+            cursor = next,
+            listed = summary.units_listed,
+            copied = summary.units_copied,
+            "sync.page"
+        );
+    }
+
+    Ok(summary)
+}
+
+/// Copy the units in a page that the destination doesn't have yet.
+async fn sync_page(
+    config: &SyncConfig,
+    units: Vec<CargoListedUnit>,
+    summary: &mut SyncSummary,
+) -> Result<()> {
+    let existing = present_units(&config.to, &units).await?;
+    let (present, missing) = units
+        .into_iter()
+        .partition::<Vec<_>, _>(|listed| existing.contains(&listed.unit.saved_unit_hash()));
+    summary.units_present += present.len() as u64;
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let keys = missing
+        .iter()
+        .flat_map(|listed| listed.unit.unit.object_keys())
+        .cloned()
+        .collect::<HashSet<_>>();
+    let keys = missing_objects(&config.to, keys, config.concurrency).await?;
+    if config.dry_run {
+        summary.units_copied += missing.len() as u64;
+        summary.objects_copied += keys.len() as u64;
+        return Ok(());
+    }
+
+    let mut unavailable = HashSet::new();
+    let mut lists = HashMap::new();
+    let keys = keys.into_iter().collect::<Vec<_>>();
+    for batch in keys.chunks(OBJECT_BATCH_SIZE) {
+        let copied = copy_objects(&config.from, &config.to, batch).await?;
+        summary.objects_copied += copied.keys.len() as u64;
+        summary.bytes_copied += copied.bytes;
+        unavailable.extend(
+            batch
+                .iter()
+                .filter(|key| !copied.keys.contains(*key) && !copied.lists.contains_key(*key))
+                .cloned(),
+        );
+        lists.extend(copied.lists);
+    }
+    unavailable.extend(copy_chunk_lists(config, lists, summary).await?);
+
+    // Builds fail to restore units whose objects are missing, so those units
+    // are left for a later sync instead of being saved.
+    let mut requests = HashMap::<SavedUnitMetadata, Vec<CargoSaveUnitRequest>>::new();
+    for listed in missing {
+        if listed
+            .unit
+            .unit
+            .object_keys()
+            .iter()
+            .any(|key| unavailable.contains(*key))
+        {
+            warn!(unit_hash = %listed.unit.saved_unit_hash(), "sync.unit.incomplete");
+            summary.units_incomplete += 1;
+            continue;
+        }
+        requests
+            .entry(listed.metadata)
+            .or_default()
+            .push(listed.unit);
+    }
+    for (metadata, units) in requests {
+        let count = units.len() as u64;
+        let request = CargoSaveRequest::new(units).with_metadata(metadata);
+        config
+            .to
+            .cargo_cache_save(request)
+            .await
+            .context("save units on destination")?;
+        summary.units_copied += count;
+    }
+
+    Ok(())
+}
+
+/// Find which of the units the destination already has.
+async fn present_units(to: &Client, units: &[CargoListedUnit]) -> Result<HashSet<SavedUnitHash>> {
+    // Courier only returns units saved against glibc if the request's glibc
+    // version is at least as new as theirs, and only returns units saved
+    // without glibc if the request doesn't have one, so they're asked for
+    // separately.
+    let (glibc, other) = units
+        .iter()
+        .partition::<Vec<_>, _>(|listed| listed.unit.linux_glibc_version.is_some());
+    let newest = glibc
+        .iter()
+        .filter_map(|listed| listed.unit.linux_glibc_version.clone())
+        .max();
+
+    let mut present = HashSet::new();
+    for (units, glibc) in [(glibc, newest), (other, None)] {
+        if units.is_empty() {
+            continue;
+        }
+        let hashes = units.iter().map(|listed| listed.unit.saved_unit_hash());
+        let response = to
+            .cargo_cache_restore(CargoRestoreRequest::new(hashes, glibc))
+            .await
+            .context("check destination for units")?;
+        present.extend(response.iter().map(|(hash, _)| hash.clone()));
+    }
+    Ok(present)
+}
+
+/// Find which of the objects the destination doesn't have.
+async fn missing_objects(
+    to: &Client,
+    keys: HashSet<Key>,
+    concurrency: usize,
+) -> Result<HashSet<Key>> {
+    stream::iter(keys)
+        .map(|key| async move {
+            let exists = to
+                .cas_exists(&key)
+                .await
+                .with_context(|| format!("check destination for object: {key}"))?;
+            Result::<_>::Ok((!exists).then_some(key))
+        })
+        .buffer_unordered(concurrency.max(1))
+        .try_filter_map(|key| async move { Ok(key) })
+        .try_collect()
+        .await
+}
+
+/// The objects a bulk transfer copied.
+struct CopiedObjects {
+    keys: HashSet<Key>,
+    bytes: u64,
+
+    /// Chunk lists that were read from the source but not written, since
+    /// their chunks have to be copied first.
+    lists: HashMap<Key, (Vec<u8>, ChunkList)>,
+}
+
+/// Copy the chunks of the chunk lists that the destination doesn't have yet,
+/// and then the lists whose chunks are all there.
+///
+/// Returns the keys of the lists that weren't copied because some of their
+/// chunks aren't available.
+async fn copy_chunk_lists(
+    config: &SyncConfig,
+    lists: HashMap<Key, (Vec<u8>, ChunkList)>,
+    summary: &mut SyncSummary,
+) -> Result<HashSet<Key>> {
+    if lists.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let chunks = lists
+        .values()
+        .flat_map(|(_, list)| list.chunks.iter().cloned())
+        .collect::<HashSet<_>>();
+    let chunks = missing_objects(&config.to, chunks, config.concurrency).await?;
+    let chunks = chunks.into_iter().collect::<Vec<_>>();
+    let mut unavailable = HashSet::new();
+    for batch in chunks.chunks(OBJECT_BATCH_SIZE) {
+        let copied = copy_objects(&config.from, &config.to, batch).await?;
+        summary.objects_copied += copied.keys.len() as u64;
+        summary.bytes_copied += copied.bytes;
+        unavailable.extend(
+            batch
+                .iter()
+                .filter(|key| !copied.keys.contains(*key))
+                .cloned(),
+        );
+    }
+
+    let (complete, incomplete) = lists.into_iter().partition::<Vec<_>, _>(|(_, (_, list))| {
+        list.chunks.iter().all(|chunk| !unavailable.contains(chunk))
+    });
+    let count = complete.len() as u64;
+    let bytes = complete
+        .iter()
+        .map(|(_, (content, _))| content.len() as u64)
+        .sum::<u64>();
+    let written = config
+        .to
+        .cas_write_bulk(stream::iter(
+            complete
+                .into_iter()
+                .map(|(key, (content, _))| (key, content)),
+        ))
+        .await
+        .context("write chunk lists to destination")?;
+    if let Some(error) = written.errors.first() {
+        bail!("write chunk list to destination: {error:?}");
+    }
+    summary.objects_copied += count;
+    summary.bytes_copied += bytes;
+    Ok(incomplete.into_iter().map(|(key, _)| key).collect())
+}
+
+/// Copy objects from the source to the destination, streaming each one from
+/// the source's bulk read into the destination's bulk write.
+///
+/// Objects the source doesn't have are left out of the returned keys. Chunk
+/// lists are returned without being written.
+async fn copy_objects(from: &Client, to: &Client, keys: &[Key]) -> Result<CopiedObjects> {
+    let (mut tx, rx) = futures::channel::mpsc::channel::<(Key, Vec<u8>)>(0);
+    let read = async move {
+        let mut entries = from
+            .cas_read_bulk(keys.iter().cloned())
+            .await
+            .context("read objects from source")?;
+        let mut copied = CopiedObjects {
+            keys: HashSet::new(),
+            bytes: 0,
+            lists: HashMap::new(),
+        };
+        while let Some(entry) = entries.next().await {
+            let (key, content) = entry.context("read object from source")?;
+            if let Some(list) = ChunkList::decode(&content) {
+                copied.lists.insert(key, (content, list));
+                continue;
+            }
+            copied.bytes += content.len() as u64;
+            copied.keys.insert(key.clone());
+            tx.send((key, content))
+                .await
+                .context("send object to destination")?;
+        }
+        Result::<_>::Ok(copied)
+    };
+    let write = to.cas_write_bulk(rx);
+
+    let (copied, written) = tokio::join!(read, write);
+    let written = written.context("write objects to destination")?;
+    if let Some(error) = written.errors.first() {
+        bail!("write object to destination: {error:?}");
+    }
+    copied
+}
+
+/// Read the cursor to resume from.
+async fn read_state(path: &Path, config: &SyncConfig) -> Result<Option<i64>> {
+    let content = match tokio::fs::read(path).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("read state file: {path:?}")),
+    };
+    let state = serde_json::from_slice::<SyncState>(&content)
+        .with_context(|| format!("parse state file: {path:?}"))?;
+    if state.from != config.from.to_string() || state.to != config.to.to_string() {
+        bail!(
+            "state file {path:?} is for a sync from {} to {}",
+            state.from,
+            state.to
+        );
+    }
+    Ok(Some(state.after))
+}
+
+/// Record the cursor of the last page copied.
+///
+/// The state is written to a temporary file that then replaces the state
+/// file, so that an interrupted sync never leaves a partial state file behind.
+async fn write_state(path: &Path, config: &SyncConfig, after: i64) -> Result<()> {
+    let state = SyncState {
+        from: config.from.to_string(),
+        to: config.to.to_string(),
+        after,
+    };
+    let content = serde_json::to_vec_pretty(&state).context("serialize state")?;
+    let temp = path.with_extension("tmp");
+    tokio::fs::write(&temp, content)
+        .await
+        .with_context(|| format!("write state file: {temp:?}"))?;
+    tokio::fs::rename(&temp, path)
+        .await
+        .with_context(|| format!("replace state file: {path:?}"))
+}
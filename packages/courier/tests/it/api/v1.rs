@@ -1,9 +1,12 @@
 //! v1 API integration tests.
 
+mod access;
 mod api_keys;
 mod cargo_cache;
 mod cas;
+mod device;
 mod integration;
 mod invitations;
 mod me;
 mod organizations;
+mod registry;
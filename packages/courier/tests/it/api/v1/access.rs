@@ -0,0 +1,108 @@
+//! Access token exchange tests.
+
+use std::time::Duration;
+
+use color_eyre::Result;
+use courier::access::AccessTokens;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use sqlx::PgPool;
+
+use crate::helpers::TestFixture;
+
+fn access_tokens() -> AccessTokens {
+    AccessTokens::new(
+        "0123456789abcdef0123456789abcdef",
+        AccessTokens::DEFAULT_TTL,
+    )
+    .expect("create access tokens")
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn not_configured(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let issued = fixture.client_alice.access_token_exchange().await?;
+    pretty_assert_eq!(issued, None);
+
+    // The client keeps working with its API key.
+    fixture.client_alice.cargo_cache_generation().await?;
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn access_token_authenticates(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::builder()
+        .access_tokens(access_tokens())
+        .spawn(pool)
+        .await?;
+
+    let issued = fixture
+        .client_alice
+        .access_token_exchange()
+        .await?
+        .expect("access tokens are configured");
+    pretty_assert_eq!(issued.expires_in, AccessTokens::DEFAULT_TTL.as_secs());
+
+    let client = fixture
+        .client_with_token(issued.token.clone())?
+        .without_access_tokens();
+    client.cargo_cache_generation().await?;
+
+    // Access tokens can't be exchanged for new ones, so that they can't
+    // outlive their API key.
+    let result = client.access_token_exchange().await;
+    assert!(result.is_err(), "access token was exchanged: {result:?}");
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn access_token_from_other_secret_rejected(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::builder()
+        .access_tokens(access_tokens())
+        .spawn(pool)
+        .await?;
+    let other = AccessTokens::new(
+        "fedcba9876543210fedcba9876543210",
+        AccessTokens::DEFAULT_TTL,
+    )?;
+    let auth = fixture
+        .db
+        .validate(fixture.auth.token_alice())
+        .await?
+        .expect("alice's token is valid");
+
+    let client = fixture
+        .client_with_token(other.issue(&auth).expose())?
+        .without_access_tokens();
+    let result = client.cargo_cache_generation().await;
+    assert!(result.is_err(), "forged access token was accepted");
+
+    Ok(())
+}
+
+/// The client exchanges its API key in the background and then authenticates
+/// with the access token, which keeps working until it expires even if the
+/// API key is revoked.
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn client_uses_access_token(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::builder()
+        .access_tokens(access_tokens())
+        .spawn(pool)
+        .await?;
+
+    fixture.client_alice.cargo_cache_generation().await?;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    fixture.db.revoke_token(fixture.auth.token_alice()).await?;
+    fixture.client_alice.cargo_cache_generation().await?;
+
+    let client = fixture
+        .client_with_token(fixture.auth.token_alice().expose())?
+        .without_access_tokens();
+    let result = client.cargo_cache_generation().await;
+    assert!(result.is_err(), "revoked API key was accepted");
+
+    Ok(())
+}
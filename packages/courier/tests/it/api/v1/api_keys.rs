@@ -14,6 +14,7 @@ struct CreateApiKeyResponse {
     id: i64,
     name: String,
     token: String,
+    read_only: bool,
     created_at: String,
 }
 
@@ -35,6 +36,7 @@ struct OrgApiKeyEntry {
     name: String,
     account_id: i64,
     account_email: String,
+    read_only: bool,
     created_at: String,
     accessed_at: String,
 }
@@ -104,6 +106,43 @@ async fn create_org_api_key(pool: PgPool) -> Result<()> {
     let key = response.json::<CreateApiKeyResponse>().await?;
     pretty_assert_eq!(key.name, "CI/CD Key");
     assert!(!key.token.is_empty());
+    assert!(!key.read_only);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn create_org_api_key_read_only(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let org_id = fixture.auth.org_acme().as_i64();
+    let url = fixture
+        .base_url
+        .join(&format!("api/v1/organizations/{org_id}/api-keys"))?;
+
+    let response = reqwest::Client::new()
+        .post(url.clone())
+        .bearer_auth(fixture.auth.session_alice().expose())
+        .json(&serde_json::json!({ "name": "Fork PRs", "read_only": true }))
+        .send()
+        .await?;
+
+    pretty_assert_eq!(response.status(), StatusCode::CREATED);
+    let key = response.json::<CreateApiKeyResponse>().await?;
+    assert!(key.read_only);
+
+    let list = reqwest::Client::new()
+        .get(url)
+        .bearer_auth(fixture.auth.session_alice().expose())
+        .send()
+        .await?
+        .json::<OrgApiKeyListResponse>()
+        .await?;
+    let listed = list
+        .api_keys
+        .iter()
+        .find(|listed| listed.id == key.id)
+        .expect("created key should be listed");
+    assert!(listed.read_only);
 
     Ok(())
 }
@@ -1,5 +1,13 @@
 //! Cargo cache API tests.
 
+mod as_of;
+mod generation;
+mod list;
+mod origins;
+mod read_only;
 mod reset;
 mod restore;
 mod save;
+mod scope;
+mod usage;
+mod write_policy;
@@ -0,0 +1,152 @@
+//! Cargo cache restore tests for restoring units as of a point in history.
+
+use std::collections::HashSet;
+
+use clients::courier::v1::{
+    GlibcVersion, SavedUnitHash,
+    cache::{
+        CacheAsOf, CargoRestoreRequest, CargoSaveRequest, CargoSaveUnitRequest, SavedUnitMetadata,
+    },
+};
+use color_eyre::Result;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use sqlx::PgPool;
+
+use crate::helpers::{TestFixture, test_saved_unit};
+
+const GLIBC_VERSION: GlibcVersion = GlibcVersion {
+    major: 2,
+    minor: 41,
+    patch: 0,
+};
+
+const OLD_COMMIT: &str = "3f2a9c1e8b7d6f5a4c3b2a1f0e9d8c7b6a5f4e3d";
+const NEW_COMMIT: &str = "9e8d7c6b5a4f3e2d1c0b9a8f7e6d5c4b3a2f1e0d";
+
+fn save_request(hash: &str, commit_sha: &str) -> CargoSaveRequest {
+    let request = CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit(hash))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+        .build();
+    let metadata = SavedUnitMetadata::builder().commit_sha(commit_sha).build();
+    CargoSaveRequest::new([request]).with_metadata(metadata)
+}
+
+fn restore_request(as_of: CacheAsOf) -> CargoRestoreRequest {
+    CargoRestoreRequest::new(["hash-old", "hash-new"], Some(GLIBC_VERSION)).with_as_of(as_of)
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn restore_as_of_commit(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    fixture
+        .client_alice
+        .cargo_cache_save(save_request("hash-old", OLD_COMMIT))
+        .await?;
+    fixture
+        .client_alice
+        .cargo_cache_save(save_request("hash-new", NEW_COMMIT))
+        .await?;
+
+    let response = fixture
+        .client_alice
+        .cargo_cache_restore(restore_request(CacheAsOf::Commit(OLD_COMMIT[..7].into())))
+        .await?;
+    let restored = response
+        .iter()
+        .map(|(hash, _)| hash.clone())
+        .collect::<HashSet<_>>();
+    pretty_assert_eq!(restored, HashSet::from([SavedUnitHash::new("hash-old")]));
+
+    let response = fixture
+        .client_alice
+        .cargo_cache_restore(restore_request(CacheAsOf::Commit(NEW_COMMIT.into())))
+        .await?;
+    let restored = response
+        .iter()
+        .map(|(hash, _)| hash.clone())
+        .collect::<HashSet<_>>();
+    pretty_assert_eq!(
+        restored,
+        HashSet::from([
+            SavedUnitHash::new("hash-old"),
+            SavedUnitHash::new("hash-new")
+        ])
+    );
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn restore_as_of_time(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    fixture
+        .client_alice
+        .cargo_cache_save(save_request("hash-old", OLD_COMMIT))
+        .await?;
+
+    let before = CacheAsOf::Time(String::from("2000-01-01T00:00:00Z"));
+    let response = fixture
+        .client_alice
+        .cargo_cache_restore(restore_request(before))
+        .await?;
+    pretty_assert_eq!(response.iter().count(), 0);
+
+    let after = CacheAsOf::Time(String::from("2999-01-01T00:00:00Z"));
+    let response = fixture
+        .client_alice
+        .cargo_cache_restore(restore_request(after))
+        .await?;
+    let restored = response
+        .iter()
+        .map(|(hash, _)| hash.clone())
+        .collect::<Vec<_>>();
+    pretty_assert_eq!(restored, vec![SavedUnitHash::new("hash-old")]);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn restore_as_of_rejects_unknown_points(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    fixture
+        .client_alice
+        .cargo_cache_save(save_request("hash-old", OLD_COMMIT))
+        .await?;
+
+    for as_of in [
+        CacheAsOf::Commit(NEW_COMMIT.into()),
+        CacheAsOf::Commit(String::from("3f2a")),
+        CacheAsOf::Commit(String::from("not-a-sha")),
+        CacheAsOf::Time(String::from("yesterday")),
+    ] {
+        let result = fixture
+            .client_alice
+            .cargo_cache_restore(restore_request(as_of.clone()))
+            .await;
+        assert!(result.is_err(), "restore as of {as_of:?} should fail");
+    }
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn restore_as_of_commit_is_per_organization(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    fixture
+        .client_alice
+        .cargo_cache_save(save_request("hash-old", OLD_COMMIT))
+        .await?;
+
+    let result = fixture
+        .client_charlie
+        .cargo_cache_restore(restore_request(CacheAsOf::Commit(OLD_COMMIT.into())))
+        .await;
+    assert!(
+        result.is_err(),
+        "commits of other organizations are unknown"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,143 @@
+//! Cargo cache generation endpoint tests.
+
+use clients::courier::v1::{
+    SavedUnitHash, UnitHashVersion,
+    cache::{CargoListRequest, CargoRestoreRequest, CargoSaveRequest, CargoSaveUnitRequest},
+};
+use color_eyre::Result;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use sqlx::PgPool;
+
+use crate::helpers::{TestFixture, test_saved_unit};
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn generation_starts_at_zero(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let response = fixture.client_alice.cargo_cache_generation().await?;
+    pretty_assert_eq!(response.generation, 0);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn admin_bumps_generation(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let bumped = fixture.client_alice.cargo_cache_bump_generation().await?;
+    pretty_assert_eq!(bumped.generation, 1);
+    let bumped = fixture.client_alice.cargo_cache_bump_generation().await?;
+    pretty_assert_eq!(bumped.generation, 2);
+
+    // Members of the organization see the new generation.
+    let response = fixture.client_bob.cargo_cache_generation().await?;
+    pretty_assert_eq!(response.generation, 2);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn member_cannot_bump_generation(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let result = fixture.client_bob.cargo_cache_bump_generation().await;
+    assert!(result.is_err(), "members should not be able to bump");
+
+    let response = fixture.client_alice.cargo_cache_generation().await?;
+    pretty_assert_eq!(response.generation, 0);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn generation_is_per_organization(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    fixture.client_alice.cargo_cache_bump_generation().await?;
+
+    let response = fixture.client_charlie.cargo_cache_generation().await?;
+    pretty_assert_eq!(response.generation, 0);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn units_are_saved_under_their_generation(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let unit = test_saved_unit("generation-hash");
+    let save = |generation| {
+        CargoSaveUnitRequest::builder()
+            .unit(unit.clone())
+            .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+            .unit_hash_version(UnitHashVersion::CURRENT)
+            .cache_generation(generation)
+            .build()
+    };
+    let old = save(0);
+    let new = save(1);
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new([old.clone(), new.clone()]))
+        .await?;
+
+    // The same unit saved in different generations has different hashes, so
+    // restoring in one generation doesn't find the other's unit.
+    let restored = fixture
+        .client_alice
+        .cargo_cache_restore(CargoRestoreRequest::new([new.saved_unit_hash()], None))
+        .await?;
+    pretty_assert_eq!(
+        restored
+            .iter()
+            .map(|(hash, _)| hash.clone())
+            .collect::<Vec<_>>(),
+        vec![new.saved_unit_hash()]
+    );
+    assert_ne!(old.saved_unit_hash(), new.saved_unit_hash());
+
+    // Listing preserves the generation, so the listed units hash the same.
+    let listed = fixture
+        .client_alice
+        .cargo_cache_list(CargoListRequest::default())
+        .await?;
+    let listed = listed
+        .units
+        .into_iter()
+        .map(|listed| listed.unit)
+        .collect::<Vec<_>>();
+    pretty_assert_eq!(listed, vec![old, new]);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn bumping_generation_drops_near_matches(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let request = CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit("near-match-hash"))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .unit_hash_version(UnitHashVersion::CURRENT)
+        .near_match_key(String::from("near-match-key"))
+        .build();
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new([request]))
+        .await?;
+
+    let restore = || {
+        CargoRestoreRequest::new(Vec::<SavedUnitHash>::new(), None)
+            .with_near_match_keys(["near-match-key"])
+    };
+    let mut response = fixture.client_alice.cargo_cache_restore(restore()).await?;
+    pretty_assert_eq!(response.take_near_matches("near-match-key").len(), 1);
+
+    // Units saved before the bump aren't candidates anymore.
+    fixture.client_alice.cargo_cache_bump_generation().await?;
+    let mut response = fixture.client_alice.cargo_cache_restore(restore()).await?;
+    assert!(
+        response.take_near_matches("near-match-key").is_empty(),
+        "units from earlier generations should not be near matches"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+//! Cargo cache list endpoint tests.
+
+use clients::courier::v1::{
+    GlibcVersion,
+    cache::{CargoListRequest, CargoSaveRequest, CargoSaveUnitRequest, SavedUnitMetadata},
+};
+use color_eyre::Result;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use sqlx::PgPool;
+
+use crate::helpers::{TestFixture, test_saved_unit};
+
+const GLIBC_VERSION: GlibcVersion = GlibcVersion {
+    major: 2,
+    minor: 41,
+    patch: 0,
+};
+
+fn save_unit(hash: &str) -> CargoSaveUnitRequest {
+    CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit(hash))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+        .near_match_key(format!("near-{hash}"))
+        .build()
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn list_pages_in_save_order(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let metadata = SavedUnitMetadata::builder()
+        .ci_provider("github-actions")
+        .build();
+    let units = ["hash-a", "hash-b", "hash-c"].map(save_unit);
+    for unit in &units {
+        let request = CargoSaveRequest::new([unit.clone()]).with_metadata(metadata.clone());
+        fixture.client_alice.cargo_cache_save(request).await?;
+    }
+
+    let first = fixture
+        .client_alice
+        .cargo_cache_list(CargoListRequest::builder().limit(2).build())
+        .await?;
+    let listed = first
+        .units
+        .iter()
+        .map(|listed| (&listed.unit, &listed.metadata))
+        .collect::<Vec<_>>();
+    pretty_assert_eq!(listed, vec![(&units[0], &metadata), (&units[1], &metadata)]);
+
+    let second = fixture
+        .client_alice
+        .cargo_cache_list(
+            CargoListRequest::builder()
+                .maybe_after(first.next)
+                .limit(2)
+                .build(),
+        )
+        .await?;
+    let listed = second
+        .units
+        .iter()
+        .map(|listed| &listed.unit)
+        .collect::<Vec<_>>();
+    pretty_assert_eq!(listed, vec![&units[2]]);
+
+    let last = fixture
+        .client_alice
+        .cargo_cache_list(CargoListRequest::builder().maybe_after(second.next).build())
+        .await?;
+    pretty_assert_eq!(last.units, vec![]);
+    pretty_assert_eq!(last.next, None);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn list_is_org_scoped(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let request = CargoSaveRequest::new([save_unit("hash-acme")]);
+    fixture.client_alice.cargo_cache_save(request).await?;
+
+    let response = fixture
+        .client_charlie
+        .cargo_cache_list(CargoListRequest::default())
+        .await?;
+    pretty_assert_eq!(response.units, vec![]);
+    pretty_assert_eq!(response.next, None);
+
+    Ok(())
+}
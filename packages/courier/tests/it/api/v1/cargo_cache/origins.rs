@@ -0,0 +1,127 @@
+//! Cargo unit origins endpoint tests.
+
+use clients::courier::v1::{
+    GlibcVersion, SavedUnitHash,
+    cache::{CargoSaveRequest, CargoSaveUnitRequest, SavedUnitMetadata},
+};
+use color_eyre::Result;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use sqlx::PgPool;
+
+use crate::helpers::{TestAuth, TestFixture, test_saved_unit};
+
+const GLIBC_VERSION: GlibcVersion = GlibcVersion {
+    major: 2,
+    minor: 41,
+    patch: 0,
+};
+
+fn save_request(hash: &str, metadata: SavedUnitMetadata) -> CargoSaveRequest {
+    let request = CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit(hash))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+        .build();
+    CargoSaveRequest::new([request]).with_metadata(metadata)
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn origins_list_every_upload(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let metadata = SavedUnitMetadata::builder()
+        .ci_provider("github-actions")
+        .commit_sha("3f2a9c1e8b7d6f5a4c3b2a1f0e9d8c7b6a5f4e3d")
+        .build();
+    fixture
+        .client_alice
+        .cargo_cache_save(save_request("hash-origins", metadata.clone()))
+        .await?;
+    fixture
+        .client_bob
+        .cargo_cache_save(save_request("hash-origins", SavedUnitMetadata::default()))
+        .await?;
+
+    let response = fixture
+        .client_alice
+        .cargo_unit_origins(&SavedUnitHash::new("hash-origins"))
+        .await?;
+    let origins = response
+        .origins
+        .iter()
+        .map(|origin| (origin.account_email.as_str(), origin.bot, &origin.metadata))
+        .collect::<Vec<_>>();
+    pretty_assert_eq!(
+        origins,
+        vec![
+            (TestAuth::ACCT_BOB, false, &SavedUnitMetadata::default()),
+            (TestAuth::ACCT_ALICE, false, &metadata),
+        ]
+    );
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn origins_identify_bots(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let (bot_id, token) = fixture
+        .db
+        .create_bot_account(fixture.auth.org_acme(), "ci", "ops@acme.com")
+        .await?;
+    let bot = fixture.client_with_token(token.expose())?;
+    bot.cargo_cache_save(save_request("hash-bot", SavedUnitMetadata::default()))
+        .await?;
+
+    let response = fixture
+        .client_alice
+        .cargo_unit_origins(&SavedUnitHash::new("hash-bot"))
+        .await?;
+    pretty_assert_eq!(response.origins.len(), 1);
+    pretty_assert_eq!(response.origins[0].account_id, bot_id.as_i64());
+    pretty_assert_eq!(response.origins[0].account_name.as_deref(), Some("ci"));
+    assert!(response.origins[0].bot);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn origins_scoped_to_organization(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    fixture
+        .client_alice
+        .cargo_cache_save(save_request("hash-scoped", SavedUnitMetadata::default()))
+        .await?;
+
+    let response = fixture
+        .client_charlie
+        .cargo_unit_origins(&SavedUnitHash::new("hash-scoped"))
+        .await?;
+    pretty_assert_eq!(response.origins, vec![]);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn origins_queries_are_audited(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    fixture
+        .client_alice
+        .cargo_unit_origins(&SavedUnitHash::new("hash-audited"))
+        .await?;
+
+    let entries = fixture
+        .db
+        .list_audit_log(fixture.auth.org_acme(), 10, None)
+        .await?;
+    let entry = entries
+        .iter()
+        .find(|entry| entry.action == "cargo.unit.origins_viewed")
+        .expect("origins query should be audited");
+    pretty_assert_eq!(entry.account_id, Some(fixture.auth.account_id_alice()));
+    pretty_assert_eq!(
+        entry.details,
+        Some(serde_json::json!({ "unit_hash": "hash-audited" }))
+    );
+
+    Ok(())
+}
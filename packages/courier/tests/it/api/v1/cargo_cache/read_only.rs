@@ -0,0 +1,198 @@
+//! Cargo cache read-only mode tests.
+
+use clients::courier::v1::{
+    Key, UnitHashVersion,
+    cache::{
+        CacheReadOnly, CargoReadOnly, CargoRestoreRequest, CargoSaveRequest, CargoSaveUnitRequest,
+    },
+};
+use color_eyre::{Report, Result};
+use courier::maintenance::ReadOnly;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use sqlx::PgPool;
+
+use crate::helpers::{TestFixture, test_saved_unit};
+
+fn save_request(hash: &str) -> CargoSaveRequest {
+    let unit = CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit(hash))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .unit_hash_version(UnitHashVersion::CURRENT)
+        .build();
+    CargoSaveRequest::new([unit])
+}
+
+/// The reason the write was rejected, if it was because the cache is
+/// read-only.
+fn read_only_reason(error: &Report) -> Option<&str> {
+    error
+        .downcast_ref::<CacheReadOnly>()
+        .map(|read_only| read_only.reason.as_str())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn cache_starts_writable(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let read_only = fixture.client_alice.cargo_cache_read_only().await?;
+    pretty_assert_eq!(read_only, CargoReadOnly::writable());
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn admin_makes_org_read_only(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    fixture
+        .client_bob
+        .cargo_cache_save(save_request("saved-hash"))
+        .await?;
+
+    let read_only = CargoReadOnly::read_only("migrating storage");
+    fixture
+        .client_alice
+        .cargo_cache_set_read_only(&read_only)
+        .await?;
+    let response = fixture.client_bob.cargo_cache_read_only().await?;
+    pretty_assert_eq!(response, read_only);
+
+    // Writes are rejected with the reason.
+    let error = fixture
+        .client_bob
+        .cargo_cache_save(save_request("rejected-hash"))
+        .await
+        .expect_err("saves should be rejected");
+    pretty_assert_eq!(read_only_reason(&error), Some("migrating storage"));
+    let error = fixture
+        .client_bob
+        .cas_write_bytes(&Key::from_buffer(b"content"), b"content".to_vec())
+        .await
+        .expect_err("CAS writes should be rejected");
+    pretty_assert_eq!(read_only_reason(&error), Some("migrating storage"));
+
+    // Restores are still served.
+    let hash = UnitHashVersion::CURRENT.derive(test_saved_unit("saved-hash").info());
+    let restored = fixture
+        .client_bob
+        .cargo_cache_restore(CargoRestoreRequest::new([hash.clone()], None))
+        .await?;
+    assert!(restored.get(&hash).is_some(), "restores should be served");
+
+    // Other organizations are unaffected.
+    fixture
+        .client_charlie
+        .cargo_cache_save(save_request("other-org-hash"))
+        .await?;
+
+    // Clearing the reason accepts writes again.
+    fixture
+        .client_alice
+        .cargo_cache_set_read_only(&CargoReadOnly::writable())
+        .await?;
+    fixture
+        .client_bob
+        .cargo_cache_save(save_request("rejected-hash"))
+        .await?;
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn member_cannot_make_org_read_only(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let result = fixture
+        .client_bob
+        .cargo_cache_set_read_only(&CargoReadOnly::read_only("maintenance"))
+        .await;
+    assert!(
+        result.is_err(),
+        "members should not be able to make the cache read-only"
+    );
+
+    let read_only = fixture.client_alice.cargo_cache_read_only().await?;
+    pretty_assert_eq!(read_only, CargoReadOnly::writable());
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn read_only_instance_rejects_writes(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::builder()
+        .read_only(ReadOnly::new("database migration"))
+        .spawn(pool)
+        .await?;
+
+    let read_only = fixture.client_charlie.cargo_cache_read_only().await?;
+    pretty_assert_eq!(read_only, CargoReadOnly::read_only("database migration"));
+
+    let error = fixture
+        .client_alice
+        .cargo_cache_save_stream(save_request("streamed-hash"))
+        .await
+        .expect_err("streamed saves should be rejected");
+    pretty_assert_eq!(read_only_reason(&error), Some("database migration"));
+    let error = fixture
+        .client_alice
+        .cas_write_resumable(&Key::from_buffer(b"content"), b"content".to_vec())
+        .await
+        .expect_err("resumable uploads should be rejected");
+    pretty_assert_eq!(read_only_reason(&error), Some("database migration"));
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn read_only_api_key_rejects_writes(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    fixture
+        .client_alice
+        .cargo_cache_save(save_request("saved-hash"))
+        .await?;
+
+    let (_, token) = fixture
+        .db
+        .create_api_key(
+            fixture.auth.account_id_alice(),
+            "Fork PRs",
+            fixture.auth.org_acme(),
+            true,
+        )
+        .await?;
+    let client = fixture.client_with_token(token.expose())?;
+
+    // Clients learn that they can't save before uploading.
+    let read_only = client.cargo_cache_read_only().await?;
+    pretty_assert_eq!(read_only, CargoReadOnly::read_only(ReadOnly::READ_ONLY_KEY));
+
+    // Writes are rejected, even though the cache itself accepts writes.
+    let error = client
+        .cargo_cache_save(save_request("rejected-hash"))
+        .await
+        .expect_err("saves should be rejected");
+    pretty_assert_eq!(read_only_reason(&error), Some(ReadOnly::READ_ONLY_KEY));
+    let error = client
+        .cas_write_bytes(&Key::from_buffer(b"content"), b"content".to_vec())
+        .await
+        .expect_err("CAS writes should be rejected");
+    pretty_assert_eq!(read_only_reason(&error), Some(ReadOnly::READ_ONLY_KEY));
+    assert!(
+        client.cache_reset().await.is_err(),
+        "read-only keys should not be able to reset the cache"
+    );
+
+    // Restores are still served.
+    let hash = UnitHashVersion::CURRENT.derive(test_saved_unit("saved-hash").info());
+    let restored = client
+        .cargo_cache_restore(CargoRestoreRequest::new([hash.clone()], None))
+        .await?;
+    assert!(restored.get(&hash).is_some(), "restores should be served");
+
+    // Other keys in the organization can still write.
+    fixture
+        .client_alice
+        .cargo_cache_save(save_request("rejected-hash"))
+        .await?;
+
+    Ok(())
+}
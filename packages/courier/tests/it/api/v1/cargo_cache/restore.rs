@@ -1,7 +1,9 @@
 //! Cargo cache restore endpoint tests.
 
+use std::collections::HashMap;
+
 use clients::courier::v1::{
-    GlibcVersion, SavedUnitHash,
+    GlibcVersion, SavedUnitHash, UnitHashVersion,
     cache::{CargoRestoreRequest, CargoSaveRequest, CargoSaveUnitRequest},
 };
 use color_eyre::Result;
@@ -46,6 +48,126 @@ async fn restore_after_save(pool: PgPool) -> Result<()> {
     Ok(())
 }
 
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn restore_after_save_json(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let client = fixture.client_alice.clone().with_json_messages();
+    let unit = test_saved_unit("serde-json");
+    let key = unit.unit_hash().clone();
+    let request = CargoSaveUnitRequest::builder()
+        .unit(unit.clone())
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+        .build();
+    client
+        .cargo_cache_save(CargoSaveRequest::new([request]))
+        .await?;
+
+    let restore_request = CargoRestoreRequest::new([key.clone()], Some(GLIBC_VERSION));
+    let response = client.cargo_cache_restore(restore_request).await?;
+    let restored = response.into_iter().collect::<HashMap<_, _>>();
+    pretty_assert_eq!(restored, HashMap::from([(key, unit)]));
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn restore_across_unit_hash_versions(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let legacy = test_saved_unit("hash-legacy");
+    let current = test_saved_unit("hash-current");
+    let requests = [
+        (&legacy, UnitHashVersion::V1),
+        (&current, UnitHashVersion::CURRENT),
+    ]
+    .map(|(unit, version)| {
+        CargoSaveUnitRequest::builder()
+            .unit(unit)
+            .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+            .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+            .unit_hash_version(version)
+            .build()
+    });
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new(requests))
+        .await?;
+
+    let keys = [&legacy, &current]
+        .into_iter()
+        .flat_map(|unit| UnitHashVersion::RESTORABLE.map(|version| version.derive(unit.info())));
+    let restore_request = CargoRestoreRequest::new(keys, Some(GLIBC_VERSION));
+    let response = fixture
+        .client_alice
+        .cargo_cache_restore(restore_request)
+        .await?;
+
+    let restored = response.into_iter().collect::<HashMap<_, _>>();
+    let expected = HashMap::from([
+        (UnitHashVersion::V1.derive(legacy.info()), legacy.clone()),
+        (
+            UnitHashVersion::CURRENT.derive(current.info()),
+            current.clone(),
+        ),
+    ]);
+    pretty_assert_eq!(restored, expected);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn restore_near_matches(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let units = [
+        ("near-default", Some("serde-key")),
+        ("near-derive", Some("serde-key")),
+        ("near-other", Some("tokio-key")),
+        ("near-none", None),
+    ]
+    .map(|(hash, key)| (test_saved_unit(hash), key));
+    let requests = units.iter().map(|(unit, key)| {
+        CargoSaveUnitRequest::builder()
+            .unit(unit)
+            .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+            .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+            .maybe_near_match_key(key.map(String::from))
+            .build()
+    });
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new(requests))
+        .await?;
+
+    let restore_request =
+        CargoRestoreRequest::new([SavedUnitHash::from("near-missing")], Some(GLIBC_VERSION))
+            .with_near_match_keys(["serde-key", "missing-key"]);
+    let mut response = fixture
+        .client_alice
+        .cargo_cache_restore(restore_request)
+        .await?;
+    assert!(response.is_empty(), "no unit matches exactly");
+    pretty_assert_eq!(response.near_match_count(), 2);
+
+    let mut candidates = response
+        .take_near_matches("serde-key")
+        .into_iter()
+        .map(|unit| unit.unit_hash().clone())
+        .collect::<Vec<_>>();
+    candidates.sort();
+    pretty_assert_eq!(
+        candidates,
+        vec![
+            SavedUnitHash::from("near-default"),
+            SavedUnitHash::from("near-derive"),
+        ]
+    );
+    pretty_assert_eq!(response.take_near_matches("missing-key"), vec![]);
+
+    Ok(())
+}
+
 #[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
 async fn restore_nonexistent_cache(pool: PgPool) -> Result<()> {
     let fixture = TestFixture::spawn(pool).await?;
@@ -1,15 +1,19 @@
 //! Cargo cache save endpoint tests.
 
-use clients::courier::v1::{
-    GlibcVersion, SavedUnitHash,
-    cache::{CargoRestoreRequest, CargoSaveRequest, CargoSaveUnitRequest},
+use clients::{
+    ContentType,
+    courier::v1::{
+        GlibcVersion, SavedUnitHash,
+        cache::{CargoRestoreRequest, CargoSaveRequest, CargoSaveUnitRequest, SavedUnitMetadata},
+    },
 };
 use color_eyre::Result;
 use pretty_assertions::assert_eq as pretty_assert_eq;
+use reqwest::StatusCode;
 use sqlx::PgPool;
 use tap::Pipe;
 
-use crate::helpers::{TestFixture, test_saved_unit};
+use crate::helpers::{TestFixture, test_blob, test_saved_unit};
 
 const GLIBC_VERSION: GlibcVersion = GlibcVersion {
     major: 2,
@@ -284,3 +288,290 @@ async fn save_revoked_token_returns_401(pool: PgPool) -> Result<()> {
 
     Ok(())
 }
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn stream_save_flow(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    // More units than fit in a single batch, so that the handler has to
+    // flush several times.
+    let units = (0..250)
+        .map(|i| test_saved_unit(format!("hash-stream-{i}")))
+        .collect::<Vec<_>>();
+    let requests = units.iter().map(|unit| {
+        CargoSaveUnitRequest::builder()
+            .unit(unit.clone())
+            .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+            .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+            .build()
+    });
+    let save_request = CargoSaveRequest::new(requests);
+    fixture
+        .client_alice
+        .cargo_cache_save_stream(save_request)
+        .await?;
+
+    let keys = units.iter().map(|unit| unit.unit_hash().clone());
+    let restore_request = CargoRestoreRequest::new(keys, Some(GLIBC_VERSION));
+    let response = fixture
+        .client_alice
+        .cargo_cache_restore(restore_request)
+        .await?;
+
+    for unit in &units {
+        let restored_unit = response
+            .iter()
+            .find(|(k, _)| *k == unit.unit_hash())
+            .map(|(_, v)| v)
+            .unwrap_or_else(|| panic!("unit {} should be restored", unit.unit_hash()));
+        pretty_assert_eq!(restored_unit, unit);
+    }
+
+    Ok(())
+}
+
+fn sample_metadata() -> SavedUnitMetadata {
+    SavedUnitMetadata::builder()
+        .rustc_version("rustc 1.90.0 (1159e78c4 2025-09-14)")
+        .hurry_version("0.5.0")
+        .ci_provider("github-actions")
+        .commit_sha("3f2a9c1e8b7d6f5a4c3b2a1f0e9d8c7b6a5f4e3d")
+        .builder_hostname_hash("9e107d9d372bb6826bd81d3542a419d6")
+        .build()
+}
+
+async fn stored_metadata(pool: &PgPool, hash: &str) -> Result<SavedUnitMetadata> {
+    let (rustc_version, hurry_version, ci_provider, commit_sha, builder_hostname_hash) =
+        sqlx::query_as::<
+            _,
+            (
+                Option<String>,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+            ),
+        >(
+            "SELECT rustc_version, hurry_version, ci_provider, commit_sha, builder_hostname_hash
+             FROM cargo_saved_unit
+             WHERE unit_hash = $1",
+        )
+        .bind(hash)
+        .fetch_one(pool)
+        .await?;
+    SavedUnitMetadata::builder()
+        .maybe_rustc_version(rustc_version)
+        .maybe_hurry_version(hurry_version)
+        .maybe_ci_provider(ci_provider)
+        .maybe_commit_sha(commit_sha)
+        .maybe_builder_hostname_hash(builder_hostname_hash)
+        .build()
+        .pipe(Ok)
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn save_with_metadata(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let request = CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit("hash-metadata"))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+        .build();
+    let save_request = CargoSaveRequest::new([request]).with_metadata(sample_metadata());
+    fixture.client_alice.cargo_cache_save(save_request).await?;
+
+    let stored = stored_metadata(&fixture.db.pool, "hash-metadata").await?;
+    pretty_assert_eq!(stored, sample_metadata());
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn save_without_metadata(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let request = CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit("hash-no-metadata"))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+        .build();
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new([request]))
+        .await?;
+
+    let stored = stored_metadata(&fixture.db.pool, "hash-no-metadata").await?;
+    pretty_assert_eq!(stored, SavedUnitMetadata::default());
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn stream_save_with_metadata(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let request = CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit("hash-stream-metadata"))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+        .build();
+    let save_request = CargoSaveRequest::new([request]).with_metadata(sample_metadata());
+    fixture
+        .client_alice
+        .cargo_cache_save_stream(save_request)
+        .await?;
+
+    let stored = stored_metadata(&fixture.db.pool, "hash-stream-metadata").await?;
+    pretty_assert_eq!(stored, sample_metadata());
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn stream_save_invalid_line_returns_400(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let url = fixture.base_url.join("api/v1/cache/cargo/save/stream")?;
+
+    let response = reqwest::Client::new()
+        .post(url)
+        .bearer_auth(fixture.auth.token_alice().expose())
+        .header(ContentType::HEADER, ContentType::NdJson.value())
+        .body("{\"not\": \"a unit\"}\n")
+        .send()
+        .await?;
+    pretty_assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn stream_save_without_auth_fails(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let unit = test_saved_unit("hash-stream-noauth");
+    let request = CargoSaveUnitRequest::builder()
+        .unit(unit)
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+        .build();
+    let save_request = CargoSaveRequest::new([request]);
+
+    let client = fixture.client_with_token("invalid-token-that-does-not-exist")?;
+    let result = client.cargo_cache_save_stream(save_request).await;
+    assert!(
+        result.is_err(),
+        "stream save with invalid token should fail"
+    );
+
+    Ok(())
+}
+
+fn save_request(hash: &str, repair: bool) -> CargoSaveUnitRequest {
+    CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit(hash))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+        .repair(repair)
+        .build()
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn repair_replaces_unit_with_missing_objects(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new([save_request(
+            "hash-repair-missing",
+            false,
+        )]))
+        .await?;
+
+    // None of the unit's objects were uploaded, so the repair replaces it.
+    let request = CargoSaveRequest::new([save_request("hash-repair-missing", true)])
+        .with_metadata(sample_metadata());
+    fixture.client_alice.cargo_cache_save(request).await?;
+
+    let stored = stored_metadata(&fixture.db.pool, "hash-repair-missing").await?;
+    pretty_assert_eq!(stored, sample_metadata());
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn repair_keeps_intact_unit(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    for content in [b"dep-info".as_slice(), b"encoded-dep-info"] {
+        fixture
+            .client_alice
+            .cas_write_bytes(&test_blob(content), content.to_vec())
+            .await?;
+    }
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new([save_request(
+            "hash-repair-intact",
+            false,
+        )]))
+        .await?;
+
+    // All of the unit's objects exist, so the repair is ignored like any
+    // other save of an existing unit.
+    let request = CargoSaveRequest::new([save_request("hash-repair-intact", true)])
+        .with_metadata(sample_metadata());
+    fixture.client_alice.cargo_cache_save(request).await?;
+
+    let stored = stored_metadata(&fixture.db.pool, "hash-repair-intact").await?;
+    pretty_assert_eq!(stored, SavedUnitMetadata::default());
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn build_units_restored_once_finalized(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let unit = test_saved_unit("hash-build-finalize");
+    let key = unit.unit_hash().clone();
+    let metadata = SavedUnitMetadata::builder().build_id("build-1").build();
+    let request =
+        CargoSaveRequest::new([save_request("hash-build-finalize", false)]).with_metadata(metadata);
+    fixture.client_alice.cargo_cache_save(request).await?;
+
+    let restore = CargoRestoreRequest::new([key.clone()], Some(GLIBC_VERSION));
+    let response = fixture
+        .client_alice
+        .cargo_cache_restore(restore.clone())
+        .await?;
+    assert!(
+        response.is_empty(),
+        "units aren't restored until the build is finalized"
+    );
+
+    fixture.client_alice.cargo_cache_finalize("build-1").await?;
+    fixture.client_alice.cargo_cache_finalize("build-1").await?;
+    let response = fixture.client_alice.cargo_cache_restore(restore).await?;
+    pretty_assert_eq!(response.get(&key), Some(&unit));
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn interrupted_build_units_adopted_by_later_build(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let unit = test_saved_unit("hash-build-interrupted");
+    let key = unit.unit_hash().clone();
+
+    // The first build saves the unit but is interrupted before finalizing.
+    for build_id in ["build-interrupted", "build-complete"] {
+        let metadata = SavedUnitMetadata::builder().build_id(build_id).build();
+        let request = CargoSaveRequest::new([save_request("hash-build-interrupted", false)])
+            .with_metadata(metadata);
+        fixture.client_alice.cargo_cache_save(request).await?;
+    }
+    fixture
+        .client_alice
+        .cargo_cache_finalize("build-complete")
+        .await?;
+
+    let restore = CargoRestoreRequest::new([key.clone()], Some(GLIBC_VERSION));
+    let response = fixture.client_alice.cargo_cache_restore(restore).await?;
+    pretty_assert_eq!(response.get(&key), Some(&unit));
+
+    Ok(())
+}
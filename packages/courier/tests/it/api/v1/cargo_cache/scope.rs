@@ -0,0 +1,191 @@
+//! Cargo cache scope tests.
+
+use clients::courier::v1::{
+    CacheScope, SavedUnitHash, UnitHashVersion,
+    cache::{
+        CargoListRequest, CargoRestoreRequest, CargoSaveRequest, CargoSaveUnitRequest,
+        CargoScopeResetResponse,
+    },
+};
+use color_eyre::Result;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use sqlx::PgPool;
+
+use crate::helpers::{TestFixture, test_saved_unit};
+
+fn scope(name: &str) -> CacheScope {
+    CacheScope::new(name).expect("valid scope name")
+}
+
+fn save_request(hash: &str, scope: Option<&CacheScope>) -> CargoSaveUnitRequest {
+    CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit(hash))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .unit_hash_version(UnitHashVersion::CURRENT)
+        .maybe_cache_scope(scope.cloned())
+        .near_match_key(String::from("shared-key"))
+        .build()
+}
+
+/// The hashes of the units that restoring `hashes` finds.
+async fn restored(fixture: &TestFixture, hashes: &[SavedUnitHash]) -> Result<Vec<SavedUnitHash>> {
+    let response = fixture
+        .client_alice
+        .cargo_cache_restore(CargoRestoreRequest::new(hashes.iter().cloned(), None))
+        .await?;
+    let mut restored = response
+        .iter()
+        .map(|(hash, _)| hash.clone())
+        .collect::<Vec<_>>();
+    restored.sort();
+    Ok(restored)
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn units_are_saved_under_their_scope(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let api = scope("acme/api");
+    let unscoped = save_request("scoped-hash", None);
+    let scoped = save_request("scoped-hash", Some(&api));
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new([unscoped.clone(), scoped.clone()]))
+        .await?;
+
+    // The same unit saved in different scopes has different hashes, so
+    // restoring in one scope doesn't find the other's unit.
+    assert_ne!(unscoped.saved_unit_hash(), scoped.saved_unit_hash());
+    let hashes = [scoped.saved_unit_hash()];
+    pretty_assert_eq!(restored(&fixture, &hashes).await?, hashes.to_vec());
+
+    // Listing preserves the scope, so the listed units hash the same.
+    let listed = fixture
+        .client_alice
+        .cargo_cache_list(CargoListRequest::default())
+        .await?;
+    let listed = listed
+        .units
+        .into_iter()
+        .map(|listed| listed.unit)
+        .collect::<Vec<_>>();
+    pretty_assert_eq!(listed, vec![unscoped, scoped]);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn near_matches_are_scoped(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let api = scope("acme/api");
+    let web = scope("acme/web");
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new([
+            save_request("unscoped-unit", None),
+            save_request("api-unit", Some(&api)),
+            save_request("web-unit", Some(&web)),
+        ]))
+        .await?;
+
+    for (scope, expected) in [
+        (None, "unscoped-unit"),
+        (Some(api), "api-unit"),
+        (Some(web), "web-unit"),
+    ] {
+        let request = CargoRestoreRequest::new(Vec::<SavedUnitHash>::new(), None)
+            .with_near_match_keys(["shared-key"])
+            .with_cache_scope(scope.clone());
+        let mut response = fixture.client_alice.cargo_cache_restore(request).await?;
+        let candidates = response
+            .take_near_matches("shared-key")
+            .into_iter()
+            .map(|unit| unit.unit_hash().clone())
+            .collect::<Vec<_>>();
+        pretty_assert_eq!(
+            candidates,
+            vec![SavedUnitHash::from(expected)],
+            "near matches in scope {scope:?}"
+        );
+    }
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn admin_resets_scope(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let api = scope("acme/api");
+    let web = scope("acme/web");
+    let requests = [
+        save_request("unscoped-unit", None),
+        save_request("api-unit", Some(&api)),
+        save_request("api-other-unit", Some(&api)),
+        save_request("web-unit", Some(&web)),
+    ];
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new(requests.clone()))
+        .await?;
+
+    let reset = fixture.client_alice.cache_reset_scope(&api).await?;
+    pretty_assert_eq!(reset, CargoScopeResetResponse::new(2));
+
+    // Only the units saved in the reset scope are gone.
+    let hashes = requests
+        .iter()
+        .map(|request| request.saved_unit_hash())
+        .collect::<Vec<_>>();
+    let mut expected = vec![hashes[0].clone(), hashes[3].clone()];
+    expected.sort();
+    pretty_assert_eq!(restored(&fixture, &hashes).await?, expected);
+
+    // Resetting a scope without units deletes nothing.
+    let reset = fixture.client_alice.cache_reset_scope(&api).await?;
+    pretty_assert_eq!(reset, CargoScopeResetResponse::new(0));
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn member_cannot_reset_scope(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let api = scope("acme/api");
+    let request = save_request("api-unit", Some(&api));
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new([request.clone()]))
+        .await?;
+
+    let result = fixture.client_bob.cache_reset_scope(&api).await;
+    assert!(
+        result.is_err(),
+        "members should not be able to reset a scope"
+    );
+
+    let hashes = [request.saved_unit_hash()];
+    pretty_assert_eq!(restored(&fixture, &hashes).await?, hashes.to_vec());
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn scope_reset_is_per_organization(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let api = scope("acme/api");
+    let request = save_request("api-unit", Some(&api));
+    fixture
+        .client_charlie
+        .cargo_cache_save(CargoSaveRequest::new([request.clone()]))
+        .await?;
+
+    let reset = fixture.client_alice.cache_reset_scope(&api).await?;
+    pretty_assert_eq!(reset, CargoScopeResetResponse::new(0));
+
+    let response = fixture
+        .client_charlie
+        .cargo_cache_restore(CargoRestoreRequest::new([request.saved_unit_hash()], None))
+        .await?;
+    assert!(!response.is_empty(), "other organizations keep their units");
+
+    Ok(())
+}
@@ -0,0 +1,189 @@
+//! Cargo cache storage usage and quota tests.
+
+use clients::courier::v1::{
+    UnitHashVersion,
+    cache::{CargoSaveRequest, CargoSaveUnitRequest, CargoStorageUsage, StorageQuotaExceeded},
+};
+use color_eyre::{Report, Result};
+use courier::quota::StorageQuota;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use reqwest::StatusCode;
+use sqlx::PgPool;
+
+use crate::helpers::{TestFixture, test_blob, test_saved_unit};
+
+fn save_request(hash: &str) -> CargoSaveRequest {
+    let unit = CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit(hash))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .unit_hash_version(UnitHashVersion::CURRENT)
+        .build();
+    CargoSaveRequest::new([unit])
+}
+
+/// The usage the write was rejected with, if it was because the storage quota
+/// is used up.
+fn quota_exceeded(error: &Report) -> Option<CargoStorageUsage> {
+    error
+        .downcast_ref::<StorageQuotaExceeded>()
+        .map(|exceeded| exceeded.usage)
+}
+
+async fn stored_size(fixture: &TestFixture, content: &[u8]) -> Result<u64> {
+    Ok(fixture
+        .storage
+        .size_compressed(&test_blob(content))
+        .await?
+        .unwrap_or_default())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn usage_counts_objects_per_organization(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let usage = fixture.client_alice.cargo_cache_usage().await?;
+    pretty_assert_eq!(usage, Some(CargoStorageUsage::new(0, None)));
+
+    let content = b"shared content";
+    fixture
+        .client_alice
+        .cas_write_bytes(&test_blob(content), content.to_vec())
+        .await?;
+    let size = stored_size(&fixture, content).await?;
+    assert!(size > 0, "object should be stored");
+    let usage = fixture.client_alice.cargo_cache_usage().await?;
+    pretty_assert_eq!(usage, Some(CargoStorageUsage::new(size, None)));
+
+    // Writing the object again in the same organization doesn't count twice.
+    fixture
+        .client_bob
+        .cas_write_bytes(&test_blob(content), content.to_vec())
+        .await?;
+    let usage = fixture.client_bob.cargo_cache_usage().await?;
+    pretty_assert_eq!(usage, Some(CargoStorageUsage::new(size, None)));
+
+    // Another organization that writes it is charged for it too.
+    fixture
+        .client_charlie
+        .cas_write_bytes(&test_blob(content), content.to_vec())
+        .await?;
+    let usage = fixture.client_charlie.cargo_cache_usage().await?;
+    pretty_assert_eq!(usage, Some(CargoStorageUsage::new(size, None)));
+
+    // Resetting the cache releases the organization's usage.
+    fixture.client_alice.cache_reset().await?;
+    let usage = fixture.client_alice.cargo_cache_usage().await?;
+    pretty_assert_eq!(usage, Some(CargoStorageUsage::new(0, None)));
+    let usage = fixture.client_charlie.cargo_cache_usage().await?;
+    pretty_assert_eq!(usage, Some(CargoStorageUsage::new(size, None)));
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn writes_rejected_over_quota(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::builder()
+        .storage_quota(StorageQuota::new(1))
+        .spawn(pool)
+        .await?;
+
+    let content = b"first content";
+    fixture
+        .client_alice
+        .cas_write_bytes(&test_blob(content), content.to_vec())
+        .await?;
+    let size = stored_size(&fixture, content).await?;
+    let expected = CargoStorageUsage::new(size, Some(1));
+
+    // Writes and saves are rejected with the usage.
+    let error = fixture
+        .client_alice
+        .cas_write_bytes(&test_blob(b"more content"), b"more content".to_vec())
+        .await
+        .expect_err("CAS writes should be rejected");
+    pretty_assert_eq!(quota_exceeded(&error), Some(expected));
+    let error = fixture
+        .client_bob
+        .cargo_cache_save(save_request("rejected-hash"))
+        .await
+        .expect_err("saves should be rejected");
+    pretty_assert_eq!(quota_exceeded(&error), Some(expected));
+
+    // Reads are still served.
+    let read = fixture
+        .client_alice
+        .cas_read_bytes(&test_blob(content))
+        .await?;
+    pretty_assert_eq!(read, Some(content.to_vec()));
+
+    // Other organizations are unaffected.
+    fixture
+        .client_charlie
+        .cargo_cache_save(save_request("other-org-hash"))
+        .await?;
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn organization_quota_replaces_default(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::builder()
+        .storage_quota(StorageQuota::new(1))
+        .spawn(pool)
+        .await?;
+    fixture
+        .db
+        .set_organization_storage_quota(fixture.auth.org_acme(), Some(1024 * 1024))
+        .await?;
+
+    for content in [b"first content".as_slice(), b"second content".as_slice()] {
+        fixture
+            .client_alice
+            .cas_write_bytes(&test_blob(content), content.to_vec())
+            .await?;
+    }
+    let usage = fixture
+        .client_alice
+        .cargo_cache_usage()
+        .await?
+        .expect("usage should be reported");
+    pretty_assert_eq!(usage.quota_bytes, Some(1024 * 1024));
+    assert!(!usage.exceeded(), "usage should be within the quota");
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn organization_usage_requires_membership(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::builder()
+        .storage_quota(StorageQuota::new(1024))
+        .spawn(pool)
+        .await?;
+    let content = b"content";
+    fixture
+        .client_alice
+        .cas_write_bytes(&test_blob(content), content.to_vec())
+        .await?;
+    let size = stored_size(&fixture, content).await?;
+
+    let org_id = fixture.auth.org_acme().as_i64();
+    let url = fixture
+        .base_url
+        .join(&format!("api/v1/organizations/{org_id}/usage"))?;
+    let response = reqwest::Client::new()
+        .get(url.clone())
+        .bearer_auth(fixture.auth.session_bob().expose())
+        .send()
+        .await?;
+    pretty_assert_eq!(response.status(), StatusCode::OK);
+    let usage = response.json::<CargoStorageUsage>().await?;
+    pretty_assert_eq!(usage, CargoStorageUsage::new(size, Some(1024)));
+
+    let response = reqwest::Client::new()
+        .get(url)
+        .bearer_auth(fixture.auth.session_charlie().expose())
+        .send()
+        .await?;
+    pretty_assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    Ok(())
+}
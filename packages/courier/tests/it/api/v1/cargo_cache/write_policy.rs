@@ -0,0 +1,147 @@
+//! Cargo cache write policy tests.
+
+use std::collections::HashSet;
+
+use clients::courier::v1::{
+    UnitHashVersion,
+    cache::{
+        CargoRestoreRequest, CargoSaveRequest, CargoSaveUnitRequest, CargoWritePolicy,
+        SavedUnitMetadata,
+    },
+};
+use color_eyre::Result;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use sqlx::PgPool;
+
+use crate::helpers::{TestFixture, test_saved_unit};
+
+fn save_request(hash: &str, branch: Option<&str>) -> CargoSaveRequest {
+    let unit = CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit(hash))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .unit_hash_version(UnitHashVersion::CURRENT)
+        .build();
+    let metadata = SavedUnitMetadata::builder().maybe_branch(branch).build();
+    CargoSaveRequest::new([unit]).with_metadata(metadata)
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn policy_starts_unrestricted(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let policy = fixture.client_alice.cargo_cache_write_policy().await?;
+    pretty_assert_eq!(policy, CargoWritePolicy::unrestricted());
+
+    // Without a policy, builds of any branch (or none) can save.
+    fixture
+        .client_bob
+        .cargo_cache_save(save_request("unrestricted-hash", None))
+        .await?;
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn admin_sets_policy(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let policy = CargoWritePolicy::protected(["main", "release/*"]);
+
+    fixture
+        .client_alice
+        .cargo_cache_set_write_policy(&policy)
+        .await?;
+
+    // Members of the organization see the new policy.
+    let response = fixture.client_bob.cargo_cache_write_policy().await?;
+    pretty_assert_eq!(response, policy);
+
+    // Other organizations are unaffected.
+    let response = fixture.client_charlie.cargo_cache_write_policy().await?;
+    pretty_assert_eq!(response, CargoWritePolicy::unrestricted());
+
+    // Clearing the policy lets every branch save again.
+    fixture
+        .client_alice
+        .cargo_cache_set_write_policy(&CargoWritePolicy::unrestricted())
+        .await?;
+    let response = fixture.client_bob.cargo_cache_write_policy().await?;
+    pretty_assert_eq!(response, CargoWritePolicy::unrestricted());
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn member_cannot_set_policy(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let result = fixture
+        .client_bob
+        .cargo_cache_set_write_policy(&CargoWritePolicy::protected(["main"]))
+        .await;
+    assert!(
+        result.is_err(),
+        "members should not be able to set the policy"
+    );
+
+    let policy = fixture.client_alice.cargo_cache_write_policy().await?;
+    pretty_assert_eq!(policy, CargoWritePolicy::unrestricted());
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn only_protected_branches_save(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    fixture
+        .client_alice
+        .cargo_cache_set_write_policy(&CargoWritePolicy::protected(["main", "release/*"]))
+        .await?;
+
+    fixture
+        .client_bob
+        .cargo_cache_save(save_request("main-hash", Some("main")))
+        .await?;
+    fixture
+        .client_bob
+        .cargo_cache_save(save_request("release-hash", Some("release/1.0")))
+        .await?;
+
+    let feature = fixture
+        .client_bob
+        .cargo_cache_save(save_request("feature-hash", Some("feature/foo")))
+        .await;
+    assert!(feature.is_err(), "feature branches should be read-only");
+    let unknown = fixture
+        .client_bob
+        .cargo_cache_save(save_request("unknown-hash", None))
+        .await;
+    assert!(unknown.is_err(), "unknown branches should be read-only");
+    let streamed = fixture
+        .client_bob
+        .cargo_cache_save_stream(save_request("streamed-hash", Some("feature/foo")))
+        .await;
+    assert!(streamed.is_err(), "streamed saves should be checked too");
+
+    let hashes = [
+        "main-hash",
+        "release-hash",
+        "feature-hash",
+        "unknown-hash",
+        "streamed-hash",
+    ]
+    .map(|hash| UnitHashVersion::CURRENT.derive(test_saved_unit(hash).info()));
+    let restored = fixture
+        .client_bob
+        .cargo_cache_restore(CargoRestoreRequest::new(hashes.clone(), None))
+        .await?;
+    let restored = restored
+        .iter()
+        .map(|(hash, _)| hash.clone())
+        .collect::<HashSet<_>>();
+    pretty_assert_eq!(
+        restored,
+        HashSet::from([hashes[0].clone(), hashes[1].clone()])
+    );
+
+    Ok(())
+}
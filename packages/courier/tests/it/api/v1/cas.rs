@@ -1,7 +1,11 @@
 //! CAS API tests.
 
+mod algorithms;
 mod bulk_read;
 mod bulk_write;
 mod check;
+mod dictionaries;
+mod direct;
 mod read;
+mod upload;
 mod write;
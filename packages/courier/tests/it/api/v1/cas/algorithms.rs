@@ -0,0 +1,95 @@
+//! CAS hash algorithm tests.
+
+use clients::courier::v1::{HashAlgorithm, Key};
+use color_eyre::Result;
+use futures::stream;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use sqlx::PgPool;
+
+use crate::helpers::{TestFixture, test_blob};
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn negotiates_sha256(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let supported = fixture.client_alice.cas_algorithms().await?;
+    pretty_assert_eq!(
+        supported.algorithms.into_iter().collect::<Vec<_>>(),
+        HashAlgorithm::ALL.to_vec()
+    );
+
+    let negotiated = fixture
+        .client_alice
+        .negotiate_hash_algorithm(HashAlgorithm::Sha256)
+        .await?;
+    pretty_assert_eq!(negotiated, HashAlgorithm::Sha256);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn sha256_roundtrip(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let content = b"hashed with sha256";
+    let key = Key::from_buffer_with(HashAlgorithm::Sha256, content);
+
+    fixture
+        .client_alice
+        .cas_write_bytes(&key, content.to_vec())
+        .await?;
+    assert!(fixture.client_alice.cas_exists(&key).await?);
+
+    let read = fixture
+        .client_alice
+        .cas_read_bytes(&key)
+        .await?
+        .expect("blob should exist");
+    pretty_assert_eq!(read.as_slice(), content);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn sha256_key_for_wrong_content(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let key = Key::from_buffer_with(HashAlgorithm::Sha256, b"expected");
+
+    let result = fixture
+        .client_alice
+        .cas_write_bytes(&key, b"actual".to_vec())
+        .await;
+    assert!(result.is_err(), "write with wrong hash should fail");
+
+    Ok(())
+}
+
+/// During a migration between algorithms, the same content is stored under
+/// both keys, and either can be read.
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn mixed_algorithms(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let content = b"same content, two algorithms".to_vec();
+    let blake3 = test_blob(&content);
+    let sha256 = Key::from_buffer_with(HashAlgorithm::Sha256, &content);
+
+    let response = fixture
+        .client_alice
+        .cas_write_bulk(stream::iter([
+            (blake3.clone(), content.clone()),
+            (sha256.clone(), content.clone()),
+        ]))
+        .await?;
+    pretty_assert_eq!(response.written.len(), 2);
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    for key in [&blake3, &sha256] {
+        let read = fixture
+            .client_alice
+            .cas_read_bytes(key)
+            .await?
+            .expect("blob should exist");
+        pretty_assert_eq!(read, content);
+    }
+
+    Ok(())
+}
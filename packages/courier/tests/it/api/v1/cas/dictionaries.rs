@@ -0,0 +1,188 @@
+//! CAS compression dictionary tests.
+
+use std::collections::BTreeMap;
+
+use clients::courier::v1::{
+    Fingerprint, Key, LibraryCrateUnitPlan, LibraryFiles, SavedFile, SavedUnit, UnitPlanInfo,
+    cache::{CargoSaveRequest, CargoSaveUnitRequest},
+};
+use color_eyre::Result;
+use courier::dictionary::{TrainConfig, TrainSummary};
+use futures::{TryStreamExt, stream};
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use sqlx::PgPool;
+
+use crate::helpers::{TestFixture, test_blob};
+
+/// Generate small, similar blobs that look like fingerprint JSON.
+fn fingerprint_blobs(range: std::ops::Range<u64>) -> BTreeMap<Key, Vec<u8>> {
+    range
+        .map(|i| {
+            let path = i * 31;
+            let blob = format!(
+                r#"{{"rustc":{i},"features":"[\"default\", \"std\"]","target":{path},"profile":{i},"deps":[],"local":[{{"CheckDepInfo":{{"dep_info":"lib/fingerprint-{i}"}}}}]}}"#
+            )
+            .into_bytes();
+            (test_blob(&blob), blob)
+        })
+        .collect()
+}
+
+/// Save a unit for the package that references the blobs, so that training
+/// can associate them with the package.
+async fn save_unit(fixture: &TestFixture, package_name: &str, keys: &[Key]) -> Result<()> {
+    let info = UnitPlanInfo::builder()
+        .unit_hash(format!("{package_name}-unit"))
+        .package_name(package_name)
+        .crate_name(package_name)
+        .maybe_target_arch(Some("x86_64-unknown-linux-gnu"))
+        .build();
+    let output_files = keys
+        .iter()
+        .map(|key| {
+            SavedFile::builder()
+                .executable(false)
+                .object_key(key)
+                .path(format!("deps/{key}"))
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let files = LibraryFiles::builder()
+        .output_files(output_files)
+        .fingerprint(Fingerprint::from("test-fingerprint"))
+        .dep_info_file(&keys[0])
+        .encoded_dep_info_file(&keys[0])
+        .build();
+    let plan = LibraryCrateUnitPlan::builder()
+        .info(info)
+        .src_path("lib.rs")
+        .outputs(vec![])
+        .build();
+    let request = CargoSaveUnitRequest::builder()
+        .unit(SavedUnit::LibraryCrate(files, plan))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .build();
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new([request]))
+        .await
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn train_and_transfer_with_dictionary(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let blobs = fingerprint_blobs(0..1024);
+    fixture
+        .client_alice
+        .cas_write_bulk(stream::iter(blobs.clone()))
+        .await?;
+    let keys = blobs.keys().cloned().collect::<Vec<_>>();
+    save_unit(&fixture, "serde", &keys).await?;
+
+    let config = TrainConfig {
+        dictionary_size: 4096,
+        ..Default::default()
+    };
+    let summary = courier::dictionary::train(&fixture.db, &fixture.storage, &config).await?;
+    pretty_assert_eq!(
+        summary,
+        TrainSummary {
+            organizations: 1,
+            dictionaries: 2,
+        }
+    );
+
+    let listing = fixture.client_alice.cas_dictionaries().await?;
+    let packages = listing
+        .dictionaries
+        .iter()
+        .map(|dictionary| dictionary.package_name.as_deref())
+        .collect::<Vec<_>>();
+    pretty_assert_eq!(packages, vec![None, Some("serde")]);
+
+    let global = listing.global().expect("global dictionary should exist");
+    let dictionary = fixture
+        .client_alice
+        .cas_dictionary(global)
+        .await?
+        .expect("dictionary content should exist");
+
+    // Reads compressed with the dictionary decompress to the original content.
+    let read = fixture
+        .client_alice
+        .cas_read_bulk_with_dictionary(&keys, Some(dictionary.clone()))
+        .await?
+        .try_collect::<BTreeMap<_, _>>()
+        .await?;
+    pretty_assert_eq!(read, blobs);
+
+    // Writes compressed with the dictionary are stored as the original content.
+    let new_blobs = fingerprint_blobs(1024..1040);
+    let response = fixture
+        .client_alice
+        .cas_write_bulk_with_dictionary(stream::iter(new_blobs.clone()), Some(dictionary))
+        .await?;
+    assert!(response.errors.is_empty(), "{response:?}");
+    let read = fixture
+        .client_alice
+        .cas_read_bulk(new_blobs.keys())
+        .await?
+        .try_collect::<BTreeMap<_, _>>()
+        .await?;
+    pretty_assert_eq!(read, new_blobs);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn dictionaries_are_isolated_by_organization(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let blobs = fingerprint_blobs(0..256);
+    fixture
+        .client_alice
+        .cas_write_bulk(stream::iter(blobs.clone()))
+        .await?;
+    let keys = blobs.keys().cloned().collect::<Vec<_>>();
+    save_unit(&fixture, "serde", &keys).await?;
+
+    let config = TrainConfig {
+        dictionary_size: 4096,
+        ..Default::default()
+    };
+    courier::dictionary::train(&fixture.db, &fixture.storage, &config).await?;
+
+    let listing = fixture.client_charlie.cas_dictionaries().await?;
+    pretty_assert_eq!(listing.dictionaries, vec![]);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn bulk_read_ignores_unknown_dictionary(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let blobs = fingerprint_blobs(0..8);
+    fixture
+        .client_alice
+        .cas_write_bulk(stream::iter(blobs.clone()))
+        .await?;
+
+    // A dictionary that the organization doesn't have is ignored, so objects
+    // are compressed without it and decompress without it too.
+    let samples = fingerprint_blobs(100..1124)
+        .into_values()
+        .collect::<Vec<_>>();
+    let content = zstd::dict::from_samples(&samples, 4096)?;
+    let dictionary = clients::courier::v1::cas::Dictionary::new(content)?;
+    let read = fixture
+        .client_alice
+        .cas_read_bulk_with_dictionary(blobs.keys(), Some(dictionary))
+        .await?
+        .try_collect::<BTreeMap<_, _>>()
+        .await?;
+    pretty_assert_eq!(read, blobs);
+
+    Ok(())
+}
@@ -0,0 +1,39 @@
+//! Direct object storage transfer endpoint tests.
+
+use clients::courier::v1::cas::CasDirectRequest;
+use color_eyre::Result;
+use futures::TryStreamExt;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use sqlx::PgPool;
+
+use crate::helpers::{TestFixture, test_blob};
+
+/// Without object storage, the client learns that direct transfers aren't
+/// available and transfers everything through the API.
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn falls_back_without_object_store(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let content = b"transferred through the API".to_vec();
+    let key = test_blob(&content);
+
+    let request = CasDirectRequest::builder()
+        .reads([&key])
+        .writes([&key])
+        .build();
+    let direct = fixture.client_alice.cas_direct(&request).await?;
+    pretty_assert_eq!(direct, None);
+
+    fixture
+        .client_alice
+        .cas_write_resumable(&key, content.clone())
+        .await?;
+    let read = fixture
+        .client_alice
+        .cas_read_bulk([&key])
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    pretty_assert_eq!(read, vec![(key, content)]);
+
+    Ok(())
+}
@@ -2,6 +2,7 @@
 
 use color_eyre::Result;
 use pretty_assertions::assert_eq as pretty_assert_eq;
+use reqwest::{StatusCode, header};
 use sqlx::PgPool;
 
 use crate::helpers::{TestFixture, test_blob};
@@ -60,6 +61,35 @@ async fn read_large_blob(pool: PgPool) -> Result<()> {
     Ok(())
 }
 
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn read_sets_cache_control(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let content = b"immutable content";
+    let key = test_blob(content);
+
+    fixture
+        .client_alice
+        .cas_write_bytes(&key, content.to_vec())
+        .await?;
+
+    let url = fixture.base_url.join(&format!("api/v1/cas/{key}"))?;
+    let response = reqwest::Client::new()
+        .get(url)
+        .bearer_auth(fixture.auth.token_alice().expose())
+        .send()
+        .await?;
+    pretty_assert_eq!(response.status(), StatusCode::OK);
+    pretty_assert_eq!(
+        response
+            .headers()
+            .get(header::CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok()),
+        Some("private, max-age=31536000, immutable")
+    );
+
+    Ok(())
+}
+
 #[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
 async fn read_missing_auth_returns_401(pool: PgPool) -> Result<()> {
     let fixture = TestFixture::spawn(pool).await?;
@@ -0,0 +1,130 @@
+//! Resumable CAS upload endpoint tests.
+
+use clients::courier::v1::cas::{CasUploadStatus, UPLOAD_OFFSET_HEADER};
+use color_eyre::Result;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use reqwest::StatusCode;
+use sqlx::PgPool;
+
+use crate::helpers::{TestFixture, test_blob};
+
+/// Content spanning several upload chunks.
+fn large_content() -> Vec<u8> {
+    (0..20 * 1024 * 1024).map(|i| (i % 251) as u8).collect()
+}
+
+/// Append a chunk of uncompressed content to an upload without the client,
+/// returning the response status and the upload's offset.
+async fn append(
+    fixture: &TestFixture,
+    key: &clients::courier::v1::Key,
+    offset: u64,
+    chunk: &[u8],
+) -> Result<(StatusCode, u64)> {
+    let url = fixture
+        .base_url
+        .join(&format!("api/v1/cas/uploads/{key}"))?;
+    let response = reqwest::Client::new()
+        .patch(url)
+        .bearer_auth(fixture.auth.token_alice().expose())
+        .header(UPLOAD_OFFSET_HEADER, offset.to_string())
+        .body(chunk.to_vec())
+        .send()
+        .await?;
+    let status = response.status();
+    let upload = response.json::<CasUploadStatus>().await?;
+    Ok((status, upload.offset))
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn resumable_write_flow(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let content = large_content();
+    let key = test_blob(&content);
+
+    fixture
+        .client_alice
+        .cas_write_resumable(&key, content.clone())
+        .await?;
+
+    let read = fixture
+        .client_alice
+        .cas_read_bytes(&key)
+        .await?
+        .expect("blob should exist");
+    pretty_assert_eq!(read.len(), content.len());
+    assert!(read == content, "read content differs from written content");
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn resumes_partial_upload(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let content = large_content();
+    let key = test_blob(&content);
+
+    // An earlier upload got partway through before it stopped.
+    let (status, offset) = append(&fixture, &key, 0, &content[..1024 * 1024]).await?;
+    pretty_assert_eq!(status, StatusCode::OK);
+    pretty_assert_eq!(offset, 1024 * 1024);
+
+    fixture
+        .client_alice
+        .cas_write_resumable(&key, content.clone())
+        .await?;
+
+    let read = fixture
+        .client_alice
+        .cas_read_bytes(&key)
+        .await?
+        .expect("blob should exist");
+    assert!(read == content, "read content differs from written content");
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn append_at_wrong_offset_conflicts(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let content = b"resumable content";
+    let key = test_blob(content);
+
+    let (status, offset) = append(&fixture, &key, 0, &content[..8]).await?;
+    pretty_assert_eq!(status, StatusCode::OK);
+    pretty_assert_eq!(offset, 8);
+
+    let (status, offset) = append(&fixture, &key, 4, &content[4..]).await?;
+    pretty_assert_eq!(status, StatusCode::CONFLICT);
+    pretty_assert_eq!(offset, 8);
+
+    let (status, offset) = append(&fixture, &key, 8, &content[8..]).await?;
+    pretty_assert_eq!(status, StatusCode::OK);
+    pretty_assert_eq!(offset, content.len() as u64);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn uploads_are_per_organization(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let content = b"resumable content";
+    let key = test_blob(content);
+    append(&fixture, &key, 0, &content[..8]).await?;
+
+    // Charlie is in another organization, so Alice's upload isn't his.
+    let url = fixture
+        .base_url
+        .join(&format!("api/v1/cas/uploads/{key}"))?;
+    let upload = reqwest::Client::new()
+        .get(url)
+        .bearer_auth(fixture.auth.token_charlie().expose())
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<CasUploadStatus>()
+        .await?;
+    pretty_assert_eq!(upload.offset, 0);
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+//! Integration tests for device authorization endpoints.
+
+use clients::courier::v1::device::DeviceTokenPoll;
+use color_eyre::Result;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use reqwest::StatusCode;
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::helpers::TestFixture;
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn approved_device_receives_working_token(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let device = fixture.client_with_token("unused")?;
+    let authorization = device.device_authorization_start("alice-laptop").await?;
+    pretty_assert_eq!(
+        authorization.verification_path,
+        format!("/device?code={}", authorization.user_code)
+    );
+
+    let poll = device
+        .device_authorization_poll(&authorization.device_code)
+        .await?;
+    pretty_assert_eq!(poll, DeviceTokenPoll::Pending);
+
+    let org_id = fixture.auth.org_acme().as_i64();
+    let response = reqwest::Client::new()
+        .post(fixture.base_url.join("api/v1/oauth/device/approve")?)
+        .bearer_auth(fixture.auth.session_alice().expose())
+        .json(&json!({
+            "user_code": authorization.user_code.to_lowercase(),
+            "org_id": org_id,
+        }))
+        .send()
+        .await?;
+    pretty_assert_eq!(response.status(), StatusCode::OK);
+
+    let DeviceTokenPoll::Approved(approved) = device
+        .device_authorization_poll(&authorization.device_code)
+        .await?
+    else {
+        panic!("authorization was not approved");
+    };
+    pretty_assert_eq!(approved.org_id, org_id);
+    fixture
+        .client_with_token(approved.token)?
+        .cargo_cache_generation()
+        .await?;
+
+    // The authorization can only be claimed once.
+    let poll = device
+        .device_authorization_poll(&authorization.device_code)
+        .await?;
+    pretty_assert_eq!(poll, DeviceTokenPoll::Expired);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn approve_for_other_org_forbidden(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let device = fixture.client_with_token("unused")?;
+    let authorization = device.device_authorization_start("charlie-laptop").await?;
+
+    // Charlie is not a member of Acme
+    let response = reqwest::Client::new()
+        .post(fixture.base_url.join("api/v1/oauth/device/approve")?)
+        .bearer_auth(fixture.auth.session_charlie().expose())
+        .json(&json!({
+            "user_code": authorization.user_code,
+            "org_id": fixture.auth.org_acme().as_i64(),
+        }))
+        .send()
+        .await?;
+    pretty_assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let poll = device
+        .device_authorization_poll(&authorization.device_code)
+        .await?;
+    pretty_assert_eq!(poll, DeviceTokenPoll::Pending);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn approve_unknown_code_not_found(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    let response = reqwest::Client::new()
+        .post(fixture.base_url.join("api/v1/oauth/device/approve")?)
+        .bearer_auth(fixture.auth.session_alice().expose())
+        .json(&json!({
+            "user_code": "BCDF-GHJK",
+            "org_id": fixture.auth.org_acme().as_i64(),
+        }))
+        .send()
+        .await?;
+    pretty_assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
@@ -136,3 +136,34 @@ async fn request_id_generated_when_not_provided(pool: PgPool) -> Result<()> {
 
     Ok(())
 }
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn connection_pool_reuses_connections(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let connections = clients::courier::v1::ConnectionPool::new()?;
+
+    // Clients with different tokens share the pool's connections.
+    let alice = connections.client(
+        fixture.base_url.clone(),
+        fixture.auth.token_alice().expose().into(),
+    );
+    let charlie = connections.client(
+        fixture.base_url.clone(),
+        fixture.auth.token_charlie().expose().into(),
+    );
+
+    alice.warm().await?;
+    pretty_assert_eq!(connections.stats().connections_opened, 1);
+    pretty_assert_eq!(connections.stats().warmups, 1);
+
+    let key = test_blob(b"pooled content");
+    alice
+        .cas_write_bytes(&key, b"pooled content".to_vec())
+        .await?;
+    charlie.ping().await?;
+    alice.cas_exists(&key).await?;
+    pretty_assert_eq!(alice.stats(), connections.stats());
+    pretty_assert_eq!(connections.stats().connections_opened, 1);
+
+    Ok(())
+}
@@ -0,0 +1,235 @@
+//! Crate registry proxy tests.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    Router,
+    extract::State,
+    http::{HeaderMap, StatusCode as AxumStatusCode, header},
+    response::IntoResponse,
+    routing::get,
+};
+use color_eyre::{Result, eyre::Context};
+use courier::registry::RegistryConfig;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use reqwest::StatusCode;
+use serde_json::Value;
+use sqlx::PgPool;
+use url::Url;
+
+use crate::helpers::TestFixture;
+
+const SERDE_INDEX: &str =
+    r#"{"name":"serde","vers":"1.0.0","deps":[],"cksum":"00","features":{},"yanked":false}"#;
+const SERDE_CRATE: &[u8] = b"not really a tarball";
+
+/// Requests received by the fake upstream registry.
+#[derive(Clone, Default)]
+struct Upstream {
+    index_fetches: Arc<AtomicUsize>,
+    index_revalidations: Arc<AtomicUsize>,
+    crate_fetches: Arc<AtomicUsize>,
+    unavailable: Arc<AtomicBool>,
+}
+
+async fn upstream_index(State(upstream): State<Upstream>, headers: HeaderMap) -> impl IntoResponse {
+    if upstream.unavailable.load(Ordering::SeqCst) {
+        return AxumStatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|etag| etag == "\"v1\"")
+    {
+        upstream.index_revalidations.fetch_add(1, Ordering::SeqCst);
+        return (AxumStatusCode::NOT_MODIFIED, [(header::ETAG, "\"v1\"")], "").into_response();
+    }
+    upstream.index_fetches.fetch_add(1, Ordering::SeqCst);
+    (AxumStatusCode::OK, [(header::ETAG, "\"v1\"")], SERDE_INDEX).into_response()
+}
+
+async fn upstream_crate(State(upstream): State<Upstream>) -> impl IntoResponse {
+    upstream.crate_fetches.fetch_add(1, Ordering::SeqCst);
+    SERDE_CRATE
+}
+
+/// Spawn a fake upstream registry serving a single version of `serde`.
+async fn spawn_upstream(upstream: Upstream) -> Result<Url> {
+    let router = Router::new()
+        .route("/index/se/rd/serde", get(upstream_index))
+        .route("/crates/serde/serde-1.0.0.crate", get(upstream_crate))
+        .with_state(upstream);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("bind upstream")?;
+    let addr = listener.local_addr()?;
+    tokio::task::spawn(async move {
+        axum::serve(listener, router)
+            .await
+            .expect("upstream server failed");
+    });
+    Ok(Url::parse(&format!("http://{addr}/"))?)
+}
+
+fn registry_config(upstream: &Url, index_ttl: Duration) -> Result<RegistryConfig> {
+    Ok(RegistryConfig {
+        index_upstream: upstream.join("index/")?,
+        download_upstream: upstream.join("crates/")?,
+        index_ttl,
+        public_url: None,
+    })
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn config_points_downloads_at_courier(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+
+    // Cargo requests the configuration before it knows to authenticate.
+    let url = fixture
+        .base_url
+        .join("api/v1/registry/crates-io/index/config.json")?;
+    let response = reqwest::get(url).await?;
+    pretty_assert_eq!(response.status(), StatusCode::OK);
+
+    let config = response.json::<Value>().await?;
+    let dl = format!(
+        "{}api/v1/registry/crates-io/crates/{{crate}}/{{version}}/download",
+        fixture.base_url
+    );
+    pretty_assert_eq!(config["dl"], Value::from(dl));
+    pretty_assert_eq!(config["auth-required"], Value::from(true));
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn index_is_cached_and_revalidated(pool: PgPool) -> Result<()> {
+    let upstream = Upstream::default();
+    let upstream_url = spawn_upstream(upstream.clone()).await?;
+    let config = registry_config(&upstream_url, Duration::ZERO)?;
+    let fixture = TestFixture::builder().registry(config).spawn(pool).await?;
+    let url = fixture
+        .base_url
+        .join("api/v1/registry/crates-io/index/se/rd/serde")?;
+    let http = reqwest::Client::new();
+
+    // Cargo sends the token without a scheme.
+    let response = http
+        .get(url.clone())
+        .header(header::AUTHORIZATION, fixture.auth.token_alice().expose())
+        .send()
+        .await?;
+    pretty_assert_eq!(response.status(), StatusCode::OK);
+    let etag = response
+        .headers()
+        .get(header::ETAG)
+        .cloned()
+        .expect("response should have an etag");
+    pretty_assert_eq!(response.text().await?, SERDE_INDEX);
+
+    // With a zero TTL, every request revalidates with upstream, and unchanged
+    // files aren't downloaded again.
+    let response = http
+        .get(url)
+        .header(header::AUTHORIZATION, fixture.auth.token_alice().expose())
+        .header(header::IF_NONE_MATCH, etag)
+        .send()
+        .await?;
+    pretty_assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    pretty_assert_eq!(upstream.index_fetches.load(Ordering::SeqCst), 1);
+    pretty_assert_eq!(upstream.index_revalidations.load(Ordering::SeqCst), 1);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn stale_index_is_served_when_upstream_is_down(pool: PgPool) -> Result<()> {
+    let upstream = Upstream::default();
+    let upstream_url = spawn_upstream(upstream.clone()).await?;
+    let config = registry_config(&upstream_url, Duration::ZERO)?;
+    let fixture = TestFixture::builder().registry(config).spawn(pool).await?;
+    let url = fixture
+        .base_url
+        .join("api/v1/registry/crates-io/index/se/rd/serde")?;
+    let http = reqwest::Client::new();
+
+    let response = http
+        .get(url.clone())
+        .bearer_auth(fixture.auth.token_alice().expose())
+        .send()
+        .await?;
+    pretty_assert_eq!(response.status(), StatusCode::OK);
+
+    upstream.unavailable.store(true, Ordering::SeqCst);
+
+    let response = http
+        .get(url)
+        .bearer_auth(fixture.auth.token_alice().expose())
+        .send()
+        .await?;
+    pretty_assert_eq!(response.status(), StatusCode::OK);
+    pretty_assert_eq!(response.text().await?, SERDE_INDEX);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn crate_is_downloaded_once(pool: PgPool) -> Result<()> {
+    let upstream = Upstream::default();
+    let upstream_url = spawn_upstream(upstream.clone()).await?;
+    let config = registry_config(&upstream_url, RegistryConfig::DEFAULT_INDEX_TTL)?;
+    let fixture = TestFixture::builder().registry(config).spawn(pool).await?;
+    let url = fixture
+        .base_url
+        .join("api/v1/registry/crates-io/crates/serde/1.0.0/download")?;
+    let http = reqwest::Client::new();
+
+    // The cache is shared across organizations.
+    for token in [fixture.auth.token_alice(), fixture.auth.token_charlie()] {
+        let response = http
+            .get(url.clone())
+            .bearer_auth(token.expose())
+            .send()
+            .await?;
+        pretty_assert_eq!(response.status(), StatusCode::OK);
+        pretty_assert_eq!(response.bytes().await?.as_ref(), SERDE_CRATE);
+    }
+    pretty_assert_eq!(upstream.crate_fetches.load(Ordering::SeqCst), 1);
+
+    let missing = fixture
+        .base_url
+        .join("api/v1/registry/crates-io/crates/serde/9.9.9/download")?;
+    let response = http
+        .get(missing)
+        .bearer_auth(fixture.auth.token_alice().expose())
+        .send()
+        .await?;
+    pretty_assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn registry_requires_auth(pool: PgPool) -> Result<()> {
+    let upstream = Upstream::default();
+    let upstream_url = spawn_upstream(upstream.clone()).await?;
+    let config = registry_config(&upstream_url, RegistryConfig::DEFAULT_INDEX_TTL)?;
+    let fixture = TestFixture::builder().registry(config).spawn(pool).await?;
+
+    for path in [
+        "api/v1/registry/crates-io/index/se/rd/serde",
+        "api/v1/registry/crates-io/crates/serde/1.0.0/download",
+    ] {
+        let response = reqwest::get(fixture.base_url.join(path)?).await?;
+        pretty_assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{path}");
+    }
+    pretty_assert_eq!(upstream.index_fetches.load(Ordering::SeqCst), 0);
+    pretty_assert_eq!(upstream.crate_fetches.load(Ordering::SeqCst), 0);
+
+    Ok(())
+}
@@ -0,0 +1,521 @@
+//! Wire protocol conformance tests between the `clients` crate and Courier.
+//!
+//! Courier and Hurry are released separately, so a Courier deployment has to
+//! serve the Hurry releases that are still in use, and Hurry releases have to
+//! work against the Courier deployments that are still running. Each
+//! directory in `tests/it/conformance` holds the exchanges recorded from one
+//! release: the requests its client sent and the responses its Courier
+//! returned, in order. These tests check both directions for every recorded
+//! release:
+//!
+//! - Old clients against the current Courier: the recorded requests are
+//!   replayed against the current Courier, whose responses must have the
+//!   recorded status and still contain everything the recorded responses did.
+//! - The current client against old Courier instances: the recorded responses
+//!   must parse with the current client's types, and the requests the current
+//!   client sends in place of the recorded ones must still contain everything
+//!   the recorded requests did.
+//!
+//! Save and restore exchanges are replayed both as JSON and as MessagePack,
+//! since Courier accepts either encoding for them.
+//!
+//! A change that fails these tests breaks compatibility with a release that's
+//! still supported. If that's intended, drop support for the release
+//! explicitly by deleting its directory in the same change.
+//!
+//! Directories are named for the date their exchanges were recorded, so that
+//! they sort oldest first. The current client has to send exactly the
+//! requests recorded for the latest release, so changes to the protocol
+//! fail until a new release is recorded with:
+//!
+//! ```not_rust
+//! COURIER_CONFORMANCE_RECORD=$(date +%F) cargo nextest run -p courier conformance::record
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use clients::{
+    ContentType,
+    courier::v1::{
+        CacheScope, Fingerprint, Key, LibraryCrateUnitPlan, LibraryFiles, SavedUnit, SavedUnitHash,
+        UnitHashVersion, UnitPlanInfo,
+        cache::{
+            CargoGenerationResponse, CargoListRequest, CargoListResponse, CargoReadOnly,
+            CargoRestoreRequest, CargoRestoreResponse, CargoSaveRequest, CargoSaveUnitRequest,
+            CargoScopeResetRequest, CargoScopeResetResponse, CargoStorageUsage, CargoWritePolicy,
+            decode_binary, encode_binary,
+        },
+    },
+};
+use color_eyre::{
+    Result,
+    eyre::{Context, bail},
+};
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use sqlx::PgPool;
+
+use crate::helpers::TestFixture;
+
+/// A request and the response Courier returned for it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Exchange {
+    method: String,
+    path: String,
+
+    /// The JSON request body, if the request had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request: Option<Value>,
+
+    status: u16,
+
+    /// The JSON response body, if the response had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response: Option<Value>,
+}
+
+impl Exchange {
+    /// An exchange that hasn't been sent yet.
+    fn new(method: Method, path: &str, request: Option<&impl Serialize>) -> Result<Self> {
+        Ok(Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            request: request.map(serde_json::to_value).transpose()?,
+            status: 0,
+            response: None,
+        })
+    }
+}
+
+/// The exchanges recorded from each release, oldest release first.
+fn releases() -> Result<Vec<(String, Vec<Exchange>)>> {
+    let mut releases = Vec::new();
+    for entry in std::fs::read_dir(fixtures_dir()).context("read fixtures directory")? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let mut files = std::fs::read_dir(entry.path())?
+            .map(|file| file.map(|file| file.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        files.retain(|file| file.extension().is_some_and(|ext| ext == "json"));
+        files.sort();
+        let exchanges = files
+            .iter()
+            .map(|file| {
+                let content = std::fs::read_to_string(file)?;
+                serde_json::from_str::<Exchange>(&content)
+                    .with_context(|| format!("parse {}", file.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let release = entry.file_name().to_string_lossy().into_owned();
+        releases.push((release, exchanges));
+    }
+    releases.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(releases)
+}
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/it/conformance")
+}
+
+/// Endpoints that also accept and answer with [`ContentType::MsgPackZstd`].
+const BINARY_PATHS: [&str; 2] = ["api/v1/cache/cargo/save", "api/v1/cache/cargo/restore"];
+
+/// How a request body is encoded when it's sent to Courier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, derive_more::Display)]
+enum Encoding {
+    #[display("JSON")]
+    Json,
+    #[display("MessagePack")]
+    Binary,
+}
+
+impl Encoding {
+    /// The encodings the exchange is replayed in.
+    fn for_exchange(exchange: &Exchange) -> &'static [Encoding] {
+        if BINARY_PATHS.contains(&exchange.path.as_str()) {
+            &[Encoding::Json, Encoding::Binary]
+        } else {
+            &[Encoding::Json]
+        }
+    }
+}
+
+/// Send the exchange's request to Courier as Alice (an Acme admin) in the
+/// provided encoding, returning the response status and body as JSON.
+async fn send(
+    fixture: &TestFixture,
+    exchange: &Exchange,
+    encoding: Encoding,
+) -> Result<(StatusCode, Option<Value>)> {
+    let method = Method::from_bytes(exchange.method.as_bytes())?;
+    let url = fixture.base_url.join(&exchange.path)?;
+    let mut request = reqwest::Client::new()
+        .request(method, url)
+        .bearer_auth(fixture.auth.token_alice().expose());
+    request = match (encoding, &exchange.request) {
+        (Encoding::Json, Some(body)) => request.json(body),
+        (Encoding::Binary, Some(body)) => request
+            .header(ContentType::HEADER, ContentType::MsgPackZstd.value())
+            .body(encode_binary(body)?),
+        (_, None) => request,
+    };
+    if encoding == Encoding::Binary {
+        request = request.header(ContentType::ACCEPT, ContentType::MsgPackZstd.value());
+    }
+
+    let response = request.send().await.context("send request")?;
+    let status = response.status();
+    let binary = response
+        .headers()
+        .get(ContentType::HEADER)
+        .is_some_and(|value| value == ContentType::MsgPackZstd);
+    let body = response.bytes().await.context("read response")?;
+    let body = match binary {
+        true => Some(decode_binary::<Value>(&body, u64::MAX).context("decode response")?),
+        false => serde_json::from_slice(&body).ok(),
+    };
+    Ok((status, body))
+}
+
+/// Check that a reader of `expected` can also read `actual`, recording any
+/// problems found.
+///
+/// Every field in `expected` has to be in `actual` with a value of the same
+/// kind. Fields that are `null` in `expected` may be missing or hold anything,
+/// since optional fields parse as `None` when they're absent.
+fn compatible(expected: &Value, actual: Option<&Value>, path: &str, problems: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Null, _) => {}
+        (_, None) => problems.push(format!("{path}: missing")),
+        (Value::Object(expected), Some(Value::Object(actual))) => {
+            for (key, value) in expected {
+                compatible(value, actual.get(key), &format!("{path}.{key}"), problems);
+            }
+        }
+        (Value::Array(expected), Some(Value::Array(actual))) => {
+            for (index, value) in expected.iter().enumerate() {
+                compatible(
+                    value,
+                    actual.get(index),
+                    &format!("{path}[{index}]"),
+                    problems,
+                );
+            }
+        }
+        (Value::Bool(_), Some(Value::Bool(_)))
+        | (Value::Number(_), Some(Value::Number(_)))
+        | (Value::String(_), Some(Value::String(_))) => {}
+        (expected, Some(actual)) => problems.push(format!(
+            "{path}: expected {}, got {}",
+            kind(expected),
+            kind(actual)
+        )),
+    }
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Check the exchange against the current client's types, returning any
+/// problems found.
+///
+/// New endpoints that have recorded exchanges need to be added here.
+fn check_client(exchange: &Exchange) -> Result<Vec<String>> {
+    match (exchange.method.as_str(), exchange.path.as_str()) {
+        ("POST", "api/v1/cache/cargo/save") => check_types::<CargoSaveRequest, ()>(exchange),
+        ("POST", "api/v1/cache/cargo/restore") => {
+            check_types::<CargoRestoreRequest, CargoRestoreResponse>(exchange)
+        }
+        ("POST", "api/v1/cache/cargo/list") => {
+            check_types::<CargoListRequest, CargoListResponse>(exchange)
+        }
+        ("POST", "api/v1/cache/cargo/reset") => check_types::<(), ()>(exchange),
+        ("POST", "api/v1/cache/cargo/reset/scope") => {
+            check_types::<CargoScopeResetRequest, CargoScopeResetResponse>(exchange)
+        }
+        ("GET", "api/v1/cache/cargo/generation") => {
+            check_types::<(), CargoGenerationResponse>(exchange)
+        }
+        ("GET", "api/v1/cache/cargo/write-policy") => check_types::<(), CargoWritePolicy>(exchange),
+        ("GET", "api/v1/cache/cargo/read-only") => check_types::<(), CargoReadOnly>(exchange),
+        ("GET", "api/v1/cache/cargo/usage") => check_types::<(), CargoStorageUsage>(exchange),
+        (method, path) => bail!("no client types for {method} {path}"),
+    }
+}
+
+/// Check that the recorded response parses as `Res`, and that the current
+/// client sends everything the recorded request contained when it sends the
+/// same request as a `Req`.
+fn check_types<Req, Res>(exchange: &Exchange) -> Result<Vec<String>>
+where
+    Req: Serialize + DeserializeOwned,
+    Res: DeserializeOwned,
+{
+    let mut problems = Vec::new();
+    if let Some(recorded) = &exchange.request {
+        let request = serde_json::from_value::<Req>(recorded.clone())
+            .context("parse recorded request with current types")?;
+        let sent = serde_json::to_value(&request)?;
+        compatible(recorded, Some(&sent), "request", &mut problems);
+    }
+    if let Some(recorded) = &exchange.response {
+        serde_json::from_value::<Res>(recorded.clone())
+            .context("parse recorded response with current types")?;
+    }
+    Ok(problems)
+}
+
+/// The exchanges recorded for the current release.
+///
+/// The unit is saved with the V1 unit hash version, which uses the unit hash
+/// verbatim, so that the recorded requests refer to it by a readable name.
+fn scenario() -> Result<Vec<(&'static str, Exchange)>> {
+    let info = UnitPlanInfo::builder()
+        .unit_hash("conformance-unit")
+        .package_name("conformance-package")
+        .crate_name("conformance_crate")
+        .maybe_target_arch(Some("x86_64-unknown-linux-gnu"))
+        .build();
+    let files = LibraryFiles::builder()
+        .output_files(vec![])
+        .fingerprint(Fingerprint::from("conformance-fingerprint"))
+        .dep_info_file(Key::from_hex("11".repeat(32))?)
+        .encoded_dep_info_file(Key::from_hex("22".repeat(32))?)
+        .build();
+    let plan = LibraryCrateUnitPlan::builder()
+        .info(info)
+        .src_path("src/lib.rs")
+        .outputs(vec![])
+        .build();
+    let unit = CargoSaveUnitRequest::builder()
+        .unit(SavedUnit::LibraryCrate(files, plan))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .unit_hash_version(UnitHashVersion::V1)
+        .build();
+    let hash = unit.saved_unit_hash();
+    let scope = CacheScope::new("conformance")?;
+
+    Ok(vec![
+        (
+            "save",
+            Exchange::new(
+                Method::POST,
+                "api/v1/cache/cargo/save",
+                Some(&CargoSaveRequest::new([unit])),
+            )?,
+        ),
+        (
+            "restore",
+            Exchange::new(
+                Method::POST,
+                "api/v1/cache/cargo/restore",
+                Some(&CargoRestoreRequest::new([hash], None)),
+            )?,
+        ),
+        (
+            "restore-miss",
+            Exchange::new(
+                Method::POST,
+                "api/v1/cache/cargo/restore",
+                Some(&CargoRestoreRequest::new(
+                    [SavedUnitHash::from("missing-unit")],
+                    None,
+                )),
+            )?,
+        ),
+        (
+            "list",
+            Exchange::new(
+                Method::POST,
+                "api/v1/cache/cargo/list",
+                Some(&CargoListRequest::default()),
+            )?,
+        ),
+        (
+            "generation",
+            Exchange::new(Method::GET, "api/v1/cache/cargo/generation", None::<&()>)?,
+        ),
+        (
+            "write-policy",
+            Exchange::new(Method::GET, "api/v1/cache/cargo/write-policy", None::<&()>)?,
+        ),
+        (
+            "read-only",
+            Exchange::new(Method::GET, "api/v1/cache/cargo/read-only", None::<&()>)?,
+        ),
+        (
+            "usage",
+            Exchange::new(Method::GET, "api/v1/cache/cargo/usage", None::<&()>)?,
+        ),
+        (
+            "reset-scope",
+            Exchange::new(
+                Method::POST,
+                "api/v1/cache/cargo/reset/scope",
+                Some(&CargoScopeResetRequest::new(scope)),
+            )?,
+        ),
+    ])
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn old_clients_against_current_courier(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let releases = releases()?;
+    assert!(!releases.is_empty(), "no releases have been recorded");
+
+    let mut problems = Vec::new();
+    for (release, exchanges) in releases {
+        // Exchanges build on the state left by the ones before them, so each
+        // release starts from an empty cache.
+        fixture.client_alice.cache_reset().await?;
+        for exchange in exchanges {
+            for &encoding in Encoding::for_exchange(&exchange) {
+                let (status, response) = send(&fixture, &exchange, encoding).await?;
+                let name = format!(
+                    "{release}: {} {} ({encoding})",
+                    exchange.method, exchange.path
+                );
+                if status.as_u16() != exchange.status {
+                    problems.push(format!(
+                        "{name}: expected status {}, got {status}",
+                        exchange.status
+                    ));
+                    continue;
+                }
+                if let Some(recorded) = &exchange.response {
+                    compatible(recorded, response.as_ref(), &name, &mut problems);
+                }
+            }
+        }
+    }
+
+    assert!(
+        problems.is_empty(),
+        "Courier is incompatible with recorded clients:\n{}",
+        problems.join("\n")
+    );
+    Ok(())
+}
+
+#[test]
+fn current_client_against_old_courier() -> Result<()> {
+    let releases = releases()?;
+    assert!(!releases.is_empty(), "no releases have been recorded");
+
+    let mut problems = Vec::new();
+    for (release, exchanges) in releases {
+        for exchange in exchanges {
+            let name = format!("{release}: {} {}", exchange.method, exchange.path);
+            match check_client(&exchange) {
+                Ok(found) => problems.extend(found.into_iter().map(|p| format!("{name}: {p}"))),
+                Err(err) => problems.push(format!("{name}: {err:#}")),
+            }
+        }
+    }
+
+    assert!(
+        problems.is_empty(),
+        "the client is incompatible with recorded Courier releases:\n{}",
+        problems.join("\n")
+    );
+    Ok(())
+}
+
+#[test]
+fn latest_release_is_current() -> Result<()> {
+    let releases = releases()?;
+    let Some((release, recorded)) = releases.last() else {
+        bail!("no releases have been recorded");
+    };
+
+    // Only requests are compared: responses depend on Courier's state, and
+    // they're checked against every release by
+    // `old_clients_against_current_courier`.
+    let requests = |exchanges: Vec<&Exchange>| {
+        exchanges
+            .into_iter()
+            .map(|exchange| {
+                (
+                    exchange.method.clone(),
+                    exchange.path.clone(),
+                    exchange.request.clone(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let scenario = scenario()?;
+    pretty_assert_eq!(
+        requests(recorded.iter().collect()),
+        requests(scenario.iter().map(|(_, exchange)| exchange).collect()),
+        "the client's requests changed since {release} was recorded; record a new release (see the module docs)"
+    );
+    Ok(())
+}
+
+/// Record the exchanges of the current release into the directory named by
+/// `COURIER_CONFORMANCE_RECORD`; does nothing if it isn't set.
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn record(pool: PgPool) -> Result<()> {
+    let Ok(release) = std::env::var("COURIER_CONFORMANCE_RECORD") else {
+        return Ok(());
+    };
+    let fixture = TestFixture::spawn(pool).await?;
+    let dir = fixtures_dir().join(release);
+    std::fs::create_dir_all(&dir)?;
+
+    for (index, (name, exchange)) in scenario()?.into_iter().enumerate() {
+        let (status, response) = send(&fixture, &exchange, Encoding::Json).await?;
+        let exchange = Exchange {
+            status: status.as_u16(),
+            response,
+            ..exchange
+        };
+        let content = serde_json::to_string_pretty(&exchange)?;
+        std::fs::write(
+            dir.join(format!("{:02}-{name}.json", index + 1)),
+            content + "\n",
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn compatibility_rules() {
+    let check = |expected: Value, actual: Value| {
+        let mut problems = Vec::new();
+        compatible(&expected, Some(&actual), "body", &mut problems);
+        problems.sort();
+        problems
+    };
+
+    // Added fields and optional fields that became absent are compatible.
+    let recorded = serde_json::json!({ "units": [{ "hash": "a" }], "next": null });
+    let current = serde_json::json!({ "units": [{ "hash": "b", "scope": "c" }] });
+    assert!(check(recorded, current).is_empty());
+
+    // Removed fields and changed kinds are not.
+    let recorded = serde_json::json!({ "units": [{ "hash": "a" }], "count": 1 });
+    let current = serde_json::json!({ "units": [{}], "count": "1" });
+    pretty_assert_eq!(
+        check(recorded, current),
+        vec![
+            String::from("body.count: expected a number, got a string"),
+            String::from("body.units[0].hash: missing"),
+        ]
+    );
+}
@@ -11,7 +11,7 @@ async fn create_api_key(pool: sqlx::PgPool) {
     let account_id = db.create_account("test@test.com", None).await.unwrap();
 
     let (key_id, token) = db
-        .create_api_key(account_id, "Test Key", org_id)
+        .create_api_key(account_id, "Test Key", org_id, false)
         .await
         .unwrap();
 
@@ -23,6 +23,7 @@ async fn create_api_key(pool: sqlx::PgPool) {
     pretty_assert_eq!(key.organization_id, org_id);
     pretty_assert_eq!(key.name, "Test Key");
     assert!(key.revoked_at.is_none());
+    assert!(!key.read_only);
 
     // Token should be 32 hex chars (16 bytes)
     pretty_assert_eq!(token.expose().len(), 32);
@@ -37,13 +38,13 @@ async fn list_org_api_keys(pool: sqlx::PgPool) {
     let account_id = db.create_account("test@test.com", None).await.unwrap();
 
     // Create keys for different orgs
-    db.create_api_key(account_id, "Org1 Key 1", org1_id)
+    db.create_api_key(account_id, "Org1 Key 1", org1_id, false)
         .await
         .unwrap();
-    db.create_api_key(account_id, "Org1 Key 2", org1_id)
+    db.create_api_key(account_id, "Org1 Key 2", org1_id, false)
         .await
         .unwrap();
-    db.create_api_key(account_id, "Org2 Key", org2_id)
+    db.create_api_key(account_id, "Org2 Key", org2_id, false)
         .await
         .unwrap();
 
@@ -62,7 +63,7 @@ async fn revoke_api_key(pool: sqlx::PgPool) {
     let account_id = db.create_account("test@test.com", None).await.unwrap();
 
     let (key_id, _token) = db
-        .create_api_key(account_id, "Test Key", org_id)
+        .create_api_key(account_id, "Test Key", org_id, false)
         .await
         .unwrap();
 
@@ -105,7 +106,7 @@ async fn disabled_account_api_key_invalid(pool: sqlx::PgPool) {
     let account_id = db.create_account("test@test.com", None).await.unwrap();
 
     let (_key_id, token) = db
-        .create_api_key(account_id, "Test Key", org_id)
+        .create_api_key(account_id, "Test Key", org_id, false)
         .await
         .unwrap();
 
@@ -127,3 +128,28 @@ async fn disabled_account_api_key_invalid(pool: sqlx::PgPool) {
         "API key should be invalid after account is disabled"
     );
 }
+
+#[sqlx::test(migrator = "Postgres::MIGRATOR")]
+async fn read_only_api_key(pool: sqlx::PgPool) {
+    use courier::auth::RawToken;
+
+    let db = Postgres { pool };
+
+    let org_id = db.create_organization("Test Org").await.unwrap();
+    let account_id = db.create_account("test@test.com", None).await.unwrap();
+
+    let (key_id, token) = db
+        .create_api_key(account_id, "Fork PRs", org_id, true)
+        .await
+        .unwrap();
+    let key = db.get_api_key(key_id).await.unwrap().unwrap();
+    assert!(key.read_only);
+
+    // Tokens validated from read-only keys stay read-only.
+    let auth = db
+        .validate(RawToken::new(token.expose()))
+        .await
+        .unwrap()
+        .expect("read-only API key should be valid");
+    assert!(auth.read_only);
+}
@@ -0,0 +1,286 @@
+//! Tests for garbage collection of unreferenced CAS objects.
+
+use std::time::Duration;
+
+use clients::courier::v1::{
+    ChunkList, Fingerprint, GlibcVersion, LibraryCrateUnitPlan, LibraryFiles, SavedUnit,
+    UnitPlanInfo,
+    cache::{CargoSaveRequest, CargoSaveUnitRequest},
+};
+use color_eyre::Result;
+use courier::gc::{GcConfig, GcSummary, collect};
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+
+use crate::helpers::{TestFixture, test_blob, test_saved_unit};
+
+const GLIBC_VERSION: GlibcVersion = GlibcVersion {
+    major: 2,
+    minor: 41,
+    patch: 0,
+};
+
+/// Content that no saved unit references.
+const UNREFERENCED: &[u8] = b"unreferenced";
+
+/// Save a unit as Alice along with the objects it references, and write an
+/// object that no unit references.
+async fn seed(fixture: &TestFixture) -> Result<()> {
+    for content in [
+        b"dep-info".as_slice(),
+        b"encoded-dep-info".as_slice(),
+        UNREFERENCED,
+    ] {
+        fixture
+            .client_alice
+            .cas_write_bytes(&test_blob(content), content.to_vec())
+            .await?;
+    }
+    let request = CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit("hash-a"))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+        .build();
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new([request]))
+        .await
+}
+
+/// Make every object look like it was created and last accessed long ago.
+async fn age_objects(fixture: &TestFixture) -> Result<()> {
+    sqlx::query(
+        "UPDATE cas_key SET created_at = NOW() - INTERVAL '60 days', last_accessed_at = NOW() - INTERVAL '60 days'",
+    )
+    .execute(&fixture.db.pool)
+    .await?;
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn deletes_old_unreferenced_objects(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    seed(&fixture).await?;
+    age_objects(&fixture).await?;
+
+    let summary = collect(&fixture.db, &fixture.storage, &GcConfig::default()).await?;
+    pretty_assert_eq!(summary.units_checked, 1);
+    pretty_assert_eq!(summary.objects_referenced, 2);
+    pretty_assert_eq!(summary.objects_deleted, 1);
+    pretty_assert_eq!(summary.orphans_deleted, 0);
+
+    assert!(!fixture.storage.exists(&test_blob(UNREFERENCED)).await?);
+    assert!(fixture.storage.exists(&test_blob(b"dep-info")).await?);
+    let content = fixture
+        .client_alice
+        .cas_read_bytes(&test_blob(UNREFERENCED))
+        .await?;
+    pretty_assert_eq!(content, None);
+    let content = fixture
+        .client_alice
+        .cas_read_bytes(&test_blob(b"dep-info"))
+        .await?;
+    pretty_assert_eq!(content, Some(b"dep-info".to_vec()));
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn keeps_recent_objects(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    seed(&fixture).await?;
+
+    let summary = collect(&fixture.db, &fixture.storage, &GcConfig::default()).await?;
+    pretty_assert_eq!(summary.objects_deleted, 0);
+    assert!(fixture.storage.exists(&test_blob(UNREFERENCED)).await?);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn reading_an_object_keeps_it(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    seed(&fixture).await?;
+    age_objects(&fixture).await?;
+
+    let content = fixture
+        .client_alice
+        .cas_read_bytes(&test_blob(UNREFERENCED))
+        .await?;
+    pretty_assert_eq!(content, Some(UNREFERENCED.to_vec()));
+
+    let summary = collect(&fixture.db, &fixture.storage, &GcConfig::default()).await?;
+    pretty_assert_eq!(summary.objects_deleted, 0);
+    assert!(fixture.storage.exists(&test_blob(UNREFERENCED)).await?);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn dry_run_deletes_nothing(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    seed(&fixture).await?;
+    age_objects(&fixture).await?;
+
+    let config = GcConfig {
+        dry_run: true,
+        ..Default::default()
+    };
+    let summary = collect(&fixture.db, &fixture.storage, &config).await?;
+    pretty_assert_eq!(
+        summary,
+        GcSummary {
+            units_checked: 1,
+            objects_referenced: 2,
+            objects_deleted: 1,
+            orphans_deleted: 0,
+            bytes_deleted: fixture
+                .storage
+                .size_compressed(&test_blob(UNREFERENCED))
+                .await?
+                .unwrap_or_default(),
+        }
+    );
+    assert!(fixture.storage.exists(&test_blob(UNREFERENCED)).await?);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn rejects_short_retention(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let config = GcConfig {
+        retention: Duration::from_secs(60),
+        ..Default::default()
+    };
+    assert!(
+        collect(&fixture.db, &fixture.storage, &config)
+            .await
+            .is_err()
+    );
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn keeps_chunks_of_referenced_chunk_lists(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let chunks = [b"chunk-a".as_slice(), b"chunk-b".as_slice()];
+    for chunk in chunks {
+        fixture
+            .client_alice
+            .cas_write_bytes(&test_blob(chunk), chunk.to_vec())
+            .await?;
+    }
+    let content = chunks.concat();
+    let list = ChunkList::new(
+        test_blob(&content),
+        content.len() as u64,
+        chunks.iter().map(|chunk| test_blob(chunk)).collect(),
+    );
+    fixture
+        .client_alice
+        .cas_write_bytes(&list.key(), list.encode())
+        .await?;
+    fixture
+        .client_alice
+        .cas_write_bytes(&test_blob(b"dep-info"), b"dep-info".to_vec())
+        .await?;
+
+    // The unit references the chunked object by its chunk list.
+    let info = UnitPlanInfo::builder()
+        .unit_hash("chunked-hash")
+        .package_name("test-package")
+        .crate_name("test_crate")
+        .maybe_target_arch(Some("x86_64-unknown-linux-gnu"))
+        .build();
+    let files = LibraryFiles::builder()
+        .output_files(vec![])
+        .fingerprint(Fingerprint::from("test-fingerprint"))
+        .dep_info_file(test_blob(b"dep-info"))
+        .encoded_dep_info_file(list.key())
+        .build();
+    let plan = LibraryCrateUnitPlan::builder()
+        .info(info)
+        .src_path("test.rs")
+        .outputs(vec![])
+        .build();
+    let request = CargoSaveUnitRequest::builder()
+        .unit(SavedUnit::LibraryCrate(files, plan))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .build();
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new([request]))
+        .await?;
+    age_objects(&fixture).await?;
+
+    let summary = collect(&fixture.db, &fixture.storage, &GcConfig::default()).await?;
+    pretty_assert_eq!(summary.objects_referenced, 4);
+    pretty_assert_eq!(summary.objects_deleted, 0);
+
+    // The object still restores from its chunks.
+    let mut restored = Vec::new();
+    for chunk in &list.chunks {
+        let chunk = fixture.client_alice.cas_read_bytes(chunk).await?;
+        restored.push(chunk.expect("chunk should still be stored"));
+    }
+    pretty_assert_eq!(list.assemble(restored)?, content);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn keeps_objects_accessed_after_selection(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    seed(&fixture).await?;
+    age_objects(&fixture).await?;
+
+    let cutoff = OffsetDateTime::now_utc() - GcConfig::DEFAULT_RETENTION;
+    let candidates = fixture.db.gc_candidates(cutoff, 0, 100).await?;
+    let candidate = candidates
+        .iter()
+        .find(|candidate| candidate.key == test_blob(UNREFERENCED))
+        .expect("unreferenced object should be a candidate");
+
+    // A client reads the object between selecting it and deleting it.
+    let content = fixture
+        .client_alice
+        .cas_read_bytes(&test_blob(UNREFERENCED))
+        .await?;
+    pretty_assert_eq!(content, Some(UNREFERENCED.to_vec()));
+
+    let deletion = fixture.db.gc_delete_cas_key(candidate.id, cutoff).await?;
+    assert!(deletion.is_none(), "accessed objects should not be deleted");
+    assert!(fixture.storage.exists(&test_blob(UNREFERENCED)).await?);
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn uncommitted_deletion_keeps_key(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    seed(&fixture).await?;
+    age_objects(&fixture).await?;
+
+    let cutoff = OffsetDateTime::now_utc() - GcConfig::DEFAULT_RETENTION;
+    let candidates = fixture.db.gc_candidates(cutoff, 0, 100).await?;
+    let candidate = candidates
+        .iter()
+        .find(|candidate| candidate.key == test_blob(UNREFERENCED))
+        .expect("unreferenced object should be a candidate");
+
+    // If removing the file fails, the deletion is dropped without being
+    // committed, which leaves the key for a later run.
+    let deletion = fixture.db.gc_delete_cas_key(candidate.id, cutoff).await?;
+    drop(deletion.expect("unaccessed object should be deleted"));
+    let candidates = fixture.db.gc_candidates(cutoff, 0, 100).await?;
+    assert!(
+        candidates
+            .iter()
+            .any(|candidate| candidate.key == test_blob(UNREFERENCED)),
+        "the key should be kept"
+    );
+
+    Ok(())
+}
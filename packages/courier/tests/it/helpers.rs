@@ -7,6 +7,7 @@ use std::collections::HashMap;
 
 use aerosol::Aero;
 use async_tempfile::TempDir;
+use bon::bon;
 use clients::{
     Token,
     courier::v1::{
@@ -17,9 +18,16 @@ use clients::{
 };
 use color_eyre::{Result, eyre::Context};
 use courier::{
+    access::AccessTokens,
     api,
     auth::{AccountId, OrgId, OrgRole, RawToken, SessionToken},
-    db, oauth, storage,
+    db,
+    lanes::{Lanes, LanesConfig},
+    maintenance::ReadOnly,
+    oauth,
+    quota::StorageQuota,
+    registry::{Registry, RegistryConfig},
+    storage,
 };
 use futures::{StreamExt, TryStreamExt, stream};
 use sqlx::PgPool;
@@ -52,16 +60,46 @@ pub struct TestFixture {
     /// Database connection for direct queries in tests.
     pub db: db::Postgres,
 
+    /// CAS storage used by the server, for direct access in tests.
+    pub storage: storage::Disk,
+
     /// Temporary directory that will be cleaned up after the test.
     pub _temp: TempDir,
 }
 
+#[bon]
 impl TestFixture {
     /// Spawn a new test server with isolated database and storage.
     ///
     /// The database pool should come from the `#[sqlx::test]` macro, which
     /// provides an isolated database for each test.
     pub async fn spawn(pool: PgPool) -> Result<Self> {
+        Self::builder().spawn(pool).await
+    }
+
+    /// Configure a test server that differs from the one
+    /// [`TestFixture::spawn`] starts, then start it with `spawn(pool)`.
+    #[builder(builder_type = TestFixtureBuilder, finish_fn = spawn)]
+    pub async fn builder(
+        #[builder(finish_fn)] pool: PgPool,
+
+        /// The configuration of the crate registry proxy, for tests that need
+        /// a fake upstream registry.
+        #[builder(default)]
+        registry: RegistryConfig,
+
+        /// Whether the server is read-only, and why.
+        #[builder(default = ReadOnly::writable())]
+        read_only: ReadOnly,
+
+        /// The configuration for issuing access tokens, if the server issues
+        /// them.
+        access_tokens: Option<AccessTokens>,
+
+        /// The storage each organization is limited to by default.
+        #[builder(default = StorageQuota::unlimited())]
+        storage_quota: StorageQuota,
+    ) -> Result<Self> {
         let db = db::Postgres { pool };
         let auth = TestAuth::seed(&db).await?;
         let (storage, _temp) = storage::Disk::new_temp()
@@ -69,9 +107,18 @@ impl TestFixture {
             .context("create temp storage")?;
         // In tests, we don't configure GitHub OAuth (tests use API keys)
         let github = None::<oauth::GitHub>;
-        let state = Aero::new().with(github).with(storage).with(db.clone());
+        let registry = Registry::new(registry).context("create registry")?;
+        let state = Aero::new()
+            .with(storage_quota)
+            .with(access_tokens)
+            .with(read_only)
+            .with(registry)
+            .with(github)
+            .with(storage.clone())
+            .with(db.clone());
         // Tests don't need CORS (not browser-based) or console serving
-        let router = api::router(state, vec![], None);
+        let lanes = Lanes::new(LanesConfig::default());
+        let router = api::router(state, lanes, vec![], None);
 
         let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
             .await
@@ -104,6 +151,7 @@ impl TestFixture {
             client_charlie,
             auth,
             db,
+            storage,
             _temp,
         })
     }
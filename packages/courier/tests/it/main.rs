@@ -4,8 +4,12 @@
 //! server, ensuring that the API works as expected from a client's perspective.
 
 mod api;
+mod conformance;
 mod crypto;
 mod db;
+mod gc;
 mod helpers;
+mod storage;
+mod sync;
 
 pub use helpers::*;
@@ -0,0 +1,133 @@
+//! Tests for storing the CAS in an S3-compatible bucket.
+//!
+//! These run against the bucket named by `COURIER_TEST_OBJECT_STORE_BUCKET` at
+//! `COURIER_TEST_OBJECT_STORE_ENDPOINT` (e.g. MinIO, which CI runs), with the
+//! access key in `COURIER_TEST_OBJECT_STORE_ACCESS_KEY_ID` and
+//! `COURIER_TEST_OBJECT_STORE_SECRET_ACCESS_KEY`; they do nothing if those
+//! aren't set. Each test works under its own prefix in the bucket.
+
+use std::time::Duration;
+
+use clients::courier::v1::Key;
+use color_eyre::{Result, eyre::Context};
+use courier::storage::{Disk, ObjectStoreConfig, StorageConfig, UploadAppend};
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use reqwest::StatusCode;
+use tokio::io::AsyncReadExt;
+use url::Url;
+
+/// Open storage under a new prefix in the test bucket, if one is configured.
+fn open_bucket() -> Result<Option<Disk>> {
+    let var = |name: &str| std::env::var(format!("COURIER_TEST_OBJECT_STORE_{name}")).ok();
+    let (Some(endpoint), Some(bucket), Some(access_key_id), Some(secret_access_key)) = (
+        var("ENDPOINT"),
+        var("BUCKET"),
+        var("ACCESS_KEY_ID"),
+        var("SECRET_ACCESS_KEY"),
+    ) else {
+        return Ok(None);
+    };
+
+    let config = ObjectStoreConfig {
+        endpoint: Url::parse(&endpoint).context("parse endpoint")?,
+        bucket: String::new(),
+        prefix: None,
+        region: var("REGION").unwrap_or_else(|| String::from("us-east-1")),
+        access_key_id,
+        secret_access_key,
+        url_ttl: ObjectStoreConfig::DEFAULT_URL_TTL,
+        min_read_bytes: 0,
+    };
+    let storage =
+        format!("s3:{bucket}/courier-test-{}", uuid::Uuid::new_v4()).parse::<StorageConfig>()?;
+    storage.open(Some(&config)).map(Some)
+}
+
+async fn read_all(disk: &Disk, key: &Key) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    disk.read(key).await?.read_to_end(&mut content).await?;
+    Ok(content)
+}
+
+#[tokio::test]
+async fn reads_and_writes_objects() -> Result<()> {
+    let Some(disk) = open_bucket()? else {
+        return Ok(());
+    };
+    disk.ping().await?;
+
+    let content = b"stored in the bucket".repeat(1024);
+    let key = Key::from_buffer(&content);
+    assert!(!disk.exists(&key).await?);
+    disk.write(&key, content.as_slice()).await?;
+    assert!(disk.exists(&key).await?);
+    pretty_assert_eq!(disk.size(&key).await?, Some(content.len() as u64));
+    pretty_assert_eq!(read_all(&disk, &key).await?, content);
+
+    // Writing an object that already exists leaves it as it was.
+    disk.write(&key, content.as_slice()).await?;
+    pretty_assert_eq!(read_all(&disk, &key).await?, content);
+
+    let mismatched = Key::from_buffer(b"something else");
+    assert!(disk.write(&mismatched, content.as_slice()).await.is_err());
+    assert!(!disk.exists(&mismatched).await?);
+
+    assert!(disk.list().await?.contains(&key));
+    assert!(disk.modified(&key).await?.is_some());
+    assert!(disk.remove(&key).await?.is_some());
+    assert!(!disk.exists(&key).await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn completes_resumable_uploads() -> Result<()> {
+    let Some(disk) = open_bucket()? else {
+        return Ok(());
+    };
+
+    let content = b"uploaded in parts".repeat(1024);
+    let key = Key::from_buffer(&content);
+    let (first, second) = content.split_at(1000);
+    let appended = disk.append_upload("1", &key, 0, first).await?;
+    pretty_assert_eq!(appended, UploadAppend::Appended(1000));
+    let conflict = disk.append_upload("1", &key, 0, second).await?;
+    pretty_assert_eq!(conflict, UploadAppend::Conflict(1000));
+    let appended = disk.append_upload("1", &key, 1000, second).await?;
+    pretty_assert_eq!(appended, UploadAppend::Appended(content.len() as u64));
+
+    assert!(disk.complete_upload("1", &key).await?);
+    pretty_assert_eq!(read_all(&disk, &key).await?, content);
+    pretty_assert_eq!(disk.upload_offset("1", &key).await?, 0);
+    pretty_assert_eq!(disk.remove_stale_uploads(Duration::ZERO).await?, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn transfers_directly_with_presigned_urls() -> Result<()> {
+    let Some(disk) = open_bucket()? else {
+        return Ok(());
+    };
+    let client = reqwest::Client::new();
+
+    // The client writes the uncompressed content as the upload, which Courier
+    // then completes.
+    let content = b"transferred directly".repeat(1024);
+    let key = Key::from_buffer(&content);
+    let url = disk
+        .presign_upload("1", &key)?
+        .expect("bucket storage presigns uploads");
+    let response = client.put(url).body(content.clone()).send().await?;
+    pretty_assert_eq!(response.status(), StatusCode::OK);
+    pretty_assert_eq!(disk.upload_offset("1", &key).await?, content.len() as u64);
+    assert!(disk.complete_upload("1", &key).await?);
+
+    // Reads get the compressed object.
+    let url = disk
+        .presign_read(&key)?
+        .expect("bucket storage presigns reads");
+    let response = client.get(url).send().await?;
+    pretty_assert_eq!(response.status(), StatusCode::OK);
+    let compressed = response.bytes().await?;
+    pretty_assert_eq!(zstd::decode_all(compressed.as_ref())?, content);
+    Ok(())
+}
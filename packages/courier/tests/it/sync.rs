@@ -0,0 +1,143 @@
+//! Tests for syncing cached units between Courier instances.
+//!
+//! These sync between two organizations on the same server, which exercises
+//! the same API calls as syncing between two servers.
+
+use async_tempfile::TempDir;
+use clients::courier::v1::{
+    GlibcVersion,
+    cache::{CargoRestoreRequest, CargoSaveRequest, CargoSaveUnitRequest},
+};
+use color_eyre::Result;
+use courier::sync::{SyncConfig, SyncSummary, sync};
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use sqlx::PgPool;
+
+use crate::helpers::{TestFixture, test_blob, test_saved_unit};
+
+const GLIBC_VERSION: GlibcVersion = GlibcVersion {
+    major: 2,
+    minor: 41,
+    patch: 0,
+};
+
+/// Save a unit as Alice, along with the objects it references.
+async fn save_unit(fixture: &TestFixture, hash: &str) -> Result<()> {
+    for content in [b"dep-info".as_slice(), b"encoded-dep-info".as_slice()] {
+        fixture
+            .client_alice
+            .cas_write_bytes(&test_blob(content), content.to_vec())
+            .await?;
+    }
+    let request = CargoSaveUnitRequest::builder()
+        .unit(test_saved_unit(hash))
+        .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+        .maybe_linux_glibc_version(Some(GLIBC_VERSION))
+        .build();
+    fixture
+        .client_alice
+        .cargo_cache_save(CargoSaveRequest::new([request]))
+        .await
+}
+
+fn config(fixture: &TestFixture) -> SyncConfig {
+    SyncConfig {
+        from: fixture.client_alice.clone(),
+        to: fixture.client_charlie.clone(),
+        state: None,
+        dry_run: false,
+        page_size: 1,
+        concurrency: 4,
+    }
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn sync_copies_units_and_objects(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    save_unit(&fixture, "hash-a").await?;
+    save_unit(&fixture, "hash-b").await?;
+
+    let summary = sync(&config(&fixture)).await?;
+    pretty_assert_eq!(
+        summary,
+        SyncSummary {
+            units_listed: 2,
+            units_present: 0,
+            units_copied: 2,
+            units_incomplete: 0,
+            objects_copied: 2,
+            bytes_copied: (b"dep-info".len() + b"encoded-dep-info".len()) as u64,
+        }
+    );
+
+    let request = CargoRestoreRequest::new(["hash-a", "hash-b"], Some(GLIBC_VERSION));
+    let restored = fixture.client_charlie.cargo_cache_restore(request).await?;
+    pretty_assert_eq!(restored.len(), 2);
+    let content = fixture
+        .client_charlie
+        .cas_read_bytes(&test_blob(b"dep-info"))
+        .await?;
+    pretty_assert_eq!(content, Some(b"dep-info".to_vec()));
+
+    // Syncing again finds everything already copied.
+    let summary = sync(&config(&fixture)).await?;
+    pretty_assert_eq!(
+        summary,
+        SyncSummary {
+            units_listed: 2,
+            units_present: 2,
+            ..Default::default()
+        }
+    );
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn sync_dry_run_copies_nothing(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    save_unit(&fixture, "hash-a").await?;
+
+    let config = SyncConfig {
+        dry_run: true,
+        ..config(&fixture)
+    };
+    let summary = sync(&config).await?;
+    pretty_assert_eq!(
+        summary,
+        SyncSummary {
+            units_listed: 1,
+            units_copied: 1,
+            objects_copied: 2,
+            ..Default::default()
+        }
+    );
+
+    let request = CargoRestoreRequest::new(["hash-a"], Some(GLIBC_VERSION));
+    let restored = fixture.client_charlie.cargo_cache_restore(request).await?;
+    assert!(restored.is_empty());
+
+    Ok(())
+}
+
+#[sqlx::test(migrator = "courier::db::Postgres::MIGRATOR")]
+async fn sync_resumes_from_state(pool: PgPool) -> Result<()> {
+    let fixture = TestFixture::spawn(pool).await?;
+    let temp = TempDir::new().await?;
+    let config = SyncConfig {
+        state: Some(temp.dir_path().join("sync.json")),
+        ..config(&fixture)
+    };
+
+    save_unit(&fixture, "hash-a").await?;
+    let summary = sync(&config).await?;
+    pretty_assert_eq!(summary.units_copied, 1);
+
+    // Only the unit saved since the last sync is listed.
+    save_unit(&fixture, "hash-b").await?;
+    let summary = sync(&config).await?;
+    pretty_assert_eq!(summary.units_listed, 1);
+    pretty_assert_eq!(summary.units_copied, 1);
+
+    Ok(())
+}
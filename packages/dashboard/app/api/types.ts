@@ -43,6 +43,7 @@ export type OrgApiKeyEntry = {
   account_id: number;
   account_email: string;
   bot: boolean;
+  read_only: boolean;
   created_at: string;
   accessed_at: string;
 };
@@ -55,6 +56,7 @@ export type CreateOrgApiKeyResponse = {
   id: number;
   name: string;
   token: string;
+  read_only: boolean;
   created_at: string;
 };
 
@@ -129,3 +131,7 @@ export type AuditLogListResponse = {
   entries: AuditLogEntry[];
   has_more: boolean;
 };
+
+export type ApproveDeviceResponse = {
+  client_name: string;
+};
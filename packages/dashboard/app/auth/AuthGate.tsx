@@ -14,7 +14,7 @@ const PUBLIC_ROUTES = ["/auth", "/invite"];
  * Routes that require authentication but render without the app shell.
  * Used for full-page experiences like onboarding.
  */
-const SHELLLESS_ROUTES = ["/onboarding"];
+const SHELLLESS_ROUTES = ["/onboarding", "/device"];
 
 function isPublicRoute(pathname: string): boolean {
   return PUBLIC_ROUTES.some((route) => pathname.startsWith(route));
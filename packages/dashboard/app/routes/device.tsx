@@ -0,0 +1,121 @@
+import { useEffect, useState } from "react";
+import { useNavigate, useSearchParams } from "react-router";
+
+import type { ApproveDeviceResponse } from "../api/types";
+import { useApi } from "../api/useApi";
+import { useOrgs } from "../org/OrgContext";
+import { Button } from "../ui/primitives/Button";
+import { Input } from "../ui/primitives/Input";
+import { Label } from "../ui/primitives/Label";
+import { Noise } from "../ui/primitives/Noise";
+import { useToast } from "../ui/toast/ToastProvider";
+
+export default function DevicePage() {
+  const nav = useNavigate();
+  const toast = useToast();
+  const [searchParams] = useSearchParams();
+  const { request } = useApi();
+  const { orgs, lastOrgId } = useOrgs();
+  const [userCode, setUserCode] = useState(searchParams.get("code") ?? "");
+  const [orgId, setOrgId] = useState<number | null>(null);
+  const [approving, setApproving] = useState(false);
+  const [approved, setApproved] = useState<string | null>(null);
+
+  useEffect(() => {
+    if (orgId !== null || !orgs || orgs.length === 0) return;
+    setOrgId(orgs.find((org) => org.id === lastOrgId)?.id ?? orgs[0].id);
+  }, [orgId, orgs, lastOrgId]);
+
+  async function approve() {
+    if (orgId === null) return;
+    setApproving(true);
+    try {
+      const out = await request<ApproveDeviceResponse>({
+        path: "/api/v1/oauth/device/approve",
+        method: "POST",
+        body: { user_code: userCode.trim(), org_id: orgId },
+      });
+      setApproved(out.client_name);
+    } catch (e) {
+      if (e && typeof e === "object" && "status" in e && (e as { status: number }).status === 401) return;
+      const msg = e && typeof e === "object" && "message" in e ? String((e as { message: unknown }).message) : "";
+      toast.push({ kind: "error", title: "Approval failed", detail: msg });
+    } finally {
+      setApproving(false);
+    }
+  }
+
+  return (
+    <Noise className="fixed inset-0 flex items-center justify-center">
+      <div className="w-full max-w-md px-6">
+        {/* Brand */}
+        <div className="mb-8 flex items-center justify-center gap-3">
+          <div className="grid h-11 w-11 place-items-center rounded-xl border border-border bg-surface-subtle shadow-glow-soft">
+            <span className="text-2xl font-bold bg-linear-to-br from-attune-300 to-attune-500 bg-clip-text text-transparent">
+              A
+            </span>
+          </div>
+          <div className="text-xl font-semibold text-content-primary">Hurry</div>
+        </div>
+
+        {/* Device card */}
+        <div className="rounded-2xl border border-border bg-surface-raised shadow-glow-soft backdrop-blur">
+          <div className="border-b border-border px-6 py-4">
+            <div className="text-base font-semibold text-content-primary">Authorize device</div>
+            <div className="mt-1 text-sm text-content-tertiary">
+              Check that the code matches the one shown in your terminal.
+            </div>
+          </div>
+
+          <div className="p-6">
+            {approved ? (
+              <div className="space-y-4">
+                <div className="text-sm text-content-primary">
+                  <span className="font-semibold">{approved}</span> is authorized. You can return to your terminal.
+                </div>
+                <Button variant="secondary" onClick={() => nav("/")}>
+                  Go to console
+                </Button>
+              </div>
+            ) : (
+              <div className="space-y-4">
+                <div>
+                  <Label htmlFor="userCode">Code</Label>
+                  <Input
+                    id="userCode"
+                    value={userCode}
+                    onChange={(e) => setUserCode(e.target.value)}
+                    placeholder="e.g. BCDF-GHJK"
+                  />
+                </div>
+                <div>
+                  <Label htmlFor="org">Organization</Label>
+                  <select
+                    id="org"
+                    className="h-10 w-full cursor-pointer rounded-xl border border-border bg-surface-subtle px-3 text-sm text-content-primary focus:border-border-accent-hover focus:bg-surface-subtle-hover focus:outline-none"
+                    value={orgId ?? ""}
+                    onChange={(e) => setOrgId(Number(e.target.value))}
+                  >
+                    {(orgs ?? []).map((org) => (
+                      <option key={org.id} value={org.id}>
+                        {org.name}
+                      </option>
+                    ))}
+                  </select>
+                </div>
+                <div className="flex gap-2">
+                  <Button onClick={approve} disabled={!userCode.trim() || orgId === null || approving}>
+                    Authorize
+                  </Button>
+                  <Button variant="secondary" onClick={() => nav("/")}>
+                    Cancel
+                  </Button>
+                </div>
+              </div>
+            )}
+          </div>
+        </div>
+      </div>
+    </Noise>
+  );
+}
@@ -3,6 +3,7 @@ import { useCallback, useEffect, useMemo, useState } from "react";
 
 import type { CreateOrgApiKeyResponse, OrgApiKeyListResponse } from "../api/types";
 import { useApi } from "../api/useApi";
+import { Badge } from "../ui/primitives/Badge";
 import { Button } from "../ui/primitives/Button";
 import { Card, CardBody, CardHeader } from "../ui/primitives/Card";
 import { CodeBlock } from "../ui/primitives/CodeBlock";
@@ -20,6 +21,7 @@ export default function OrgApiKeysPage() {
   const [loading, setLoading] = useState(false);
   const [createOpen, setCreateOpen] = useState(false);
   const [name, setName] = useState("");
+  const [readOnly, setReadOnly] = useState(false);
   const [created, setCreated] = useState<CreateOrgApiKeyResponse | null>(null);
 
   const keys = useMemo(() => data?.api_keys ?? [], [data]);
@@ -54,10 +56,11 @@ export default function OrgApiKeysPage() {
       const out = await request<CreateOrgApiKeyResponse>({
         path: `/api/v1/organizations/${orgId}/api-keys`,
         method: "POST",
-        body: { name: n },
+        body: { name: n, read_only: readOnly },
       });
       setCreated(out);
       setName("");
+      setReadOnly(false);
       await load();
     } catch (e) {
       if (e && typeof e === "object" && "status" in e && (e as { status: number }).status === 401) return;
@@ -121,6 +124,7 @@ export default function OrgApiKeysPage() {
                       <div className="flex items-center gap-2 font-medium text-content-primary">
                         <KeyRound className="h-4 w-4 text-accent-text" />
                         {k.name}
+                        {k.read_only ? <Badge>read-only</Badge> : null}
                       </div>
                     </td>
                     <td className="py-3 pr-3 text-content-secondary">
@@ -167,6 +171,18 @@ export default function OrgApiKeysPage() {
               placeholder="ci-key"
             />
           </div>
+          <label className="flex items-start gap-2 text-sm text-content-secondary">
+            <input
+              type="checkbox"
+              className="mt-0.5"
+              checked={readOnly}
+              onChange={(e) => setReadOnly(e.target.checked)}
+            />
+            <span>
+              Read-only: builds using this key restore from the cache but never upload to it. Use this
+              for untrusted builds, like pull requests from forks.
+            </span>
+          </label>
           <div className="flex justify-end gap-2">
             <Button variant="secondary" onClick={() => setCreateOpen(false)}>
               Cancel
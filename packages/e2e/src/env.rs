@@ -1,4 +1,4 @@
-use std::{fs::File, process::Command};
+use std::{fs::File, path::PathBuf, process::Command};
 
 use color_eyre::{
     Result,
@@ -9,6 +9,8 @@ use testcontainers::compose::DockerCompose;
 use tracing::{debug, info, instrument};
 use workspace_root::get_workspace_root;
 
+use crate::Toolchain;
+
 /// Test environment with ephemeral Docker Compose stack (Postgres + Courier +
 /// Hurry).
 ///
@@ -30,6 +32,12 @@ use workspace_root::get_workspace_root;
 ///   stack)
 ///
 /// Single-container tests should use [`TestEnv::HURRY_INSTANCE_1`].
+///
+/// ## Toolchains
+///
+/// [`TestEnv::new`] uses the toolchain of the default `rust` image. Use
+/// [`TestEnv::with_toolchain`] to run the hurry containers with a different
+/// [`Toolchain`]; each toolchain is built into its own images.
 pub struct TestEnv {
     compose: DockerCompose,
 }
@@ -41,18 +49,86 @@ impl TestEnv {
     /// Service name for the second hurry container instance.
     pub const HURRY_INSTANCE_2: &str = "hurry-2";
 
+    /// Service name for the hurry container that can only reach courier
+    /// through a proxy: [`TestEnv::HTTP_PROXY_URL`] or
+    /// [`TestEnv::SOCKS_PROXY_URL`].
+    pub const HURRY_INSTANCE_PROXIED: &str = "hurry-proxied";
+
+    /// URL of the HTTP proxy, reachable from every hurry container.
+    pub const HTTP_PROXY_URL: &str = "http://proxy-http:3128";
+
+    /// URL of the SOCKS5 proxy, reachable from every hurry container.
+    ///
+    /// This uses `socks5h` so that the proxy resolves hostnames: the proxied
+    /// container can't resolve `courier` on its own.
+    pub const SOCKS_PROXY_URL: &str = "socks5h://proxy-socks:1080";
+
+    /// The hurry services, whose images depend on the toolchain.
+    const HURRY_SERVICES: [&str; 3] = [
+        Self::HURRY_INSTANCE_1,
+        Self::HURRY_INSTANCE_2,
+        Self::HURRY_INSTANCE_PROXIED,
+    ];
+
+    /// The compose files for the stack.
+    ///
+    /// Toolchains other than the default are applied by an override file
+    /// that's generated in the target directory, which builds the hurry
+    /// services into images tagged for the toolchain so that stacks for
+    /// different toolchains don't replace each other's images.
+    #[instrument]
+    fn compose_files(toolchain: Option<&Toolchain>) -> Result<Vec<PathBuf>> {
+        let workspace_root = get_workspace_root();
+        let base = workspace_root.join("docker-compose.e2e.yml");
+        let Some(toolchain) = toolchain else {
+            return Ok(vec![base]);
+        };
+
+        let tag = toolchain.tag();
+        let dir = workspace_root.join("target").join("docker-compose-e2e");
+        std::fs::create_dir_all(&dir).context("create compose override directory")?;
+        let path = dir.join(format!("{tag}.yml"));
+        std::fs::write(&path, Self::compose_override(toolchain))
+            .context("write compose override")?;
+        Ok(vec![base, path])
+    }
+
+    /// Render the compose override for a toolchain.
+    fn compose_override(toolchain: &Toolchain) -> String {
+        let tag = toolchain.tag();
+        let mut rendered = String::from("services:\n");
+        for service in Self::HURRY_SERVICES {
+            rendered.push_str(&format!("    {service}:\n"));
+            rendered.push_str(&format!("        image: hurry-e2e:{tag}\n"));
+            rendered.push_str("        build:\n");
+            rendered.push_str("            args:\n");
+            rendered.push_str(&format!(
+                "                BASE_IMAGE: {:?}\n",
+                toolchain.base_image
+            ));
+            rendered.push_str(&format!(
+                "                RUST_TOOLCHAIN: {:?}\n",
+                toolchain.rust
+            ));
+        }
+        rendered
+    }
+
     /// Ensure Docker Compose images are built.
     ///
     /// Uses file-based locking to coordinate builds across multiple test
     /// processes. Only builds images once, even when tests run in parallel
     /// via cargo nextest.
     #[instrument]
-    async fn ensure_built() -> Result<()> {
+    async fn ensure_built(toolchain: Option<&Toolchain>, compose_files: &[PathBuf]) -> Result<()> {
         let workspace_root = get_workspace_root();
-        let compose_file = workspace_root.join("docker-compose.e2e.yml");
 
         // Get working tree hash to include uncommitted changes
         let hash = crate::container::working_tree_hash(&workspace_root)?;
+        let hash = match toolchain {
+            Some(toolchain) => format!("{hash}_{}", toolchain.tag()),
+            None => hash,
+        };
 
         // Create marker and lock files in target directory with hash suffix
         let target_dir = workspace_root.join("target");
@@ -78,10 +154,12 @@ impl TestEnv {
         }
 
         info!("building docker compose images for hash {hash}...");
-        let status = Command::new("docker")
-            .arg("compose")
-            .arg("-f")
-            .arg(&compose_file)
+        let mut command = Command::new("docker");
+        command.arg("compose");
+        for file in compose_files {
+            command.arg("-f").arg(file);
+        }
+        let status = command
             .arg("build")
             .status()
             .context("execute docker compose build")?;
@@ -114,18 +192,35 @@ impl TestEnv {
     /// The entire stack is automatically cleaned up when TestEnv is dropped.
     #[instrument]
     pub async fn new() -> Result<Self> {
-        Self::ensure_built().await.context("build compose stack")?;
+        Self::start(None).await
+    }
+
+    /// Create a new test environment whose hurry containers use the provided
+    /// toolchain.
+    ///
+    /// This is otherwise the same as [`TestEnv::new`].
+    #[instrument]
+    pub async fn with_toolchain(toolchain: &Toolchain) -> Result<Self> {
+        Self::start(Some(toolchain)).await
+    }
+
+    async fn start(toolchain: Option<&Toolchain>) -> Result<Self> {
+        let compose_files = Self::compose_files(toolchain)?;
+        Self::ensure_built(toolchain, &compose_files)
+            .await
+            .context("build compose stack")?;
 
-        // Get workspace root and construct path to compose file
         info!("starting docker compose stack...");
-        let workspace_root = get_workspace_root();
-        let compose_file = workspace_root.join("docker-compose.e2e.yml");
-        let compose_file = compose_file
-            .to_str()
-            .ok_or_else(|| eyre!("invalid compose file path"))?;
+        let compose_files = compose_files
+            .iter()
+            .map(|file| {
+                file.to_str()
+                    .ok_or_else(|| eyre!("invalid compose file path: {file:?}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         // Images were already built via `ensure_built`, so we can just start.
-        let mut compose = DockerCompose::with_local_client(&[compose_file]);
+        let mut compose = DockerCompose::with_local_client(compose_files.as_slice());
 
         // The compose file has health checks built in, so we don't have to.
         compose.up().await?;
@@ -5,6 +5,9 @@ use cargo_metadata::{Artifact, Message, PackageId};
 pub trait MessageIterExt<'a> {
     /// Iterate over the third-party artifacts in the set of messages.
     fn thirdparty_artifacts(self) -> impl Iterator<Item = &'a Artifact>;
+
+    /// Iterate over the lines of output that were not valid JSON messages.
+    fn text_lines(self) -> impl Iterator<Item = &'a String>;
 }
 
 impl<'a, I> MessageIterExt<'a> for I
@@ -26,6 +29,13 @@ where
             _ => None,
         })
     }
+
+    fn text_lines(self) -> impl Iterator<Item = &'a String> {
+        self.into_iter().filter_map(|m| match &m {
+            Message::TextLine(line) => Some(line),
+            _ => None,
+        })
+    }
 }
 
 pub trait ArtifactIterExt<'a> {
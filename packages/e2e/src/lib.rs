@@ -35,9 +35,13 @@ pub mod command;
 pub mod container;
 pub mod env;
 pub mod ext;
+pub mod report;
+pub mod toolchain;
 
 pub use build::*;
 pub use command::*;
 pub use env::*;
+pub use report::*;
+pub use toolchain::*;
 
 static GITHUB_TOKEN: LazyLock<Option<String>> = LazyLock::new(|| var("GITHUB_TOKEN").ok());
@@ -0,0 +1,64 @@
+//! Summaries of how much of a build was restored from the cache.
+
+use std::fmt;
+
+use cargo_metadata::Message;
+
+use crate::ext::MessageIterExt;
+
+/// How many of the third-party units in a build were restored from the
+/// cache, derived from the build's Cargo messages.
+///
+/// First-party units are always rebuilt after a fresh checkout (their sources
+/// have new mtimes), so only third-party units are counted.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct CacheReport {
+    /// The number of third-party units in the build.
+    pub units: usize,
+
+    /// The number of third-party units that Cargo considered fresh.
+    pub fresh: usize,
+}
+
+impl CacheReport {
+    /// Summarize the messages of a build.
+    pub fn from_messages(messages: &[Message]) -> Self {
+        messages
+            .iter()
+            .thirdparty_artifacts()
+            .fold(Self::default(), |report, artifact| Self {
+                units: report.units + 1,
+                fresh: report.fresh + usize::from(artifact.fresh),
+            })
+    }
+
+    /// The number of third-party units that Cargo had to compile.
+    pub fn recompiled(&self) -> usize {
+        self.units - self.fresh
+    }
+
+    /// The fraction of third-party units that were fresh.
+    ///
+    /// This is zero for builds without third-party units, so that asserting
+    /// a minimum ratio also catches builds that didn't produce messages.
+    pub fn hit_ratio(&self) -> f64 {
+        if self.units == 0 {
+            0.0
+        } else {
+            self.fresh as f64 / self.units as f64
+        }
+    }
+}
+
+impl fmt::Display for CacheReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} units fresh ({:.1}%), {} recompiled",
+            self.fresh,
+            self.units,
+            self.hit_ratio() * 100.0,
+            self.recompiled()
+        )
+    }
+}
@@ -0,0 +1,138 @@
+use bon::Builder;
+
+/// The Rust toolchain and OS base image of the hurry containers in a
+/// [`TestEnv`](crate::TestEnv).
+///
+/// Hurry depends on details of Cargo's target directory layout and of the
+/// glibc that units are compiled against, both of which vary across toolchains
+/// and operating systems. Running the same tests against several of them is
+/// how compatibility with each is continuously exercised; see
+/// [`toolchain_matrix!`](crate::toolchain_matrix) for generating the tests.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Builder)]
+#[builder(start_fn = new, finish_fn = finish)]
+pub struct Toolchain {
+    /// The toolchain used for builds in the containers, in any form accepted
+    /// by `rustup toolchain install` (e.g. `stable` or `1.85.0`).
+    #[builder(into)]
+    pub rust: String,
+
+    /// The image the containers are based on.
+    ///
+    /// This must be Debian-based with rustup installed, like the official
+    /// `rust` images.
+    #[builder(into, default = String::from(Toolchain::DEFAULT_BASE_IMAGE))]
+    pub base_image: String,
+}
+
+impl Toolchain {
+    /// The base image used when none is specified.
+    pub const DEFAULT_BASE_IMAGE: &str = "rust:latest";
+
+    /// The oldest toolchain Hurry supports building with.
+    ///
+    /// Hurry reads the host target with `--print host-tuple`, which was added
+    /// in 1.84, and test projects use the 2024 edition, which was stabilized
+    /// in 1.85.
+    pub const OLDEST_SUPPORTED: &str = "1.85.0";
+
+    /// The latest stable toolchain on the default base image.
+    pub fn stable() -> Self {
+        Self::new().rust("stable").finish()
+    }
+
+    /// The oldest supported toolchain on the default base image.
+    pub fn oldest_supported() -> Self {
+        Self::new().rust(Self::OLDEST_SUPPORTED).finish()
+    }
+
+    /// The latest stable toolchain on Debian bookworm, whose glibc is older
+    /// than the default base image's.
+    pub fn bookworm() -> Self {
+        Self::new()
+            .rust("stable")
+            .base_image("rust:bookworm")
+            .finish()
+    }
+
+    /// A name for the toolchain that's safe to use in image tags and file
+    /// names.
+    pub fn tag(&self) -> String {
+        format!("{}-{}", self.rust, self.base_image)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect()
+    }
+}
+
+/// Generate a test for each toolchain in a matrix.
+///
+/// The test is written as an async function that takes the [`Toolchain`] to
+/// test with; the macro generates a module of the same name containing one
+/// `#[test_log::test(tokio::test)]` per toolchain, so the calling crate needs
+/// `test-log` and `tokio` as dependencies.
+///
+/// Without an explicit matrix, tests run against [`Toolchain::stable`], plus
+/// [`Toolchain::oldest_supported`] and [`Toolchain::bookworm`] when the `ci`
+/// feature is enabled (each toolchain needs its own images, which are slow to
+/// build).
+///
+/// ```ignore
+/// e2e::toolchain_matrix! {
+///     async fn builds(toolchain: Toolchain) -> Result<()> {
+///         let env = TestEnv::with_toolchain(&toolchain).await?;
+///         // ...
+///         Ok(())
+///     }
+/// }
+///
+/// // Or with an explicit matrix:
+/// e2e::toolchain_matrix! {
+///     [
+///         stable => Toolchain::stable(),
+///         nightly => Toolchain::new().rust("nightly").finish(),
+///     ]
+///     async fn builds(toolchain: Toolchain) -> Result<()> {
+///         // ...
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! toolchain_matrix {
+    (
+        $(#[$meta:meta])*
+        async fn $name:ident($toolchain:ident: Toolchain) -> Result<()> $body:block
+    ) => {
+        $crate::toolchain_matrix! {
+            [
+                stable => $crate::Toolchain::stable(),
+                #[cfg(feature = "ci")]
+                oldest_supported => $crate::Toolchain::oldest_supported(),
+                #[cfg(feature = "ci")]
+                bookworm => $crate::Toolchain::bookworm(),
+            ]
+            $(#[$meta])*
+            async fn $name($toolchain: Toolchain) -> Result<()> $body
+        }
+    };
+    (
+        [$($(#[$variant_meta:meta])* $variant:ident => $value:expr),+ $(,)?]
+        $(#[$meta:meta])*
+        async fn $name:ident($toolchain:ident: Toolchain) -> Result<()> $body:block
+    ) => {
+        $(#[$meta])*
+        async fn $name($toolchain: $crate::Toolchain) -> ::color_eyre::Result<()> $body
+
+        mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            $(
+                $(#[$variant_meta])*
+                #[test_log::test(tokio::test)]
+                async fn $variant() -> ::color_eyre::Result<()> {
+                    super::$name($value).await
+                }
+            )+
+        }
+    };
+}
@@ -0,0 +1,104 @@
+//! Exercises caching on a corpus of real-world projects.
+//!
+//! Each project is built twice: once cold, then again from the cache on a
+//! clean checkout in a different container. The second build has to meet a
+//! minimum cache hit ratio and recompile no more than a maximum number of
+//! third-party units, which catches regressions that only show up in large
+//! dependency graphs.
+//!
+//! Thresholds are set per project: they should be as strict as the project
+//! currently allows, and tightened whenever a fix makes more units cacheable.
+//!
+//! The small projects run with the `ci` feature; the large ones take hours
+//! and only run with the `corpus` feature.
+
+use std::{path::PathBuf, time::Instant};
+
+use color_eyre::{Result, eyre::bail};
+use e2e::{Build, CacheReport, Command, TestEnv};
+use simple_test_case::test_case;
+use tracing::info;
+
+/// Builds a project cold and then from the cache, asserting on how much of
+/// the second build was restored.
+#[test_case("attunehq", "hurry-tests", "test/tiny", None, 1.0, 0; "attunehq/hurry-tests:test/tiny")]
+#[cfg_attr(feature = "ci", test_case("attunehq", "attune", "main", None, 1.0, 0; "attunehq/attune:main"))]
+#[cfg_attr(feature = "ci", test_case("attunehq", "hurry", "main", None, 1.0, 0; "attunehq/hurry:main"))]
+#[cfg_attr(feature = "corpus", test_case("astral-sh", "ruff", "0.6.9", Some("ruff"), 0.98, 10; "astral-sh/ruff:0.6.9"))]
+#[cfg_attr(feature = "corpus", test_case("meilisearch", "meilisearch", "v1.10.0", Some("meilisearch"), 0.95, 25; "meilisearch/meilisearch:v1.10.0"))]
+#[test_log::test(tokio::test)]
+async fn cache_hits(
+    username: &str,
+    repo: &str,
+    branch: &str,
+    package: Option<&str>,
+    min_hit_ratio: f64,
+    max_recompiled: usize,
+) -> Result<()> {
+    color_eyre::install()?;
+
+    // Check for GITHUB_TOKEN early to fail fast with a clear error message
+    if std::env::var("GITHUB_TOKEN").is_err() {
+        bail!(
+            "GITHUB_TOKEN environment variable is required to clone repositories from GitHub. \
+             Please set it to a personal access token with 'repo' scope."
+        );
+    }
+
+    let env = TestEnv::new().await?;
+    let pwd = PathBuf::from("/workspace");
+
+    let mut reports = Vec::with_capacity(2);
+    for service in [TestEnv::HURRY_INSTANCE_1, TestEnv::HURRY_INSTANCE_2] {
+        // Each build gets its own checkout so that nothing is reused through
+        // the filesystem; the cache is only shared through courier.
+        let repo_root = pwd.join(format!("{repo}-{service}"));
+        Command::clone_github()
+            .pwd(&pwd)
+            .user(username)
+            .repo(repo)
+            .branch(branch)
+            .dir(&repo_root)
+            .finish()
+            .run_compose(env.service(service)?)
+            .await?;
+
+        let start = Instant::now();
+        let messages = Build::new()
+            .pwd(&repo_root)
+            .maybe_package(package)
+            .wrapper(Build::HURRY_NAME)
+            .api_url(env.api_url())
+            .api_token(env.test_token())
+            .finish()
+            .run_compose(env.service(service)?)
+            .await?;
+        let elapsed = start.elapsed();
+
+        let report = CacheReport::from_messages(&messages);
+        info!(%repo, %branch, %service, ?elapsed, %report, "built corpus project");
+        reports.push(report);
+    }
+
+    let [cold, warm] = reports[..] else {
+        unreachable!("built exactly twice");
+    };
+    assert!(
+        cold.units > 0,
+        "cold build should have third-party units (this likely means --message-format is missing)"
+    );
+    assert_eq!(
+        cold.units, warm.units,
+        "both builds should have the same third-party units"
+    );
+    assert!(
+        warm.hit_ratio() >= min_hit_ratio,
+        "cache hit ratio should be at least {min_hit_ratio}: {warm}"
+    );
+    assert!(
+        warm.recompiled() <= max_recompiled,
+        "at most {max_recompiled} units should be recompiled: {warm}"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,111 @@
+//! Exercises caching `cdylib` workspace members for FFI consumers.
+//!
+//! Python wheels and Android apps load the `cdylib` that Cargo links, so a
+//! restored one has to be the complete library: this builds a workspace
+//! member, restores it into a clean build directory, and loads it with
+//! `dlopen` the way an FFI consumer would.
+
+use std::path::PathBuf;
+
+use cargo_metadata::Message;
+use color_eyre::Result;
+use e2e::{Build, Command, TestEnv};
+use itertools::Itertools;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+
+/// A library that exports a C function, with first-party caching enabled.
+const SETUP: &str = r#"
+set -e
+cargo new --lib --name ffi ffi
+cd ffi
+printf '\n[lib]\ncrate-type = ["cdylib"]\n' >> Cargo.toml
+printf '[first-party]\ncache = true\n' > hurry.toml
+cat > src/lib.rs <<'EOF'
+#[unsafe(no_mangle)]
+pub extern "C" fn hurry_answer() -> u32 {
+    42
+}
+EOF
+cat > load.c <<'EOF'
+#include <dlfcn.h>
+#include <stdio.h>
+
+int main(int argc, char **argv) {
+    void *lib = dlopen(argv[1], RTLD_NOW);
+    if (!lib) {
+        fprintf(stderr, "dlopen: %s\n", dlerror());
+        return 1;
+    }
+    unsigned (*answer)(void) = dlsym(lib, "hurry_answer");
+    if (!answer) {
+        fprintf(stderr, "dlsym: %s\n", dlerror());
+        return 1;
+    }
+    return answer() == 42 ? 0 : 1;
+}
+EOF
+cc -o load load.c -ldl
+"#;
+
+#[test_log::test(tokio::test)]
+async fn restored_cdylib_loads() -> Result<()> {
+    color_eyre::install()?;
+
+    let env = TestEnv::new().await?;
+    let container = env.service(TestEnv::HURRY_INSTANCE_1)?;
+
+    let pwd = PathBuf::from("/workspace");
+    let root = pwd.join("ffi");
+    Command::new()
+        .pwd(&pwd)
+        .name("sh")
+        .arg("-c")
+        .arg(SETUP)
+        .finish()
+        .run_compose(&container)
+        .await?;
+
+    // The first build saves the library, and the second restores it.
+    Build::new()
+        .pwd(&root)
+        .wrapper(Build::HURRY_NAME)
+        .api_url(env.api_url())
+        .api_token(env.test_token())
+        .finish()
+        .run_compose(&container)
+        .await?;
+    Command::cargo_clean(&root).run_compose(&container).await?;
+    let messages = Build::new()
+        .pwd(&root)
+        .wrapper(Build::HURRY_NAME)
+        .api_url(env.api_url())
+        .api_token(env.test_token())
+        .finish()
+        .run_compose(&container)
+        .await?;
+
+    let freshness = messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::CompilerArtifact(artifact) if artifact.target.name == "ffi" => {
+                Some(artifact.fresh)
+            }
+            _ => None,
+        })
+        .collect_vec();
+    pretty_assert_eq!(
+        freshness,
+        vec![true],
+        "the cdylib should be restored: {messages:?}"
+    );
+
+    Command::new()
+        .pwd(&root)
+        .name("./load")
+        .arg("target/debug/libffi.so")
+        .finish()
+        .run_compose(&container)
+        .await?;
+
+    Ok(())
+}
@@ -4,7 +4,13 @@ use e2e::{Build, Command, TestEnv};
 
 use color_eyre::{Result, eyre::Context};
 
+pub mod corpus;
+pub mod ffi;
+pub mod message_format;
+pub mod proxy;
+pub mod restore;
 pub mod thirdparty;
+pub mod toolchains;
 
 #[test_log::test(tokio::test)]
 async fn run_compose() -> Result<()> {
@@ -0,0 +1,87 @@
+//! Exercises Hurry's handling of Cargo's JSON message stream.
+//!
+//! Tools like `cargo-llvm-cov` run builds with `--message-format=json` and
+//! parse stdout as a stream of JSON messages, so Hurry must not write anything
+//! of its own to stdout.
+
+use std::path::PathBuf;
+
+use color_eyre::{Result, eyre::bail};
+use e2e::{Build, Command, TestEnv, ext::MessageIterExt};
+use itertools::Itertools;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+
+/// Builds with and without restoring from the cache, since both paths report
+/// progress while Cargo's messages are being streamed.
+#[test_log::test(tokio::test)]
+async fn json_stdout_only_contains_cargo_messages() -> Result<()> {
+    color_eyre::install()?;
+
+    // Check for GITHUB_TOKEN early to fail fast with a clear error message
+    if std::env::var("GITHUB_TOKEN").is_err() {
+        bail!(
+            "GITHUB_TOKEN environment variable is required to clone repositories from GitHub. \
+             Please set it to a personal access token with 'repo' scope."
+        );
+    }
+
+    // Start test environment with courier
+    let env = TestEnv::new().await?;
+
+    let pwd = PathBuf::from("/workspace");
+    let repo_root = pwd.join("hurry-tests");
+    Command::clone_github()
+        .pwd(&pwd)
+        .user("attunehq")
+        .repo("hurry-tests")
+        .branch("test/tiny")
+        .finish()
+        .run_compose(env.service(TestEnv::HURRY_INSTANCE_1)?)
+        .await?;
+
+    // The first build populates the cache.
+    let messages = Build::new()
+        .pwd(&repo_root)
+        .wrapper(Build::HURRY_NAME)
+        .api_url(env.api_url())
+        .api_token(env.test_token())
+        .finish()
+        .run_compose(env.service(TestEnv::HURRY_INSTANCE_1)?)
+        .await?;
+    let text = messages.iter().text_lines().collect::<Vec<_>>();
+    pretty_assert_eq!(
+        text,
+        Vec::<&String>::new(),
+        "stdout should only contain JSON messages"
+    );
+
+    // The second build restores from the cache.
+    Command::cargo_clean(&repo_root)
+        .run_compose(env.service(TestEnv::HURRY_INSTANCE_1)?)
+        .await?;
+    let messages = Build::new()
+        .pwd(&repo_root)
+        .wrapper(Build::HURRY_NAME)
+        .api_url(env.api_url())
+        .api_token(env.test_token())
+        .finish()
+        .run_compose(env.service(TestEnv::HURRY_INSTANCE_1)?)
+        .await?;
+    let text = messages.iter().text_lines().collect::<Vec<_>>();
+    pretty_assert_eq!(
+        text,
+        Vec::<&String>::new(),
+        "stdout should only contain JSON messages"
+    );
+    let fresh = messages
+        .iter()
+        .thirdparty_artifacts()
+        .filter(|artifact| artifact.fresh)
+        .collect_vec();
+    assert!(
+        !fresh.is_empty(),
+        "build should have restored third-party artifacts"
+    );
+
+    Ok(())
+}
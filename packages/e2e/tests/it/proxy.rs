@@ -0,0 +1,129 @@
+//! Exercises reaching the Hurry API through HTTP and SOCKS5 proxies.
+//!
+//! These tests build in the `hurry-proxied` container, which can only reach
+//! courier through a proxy; a build that restores from the cache proves that
+//! both the CLI and the daemon sent their requests through it.
+
+use std::path::PathBuf;
+
+use color_eyre::{Result, eyre::bail};
+use e2e::{
+    Build, Command, TestEnv,
+    ext::{ArtifactIterExt, MessageIterExt},
+};
+use itertools::Itertools;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use simple_test_case::test_case;
+
+/// How the proxy is configured for hurry.
+#[derive(Clone, Copy, Debug)]
+enum ProxySetup {
+    /// `HTTP_PROXY` in the environment.
+    HttpEnv,
+
+    /// `ALL_PROXY` in the environment, pointing at the SOCKS5 proxy.
+    SocksEnv,
+
+    /// `hurry.toml` in the workspace, pointing at the SOCKS5 proxy.
+    SocksConfig,
+}
+
+impl ProxySetup {
+    fn envs(self) -> Vec<(&'static str, &'static str)> {
+        match self {
+            ProxySetup::HttpEnv => vec![("HTTP_PROXY", TestEnv::HTTP_PROXY_URL)],
+            ProxySetup::SocksEnv => vec![("ALL_PROXY", TestEnv::SOCKS_PROXY_URL)],
+            ProxySetup::SocksConfig => vec![],
+        }
+    }
+}
+
+#[test_case(ProxySetup::HttpEnv; "http proxy from env")]
+#[test_case(ProxySetup::SocksEnv; "socks proxy from env")]
+#[test_case(ProxySetup::SocksConfig; "socks proxy from hurry.toml")]
+#[test_log::test(tokio::test)]
+async fn build_through_proxy(setup: ProxySetup) -> Result<()> {
+    color_eyre::install()?;
+
+    // Check for GITHUB_TOKEN early to fail fast with a clear error message
+    if std::env::var("GITHUB_TOKEN").is_err() {
+        bail!(
+            "GITHUB_TOKEN environment variable is required to clone repositories from GitHub. \
+             Please set it to a personal access token with 'repo' scope."
+        );
+    }
+
+    let env = TestEnv::new().await?;
+    let container = env.service(TestEnv::HURRY_INSTANCE_PROXIED)?;
+
+    let pwd = PathBuf::from("/workspace");
+    let repo_root = pwd.join("hurry-tests");
+    Command::clone_github()
+        .pwd(&pwd)
+        .user("attunehq")
+        .repo("hurry-tests")
+        .branch("test/tiny")
+        .finish()
+        .run_compose(&container)
+        .await?;
+    if let ProxySetup::SocksConfig = setup {
+        let config = format!("[proxy]\nurl = \"{}\"\n", TestEnv::SOCKS_PROXY_URL);
+        Command::new()
+            .pwd(&repo_root)
+            .name("sh")
+            .arg("-c")
+            .arg(format!("printf '{config}' > hurry.toml"))
+            .finish()
+            .run_compose(&container)
+            .await?;
+    }
+
+    // The first build uploads through the proxy, and the second build
+    // restores through it.
+    Build::new()
+        .pwd(&repo_root)
+        .wrapper(Build::HURRY_NAME)
+        .api_url(env.api_url())
+        .api_token(env.test_token())
+        .envs(setup.envs())
+        .finish()
+        .run_compose(&container)
+        .await?;
+    Command::cargo_clean(&repo_root)
+        .run_compose(&container)
+        .await?;
+    let messages = Build::new()
+        .pwd(&repo_root)
+        .wrapper(Build::HURRY_NAME)
+        .api_url(env.api_url())
+        .api_token(env.test_token())
+        .envs(setup.envs())
+        .finish()
+        .run_compose(&container)
+        .await?;
+
+    let expected = messages
+        .iter()
+        .thirdparty_artifacts()
+        .package_ids()
+        .map(|id| (id, true))
+        .sorted()
+        .collect::<Vec<_>>();
+    let freshness = messages
+        .iter()
+        .thirdparty_artifacts()
+        .freshness()
+        .sorted()
+        .collect::<Vec<_>>();
+    pretty_assert_eq!(
+        expected,
+        freshness,
+        "all artifacts should be fresh: {messages:?}"
+    );
+    assert!(
+        !expected.is_empty(),
+        "build should have third-party artifacts"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,275 @@
+//! Exercises the restore options of `hurry cargo build`.
+
+use std::path::{Path, PathBuf};
+
+use cargo_metadata::{Message, PackageId};
+use color_eyre::{
+    Result,
+    eyre::{OptionExt as _, bail},
+};
+use e2e::{
+    Build, Command, TestEnv,
+    ext::{ArtifactIterExt, MessageIterExt},
+};
+use itertools::Itertools;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+use simple_test_case::test_case;
+
+/// A package whose build script writes 1024 nested files (64 MiB) into
+/// OUT_DIR, under a directory named after the time it ran, with first-party
+/// caching enabled.
+///
+/// Cargo doesn't clear OUT_DIR before rerunning a build script, so if a
+/// partially restored OUT_DIR were left behind for Cargo, the rerun would add
+/// a second directory next to the restored one.
+const OUT_DIR_SETUP: &str = r#"
+set -e
+cargo new --lib --name outdir outdir
+cd outdir
+printf '[first-party]\ncache = true\n' > hurry.toml
+cat > build.rs <<'EOF'
+use std::{env, fs, path::PathBuf, time::SystemTime};
+
+fn main() {
+    println!("cargo::rerun-if-changed=build.rs");
+    let nonce = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let root = PathBuf::from(env::var("OUT_DIR").unwrap()).join(nonce.to_string());
+    let mut state = nonce as u64 | 1;
+    for a in 0..32 {
+        let dir = root.join(format!("a{a}"));
+        fs::create_dir_all(&dir).unwrap();
+        for b in 0..32 {
+            let data = (0..8192)
+                .flat_map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state.to_le_bytes()
+                })
+                .collect::<Vec<_>>();
+            fs::write(dir.join(format!("b{b}.bin")), data).unwrap();
+        }
+    }
+}
+EOF
+"#;
+
+/// Prints the number of files in OUT_DIR, the number of entries at its top
+/// level, and the number of restore staging directories left behind.
+const OUT_DIR_CHECK: &str = r#"
+find target/debug/build -path '*/out/*' -type f | wc -l
+find target/debug/build -path '*/out/*' -prune | wc -l
+find target/debug/build -name out.hurry-restore | wc -l
+"#;
+
+/// Clone the test repository and build it once so that its third-party
+/// dependencies are in the cache, then clean the target directory so that the
+/// next build has to restore them.
+async fn populate_cache(env: &TestEnv, container: &str) -> Result<PathBuf> {
+    // Check for GITHUB_TOKEN early to fail fast with a clear error message
+    if std::env::var("GITHUB_TOKEN").is_err() {
+        bail!(
+            "GITHUB_TOKEN environment variable is required to clone repositories from GitHub. \
+             Please set it to a personal access token with 'repo' scope."
+        );
+    }
+
+    let pwd = PathBuf::from("/workspace");
+    let repo_root = pwd.join("hurry-tests");
+    Command::clone_github()
+        .pwd(&pwd)
+        .user("attunehq")
+        .repo("hurry-tests")
+        .branch("test/tiny")
+        .finish()
+        .run_compose(container)
+        .await?;
+    Build::new()
+        .pwd(&repo_root)
+        .wrapper(Build::HURRY_NAME)
+        .api_url(env.api_url())
+        .api_token(env.test_token())
+        .finish()
+        .run_compose(container)
+        .await?;
+    Command::cargo_clean(&repo_root)
+        .run_compose(container)
+        .await?;
+    Ok(repo_root)
+}
+
+async fn build_messages(
+    env: &TestEnv,
+    container: &str,
+    repo_root: &Path,
+    args: &[&str],
+) -> Result<Vec<Message>> {
+    Build::new()
+        .pwd(repo_root)
+        .wrapper(Build::HURRY_NAME)
+        .api_url(env.api_url())
+        .api_token(env.test_token())
+        .additional_args(args.iter().copied())
+        .finish()
+        .run_compose(container)
+        .await
+}
+
+async fn build_with(
+    env: &TestEnv,
+    container: &str,
+    repo_root: &Path,
+    args: &[&str],
+) -> Result<Vec<(PackageId, bool)>> {
+    let messages = build_messages(env, container, repo_root, args).await?;
+    let freshness = messages
+        .iter()
+        .thirdparty_artifacts()
+        .freshness()
+        .map(|(id, fresh)| (id.clone(), fresh))
+        .sorted()
+        .collect::<Vec<_>>();
+    assert!(
+        !freshness.is_empty(),
+        "build should have third-party artifacts: {messages:?}"
+    );
+    Ok(freshness)
+}
+
+/// Builds that restore the cache before starting Cargo find every third-party
+/// artifact fresh.
+#[test_log::test(tokio::test)]
+async fn restores_before_compiling() -> Result<()> {
+    color_eyre::install()?;
+
+    let env = TestEnv::new().await?;
+    let container = env.service(TestEnv::HURRY_INSTANCE_1)?;
+    let repo_root = populate_cache(&env, &container).await?;
+
+    let freshness = build_with(&env, &container, &repo_root, &[]).await?;
+    let expected = freshness
+        .iter()
+        .map(|(id, _)| (id.clone(), true))
+        .collect::<Vec<_>>();
+    pretty_assert_eq!(freshness, expected, "all artifacts should be fresh");
+
+    Ok(())
+}
+
+/// Builds that start Cargo while restore is running hand units to Cargo as
+/// they're restored. Cargo reports the units it was handed as compiled rather
+/// than fresh, so the build's stats show that they were restored, and the next
+/// build finds every third-party artifact fresh.
+#[test_case(&["--hurry-overlap-restore"]; "overlapped")]
+#[test_case(&["--hurry-overlap-restore", "--hurry-restore-timeout", "600"]; "overlapped with timeout")]
+#[test_log::test(tokio::test)]
+async fn hands_off_units_while_compiling(args: &[&str]) -> Result<()> {
+    color_eyre::install()?;
+
+    let env = TestEnv::new().await?;
+    let container = env.service(TestEnv::HURRY_INSTANCE_1)?;
+    let repo_root = populate_cache(&env, &container).await?;
+
+    let args = args
+        .iter()
+        .copied()
+        .chain(["--hurry-stats-format", "json"])
+        .collect::<Vec<_>>();
+    let messages = build_messages(&env, &container, &repo_root, &args).await?;
+    let stats = messages
+        .iter()
+        .text_lines()
+        .find_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .ok_or_eyre("build should print its stats")?;
+    let restored = stats
+        .get("restored")
+        .and_then(serde_json::Value::as_u64)
+        .ok_or_eyre("stats should count restored units")?;
+    let units = stats
+        .get("units")
+        .and_then(serde_json::Value::as_u64)
+        .ok_or_eyre("stats should count units")?;
+    pretty_assert_eq!(restored, units, "every unit should be restored: {stats}");
+
+    let freshness = build_with(&env, &container, &repo_root, &["--hurry-skip-restore"]).await?;
+    let expected = freshness
+        .iter()
+        .map(|(id, _)| (id.clone(), true))
+        .collect::<Vec<_>>();
+    pretty_assert_eq!(freshness, expected, "all artifacts should be fresh");
+
+    Ok(())
+}
+
+/// When the restore timeout passes before anything is restored, the build
+/// falls back to Cargo building every unit instead of failing.
+#[test_case(&["--hurry-restore-timeout", "0"]; "sequential")]
+#[test_case(&["--hurry-overlap-restore", "--hurry-restore-timeout", "0"]; "overlapped")]
+#[test_log::test(tokio::test)]
+async fn restore_timeout_falls_back_to_building(args: &[&str]) -> Result<()> {
+    color_eyre::install()?;
+
+    let env = TestEnv::new().await?;
+    let container = env.service(TestEnv::HURRY_INSTANCE_1)?;
+    let repo_root = populate_cache(&env, &container).await?;
+
+    let freshness = build_with(&env, &container, &repo_root, args).await?;
+    let expected = freshness
+        .iter()
+        .map(|(id, _)| (id.clone(), false))
+        .collect::<Vec<_>>();
+    pretty_assert_eq!(freshness, expected, "no artifacts should be fresh");
+
+    Ok(())
+}
+
+/// A restore that the timeout cuts off while OUT_DIR files are still being
+/// written leaves OUT_DIR as it was, so Cargo reruns the build script into a
+/// clean OUT_DIR instead of mixing its files with restored ones. Where the
+/// timeout falls depends on how fast the files download, so the restore may
+/// also finish in time, in which case OUT_DIR is the restored one.
+#[test_log::test(tokio::test)]
+async fn restore_timeout_leaves_no_mixed_out_dir() -> Result<()> {
+    color_eyre::install()?;
+
+    let env = TestEnv::new().await?;
+    let container = env.service(TestEnv::HURRY_INSTANCE_1)?;
+
+    let pwd = PathBuf::from("/workspace");
+    let root = pwd.join("outdir");
+    Command::new()
+        .pwd(&pwd)
+        .name("sh")
+        .arg("-c")
+        .arg(OUT_DIR_SETUP)
+        .finish()
+        .run_compose(&container)
+        .await?;
+    build_messages(&env, &container, &root, &[]).await?;
+    Command::cargo_clean(&root).run_compose(&container).await?;
+    build_messages(&env, &container, &root, &["--hurry-restore-timeout", "1"]).await?;
+
+    let output = Command::new()
+        .pwd(&root)
+        .name("sh")
+        .arg("-c")
+        .arg(OUT_DIR_CHECK)
+        .finish()
+        .run_compose_with_output(&container)
+        .await?;
+    let counts = output
+        .stdout_lossy()
+        .lines()
+        .map(|line| line.trim().parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()?;
+    pretty_assert_eq!(
+        counts,
+        vec![1024, 1, 0],
+        "OUT_DIR should hold the files of exactly one build script run"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,80 @@
+//! Exercises caching with each toolchain in the matrix.
+
+use std::path::PathBuf;
+
+use color_eyre::{Result, eyre::bail};
+use e2e::{
+    Build, Command, TestEnv, Toolchain,
+    ext::{ArtifactIterExt, MessageIterExt},
+};
+use itertools::Itertools;
+use pretty_assertions::assert_eq as pretty_assert_eq;
+
+e2e::toolchain_matrix! {
+    /// Exercises restoring artifacts built in one container into another with
+    /// the same toolchain.
+    async fn cross_container(toolchain: Toolchain) -> Result<()> {
+        color_eyre::install()?;
+
+        // Check for GITHUB_TOKEN early to fail fast with a clear error message
+        if std::env::var("GITHUB_TOKEN").is_err() {
+            bail!(
+                "GITHUB_TOKEN environment variable is required to clone repositories from GitHub. \
+                 Please set it to a personal access token with 'repo' scope."
+            );
+        }
+
+        let env = TestEnv::with_toolchain(&toolchain).await?;
+        let pwd = PathBuf::from("/workspace");
+        let (username, repo, branch) = ("attunehq", "hurry-tests", "test/tiny");
+
+        for (service, fresh) in [
+            (TestEnv::HURRY_INSTANCE_1, false),
+            (TestEnv::HURRY_INSTANCE_2, true),
+        ] {
+            let repo_root = pwd.join(format!("{repo}-{service}"));
+            Command::clone_github()
+                .pwd(&pwd)
+                .user(username)
+                .repo(repo)
+                .branch(branch)
+                .dir(&repo_root)
+                .finish()
+                .run_compose(env.service(service)?)
+                .await?;
+            let messages = Build::new()
+                .pwd(&repo_root)
+                .wrapper(Build::HURRY_NAME)
+                .api_url(env.api_url())
+                .api_token(env.test_token())
+                .finish()
+                .run_compose(env.service(service)?)
+                .await?;
+
+            let expected = messages
+                .iter()
+                .thirdparty_artifacts()
+                .package_ids()
+                .map(|id| (id, fresh))
+                .sorted()
+                .collect::<Vec<_>>();
+            let freshness = messages
+                .iter()
+                .thirdparty_artifacts()
+                .freshness()
+                .sorted()
+                .collect::<Vec<_>>();
+            pretty_assert_eq!(
+                expected,
+                freshness,
+                "artifacts in {service} should have freshness {fresh} with {toolchain:?}: {messages:?}"
+            );
+            assert!(
+                !expected.is_empty(),
+                "build should have third-party artifacts"
+            );
+        }
+
+        Ok(())
+    }
+}
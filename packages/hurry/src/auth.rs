@@ -0,0 +1,240 @@
+//! Authorizing this machine with the Hurry API.
+//!
+//! `hurry auth login` and `hurry setup` get a token through device
+//! authorization: they start an authorization, the user approves it in the
+//! browser, and they poll Courier until the approval comes through.
+
+use std::time::Duration;
+
+use color_eyre::{Result, eyre::Context as _};
+use tokio::time::Instant;
+use tracing::{debug, instrument};
+
+use clients::{
+    Courier, Token,
+    courier::v1::device::{DeviceTokenPoll, DeviceTokenResponse},
+};
+
+/// Poll a device authorization until the user approves it, returning the
+/// token it's issued, or `None` if it expires first.
+///
+/// Polls are `interval` apart; when Courier asks the device to slow down, the
+/// interval doubles for the rest of the polls.
+#[instrument(skip(courier, device_code))]
+pub async fn poll_device_authorization(
+    courier: &Courier,
+    device_code: &Token,
+    mut interval: Duration,
+    expires_in: Duration,
+) -> Result<Option<DeviceTokenResponse>> {
+    let deadline = Instant::now() + expires_in;
+    while Instant::now() < deadline {
+        tokio::time::sleep(interval).await;
+        match courier
+            .device_authorization_poll(device_code)
+            .await
+            .context("check authorization")?
+        {
+            DeviceTokenPoll::Approved(approved) => return Ok(Some(approved)),
+            DeviceTokenPoll::Pending => continue,
+            DeviceTokenPoll::SlowDown => {
+                interval *= 2;
+                debug!(?interval, "courier asked to slow down polling");
+            }
+            DeviceTokenPoll::Expired => return Ok(None),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use axum::{
+        Json, Router, extract::State, http::StatusCode, response::IntoResponse as _, routing::post,
+    };
+    use clients::{Courier, Token, courier::v1::device::DeviceTokenResponse};
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+    use tokio::{net::TcpListener, time::Instant};
+    use url::Url;
+
+    use super::poll_device_authorization;
+
+    const INTERVAL: Duration = Duration::from_millis(50);
+
+    /// What the mock Courier answers to a poll.
+    #[derive(Debug, Clone)]
+    enum Answer {
+        Pending,
+        SlowDown,
+        Expired,
+        NotFound,
+        Approved,
+        Error,
+    }
+
+    /// The polls the mock Courier received, and the answers it has left.
+    #[derive(Debug, Default)]
+    struct MockState {
+        answers: VecDeque<Answer>,
+        polls: Vec<Instant>,
+    }
+
+    fn approved() -> DeviceTokenResponse {
+        DeviceTokenResponse {
+            token: Token::from("issued-token"),
+            org_id: 7,
+        }
+    }
+
+    /// Start a mock Courier that gives `answers` to polls in order, then
+    /// answers that the authorization is pending.
+    async fn mock_courier(answers: &[Answer]) -> (Courier, Arc<Mutex<MockState>>) {
+        let state = Arc::new(Mutex::new(MockState {
+            answers: answers.iter().cloned().collect(),
+            polls: Vec::new(),
+        }));
+        let poll = |State(state): State<Arc<Mutex<MockState>>>| async move {
+            let answer = {
+                let mut state = state.lock().unwrap();
+                state.polls.push(Instant::now());
+                state.answers.pop_front().unwrap_or(Answer::Pending)
+            };
+            match answer {
+                Answer::Pending => StatusCode::ACCEPTED.into_response(),
+                Answer::SlowDown => StatusCode::TOO_MANY_REQUESTS.into_response(),
+                Answer::Expired => StatusCode::GONE.into_response(),
+                Answer::NotFound => StatusCode::NOT_FOUND.into_response(),
+                Answer::Approved => Json(approved()).into_response(),
+                Answer::Error => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            }
+        };
+        let app = Router::new()
+            .route("/api/v1/oauth/device/token", post(poll))
+            .with_state(state.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let courier = Courier::new(url, Token::from("unauthenticated")).unwrap();
+        (courier, state)
+    }
+
+    /// The time between each poll and the one before it.
+    fn gaps(state: &Mutex<MockState>) -> Vec<Duration> {
+        let state = state.lock().unwrap();
+        state
+            .polls
+            .iter()
+            .zip(state.polls.iter().skip(1))
+            .map(|(a, b)| b.duration_since(*a))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn approved_after_pending() {
+        let (courier, state) =
+            mock_courier(&[Answer::Pending, Answer::Pending, Answer::Approved]).await;
+        let token = poll_device_authorization(
+            &courier,
+            &Token::from("device-code"),
+            INTERVAL,
+            Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+
+        pretty_assert_eq!(token, Some(approved()));
+        let gaps = gaps(&state);
+        pretty_assert_eq!(gaps.len(), 2);
+        assert!(gaps.iter().all(|gap| *gap >= INTERVAL), "{gaps:?}");
+    }
+
+    #[tokio::test]
+    async fn slow_down_doubles_the_interval() {
+        let (courier, state) = mock_courier(&[
+            Answer::Pending,
+            Answer::SlowDown,
+            Answer::Pending,
+            Answer::SlowDown,
+            Answer::Approved,
+        ])
+        .await;
+        let token = poll_device_authorization(
+            &courier,
+            &Token::from("device-code"),
+            INTERVAL,
+            Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+
+        pretty_assert_eq!(token, Some(approved()));
+        let minimums = [INTERVAL, INTERVAL * 2, INTERVAL * 2, INTERVAL * 4];
+        let gaps = gaps(&state);
+        let too_short = gaps
+            .iter()
+            .zip(minimums)
+            .filter(|(gap, minimum)| **gap < *minimum)
+            .collect::<Vec<_>>();
+        pretty_assert_eq!(gaps.len(), minimums.len());
+        pretty_assert_eq!(too_short, Vec::new(), "polls should back off: {gaps:?}");
+    }
+
+    #[test_case(Answer::Expired; "expired")]
+    #[test_case(Answer::NotFound; "already claimed")]
+    #[tokio::test]
+    async fn expired_authorization_stops_polling(answer: Answer) {
+        let (courier, state) = mock_courier(&[Answer::Pending, answer]).await;
+        let token = poll_device_authorization(
+            &courier,
+            &Token::from("device-code"),
+            INTERVAL,
+            Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+
+        pretty_assert_eq!(token, None);
+        pretty_assert_eq!(state.lock().unwrap().polls.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn gives_up_at_the_deadline() {
+        let (courier, state) = mock_courier(&[]).await;
+        let start = Instant::now();
+        let token = poll_device_authorization(
+            &courier,
+            &Token::from("device-code"),
+            INTERVAL,
+            INTERVAL * 3,
+        )
+        .await
+        .unwrap();
+
+        pretty_assert_eq!(token, None);
+        let polls = state.lock().unwrap().polls.len();
+        assert!((1..=3).contains(&polls), "{polls} polls");
+        assert!(start.elapsed() < INTERVAL * 5, "{:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn unexpected_status_is_an_error() {
+        let (courier, _) = mock_courier(&[Answer::Error]).await;
+        let err = poll_device_authorization(
+            &courier,
+            &Token::from("device-code"),
+            INTERVAL,
+            Duration::from_secs(10),
+        )
+        .await
+        .unwrap_err();
+        pretty_assert_eq!(err.to_string(), "check authorization");
+    }
+}
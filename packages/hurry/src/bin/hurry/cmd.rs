@@ -1,5 +1,14 @@
+pub mod auth;
 pub mod cache;
+pub mod cancel;
 pub mod cargo;
+pub mod config;
 pub mod cross;
 pub mod daemon;
 pub mod debug;
+pub mod gc_target;
+pub mod init;
+pub mod setup;
+pub mod support_bundle;
+pub mod verify_hermetic;
+pub mod wrap;
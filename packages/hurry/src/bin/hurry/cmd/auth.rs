@@ -0,0 +1,32 @@
+use clap::Subcommand;
+use color_eyre::Result;
+
+pub mod login;
+pub mod logout;
+pub mod status;
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Log in to the Hurry API in the browser.
+    ///
+    /// Opens the console, where you sign in with GitHub and choose the
+    /// organization to log in to. Hurry then receives an API key for that
+    /// organization and saves it to the user's `hurry.toml`, so there's no
+    /// key to create and paste by hand.
+    Login(login::Options),
+
+    /// Remove the saved API key from the user's `hurry.toml`.
+    Logout(logout::Options),
+
+    /// Show which Hurry API this machine is connected to, and whether its
+    /// token works.
+    Status(status::Options),
+}
+
+pub async fn exec(cmd: Command) -> Result<()> {
+    match cmd {
+        Command::Login(opts) => login::exec(opts).await,
+        Command::Logout(opts) => logout::exec(opts).await,
+        Command::Status(opts) => status::exec(opts).await,
+    }
+}
@@ -0,0 +1,34 @@
+use clap::Args;
+use color_eyre::{Result, eyre::Context as _};
+use derive_more::Debug;
+use tracing::instrument;
+use url::Url;
+
+use crate::cmd;
+use hurry::config::HurryConfig;
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// Base URL for the Hurry API. Defaults to the URL in the user's
+    /// `hurry.toml`.
+    #[arg(long = "api-url", env = "HURRY_API_URL")]
+    #[debug("{api_url:?}")]
+    api_url: Option<Url>,
+
+    /// Print the login link instead of opening it in the browser, e.g. when
+    /// logging in on a remote machine.
+    #[arg(long)]
+    no_browser: bool,
+}
+
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let config = HurryConfig::load_user()
+        .await
+        .context("load user config")?
+        .unwrap_or_default();
+    let url = config.api.url(options.api_url);
+    let token = cmd::setup::authorize(&url, !options.no_browser).await?;
+    cmd::setup::save(config, url, token).await?;
+    Ok(())
+}
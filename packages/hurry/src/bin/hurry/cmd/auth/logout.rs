@@ -0,0 +1,28 @@
+use clap::Args;
+use color_eyre::{Result, eyre::Context as _};
+use tracing::instrument;
+
+use hurry::config::HurryConfig;
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {}
+
+#[instrument]
+pub async fn exec(_options: Options) -> Result<()> {
+    let Some(mut config) = HurryConfig::load_user().await.context("load user config")? else {
+        println!("Not logged in");
+        return Ok(());
+    };
+    if config.api.token.take().is_none() {
+        println!("Not logged in");
+        return Ok(());
+    }
+
+    let path = config.save_user().await.context("save user config")?;
+    println!("Removed the API key from {path}");
+    println!("The key itself stays valid until it's revoked in the console.");
+    if std::env::var_os("HURRY_API_TOKEN").is_some() {
+        println!("Note: `HURRY_API_TOKEN` is still set in this environment.");
+    }
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use clap::Args;
+use color_eyre::{Result, eyre::Context as _};
+use derive_more::Debug;
+use tracing::instrument;
+use url::Url;
+
+use clients::{Courier, Token};
+use hurry::{config::HurryConfig, path::AbsDirPath};
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// Base URL for the Hurry API.
+    #[arg(long = "api-url", env = "HURRY_API_URL")]
+    #[debug("{api_url:?}")]
+    api_url: Option<Url>,
+
+    /// Authentication token for the Hurry API.
+    #[arg(long = "api-token", env = "HURRY_API_TOKEN")]
+    api_token: Option<Token>,
+}
+
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let cwd = AbsDirPath::current()?;
+    let config = HurryConfig::load(&cwd).await.context("load config")?;
+    let from_args = options.api_token.is_some();
+    let url = config.api.url(options.api_url);
+    println!("Hurry API: {url}");
+
+    let Some(token) = config.api.token(options.api_token) else {
+        println!("Not logged in; run `hurry auth login` to log in");
+        return Ok(());
+    };
+    if from_args {
+        println!("Token: from `--api-token` or `HURRY_API_TOKEN`");
+    } else {
+        println!("Token: from hurry.toml");
+    }
+
+    let courier = Courier::new(url.clone(), token)?;
+    courier
+        .ping()
+        .await
+        .with_context(|| format!("reach the Hurry API at {url}"))?;
+    match courier.cargo_cache_generation().await {
+        Ok(_) => println!("Logged in"),
+        Err(err) => {
+            println!("The token was rejected: {err:#}");
+            println!("Run `hurry auth login` to log in again");
+        }
+    }
+    Ok(())
+}
@@ -1,22 +1,71 @@
 use clap::Subcommand;
 use color_eyre::Result;
 
+pub mod bump_generation;
+pub mod read_only;
 pub mod reset;
+pub mod restore;
 pub mod show;
+pub mod usage;
+pub mod warm;
+pub mod write_policy;
 
 #[derive(Clone, Debug, Subcommand)]
 pub enum Command {
+    /// Invalidate the remote cache by bumping its generation.
+    ///
+    /// Units saved before the bump are no longer restored, but aren't deleted.
+    /// Only organization admins can do this.
+    BumpGeneration(bump_generation::Options),
+
+    /// Show or change whether the remote cache is read-only.
+    ///
+    /// While it is, builds restore from the cache but skip uploading, e.g.
+    /// during maintenance. Only organization admins can change this.
+    ReadOnly(read_only::Options),
+
     /// Reset the cache.
     Reset(reset::Options),
 
+    /// Restore the cache for the workspace without building it.
+    ///
+    /// With `--as-of`, only units saved at or before a given time or commit
+    /// are restored, e.g. to rebuild an old commit with the dependency
+    /// artifacts it was built with when bisecting a regression.
+    Restore(restore::Options),
+
     /// Print the location of the local cache directory for the user.
     #[clap(subcommand)]
     Show(show::Command),
+
+    /// Show how much the organization stores in the remote cache.
+    ///
+    /// Once the organization stores as much as its quota, uploads are
+    /// rejected until the cache is reset or the quota is raised.
+    Usage(usage::Options),
+
+    /// Restore the cache for several profiles at once.
+    ///
+    /// The profiles are restored concurrently, and objects they share are
+    /// only downloaded once.
+    Warm(warm::Options),
+
+    /// Show or change which branches can save to the remote cache.
+    ///
+    /// Builds of other branches only read from the cache. Only organization
+    /// admins can change this.
+    WritePolicy(write_policy::Options),
 }
 
 pub async fn exec(cmd: Command) -> Result<()> {
     match cmd {
+        Command::BumpGeneration(opts) => bump_generation::exec(opts).await,
+        Command::ReadOnly(opts) => read_only::exec(opts).await,
         Command::Reset(opts) => reset::exec(opts).await,
+        Command::Restore(opts) => restore::exec(opts).await,
         Command::Show(cmd) => show::exec(cmd).await,
+        Command::Usage(opts) => usage::exec(opts).await,
+        Command::Warm(opts) => warm::exec(opts).await,
+        Command::WritePolicy(opts) => write_policy::exec(opts).await,
     }
 }
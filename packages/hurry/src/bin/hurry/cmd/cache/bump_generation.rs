@@ -0,0 +1,63 @@
+use clap::Args;
+use color_eyre::{Result, eyre::Context as _};
+use colored::Colorize as _;
+use derive_more::Debug;
+use inquire::Confirm;
+use tracing::instrument;
+use url::Url;
+
+use clients::{Courier, Token};
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// Skip all confirmation prompts.
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Base URL for the Hurry API.
+    #[arg(
+        long = "api-url",
+        env = "HURRY_API_URL",
+        default_value = "https://app.hurry.build"
+    )]
+    #[debug("{api_url}")]
+    api_url: Url,
+
+    /// Authentication token for the Hurry API.
+    ///
+    /// The token's account must be an admin of its organization.
+    #[arg(long = "api-token", env = "HURRY_API_TOKEN")]
+    api_token: Token,
+}
+
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    if !options.yes {
+        println!(
+            "{}",
+            "WARNING: This will invalidate all cached data across your entire organization"
+                .on_red()
+        );
+        let confirmed = Confirm::new("Are you sure you want to proceed?")
+            .with_default(false)
+            .prompt()?;
+        if !confirmed {
+            return Ok(());
+        }
+    }
+
+    let courier = Courier::new(options.api_url, options.api_token)?;
+    courier.ping().await.context("ping Hurry API")?;
+
+    println!("Bumping cache generation...");
+    let bumped = courier
+        .cargo_cache_bump_generation()
+        .await
+        .context("bump cache generation")?;
+
+    println!(
+        "Done! The cache is now at generation {}.",
+        bumped.generation
+    );
+    Ok(())
+}
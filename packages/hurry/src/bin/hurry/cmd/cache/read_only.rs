@@ -0,0 +1,66 @@
+use clap::Args;
+use color_eyre::{Result, eyre::Context as _};
+use derive_more::Debug;
+use tracing::instrument;
+use url::Url;
+
+use clients::{Courier, Token, courier::v1::cache::CargoReadOnly};
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// Make the organization's cache read-only for this reason, e.g. during
+    /// maintenance. Builds keep restoring from the cache but skip uploads.
+    ///
+    /// Only organization admins can change this.
+    #[arg(long, value_name = "REASON")]
+    enable: Option<String>,
+
+    /// Let the organization's cache accept uploads again.
+    ///
+    /// Only organization admins can change this.
+    #[arg(long, conflicts_with = "enable")]
+    disable: bool,
+
+    /// Base URL for the Hurry API.
+    #[arg(
+        long = "api-url",
+        env = "HURRY_API_URL",
+        default_value = "https://app.hurry.build"
+    )]
+    #[debug("{api_url}")]
+    api_url: Url,
+
+    /// Authentication token for the Hurry API.
+    #[arg(long = "api-token", env = "HURRY_API_TOKEN")]
+    api_token: Token,
+}
+
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let courier = Courier::new(options.api_url, options.api_token)?;
+    courier.ping().await.context("ping Hurry API")?;
+
+    let update = match (options.enable, options.disable) {
+        (Some(reason), _) => Some(CargoReadOnly::read_only(reason)),
+        (None, true) => Some(CargoReadOnly::writable()),
+        (None, false) => None,
+    };
+    if let Some(update) = update {
+        courier
+            .cargo_cache_set_read_only(&update)
+            .await
+            .context("set cache read-only status")?;
+    }
+
+    // The whole instance can be read-only regardless of the organization's
+    // setting, so report what builds actually see.
+    let read_only = courier
+        .cargo_cache_read_only()
+        .await
+        .context("get cache read-only status")?;
+    match read_only.reason {
+        Some(reason) => println!("The cache is read-only: {reason}"),
+        None => println!("The cache accepts uploads"),
+    }
+    Ok(())
+}
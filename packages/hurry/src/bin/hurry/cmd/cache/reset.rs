@@ -6,7 +6,7 @@ use inquire::Confirm;
 use tracing::instrument;
 use url::Url;
 
-use clients::{Courier, Token};
+use clients::{Courier, Token, courier::v1::CacheScope};
 
 #[derive(Clone, Args, Debug)]
 pub struct Options {
@@ -34,6 +34,12 @@ pub struct Options {
     // `hurry cache reset cargo`?
     #[arg(long)]
     remote: bool,
+
+    /// Only delete the units saved in this cache scope, leaving the rest of
+    /// the organization's cache alone. Only organization admins can reset a
+    /// scope.
+    #[arg(long, requires = "remote", value_name = "NAME")]
+    scope: Option<CacheScope>,
 }
 
 #[instrument]
@@ -44,10 +50,15 @@ pub async fn exec(options: Options) -> Result<()> {
     }
 
     if !options.yes {
-        println!(
-            "{}",
-            "WARNING: This will delete all cached data across your entire organization".on_red()
-        );
+        let warning = match &options.scope {
+            Some(scope) => {
+                format!("WARNING: This will delete all cached data in the cache scope {scope}")
+            }
+            None => String::from(
+                "WARNING: This will delete all cached data across your entire organization",
+            ),
+        };
+        println!("{}", warning.on_red());
         let confirmed = Confirm::new("Are you sure you want to proceed?")
             .with_default(false)
             .prompt()?;
@@ -59,8 +70,20 @@ pub async fn exec(options: Options) -> Result<()> {
         let courier = Courier::new(options.api_url, options.api_token)?;
         courier.ping().await.context("ping Hurry API")?;
 
-        println!("Resetting remote cache...");
-        courier.cache_reset().await.context("reset remote cache")?;
+        match &options.scope {
+            Some(scope) => {
+                println!("Resetting cache scope {scope}...");
+                let reset = courier
+                    .cache_reset_scope(scope)
+                    .await
+                    .context("reset cache scope")?;
+                println!("Deleted {} saved units.", reset.units_deleted);
+            }
+            None => {
+                println!("Resetting remote cache...");
+                courier.cache_reset().await.context("reset remote cache")?;
+            }
+        }
     }
 
     println!("Done!");
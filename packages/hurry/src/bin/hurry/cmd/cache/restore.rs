@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use clap::Args;
+use color_eyre::{
+    Result,
+    eyre::{Context as _, bail},
+};
+use derive_more::Debug;
+use jiff::{Timestamp, civil::Date, tz::TimeZone};
+use tracing::{debug, instrument};
+use url::Url;
+
+use clients::{
+    Token,
+    courier::v1::{CacheScope, cache::CacheAsOf},
+};
+use hurry::{
+    cargo::{CargoBuildArguments, CargoCache, LockWait, Workspace},
+    path::AbsDirPath,
+    progress::TransferBar,
+};
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// Base URL for the Hurry API.
+    #[arg(
+        long = "api-url",
+        env = "HURRY_API_URL",
+        default_value = "https://app.hurry.build"
+    )]
+    #[debug("{api_url}")]
+    api_url: Url,
+
+    /// Authentication token for the Hurry API.
+    #[arg(long = "api-token", env = "HURRY_API_TOKEN")]
+    api_token: Token,
+
+    /// Only restore units that were saved at or before this point: a
+    /// timestamp (`2025-06-01T12:00:00Z`), a date (`2025-06-01`, through the
+    /// end of the day in the local time zone), or a commit.
+    ///
+    /// Commits can be any Git revision of the workspace (e.g. `HEAD~3`), and
+    /// stand for the last time units built from that commit were uploaded.
+    #[arg(long = "as-of", value_name = "DATE|COMMIT")]
+    as_of: Option<String>,
+
+    /// Restore units from this cache scope rather than from the
+    /// organization's unscoped cache.
+    #[arg(long = "cache-scope", env = "HURRY_CACHE_SCOPE", value_name = "NAME")]
+    cache_scope: Option<CacheScope>,
+
+    /// Fail after this many seconds if another build holds the lock on the
+    /// build directory. By default, Hurry waits until the lock is released.
+    #[arg(
+        long = "lock-timeout",
+        env = "HURRY_LOCK_TIMEOUT",
+        value_name = "SECONDS"
+    )]
+    lock_timeout: Option<u64>,
+
+    /// These arguments are interpreted as they would be by `cargo build`.
+    #[arg(
+        num_args = ..,
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "ARGS",
+    )]
+    argv: Vec<String>,
+}
+
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let args = CargoBuildArguments::from_iter(&options.argv);
+    let workspace = Workspace::from_argv(&args)
+        .await
+        .context("open workspace")?;
+    let as_of = match &options.as_of {
+        Some(as_of) => Some(resolve_as_of(&workspace.root, as_of).await?),
+        None => None,
+    };
+    debug!(?as_of, "restoring cache");
+    let units = workspace
+        .units(&args)
+        .await
+        .context("calculate expected units")?;
+    let cache = CargoCache::open(
+        options.api_url.clone(),
+        options.api_token.clone(),
+        workspace.clone(),
+    )
+    .await
+    .context("opening cache")?
+    .with_as_of(as_of)
+    .with_cache_scope(options.cache_scope.clone());
+
+    // Hold the profile directory locks while restoring so that we don't
+    // restore into a directory that a build is writing to.
+    let lock_wait = match options.lock_timeout {
+        Some(secs) => LockWait::Timeout(Duration::from_secs(secs)),
+        None => LockWait::Forever,
+    };
+    let locks = workspace
+        .lock_profile_dirs(lock_wait)
+        .await
+        .context("lock profile directories")?;
+    let progress = TransferBar::new(units.len() as u64, "Restoring cache");
+    let restored = cache
+        .restore(&units, &progress)
+        .await
+        .context("restore cache")?;
+    progress.finish();
+    drop(locks);
+
+    println!("Restored {} of {} units", restored.units.len(), units.len());
+    Ok(())
+}
+
+/// Resolve the `--as-of` argument into the point to restore the cache as of.
+///
+/// Anything that isn't a timestamp or a date is taken to be a Git revision of
+/// the workspace. Commits that aren't in the local repository (e.g. because
+/// it's a shallow clone) can still be given by SHA.
+async fn resolve_as_of(root: &AbsDirPath, as_of: &str) -> Result<CacheAsOf> {
+    if let Ok(timestamp) = as_of.parse::<Timestamp>() {
+        return Ok(CacheAsOf::Time(timestamp.to_string()));
+    }
+    if let Ok(date) = as_of.parse::<Date>() {
+        let end = date
+            .at(23, 59, 59, 999_999_999)
+            .to_zoned(TimeZone::system())
+            .context("resolve end of day")?;
+        return Ok(CacheAsOf::Time(end.timestamp().to_string()));
+    }
+
+    let revision = format!("{as_of}^{{commit}}");
+    let output = tokio::process::Command::new("git")
+        .args(["rev-parse", "--verify", "--quiet", &revision])
+        .current_dir(root.as_std_path())
+        .output()
+        .await;
+    if let Ok(output) = output
+        && output.status.success()
+        && let Ok(sha) = String::from_utf8(output.stdout)
+    {
+        return Ok(CacheAsOf::Commit(sha.trim().to_string()));
+    }
+    if as_of.len() >= CacheAsOf::MIN_COMMIT_PREFIX && as_of.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(CacheAsOf::Commit(as_of.to_string()));
+    }
+    bail!("`{as_of}` isn't a timestamp, a date, or a commit of the workspace");
+}
@@ -0,0 +1,54 @@
+use clap::Args;
+use color_eyre::{Result, eyre::Context as _};
+use derive_more::Debug;
+use tracing::instrument;
+use url::Url;
+
+use clients::{Courier, Token};
+use hurry::progress::format_size;
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// Base URL for the Hurry API.
+    #[arg(
+        long = "api-url",
+        env = "HURRY_API_URL",
+        default_value = "https://app.hurry.build"
+    )]
+    #[debug("{api_url}")]
+    api_url: Url,
+
+    /// Authentication token for the Hurry API.
+    #[arg(long = "api-token", env = "HURRY_API_TOKEN")]
+    api_token: Token,
+}
+
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let courier = Courier::new(options.api_url, options.api_token)?;
+    courier.ping().await.context("ping Hurry API")?;
+
+    let Some(usage) = courier
+        .cargo_cache_usage()
+        .await
+        .context("get cache storage usage")?
+    else {
+        println!("This Hurry API doesn't report storage usage");
+        return Ok(());
+    };
+
+    let stored = format_size(usage.bytes_stored);
+    match usage.quota_bytes {
+        Some(quota) => {
+            let percent = usage.bytes_stored as f64 / quota.max(1) as f64 * 100.0;
+            println!("Stored: {stored} of {} ({percent:.0}%)", format_size(quota));
+            if usage.exceeded() {
+                println!(
+                    "The storage quota is used up, so builds can't upload to the cache; reset the cache with `hurry cache reset` or ask for a larger quota"
+                );
+            }
+        }
+        None => println!("Stored: {stored} (no quota)"),
+    }
+    Ok(())
+}
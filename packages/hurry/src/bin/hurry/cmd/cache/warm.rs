@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use clap::Args;
+use color_eyre::{
+    Result,
+    eyre::{Context as _, eyre},
+};
+use derive_more::Debug;
+use futures::future::try_join_all;
+use itertools::Itertools as _;
+use tracing::{debug, info, instrument};
+use url::Url;
+
+use clients::{Token, courier::v1::CacheScope};
+use hurry::{
+    cargo::{CargoBuildArguments, CargoCache, LockWait, Profile, UnitPlan, Workspace},
+    progress::TransferBar,
+};
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// Base URL for the Hurry API.
+    #[arg(
+        long = "api-url",
+        env = "HURRY_API_URL",
+        default_value = "https://app.hurry.build"
+    )]
+    #[debug("{api_url}")]
+    api_url: Url,
+
+    /// Authentication token for the Hurry API.
+    #[arg(long = "api-token", env = "HURRY_API_TOKEN")]
+    api_token: Token,
+
+    /// The profiles to restore, separated by commas.
+    ///
+    /// Objects shared between the profiles are only downloaded once.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "debug",
+        value_name = "PROFILES"
+    )]
+    profiles: Vec<String>,
+
+    /// Restore units from this cache scope rather than from the
+    /// organization's unscoped cache.
+    #[arg(long = "cache-scope", env = "HURRY_CACHE_SCOPE", value_name = "NAME")]
+    cache_scope: Option<CacheScope>,
+
+    /// Fail after this many seconds if another build holds the lock on a
+    /// profile directory. By default, Hurry waits until the lock is released.
+    #[arg(
+        long = "lock-timeout",
+        env = "HURRY_LOCK_TIMEOUT",
+        value_name = "SECONDS"
+    )]
+    lock_timeout: Option<u64>,
+
+    /// These arguments are interpreted as they would be by `cargo build`,
+    /// except that any profile they specify is replaced by each of
+    /// `--profiles` in turn.
+    #[arg(
+        num_args = ..,
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "ARGS",
+    )]
+    argv: Vec<String>,
+}
+
+/// A profile of the workspace, planned and ready to restore.
+#[derive(Debug)]
+struct PlannedProfile {
+    profile: Profile,
+    workspace: Workspace,
+    cache: CargoCache,
+    units: Vec<UnitPlan>,
+}
+
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let args = CargoBuildArguments::from_iter(&options.argv);
+    let profiles = options
+        .profiles
+        .iter()
+        .map(Profile::from)
+        .unique()
+        .collect::<Vec<_>>();
+    let lock_wait = match options.lock_timeout {
+        Some(secs) => LockWait::Timeout(Duration::from_secs(secs)),
+        None => LockWait::Forever,
+    };
+
+    // Computing a build plan temporarily renames the build directory, so the
+    // profiles are planned one at a time.
+    let mut planned = Vec::with_capacity(profiles.len());
+    for profile in profiles {
+        let args = args.clone().with_profile(&profile);
+        let workspace = Workspace::from_argv(&args)
+            .await
+            .with_context(|| format!("open workspace for profile: {profile}"))?;
+        let units = workspace
+            .units(&args)
+            .await
+            .with_context(|| format!("calculate expected units for profile: {profile}"))?;
+        debug!(%profile, units = units.len(), "planned profile");
+
+        // The profiles are restored concurrently, which the daemon doesn't
+        // support: it treats each restore as the start of a new build in the
+        // workspace, cancelling the restore before it.
+        let cache = CargoCache::open(
+            options.api_url.clone(),
+            options.api_token.clone(),
+            workspace.clone(),
+        )
+        .await
+        .context("opening cache")?
+        .with_restore_in_daemon(false)
+        .with_cache_scope(options.cache_scope.clone());
+        planned.push(PlannedProfile {
+            profile,
+            workspace,
+            cache,
+            units,
+        });
+    }
+    let Some(first) = planned.first() else {
+        return Err(eyre!("no profiles to restore"));
+    };
+
+    // Download the objects of every profile up front, so that objects shared
+    // between profiles are only downloaded once when they're restored.
+    let prefetched = first
+        .cache
+        .prefetch(planned.iter().flat_map(|planned| &planned.units))
+        .await
+        .context("prefetch objects")?;
+    info!(prefetched, "prefetched objects");
+
+    let total = planned.iter().map(|p| p.units.len() as u64).sum::<u64>();
+    let progress = TransferBar::new(total, "Restoring cache");
+    let restored = try_join_all(planned.iter().map(|planned| {
+        let progress = &progress;
+        async move {
+            // Hold the profile directory locks while restoring so that we
+            // don't restore into a directory that a build is writing to.
+            let locks = planned
+                .workspace
+                .lock_profile_dirs(lock_wait)
+                .await
+                .with_context(|| {
+                    format!("lock profile directories for profile: {}", planned.profile)
+                })?;
+            let restored = planned
+                .cache
+                .restore(&planned.units, progress)
+                .await
+                .with_context(|| format!("restore profile: {}", planned.profile))?;
+            drop(locks);
+            Result::<_>::Ok(restored.units.len())
+        }
+    }))
+    .await?;
+    progress.finish();
+
+    for (planned, restored) in planned.iter().zip(restored) {
+        println!(
+            "Restored {restored} of {} units for the {} profile",
+            planned.units.len(),
+            planned.profile
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use clap::Args;
+use color_eyre::{Result, eyre::Context as _};
+use derive_more::Debug;
+use tracing::instrument;
+use url::Url;
+
+use clients::{Courier, Token, courier::v1::cache::CargoWritePolicy};
+use hurry::{cargo::current_branch, path::AbsDirPath};
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// Only let builds of branches matching these patterns save to the cache,
+    /// separated by commas. `*` matches any sequence of characters, e.g.
+    /// `main,release/*`.
+    ///
+    /// Only organization admins can change the policy.
+    #[arg(long, value_delimiter = ',', value_name = "PATTERNS")]
+    protect: Option<Vec<String>>,
+
+    /// Let builds of every branch save to the cache again.
+    ///
+    /// Only organization admins can change the policy.
+    #[arg(long, conflicts_with = "protect")]
+    unrestricted: bool,
+
+    /// Base URL for the Hurry API.
+    #[arg(
+        long = "api-url",
+        env = "HURRY_API_URL",
+        default_value = "https://app.hurry.build"
+    )]
+    #[debug("{api_url}")]
+    api_url: Url,
+
+    /// Authentication token for the Hurry API.
+    #[arg(long = "api-token", env = "HURRY_API_TOKEN")]
+    api_token: Token,
+}
+
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let courier = Courier::new(options.api_url, options.api_token)?;
+    courier.ping().await.context("ping Hurry API")?;
+
+    let update = match (options.protect, options.unrestricted) {
+        (Some(branches), _) => Some(CargoWritePolicy::protected(branches)),
+        (None, true) => Some(CargoWritePolicy::unrestricted()),
+        (None, false) => None,
+    };
+    let policy = match update {
+        Some(policy) => {
+            courier
+                .cargo_cache_set_write_policy(&policy)
+                .await
+                .context("set cache write policy")?;
+            policy
+        }
+        None => courier
+            .cargo_cache_write_policy()
+            .await
+            .context("get cache write policy")?,
+    };
+
+    match &policy.branches {
+        Some(branches) => println!(
+            "Only these branches can save to the cache: {}",
+            branches.join(", ")
+        ),
+        None => println!("Every branch can save to the cache"),
+    }
+
+    let pwd = AbsDirPath::current().context("get working directory")?;
+    let branch = current_branch(&pwd).await;
+    let access = if policy.allows(branch.as_deref()) {
+        "can save to"
+    } else {
+        "can only read from"
+    };
+    match branch {
+        Some(branch) => println!("This branch ({branch}) {access} the cache"),
+        None => println!("This build's branch is unknown, so it {access} the cache"),
+    }
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use clap::Args;
+use color_eyre::{Result, eyre::Context as _};
+use hurry::daemon::{CargoCancelRequest, CargoCancelResponse, DaemonPaths, local_client};
+use tracing::instrument;
+use uuid::Uuid;
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// The upload to cancel. If unset, every running upload is cancelled.
+    #[arg(long = "request", value_name = "ID")]
+    request_id: Option<Uuid>,
+}
+
+/// Cancel uploads that the daemon is running in the background.
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let Some(cancelled) = cancel_uploads(options.request_id).await? else {
+        println!("Daemon not running");
+        return Ok(());
+    };
+
+    if cancelled.is_empty() {
+        match options.request_id {
+            Some(request_id) => println!("Upload {request_id} is not running"),
+            None => println!("No uploads running"),
+        }
+    }
+    for request_id in cancelled {
+        println!("Cancelled upload {request_id}");
+    }
+    Ok(())
+}
+
+/// Ask the daemon to cancel the upload with the provided ID, or every running
+/// upload if there's no ID.
+///
+/// Returns the IDs of the cancelled uploads, or `None` if the daemon isn't
+/// running.
+#[instrument]
+pub async fn cancel_uploads(request_id: Option<Uuid>) -> Result<Option<Vec<Uuid>>> {
+    let paths = DaemonPaths::initialize().await?;
+    let Some(daemon) = paths.daemon_running().await? else {
+        return Ok(None);
+    };
+
+    let endpoint = format!("http://{}/api/v0/cargo/cancel", daemon.url);
+    let response = local_client()?
+        .post(&endpoint)
+        .json(&CargoCancelRequest { request_id })
+        .send()
+        .await
+        .with_context(|| format!("send cancel request to daemon at: {endpoint}"))?
+        .error_for_status()
+        .context("cancel uploads")?
+        .json::<CargoCancelResponse>()
+        .await
+        .context("parse cancel response")?;
+    Ok(Some(response.cancelled))
+}
@@ -2,10 +2,14 @@ use std::ffi::OsString;
 
 use clap::{Args, CommandFactory, Parser};
 use color_eyre::{Result, eyre::Context};
-use hurry::cargo;
+use hurry::{
+    cargo::{self, CargoInvocation},
+    path::AbsDirPath,
+};
 use tracing::debug;
 
 pub mod build;
+pub mod clean;
 
 /// Helper type for parsing options with `clap`.
 #[derive(Parser)]
@@ -24,7 +28,7 @@ impl<T: Args> CommandOptions<T> {
     }
 }
 
-/// Execute a cargo command by dispatching based on the first argument.
+/// Execute a cargo command by dispatching based on the subcommand.
 pub async fn exec(arguments: Vec<String>) -> Result<()> {
     let Some((command, options)) = arguments.split_first() else {
         return cargo::invoke_plain(Vec::<String>::new()).await;
@@ -42,12 +46,8 @@ pub async fn exec(arguments: Vec<String>) -> Result<()> {
         return cargo::invoke(command, options).await;
     }
 
-    // The first argument being a flag means we're running against `cargo` directly.
-    if command.starts_with('-') {
-        return cargo::invoke(command, options).await;
-    }
-
-    // Otherwise, we're running a subcommand.
+    // Otherwise, find the subcommand, which may come after global options or
+    // be an alias.
     //
     // We do it this way instead of constructing subcommands "the clap way" because
     // we want to passthrough things like `help` and `version` to cargo instead of
@@ -55,20 +55,48 @@ pub async fn exec(arguments: Vec<String>) -> Result<()> {
     //
     // As we add special cased handling for more subcommands we'll extend this match
     // statement with other functions similar to the one we use for `build`.
-    match command.as_str() {
-        "build" => {
-            let opts: CommandOptions<build::Options> = CommandOptions::parse(&arguments)?;
-            if opts.opts.help {
-                // Help flag handling happens here because `build --help` passes
-                // through to `cargo build --help`, and we need the `Command`
-                // struct in order to print the generated help text.
-                let mut cmd = CommandOptions::<build::Options>::command();
-                cmd = cmd.about("Run `cargo build` with Hurry build acceleration");
-                cmd.print_help()?;
-                return Ok(());
-            }
-            build::exec(opts.into_inner()).await
-        }
-        _ => cargo::invoke(command, options).await,
+    let Some((subcommand, argv)) = route(&arguments).await else {
+        return cargo::invoke_plain(&arguments).await;
+    };
+    let command = match subcommand.as_str() {
+        "build" => build::Command::Build,
+        "test" => build::Command::Test,
+        "check" => build::Command::Check,
+        "clippy" => build::Command::Clippy,
+        "clean" => return clean::exec(&argv[1..]).await,
+        _ => return cargo::invoke_plain(&arguments).await,
+    };
+    let opts: CommandOptions<build::Options> = CommandOptions::parse(&argv)?;
+    if opts.opts.help {
+        // Help flag handling happens here because `build --help` passes
+        // through to `cargo build --help`, and we need the `Command` struct in
+        // order to print the generated help text.
+        let mut cmd = CommandOptions::<build::Options>::command();
+        cmd = cmd.about(format!(
+            "Run `cargo {command}` with Hurry build acceleration"
+        ));
+        cmd.print_help()?;
+        return Ok(());
     }
+    build::exec(command, opts.into_inner()).await
+}
+
+/// Find the subcommand to route the arguments to, along with the arguments
+/// rearranged to start with that subcommand.
+///
+/// Returns `None` if the arguments should be passed to Cargo as-is, e.g.
+/// because there's no subcommand or because aliases couldn't be resolved (in
+/// which case Cargo reports the problem).
+async fn route(arguments: &[String]) -> Option<(String, Vec<String>)> {
+    let pwd = AbsDirPath::current()
+        .inspect_err(|err| debug!(?err, "get working directory"))
+        .ok()?;
+    let invocation = CargoInvocation::parse(arguments)
+        .resolve_aliases(&pwd)
+        .await
+        .inspect_err(|err| debug!(?err, "resolve cargo aliases"))
+        .ok()?;
+    debug!(?invocation, "parsed cargo invocation");
+    let argv = invocation.subcommand_argv()?;
+    Some((argv[0].clone(), argv))
 }
@@ -1,29 +1,151 @@
 //! Builds Cargo projects using an optimized cache.
 //!
+//! `cargo test` goes through the same pipeline: its test harnesses are
+//! compiled with `cargo test --no-run` like `cargo build` compiles binaries,
+//! and the tests run once the compiled units are in the cache. `cargo check`
+//! and `cargo clippy` go through it too, with the units planned by
+//! `CheckPlan`.
+//!
 //! Reference:
 //! - `docs/DESIGN.md`
 //! - `docs/development/cargo.md`
 
-use std::time::Duration;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use clap::Args;
 use color_eyre::{
     Result, Section as _, SectionExt as _,
     eyre::{Context, OptionExt as _, bail, eyre},
 };
-use derive_more::Debug;
-use tracing::{debug, info, instrument, trace, warn};
+use derive_more::{Debug, Display};
+use tracing::{Instrument as _, debug, info, instrument, trace, warn};
 use url::Url;
 use uuid::Uuid;
 
-use clients::Token;
+use clients::{Token, courier::v1::CacheScope};
 use hurry::{
-    cargo::{self, CargoBuildArguments, CargoCache, Workspace},
-    daemon::{CargoUploadStatus, CargoUploadStatusRequest, CargoUploadStatusResponse, DaemonPaths},
+    cargo::{
+        self, BuildStats, CargoBuildArguments, CargoCache, CheckMode, CheckPlan, DeterminismCheck,
+        LockWait, Phase, PhaseTimings, RestoreHandoff, Restored, SaveProgress, StatsFormat,
+        UnitHash, UnitPlan, UploadPolicy, Workspace, split_for_handoff,
+        wrapper::{self, CargoWrapper},
+    },
+    ci::github,
+    daemon::{CargoUploadProgressResponse, CargoUploadStatus, DaemonPaths},
     progress::TransferBar,
 };
 
-/// Options for `cargo build`.
+use crate::cmd;
+
+/// The Cargo subcommands that compile through the cache.
+#[derive(Clone, Debug, Display)]
+pub enum Command {
+    #[display("build")]
+    Build,
+
+    #[display("test")]
+    Test,
+
+    #[display("check")]
+    Check,
+
+    #[display("clippy")]
+    Clippy,
+
+    /// A subcommand of a build tool that runs `cargo build` itself.
+    #[display("{_0}")]
+    Wrapped(Wrapped),
+}
+
+/// A subcommand of a build tool that wraps Cargo, e.g. `maturin build`.
+#[derive(Clone, Debug, Display)]
+#[display("{} {subcommand}", wrapper.name())]
+pub struct Wrapped {
+    pub wrapper: Arc<dyn CargoWrapper>,
+    pub subcommand: String,
+}
+
+impl Command {
+    /// Parse the subcommand's arguments into the arguments of the `cargo
+    /// build` invocation that compiles the same units.
+    fn build_args(&self, argv: &[String]) -> CargoBuildArguments {
+        match self {
+            Self::Build | Self::Check => CargoBuildArguments::from_iter(argv),
+            Self::Test => CargoBuildArguments::from_test_argv(argv),
+            Self::Clippy => CargoBuildArguments::from_clippy_argv(argv),
+            // Wrapped subcommands are only routed here if the wrapper knows
+            // their `cargo build` arguments.
+            Self::Wrapped(wrapped) => wrapped
+                .wrapper
+                .build_args(&wrapped.subcommand, argv)
+                .unwrap_or_else(CargoBuildArguments::empty),
+        }
+    }
+
+    /// Compile the subcommand's units with Cargo without running anything.
+    async fn compile(&self, argv: &[String]) -> Result<()> {
+        self.compile_with(argv, &[]).await
+    }
+
+    /// Compile the subcommand's units with Cargo without running anything,
+    /// with additional environment variables for Cargo.
+    async fn compile_with(&self, argv: &[String], env: &[(String, OsString)]) -> Result<()> {
+        let env = env.to_vec();
+        match self {
+            Self::Build => cargo::invoke_env("build", argv, env).await,
+            Self::Check => cargo::invoke_env("check", argv, env).await,
+            Self::Clippy => cargo::invoke_env("clippy", argv, env).await,
+            Self::Test if no_run(argv) => cargo::invoke_env("test", argv, env).await,
+            Self::Test => {
+                let mut compile = vec![String::from("--no-run")];
+                compile.extend(argv.iter().cloned());
+                cargo::invoke_env("test", compile, env).await
+            }
+            Self::Wrapped(wrapped) => wrapped.invoke_with(argv, env).await,
+        }
+    }
+
+    /// Run whatever the subcommand runs once its units are compiled.
+    async fn run(&self, argv: &[String]) -> Result<()> {
+        match self {
+            Self::Build | Self::Check | Self::Clippy | Self::Wrapped(_) => Ok(()),
+            Self::Test if no_run(argv) => Ok(()),
+            Self::Test => cargo::invoke("test", argv).await,
+        }
+    }
+
+    /// Run the subcommand without the cache.
+    async fn passthrough(&self, argv: &[String]) -> Result<()> {
+        match self {
+            Self::Wrapped(wrapped) => wrapped.invoke_with(argv, Vec::new()).await,
+            command => cargo::invoke(command.to_string(), argv).await,
+        }
+    }
+}
+
+impl Wrapped {
+    /// Run the wrapper's subcommand with the arguments and additional
+    /// environment variables.
+    async fn invoke_with(&self, argv: &[String], env: Vec<(String, OsString)>) -> Result<()> {
+        let args = std::iter::once(&self.subcommand).chain(argv);
+        wrapper::invoke_env(self.wrapper.as_ref(), args, env).await
+    }
+}
+
+/// Whether `cargo test` arguments ask to only compile the tests.
+fn no_run(argv: &[String]) -> bool {
+    argv.iter()
+        .take_while(|arg| *arg != "--")
+        .any(|arg| arg == "--no-run")
+}
+
+/// Options for `cargo build`, `cargo test`, `cargo check`, `cargo clippy`, and
+/// the build subcommands of tools that wrap Cargo.
 //
 // Hurry options are prefixed with `hurry-` to disambiguate from `cargo` args.
 //
@@ -36,14 +158,11 @@ use hurry::{
 #[derive(Clone, Args, Debug)]
 #[command(disable_help_flag = true)]
 pub struct Options {
-    /// Base URL for the Hurry API.
-    #[arg(
-        long = "hurry-api-url",
-        env = "HURRY_API_URL",
-        default_value = "https://app.hurry.build"
-    )]
-    #[debug("{api_url}")]
-    api_url: Url,
+    /// Base URL for the Hurry API. Defaults to the URL in `hurry.toml`, or
+    /// https://app.hurry.build.
+    #[arg(long = "hurry-api-url", env = "HURRY_API_URL")]
+    #[debug("{api_url:?}")]
+    api_url: Option<Url>,
 
     /// Authentication token for the Hurry API.
     // Note: this field is not _actually_ optional for `hurry` to operate; we're just telling clap
@@ -52,11 +171,21 @@ pub struct Options {
     #[arg(long = "hurry-api-token", env = "HURRY_API_TOKEN")]
     api_token: Option<Token>,
 
+    /// Never prompt, even to set Hurry up the first time it runs in a
+    /// terminal.
+    #[arg(
+        long = "hurry-non-interactive",
+        env = "HURRY_NON_INTERACTIVE",
+        default_value_t = false
+    )]
+    non_interactive: bool,
+
     /// Skip backing up the cache.
     #[arg(long = "hurry-skip-backup", default_value_t = false)]
     skip_backup: bool,
 
-    /// Skip the Cargo build, only performing the cache actions.
+    /// Skip the Cargo build (and running tests), only performing the cache
+    /// actions.
     #[arg(long = "hurry-skip-build", default_value_t = false)]
     skip_build: bool,
 
@@ -64,6 +193,48 @@ pub struct Options {
     #[arg(long = "hurry-skip-restore", default_value_t = false)]
     skip_restore: bool,
 
+    /// Build without contacting Courier: skip restoring and backing up the
+    /// cache.
+    ///
+    /// Hurry also builds this way on its own, after a single warning, if
+    /// Courier can't be reached.
+    #[arg(long = "hurry-offline", env = "HURRY_OFFLINE", default_value_t = false)]
+    offline: bool,
+
+    /// Restore from the cache but never upload to it, e.g. for builds of pull
+    /// requests from forks, which shouldn't be able to change the cache that
+    /// other builds restore from.
+    ///
+    /// Courier enforces this on its own for read-only API keys.
+    #[arg(
+        long = "hurry-cache-read-only",
+        env = "HURRY_CACHE_READ_ONLY",
+        default_value_t = false
+    )]
+    cache_read_only: bool,
+
+    /// Start Cargo while the cache is still being restored.
+    ///
+    /// Build scripts and the units they depend on are restored before Cargo
+    /// starts compiling. Other libraries are handed to Cargo as they're
+    /// restored: Cargo compiles the units that restore hasn't reached yet
+    /// instead of waiting for them.
+    #[arg(
+        long = "hurry-overlap-restore",
+        env = "HURRY_OVERLAP_RESTORE",
+        default_value_t = false
+    )]
+    overlap_restore: bool,
+
+    /// Stop restoring the cache after this many seconds, leaving any units that
+    /// haven't been restored yet for Cargo to build.
+    #[arg(
+        long = "hurry-restore-timeout",
+        env = "HURRY_RESTORE_TIMEOUT",
+        value_name = "SECONDS"
+    )]
+    restore_timeout: Option<u64>,
+
     /// Upload artifacts asynchronously in the background instead of waiting.
     ///
     /// By default, hurry waits for uploads to complete before exiting.
@@ -76,11 +247,104 @@ pub struct Options {
     )]
     async_upload: bool,
 
-    /// Show help for `hurry cargo build`.
+    /// Always upload units whose artifacts total at most this many bytes.
+    #[arg(
+        long = "hurry-upload-size-floor",
+        env = "HURRY_UPLOAD_SIZE_FLOOR",
+        value_name = "BYTES",
+        default_value_t = UploadPolicy::DEFAULT_SIZE_FLOOR
+    )]
+    upload_size_floor: u64,
+
+    /// Skip uploading units above the size floor that rebuild in less than
+    /// this many seconds per GiB of artifacts. Set to 0 to upload every unit.
+    #[arg(
+        long = "hurry-upload-min-rebuild-per-gib",
+        env = "HURRY_UPLOAD_MIN_REBUILD_PER_GIB",
+        value_name = "SECONDS",
+        default_value_t = UploadPolicy::DEFAULT_MIN_REBUILD_PER_GIB
+    )]
+    upload_min_rebuild_per_gib: u64,
+
+    /// Upload this many units at once. Defaults to `parallelism` under
+    /// `[upload]` in `hurry.toml`, or 8.
+    #[arg(
+        long = "hurry-upload-parallelism",
+        env = "HURRY_UPLOAD_PARALLELISM",
+        value_name = "UNITS"
+    )]
+    upload_parallelism: Option<usize>,
+
+    /// Save and restore units in this cache scope, isolating them from builds
+    /// in other scopes (e.g. other repositories or branch families) of the
+    /// organization. By default, builds share the organization's unscoped
+    /// cache.
+    #[arg(
+        long = "hurry-cache-scope",
+        env = "HURRY_CACHE_SCOPE",
+        value_name = "NAME"
+    )]
+    cache_scope: Option<CacheScope>,
+
+    /// Compare units with what the cache already stores for the same unit
+    /// hash before uploading them, and report units that differ
+    /// (`warn`) or report them and skip uploading them (`refuse`).
+    #[arg(
+        long = "hurry-determinism-check",
+        env = "HURRY_DETERMINISM_CHECK",
+        value_enum,
+        default_value_t = DeterminismCheck::Off
+    )]
+    determinism_check: DeterminismCheck,
+
+    /// Restore the cache in this process instead of in the Hurry daemon.
+    ///
+    /// Uploads still run in the daemon. Restore also falls back to this
+    /// process on its own if the daemon can't be started.
+    #[arg(
+        long = "hurry-no-daemon",
+        env = "HURRY_NO_DAEMON",
+        default_value_t = false
+    )]
+    no_daemon: bool,
+
+    /// Fail after this many seconds if another build holds the lock on the
+    /// build directory. By default, Hurry waits until the lock is released.
+    #[arg(
+        long = "hurry-lock-timeout",
+        env = "HURRY_LOCK_TIMEOUT",
+        value_name = "SECONDS",
+        conflicts_with = "no_wait"
+    )]
+    lock_timeout: Option<u64>,
+
+    /// Fail immediately if another build holds the lock on the build
+    /// directory, instead of waiting for it to be released.
+    #[arg(long = "hurry-no-wait", env = "HURRY_NO_WAIT", default_value_t = false)]
+    no_wait: bool,
+
+    /// Have the daemon watch the workspace after this build, prefetching
+    /// cached artifacts for builds with these arguments whenever the
+    /// toolchain or lockfile changes.
+    #[arg(long = "hurry-watch", env = "HURRY_WATCH", default_value_t = false)]
+    watch: bool,
+
+    /// How to report what the cache did for the build: a one-line summary
+    /// (`text`), a line of JSON on stdout (`json`), or nothing (`off`).
+    #[arg(
+        long = "hurry-stats-format",
+        env = "HURRY_STATS_FORMAT",
+        value_enum,
+        default_value_t = StatsFormat::Text
+    )]
+    stats_format: StatsFormat,
+
+    /// Show help for `hurry cargo build` or `hurry cargo test`.
     #[arg(long = "hurry-help", default_value_t = false)]
     pub help: bool,
 
-    /// These arguments are passed directly to `cargo build` as provided.
+    /// These arguments are passed directly to `cargo build` or `cargo test`
+    /// as provided.
     #[arg(
         num_args = ..,
         trailing_var_arg = true,
@@ -91,10 +355,10 @@ pub struct Options {
 }
 
 impl Options {
-    /// Parse the cargo build arguments.
+    /// Parse the arguments into `cargo build` arguments for the subcommand.
     #[instrument(name = "Options::parsed_args")]
-    pub fn parsed_args(&self) -> CargoBuildArguments {
-        CargoBuildArguments::from_iter(&self.argv)
+    pub fn parsed_args(&self, command: &Command) -> CargoBuildArguments {
+        command.build_args(&self.argv)
     }
 
     /// Check if help is requested in the arguments.
@@ -106,57 +370,90 @@ impl Options {
 }
 
 #[instrument]
-pub async fn exec(options: Options) -> Result<()> {
+pub async fn exec(command: Command, options: Options) -> Result<()> {
     // If help is requested, passthrough directly to cargo to show cargo's help
     if options.is_help_request() {
-        return cargo::invoke("build", &options.argv).await;
+        return command.passthrough(&options.argv).await;
     }
 
-    // We make the API token required here; if we make it required in the actual
-    // clap state then we aren't able to support e.g. `cargo build -h` passthrough.
-    let Some(token) = &options.api_token else {
-        return Err(eyre!("Hurry API authentication token is required"))
-            .suggestion("Set the `HURRY_API_TOKEN` environment variable")
-            .suggestion("Provide it with the `--hurry-api-token` argument");
-    };
-
     info!("Starting");
+    let mut timings = PhaseTimings::start();
 
     // Parse and validate cargo build arguments.
-    let args = options.parsed_args();
-    debug!(?args, "parsed cargo build arguments");
-
-    // Open workspace.
-    let workspace = Workspace::from_argv(&args)
-        .await
-        .context("opening workspace")?;
-    debug!(?workspace, "opened workspace");
-
-    // Compute expected unit plans. Note that because we are not actually
-    // running build scripts, these "unit plans" do not contain fully
-    // unambiguous cache key information (e.g. they do not provide build script
-    // outputs).
-    let units = workspace
-        .units(&args)
-        .await
-        .context("calculating expected units")?;
-
-    // Initialize cache.
-    let cache = CargoCache::open(options.api_url, token.clone(), workspace)
-        .await
-        .context("opening cache")?;
+    let args = options.parsed_args(&command);
+    debug!(?args, %command, "parsed cargo build arguments");
+
+    let Planned {
+        workspace,
+        cache,
+        units,
+        check_plan,
+    } = timings
+        .time(Phase::Plan, plan(&command, &options, &args))
+        .await?;
+    let skip_restore = options.skip_restore || cache.is_none();
+    let skip_backup = options.skip_backup || options.cache_read_only || cache.is_none();
 
     // Restore artifacts.
     let unit_count = units.len() as u64;
-    let restored = if !options.skip_restore {
-        let progress = TransferBar::new(unit_count, "Restoring cache");
-        cache.restore(&units, &progress).await?
+    let deadline = options
+        .restore_timeout
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    let lock_wait = match (options.no_wait, options.lock_timeout) {
+        (true, _) => LockWait::NoWait,
+        (false, Some(secs)) => LockWait::Timeout(Duration::from_secs(secs)),
+        (false, None) => LockWait::Forever,
+    };
+    let overlap = options.overlap_restore && !skip_restore && !options.skip_build;
+    let progress = if skip_restore {
+        TransferBar::hidden(unit_count)
+    } else {
+        TransferBar::new(unit_count, "Restoring cache")
+    };
+    let restored = if let Some(cache) = cache.as_ref().filter(|_| !skip_restore) {
+        if overlap {
+            let restore = OverlapRestore {
+                workspace: &workspace,
+                cache,
+                units: &units,
+                progress: &progress,
+                deadline,
+                lock_wait,
+            };
+            restore_while_building(restore, &command, &options.argv, &mut timings).await?
+        } else {
+            let restore = async {
+                // Hold the profile directory locks while restoring so that we
+                // don't restore into a directory that another build is
+                // writing to.
+                let locks = workspace
+                    .lock_profile_dirs(lock_wait)
+                    .await
+                    .context("lock profile directories")?;
+                let restored = match deadline {
+                    Some(deadline) => cache.restore_until(&units, &progress, deadline).await?,
+                    None => cache.restore(&units, &progress).await?,
+                };
+                drop(locks);
+                Result::<_>::Ok(restored)
+            };
+            timings.time(Phase::Restore, restore).await?
+        }
     } else {
         Default::default()
     };
+    let downloaded_bytes = progress.bytes();
+    progress.finish();
+    if let Some(summary) = restored.policy_summary() {
+        eprintln!("{summary}");
+    }
+    if let Some(summary) = restored.repair_summary() {
+        eprintln!("{summary}");
+    }
 
-    // Run the build.
-    if !options.skip_build {
+    // Run the build. If restore overlapped with the build, the build already
+    // ran alongside it.
+    if !options.skip_build && !overlap {
         info!("Building target directory");
 
         // There are two integration points here that we specifically do _not_
@@ -222,7 +519,8 @@ pub async fn exec(options: Options) -> Result<()> {
         // processes, and use that to determine invocation and OUT_DIR from argv
         // and environment variables?
 
-        cargo::invoke("build", &options.argv)
+        timings
+            .time(Phase::Build, command.compile(&options.argv))
             .await
             .context("build with cargo")?;
 
@@ -232,57 +530,398 @@ pub async fn exec(options: Options) -> Result<()> {
         // about what changed and needs to be cached.
     }
 
-    // Cache the built artifacts.
-    if !options.skip_backup {
-        let upload_id = cache.save(units, restored).await?;
-        if !options.async_upload {
-            let progress = TransferBar::new(unit_count, "Uploading cache");
-            wait_for_upload(upload_id, &progress).await?;
+    // Run the tests, if any. Failing tests don't make the compiled units any
+    // less worth caching, so the failure is only reported after saving them.
+    let ran = match &command {
+        _ if options.skip_build => Ok(()),
+        Command::Test => timings.time(Phase::Test, command.run(&options.argv)).await,
+        _ => command.run(&options.argv).await,
+    };
+
+    // Checked units that weren't in the cache only have known unit hashes
+    // once Cargo has checked them.
+    let units = match check_plan {
+        Some((plan, mut checked)) if !options.skip_build => {
+            let discovered = plan.discover().await.context("discovering checked units")?;
+            checked.extend(discovered);
+            plan.units(&checked)
+        }
+        _ => units,
+    };
+
+    if !options.skip_build
+        && let Some(cache) = &cache
+    {
+        cache.record_build_timings(&units, &restored).await;
+    }
+
+    // Watching is only an optimization for the next build, so failures don't
+    // fail this one.
+    if options.watch
+        && let Some(cache) = &cache
+        && let Err(err) = cache.watch(&args.to_argv()).await
+    {
+        warn!(?err, "failed to watch workspace");
+    }
+
+    // Prewarming is only an optimization for later builds, so failures don't
+    // fail this one. CI machines rarely build the same workspace again.
+    if !cargo::in_ci()
+        && let Some(cache) = &cache
+        && let Err(err) = cache.prewarm(&args.to_argv()).await
+    {
+        warn!(?err, "failed to register workspace for prewarming");
+    }
+
+    // Cache the built artifacts. Read-only builds never ask the daemon to
+    // upload at all.
+    if options.cache_read_only && cache.is_some() {
+        eprintln!("[hurry] Skipped uploading: this build can only read from the cache");
+    }
+    let mut saved = None;
+    if let Some(cache) = cache.as_ref().filter(|_| !skip_backup) {
+        let policy = UploadPolicy::builder()
+            .size_floor(options.upload_size_floor)
+            .min_rebuild_per_gib(options.upload_min_rebuild_per_gib)
+            .determinism(options.determinism_check)
+            .build();
+        let save = async {
+            let upload_id = cache.save(units.clone(), restored.clone(), policy).await?;
+            if options.async_upload {
+                return Ok(None);
+            }
+            let progress = TransferBar::new(units.len() as u64, "Uploading cache");
+            let upload = tokio::select! {
+                saved = wait_for_upload(upload_id, &progress) => saved,
+                _ = tokio::signal::ctrl_c() => {
+                    progress.finish();
+                    cancel_upload(upload_id).await;
+                    bail!("interrupted while uploading cache");
+                }
+            };
+            progress.finish();
+            Result::<_>::Ok(Some(upload))
+        };
+
+        // The build itself succeeded, so a failed upload only means the
+        // next build can't restore from it.
+        match timings.time(Phase::Save, save).await? {
+            Some(Ok(upload)) => {
+                if let Some(summary) = upload.write_policy_summary() {
+                    eprintln!("{summary}");
+                }
+                if let Some(summary) = upload.maintenance_summary() {
+                    eprintln!("{summary}");
+                }
+                if let Some(summary) = upload.policy_summary() {
+                    eprintln!("{summary}");
+                }
+                if let Some(summary) = upload.validation_summary() {
+                    eprintln!("{summary}");
+                }
+                if let Some(summary) = upload.determinism_summary() {
+                    eprintln!("{summary}");
+                }
+                if let Some(summary) = upload.repair_summary() {
+                    eprintln!("{summary}");
+                }
+                saved = Some(upload);
+            }
+            Some(Err(err)) => eprintln!("Failed to upload cache: {err:#}"),
+            None => {}
+        }
+    }
+    timings.finish();
+
+    // Statistics are only informational, so failing to report them doesn't
+    // fail the build.
+    let report = cache.is_some() && options.stats_format != StatsFormat::Off;
+    if report || github::enabled() {
+        let restored_units = restored.units.len() as u64;
+        let stats = BuildStats {
+            command: command.to_string(),
+            units: units.len() as u64,
+            restored: restored_units,
+            compiled: (units.len() as u64).saturating_sub(restored_units),
+            downloaded_bytes,
+            uploaded_units: saved.as_ref().map(|saved| saved.uploaded_units),
+            uploaded_bytes: saved.as_ref().map(|saved| saved.uploaded_bytes),
+            time_saved: cargo::estimate_time_saved(&workspace, &units, &restored).await,
+            timings,
+        };
+        if report {
+            stats.report(options.stats_format);
+        }
+        if github::enabled() {
+            if let Some(saved) = &saved {
+                for annotation in github::save_annotations(saved) {
+                    annotation.emit();
+                }
+            }
+            let summary = github::JobSummary { stats, saved };
+            if let Err(err) = summary.write().await {
+                warn!(?err, "failed to write job summary");
+            }
         }
     }
 
-    Ok(())
+    ran.with_context(|| format!("{command} with cargo"))
+}
+
+/// Open the workspace and the cache, and compute the units that the build
+/// compiles.
+async fn plan(command: &Command, options: &Options, args: &CargoBuildArguments) -> Result<Planned> {
+    // Open workspace.
+    let workspace = Workspace::from_argv(args)
+        .await
+        .context("opening workspace")?;
+    debug!(?workspace, "opened workspace");
+
+    // We make the API token required here; if we make it required in the actual
+    // clap state then we aren't able to support e.g. `cargo build -h` passthrough.
+    let (api_url, token) = cmd::setup::connection(
+        &workspace.root,
+        options.api_url.clone(),
+        options.api_token.clone(),
+        options.non_interactive || options.offline,
+    )
+    .await?;
+
+    // Initialize cache. A build that can't reach Courier still builds, it
+    // just can't restore or back up anything.
+    let cache = if options.offline {
+        None
+    } else {
+        CargoCache::open_unless_unreachable(api_url.clone(), token.clone(), workspace.clone())
+            .await
+            .context("opening cache")?
+            .map(|cache| {
+                cache
+                    .with_restore_in_daemon(!options.no_daemon)
+                    .with_deferred_upload(options.async_upload)
+                    .with_upload_parallelism(options.upload_parallelism)
+                    .with_cache_scope(options.cache_scope.clone())
+            })
+    };
+
+    // Compute expected unit plans. Note that because we are not actually
+    // running build scripts, these "unit plans" do not contain fully
+    // unambiguous cache key information (e.g. they do not provide build script
+    // outputs).
+    //
+    // `cargo check` and `cargo clippy` compile most units in check mode, whose
+    // unit hashes are looked up in the cache instead (see `CheckPlan`).
+    let (units, check_plan) = match command {
+        Command::Build | Command::Test | Command::Wrapped(_) => {
+            let units = workspace
+                .units(args)
+                .await
+                .context("calculating expected units")?;
+            (units, None)
+        }
+        Command::Check | Command::Clippy => {
+            let mode = match command {
+                Command::Clippy => workspace
+                    .clippy_version()
+                    .await
+                    .map(CheckMode::Clippy)
+                    .context("reading clippy version")?,
+                _ => CheckMode::Check,
+            };
+            let plan = workspace
+                .check_plan(mode, args)
+                .await
+                .context("calculating check plan")?;
+            let checked = match &cache {
+                Some(cache) => cache
+                    .resolve_checked(&plan)
+                    .await
+                    .context("resolving checked units")?,
+                None => Default::default(),
+            };
+            (plan.units(&checked), Some((plan, checked)))
+        }
+    };
+
+    Ok(Planned {
+        workspace,
+        cache,
+        units,
+        check_plan,
+    })
+}
+
+/// The units that a build compiles, and the cache it uses.
+struct Planned {
+    workspace: Workspace,
+
+    /// The cache, unless the build is offline.
+    cache: Option<CargoCache>,
+    units: Vec<UnitPlan>,
+
+    /// For `cargo check` and `cargo clippy`, the check plan and the unit
+    /// hashes of checked units that were found in the cache.
+    check_plan: Option<(CheckPlan, HashMap<UnitHash, UnitHash>)>,
+}
+
+/// What to restore while Cargo builds.
+struct OverlapRestore<'a> {
+    workspace: &'a Workspace,
+    cache: &'a CargoCache,
+    units: &'a Vec<UnitPlan>,
+    progress: &'a TransferBar,
+    deadline: Option<Instant>,
+    lock_wait: LockWait,
+}
+
+/// Restore the cache while Cargo builds.
+///
+/// Build script executions and the units they depend on are restored first,
+/// while holding the profile directory locks: Cargo only skips rerunning a
+/// build script if it's fresh when Cargo starts compiling, which it does once
+/// the locks are released. Other library crates are then handed to Cargo as
+/// they're restored, in dependency order, through Hurry running as Cargo's
+/// rustc wrapper (see `RestoreHandoff`). Units that restore hasn't reached by
+/// the time Cargo needs them are compiled by Cargo instead.
+///
+/// Restore and the build each run in their own phase span, and their timings
+/// overlap.
+async fn restore_while_building(
+    restore: OverlapRestore<'_>,
+    command: &Command,
+    argv: &[String],
+    timings: &mut PhaseTimings,
+) -> Result<Restored> {
+    let OverlapRestore {
+        workspace,
+        cache,
+        units,
+        progress,
+        deadline,
+        lock_wait,
+    } = restore;
+    let start = Instant::now();
+    let handoff = RestoreHandoff::default();
+    let server = handoff.serve().await.context("start restore hand-off")?;
+    let env = server
+        .cargo_env(&workspace.root)
+        .await
+        .context("configure restore hand-off")?;
+
+    let (before_build, handed_off) = split_for_handoff(units);
+    debug!(
+        before_build = before_build.len(),
+        handed_off = handed_off.len(),
+        "restoring while building"
+    );
+    progress.dec_length(handed_off.len() as u64);
+    handoff
+        .expect(workspace, &handed_off)
+        .context("expect handed off units")?;
+    let locks = workspace
+        .lock_profile_dirs(lock_wait)
+        .instrument(Phase::Restore.span())
+        .await
+        .context("lock profile directories")?;
+
+    let restore = async {
+        let restored = match deadline {
+            Some(deadline) => cache.restore_until(&before_build, progress, deadline).await,
+            None => cache.restore(&before_build, progress).await,
+        };
+
+        // Release the locks even if restore failed so that Cargo can build.
+        drop(locks);
+        let restored = match restored {
+            Ok(mut restored) => {
+                // Units restored before the build are skipped, so the
+                // hand-off reports its progress separately.
+                let handoff_progress = TransferBar::hidden(units.len() as u64);
+                let handed_off = cache
+                    .restore_handoff(units, &handoff_progress, deadline, &handoff)
+                    .await;
+                progress.add_bytes(handoff_progress.bytes());
+                handed_off.map(|handed_off| {
+                    restored.extend(handed_off);
+                    restored
+                })
+            }
+            Err(err) => Err(err),
+        };
+
+        // Cargo compiles whatever wasn't handed to it.
+        handoff.close();
+        (restored, start.elapsed())
+    };
+    let build = async {
+        let start = Instant::now();
+        info!("Building target directory");
+        (command.compile_with(argv, &env).await, start.elapsed())
+    };
+
+    let ((restored, restore_elapsed), (built, build_elapsed)) = tokio::join!(
+        restore.instrument(Phase::Restore.span()),
+        build.instrument(Phase::Build.span()),
+    );
+    drop(server);
+    timings.record(Phase::Restore, restore_elapsed);
+    timings.record(Phase::Build, build_elapsed);
+    built.context("build with cargo")?;
+    restored.context("restore cache")
+}
+
+/// Cancel the upload, since the user interrupted the build that's waiting on
+/// it and an upload nobody is waiting for only competes with their next build.
+async fn cancel_upload(request_id: Uuid) {
+    match cmd::cancel::cancel_uploads(Some(request_id)).await {
+        Ok(_) => debug!(?request_id, "cancelled upload"),
+        Err(err) => warn!(?err, ?request_id, "failed to cancel upload"),
+    }
 }
 
 #[instrument]
-async fn wait_for_upload(request_id: Uuid, progress: &TransferBar) -> Result<()> {
+async fn wait_for_upload(request_id: Uuid, progress: &TransferBar) -> Result<SaveProgress> {
     let paths = DaemonPaths::initialize().await?;
     let Some(daemon) = paths.daemon_running().await? else {
         bail!("daemon is not running");
     };
 
-    let client = reqwest::Client::default();
-    let endpoint = format!("http://{}/api/v0/cargo/status", daemon.url);
-    let request = CargoUploadStatusRequest { request_id };
+    let client = hurry::daemon::local_client()?;
+    let endpoint = format!("http://{}/api/v0/cargo/status/{request_id}", daemon.url);
     let mut interval = tokio::time::interval(Duration::from_secs(1));
 
     let mut last_uploaded_artifacts = 0u64;
     let mut last_uploaded_files = 0u64;
-    let mut last_uploaded_bytes = 0u64;
+    let mut last_transferred_bytes = 0u64;
     let mut last_total_artifacts = 0u64;
     loop {
         interval.tick().await;
-        trace!(?request, "submitting upload status request");
+        trace!(?request_id, "requesting upload progress");
         let response = client
-            .post(&endpoint)
-            .json(&request)
+            .get(&endpoint)
             .send()
             .await
             .with_context(|| format!("send upload status request to daemon at: {endpoint}"))
             .with_section(|| format!("{daemon:?}").header("Daemon context:"))?;
         trace!(?response, "got upload status response");
-        let response = response.json::<CargoUploadStatusResponse>().await?;
+        let response = response.json::<CargoUploadProgressResponse>().await?;
         trace!(?response, "parsed upload status response");
+        progress.set_detail(response.detail());
+        progress.add_bytes(
+            response
+                .transferred_bytes
+                .saturating_sub(last_transferred_bytes),
+        );
+        last_transferred_bytes = response.transferred_bytes;
         let status = response.status.ok_or_eyre("no upload status")?;
         match status {
-            CargoUploadStatus::Complete => break,
+            CargoUploadStatus::Complete(save_progress) => return Ok(save_progress),
+            CargoUploadStatus::Failed { error, .. } => return Err(eyre!(error)),
+            CargoUploadStatus::Cancelled(_) => bail!("upload cancelled"),
+            CargoUploadStatus::Superseded { by } => {
+                bail!("upload superseded by a later upload: {by}")
+            }
             CargoUploadStatus::InProgress(save_progress) => {
-                progress.add_bytes(
-                    save_progress
-                        .uploaded_bytes
-                        .saturating_sub(last_uploaded_bytes),
-                );
-                last_uploaded_bytes = save_progress.uploaded_bytes;
                 progress.add_files(
                     save_progress
                         .uploaded_files
@@ -300,6 +939,4 @@ async fn wait_for_upload(request_id: Uuid, progress: &TransferBar) -> Result<()>
             }
         }
     }
-
-    Ok(())
 }
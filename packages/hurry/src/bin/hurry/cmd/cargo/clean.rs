@@ -0,0 +1,69 @@
+use color_eyre::{
+    Result,
+    eyre::{Context as _, OptionExt as _, bail},
+};
+use hurry::{
+    cargo,
+    daemon::{CargoSessionEndRequest, CargoSessionEndResponse, DaemonPaths, local_client},
+    path::{AbsDirPath, AbsFilePath},
+};
+use tracing::{debug, instrument};
+
+/// Run `cargo clean`, first cancelling the daemon's background work for the
+/// workspace.
+///
+/// Uploads that are still running read artifacts from the target directory
+/// that `cargo clean` is about to remove, so they can only fail.
+#[instrument]
+pub async fn exec(options: &[String]) -> Result<()> {
+    if let Err(err) = end_session().await {
+        debug!(?err, "failed to end daemon session");
+    }
+    cargo::invoke("clean", options).await
+}
+
+async fn end_session() -> Result<()> {
+    let paths = DaemonPaths::initialize().await?;
+    let Some(daemon) = paths.daemon_running().await? else {
+        return Ok(());
+    };
+
+    let root = workspace_root().await?;
+    let endpoint = format!("http://{}/api/v0/cargo/session/end", daemon.url);
+    let response = local_client()?
+        .post(&endpoint)
+        .json(&CargoSessionEndRequest { root })
+        .send()
+        .await
+        .with_context(|| format!("send session end request to daemon at: {endpoint}"))?
+        .error_for_status()
+        .context("end session")?
+        .json::<CargoSessionEndResponse>()
+        .await
+        .context("parse session end response")?;
+    debug!(ended = response.ended, "ended daemon session");
+    Ok(())
+}
+
+/// The root of the workspace in the working directory.
+///
+/// This uses `cargo locate-project` rather than `cargo metadata` since it
+/// doesn't need to resolve dependencies, which keeps `clean` fast.
+async fn workspace_root() -> Result<AbsDirPath> {
+    let output = tokio::process::Command::new("cargo")
+        .args(["locate-project", "--workspace", "--message-format", "plain"])
+        .output()
+        .await
+        .context("run cargo locate-project")?;
+    if !output.status.success() {
+        bail!(
+            "cargo locate-project failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let manifest = String::from_utf8(output.stdout).context("parse manifest path as utf8")?;
+    let manifest = AbsFilePath::try_from(manifest.trim()).context("parse manifest path")?;
+    manifest
+        .parent()
+        .ok_or_eyre("manifest has no parent directory")
+}
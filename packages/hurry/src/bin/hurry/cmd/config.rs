@@ -0,0 +1,20 @@
+use clap::Subcommand;
+use color_eyre::Result;
+
+pub mod show;
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Print the effective configuration for the workspace, and check it
+    /// for keys Hurry doesn't know.
+    ///
+    /// With `--origins`, each setting is shown with where it came from: a
+    /// `hurry.toml`, an environment variable, or its default.
+    Show(show::Options),
+}
+
+pub async fn exec(cmd: Command) -> Result<()> {
+    match cmd {
+        Command::Show(opts) => show::exec(opts).await,
+    }
+}
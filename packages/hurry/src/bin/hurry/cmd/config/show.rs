@@ -0,0 +1,55 @@
+use clap::Args;
+use color_eyre::{
+    Result, Section as _, SectionExt as _,
+    eyre::{Context as _, eyre},
+};
+use tracing::instrument;
+
+use hurry::{config::HurryConfig, path::AbsDirPath};
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// Show where each setting came from.
+    #[arg(long)]
+    origins: bool,
+}
+
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let cwd = AbsDirPath::current()?;
+    let resolved = HurryConfig::resolve(&cwd).await.context("load config")?;
+
+    match &resolved.file {
+        Some(file) => println!("# Config file: {file}"),
+        None => println!("# No config file; using defaults"),
+    }
+    for ignored in &resolved.ignored {
+        println!("# Ignored: {ignored} (config files aren't merged)");
+    }
+
+    let width = resolved
+        .settings
+        .iter()
+        .map(|setting| setting.key.len() + setting.value.to_string().len())
+        .max()
+        .unwrap_or_default();
+    for setting in &resolved.settings {
+        let line = format!("{} = {}", setting.key, setting.value);
+        if options.origins {
+            println!("{line:<pad$}  # {}", setting.origin, pad = width + 3);
+        } else {
+            println!("{line}");
+        }
+    }
+
+    if resolved.unknown_keys.is_empty() {
+        return Ok(());
+    }
+    let file = resolved
+        .file
+        .map(|file| file.to_string())
+        .unwrap_or_default();
+    Err(eyre!("unknown keys in config file: {file}"))
+        .with_section(|| resolved.unknown_keys.join("\n").header("Unknown keys:"))
+        .suggestion("Check the keys for typos; Hurry ignores keys it doesn't know.")
+}
@@ -2,7 +2,7 @@ use std::ffi::OsString;
 
 use clap::{Args, CommandFactory, Parser};
 use color_eyre::{Result, eyre::Context};
-use hurry::cross;
+use hurry::{cargo::CargoInvocation, cross, path::AbsDirPath};
 use tracing::debug;
 
 mod build;
@@ -24,7 +24,7 @@ impl<T: Args> CommandOptions<T> {
     }
 }
 
-/// Execute a cross command by dispatching based on the first argument.
+/// Execute a cross command by dispatching based on the subcommand.
 pub async fn exec(arguments: Vec<String>) -> Result<()> {
     let Some((command, options)) = arguments.split_first() else {
         return cross::invoke_plain(Vec::<String>::new()).await;
@@ -40,16 +40,9 @@ pub async fn exec(arguments: Vec<String>) -> Result<()> {
         return cross::invoke(command, options).await;
     }
 
-    // The first argument being a flag means we're running against `cross` directly.
-    //
-    // FIXME: This isn't always true - e.g. `cross --locked build` would have
-    // `--locked` as the first argument but should still accelerate `build`.
-    // See: https://github.com/attunehq/hurry/issues/266
-    if command.starts_with('-') {
-        return cross::invoke(command, options).await;
-    }
-
-    // Otherwise, we're running a subcommand.
+    // Otherwise, find the subcommand, which may come after global options or
+    // be an alias. Cross reads aliases from Cargo's configuration the same way
+    // Cargo does.
     //
     // We do it this way instead of constructing subcommands "the clap way" because
     // we want to passthrough things like `help` and `version` to cross instead of
@@ -57,9 +50,12 @@ pub async fn exec(arguments: Vec<String>) -> Result<()> {
     //
     // As we add special cased handling for more subcommands we'll extend this match
     // statement with other functions similar to the one we use for `build`.
-    match command.as_str() {
-        "build" | "b" => {
-            let opts = CommandOptions::<build::Options>::parse(&arguments)?;
+    let Some((subcommand, argv)) = route(&arguments).await else {
+        return cross::invoke_plain(&arguments).await;
+    };
+    match subcommand.as_str() {
+        "build" => {
+            let opts = CommandOptions::<build::Options>::parse(&argv)?;
             if opts.opts.help {
                 // Help flag handling happens here because `build --help` passes
                 // through to `cross build --help`, and we need the `Command`
@@ -71,6 +67,24 @@ pub async fn exec(arguments: Vec<String>) -> Result<()> {
             }
             build::exec(opts.into_inner()).await
         }
-        _ => cross::invoke(command, options).await,
+        _ => cross::invoke_plain(&arguments).await,
     }
 }
+
+/// Find the subcommand to route the arguments to, along with the arguments
+/// rearranged to start with that subcommand.
+///
+/// Returns `None` if the arguments should be passed to cross as-is.
+async fn route(arguments: &[String]) -> Option<(String, Vec<String>)> {
+    let pwd = AbsDirPath::current()
+        .inspect_err(|err| debug!(?err, "get working directory"))
+        .ok()?;
+    let invocation = CargoInvocation::parse(arguments)
+        .resolve_aliases(&pwd)
+        .await
+        .inspect_err(|err| debug!(?err, "resolve cargo aliases"))
+        .ok()?;
+    debug!(?invocation, "parsed cross invocation");
+    let argv = invocation.subcommand_argv()?;
+    Some((argv[0].clone(), argv))
+}
@@ -15,35 +15,57 @@ use tracing::{debug, info, instrument, trace, warn};
 use url::Url;
 use uuid::Uuid;
 
-use clients::Token;
+use clients::{Token, courier::v1::CacheScope};
 use hurry::{
-    cargo::{CargoBuildArguments, CargoCache, Workspace},
+    cargo::{
+        CargoBuildArguments, CargoCache, DeterminismCheck, SaveProgress, UploadPolicy, Workspace,
+    },
     cross,
-    daemon::{CargoUploadStatus, CargoUploadStatusRequest, CargoUploadStatusResponse, DaemonPaths},
+    daemon::{CargoUploadProgressResponse, CargoUploadStatus, DaemonPaths},
     progress::TransferBar,
 };
 
+use crate::cmd;
+
 /// Options for `cross build`.
 #[derive(Clone, clap::Args, Debug)]
 #[command(disable_help_flag = true)]
 pub struct Options {
-    /// Base URL for the Hurry API.
-    #[arg(
-        long = "hurry-api-url",
-        env = "HURRY_API_URL",
-        default_value = "https://app.hurry.build"
-    )]
-    #[debug("{api_url}")]
-    api_url: Url,
+    /// Base URL for the Hurry API. Defaults to the URL in `hurry.toml`, or
+    /// https://app.hurry.build.
+    #[arg(long = "hurry-api-url", env = "HURRY_API_URL")]
+    #[debug("{api_url:?}")]
+    api_url: Option<Url>,
 
     /// Authentication token for the Hurry API.
     #[arg(long = "hurry-api-token", env = "HURRY_API_TOKEN")]
     api_token: Option<Token>,
 
+    /// Never prompt, even to set Hurry up the first time it runs in a
+    /// terminal.
+    #[arg(
+        long = "hurry-non-interactive",
+        env = "HURRY_NON_INTERACTIVE",
+        default_value_t = false
+    )]
+    non_interactive: bool,
+
     /// Skip backing up the cache.
     #[arg(long = "hurry-skip-backup", default_value_t = false)]
     skip_backup: bool,
 
+    /// Restore from the cache but never upload to it, e.g. for builds of pull
+    /// requests from forks, which shouldn't be able to change the cache that
+    /// other builds restore from.
+    ///
+    /// Courier enforces this on its own for read-only API keys.
+    #[arg(
+        long = "hurry-cache-read-only",
+        env = "HURRY_CACHE_READ_ONLY",
+        default_value_t = false
+    )]
+    cache_read_only: bool,
+
     /// Skip the cross build, only performing the cache actions.
     #[arg(long = "hurry-skip-build", default_value_t = false)]
     skip_build: bool,
@@ -64,6 +86,58 @@ pub struct Options {
     )]
     async_upload: bool,
 
+    /// Always upload units whose artifacts total at most this many bytes.
+    #[arg(
+        long = "hurry-upload-size-floor",
+        env = "HURRY_UPLOAD_SIZE_FLOOR",
+        value_name = "BYTES",
+        default_value_t = UploadPolicy::DEFAULT_SIZE_FLOOR
+    )]
+    upload_size_floor: u64,
+
+    /// Skip uploading units above the size floor that rebuild in less than
+    /// this many seconds per GiB of artifacts. Set to 0 to upload every unit.
+    #[arg(
+        long = "hurry-upload-min-rebuild-per-gib",
+        env = "HURRY_UPLOAD_MIN_REBUILD_PER_GIB",
+        value_name = "SECONDS",
+        default_value_t = UploadPolicy::DEFAULT_MIN_REBUILD_PER_GIB
+    )]
+    upload_min_rebuild_per_gib: u64,
+
+    /// Save and restore units in this cache scope, isolating them from builds
+    /// in other scopes (e.g. other repositories or branch families) of the
+    /// organization. By default, builds share the organization's unscoped
+    /// cache.
+    #[arg(
+        long = "hurry-cache-scope",
+        env = "HURRY_CACHE_SCOPE",
+        value_name = "NAME"
+    )]
+    cache_scope: Option<CacheScope>,
+
+    /// Compare units with what the cache already stores for the same unit
+    /// hash before uploading them, and report units that differ
+    /// (`warn`) or report them and skip uploading them (`refuse`).
+    #[arg(
+        long = "hurry-determinism-check",
+        env = "HURRY_DETERMINISM_CHECK",
+        value_enum,
+        default_value_t = DeterminismCheck::Off
+    )]
+    determinism_check: DeterminismCheck,
+
+    /// Restore the cache in this process instead of in the Hurry daemon.
+    ///
+    /// Uploads still run in the daemon. Restore also falls back to this
+    /// process on its own if the daemon can't be started.
+    #[arg(
+        long = "hurry-no-daemon",
+        env = "HURRY_NO_DAEMON",
+        default_value_t = false
+    )]
+    no_daemon: bool,
+
     /// Show help for `hurry cross build`.
     #[arg(long = "hurry-help", default_value_t = false)]
     pub help: bool,
@@ -103,14 +177,6 @@ pub async fn exec(options: Options) -> Result<()> {
         return cross::invoke("build", &options.argv).await;
     }
 
-    // We make the API token required here; if we make it required in the actual
-    // clap state then we aren't able to support e.g. `cross build -h` passthrough.
-    let Some(token) = &options.api_token else {
-        return Err(eyre!("Hurry API authentication token is required"))
-            .suggestion("Set the `HURRY_API_TOKEN` environment variable")
-            .suggestion("Provide it with the `--hurry-api-token` argument");
-    };
-
     info!("Starting");
 
     // Parse and validate cargo build arguments.
@@ -123,6 +189,16 @@ pub async fn exec(options: Options) -> Result<()> {
         .context("opening workspace")?;
     debug!(?workspace, "opened workspace");
 
+    // We make the API token required here; if we make it required in the actual
+    // clap state then we aren't able to support e.g. `cross build -h` passthrough.
+    let (api_url, token) = cmd::setup::connection(
+        &workspace.root,
+        options.api_url.clone(),
+        options.api_token.clone(),
+        options.non_interactive,
+    )
+    .await?;
+
     // Compute expected unit plans using cross build plan.
     // If this fails (unsupported target, etc.), fall back to passthrough.
     println!("[hurry] Computing build plan inside Cross context");
@@ -144,15 +220,26 @@ pub async fn exec(options: Options) -> Result<()> {
     };
 
     // Initialize cache.
-    let cache = CargoCache::open(options.api_url, token.clone(), workspace)
+    let cache = CargoCache::open(api_url, token, workspace)
         .await
-        .context("opening cache")?;
+        .context("opening cache")?
+        .with_restore_in_daemon(!options.no_daemon)
+        .with_deferred_upload(options.async_upload)
+        .with_cache_scope(options.cache_scope.clone());
 
     // Restore artifacts.
     let unit_count = units.len() as u64;
     let restored = if !options.skip_restore {
         let progress = TransferBar::new(unit_count, "Restoring cache");
-        cache.restore(&units, &progress).await?
+        let restored = cache.restore(&units, &progress).await?;
+        progress.finish();
+        if let Some(summary) = restored.policy_summary() {
+            eprintln!("{summary}");
+        }
+        if let Some(summary) = restored.repair_summary() {
+            eprintln!("{summary}");
+        }
+        restored
     } else {
         Default::default()
     };
@@ -164,59 +251,115 @@ pub async fn exec(options: Options) -> Result<()> {
         cross::invoke("build", &options.argv)
             .await
             .context("build with cross")?;
+        cache.record_build_timings(&units, &restored).await;
     }
 
-    // Cache the built artifacts.
-    if !options.skip_backup {
-        let upload_id = cache.save(units, restored).await?;
+    // Cache the built artifacts. Read-only builds never ask the daemon to
+    // upload at all.
+    if options.cache_read_only {
+        eprintln!("[hurry] Skipped uploading: this build can only read from the cache");
+    } else if !options.skip_backup {
+        let policy = UploadPolicy::builder()
+            .size_floor(options.upload_size_floor)
+            .min_rebuild_per_gib(options.upload_min_rebuild_per_gib)
+            .determinism(options.determinism_check)
+            .build();
+        let upload_id = cache.save(units, restored, policy).await?;
         if !options.async_upload {
             let progress = TransferBar::new(unit_count, "Uploading cache");
-            wait_for_upload(upload_id, &progress).await?;
+            let saved = tokio::select! {
+                saved = wait_for_upload(upload_id, &progress) => saved,
+                _ = tokio::signal::ctrl_c() => {
+                    progress.finish();
+                    cancel_upload(upload_id).await;
+                    bail!("interrupted while uploading cache");
+                }
+            };
+            progress.finish();
+
+            // The build itself succeeded, so a failed upload only means the
+            // next build can't restore from it.
+            match saved {
+                Ok(saved) => {
+                    if let Some(summary) = saved.write_policy_summary() {
+                        eprintln!("{summary}");
+                    }
+                    if let Some(summary) = saved.maintenance_summary() {
+                        eprintln!("{summary}");
+                    }
+                    if let Some(summary) = saved.policy_summary() {
+                        eprintln!("{summary}");
+                    }
+                    if let Some(summary) = saved.validation_summary() {
+                        eprintln!("{summary}");
+                    }
+                    if let Some(summary) = saved.determinism_summary() {
+                        eprintln!("{summary}");
+                    }
+                    if let Some(summary) = saved.repair_summary() {
+                        eprintln!("{summary}");
+                    }
+                }
+                Err(err) => eprintln!("Failed to upload cache: {err:#}"),
+            }
         }
     }
 
     Ok(())
 }
 
+/// Cancel the upload, since the user interrupted the build that's waiting on
+/// it and an upload nobody is waiting for only competes with their next build.
+async fn cancel_upload(request_id: Uuid) {
+    match cmd::cancel::cancel_uploads(Some(request_id)).await {
+        Ok(_) => debug!(?request_id, "cancelled upload"),
+        Err(err) => warn!(?err, ?request_id, "failed to cancel upload"),
+    }
+}
+
 #[instrument]
-async fn wait_for_upload(request_id: Uuid, progress: &TransferBar) -> Result<()> {
+async fn wait_for_upload(request_id: Uuid, progress: &TransferBar) -> Result<SaveProgress> {
     let paths = DaemonPaths::initialize().await?;
     let Some(daemon) = paths.daemon_running().await? else {
         bail!("daemon is not running");
     };
 
-    let client = reqwest::Client::default();
-    let endpoint = format!("http://{}/api/v0/cargo/status", daemon.url);
-    let request = CargoUploadStatusRequest { request_id };
+    let client = hurry::daemon::local_client()?;
+    let endpoint = format!("http://{}/api/v0/cargo/status/{request_id}", daemon.url);
     let mut interval = tokio::time::interval(Duration::from_secs(1));
 
     let mut last_uploaded_artifacts = 0u64;
     let mut last_uploaded_files = 0u64;
-    let mut last_uploaded_bytes = 0u64;
+    let mut last_transferred_bytes = 0u64;
     let mut last_total_artifacts = 0u64;
     loop {
         interval.tick().await;
-        trace!(?request, "submitting upload status request");
+        trace!(?request_id, "requesting upload progress");
         let response = client
-            .post(&endpoint)
-            .json(&request)
+            .get(&endpoint)
             .send()
             .await
             .with_context(|| format!("send upload status request to daemon at: {endpoint}"))
             .with_section(|| format!("{daemon:?}").header("Daemon context:"))?;
         trace!(?response, "got upload status response");
-        let response = response.json::<CargoUploadStatusResponse>().await?;
+        let response = response.json::<CargoUploadProgressResponse>().await?;
         trace!(?response, "parsed upload status response");
+        progress.set_detail(response.detail());
+        progress.add_bytes(
+            response
+                .transferred_bytes
+                .saturating_sub(last_transferred_bytes),
+        );
+        last_transferred_bytes = response.transferred_bytes;
         let status = response.status.ok_or_eyre("no upload status")?;
         match status {
-            CargoUploadStatus::Complete => break,
+            CargoUploadStatus::Complete(save_progress) => return Ok(save_progress),
+            CargoUploadStatus::Failed { error, .. } => return Err(eyre!(error)),
+            CargoUploadStatus::Cancelled(_) => bail!("upload cancelled"),
+            CargoUploadStatus::Superseded { by } => {
+                bail!("upload superseded by a later upload: {by}")
+            }
             CargoUploadStatus::InProgress(save_progress) => {
-                progress.add_bytes(
-                    save_progress
-                        .uploaded_bytes
-                        .saturating_sub(last_uploaded_bytes),
-                );
-                last_uploaded_bytes = save_progress.uploaded_bytes;
                 progress.add_files(
                     save_progress
                         .uploaded_files
@@ -234,6 +377,4 @@ async fn wait_for_upload(request_id: Uuid, progress: &TransferBar) -> Result<()>
             }
         }
     }
-
-    Ok(())
 }
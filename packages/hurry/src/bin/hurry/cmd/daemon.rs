@@ -1,6 +1,7 @@
 use clap::Subcommand;
 
 pub mod start;
+pub mod status;
 pub mod stop;
 
 #[derive(Clone, Debug, Subcommand)]
@@ -13,4 +14,7 @@ pub enum Command {
     /// The daemon does finish serving any requests to it, but any uploads that
     /// are in-flight or enqueued are interrupted by this shutdown.
     Stop(stop::Options),
+
+    /// Show whether the daemon is running, and what it's doing.
+    Status(status::Options),
 }
@@ -1,11 +1,15 @@
+use std::time::Duration;
+
 use axum::{
     Json, Router,
     extract::{FromRef, State},
-    routing::post,
+    http::header,
+    response::IntoResponse,
+    routing::{get, post},
 };
 use clap::Args;
 use color_eyre::{
-    Result, Section, SectionExt,
+    Result,
     eyre::{Context as _, bail},
 };
 
@@ -19,9 +23,11 @@ use url::Url;
 
 use crate::{TopLevelFlags, log};
 use hurry::{
-    daemon::{CargoDaemonState, DaemonContext, DaemonPaths, cargo_router},
+    daemon::{
+        CargoDaemonState, DaemonContext, DaemonHealth, DaemonPaths, DaemonStatus, InvocationReport,
+        cargo_router,
+    },
     fs,
-    path::TryJoinWith,
 };
 
 #[derive(Clone, Args, Debug)]
@@ -34,6 +40,16 @@ pub struct Options {
     )]
     #[debug("{api_url}")]
     api_url: Url,
+
+    /// How long to wait for uploads that have started to finish when the
+    /// daemon is stopped, in seconds. Uploads that don't finish in time are
+    /// resumed by the next daemon.
+    #[arg(
+        long = "drain-timeout",
+        env = "HURRY_DAEMON_DRAIN_TIMEOUT",
+        default_value_t = 30
+    )]
+    drain_timeout: u64,
 }
 
 #[instrument(skip(cli_logger))]
@@ -48,7 +64,7 @@ pub async fn exec(
 
     let paths = DaemonPaths::initialize().await?;
     let pid = std::process::id();
-    let log_file_path = cache_dir.try_join_file(format!("hurryd.{pid}.log"))?;
+    let log_file_path = paths.log_file_path(pid)?;
 
     // Redirect logging into file (for daemon mode). We need to redirect the
     // logging firstly so that we can continue to see logs if the invoking
@@ -134,8 +150,13 @@ pub async fn exec(
 
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
+    let cargo = CargoDaemonState::new()
+        .context("initialize cargo state")?
+        .with_upload_journal(paths.uploads_dir()?);
+    cargo.resume_uploads().await;
+    cargo.start_prewarming();
     let state = ServerState {
-        cargo: CargoDaemonState::default(),
+        cargo: cargo.clone(),
         shutdown_tx,
     };
 
@@ -144,22 +165,24 @@ pub async fn exec(
             "/api/v0/cargo",
             cargo_router().with_state(state.cargo.clone()),
         )
+        .route("/api/v0/health", get(health))
+        .route("/api/v0/status", get(status))
         .route("/api/v0/shutdown", post(shutdown))
+        .route("/api/v0/metrics", get(metrics))
+        .route("/api/v0/metrics/invocations", post(report_invocation))
         .with_state(state)
         .layer(TraceLayer::new_for_http());
 
-    // Write context file for daemon clients.
-    let message = DaemonContext {
+    // Write context file for daemon clients. This is what clients wait for
+    // after spawning the daemon, so it's only written once the listener is
+    // bound: connections made from then on are queued until the server starts
+    // accepting them below.
+    let context = DaemonContext {
         pid,
         url: format!("{addr}"),
         log_file_path,
     };
-    let encoded = serde_json::to_string(&message)
-        .context("encode ready message")
-        .with_section(|| format!("{message:?}").header("Message:"))?;
-    fs::write(&paths.context_path, &encoded)
-        .await
-        .with_context(|| format!("write daemon context to {:?}", paths.context_path))?;
+    paths.write_context(&context).await?;
 
     // We don't immediately handle the error with `?` here so that we can perform
     // the cleanup operations regardless of whether an error occurred.
@@ -168,6 +191,25 @@ pub async fn exec(
         .await
         .context("start server");
 
+    // Uploads and prefetches run in the background after their requests
+    // return, so they're still running once the server stops accepting them.
+    // Give uploads that have started a chance to finish before cancelling
+    // everything; a second Ctrl+C skips the wait. Anything cancelled stays in
+    // the upload journal for the next daemon to resume.
+    let drain_timeout = Duration::from_secs(options.drain_timeout);
+    info!(?drain_timeout, "draining uploads");
+    tokio::select! {
+        remaining = cargo.drain(drain_timeout) => {
+            if remaining > 0 {
+                warn!(remaining, "uploads didn't finish in time; they'll resume on next start");
+            }
+        }
+        _ = signal::ctrl_c() => {
+            warn!("received SIGINT (Ctrl+C) while draining uploads, stopping now");
+        }
+    }
+    cargo.shutdown().await;
+
     info!(?paths, "exiting; cleaning up context files");
     if let Err(err) = fs::remove_file(&paths.pid_file_path).await {
         warn!(?err, path = ?paths.pid_file_path, "failed to remove pid file");
@@ -227,6 +269,35 @@ struct ServerState {
     cargo: CargoDaemonState,
     shutdown_tx: watch::Sender<bool>,
 }
+#[instrument]
+async fn health() -> Json<DaemonHealth> {
+    Json(DaemonHealth::current())
+}
+
+#[instrument]
+async fn status(State(state): State<ServerState>) -> Json<DaemonStatus> {
+    Json(state.cargo.status())
+}
+
+/// Serve the daemon's metrics in the Prometheus text format.
+#[instrument]
+async fn metrics(State(state): State<ServerState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.cargo.metrics(),
+    )
+}
+
+/// Record an invocation of `hurry` reported by the CLI in the metrics.
+#[instrument]
+async fn report_invocation(
+    State(state): State<ServerState>,
+    Json(report): Json<InvocationReport>,
+) -> Json<serde_json::Value> {
+    state.cargo.record_invocation(&report);
+    Json(serde_json::json!({ "ok": true }))
+}
+
 #[instrument]
 async fn shutdown(State(state): State<ServerState>) -> Json<serde_json::Value> {
     info!("shutdown request received");
@@ -0,0 +1,46 @@
+use clap::Args;
+use color_eyre::{Result, eyre::Context as _};
+use indicatif::HumanDuration;
+use tracing::instrument;
+
+use hurry::{daemon::DaemonPaths, progress::format_size};
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {}
+
+/// Report whether the daemon is running and what it's doing.
+#[instrument]
+pub async fn exec(_options: Options) -> Result<()> {
+    let paths = DaemonPaths::initialize().await?;
+
+    let Some(context) = paths.daemon_running().await? else {
+        println!("Daemon not running");
+        return Ok(());
+    };
+
+    let status = context.status().await.context("get daemon status")?;
+    println!(
+        "Daemon running (pid {}, Hurry {})",
+        status.pid, status.version
+    );
+    println!("Uptime: {}", HumanDuration(status.uptime));
+    println!(
+        "Uploads: {} in flight ({} deferred), {} units and {} queued",
+        status.uploads_in_flight,
+        status.uploads_deferred,
+        status.queued_units,
+        format_size(status.queued_bytes),
+    );
+    println!("Watched workspaces: {}", status.watched_workspaces);
+    match &status.last_error {
+        Some(error) => {
+            let at = jiff::Timestamp::from_second(error.at)
+                .map(|at| at.to_string())
+                .unwrap_or_else(|_| error.at.to_string());
+            println!("Last error ({at}): {}", error.message);
+        }
+        None => println!("Last error: none"),
+    }
+    println!("Log file: {}", context.log_file_path);
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use clap::Subcommand;
 use color_eyre::Result;
 
+pub mod artifact_diff;
 pub mod check;
 pub mod copy;
 pub mod daemon;
@@ -19,6 +20,10 @@ pub enum Command {
     /// Recursively copy the contents of the source directory to destination.
     Copy(copy::Options),
 
+    /// Compare two build artifacts (`.rlib`, `.rmeta`, or object files) and
+    /// show which archive members and sections differ.
+    ArtifactDiff(artifact_diff::Options),
+
     /// Daemon-related debugging commands.
     #[clap(subcommand)]
     Daemon(daemon::Command),
@@ -29,6 +34,7 @@ pub async fn exec(cmd: Command) -> Result<()> {
         Command::Check(opts) => check::exec(opts).await,
         Command::Metadata(opts) => metadata::exec(opts).await,
         Command::Copy(opts) => copy::exec(opts).await,
+        Command::ArtifactDiff(opts) => artifact_diff::exec(opts).await,
         Command::Daemon(subcmd) => daemon::exec(subcmd).await,
     }
 }
@@ -0,0 +1,48 @@
+use clap::Args;
+use color_eyre::{
+    Result,
+    eyre::{Context, bail},
+};
+use colored::Colorize;
+use hurry::{cargo::diff_artifacts, fs, path::SomeFilePath};
+use tracing::instrument;
+
+/// Options for `debug artifact-diff`
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// The first artifact.
+    a: SomeFilePath,
+
+    /// The second artifact.
+    b: SomeFilePath,
+}
+
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let a = options
+        .a
+        .try_as_abs_file_using_cwd()
+        .context("make first artifact path absolute")?;
+    let b = options
+        .b
+        .try_as_abs_file_using_cwd()
+        .context("make second artifact path absolute")?;
+    let content_a = fs::must_read_buffered(&a).await?;
+    let content_b = fs::must_read_buffered(&b).await?;
+
+    let differences = diff_artifacts(&content_a, &content_b).context("diff artifacts")?;
+    if differences.is_empty() {
+        println!("artifacts are identical");
+        return Ok(());
+    }
+
+    println!("{} {a}", "a:".red());
+    println!("{} {b}", "b:".green());
+    for difference in &differences {
+        println!("{difference}");
+    }
+
+    // Exit with an error like `diff` does, so that scripts can tell whether
+    // the artifacts matched.
+    bail!("found {} differences", differences.len())
+}
@@ -10,7 +10,7 @@ use derive_more::Debug;
 use tracing::{debug, info, instrument, trace, warn};
 use url::Url;
 
-use clients::Token;
+use clients::{Token, courier::v1::CacheScope};
 use hurry::{
     cargo::{self, CargoBuildArguments, CargoCache, Handles, Workspace},
     progress::TransferBar,
@@ -31,6 +31,15 @@ pub struct Options {
     #[arg(long = "hurry-api-token", env = "HURRY_API_TOKEN")]
     api_token: Token,
 
+    /// Restore units from this cache scope rather than from the
+    /// organization's unscoped cache.
+    #[arg(
+        long = "hurry-cache-scope",
+        env = "HURRY_CACHE_SCOPE",
+        value_name = "NAME"
+    )]
+    cache_scope: Option<CacheScope>,
+
     /// These arguments are passed directly to `cargo build` as provided.
     #[arg(
         num_args = ..,
@@ -64,7 +73,8 @@ pub async fn exec(options: Options) -> Result<()> {
     // Initialize cache.
     let cache = CargoCache::open(options.api_url, options.api_token, workspace)
         .await
-        .context("opening cache")?;
+        .context("opening cache")?
+        .with_cache_scope(options.cache_scope);
 
     // Restore units.
     let unit_count = units.len() as u64;
@@ -95,12 +105,12 @@ pub async fn exec(options: Options) -> Result<()> {
     for message in Message::parse_stream(output) {
         debug!(?message, "cargo message");
         let message = message?;
+        // Packages from any registry are cached, including alternative
+        // registries.
         if let Message::CompilerArtifact(msg) = message
             && !msg.fresh
-            && msg
-                .package_id
-                .repr
-                .starts_with("registry+https://github.com/rust-lang/crates.io-index#")
+            && (msg.package_id.repr.starts_with("registry+")
+                || msg.package_id.repr.starts_with("sparse+"))
         {
             // TODO: Only warn if _restored_ units are not fresh.
             warn!("unit {:?} is not fresh", msg.package_id);
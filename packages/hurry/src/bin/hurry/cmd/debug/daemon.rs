@@ -1,6 +1,7 @@
 use clap::Subcommand;
 use color_eyre::Result;
 
+pub mod connections;
 pub mod context;
 pub mod log;
 pub mod status;
@@ -16,6 +17,9 @@ pub enum Command {
 
     /// Report whether the daemon is running or stopped.
     Status(status::Options),
+
+    /// Show statistics for the daemon's connections to Courier.
+    Connections(connections::Options),
 }
 
 pub async fn exec(cmd: Command) -> Result<()> {
@@ -23,5 +27,6 @@ pub async fn exec(cmd: Command) -> Result<()> {
         Command::Log(opts) => log::exec(opts).await,
         Command::Context(opts) => context::exec(opts).await,
         Command::Status(opts) => status::exec(opts).await,
+        Command::Connections(opts) => connections::exec(opts).await,
     }
 }
@@ -0,0 +1,32 @@
+use clap::Args;
+use color_eyre::{Result, eyre::Context as _};
+use hurry::daemon::{CargoConnectionsResponse, DaemonPaths, local_client};
+use tracing::instrument;
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {}
+
+#[instrument]
+pub async fn exec(_options: Options) -> Result<()> {
+    let paths = DaemonPaths::initialize().await?;
+
+    let Some(daemon) = paths.daemon_running().await? else {
+        eprintln!("Daemon not running");
+        return Ok(());
+    };
+
+    let endpoint = format!("http://{}/api/v0/cargo/connections", daemon.url);
+    let response = local_client()?
+        .get(&endpoint)
+        .send()
+        .await
+        .with_context(|| format!("send connections request to daemon at: {endpoint}"))?
+        .json::<CargoConnectionsResponse>()
+        .await
+        .context("parse connections response")?;
+
+    let stats = serde_json::to_string_pretty(&response.stats)?;
+    println!("{stats}");
+
+    Ok(())
+}
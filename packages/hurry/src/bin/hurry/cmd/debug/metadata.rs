@@ -14,6 +14,11 @@ use tracing::instrument;
 pub struct Options {
     /// The directory to inspect.
     path: SomeDirPath,
+
+    /// Only inspect source files, skipping files ignored by `.gitignore` or
+    /// `.hurryignore` files.
+    #[arg(long)]
+    sources: bool,
 }
 
 #[instrument]
@@ -22,9 +27,15 @@ pub async fn exec(options: Options) -> Result<()> {
 
     // We have to buffer this so that we can sort the files; we want to sort the
     // files so that the output of two metadata commands can be diffed.
-    let files = fs::walk_files(&root)
-        .try_collect::<Vec<AbsFilePath>>()
-        .await?;
+    let files = if options.sources {
+        fs::walk_source_files(&root, &[])
+            .try_collect::<Vec<AbsFilePath>>()
+            .await?
+    } else {
+        fs::walk_files(&root)
+            .try_collect::<Vec<AbsFilePath>>()
+            .await?
+    };
 
     for path in files.into_iter().sorted() {
         let rel = path.relative_to(&root)?;
@@ -0,0 +1,141 @@
+use std::{collections::HashSet, time::Duration};
+
+use clap::Args;
+use color_eyre::{
+    Result,
+    eyre::{Context as _, OptionExt as _},
+};
+use derive_more::Debug;
+use tracing::{info, instrument};
+use url::Url;
+
+use clients::{Token, courier::v1::CacheScope};
+use hurry::{
+    cargo::{
+        CargoBuildArguments, CargoCache, LockWait, UnitHash, Workspace, referenced_units,
+        stale_artifacts,
+    },
+    progress::format_size,
+};
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// List the artifacts that would be removed without removing them.
+    #[arg(long = "dry-run")]
+    dry_run: bool,
+
+    /// Also remove the third-party artifacts that the build still uses if the
+    /// remote cache can restore them.
+    #[arg(long = "evict-restorable", requires = "api_token")]
+    evict_restorable: bool,
+
+    /// Base URL for the Hurry API.
+    #[arg(
+        long = "api-url",
+        env = "HURRY_API_URL",
+        default_value = "https://app.hurry.build"
+    )]
+    #[debug("{api_url}")]
+    api_url: Url,
+
+    /// Authentication token for the Hurry API.
+    ///
+    /// Only used with `--evict-restorable`.
+    #[arg(long = "api-token", env = "HURRY_API_TOKEN")]
+    api_token: Option<Token>,
+
+    /// Check whether units are restorable from this cache scope rather than
+    /// from the organization's unscoped cache.
+    ///
+    /// Only used with `--evict-restorable`.
+    #[arg(long = "cache-scope", env = "HURRY_CACHE_SCOPE", value_name = "NAME")]
+    cache_scope: Option<CacheScope>,
+
+    /// Fail after this many seconds if another build holds the lock on the
+    /// build directory. By default, Hurry waits until the lock is released.
+    #[arg(
+        long = "lock-timeout",
+        env = "HURRY_LOCK_TIMEOUT",
+        value_name = "SECONDS"
+    )]
+    lock_timeout: Option<u64>,
+
+    /// These arguments are interpreted as they would be by `cargo build`;
+    /// artifacts that such a build uses are kept. Artifacts that only other
+    /// commands (e.g. `cargo test` or `cargo check`) use are removed.
+    #[arg(
+        num_args = ..,
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "ARGS",
+    )]
+    argv: Vec<String>,
+}
+
+/// Remove artifacts from the build directory that the build no longer uses.
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let args = CargoBuildArguments::from_iter(&options.argv);
+    let workspace = Workspace::from_argv(&args)
+        .await
+        .context("open workspace")?;
+    let lock_wait = match options.lock_timeout {
+        Some(secs) => LockWait::Timeout(Duration::from_secs(secs)),
+        None => LockWait::Forever,
+    };
+
+    // Computing the build plan renames the build directory and the artifacts
+    // are removed from it, so nothing else can be building in it meanwhile.
+    let locks = workspace
+        .lock_profile_dirs(lock_wait)
+        .await
+        .context("lock profile directories")?;
+    let (mut keep, units) = referenced_units(&workspace, &args)
+        .await
+        .context("calculate referenced units")?;
+    if options.evict_restorable {
+        let token = options
+            .api_token
+            .clone()
+            .ok_or_eyre("--evict-restorable requires an API token")?;
+        let cache = CargoCache::open(options.api_url.clone(), token, workspace.clone())
+            .await
+            .context("opening cache")?
+            .with_cache_scope(options.cache_scope.clone());
+        let restorable = cache
+            .restorable(&units)
+            .await
+            .context("find restorable units")?;
+        info!(restorable = restorable.len(), "evicting restorable units");
+        keep = keep
+            .difference(&restorable)
+            .cloned()
+            .collect::<HashSet<UnitHash>>();
+    }
+
+    let stale = stale_artifacts(&workspace, &keep)
+        .await
+        .context("find stale artifacts")?;
+    let total = stale.iter().map(|artifact| artifact.size()).sum::<u64>();
+    if options.dry_run {
+        for artifact in &stale {
+            println!("{artifact} ({})", format_size(artifact.size()));
+        }
+        println!(
+            "Would remove {} artifacts ({})",
+            stale.len(),
+            format_size(total)
+        );
+        return Ok(());
+    }
+
+    for artifact in &stale {
+        artifact
+            .remove()
+            .await
+            .with_context(|| format!("remove stale artifact: {artifact}"))?;
+    }
+    drop(locks);
+    println!("Removed {} artifacts ({})", stale.len(), format_size(total));
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use clap::Args;
+use color_eyre::{Result, eyre::Context as _};
+use derive_more::Debug;
+use tracing::instrument;
+use url::Url;
+
+use hurry::{
+    fs, mk_rel_file,
+    path::{AbsDirPath, JoinWith as _},
+};
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// Base URL for the Hurry API.
+    #[arg(
+        long = "api-url",
+        env = "HURRY_API_URL",
+        default_value = "https://app.hurry.build"
+    )]
+    #[debug("{api_url}")]
+    api_url: Url,
+
+    /// Append the configuration to `.cargo/config.toml` in the current
+    /// directory instead of printing it.
+    #[arg(long)]
+    write: bool,
+}
+
+/// Generate Cargo configuration that downloads crates through Hurry.
+///
+/// The configuration replaces crates.io with the crate registry proxy in the
+/// Hurry API, which caches crates.io index files and crates close to the
+/// build. Cargo authenticates with the token in `CARGO_REGISTRIES_HURRY_TOKEN`.
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let snippet = config_snippet(&options.api_url);
+    if !options.write {
+        print!("{snippet}");
+        return Ok(());
+    }
+
+    let path = AbsDirPath::current()?.join(mk_rel_file!(".cargo/config.toml"));
+    let existing = fs::read_buffered_utf8(&path)
+        .await
+        .context("read cargo config")?
+        .unwrap_or_default();
+
+    // Cargo rejects configuration that defines the same table twice, and we
+    // don't want to silently override a source replacement the user chose.
+    if existing.contains("[source.crates-io]") {
+        println!("{path} already replaces crates.io; not modifying it.");
+        println!("To use Hurry, replace that configuration with:\n\n{snippet}");
+        return Ok(());
+    }
+
+    let separator = match existing.as_str() {
+        "" => "",
+        content if content.ends_with('\n') => "\n",
+        _ => "\n\n",
+    };
+    fs::write(&path, format!("{existing}{separator}{snippet}"))
+        .await
+        .context("write cargo config")?;
+    println!("Wrote crate registry configuration to {path}");
+    println!("Set CARGO_REGISTRIES_HURRY_TOKEN to your Hurry API token before building.");
+    Ok(())
+}
+
+fn config_snippet(api_url: &Url) -> String {
+    let base = api_url.as_str().trim_end_matches('/');
+    format!(
+        r#"# Download crates through the Hurry crate registry proxy.
+# Cargo authenticates with the Hurry API token in CARGO_REGISTRIES_HURRY_TOKEN.
+[source.crates-io]
+replace-with = "hurry"
+
+[registries.hurry]
+index = "sparse+{base}/api/v1/registry/crates-io/index/"
+"#
+    )
+}
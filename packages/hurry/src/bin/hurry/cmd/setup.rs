@@ -0,0 +1,222 @@
+use std::{io::IsTerminal as _, process::Stdio, time::Duration};
+
+use clap::Args;
+use color_eyre::{
+    Result, Section as _,
+    eyre::{Context as _, bail, eyre},
+};
+use derive_more::Debug;
+use inquire::Text;
+use tracing::instrument;
+use url::Url;
+
+use clients::{Courier, Token};
+use hurry::{
+    auth,
+    cargo::in_ci,
+    config::{ApiConfig, HurryConfig},
+    path::AbsDirPath,
+};
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// Base URL for the Hurry API. Asked for if not set.
+    #[arg(long = "api-url", env = "HURRY_API_URL")]
+    #[debug("{api_url:?}")]
+    api_url: Option<Url>,
+
+    /// Authentication token for the Hurry API. If not set, this machine is
+    /// authorized in the browser instead.
+    #[arg(long = "api-token", env = "HURRY_API_TOKEN")]
+    api_token: Option<Token>,
+
+    /// Never prompt; use the URL and token given as arguments or in the
+    /// environment.
+    #[arg(long, env = "HURRY_NON_INTERACTIVE")]
+    non_interactive: bool,
+}
+
+/// Connect Hurry to the Hurry API and save the connection to the user's
+/// configuration.
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let interactive = !options.non_interactive && is_interactive();
+    run(options.api_url, options.api_token, interactive).await?;
+    Ok(())
+}
+
+/// Whether Hurry can prompt the user: both ends of the terminal are
+/// attached, and it isn't running in CI.
+pub fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stderr().is_terminal() && !in_ci()
+}
+
+/// The connection to the Hurry API for a command that needs one.
+///
+/// Arguments and the environment take precedence, then the configuration for
+/// the workspace at `root`. If nothing is configured at all yet and Hurry can
+/// prompt, this is the first run, so the setup wizard runs first.
+pub async fn connection(
+    root: &AbsDirPath,
+    url: Option<Url>,
+    token: Option<Token>,
+    non_interactive: bool,
+) -> Result<(Url, Token)> {
+    let config = HurryConfig::load(root).await.context("load config")?;
+    if let Some(token) = config.api.token(token) {
+        return Ok((config.api.url(url), token));
+    }
+
+    let first_run = HurryConfig::load_user()
+        .await
+        .context("load user config")?
+        .is_none();
+    if first_run && !non_interactive && is_interactive() {
+        eprintln!("Hurry isn't set up on this machine yet; let's connect it to the Hurry API.");
+        return run(url, None, true).await;
+    }
+
+    Err(eyre!("Hurry API authentication token is required"))
+        .suggestion("Run `hurry setup` to authorize this machine")
+        .suggestion("Set the `HURRY_API_TOKEN` environment variable")
+        .suggestion("Provide it with the `--hurry-api-token` argument")
+}
+
+/// Set up the connection to the Hurry API, returning the URL and token it
+/// saved.
+///
+/// Whatever isn't given is asked for if `interactive` is set: the URL is
+/// prompted for and the token is issued by authorizing this machine in the
+/// browser. The connection is tested before anything is saved, so a bad URL
+/// or token is reported here rather than on the next build.
+pub async fn run(
+    url: Option<Url>,
+    token: Option<Token>,
+    interactive: bool,
+) -> Result<(Url, Token)> {
+    let config = HurryConfig::load_user()
+        .await
+        .context("load user config")?
+        .unwrap_or_default();
+
+    let url = match url {
+        Some(url) => url,
+        None if interactive => {
+            let default = config.api.url(None);
+            let url = Text::new("Hurry API URL:")
+                .with_default(default.as_str())
+                .prompt()?;
+            Url::parse(url.trim()).context("parse Hurry API URL")?
+        }
+        None => config.api.url(None),
+    };
+
+    let token = match token {
+        Some(token) => token,
+        None if interactive => authorize(&url, true).await?,
+        None => {
+            return Err(eyre!("Hurry API authentication token is required"))
+                .suggestion("Set the `HURRY_API_TOKEN` environment variable")
+                .suggestion("Provide it with the `--api-token` argument")
+                .suggestion("Run `hurry setup` in a terminal to authorize this machine");
+        }
+    };
+
+    save(config, url, token).await
+}
+
+/// Test the connection to the Hurry API, then save it to the user's
+/// configuration, returning the URL and token it saved.
+///
+/// `config` is the user's configuration to save the connection to.
+pub async fn save(mut config: HurryConfig, url: Url, token: Token) -> Result<(Url, Token)> {
+    eprintln!("Checking the connection to {url}...");
+    let courier = Courier::new(url.clone(), token.clone())?;
+    courier
+        .ping()
+        .await
+        .with_context(|| format!("reach the Hurry API at {url}"))
+        .suggestion("Check the URL, and that this machine can reach it")?;
+    courier
+        .cargo_cache_generation()
+        .await
+        .context("authenticate to the Hurry API")
+        .suggestion("Check that the token is valid and hasn't been revoked")?;
+
+    config.api = ApiConfig {
+        url: Some(url.clone()),
+        token: Some(token.clone()),
+    };
+    let path = config.save_user().await.context("save user config")?;
+    eprintln!("Hurry is set up; saved the connection to {path}");
+    Ok((url, token))
+}
+
+/// Authorize this machine in the browser, returning the token it's issued.
+///
+/// If `open_browser` is set, the authorization page is opened in the user's
+/// browser; the link is printed either way, for machines without one.
+pub async fn authorize(url: &Url, open_browser: bool) -> Result<Token> {
+    // Starting and polling an authorization doesn't need a token; the client
+    // just requires one.
+    let courier = Courier::new(url.clone(), Token::from("unauthenticated"))?;
+    let name = sysinfo::System::host_name()
+        .map(|host| format!("hurry on {host}"))
+        .unwrap_or_else(|| String::from("hurry"));
+    let authorization = courier
+        .device_authorization_start(&name)
+        .await
+        .with_context(|| format!("start authorization with the Hurry API at {url}"))?;
+
+    let link = url
+        .join(&authorization.verification_path)
+        .context("build authorization link")?;
+    if open_browser && open_in_browser(&link) {
+        eprintln!("Opened the authorization page in your browser. If it didn't open, visit:\n");
+    } else {
+        eprintln!("To authorize this machine, open this link and sign in:\n");
+    }
+    eprintln!("    {link}\n");
+    eprintln!(
+        "Then check that the code matches: {}",
+        authorization.user_code
+    );
+    eprintln!("Waiting for authorization...");
+
+    let approved = auth::poll_device_authorization(
+        &courier,
+        &authorization.device_code,
+        Duration::from_secs(authorization.interval.max(1)),
+        Duration::from_secs(authorization.expires_in),
+    )
+    .await?;
+    match approved {
+        Some(approved) => {
+            eprintln!("Authorized.");
+            Ok(approved.token)
+        }
+        None => bail!(
+            "the authorization expired before it was approved; run `hurry setup` to try again"
+        ),
+    }
+}
+
+/// Open the link in the user's default browser, returning whether a browser
+/// was started.
+fn open_in_browser(link: &Url) -> bool {
+    let (program, args): (&str, &[&str]) = if cfg!(target_os = "macos") {
+        ("open", &[])
+    } else if cfg!(windows) {
+        ("cmd", &["/C", "start", ""])
+    } else {
+        ("xdg-open", &[])
+    };
+    std::process::Command::new(program)
+        .args(args)
+        .arg(link.as_str())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .is_ok()
+}
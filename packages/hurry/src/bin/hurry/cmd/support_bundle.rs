@@ -0,0 +1,243 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use clap::Args;
+use color_eyre::{Result, eyre::Context as _};
+use derive_more::Debug;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{instrument, warn};
+
+use hurry::{
+    cargo::in_ci,
+    config::HurryConfig,
+    daemon::{CargoUploadStatusAllResponse, DaemonPaths, local_client},
+    fs,
+    path::{AbsDirPath, AbsFilePath, TryJoinWith as _},
+    support::{REDACTED, Redactor, SupportBundle, strip_ansi, tail},
+};
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// Where to write the bundle. Defaults to
+    /// `hurry-support-<timestamp>.tar.gz` in the current directory.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// How many of the most recent daemon logs to include.
+    #[arg(long, default_value_t = 3)]
+    logs: usize,
+
+    /// How many lines to keep from the end of each daemon log.
+    #[arg(long, default_value_t = 2000)]
+    log_lines: usize,
+}
+
+/// Gather diagnostics into a redacted `tar.gz` to attach to bug reports.
+///
+/// Each part of the bundle is collected on a best-effort basis: anything that
+/// can't be collected is noted in `bundle-errors.txt` instead of failing the
+/// whole bundle, since the bundle is usually wanted exactly when something is
+/// broken.
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let cwd = AbsDirPath::current()?;
+    let output = match &options.output {
+        Some(path) => std::path::absolute(path)
+            .context("resolve output path")
+            .and_then(AbsFilePath::try_from)?,
+        None => {
+            let timestamp = jiff::Timestamp::now().strftime("%Y%m%dT%H%M%SZ");
+            cwd.try_join_file(format!("hurry-support-{timestamp}.tar.gz"))?
+        }
+    };
+
+    let config = HurryConfig::load(&cwd).await;
+    let mut redactor = Redactor::default();
+    if let Ok(config) = &config
+        && let Some(token) = &config.api.token
+    {
+        redactor = redactor.with_secret(token.expose());
+    }
+    for (name, value) in std::env::vars() {
+        if Redactor::is_secret_name(&name) {
+            redactor = redactor.with_secret(value);
+        }
+    }
+
+    let mut bundle = SupportBundle::new(redactor);
+    let mut errors = Vec::new();
+
+    bundle.add_text("version.txt", &versions().await);
+    bundle.add_json("environment.json", &Environment::current())?;
+    match config {
+        Ok(mut config) => {
+            if config.api.token.is_some() {
+                config.api.token = Some(REDACTED.into());
+            }
+            match toml::to_string(&config) {
+                Ok(config) => bundle.add_text("hurry.toml", &config),
+                Err(err) => errors.push(format!("encode config: {err:?}")),
+            }
+        }
+        Err(err) => errors.push(format!("load config: {err:?}")),
+    }
+    if let Err(err) = add_daemon(&mut bundle, &options).await {
+        errors.push(format!("collect daemon state: {err:?}"));
+    }
+    if !errors.is_empty() {
+        for err in &errors {
+            warn!(%err, "support bundle is incomplete");
+        }
+        bundle.add_text("bundle-errors.txt", &errors.join("\n\n"));
+    }
+
+    bundle
+        .write(&output)
+        .await
+        .with_context(|| format!("write support bundle to {output}"))?;
+    println!("Wrote support bundle to {output}");
+    for path in bundle.paths() {
+        println!("  {path}");
+    }
+    println!("Secrets are redacted, but review the bundle before attaching it to an issue.");
+    Ok(())
+}
+
+/// Add the daemon's context, uploads, and logs to the bundle.
+///
+/// Log lines at `ERROR` or `WARN` level are also collected into
+/// `errors.log`, which is usually where a report starts.
+async fn add_daemon(bundle: &mut SupportBundle, options: &Options) -> Result<()> {
+    let paths = DaemonPaths::initialize().await?;
+
+    match paths.daemon_running().await? {
+        Some(daemon) => {
+            bundle.add_json("daemon/context.json", &daemon)?;
+            match daemon.health().await {
+                Ok(health) => bundle.add_json("daemon/health.json", &health)?,
+                Err(err) => bundle.add_text("daemon/health.txt", &format!("{err:?}")),
+            }
+            let endpoint = format!("http://{}/api/v0/cargo/status/all", daemon.url);
+            let uploads = async {
+                local_client()?
+                    .get(&endpoint)
+                    .send()
+                    .await
+                    .context("send upload status request")?
+                    .json::<CargoUploadStatusAllResponse>()
+                    .await
+                    .context("parse upload status response")
+            };
+            match uploads.await {
+                Ok(uploads) => bundle.add_json("daemon/uploads.json", &uploads)?,
+                Err(err) => bundle.add_text("daemon/uploads.txt", &format!("{err:?}")),
+            }
+        }
+        None => bundle.add_text("daemon/context.txt", "daemon not running\n"),
+    }
+
+    // Uploads the daemon hasn't finished are recorded until they are, so
+    // these are the invocations that a crashed or stopped daemon left behind.
+    let uploads_dir = paths.uploads_dir()?;
+    if fs::exists(&uploads_dir).await {
+        let mut entries = fs::read_dir(&uploads_dir).await?;
+        while let Some(entry) = entries.next_entry().await.context("read uploads")? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.ends_with(".json") {
+                continue;
+            }
+            let path = uploads_dir.try_join_file(&name)?;
+            let Some(content) = fs::read_buffered(&path).await? else {
+                continue;
+            };
+            match serde_json::from_slice::<Value>(&content) {
+                Ok(record) => bundle.add_json(format!("daemon/pending-uploads/{name}"), &record)?,
+                Err(err) => warn!(?err, ?path, "could not parse pending upload"),
+            }
+        }
+    }
+
+    let mut errors = String::new();
+    for log in paths.log_files().await?.into_iter().take(options.logs) {
+        let Some(content) = fs::read_buffered_utf8(&log).await? else {
+            continue;
+        };
+        let content = strip_ansi(tail(&content, options.log_lines));
+        let name = log.file_name_str_lossy().unwrap_or_default();
+        for line in content.lines() {
+            if line.contains(" ERROR ") || line.contains(" WARN ") {
+                errors.push_str(&format!("{name}: {line}\n"));
+            }
+        }
+        bundle.add_text(format!("daemon/logs/{name}"), &content);
+    }
+    bundle.add_text("errors.log", &errors);
+
+    Ok(())
+}
+
+/// The versions of Hurry and of the Rust toolchain it builds with.
+async fn versions() -> String {
+    let mut versions = format!("hurry {}\n", env!("HURRY_VERSION"));
+    for (program, args) in [("cargo", &["--version"][..]), ("rustc", &["-vV"][..])] {
+        let output = tokio::process::Command::new(program)
+            .args(args)
+            .output()
+            .await;
+        match output {
+            Ok(output) => versions.push_str(&String::from_utf8_lossy(&output.stdout)),
+            Err(err) => versions.push_str(&format!("{program}: {err}\n")),
+        }
+    }
+    versions
+}
+
+/// A fingerprint of the machine and environment Hurry runs in.
+#[derive(Debug, Serialize)]
+struct Environment {
+    os: String,
+    os_version: Option<String>,
+    kernel_version: Option<String>,
+    arch: &'static str,
+    cpus: usize,
+    memory_bytes: u64,
+    ci: bool,
+
+    /// Environment variables that affect Hurry, Cargo, or rustc. Values of
+    /// variables that hold secrets are redacted.
+    env: BTreeMap<String, String>,
+}
+
+impl Environment {
+    fn current() -> Self {
+        let mut system = sysinfo::System::new();
+        system.refresh_memory();
+        let env = std::env::vars()
+            .filter(|(name, _)| {
+                let upper = name.to_ascii_uppercase();
+                ["HURRY_", "CARGO", "RUST"]
+                    .iter()
+                    .any(|prefix| upper.starts_with(prefix))
+                    || upper.ends_with("_PROXY")
+            })
+            .map(|(name, value)| {
+                let value = if Redactor::is_secret_name(&name) {
+                    String::from(REDACTED)
+                } else {
+                    value
+                };
+                (name, value)
+            })
+            .collect();
+        Self {
+            os: String::from(std::env::consts::OS),
+            os_version: sysinfo::System::long_os_version(),
+            kernel_version: sysinfo::System::kernel_version(),
+            arch: std::env::consts::ARCH,
+            cpus: num_cpus::get(),
+            memory_bytes: system.total_memory(),
+            ci: in_ci(),
+            env,
+        }
+    }
+}
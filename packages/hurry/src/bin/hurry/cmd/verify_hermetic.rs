@@ -0,0 +1,139 @@
+use clap::Args;
+use color_eyre::{
+    Result,
+    eyre::{Context as _, bail},
+};
+use colored::Colorize as _;
+use tracing::{debug, instrument};
+
+use hurry::{
+    cargo::{self, CargoBuildArguments, Workspace, hermetic},
+    fs,
+    path::{AbsDirPath, SomeFilePath, TryJoinWith as _},
+};
+
+/// The most differences shown for each differing file.
+const MAX_SHOWN_DIFFERENCES: usize = 5;
+
+#[derive(Clone, Args, Debug)]
+pub struct Options {
+    /// Write the `hurry.toml` entries that exclude the crates whose units
+    /// differ from the cache to this file.
+    #[arg(long, value_name = "PATH")]
+    exclusions: Option<SomeFilePath>,
+
+    /// Keep the two build directories instead of removing them, e.g. to
+    /// inspect the artifacts that differ.
+    #[arg(long)]
+    keep: bool,
+
+    /// These arguments are passed directly to `cargo build` as provided.
+    #[arg(
+        num_args = ..,
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "ARGS",
+    )]
+    argv: Vec<String>,
+}
+
+#[instrument]
+pub async fn exec(options: Options) -> Result<()> {
+    let args = CargoBuildArguments::from_iter(&options.argv);
+    if args.target_dir().is_some() {
+        bail!("`--target-dir` can't be set: each build gets its own build directory");
+    }
+    let pwd = AbsDirPath::current().context("get working directory")?;
+    let workspace = Workspace::from_argv_in_dir(&pwd, &args)
+        .await
+        .context("opening workspace")?;
+
+    // The build directories have names of different lengths, so that paths
+    // embedded in artifacts differ in length too and can't line up by
+    // accident.
+    let root = workspace.build_dir.try_join_dir("hurry-verify-hermetic")?;
+    let dirs = [root.try_join_dir("a")?, root.try_join_dir("second")?];
+    if fs::exists(&root).await {
+        fs::remove_dir_all(&root).await?;
+    }
+
+    let mut builds = Vec::new();
+    for (n, dir) in dirs.iter().enumerate() {
+        println!("[hurry] Build {} of 2 in {dir}", n + 1);
+        let args = args.clone().with_target_dir(dir.to_string());
+        let workspace = Workspace::from_argv_in_dir(&pwd, &args)
+            .await
+            .context("opening workspace")?;
+        let units = workspace
+            .units(&args)
+            .await
+            .context("calculating expected units")?;
+        debug!(units = units.len(), build_dir = %workspace.build_dir, "building");
+        cargo::invoke("build", args.to_argv())
+            .await
+            .context("build with cargo")?;
+        builds.push((workspace, units));
+    }
+
+    let [(first_ws, first_units), (second_ws, second_units)] = builds.as_slice() else {
+        unreachable!("two builds were run");
+    };
+    let differing = hermetic::compare_builds((first_ws, first_units), (second_ws, second_units))
+        .await
+        .context("compare builds")?;
+    if !options.keep {
+        fs::remove_dir_all(&root).await?;
+    }
+
+    if differing.is_empty() {
+        println!(
+            "[hurry] All {} units built the same way twice",
+            first_units.len()
+        );
+        return Ok(());
+    }
+
+    println!(
+        "[hurry] {} units built differently in different build directories:",
+        differing.len()
+    );
+    for unit in &differing {
+        println!(
+            "  {} {} ({})",
+            unit.package_name.bold(),
+            unit.package_version,
+            unit.unit_hash
+        );
+        for file in &unit.files {
+            println!("    {}", file.name);
+            for difference in file.differences.iter().take(MAX_SHOWN_DIFFERENCES) {
+                println!("      {difference}");
+            }
+            if file.differences.len() > MAX_SHOWN_DIFFERENCES {
+                println!(
+                    "      ... and {} more",
+                    file.differences.len() - MAX_SHOWN_DIFFERENCES
+                );
+            }
+        }
+    }
+
+    let exclusions = hermetic::exclusions(&differing);
+    match options.exclusions {
+        Some(path) => {
+            let path = path
+                .try_as_abs_file_using_cwd()
+                .context("make exclusions path absolute")?;
+            fs::write(&path, &exclusions).await?;
+            println!("[hurry] Wrote exclusions to {path}; add them to hurry.toml");
+        }
+        None => {
+            println!("[hurry] Add these to hurry.toml to stop caching these crates:");
+            println!();
+            print!("{exclusions}");
+        }
+    }
+
+    // Exit with an error so that CI can tell whether the build is hermetic.
+    bail!("{} units are not hermetic", differing.len())
+}
@@ -0,0 +1,87 @@
+use std::ffi::OsString;
+
+use clap::{Args, CommandFactory, Parser};
+use color_eyre::{
+    Result, Section as _,
+    eyre::{Context, eyre},
+};
+use hurry::{
+    cargo::wrapper::{self, CargoWrappers},
+    config::HurryConfig,
+    path::AbsDirPath,
+};
+use tracing::debug;
+
+use crate::cmd::cargo::build;
+
+/// Helper type for parsing options with `clap`.
+#[derive(Parser)]
+struct CommandOptions<T: Args> {
+    #[clap(flatten)]
+    opts: T,
+}
+
+impl<T: Args> CommandOptions<T> {
+    fn parse(args: impl IntoIterator<Item = impl Into<OsString> + Clone>) -> Result<Self> {
+        Self::try_parse_from(args).context("parse options")
+    }
+
+    fn into_inner(self) -> T {
+        self.opts
+    }
+}
+
+/// Execute a build tool that wraps Cargo, caching its builds if Hurry knows
+/// which `cargo build` invocation the subcommand runs.
+///
+/// The first argument is the name of the tool, and the rest are the tool's
+/// own arguments; the subcommand has to come first among them.
+pub async fn exec(arguments: Vec<String>) -> Result<()> {
+    let pwd = AbsDirPath::current().context("get working directory")?;
+    let config = HurryConfig::load(&pwd).await.context("load config")?;
+    let wrappers = CargoWrappers::builtin().with_config(&config.wrappers);
+
+    let known = || wrappers.names().collect::<Vec<_>>().join(", ");
+    let Some((name, arguments)) = arguments.split_first() else {
+        return Err(eyre!("no build tool given"))
+            .with_note(|| format!("Known tools: {}", known()))
+            .suggestion("Run e.g. `hurry wrap maturin build --release`");
+    };
+    let Some(tool) = wrappers.get(name) else {
+        return Err(eyre!("unknown build tool: {name}"))
+            .with_note(|| format!("Known tools: {}", known()))
+            .suggestion(format!(
+                "Register it in hurry.toml under `[wrappers.{name}]`"
+            ));
+    };
+
+    // Windows passes through for the same reasons as `hurry cargo`; see issue
+    // #153.
+    if cfg!(target_os = "windows") {
+        debug!("windows currently unconditionally passes through all wrapped commands");
+        return wrapper::invoke(tool.as_ref(), arguments).await;
+    }
+
+    let Some(subcommand) = arguments.first().filter(|arg| !arg.starts_with('-')) else {
+        return wrapper::invoke(tool.as_ref(), arguments).await;
+    };
+    if tool.build_args(subcommand, &arguments[1..]).is_none() {
+        debug!(?name, ?subcommand, "subcommand doesn't build with cargo");
+        return wrapper::invoke(tool.as_ref(), arguments).await;
+    }
+
+    let command = build::Command::Wrapped(build::Wrapped {
+        wrapper: tool,
+        subcommand: subcommand.clone(),
+    });
+    let opts: CommandOptions<build::Options> = CommandOptions::parse(arguments)?;
+    if opts.opts.help {
+        // `--help` passes through to the tool, so Hurry's own options are
+        // shown with `--hurry-help` instead.
+        let mut cmd = CommandOptions::<build::Options>::command();
+        cmd = cmd.about(format!("Run `{command}` with Hurry build acceleration"));
+        cmd.print_help()?;
+        return Ok(());
+    }
+    build::exec(command, opts.into_inner()).await
+}
@@ -1,10 +1,14 @@
 //! The binary entrypoint for `hurry`, the ultra-fast build tool.
 
-use std::path::PathBuf;
+use std::{ffi::OsString, path::PathBuf, time::Instant};
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory as _, Parser, Subcommand};
 use color_eyre::{Result, eyre::Context};
-use tracing::instrument;
+use hurry::{
+    cargo,
+    daemon::{DaemonPaths, InvocationReport},
+};
+use tracing::{debug, instrument};
 use tracing_subscriber::util::SubscriberInitExt;
 
 // Since this is a binary crate, we need to ensure these modules aren't pub
@@ -37,6 +41,11 @@ struct TopLevelFlags {
     /// When to colorize output
     #[arg(long, value_enum, default_value_t = log::WhenColor::Auto)]
     color: log::WhenColor,
+
+    /// Report how long the command took to the daemon, if it's running, so
+    /// that it's included in the daemon's metrics
+    #[arg(long, env = "HURRY_PUSH_METRICS", default_value_t = false)]
+    push_metrics: bool,
 }
 
 #[derive(Clone, Debug, Subcommand)]
@@ -61,12 +70,66 @@ enum Command {
         args: Vec<String>,
     },
 
-    // TODO: /// Manage remote authentication
-    // Auth,
+    /// Fast builds with tools that wrap `cargo`, like `maturin` and `wasm-pack`
+    ///
+    /// Run as `hurry wrap <tool> <subcommand> [args...]`. Tools other than the
+    /// built-in ones can be registered in `hurry.toml` under
+    /// `[wrappers.<tool>]`.
+    #[command(disable_help_flag = true, disable_version_flag = true)]
+    Wrap {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Connect Hurry to the Hurry API
+    ///
+    /// Asks for the API URL, authorizes this machine in the browser, checks
+    /// the connection, and saves it to the user's `hurry.toml`. Builds run
+    /// this on their own the first time Hurry is used in a terminal.
+    Setup(cmd::setup::Options),
+
+    /// Configure Cargo to download crates through Hurry
+    Init(cmd::init::Options),
+
+    /// Cancel cache uploads running in the background
+    Cancel(cmd::cancel::Options),
+
+    /// Remove artifacts that the build no longer uses from the build directory
+    ///
+    /// Only the units of the `cargo build` invocation described by the
+    /// arguments are kept. Artifacts that only `cargo test`, `cargo check`, or
+    /// `cargo clippy` use are named after other units, so they're removed and
+    /// those commands rebuild (or restore) them the next time they run.
+    GcTarget(cmd::gc_target::Options),
+
+    /// Check that the build produces the same artifacts in different build
+    /// directories
+    ///
+    /// Builds the workspace twice, in two clean build directories, and
+    /// compares each unit as it would be saved to the cache. Units that differ
+    /// are reported with the `hurry.toml` entries that stop caching their
+    /// crates. Arguments are passed to `cargo build`.
+    VerifyHermetic(cmd::verify_hermetic::Options),
+
+    /// Gather diagnostics into a redacted archive to attach to bug reports
+    ///
+    /// The archive contains the tail of the daemon's logs and the errors in
+    /// them, the daemon's pending uploads, version information, configuration,
+    /// and a fingerprint of the environment. Secrets are redacted.
+    SupportBundle(cmd::support_bundle::Options),
+
+    /// Log in to the Hurry API, or manage the saved login
+    #[clap(subcommand)]
+    Auth(cmd::auth::Command),
+
     /// Manage user cache
     #[clap(subcommand)]
     Cache(cmd::cache::Command),
 
+    /// Inspect Hurry's configuration
+    #[clap(subcommand)]
+    Config(cmd::config::Command),
+
     /// Debug information
     #[clap(subcommand, hide(true))]
     Debug(cmd::debug::Command),
@@ -79,19 +142,94 @@ enum Command {
     Daemon(cmd::daemon::Command),
 }
 
+impl Command {
+    /// The name the command is reported under in the daemon's metrics, or
+    /// `None` for commands that manage the daemon itself.
+    fn metrics_name(&self) -> Option<String> {
+        let with_subcommand =
+            |tool: &str, args: &[String]| match args.iter().find(|arg| !arg.starts_with('-')) {
+                Some(subcommand) => format!("{tool} {subcommand}"),
+                None => String::from(tool),
+            };
+        let name = match self {
+            Command::Cargo { args } => with_subcommand("cargo", args),
+            Command::Cross { args } => with_subcommand("cross", args),
+            Command::Wrap { args } => with_subcommand("wrap", args),
+            Command::Setup(_) => String::from("setup"),
+            Command::Init(_) => String::from("init"),
+            Command::Cancel(_) => String::from("cancel"),
+            Command::GcTarget(_) => String::from("gc-target"),
+            Command::VerifyHermetic(_) => String::from("verify-hermetic"),
+            Command::SupportBundle(_) => String::from("support-bundle"),
+            Command::Auth(_) => String::from("auth"),
+            Command::Cache(_) => String::from("cache"),
+            Command::Config(_) => String::from("config"),
+            Command::Debug(_) => String::from("debug"),
+            Command::Daemon(_) => return None,
+        };
+        Some(name)
+    }
+}
+
+/// Report an invocation to the daemon for its metrics, if the daemon is
+/// running. Reporting is best effort, so failures are only logged.
+async fn push_metrics(report: InvocationReport) {
+    let pushed = async {
+        let paths = DaemonPaths::initialize().await?;
+        if let Some(daemon) = paths.daemon_running().await? {
+            daemon.report_invocation(&report).await?;
+        }
+        Result::<()>::Ok(())
+    };
+    if let Err(err) = pushed.await {
+        debug!(?err, "failed to push invocation metrics");
+    }
+}
+
+/// The hand-off address and arguments if Hurry is running as Cargo's rustc
+/// wrapper: Cargo runs it with the rustc executable and its arguments, rather
+/// than a Hurry subcommand.
+fn rustc_wrapper_args() -> Option<(String, Vec<OsString>)> {
+    let addr = std::env::var(cargo::HANDOFF_ENV).ok()?;
+    let args = std::env::args_os().skip(1).collect::<Vec<_>>();
+    let program = args.first()?.to_str().unwrap_or_default();
+    let subcommand =
+        program.starts_with('-') || TopLevelFlags::command().find_subcommand(program).is_some();
+    (!subcommand).then_some((addr, args))
+}
+
 #[instrument]
 #[tokio::main]
 async fn main() -> Result<()> {
+    // While restoring with `--hurry-overlap-restore`, Cargo runs Hurry as its
+    // rustc wrapper. Cargo parses rustc's output, so nothing is logged.
+    if let Some((addr, args)) = rustc_wrapper_args() {
+        std::process::exit(cargo::wrap_rustc(&addr, args).await);
+    }
+
     color_eyre::install()?;
     let top = TopLevelFlags::parse();
     let t = top.clone();
+    let started = Instant::now();
+    let metrics_name = match top.push_metrics {
+        true => top.command.metrics_name(),
+        false => None,
+    };
 
     let (logger, flame_guard) = log::make_logger(std::io::stderr, top.profile.clone(), top.color)?;
     let result = match top.command {
+        Command::Auth(cmd) => {
+            logger.init();
+            cmd::auth::exec(cmd).await
+        }
         Command::Cache(cmd) => {
             logger.init();
             cmd::cache::exec(cmd).await
         }
+        Command::Config(cmd) => {
+            logger.init();
+            cmd::config::exec(cmd).await
+        }
         Command::Cargo { args } => {
             logger.init();
             cmd::cargo::exec(args).await
@@ -100,10 +238,38 @@ async fn main() -> Result<()> {
             logger.init();
             cmd::cross::exec(args).await
         }
+        Command::Wrap { args } => {
+            logger.init();
+            cmd::wrap::exec(args).await
+        }
         Command::Debug(cmd) => {
             logger.init();
             cmd::debug::exec(cmd).await
         }
+        Command::Setup(opts) => {
+            logger.init();
+            cmd::setup::exec(opts).await
+        }
+        Command::Init(opts) => {
+            logger.init();
+            cmd::init::exec(opts).await
+        }
+        Command::SupportBundle(opts) => {
+            logger.init();
+            cmd::support_bundle::exec(opts).await
+        }
+        Command::Cancel(opts) => {
+            logger.init();
+            cmd::cancel::exec(opts).await
+        }
+        Command::GcTarget(opts) => {
+            logger.init();
+            cmd::gc_target::exec(opts).await
+        }
+        Command::VerifyHermetic(opts) => {
+            logger.init();
+            cmd::verify_hermetic::exec(opts).await
+        }
         Command::Daemon(cmd) => match cmd {
             cmd::daemon::Command::Start(opts) => {
                 // Note that in daemon mode we do not initialize the logger!
@@ -115,9 +281,22 @@ async fn main() -> Result<()> {
                 logger.init();
                 cmd::daemon::stop::exec(opts).await
             }
+            cmd::daemon::Command::Status(opts) => {
+                logger.init();
+                cmd::daemon::status::exec(opts).await
+            }
         },
     };
 
+    if let Some(command) = metrics_name {
+        push_metrics(InvocationReport {
+            command,
+            duration: started.elapsed(),
+            success: result.is_ok(),
+        })
+        .await;
+    }
+
     // TODO: Unsure if we need to keep this, the guard _should_ flush on drop.
     if let Some(flame_guard) = flame_guard {
         flame_guard.flush().context("flush flame_guard")?;
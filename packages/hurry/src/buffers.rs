@@ -0,0 +1,203 @@
+//! Buffer sizes tuned for the current machine.
+//!
+//! [`NETWORK_BUFFER_SIZE`](clients::NETWORK_BUFFER_SIZE) and
+//! [`LOCAL_BUFFER_SIZE`](clients::LOCAL_BUFFER_SIZE) are reasonable defaults,
+//! but the best sizes depend on the machine's memory bandwidth and scheduler.
+//! The first time Hurry runs on a machine it measures throughput with a few
+//! candidate sizes, then persists the fastest in the user cache directory so
+//! later runs don't pay for the measurement.
+//!
+//! Sizes set in `hurry.toml` take precedence over the measured ones.
+
+use std::time::{Duration, Instant};
+
+use clients::BufferSizes;
+use color_eyre::{Result, eyre::Context as _};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    task::spawn_blocking,
+};
+use tracing::{debug, instrument, warn};
+
+use crate::{config::BufferSizeConfig, fs, mk_rel_file, path::JoinWith as _};
+
+/// Candidate sizes for buffers that move data between tasks, e.g. when
+/// streaming to or from the network.
+const NETWORK_CANDIDATES: [usize; 4] = [256 * 1024, 1024 * 1024, 4 * 1024 * 1024, 8 * 1024 * 1024];
+
+/// Candidate sizes for buffers that are processed in place, e.g. when hashing.
+const LOCAL_CANDIDATES: [usize; 4] = [16 * 1024, 64 * 1024, 256 * 1024, 1024 * 1024];
+
+/// The amount of data moved through each candidate buffer.
+///
+/// This is large enough that per-chunk overhead dominates the differences
+/// between candidates, but small enough that tuning finishes in well under a
+/// second.
+const SAMPLE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The version of the tuning procedure.
+///
+/// Bump this when the candidates or the measurements change, so that sizes
+/// measured by the old procedure are discarded.
+const TUNING_VERSION: u32 = 1;
+
+/// Buffer sizes measured on this machine, as persisted in the cache directory.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Tuned {
+    version: u32,
+    sizes: BufferSizes,
+}
+
+/// Resolve the buffer sizes to use, tuning them for this machine if they
+/// haven't been already.
+///
+/// Sizes set in `config` are used as-is. Failing to tune or to persist the
+/// results isn't fatal: the defaults are used instead.
+#[instrument]
+pub async fn resolve(config: &BufferSizeConfig) -> BufferSizes {
+    if let (Some(network), Some(local)) = (config.network, config.local) {
+        return BufferSizes { network, local };
+    }
+
+    let tuned = match load_or_tune().await {
+        Ok(tuned) => tuned,
+        Err(err) => {
+            warn!(?err, "failed to tune buffer sizes, using defaults");
+            BufferSizes::default()
+        }
+    };
+    BufferSizes {
+        network: config.network.unwrap_or(tuned.network),
+        local: config.local.unwrap_or(tuned.local),
+    }
+}
+
+async fn load_or_tune() -> Result<BufferSizes> {
+    let path = fs::user_global_cache_path()
+        .await?
+        .join(mk_rel_file!("buffer-sizes.json"));
+    if let Some(content) = fs::read_buffered(&path).await? {
+        match serde_json::from_slice::<Tuned>(&content) {
+            Ok(tuned) if tuned.version == TUNING_VERSION => {
+                debug!(?path, sizes = ?tuned.sizes, "loaded tuned buffer sizes");
+                return Ok(tuned.sizes);
+            }
+            Ok(tuned) => debug!(version = tuned.version, "discarding outdated buffer sizes"),
+            Err(err) => debug!(?err, "discarding unreadable buffer sizes"),
+        }
+    }
+
+    let sizes = tune().await?;
+    let tuned = Tuned {
+        version: TUNING_VERSION,
+        sizes,
+    };
+    let content = serde_json::to_vec(&tuned).context("serialize buffer sizes")?;
+    fs::write(&path, content)
+        .await
+        .context("persist buffer sizes")?;
+    Ok(sizes)
+}
+
+/// Measure throughput with each candidate size and return the fastest.
+#[instrument]
+pub async fn tune() -> Result<BufferSizes> {
+    let sample = sample();
+
+    let mut network = Vec::with_capacity(NETWORK_CANDIDATES.len());
+    for size in NETWORK_CANDIDATES {
+        let elapsed = measure_network(&sample, size).await?;
+        network.push((size, elapsed));
+    }
+
+    let local = spawn_blocking(move || {
+        LOCAL_CANDIDATES
+            .map(|size| (size, measure_local(&sample, size)))
+            .to_vec()
+    })
+    .await
+    .context("join local tuning task")?;
+
+    let sizes = BufferSizes {
+        network: fastest(&network),
+        local: fastest(&local),
+    };
+    debug!(?network, ?local, ?sizes, "tuned buffer sizes");
+    Ok(sizes)
+}
+
+/// The content moved through the candidate buffers.
+///
+/// This isn't all zeroes so that it's representative of real content for
+/// anything that behaves differently on uniform input.
+fn sample() -> Vec<u8> {
+    (0..SAMPLE_SIZE).map(|i| (i % 251) as u8).collect()
+}
+
+/// Time piping the sample from one task to another through a buffer of the
+/// provided size, which is how network buffers are used.
+async fn measure_network(sample: &[u8], size: usize) -> Result<Duration> {
+    let (mut reader, mut writer) = tokio::io::duplex(size);
+    let content = sample.to_vec();
+    let start = Instant::now();
+    let write = tokio::spawn(async move {
+        for chunk in content.chunks(size) {
+            writer.write_all(chunk).await?;
+        }
+        writer.shutdown().await
+    });
+
+    let mut buffer = vec![0; size];
+    while reader.read(&mut buffer).await.context("read from pipe")? > 0 {}
+    write
+        .await
+        .context("join writer")?
+        .context("write to pipe")?;
+    Ok(start.elapsed())
+}
+
+/// Time hashing the sample in chunks of the provided size, which is how local
+/// buffers are used.
+fn measure_local(sample: &[u8], size: usize) -> Duration {
+    let start = Instant::now();
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0; size];
+    for chunk in sample.chunks(size) {
+        let buffer = &mut buffer[..chunk.len()];
+        buffer.copy_from_slice(chunk);
+        hasher.update(buffer);
+    }
+    std::hint::black_box(hasher.finalize());
+    start.elapsed()
+}
+
+/// The size with the shortest measurement.
+///
+/// Ties go to the smaller size, since it uses less memory.
+fn fastest(measurements: &[(usize, Duration)]) -> usize {
+    measurements
+        .iter()
+        .min_by_key(|(size, elapsed)| (*elapsed, *size))
+        .map(|(size, _)| *size)
+        .expect("candidates are not empty")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::fastest;
+
+    #[test]
+    fn fastest_prefers_smaller_sizes_on_ties() {
+        let measurements = [
+            (1024, Duration::from_millis(5)),
+            (64, Duration::from_millis(3)),
+            (16, Duration::from_millis(3)),
+        ];
+        pretty_assert_eq!(fastest(&measurements), 16);
+    }
+}
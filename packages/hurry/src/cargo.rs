@@ -9,44 +9,71 @@ use color_eyre::{
     Result, Section as _, SectionExt as _,
     eyre::{Context, bail, eyre},
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::process::Child;
 use tracing::{instrument, trace};
 
+use crate::path::AbsDirPath;
+
+mod argv;
+mod artifact_diff;
 mod build_args;
 mod build_plan;
 mod build_script;
 mod cache;
+pub mod config;
 mod dep_info;
 mod fingerprint;
+mod gc;
 mod glibc;
+pub mod hermetic;
+mod near_match;
 mod path;
+mod phase;
 mod profile;
 mod rustc;
+mod stats;
 mod unit_graph;
+mod unit_hashes;
 mod units;
 mod workspace;
+pub mod wrapper;
 
+pub use argv::CargoInvocation;
+pub use artifact_diff::{Difference, DifferenceKind, Format, Side, diff_artifacts};
 pub use build_args::{CargoBuildArgument, CargoBuildArguments, ColorWhen, MessageFormat};
-pub use build_plan::{BuildPlan, BuildPlanInvocation};
+pub use build_plan::{BuildPlan, BuildPlanIndex, BuildPlanInvocation};
 pub use build_script::BuildScriptOutput;
-pub use cache::{CargoCache, Restored, SaveProgress, SavedFile, save_units};
+pub use cache::{
+    BigArtifacts, CacheRepair, CargoCache, CratePolicy, DeterminismCheck, HANDOFF_ENV,
+    HandoffServer, InvalidUnit, NondeterministicUnit, RestoreDecision, RestoreHandoff,
+    RestoreOptions, Restored, SaveProgress, SavedFile, UnitProblem, UnitUploadProgress,
+    UnitUploadState, UploadDecision, UploadPolicy, UploadReason, current_branch,
+    estimate_time_saved, in_ci, prefetch_units, resolve_checked_units, restorable_units,
+    restore_units, rustc_version, save_units, split_for_handoff, traffic_class, wrap_rustc,
+};
 pub use dep_info::{DepInfo, DepInfoLine};
 pub use fingerprint::Fingerprint;
+pub use gc::{StaleArtifact, referenced_units, stale_artifacts};
 pub use glibc::host_glibc_version;
 pub use path::QualifiedPath;
+pub use phase::{Phase, PhaseTiming, PhaseTimings};
 pub use profile::Profile;
 pub use rustc::{RustcArgument, RustcArguments, RustcTarget, RustcTargetPlatform};
+pub use stats::{BuildStats, StatsFormat};
 pub use unit_graph::{
     UnitGraph, UnitGraphDependency, UnitGraphProfile, UnitGraphProfilePanicStrategy, UnitGraphUnit,
 };
+pub use unit_hashes::{ToolchainDescriptor, UnitHashEntry, UnitHashInput, UnitKind, unit_hashes};
 pub use units::{
     BuildScriptCompilationUnitPlan, BuildScriptCompiledFiles, BuildScriptExecutionUnitPlan,
     BuildScriptOutputFiles, LibraryCrateUnitPlan, LibraryFiles,
 };
-pub use workspace::{UnitHash, UnitPlan, UnitPlanInfo, Workspace};
+pub use workspace::{
+    CheckMode, CheckPlan, LockWait, UnitHash, UnitPlan, UnitPlanInfo, Workspace, WorkspaceIdentity,
+};
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Deserialize)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum CargoCompileMode {
     Test,
@@ -90,11 +117,22 @@ pub async fn invoke_plain(
 pub async fn invoke(
     subcommand: impl AsRef<str> + fmt::Debug,
     args: impl IntoIterator<Item = impl AsRef<str>> + fmt::Debug,
+) -> Result<()> {
+    invoke_env(subcommand, args, [] as [(&OsStr, &OsStr); 0]).await
+}
+
+/// Execute a Cargo subcommand with specified arguments and additional
+/// environment variables.
+#[instrument]
+pub async fn invoke_env(
+    subcommand: impl AsRef<str> + fmt::Debug,
+    args: impl IntoIterator<Item = impl AsRef<str>> + fmt::Debug,
+    env: impl IntoIterator<Item = (impl AsRef<OsStr>, impl AsRef<OsStr>)> + fmt::Debug,
 ) -> Result<()> {
     let status = invoke_with(
         subcommand,
         args,
-        [] as [(&OsStr, &OsStr); 0],
+        env,
         Handles {
             stdout: Stdio::inherit(),
             stderr: Stdio::inherit(),
@@ -128,6 +166,35 @@ pub async fn invoke_output(
         },
     )
     .await?;
+    wait_output(child).await
+}
+
+/// Execute a Cargo subcommand in `dir` with specified arguments and
+/// environment variables, capturing and returning the output.
+///
+/// Cargo finds the manifest and configuration from the directory it's
+/// invoked in, and resolves relative paths in its arguments against it.
+pub async fn invoke_output_in(
+    dir: &AbsDirPath,
+    subcommand: impl AsRef<str> + fmt::Debug,
+    args: impl IntoIterator<Item = impl AsRef<str>> + fmt::Debug,
+    env: impl IntoIterator<Item = (impl AsRef<OsStr>, impl AsRef<OsStr>)> + fmt::Debug,
+) -> Result<Output> {
+    let child = spawn(
+        Some(dir),
+        subcommand,
+        args,
+        env,
+        Handles {
+            stdout: Stdio::piped(),
+            stderr: Stdio::piped(),
+        },
+    )?;
+    wait_output(child).await
+}
+
+/// Wait for Cargo to exit, returning its output if it succeeded.
+async fn wait_output(child: Child) -> Result<Output> {
     let output = child.wait_with_output().await?;
     if output.status.success() {
         Ok(output)
@@ -160,17 +227,31 @@ pub async fn invoke_with(
     args: impl IntoIterator<Item = impl AsRef<str>> + fmt::Debug,
     env: impl IntoIterator<Item = (impl AsRef<OsStr>, impl AsRef<OsStr>)> + fmt::Debug,
     handles: Handles,
+) -> Result<Child> {
+    spawn(None, subcommand, args, env, handles)
+}
+
+/// Spawn Cargo, in `dir` if provided or the current directory otherwise.
+fn spawn(
+    dir: Option<&AbsDirPath>,
+    subcommand: impl AsRef<str>,
+    args: impl IntoIterator<Item = impl AsRef<str>>,
+    env: impl IntoIterator<Item = (impl AsRef<OsStr>, impl AsRef<OsStr>)>,
+    handles: Handles,
 ) -> Result<Child> {
     let subcommand = subcommand.as_ref();
     let args = args.into_iter().collect::<Vec<_>>();
     let args = args.iter().map(|a| a.as_ref()).collect::<Vec<_>>();
 
-    trace!(?subcommand, ?args, "invoke cargo");
+    trace!(?dir, ?subcommand, ?args, "invoke cargo");
     let mut cmd = tokio::process::Command::new("cargo");
     cmd.args(once(subcommand).chain(args.iter().copied()));
     cmd.envs(env);
     cmd.stdout(handles.stdout);
     cmd.stderr(handles.stderr);
+    if let Some(dir) = dir {
+        cmd.current_dir(dir.as_std_path());
+    }
 
     cmd.spawn().context("could not spawn cargo")
 }
@@ -0,0 +1,452 @@
+//! Parsing Cargo command lines.
+//!
+//! Hurry decides which of its commands to run by finding the Cargo subcommand
+//! in the arguments it's given. That isn't always the first argument: global
+//! options can come before it (`cargo --locked build`), it can be an alias
+//! (`cargo b`, or one defined under `[alias]` in `.cargo/config.toml`), and
+//! anything after it belongs to the subcommand even if it looks like another
+//! subcommand (`cargo run -- build`).
+
+use std::collections::{HashMap, HashSet};
+
+use color_eyre::{Result, eyre::bail};
+use serde::Deserialize;
+use tracing::{instrument, trace};
+
+use crate::{cargo::config, path::AbsDirPath};
+
+/// Global options that take a value.
+const GLOBAL_OPTIONS_WITH_VALUES: [&str; 5] = ["--color", "--config", "-Z", "-C", "--explain"];
+
+/// Global options that Cargo only accepts before the subcommand.
+const LEADING_OPTIONS: [&str; 7] = [
+    "-C",
+    "--explain",
+    "--list",
+    "-V",
+    "--version",
+    "-h",
+    "--help",
+];
+
+/// Aliases that are built into Cargo.
+const BUILTIN_ALIASES: [(&str, &str); 6] = [
+    ("b", "build"),
+    ("c", "check"),
+    ("d", "doc"),
+    ("r", "run"),
+    ("t", "test"),
+    ("rm", "remove"),
+];
+
+/// Subcommands that are built into Cargo, which can't be redefined by
+/// aliases.
+const BUILTIN_COMMANDS: [&str; 37] = [
+    "add",
+    "bench",
+    "build",
+    "check",
+    "clean",
+    "config",
+    "doc",
+    "fetch",
+    "fix",
+    "generate-lockfile",
+    "git-checkout",
+    "help",
+    "info",
+    "init",
+    "install",
+    "locate-project",
+    "login",
+    "logout",
+    "metadata",
+    "new",
+    "owner",
+    "package",
+    "pkgid",
+    "publish",
+    "read-manifest",
+    "remove",
+    "report",
+    "run",
+    "rustc",
+    "rustdoc",
+    "search",
+    "test",
+    "tree",
+    "uninstall",
+    "update",
+    "vendor",
+    "yank",
+];
+
+/// A Cargo command line, split around its subcommand.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct CargoInvocation {
+    /// Arguments before the subcommand, e.g. `--locked` in
+    /// `cargo --locked build`.
+    pub global_args: Vec<String>,
+
+    /// The subcommand, if there is one.
+    pub subcommand: Option<String>,
+
+    /// Arguments after the subcommand, including anything after `--`.
+    pub args: Vec<String>,
+}
+
+impl CargoInvocation {
+    /// Split the arguments to `cargo` around the subcommand.
+    ///
+    /// This doesn't resolve aliases; use [`CargoInvocation::resolve_aliases`]
+    /// for that.
+    pub fn parse(argv: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let mut argv = argv.into_iter().map(Into::into);
+        let mut invocation = Self::default();
+        while let Some(arg) = argv.next() {
+            if arg == "--" {
+                invocation.args.push(arg);
+                break;
+            }
+
+            // Toolchain overrides (`+nightly`) are handled by the rustup
+            // proxy, but they still come before the subcommand.
+            if arg.starts_with('+') {
+                invocation.global_args.push(arg);
+                continue;
+            }
+
+            let Some(option) = option_name(&arg) else {
+                invocation.subcommand = Some(arg);
+                break;
+            };
+            let attached = option.len() < arg.len();
+            let takes_value = GLOBAL_OPTIONS_WITH_VALUES.contains(&option);
+            invocation.global_args.push(arg);
+            if takes_value
+                && !attached
+                && let Some(value) = argv.next()
+            {
+                invocation.global_args.push(value);
+            }
+        }
+        invocation.args.extend(argv);
+        invocation
+    }
+
+    /// Resolve the subcommand if it's an alias, using the aliases configured
+    /// for Cargo invoked in `cwd`.
+    #[instrument(name = "CargoInvocation::resolve_aliases")]
+    pub async fn resolve_aliases(self, cwd: &AbsDirPath) -> Result<Self> {
+        let aliases = Aliases::load(cwd, &self.global_args).await?;
+        self.resolve_aliases_with(&aliases)
+    }
+
+    fn resolve_aliases_with(mut self, aliases: &Aliases) -> Result<Self> {
+        let mut seen = HashSet::new();
+        while let Some(subcommand) = self.subcommand.take() {
+            if BUILTIN_COMMANDS.contains(&subcommand.as_str()) {
+                self.subcommand = Some(subcommand);
+                break;
+            }
+            let Some(expansion) = aliases.get(&subcommand) else {
+                self.subcommand = Some(subcommand);
+                break;
+            };
+            if !seen.insert(subcommand.clone()) {
+                bail!("alias `{subcommand}` expands to itself");
+            }
+            trace!(?subcommand, ?expansion, "expand alias");
+
+            // Aliases can expand to global options as well as to the
+            // subcommand and its arguments.
+            let expanded = Self::parse(expansion.into_iter().chain(self.args));
+            self.global_args.extend(expanded.global_args);
+            self.subcommand = expanded.subcommand;
+            self.args = expanded.args;
+        }
+        Ok(self)
+    }
+
+    /// The arguments with the subcommand first, followed by the global
+    /// options and then the subcommand's arguments.
+    ///
+    /// Cargo accepts most global options after the subcommand too, and
+    /// putting the subcommand first lets Hurry parse the rest as that
+    /// subcommand's options. Returns `None` if there's no subcommand or if a
+    /// global option can only come before it.
+    pub fn subcommand_argv(&self) -> Option<Vec<String>> {
+        let subcommand = self.subcommand.clone()?;
+        let movable = self.global_args.iter().all(|arg| {
+            !arg.starts_with('+')
+                && option_name(arg).is_none_or(|option| !LEADING_OPTIONS.contains(&option))
+        });
+        if !movable {
+            return None;
+        }
+        let argv = [subcommand]
+            .into_iter()
+            .chain(self.global_args.iter().cloned())
+            .chain(self.args.iter().cloned())
+            .collect();
+        Some(argv)
+    }
+}
+
+/// The name of the option in `arg`, without any attached value, or `None` if
+/// `arg` isn't an option.
+fn option_name(arg: &str) -> Option<&str> {
+    if let Some(long) = arg.strip_prefix("--") {
+        let name = long.split_once('=').map_or(long, |(name, _)| name);
+        Some(&arg[..name.len() + 2])
+    } else if arg.starts_with('-') && arg.len() > 1 {
+        // Short options may have their value attached, e.g. `-Zflag`, and
+        // verbosity may be repeated, e.g. `-vv`.
+        Some(arg.get(..2).unwrap_or(arg))
+    } else {
+        None
+    }
+}
+
+/// Aliases defined for Cargo, in `[alias]` in configuration files, in
+/// `CARGO_ALIAS_<NAME>` environment variables, or with `--config`.
+#[derive(Clone, Debug, Default)]
+struct Aliases {
+    configured: HashMap<String, Vec<String>>,
+    env: HashMap<String, Vec<String>>,
+    overrides: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AliasConfig {
+    #[serde(default)]
+    alias: HashMap<String, AliasValue>,
+}
+
+/// Aliases can be written as a string that's split on whitespace, or as a
+/// list of arguments.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AliasValue {
+    String(String),
+    List(Vec<String>),
+}
+
+impl From<AliasValue> for Vec<String> {
+    fn from(value: AliasValue) -> Self {
+        match value {
+            AliasValue::String(value) => value.split_whitespace().map(String::from).collect(),
+            AliasValue::List(value) => value,
+        }
+    }
+}
+
+impl Aliases {
+    /// Load the aliases for Cargo invoked in `cwd` with the provided global
+    /// options.
+    async fn load(cwd: &AbsDirPath, global_args: &[String]) -> Result<Self> {
+        let files = config::read::<AliasConfig>(cwd, config::cargo_home(cwd).as_ref()).await?;
+        let mut configured = HashMap::new();
+        for file in files {
+            for (name, value) in file.config.alias {
+                configured.entry(name).or_insert_with(|| value.into());
+            }
+        }
+        let env = std::env::vars()
+            .filter_map(|(key, value)| {
+                let name = key.strip_prefix("CARGO_ALIAS_")?;
+                Some((name.to_string(), AliasValue::String(value).into()))
+            })
+            .collect();
+        Ok(Self {
+            configured,
+            env,
+            overrides: config_overrides(global_args),
+        })
+    }
+
+    /// The expansion of the alias, if `name` is one.
+    ///
+    /// Like other configuration, `--config` takes precedence over environment
+    /// variables, which take precedence over configuration files. All of them
+    /// take precedence over Cargo's built-in aliases.
+    fn get(&self, name: &str) -> Option<Vec<String>> {
+        let env_name = name.to_uppercase().replace('-', "_");
+        self.overrides
+            .get(name)
+            .or_else(|| self.env.get(&env_name))
+            .or_else(|| self.configured.get(name))
+            .cloned()
+            .or_else(|| {
+                BUILTIN_ALIASES
+                    .iter()
+                    .find(|(alias, _)| *alias == name)
+                    .map(|(_, command)| vec![String::from(*command)])
+            })
+    }
+}
+
+/// Aliases set with `--config 'alias.<name>=<value>'` in the global options.
+///
+/// The value of `--config` is either a TOML `key=value` pair or a path to a
+/// configuration file; only pairs are read here. Later options take
+/// precedence, the same way they do in Cargo.
+fn config_overrides(global_args: &[String]) -> HashMap<String, Vec<String>> {
+    let mut args = global_args.iter();
+    let mut overrides = HashMap::new();
+    while let Some(arg) = args.next() {
+        let value = match arg.strip_prefix("--config") {
+            Some("") => args.next().map(String::as_str),
+            Some(attached) => attached.strip_prefix('='),
+            None => None,
+        };
+        let Some(config) = value.and_then(|value| toml::from_str::<AliasConfig>(value).ok()) else {
+            continue;
+        };
+        for (name, value) in config.alias {
+            overrides.insert(name, value.into());
+        }
+    }
+    overrides
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+    use tempfile::TempDir;
+
+    use super::{Aliases, CargoInvocation};
+    use crate::{
+        fs,
+        path::{AbsDirPath, TryJoinWith as _},
+    };
+
+    fn aliases(configured: &[(&str, &[&str])]) -> Aliases {
+        Aliases {
+            configured: configured
+                .iter()
+                .map(|(name, expansion)| {
+                    let expansion = expansion.iter().copied().map(String::from).collect();
+                    (String::from(*name), expansion)
+                })
+                .collect(),
+            env: HashMap::new(),
+            overrides: HashMap::new(),
+        }
+    }
+
+    #[test_case(&["build", "--release"], Some("build"); "subcommand first")]
+    #[test_case(&["--locked", "build"], Some("build"); "global flag first")]
+    #[test_case(&["--color", "always", "build"], Some("build"); "global option with value")]
+    #[test_case(&["--config=build.jobs=4", "build"], Some("build"); "global option with attached value")]
+    #[test_case(&["-Zunstable-options", "-vv", "build"], Some("build"); "short options")]
+    #[test_case(&["+nightly", "build"], Some("build"); "toolchain override")]
+    #[test_case(&["run", "--", "build"], Some("run"); "subcommand after separator")]
+    #[test_case(&["--version"], None; "no subcommand")]
+    #[test]
+    fn parses_subcommand(argv: &[&str], expected: Option<&str>) {
+        let invocation = CargoInvocation::parse(argv.iter().copied());
+        pretty_assert_eq!(invocation.subcommand.as_deref(), expected);
+    }
+
+    #[test]
+    fn resolves_builtin_alias() {
+        let invocation = CargoInvocation::parse(["b", "--release"])
+            .resolve_aliases_with(&aliases(&[]))
+            .unwrap();
+        pretty_assert_eq!(
+            invocation.subcommand_argv(),
+            Some(vec![String::from("build"), String::from("--release")])
+        );
+    }
+
+    #[test]
+    fn resolves_configured_alias() {
+        let aliases = aliases(&[("xb", &["--locked", "rb"]), ("rb", &["build", "--release"])]);
+        let invocation = CargoInvocation::parse(["xb", "-p", "foo"])
+            .resolve_aliases_with(&aliases)
+            .unwrap();
+        pretty_assert_eq!(
+            invocation.subcommand_argv(),
+            Some(
+                ["build", "--locked", "--release", "-p", "foo"]
+                    .map(String::from)
+                    .to_vec()
+            )
+        );
+    }
+
+    #[test]
+    fn aliases_cannot_shadow_builtin_commands() {
+        let aliases = aliases(&[("build", &["check"])]);
+        let invocation = CargoInvocation::parse(["build"])
+            .resolve_aliases_with(&aliases)
+            .unwrap();
+        pretty_assert_eq!(invocation.subcommand.as_deref(), Some("build"));
+    }
+
+    #[test]
+    fn recursive_alias_is_an_error() {
+        let aliases = aliases(&[("a", &["b2"]), ("b2", &["a"])]);
+        let resolved = CargoInvocation::parse(["a"]).resolve_aliases_with(&aliases);
+        assert!(resolved.is_err());
+    }
+
+    #[test]
+    fn leading_options_are_not_moved() {
+        let invocation = CargoInvocation::parse(["+nightly", "build"]);
+        pretty_assert_eq!(invocation.subcommand_argv(), None);
+    }
+
+    #[tokio::test]
+    async fn resolves_aliases_from_config_hierarchy() {
+        let temp = TempDir::new().unwrap();
+        let root = AbsDirPath::try_from(temp.path().to_path_buf()).unwrap();
+        let nested = root.try_join_dir("crates/app").unwrap();
+        let write = |dir: &AbsDirPath, content: &'static str| {
+            let path = dir.try_join_file(".cargo/config.toml").unwrap();
+            async move { fs::write(&path, content).await.unwrap() }
+        };
+        write(
+            &root,
+            "[alias]\nhurry-test-br = \"build --release\"\nhurry-test-bl = \"build --locked\"\n",
+        )
+        .await;
+        write(
+            &nested,
+            "[alias]\nhurry-test-bl = [\"build\", \"--offline\"]\n",
+        )
+        .await;
+
+        let resolve = |argv: &[&str]| {
+            let invocation = CargoInvocation::parse(argv.iter().copied());
+            let nested = nested.clone();
+            async move {
+                invocation
+                    .resolve_aliases(&nested)
+                    .await
+                    .unwrap()
+                    .subcommand_argv()
+            }
+        };
+        pretty_assert_eq!(
+            resolve(&["hurry-test-br"]).await,
+            Some(["build", "--release"].map(String::from).to_vec())
+        );
+        pretty_assert_eq!(
+            resolve(&["hurry-test-bl"]).await,
+            Some(["build", "--offline"].map(String::from).to_vec())
+        );
+        pretty_assert_eq!(
+            resolve(&["--config", "alias.hurry-test-br=\"check\"", "hurry-test-br"]).await,
+            Some(
+                ["check", "--config", "alias.hurry-test-br=\"check\""]
+                    .map(String::from)
+                    .to_vec()
+            )
+        );
+    }
+}
@@ -0,0 +1,637 @@
+//! Structural diffs of build artifacts.
+//!
+//! When the same inputs produce artifacts with different hashes on different
+//! machines, a byte-level diff of the artifacts isn't much help: it says that
+//! they differ, but not which part of the artifact differs or why. This module
+//! understands enough of the formats `rustc` produces to narrow the difference
+//! down to a specific archive member or object file section, and to show the
+//! embedded strings (usually paths) that differ, which is normally enough to
+//! point at the input that needs to be normalized.
+//!
+//! Supported formats:
+//! - `ar` archives (`.rlib` and `.a` files), in both GNU and BSD variants.
+//! - ELF object files, including those inside archives.
+//! - Rust metadata (`.rmeta` files and the `lib.rmeta` archive member).
+//!
+//! Anything else is compared as an opaque blob.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    ops::Range,
+};
+
+use color_eyre::{
+    Result,
+    eyre::{OptionExt as _, bail},
+};
+use derive_more::Display;
+use itertools::{EitherOrBoth, Itertools as _};
+
+/// The maximum number of differing strings reported for a single location.
+const MAX_REPORTED_STRINGS: usize = 10;
+
+/// The minimum length of a printable run of bytes to be considered a string.
+const MIN_STRING_LEN: usize = 6;
+
+/// A difference between two artifacts.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Difference {
+    /// Where in the artifact the difference is, outermost first: for example,
+    /// `["foo-1234.foo.abcd-cgu.0.rcgu.o", ".debug_str"]`.
+    pub location: Vec<String>,
+
+    /// How the artifacts differ at that location.
+    pub kind: DifferenceKind,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.location.is_empty() {
+            write!(f, "<file>: {}", self.kind)
+        } else {
+            write!(f, "{}: {}", self.location.join(" > "), self.kind)
+        }
+    }
+}
+
+/// How two artifacts differ at a location.
+#[derive(Clone, Debug, Eq, PartialEq, Display)]
+pub enum DifferenceKind {
+    /// The artifacts are different formats.
+    #[display("format differs: {a} vs {b}")]
+    Format { a: Format, b: Format },
+
+    /// The item exists in only one of the artifacts.
+    #[display("only in {side}")]
+    OnlyIn { side: Side },
+
+    /// A header field differs, for example an archive member's timestamp.
+    #[display("{field} differs: {a} vs {b}")]
+    Field {
+        field: &'static str,
+        a: String,
+        b: String,
+    },
+
+    /// The content differs.
+    #[display("{}", display_content(*offset, *len_a, *len_b, only_a, only_b))]
+    Content {
+        /// The offset of the first differing byte.
+        offset: usize,
+        len_a: usize,
+        len_b: usize,
+
+        /// Strings that appear only in the first artifact's content.
+        only_a: Vec<String>,
+
+        /// Strings that appear only in the second artifact's content.
+        only_b: Vec<String>,
+    },
+}
+
+fn display_content(
+    offset: usize,
+    len_a: usize,
+    len_b: usize,
+    only_a: &[String],
+    only_b: &[String],
+) -> String {
+    let mut out = format!("content differs from byte {offset}");
+    if len_a != len_b {
+        out.push_str(&format!(" (size {len_a} vs {len_b})"));
+    }
+    for s in only_a {
+        out.push_str(&format!("\n  - {s:?}"));
+    }
+    for s in only_b {
+        out.push_str(&format!("\n  + {s:?}"));
+    }
+    out
+}
+
+/// Which of the two artifacts.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Display)]
+pub enum Side {
+    #[display("a")]
+    A,
+    #[display("b")]
+    B,
+}
+
+/// The formats of artifacts that can be diffed structurally.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Display)]
+pub enum Format {
+    #[display("ar archive")]
+    Archive,
+    #[display("ELF object")]
+    Elf,
+    #[display("Rust metadata")]
+    Rmeta,
+    #[display("unknown")]
+    Unknown,
+}
+
+impl Format {
+    /// Detect the format of the content from its magic bytes.
+    pub fn detect(content: &[u8]) -> Self {
+        if content.starts_with(AR_MAGIC) {
+            Self::Archive
+        } else if content.starts_with(ELF_MAGIC) {
+            Self::Elf
+        } else if content.starts_with(RMETA_MAGIC) {
+            Self::Rmeta
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+/// Diff two artifacts, returning every difference found.
+///
+/// Returns an empty list if the artifacts are identical.
+pub fn diff_artifacts(a: &[u8], b: &[u8]) -> Result<Vec<Difference>> {
+    let mut differences = Vec::new();
+    diff_at(&mut Vec::new(), a, b, &mut differences)?;
+    Ok(differences)
+}
+
+fn diff_at(
+    location: &mut Vec<String>,
+    a: &[u8],
+    b: &[u8],
+    out: &mut Vec<Difference>,
+) -> Result<()> {
+    if a == b {
+        return Ok(());
+    }
+
+    let (format_a, format_b) = (Format::detect(a), Format::detect(b));
+    if format_a != format_b {
+        out.push(Difference {
+            location: location.clone(),
+            kind: DifferenceKind::Format {
+                a: format_a,
+                b: format_b,
+            },
+        });
+        return Ok(());
+    }
+
+    match format_a {
+        Format::Archive => diff_archives(location, a, b, out),
+        Format::Elf => diff_elf(location, a, b, out),
+        Format::Rmeta => {
+            diff_rmeta_header(location, a, b, out);
+            out.push(content_difference(location, a, b));
+            Ok(())
+        }
+        Format::Unknown => {
+            out.push(content_difference(location, a, b));
+            Ok(())
+        }
+    }
+}
+
+fn diff_archives(
+    location: &mut Vec<String>,
+    a: &[u8],
+    b: &[u8],
+    out: &mut Vec<Difference>,
+) -> Result<()> {
+    let members_a = parse_archive(a)?.into_iter().map(|m| (m.name.clone(), m));
+    let members_b = parse_archive(b)?.into_iter().map(|m| (m.name.clone(), m));
+    for pair in by_unique_name(members_a)
+        .into_iter()
+        .merge_join_by(by_unique_name(members_b), |(a, _), (b, _)| a.cmp(b))
+    {
+        match pair {
+            EitherOrBoth::Left((name, _)) => out.push(Difference {
+                location: child(location, name),
+                kind: DifferenceKind::OnlyIn { side: Side::A },
+            }),
+            EitherOrBoth::Right((name, _)) => out.push(Difference {
+                location: child(location, name),
+                kind: DifferenceKind::OnlyIn { side: Side::B },
+            }),
+            EitherOrBoth::Both((name, a), (_, b)) => {
+                location.push(name);
+                for (field, value_a, value_b) in [
+                    ("mtime", a.mtime, b.mtime),
+                    ("uid", a.uid, b.uid),
+                    ("gid", a.gid, b.gid),
+                    ("mode", a.mode, b.mode),
+                ] {
+                    if value_a != value_b {
+                        out.push(Difference {
+                            location: location.clone(),
+                            kind: DifferenceKind::Field {
+                                field,
+                                a: String::from(value_a),
+                                b: String::from(value_b),
+                            },
+                        });
+                    }
+                }
+                diff_at(location, a.content, b.content, out)?;
+                location.pop();
+            }
+        }
+    }
+    Ok(())
+}
+
+fn diff_elf(
+    location: &mut Vec<String>,
+    a: &[u8],
+    b: &[u8],
+    out: &mut Vec<Difference>,
+) -> Result<()> {
+    // If either side can't be parsed, fall back to comparing the bytes; a
+    // truncated or unusual object is still worth reporting.
+    let (Ok(sections_a), Ok(sections_b)) = (parse_elf_sections(a), parse_elf_sections(b)) else {
+        out.push(content_difference(location, a, b));
+        return Ok(());
+    };
+
+    let before = out.len();
+    for pair in by_unique_name(sections_a)
+        .into_iter()
+        .merge_join_by(by_unique_name(sections_b), |(a, _), (b, _)| a.cmp(b))
+    {
+        match pair {
+            EitherOrBoth::Left((name, _)) => out.push(Difference {
+                location: child(location, name),
+                kind: DifferenceKind::OnlyIn { side: Side::A },
+            }),
+            EitherOrBoth::Right((name, _)) => out.push(Difference {
+                location: child(location, name),
+                kind: DifferenceKind::OnlyIn { side: Side::B },
+            }),
+            EitherOrBoth::Both((name, range_a), (_, range_b)) => {
+                let (a, b) = (&a[range_a], &b[range_b]);
+                if a != b {
+                    location.push(name);
+                    out.push(content_difference(location, a, b));
+                    location.pop();
+                }
+            }
+        }
+    }
+
+    // The sections can all match while the headers differ (e.g. because
+    // sections moved), which is still a difference worth reporting.
+    if out.len() == before {
+        out.push(content_difference(location, a, b));
+    }
+    Ok(())
+}
+
+/// Key the items by name, so that they can be matched by name across
+/// artifacts.
+///
+/// Archives can contain several members with the same name (and objects
+/// several sections with the same name), so repeated names are disambiguated
+/// by their position among the items with that name.
+fn by_unique_name<T>(items: impl IntoIterator<Item = (String, T)>) -> BTreeMap<String, T> {
+    let mut seen = HashMap::<String, usize>::new();
+    items
+        .into_iter()
+        .map(|(name, item)| {
+            let n = seen.entry(name.clone()).or_default();
+            *n += 1;
+            match *n {
+                1 => (name, item),
+                n => (format!("{name} #{n}"), item),
+            }
+        })
+        .collect()
+}
+
+fn child(location: &[String], name: String) -> Vec<String> {
+    location.iter().cloned().chain([name]).collect()
+}
+
+fn diff_rmeta_header(location: &[String], a: &[u8], b: &[u8], out: &mut Vec<Difference>) {
+    let (version_a, version_b) = (rmeta_rustc_version(a), rmeta_rustc_version(b));
+    if version_a != version_b {
+        out.push(Difference {
+            location: location.to_vec(),
+            kind: DifferenceKind::Field {
+                field: "rustc version",
+                a: version_a.unwrap_or_default(),
+                b: version_b.unwrap_or_default(),
+            },
+        });
+    }
+}
+
+fn content_difference(location: &[String], a: &[u8], b: &[u8]) -> Difference {
+    let offset = a
+        .iter()
+        .zip(b)
+        .position(|(a, b)| a != b)
+        .unwrap_or_else(|| a.len().min(b.len()));
+
+    let strings_a = printable_strings(a);
+    let strings_b = printable_strings(b);
+    let only = |this: &BTreeSet<&str>, other: &BTreeSet<&str>| {
+        this.difference(other)
+            .take(MAX_REPORTED_STRINGS)
+            .map(|s| String::from(*s))
+            .collect::<Vec<_>>()
+    };
+
+    Difference {
+        location: location.to_vec(),
+        kind: DifferenceKind::Content {
+            offset,
+            len_a: a.len(),
+            len_b: b.len(),
+            only_a: only(&strings_a, &strings_b),
+            only_b: only(&strings_b, &strings_a),
+        },
+    }
+}
+
+/// The runs of printable ASCII in the content, like `strings(1)`.
+///
+/// Paths and other environment-specific values are embedded in artifacts as
+/// plain strings, so comparing the strings on each side usually shows what
+/// differs far more clearly than the differing bytes do.
+fn printable_strings(content: &[u8]) -> BTreeSet<&str> {
+    content
+        .split(|b| !(b.is_ascii_graphic() || *b == b' '))
+        .filter(|run| run.len() >= MIN_STRING_LEN)
+        .filter_map(|run| std::str::from_utf8(run).ok())
+        .collect()
+}
+
+const AR_MAGIC: &[u8] = b"!<arch>\n";
+const AR_HEADER_LEN: usize = 60;
+
+/// A member of an `ar` archive.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct ArchiveMember<'a> {
+    name: String,
+    mtime: &'a str,
+    uid: &'a str,
+    gid: &'a str,
+    mode: &'a str,
+    content: &'a [u8],
+}
+
+/// Parse the members of an `ar` archive.
+///
+/// The symbol table and GNU long name table are consumed while parsing rather
+/// than returned as members: they're derived from the other members, so
+/// they'd only repeat differences that are already reported.
+fn parse_archive(content: &[u8]) -> Result<Vec<ArchiveMember<'_>>> {
+    let Some(mut rest) = content.strip_prefix(AR_MAGIC) else {
+        bail!("not an ar archive");
+    };
+
+    let mut long_names: &[u8] = &[];
+    let mut members = Vec::new();
+    while !rest.is_empty() {
+        if rest.len() < AR_HEADER_LEN {
+            bail!("truncated archive member header");
+        }
+        let (header, body) = rest.split_at(AR_HEADER_LEN);
+        let raw_name = header_field(header, 0..16)?;
+        let size = header_field(header, 48..58)?.parse::<usize>()?;
+        let Some(member) = body.get(..size) else {
+            bail!("truncated archive member: {raw_name}");
+        };
+
+        // Members are aligned to two bytes.
+        rest = body.get(size + size % 2..).unwrap_or_default();
+
+        let (name, content) = if raw_name == "/" || raw_name == "/SYM64/" {
+            continue;
+        } else if raw_name == "//" {
+            long_names = member;
+            continue;
+        } else if let Some(offset) = raw_name.strip_prefix('/') {
+            // GNU: the name is at this offset in the long name table, ending
+            // with `/\n`.
+            let offset = offset.parse::<usize>()?;
+            let name = long_names
+                .get(offset..)
+                .and_then(|names| names.split(|b| *b == b'\n').next())
+                .ok_or_eyre("long name offset out of bounds")?;
+            let name = String::from_utf8_lossy(name);
+            (String::from(name.trim_end_matches('/')), member)
+        } else if let Some(len) = raw_name.strip_prefix("#1/") {
+            // BSD: the name is at the start of the member's content.
+            let len = len.parse::<usize>()?;
+            let (name, content) = member.split_at_checked(len).ok_or_eyre("truncated name")?;
+            let name = String::from_utf8_lossy(name);
+            let name = String::from(name.trim_end_matches('\0'));
+            if name.starts_with("__.SYMDEF") {
+                continue;
+            }
+            (name, content)
+        } else {
+            (String::from(raw_name.trim_end_matches('/')), member)
+        };
+
+        members.push(ArchiveMember {
+            name,
+            mtime: header_field(header, 16..28)?,
+            uid: header_field(header, 28..34)?,
+            gid: header_field(header, 34..40)?,
+            mode: header_field(header, 40..48)?,
+            content,
+        });
+    }
+    Ok(members)
+}
+
+fn header_field(header: &[u8], range: Range<usize>) -> Result<&str> {
+    let field = std::str::from_utf8(&header[range])?;
+    Ok(field.trim_end())
+}
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/// Parse the named sections of an ELF object, returning the file range of
+/// each section's content.
+///
+/// Sections without content in the file (such as `.bss`) are omitted.
+fn parse_elf_sections(content: &[u8]) -> Result<Vec<(String, Range<usize>)>> {
+    let class = *content.get(4).ok_or_eyre("truncated ELF header")?;
+    let little_endian = match content.get(5) {
+        Some(1) => true,
+        Some(2) => false,
+        _ => bail!("unknown ELF byte order"),
+    };
+    let read = |offset: usize, len: usize| -> Result<u64> {
+        let bytes = content
+            .get(offset..offset + len)
+            .ok_or_eyre("ELF field out of bounds")?;
+        let mut buf = [0u8; 8];
+        if little_endian {
+            buf[..len].copy_from_slice(bytes);
+            Ok(u64::from_le_bytes(buf))
+        } else {
+            buf[8 - len..].copy_from_slice(bytes);
+            Ok(u64::from_be_bytes(buf))
+        }
+    };
+
+    // Offsets of the section header table fields and of the fields within
+    // each section header, which depend on the word size.
+    let (shoff, shentsize, shnum, shstrndx, sh_offset, sh_size, word) = match class {
+        1 => (0x20, 0x2E, 0x30, 0x32, 0x10, 0x14, 4),
+        2 => (0x28, 0x3A, 0x3C, 0x3E, 0x18, 0x20, 8),
+        _ => bail!("unknown ELF class: {class}"),
+    };
+    const SHT_NOBITS: u64 = 8;
+
+    let table = usize::try_from(read(shoff, word)?)?;
+    let entry_size = usize::try_from(read(shentsize, 2)?)?;
+    let count = usize::try_from(read(shnum, 2)?)?;
+    let names_index = usize::try_from(read(shstrndx, 2)?)?;
+
+    let mut headers = Vec::with_capacity(count);
+    for index in 0..count {
+        let header = table + index * entry_size;
+        let name = usize::try_from(read(header, 4)?)?;
+        let kind = read(header + 4, 4)?;
+        let offset = usize::try_from(read(header + sh_offset, word)?)?;
+        let size = usize::try_from(read(header + sh_size, word)?)?;
+        headers.push((name, kind, offset..offset + size));
+    }
+
+    let (_, _, names) = headers
+        .get(names_index)
+        .cloned()
+        .ok_or_eyre("section name table out of bounds")?;
+    let names = content
+        .get(names)
+        .ok_or_eyre("section name table out of bounds")?;
+
+    let mut sections = Vec::with_capacity(count);
+    for (name, kind, range) in headers {
+        if kind == SHT_NOBITS || range.is_empty() {
+            continue;
+        }
+        let name = names
+            .get(name..)
+            .and_then(|names| names.split(|b| *b == 0).next())
+            .ok_or_eyre("section name out of bounds")?;
+        if content.get(range.clone()).is_none() {
+            bail!("section content out of bounds");
+        }
+        sections.push((String::from_utf8_lossy(name).into_owned(), range));
+    }
+
+    Ok(sections)
+}
+
+const RMETA_MAGIC: &[u8] = b"rust\0\0\0";
+
+/// The version of `rustc` that wrote the metadata, if it can be found.
+///
+/// The metadata header is followed by the version string that `rustc` uses
+/// to reject metadata from other compilers; a mismatch here means the
+/// machines aren't using the same toolchain, which explains every other
+/// difference in the file.
+fn rmeta_rustc_version(content: &[u8]) -> Option<String> {
+    let header = content.get(..256.min(content.len()))?;
+    let start = header.windows(6).position(|w| w == b"rustc ")?;
+    let version = header[start..]
+        .iter()
+        .take_while(|b| b.is_ascii_graphic() || **b == b' ')
+        .copied()
+        .collect::<Vec<_>>();
+    String::from_utf8(version).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::{Difference, DifferenceKind, Side, diff_artifacts, parse_archive};
+
+    fn archive(members: &[(&str, &str, &[u8])]) -> Vec<u8> {
+        let mut out = b"!<arch>\n".to_vec();
+        for (name, mtime, content) in members {
+            out.extend(
+                format!(
+                    "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                    format!("{name}/"),
+                    mtime,
+                    0,
+                    0,
+                    644,
+                    content.len()
+                )
+                .as_bytes(),
+            );
+            out.extend(*content);
+            if content.len() % 2 == 1 {
+                out.push(b'\n');
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn parses_archive_members() {
+        let content = archive(&[("lib.rmeta", "0", b"odd"), ("foo.o", "0", b"even")]);
+        let members = parse_archive(&content).unwrap();
+        let names = members.iter().map(|m| m.name.as_str()).collect::<Vec<_>>();
+        pretty_assert_eq!(names, vec!["lib.rmeta", "foo.o"]);
+        pretty_assert_eq!(members[0].content, b"odd");
+        pretty_assert_eq!(members[1].content, b"even");
+    }
+
+    #[test]
+    fn identical() {
+        let content = archive(&[("foo.o", "0", b"content")]);
+        pretty_assert_eq!(diff_artifacts(&content, &content).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn localizes_member_differences() {
+        let a = archive(&[
+            ("lib.rmeta", "0", b"same"),
+            ("foo.o", "0", b"path: /home/alice/project/src/lib.rs"),
+            ("removed.o", "0", b""),
+        ]);
+        let b = archive(&[
+            ("lib.rmeta", "0", b"same"),
+            ("foo.o", "1700000000", b"path: /home/bob/project/src/lib.rs"),
+        ]);
+
+        pretty_assert_eq!(
+            diff_artifacts(&a, &b).unwrap(),
+            vec![
+                Difference {
+                    location: vec![String::from("foo.o")],
+                    kind: DifferenceKind::Field {
+                        field: "mtime",
+                        a: String::from("0"),
+                        b: String::from("1700000000"),
+                    },
+                },
+                Difference {
+                    location: vec![String::from("foo.o")],
+                    kind: DifferenceKind::Content {
+                        offset: 12,
+                        len_a: 36,
+                        len_b: 34,
+                        only_a: vec![String::from("path: /home/alice/project/src/lib.rs")],
+                        only_b: vec![String::from("path: /home/bob/project/src/lib.rs")],
+                    },
+                },
+                Difference {
+                    location: vec![String::from("removed.o")],
+                    kind: DifferenceKind::OnlyIn { side: Side::A },
+                },
+            ]
+        );
+    }
+}
@@ -6,7 +6,7 @@ use itertools::PeekingNext;
 use parse_display::{Display as ParseDisplay, FromStr as ParseFromStr};
 use tracing::trace;
 
-use crate::cargo::{RustcTarget, RustcTargetPlatform};
+use crate::cargo::{Profile, RustcTarget, RustcTargetPlatform};
 
 /// Parsed arguments for a `cargo build` invocation.
 ///
@@ -43,6 +43,54 @@ impl CargoBuildArguments {
         self.0.iter().flat_map(|arg| arg.to_argv()).collect()
     }
 
+    /// Parse the arguments of a `cargo test` invocation into the arguments of
+    /// the `cargo build` invocation that compiles the same units.
+    ///
+    /// Arguments for the test harness (after `--`), test name filters, and
+    /// flags that only affect running tests are dropped. Without any target
+    /// selection, `cargo test` compiles the library, binaries, and integration
+    /// tests as test harnesses and builds the examples, which is what `cargo
+    /// build --tests --examples` compiles.
+    pub fn from_test_argv(argv: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        let argv = argv
+            .into_iter()
+            .map(|arg| arg.as_ref().to_string())
+            .take_while(|arg| arg != "--")
+            .filter(|arg| !matches!(arg.as_str(), "--no-run" | "--no-fail-fast"))
+            // Doc tests only need the library to be built.
+            .map(|arg| match arg.as_str() {
+                "--doc" => String::from(CargoBuildArgument::LIB),
+                _ => arg,
+            });
+        let mut args = Self::from_iter(argv);
+        args.0
+            .retain(|arg| !matches!(arg, CargoBuildArgument::Positional(_)));
+        if !args.0.iter().any(CargoBuildArgument::selects_targets) {
+            args.0
+                .extend([CargoBuildArgument::Tests, CargoBuildArgument::Examples]);
+        }
+        args
+    }
+
+    /// Parse the arguments of a `cargo clippy` invocation into the arguments
+    /// of the `cargo build` invocation that plans the same units.
+    ///
+    /// Arguments for Clippy itself (after `--`) and flags that only affect
+    /// linting or fixing are dropped.
+    pub fn from_clippy_argv(argv: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        let argv = argv
+            .into_iter()
+            .map(|arg| arg.as_ref().to_string())
+            .take_while(|arg| arg != "--")
+            .filter(|arg| {
+                !matches!(
+                    arg.as_str(),
+                    "--fix" | "--no-deps" | "--allow-dirty" | "--allow-staged" | "--allow-no-vcs"
+                )
+            });
+        Self::from_iter(argv)
+    }
+
     /// The profile specified by the user.
     pub fn profile(&self) -> Option<&str> {
         self.0.iter().find_map(|arg| match arg {
@@ -52,6 +100,35 @@ impl CargoBuildArguments {
         })
     }
 
+    /// Replace the profile specified by the user with `profile`.
+    ///
+    /// Cargo reserves the `debug` profile name, so the debug profile is
+    /// selected by not specifying a profile at all.
+    pub fn with_profile(mut self, profile: &Profile) -> Self {
+        self.0.retain(|arg| {
+            !matches!(
+                arg,
+                CargoBuildArgument::Release | CargoBuildArgument::Profile(_)
+            )
+        });
+        match profile {
+            Profile::Debug => {}
+            Profile::Release => self.0.push(CargoBuildArgument::Release),
+            profile => self
+                .0
+                .push(CargoBuildArgument::Profile(profile.as_str().to_string())),
+        }
+        self
+    }
+
+    /// Replace the target directory specified by the user with `dir`.
+    pub fn with_target_dir(mut self, dir: impl Into<String>) -> Self {
+        self.0
+            .retain(|arg| !matches!(arg, CargoBuildArgument::TargetDir(_)));
+        self.0.push(CargoBuildArgument::TargetDir(dir.into()));
+        self
+    }
+
     /// Whether release mode is enabled.
     pub fn is_release(&self) -> bool {
         self.0
@@ -100,6 +177,25 @@ impl CargoBuildArguments {
         })
     }
 
+    /// The lockfile path if specified.
+    pub fn lockfile_path(&self) -> Option<&str> {
+        self.0.iter().find_map(|arg| match arg {
+            CargoBuildArgument::LockfilePath(p) => Some(p.as_str()),
+            _ => None,
+        })
+    }
+
+    /// All `--config` overrides, as key-value pairs.
+    pub fn config_overrides(&self) -> Vec<(&str, &str)> {
+        self.0
+            .iter()
+            .filter_map(|arg| match arg {
+                CargoBuildArgument::Config(key, value) => Some((key.as_str(), value.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
     /// All features explicitly specified.
     ///
     /// This does not change in the presence of the "all features" flag; use
@@ -415,6 +511,23 @@ impl CargoBuildArgument {
     const FUTURE_INCOMPAT_REPORT: &'static str = "--future-incompat-report";
     const MESSAGE_FORMAT: &'static str = "--message-format";
 
+    /// Whether the argument selects which targets to compile.
+    fn selects_targets(&self) -> bool {
+        matches!(
+            self,
+            Self::Lib
+                | Self::Bins
+                | Self::Bin(_)
+                | Self::Examples
+                | Self::Example(_)
+                | Self::Tests
+                | Self::Test(_)
+                | Self::Benches
+                | Self::Bench(_)
+                | Self::AllTargets
+        )
+    }
+
     fn is_flag(s: &str) -> bool {
         s.starts_with('-')
     }
@@ -678,6 +791,34 @@ mod tests {
         assert!(!parsed.is_release());
     }
 
+    #[test_case(&["--release"], Profile::Debug, None; "release_to_debug")]
+    #[test_case(&["--profile", "custom"], Profile::Release, Some("release"); "custom_to_release")]
+    #[test_case(&["-p", "foo"], Profile::Bench, Some("bench"); "unspecified_to_bench")]
+    #[test_case(&["-r"], Profile::Custom(String::from("ci")), Some("ci"); "release_to_custom")]
+    #[test]
+    fn replaces_profile(args: &[&str], profile: Profile, expected: Option<&str>) {
+        let parsed = CargoBuildArguments::from_iter(args.to_vec()).with_profile(&profile);
+        pretty_assert_eq!(parsed.profile(), expected);
+        pretty_assert_eq!(Profile::from(parsed.profile().unwrap_or("debug")), profile);
+    }
+
+    #[test_case(&["--release", "my_test", "--", "--nocapture"], &["--release", "--tests", "--examples"]; "default_targets")]
+    #[test_case(&["--no-run", "-p", "foo", "--test", "integration"], &["--package", "foo", "--test", "integration"]; "selected_targets")]
+    #[test_case(&["--doc", "--no-fail-fast"], &["--lib"]; "doc_tests")]
+    #[test]
+    fn parses_test_argv(args: &[&str], expected: &[&str]) {
+        let parsed = CargoBuildArguments::from_test_argv(args);
+        pretty_assert_eq!(parsed.to_argv(), expected);
+    }
+
+    #[test_case(&["--all-targets", "--", "-D", "warnings"], &["--all-targets"]; "lint_args")]
+    #[test_case(&["--fix", "--allow-dirty", "--no-deps", "-p", "foo"], &["--package", "foo"]; "clippy_flags")]
+    #[test]
+    fn parses_clippy_argv(args: &[&str], expected: &[&str]) {
+        let parsed = CargoBuildArguments::from_clippy_argv(args);
+        pretty_assert_eq!(parsed.to_argv(), expected);
+    }
+
     #[test_case(&["-p", "foo"], vec!["foo"]; "short_space")]
     #[test_case(&["--package", "bar"], vec!["bar"]; "long_space")]
     #[test_case(&["-p=bam"], vec!["bam"]; "short_equals")]
@@ -735,6 +876,18 @@ mod tests {
         pretty_assert_eq!(parsed.target_dir(), Some("/custom/target"));
     }
 
+    #[test_case(&["--release"]; "unspecified")]
+    #[test_case(&["--target-dir", "/custom/target", "--release"]; "specified")]
+    #[test]
+    fn replaces_target_dir(args: &[&str]) {
+        let parsed = CargoBuildArguments::from_iter(args.to_vec()).with_target_dir("/other");
+        pretty_assert_eq!(parsed.target_dir(), Some("/other"));
+        pretty_assert_eq!(
+            parsed.to_argv(),
+            vec!["--release", "--target-dir", "/other"]
+        );
+    }
+
     #[test_case(&["--manifest-path", "/path/to/Cargo.toml"]; "space_separated")]
     #[test_case(&["--manifest-path=/path/to/Cargo.toml"]; "equals_separated")]
     #[test]
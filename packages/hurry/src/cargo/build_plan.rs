@@ -2,14 +2,18 @@ use std::collections::HashMap;
 
 use cargo_metadata::TargetKind;
 use color_eyre::{Result, eyre, eyre::OptionExt as _};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    cargo::{CargoCompileMode, RustcTarget, UnitHash},
+    cargo::{CargoCompileMode, RustcArguments, RustcTarget, UnitHash},
     path::{AbsDirPath, AbsFilePath},
 };
 
-#[derive(Clone, Eq, PartialEq, Debug, Deserialize)]
+mod index;
+
+pub use index::BuildPlanIndex;
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub struct BuildPlan {
     pub invocations: Vec<BuildPlanInvocation>,
     pub inputs: Vec<String>,
@@ -17,7 +21,7 @@ pub struct BuildPlan {
 
 // Note that these fields are all undocumented. To see their definition, see
 // https://github.com/rust-lang/cargo/blob/0436f86288a4d9bce1c712c4eea5b05eb82682b9/src/cargo/core/compiler/build_plan.rs#L21-L34
-#[derive(Clone, Eq, PartialEq, Debug, Deserialize)]
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub struct BuildPlanInvocation {
     pub package_name: String,
     pub package_version: String,
@@ -43,17 +47,18 @@ pub struct BuildPlanInvocation {
 impl BuildPlanInvocation {
     /// Returns the unit hash for this build plan invocation.
     ///
-    /// Returns `None` for invocation types we don't cache (binaries,
-    /// unsupported target kinds). Returns `Some(hash)` for library crates,
-    /// build script compilations, and build script executions.
+    /// Returns `None` for invocation types we don't cache (binaries, test
+    /// harnesses, unsupported target kinds). Returns `Some(hash)` for library
+    /// crates, build script compilations, and build script executions.
     ///
     /// This is used to build an index→hash mapping before creating units, so
     /// that dep indices can be resolved to UnitHash values.
-    // TODO: Unify with the similar hash extraction logic in
-    // `Workspace::units_from_build_plan()` which parses hashes from the same
-    // paths/filenames when constructing UnitPlan objects.
     pub fn unit_hash(&self) -> Result<Option<UnitHash>> {
-        if self.target_kind == [TargetKind::CustomBuild] {
+        if self.compile_mode == CargoCompileMode::Test {
+            // Test harnesses are always first-party binaries, even when their
+            // target is a library, and nothing depends on them.
+            Ok(None)
+        } else if self.target_kind == [TargetKind::CustomBuild] {
             match self.compile_mode {
                 CargoCompileMode::Build => {
                     // Parse unit hash from output filename like
@@ -95,11 +100,7 @@ impl BuildPlanInvocation {
         } else if self.target_kind == [TargetKind::Bin] {
             // Binaries are not cached
             Ok(None)
-        } else if self.target_kind.contains(&TargetKind::Lib)
-            || self.target_kind.contains(&TargetKind::RLib)
-            || self.target_kind.contains(&TargetKind::CDyLib)
-            || self.target_kind.contains(&TargetKind::ProcMacro)
-        {
+        } else if self.target_kind.iter().any(is_library_kind) {
             // Parse unit hash from output filename like `lib{name}-{hash}.rlib`
             let output = self
                 .outputs
@@ -112,15 +113,21 @@ impl BuildPlanInvocation {
                 .ok_or_eyre("no filename")?
                 .to_string_lossy();
             let filename = filename.split_once('.').ok_or_eyre("no extension")?.0;
-            let hash = filename
-                .rsplit_once('-')
-                .ok_or_else(|| {
-                    eyre::eyre!(
-                        "no unit hash suffix in filename: {filename} (outputs: {:?})",
-                        self.outputs
-                    )
-                })?
-                .1;
+            if let Some((_, hash)) = filename.rsplit_once('-') {
+                return Ok(Some(UnitHash::from(hash.to_string())));
+            }
+
+            // Cargo leaves the hash out of the file names of `cdylib` and
+            // `dylib` workspace members so that other build systems can find
+            // them, in which case the unit is still identified by its
+            // `-C metadata`.
+            let args = RustcArguments::from_iter(self.args.iter().cloned());
+            let hash = args.metadata().ok_or_else(|| {
+                eyre::eyre!(
+                    "no unit hash suffix in filename: {filename} (outputs: {:?})",
+                    self.outputs
+                )
+            })?;
             Ok(Some(UnitHash::from(hash.to_string())))
         } else {
             // Unknown target kind - don't cache
@@ -129,6 +136,19 @@ impl BuildPlanInvocation {
     }
 }
 
+/// Whether a target kind is a library, whose compilation units Hurry caches.
+pub(crate) fn is_library_kind(kind: &TargetKind) -> bool {
+    matches!(
+        kind,
+        TargetKind::Lib
+            | TargetKind::RLib
+            | TargetKind::DyLib
+            | TargetKind::CDyLib
+            | TargetKind::StaticLib
+            | TargetKind::ProcMacro
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use color_eyre::{Result, Section as _, SectionExt as _, eyre::Context as _};
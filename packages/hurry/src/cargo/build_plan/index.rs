@@ -0,0 +1,267 @@
+//! Build plans persisted across invocations.
+//!
+//! Computing a build plan runs `cargo build --build-plan`, which resolves the
+//! whole dependency graph (and briefly renames the build directory) on every
+//! invocation, even though the plan only changes when its inputs do. Plans
+//! are therefore saved in the workspace's state directory, keyed by a hash of
+//! their inputs, and later invocations with the same inputs load them instead
+//! of asking Cargo again:
+//! - The lockfile, manifests, Cargo configuration, and toolchain files in the
+//!   workspace, along with the Cargo configuration of its ancestors and
+//!   `$CARGO_HOME`.
+//! - The `rustc` version and the host and target triples.
+//! - The build arguments and the `CARGO*` and `RUST*` environment variables.
+//! - The workspace, build, and `$CARGO_HOME` directories, since the plan
+//!   contains absolute paths.
+//!
+//! Path dependencies outside the workspace aren't part of the key. A stale
+//! plan only costs cache hits, though: Cargo still decides what to build, and
+//! units restored under unit hashes that the build doesn't use are ignored.
+
+use std::collections::BTreeMap;
+
+use color_eyre::{Result, eyre::Context as _};
+use futures::TryStreamExt as _;
+use serde::Serialize;
+use tracing::{debug, instrument, trace, warn};
+
+use clients::courier::v1::Key;
+
+use crate::{
+    cargo::{BuildPlan, CargoBuildArguments, Workspace, rustc_version},
+    fs, mk_rel_dir,
+    path::{AbsDirPath, AbsFilePath, JoinWith as _, TryJoinWith as _},
+    state,
+};
+
+/// Distinguishes build plan keys from other hashes, and invalidates saved
+/// plans if the key's inputs change.
+const KEY_DOMAIN: &str = "hurry-build-plan-v1";
+
+/// How many plans are kept per workspace. Workspaces are usually built with
+/// a handful of argument sets (e.g. debug and release), so this keeps all of
+/// them without letting plans of old lockfiles pile up.
+const MAX_SAVED_PLANS: usize = 16;
+
+/// Files in the workspace that Cargo reads to plan a build.
+const PLAN_FILES: [&str; 4] = [
+    "Cargo.toml",
+    "Cargo.lock",
+    "rust-toolchain",
+    "rust-toolchain.toml",
+];
+
+/// The inputs of a build plan.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+struct PlanInputs {
+    root: String,
+    build_dir: String,
+    cargo_home: String,
+    target_arch: Option<String>,
+    host_arch: String,
+    rustc_version: Option<String>,
+    argv: Vec<String>,
+    env: BTreeMap<String, String>,
+    files: BTreeMap<String, Key>,
+}
+
+impl PlanInputs {
+    /// Derive the key that the plan of these inputs is saved under.
+    fn key(&self) -> Result<String> {
+        let inputs = serde_json::to_vec(self).context("serialize build plan inputs")?;
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(KEY_DOMAIN.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(&inputs);
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+}
+
+/// The saved build plan of a workspace for a set of build arguments.
+#[derive(Clone, Debug)]
+pub struct BuildPlanIndex {
+    path: AbsFilePath,
+}
+
+impl BuildPlanIndex {
+    /// Locate the saved build plan for building the workspace with `args`.
+    #[instrument(name = "BuildPlanIndex::open", skip(ws))]
+    pub async fn open(ws: &Workspace, args: &CargoBuildArguments) -> Result<Self> {
+        let inputs = PlanInputs {
+            root: ws.root.to_string(),
+            build_dir: ws.build_dir.to_string(),
+            cargo_home: ws.cargo_home.to_string(),
+            target_arch: ws.target_arch.as_str().map(String::from),
+            host_arch: String::from(ws.host_arch.as_str()),
+            rustc_version: rustc_version(&ws.root).await,
+            argv: args.to_argv(),
+            env: std::env::vars()
+                .filter(|(name, _)| name.starts_with("CARGO") || name.starts_with("RUST"))
+                .collect(),
+            files: plan_files(ws).await?,
+        };
+        let key = inputs.key()?;
+        trace!(?inputs, %key, "build plan inputs");
+
+        let path = state::dir(ws)
+            .await?
+            .join(mk_rel_dir!("build-plans"))
+            .try_join_file(format!("{key}.json"))?;
+        Ok(Self { path })
+    }
+
+    /// Load the saved plan, if there is one.
+    ///
+    /// Plans that can't be read are treated as missing, since they're
+    /// recomputed and saved again anyway.
+    #[instrument(name = "BuildPlanIndex::load")]
+    pub async fn load(&self) -> Option<BuildPlan> {
+        let content = match fs::read_buffered(&self.path).await {
+            Ok(Some(content)) => content,
+            Ok(None) => {
+                debug!("no saved build plan");
+                return None;
+            }
+            Err(err) => {
+                warn!(?err, "failed to read saved build plan");
+                return None;
+            }
+        };
+        match serde_json::from_slice::<BuildPlan>(&content) {
+            Ok(plan) => {
+                debug!("loaded saved build plan");
+                Some(plan)
+            }
+            Err(err) => {
+                warn!(?err, "failed to parse saved build plan");
+                None
+            }
+        }
+    }
+
+    /// Save the plan, removing the least recently saved plans beyond
+    /// [`MAX_SAVED_PLANS`].
+    #[instrument(name = "BuildPlanIndex::save", skip(plan))]
+    pub async fn save(&self, plan: &BuildPlan) -> Result<()> {
+        let content = serde_json::to_vec(plan).context("serialize build plan")?;
+        fs::write(&self.path, content).await?;
+        if let Some(dir) = self.path.parent() {
+            prune(&dir).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Hash the files that Cargo reads to plan a build of the workspace, keyed by
+/// their path.
+async fn plan_files(ws: &Workspace) -> Result<BTreeMap<String, Key>> {
+    let mut paths = ws
+        .source_files()
+        .try_filter(|path| {
+            let name = path.file_name_str_lossy().unwrap_or_default();
+            let in_cargo_dir = path
+                .parent()
+                .and_then(|dir| dir.file_name_str_lossy().map(|name| name == ".cargo"))
+                .unwrap_or_default();
+            let plan_file = PLAN_FILES.contains(&&*name)
+                || (in_cargo_dir && (name == "config" || name == "config.toml"));
+            async move { plan_file }
+        })
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    // Cargo also reads configuration from every ancestor of the workspace and
+    // from `$CARGO_HOME`.
+    let mut config_dirs = ws
+        .root
+        .as_std_path()
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join(".cargo"))
+        .collect::<Vec<_>>();
+    config_dirs.push(ws.cargo_home.as_std_path().to_path_buf());
+    for dir in config_dirs {
+        for name in ["config", "config.toml"] {
+            let path = dir.join(name);
+            if fs::is_file(&path).await {
+                paths.push(AbsFilePath::try_from(path)?);
+            }
+        }
+    }
+
+    let hashes = fs::hash_files(paths.clone()).await?;
+    Ok(paths
+        .into_iter()
+        .map(|path| path.to_string())
+        .zip(hashes)
+        .collect())
+}
+
+/// Remove all but the [`MAX_SAVED_PLANS`] most recently saved plans in `dir`.
+async fn prune(dir: &AbsDirPath) -> Result<()> {
+    let mut plans = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let modified = entry
+            .metadata()
+            .await
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| format!("read metadata of {:?}", entry.path()))?;
+        plans.push((modified, AbsFilePath::try_from(entry.path())?));
+    }
+    plans.sort_by(|(a, _), (b, _)| b.cmp(a));
+    for (_, path) in plans.into_iter().skip(MAX_SAVED_PLANS) {
+        debug!(?path, "removing old build plan");
+        fs::remove_file(&path).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use clients::courier::v1::Key;
+
+    use super::PlanInputs;
+
+    fn inputs() -> PlanInputs {
+        PlanInputs {
+            root: String::from("/workspace"),
+            build_dir: String::from("/workspace/target"),
+            cargo_home: String::from("/home/user/.cargo"),
+            argv: vec![String::from("--release")],
+            files: BTreeMap::from([(
+                String::from("/workspace/Cargo.lock"),
+                Key::from_buffer(b"version = 4\n"),
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn key_is_stable() {
+        pretty_assert_eq!(inputs().key().unwrap(), inputs().key().unwrap());
+    }
+
+    #[test]
+    fn key_changes_with_lockfile() {
+        let mut changed = inputs();
+        changed.files.insert(
+            String::from("/workspace/Cargo.lock"),
+            Key::from_buffer(b"version = 4\n\n[[package]]\nname = \"serde\"\n"),
+        );
+        assert_ne!(inputs().key().unwrap(), changed.key().unwrap());
+    }
+
+    #[test]
+    fn key_changes_with_target() {
+        let mut changed = inputs();
+        changed
+            .argv
+            .push(String::from("--target=aarch64-unknown-linux-gnu"));
+        assert_ne!(inputs().key().unwrap(), changed.key().unwrap());
+    }
+}
@@ -1,105 +1,399 @@
-use std::{process::Stdio, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    io::IsTerminal as _,
+    process::Stdio,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
 
-use color_eyre::{Result, Section, SectionExt, eyre::Context as _};
+use color_eyre::{
+    Result, Section, SectionExt,
+    eyre::{Context as _, bail, eyre},
+};
 use derive_more::Debug;
 use serde::{Deserialize, Serialize};
-use tracing::{instrument, trace};
+use tokio::io::AsyncReadExt as _;
+use tracing::{debug, info, instrument, trace, warn};
 use url::Url;
 use uuid::Uuid;
 
 use crate::{
-    cargo::{QualifiedPath, UnitPlan, Workspace},
-    cas::CourierCas,
-    daemon::{CargoUploadRequest, DaemonPaths},
+    buffers,
+    cargo::{CheckPlan, QualifiedPath, UnitHash, UnitPlan, Workspace},
+    cas::{CourierCas, LocalCas},
+    config::{
+        HurryConfig, LocalCacheConfig, NetworkConfig, OverwritePolicy, RestoreConfig, UploadConfig,
+    },
+    daemon::{
+        CargoPrewarmRequest, CargoRestoreEvent, CargoRestoreProgress, CargoRestoreRequest,
+        CargoUploadRequest, CargoWarmRequest, CargoWatchRequest, DaemonContext, DaemonHandshake,
+        DaemonPaths, PrewarmSchedule, local_client,
+    },
+    fs,
     progress::TransferBar,
 };
-use clients::{Courier, Token};
+use clients::{
+    BufferSizes, Courier, ProxyConfig, Token,
+    courier::v1::{CacheScope, ConnectionPool, HashAlgorithm, cache::CacheAsOf},
+};
 
+mod handoff;
+mod metadata;
+mod policy;
 mod restore;
 mod save;
+mod timings;
+mod validate;
+
+pub use handoff::{HANDOFF_ENV, HandoffServer, RestoreHandoff, split_for_handoff, wrap_rustc};
+pub use metadata::{current_branch, in_ci, rustc_version, traffic_class};
+pub use policy::{
+    BigArtifacts, CratePolicy, DeterminismCheck, UploadDecision, UploadPolicy, UploadReason,
+    estimate_time_saved,
+};
+pub use restore::{
+    CacheRepair, RestoreOptions, Restored, prefetch_units, resolve_checked_units, restorable_units,
+    restore_units,
+};
+pub use save::{
+    NondeterministicUnit, SaveProgress, UnitUploadProgress, UnitUploadState, save_units,
+};
+pub use timings::RestoreDecision;
+pub use validate::{InvalidUnit, UnitProblem};
+
+/// How long to wait for a newly spawned daemon to become ready.
+const DAEMON_STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for a daemon running another version of Hurry to exit
+/// before starting a new one. The daemon finishes uploads that have started
+/// before it exits, so this covers its default drain timeout.
+const DAEMON_REPLACE_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// The shortest and longest delays between checks for whether a newly
+/// spawned daemon is ready. The delay doubles after each check, so fast
+/// startups are noticed quickly without polling slow ones constantly.
+const DAEMON_STARTUP_POLL_MIN: Duration = Duration::from_millis(25);
+const DAEMON_STARTUP_POLL_MAX: Duration = Duration::from_millis(500);
+
+/// How long to wait for the daemon to accept a warm request. The daemon
+/// warms its connection in the background, so this only needs to cover
+/// sending the request.
+const DAEMON_WARM_TIMEOUT: Duration = Duration::from_secs(1);
 
-pub use restore::{Restored, restore_units};
-pub use save::{SaveProgress, save_units};
+/// How long to wait for Courier to answer the initial ping. Courier answers
+/// pings without doing any work, so a slower answer means the network is
+/// unusable for restoring or uploading anyway.
+const COURIER_PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Shows the warning that Courier is unreachable once per process.
+static UNREACHABLE_WARNING: WarnOnce = WarnOnce::new();
+
+/// A warning that is shown at most once.
+#[derive(Debug)]
+struct WarnOnce(AtomicBool);
+
+impl WarnOnce {
+    const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    /// Whether to show the warning, which is only the case the first time.
+    fn take(&self) -> bool {
+        !self.0.swap(true, Ordering::Relaxed)
+    }
+}
+
+/// The warning shown when a build continues without the cache because
+/// Courier can't be reached.
+fn unreachable_warning(courier_url: &Url) -> String {
+    format!("Warning: couldn't reach the Hurry API at {courier_url}, building without the cache")
+}
 
 #[derive(Debug, Clone)]
 pub struct CargoCache {
     #[debug("{:?}", courier_url.as_str())]
     courier_url: Url,
     courier_token: Token,
+    proxy: ProxyConfig,
+    network: NetworkConfig,
+    hash_algorithm: HashAlgorithm,
+    buffer_sizes: BufferSizes,
+    restore: RestoreConfig,
+    upload: UploadConfig,
+    local_cache: LocalCacheConfig,
+    prewarm: Option<PrewarmSchedule>,
     courier: Courier,
     cas: CourierCas,
+    local: LocalCas,
     ws: Workspace,
+
+    /// Whether to restore in the daemon rather than in this process.
+    restore_in_daemon: bool,
+
+    /// Whether uploads may be deferred, because nothing waits for them.
+    defer_upload: bool,
 }
 
 impl CargoCache {
     #[instrument(name = "CargoCache::open", skip(courier_token))]
     pub async fn open(courier_url: Url, courier_token: Token, ws: Workspace) -> Result<Self> {
-        let courier = Courier::new(courier_url.clone(), courier_token.clone())?;
-        courier.ping().await.context("ping courier service")?;
-        let cas = CourierCas::new(courier.clone());
-        Ok(Self {
+        let config = HurryConfig::load(&ws.root)
+            .await
+            .context("load hurry config")?;
+        let proxy = config.proxy;
+        let network = config.network;
+        let buffer_sizes = buffers::resolve(&config.buffers).await;
+        let courier = ConnectionPool::with_options(&proxy, &network.pool_options())?
+            .client(courier_url.clone(), courier_token.clone())
+            .with_timeouts(network.timeouts())
+            .with_buffer_sizes(buffer_sizes)
+            .with_traffic_class(metadata::traffic_class());
+        tokio::time::timeout(COURIER_PING_TIMEOUT, courier.ping())
+            .await
+            .context("ping courier service")?
+            .context("ping courier service")?;
+        let hash_algorithm = courier
+            .negotiate_hash_algorithm(config.hash_algorithm)
+            .await
+            .context("negotiate hash algorithm")?;
+        let cas = CourierCas::new(courier.clone()).with_hash_algorithm(hash_algorithm);
+        let local = LocalCas::open(&config.local_cache).await?;
+        let prewarm = PrewarmSchedule::from_config(&config.prewarm)
+            .context("read prewarm schedule from hurry config")?;
+        let cache = Self {
             courier_url,
             courier_token,
+            proxy,
+            network,
+            hash_algorithm,
+            buffer_sizes,
+            restore: config.restore,
+            upload: config.upload,
+            local_cache: config.local_cache,
+            prewarm,
             courier,
             cas,
+            local,
             ws,
+            restore_in_daemon: true,
+            defer_upload: false,
+        };
+        cache.warm_daemon().await;
+        Ok(cache)
+    }
+
+    /// Open the cache, or return `None` if Courier can't be reached so that
+    /// the build continues without it.
+    ///
+    /// A warning that the build runs without the cache is shown the first
+    /// time this happens in a process, however many times the cache is
+    /// opened.
+    pub async fn open_unless_unreachable(
+        courier_url: Url,
+        courier_token: Token,
+        ws: Workspace,
+    ) -> Result<Option<Self>> {
+        match Self::open(courier_url.clone(), courier_token, ws).await {
+            Ok(cache) => Ok(Some(cache)),
+            Err(err) if Self::is_unreachable(&err) => {
+                debug!(?err, "courier is unreachable");
+                if UNREACHABLE_WARNING.take() {
+                    eprintln!("{}", unreachable_warning(&courier_url));
+                }
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Whether opening the cache failed because Courier couldn't be reached,
+    /// as opposed to e.g. because it rejected the token.
+    ///
+    /// Builds continue without the cache in this case rather than failing.
+    pub fn is_unreachable(err: &color_eyre::Report) -> bool {
+        err.chain().any(|cause| {
+            cause.is::<tokio::time::error::Elapsed>()
+                || cause
+                    .downcast_ref::<reqwest::Error>()
+                    .is_some_and(|err| err.is_connect() || err.is_timeout())
         })
     }
 
-    #[instrument(name = "CargoCache::save", skip_all)]
-    pub async fn save(&self, units: Vec<UnitPlan>, restored: Restored) -> Result<Uuid> {
-        let paths = DaemonPaths::initialize().await?;
+    /// Set whether to restore in the daemon (the default) or in this process.
+    ///
+    /// Restoring in the daemon shares its connections to Courier with uploads
+    /// and with other builds; restoring in this process is a fallback for
+    /// environments where the daemon can't run.
+    pub fn with_restore_in_daemon(mut self, restore_in_daemon: bool) -> Self {
+        self.restore_in_daemon = restore_in_daemon;
+        self
+    }
 
-        // Start daemon if it's not already running. If it is, try to read its
-        // context file to get its url, which we need to know in order to
-        // communicate with it.
-        let daemon = if let Some(daemon) = paths.daemon_running().await? {
-            daemon
-        } else {
-            // TODO: Ideally we'd replace this with proper double-fork
-            // daemonization to avoid the security and compatibility concerns
-            // here: someone could replace the binary at this path in the time
-            // between when this binary launches and when it re-launches itself
-            // as a daemon.
-            let hurry_binary = std::env::current_exe().context("read current binary path")?;
-
-            // Spawn self as a child and wait for the ready message on STDOUT.
-            let mut cmd = tokio::process::Command::new(hurry_binary);
-            cmd.arg("daemon")
-                .arg("start")
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped());
-
-            cmd.spawn()?;
-
-            // This value was chosen arbitrarily. Adjust as needed.
-            const DAEMON_STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
-            tokio::time::timeout(DAEMON_STARTUP_TIMEOUT, async {
-                let mut interval = tokio::time::interval(Duration::from_secs(1));
-                loop {
-                    interval.tick().await;
-                    if let Some(daemon) = paths.daemon_running().await? {
-                        break Result::<_>::Ok(daemon);
-                    }
-                }
-            })
+    /// Only restore units that were saved at or before the provided point,
+    /// to rebuild the workspace the way it was built then.
+    pub fn with_as_of(mut self, as_of: Option<CacheAsOf>) -> Self {
+        self.restore.as_of = as_of;
+        self
+    }
+
+    /// Save and restore units in the provided cache scope, isolating them
+    /// from the rest of the organization's cache, or in the organization's
+    /// unscoped cache if there's no scope.
+    pub fn with_cache_scope(mut self, scope: Option<CacheScope>) -> Self {
+        self.courier = self.courier.with_cache_scope(scope);
+        self
+    }
+
+    /// Set whether the daemon may defer uploads, as configured in
+    /// `hurry.toml`, so that uploads of successive builds can be merged.
+    ///
+    /// Only set this if nothing waits for the upload to finish.
+    pub fn with_deferred_upload(mut self, defer_upload: bool) -> Self {
+        self.defer_upload = defer_upload;
+        self
+    }
+
+    /// Set how many units to upload at once, overriding `hurry.toml`.
+    pub fn with_upload_parallelism(mut self, parallelism: Option<usize>) -> Self {
+        if let Some(parallelism) = parallelism {
+            self.upload.parallelism = Some(parallelism);
+        }
+        self
+    }
+
+    /// Ask the daemon, if it's already running, to open its connection to
+    /// Courier now so that it's ready once the build finishes and the upload
+    /// starts.
+    ///
+    /// This is only an optimization, so failures are logged and ignored.
+    #[instrument(name = "CargoCache::warm_daemon", skip_all)]
+    async fn warm_daemon(&self) {
+        let warm = async {
+            let paths = DaemonPaths::initialize().await?;
+            let Some(daemon) = paths.daemon_running().await? else {
+                return Result::<_>::Ok(());
+            };
+
+            let endpoint = format!("http://{}/api/v0/cargo/warm", daemon.url);
+            let request = CargoWarmRequest {
+                courier_url: self.courier_url.clone(),
+                courier_token: self.courier_token.clone(),
+                proxy: self.proxy.clone(),
+                network: self.network,
+            };
+            local_client()?
+                .post(&endpoint)
+                .json(&request)
+                .timeout(DAEMON_WARM_TIMEOUT)
+                .send()
+                .await
+                .with_context(|| format!("send warm request to daemon at: {endpoint}"))?;
+            Ok(())
+        };
+        if let Err(err) = warm.await {
+            debug!(?err, "failed to warm daemon connection");
+        }
+    }
+
+    /// Ask the daemon to watch the workspace, so that it prefetches the
+    /// artifacts for builds with `argv` whenever the workspace's toolchain or
+    /// lockfile changes.
+    #[instrument(name = "CargoCache::watch", skip_all)]
+    pub async fn watch(&self, argv: &[String]) -> Result<()> {
+        let daemon = start_daemon().await?;
+        let endpoint = format!("http://{}/api/v0/cargo/watch", daemon.url);
+        let request = CargoWatchRequest {
+            courier_url: self.courier_url.clone(),
+            courier_token: self.courier_token.clone(),
+            proxy: self.proxy.clone(),
+            network: self.network,
+            root: self.ws.root.clone(),
+            invoked_in: self.ws.invoked_in.clone(),
+            argv: argv.to_vec(),
+            cache_scope: self.courier.cache_scope().cloned(),
+        };
+        trace!(?request, "submitting watch request");
+        local_client()?
+            .post(&endpoint)
+            .json(&request)
+            .send()
             .await
-            .context("wait for daemon to start")??
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("send watch request to daemon at: {endpoint}"))
+            .with_section(|| format!("{daemon:?}").header("Daemon context:"))?;
+        Ok(())
+    }
+
+    /// Register the workspace with the daemon for prewarming, if it has a
+    /// prewarm schedule in `hurry.toml`, so that the daemon prefetches the
+    /// artifacts for builds with `argv` whenever the schedule is due.
+    #[instrument(name = "CargoCache::prewarm", skip_all)]
+    pub async fn prewarm(&self, argv: &[String]) -> Result<()> {
+        let Some(schedule) = &self.prewarm else {
+            return Ok(());
+        };
+        let daemon = start_daemon().await?;
+        let endpoint = format!("http://{}/api/v0/cargo/prewarm", daemon.url);
+        let request = CargoPrewarmRequest {
+            workspace: CargoWatchRequest {
+                courier_url: self.courier_url.clone(),
+                courier_token: self.courier_token.clone(),
+                proxy: self.proxy.clone(),
+                network: self.network,
+                root: self.ws.root.clone(),
+                invoked_in: self.ws.invoked_in.clone(),
+                argv: argv.to_vec(),
+                cache_scope: self.courier.cache_scope().cloned(),
+            },
+            schedule: schedule.clone(),
         };
+        trace!(?request, "submitting prewarm request");
+        local_client()?
+            .post(&endpoint)
+            .json(&request)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("send prewarm request to daemon at: {endpoint}"))
+            .with_section(|| format!("{daemon:?}").header("Daemon context:"))?;
+        Ok(())
+    }
+
+    #[instrument(name = "CargoCache::save", skip_all)]
+    pub async fn save(
+        &self,
+        units: Vec<UnitPlan>,
+        restored: Restored,
+        policy: UploadPolicy,
+    ) -> Result<Uuid> {
+        let daemon = start_daemon().await?;
 
         // Connect to daemon HTTP server.
-        let client = reqwest::Client::default();
+        let client = local_client()?;
         let endpoint = format!("http://{}/api/v0/cargo/upload", daemon.url);
 
         // Send upload request.
         let request_id = Uuid::new_v4();
+        let policy = policy.require_dependencies(&units);
+        let metadata = metadata::collect(&self.ws).await;
         let request = CargoUploadRequest {
             request_id,
             courier_url: self.courier_url.clone(),
             courier_token: self.courier_token.clone(),
+            proxy: self.proxy.clone(),
+            network: self.network,
+            hash_algorithm: self.hash_algorithm,
+            buffer_sizes: self.buffer_sizes,
             ws: self.ws.clone(),
             units,
             skip: restored,
+            policy,
+            metadata,
+            local_cache: self.local_cache,
+            defer: self.defer_upload.then(|| self.upload.defer()).flatten(),
+            parallelism: self.upload.parallelism(),
+            chunking: self.upload.chunking,
+            cache_scope: self.courier.cache_scope().cloned(),
         };
         trace!(?request, "submitting upload request");
         let response = client
@@ -114,9 +408,385 @@ impl CargoCache {
         Ok(request_id)
     }
 
+    /// Record how long Cargo took to build the units it built, for adaptive
+    /// restore to compare with how long they take to download.
+    ///
+    /// This does nothing unless adaptive restore is enabled. The timings are
+    /// only an optimization, so failing to record them doesn't fail the build.
+    #[instrument(name = "CargoCache::record_build_timings", skip_all)]
+    pub async fn record_build_timings(&self, units: &[UnitPlan], restored: &Restored) {
+        if !self.restore.adaptive.enabled {
+            return;
+        }
+        let mut timings = timings::UnitTimings::load(&self.ws).await;
+        timings.record_builds(&self.ws, units, restored).await;
+        if let Err(err) = timings.save(&self.ws).await {
+            warn!(?err, "failed to save unit timings");
+        }
+    }
+
     #[instrument(name = "CargoCache::restore", skip_all)]
     pub async fn restore(&self, units: &Vec<UnitPlan>, progress: &TransferBar) -> Result<Restored> {
-        restore_units(&self.courier, &self.cas, &self.ws, units, progress).await
+        self.restore_inner(units, progress, None).await
+    }
+
+    /// Restore units, leaving any units that haven't been restored by
+    /// `deadline` for Cargo to build.
+    #[instrument(name = "CargoCache::restore_until", skip_all)]
+    pub async fn restore_until(
+        &self,
+        units: &Vec<UnitPlan>,
+        progress: &TransferBar,
+        deadline: Instant,
+    ) -> Result<Restored> {
+        self.restore_inner(units, progress, Some(deadline)).await
+    }
+
+    /// Restore units while Cargo builds, handing them to Cargo through
+    /// `handoff` as they're restored and leaving any that haven't been
+    /// restored by `deadline` for Cargo to build.
+    ///
+    /// Cargo's rustc wrapper asks this process for units, so this always
+    /// restores in this process.
+    #[instrument(name = "CargoCache::restore_handoff", skip_all)]
+    pub async fn restore_handoff(
+        &self,
+        units: &Vec<UnitPlan>,
+        progress: &TransferBar,
+        deadline: Option<Instant>,
+        handoff: &RestoreHandoff,
+    ) -> Result<Restored> {
+        let restored = restore_units(
+            &self.courier,
+            &self.cas,
+            &self.local,
+            &self.ws,
+            units,
+            progress,
+            RestoreOptions::new(&self.restore)
+                .with_deadline(deadline)
+                .with_handoff(handoff),
+        )
+        .await;
+        self.local.trim().await;
+        restored
+    }
+
+    /// Download the objects of `units` into the local CAS without restoring
+    /// them, returning the number of objects downloaded.
+    ///
+    /// Restores check the local CAS first, whether they run in the daemon or
+    /// in this process, so prefetching the units of several workspaces before
+    /// restoring them downloads objects they share only once.
+    #[instrument(name = "CargoCache::prefetch", skip_all)]
+    pub async fn prefetch(&self, units: impl IntoIterator<Item = &UnitPlan>) -> Result<usize> {
+        prefetch_units(&self.courier, &self.cas, &self.local, units).await
+    }
+
+    /// Find which of the units the remote cache can restore, returning their
+    /// unit hashes.
+    #[instrument(name = "CargoCache::restorable", skip_all)]
+    pub async fn restorable(
+        &self,
+        units: impl IntoIterator<Item = &UnitPlan>,
+    ) -> Result<HashSet<UnitHash>> {
+        restorable_units(&self.courier, units).await
+    }
+
+    /// Look up the unit hashes of the checked units of a check plan, keyed by
+    /// their build-mode unit hashes.
+    #[instrument(name = "CargoCache::resolve_checked", skip_all)]
+    pub async fn resolve_checked(&self, plan: &CheckPlan) -> Result<HashMap<UnitHash, UnitHash>> {
+        resolve_checked_units(&self.courier, plan).await
+    }
+
+    /// Restore units in the daemon, falling back to restoring in this process
+    /// if the daemon can't be reached.
+    async fn restore_inner(
+        &self,
+        units: &Vec<UnitPlan>,
+        progress: &TransferBar,
+        deadline: Option<Instant>,
+    ) -> Result<Restored> {
+        // The daemon has no terminal to ask whether to overwrite local files
+        // in, so restores that may need to ask run in this process.
+        let prompt =
+            self.restore.overwrite == OverwritePolicy::Prompt && std::io::stdin().is_terminal();
+        if self.restore_in_daemon && !prompt {
+            match self.request_restore(units, deadline).await {
+                Ok(response) => return read_restore_events(response, units, progress).await,
+                Err(err) => warn!(?err, "failed to restore in daemon, restoring in process"),
+            }
+        }
+        let restored = restore_units(
+            &self.courier,
+            &self.cas,
+            &self.local,
+            &self.ws,
+            units,
+            progress,
+            RestoreOptions::new(&self.restore).with_deadline(deadline),
+        )
+        .await;
+        self.local.trim().await;
+        restored
+    }
+
+    /// Ask the daemon to restore units, returning the response that its
+    /// progress is streamed on.
+    ///
+    /// Nothing has been restored if this fails, so it's safe to retry the
+    /// restore elsewhere.
+    #[instrument(name = "CargoCache::request_restore", skip_all)]
+    async fn request_restore(
+        &self,
+        units: &[UnitPlan],
+        deadline: Option<Instant>,
+    ) -> Result<reqwest::Response> {
+        let daemon = start_daemon().await?;
+        let endpoint = format!("http://{}/api/v0/cargo/restore", daemon.url);
+        let request = CargoRestoreRequest {
+            courier_url: self.courier_url.clone(),
+            courier_token: self.courier_token.clone(),
+            proxy: self.proxy.clone(),
+            network: self.network,
+            hash_algorithm: self.hash_algorithm,
+            buffer_sizes: self.buffer_sizes,
+            ws: self.ws.clone(),
+            units: units.to_vec(),
+            config: self.restore.clone(),
+            local_cache: self.local_cache,
+            timeout: deadline.map(|deadline| deadline.saturating_duration_since(Instant::now())),
+            cache_scope: self.courier.cache_scope().cloned(),
+        };
+        trace!(?request, "submitting restore request");
+        local_client()?
+            .post(&endpoint)
+            .json(&request)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("send restore request to daemon at: {endpoint}"))
+            .with_section(|| format!("{daemon:?}").header("Daemon context:"))
+    }
+}
+
+/// Read the events the daemon streams in response to a restore request,
+/// reporting its progress until it finishes.
+#[instrument(skip_all)]
+async fn read_restore_events(
+    mut response: reqwest::Response,
+    units: &[UnitPlan],
+    progress: &TransferBar,
+) -> Result<Restored> {
+    let mut last = CargoRestoreProgress {
+        total_units: units.len() as u64,
+        ..Default::default()
+    };
+    let mut buffer = Vec::new();
+    loop {
+        let Some(chunk) = response
+            .chunk()
+            .await
+            .context("read restore response from daemon")?
+        else {
+            bail!("daemon stopped responding before restore finished");
+        };
+        buffer.extend_from_slice(&chunk);
+
+        while let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+            let line = buffer.drain(..=end).collect::<Vec<_>>();
+            let event = serde_json::from_slice::<CargoRestoreEvent>(&line)
+                .context("parse restore event from daemon")?;
+            trace!(?event, "got restore event");
+            match event {
+                CargoRestoreEvent::Progress(current) => {
+                    progress.inc(current.restored_units.saturating_sub(last.restored_units));
+                    progress.add_files(current.restored_files.saturating_sub(last.restored_files));
+                    progress.add_bytes(current.restored_bytes.saturating_sub(last.restored_bytes));
+                    progress.dec_length(last.total_units.saturating_sub(current.total_units));
+                    last = current;
+                }
+                CargoRestoreEvent::Complete(restored) => return Ok(restored),
+                CargoRestoreEvent::Failed { error } => return Err(eyre!(error)),
+            }
+        }
+    }
+}
+
+/// Start the daemon if it's not already running, returning its context once
+/// it's ready to accept requests.
+///
+/// Before returning, the daemon is checked to be serving requests and running
+/// the same version of Hurry as this process, so that work is never sent to a
+/// daemon that can't handle it. A daemon left running by another version of
+/// Hurry (usually from before an upgrade) is stopped and replaced.
+#[instrument]
+async fn start_daemon() -> Result<DaemonContext> {
+    let paths = DaemonPaths::initialize().await?;
+
+    // A daemon started by an older version of Hurry doesn't see the files
+    // of the daemon started here, so it would keep running next to it.
+    if let Err(err) = paths.stop_legacy_daemon(DAEMON_REPLACE_TIMEOUT).await {
+        warn!(
+            ?err,
+            "failed to stop daemon started by an older version of Hurry"
+        );
+    }
+
+    // Start daemon if it's not already running. If it is, try to read its
+    // context file to get its url, which we need to know in order to
+    // communicate with it.
+    if let Some(daemon) = paths.daemon_running().await?
+        && let Some(daemon) = reuse_or_replace(daemon, DAEMON_REPLACE_TIMEOUT).await?
+    {
+        return Ok(daemon);
+    }
+
+    // TODO: Ideally we'd replace this with proper double-fork
+    // daemonization to avoid the security and compatibility concerns
+    // here: someone could replace the binary at this path in the time
+    // between when this binary launches and when it re-launches itself
+    // as a daemon.
+    let hurry_binary = std::env::current_exe().context("read current binary path")?;
+
+    // Spawn self as a child. The daemon logs to a file once it starts, so
+    // only errors from before then show up on its STDERR.
+    let mut cmd = tokio::process::Command::new(hurry_binary);
+    cmd.arg("daemon")
+        .arg("start")
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let mut child = cmd.spawn().context("spawn daemon")?;
+
+    // The daemon is ready once it has written its context file and answers
+    // health checks. Another client may have started a daemon at the same
+    // time, in which case the child exits and the other daemon is used.
+    let pid = child.id();
+    let mut backoff = StartupBackoff::new(Instant::now(), DAEMON_STARTUP_TIMEOUT);
+    let mut last_err = None;
+    let exit = loop {
+        if let Some(daemon) = paths.daemon_running().await? {
+            match daemon.health().await {
+                Ok(health) => {
+                    daemon
+                        .check(&health)
+                        .context("check started daemon")
+                        .with_section(|| format!("{daemon:?}").header("Daemon context:"))?;
+                    return Ok(daemon);
+                }
+                Err(err) => {
+                    trace!(?err, "daemon not ready yet");
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        // If the child failed without another daemon having started, there's
+        // nothing left to wait for.
+        let exit = child.try_wait().context("check daemon process")?;
+        if let Some(status) = exit
+            && !status.success()
+            && !fs::exists(&paths.pid_file_path).await
+        {
+            break exit;
+        }
+        let Some(delay) = backoff.next_delay(Instant::now()) else {
+            break exit;
+        };
+        tokio::time::sleep(delay).await;
+    };
+
+    let stderr = match (exit, child.stderr.take()) {
+        (Some(_), Some(mut stderr)) => {
+            let mut output = String::new();
+            stderr
+                .read_to_string(&mut output)
+                .await
+                .context("read daemon stderr")?;
+            output
+        }
+        _ => String::new(),
+    };
+    let log_file_path = pid.map(|pid| paths.log_file_path(pid)).transpose()?;
+    let process = match (exit, pid) {
+        (Some(status), _) => format!("exited with {status}"),
+        (None, Some(pid)) => format!("still running as pid {pid}"),
+        (None, None) => String::from("still running"),
+    };
+    Err(eyre!("daemon did not become ready"))
+        .with_section(|| process.header("Daemon process:"))
+        .with_section(|| stderr.header("Daemon stderr:"))
+        .with_section(|| format!("{last_err:?}").header("Last health check error:"))
+        .with_suggestion(|| match log_file_path {
+            Some(path) => format!("Check the daemon logs at {path}"),
+            None => String::from("Check the daemon logs in the Hurry cache directory"),
+        })
+}
+
+/// Reuse the running daemon if it runs the same version of Hurry as this
+/// process.
+///
+/// Otherwise the daemon is asked to stop, waiting up to `timeout` for it to
+/// finish the uploads it has started, and `None` is returned so that a new
+/// daemon is started in its place.
+async fn reuse_or_replace(
+    daemon: DaemonContext,
+    timeout: Duration,
+) -> Result<Option<DaemonContext>> {
+    let handshake = daemon
+        .handshake()
+        .await
+        .context("check running daemon")
+        .with_section(|| format!("{daemon:?}").header("Daemon context:"))?;
+    match handshake {
+        DaemonHandshake::Current(_) => Ok(Some(daemon)),
+        DaemonHandshake::Outdated { version } => {
+            info!(
+                ?version,
+                pid = daemon.pid,
+                "replacing daemon running another version"
+            );
+            daemon
+                .stop(timeout)
+                .await
+                .context("stop daemon running another version")
+                .with_section(|| format!("{daemon:?}").header("Daemon context:"))
+                .suggestion("Stop the daemon with `hurry daemon stop`.")?;
+            Ok(None)
+        }
+    }
+}
+
+/// The delays between checks for whether a newly spawned daemon is ready.
+///
+/// The delay doubles after each check, from [`DAEMON_STARTUP_POLL_MIN`] up to
+/// [`DAEMON_STARTUP_POLL_MAX`], and the checks stop at the deadline.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct StartupBackoff {
+    deadline: Instant,
+    delay: Duration,
+}
+
+impl StartupBackoff {
+    fn new(start: Instant, timeout: Duration) -> Self {
+        Self {
+            deadline: start + timeout,
+            delay: DAEMON_STARTUP_POLL_MIN,
+        }
+    }
+
+    /// How long to wait before checking again, or `None` once the deadline
+    /// has passed. The last delay is cut short so that it ends at the
+    /// deadline.
+    fn next_delay(&mut self, now: Instant) -> Option<Duration> {
+        let remaining = self
+            .deadline
+            .checked_duration_since(now)
+            .filter(|remaining| !remaining.is_zero())?;
+        let delay = self.delay.min(remaining);
+        self.delay = (self.delay * 2).min(DAEMON_STARTUP_POLL_MAX);
+        Some(delay)
     }
 }
 
@@ -126,3 +796,210 @@ pub struct SavedFile {
     pub contents: Vec<u8>,
     pub executable: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
+        time::{Duration, Instant},
+    };
+
+    use axum::{
+        Json, Router,
+        http::StatusCode,
+        response::{IntoResponse as _, Response},
+        routing::{get, post},
+    };
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+    use tokio::{net::TcpListener, process::Child, sync::Mutex};
+
+    use clients::Token;
+    use url::Url;
+
+    use super::{CargoCache, StartupBackoff, WarnOnce, reuse_or_replace, unreachable_warning};
+    use crate::{
+        cargo::{CargoBuildArguments, Workspace},
+        daemon::{DAEMON_VERSION, DaemonContext, DaemonHealth},
+        path::AbsFilePath,
+    };
+
+    /// A stand-in for a running daemon: `process` is the daemon's process,
+    /// which exits when the daemon is asked to shut down.
+    struct MockDaemon {
+        context: DaemonContext,
+        shutdown_requested: Arc<AtomicBool>,
+        process: Arc<Mutex<Child>>,
+    }
+
+    /// Start a mock daemon that reports `version` in health checks, or that
+    /// predates health checks if `version` is `None`.
+    async fn mock_daemon(version: Option<&str>) -> MockDaemon {
+        let process = tokio::process::Command::new("sleep")
+            .arg("60")
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let pid = process.id().unwrap();
+        let process = Arc::new(Mutex::new(process));
+        let shutdown_requested = Arc::new(AtomicBool::new(false));
+
+        let version = version.map(String::from);
+        let health = move || {
+            let version = version.clone();
+            async move {
+                match version {
+                    Some(version) => Json(DaemonHealth { pid, version }).into_response(),
+                    None => StatusCode::NOT_FOUND.into_response(),
+                }
+            }
+        };
+        let shutdown = {
+            let process = process.clone();
+            let shutdown_requested = shutdown_requested.clone();
+            move || async move {
+                shutdown_requested.store(true, Ordering::SeqCst);
+                process.lock().await.kill().await.unwrap();
+                Response::default()
+            }
+        };
+        let app = Router::new()
+            .route("/api/v0/health", get(health))
+            .route("/api/v0/shutdown", post(shutdown));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        MockDaemon {
+            context: DaemonContext {
+                pid,
+                url,
+                log_file_path: AbsFilePath::try_from("/cache/hurryd-test.1.log").unwrap(),
+            },
+            shutdown_requested,
+            process,
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reuses_current_daemon() {
+        let daemon = mock_daemon(Some(DAEMON_VERSION)).await;
+        let reused = reuse_or_replace(daemon.context.clone(), Duration::from_secs(10))
+            .await
+            .unwrap();
+        pretty_assert_eq!(reused, Some(daemon.context));
+        assert!(!daemon.shutdown_requested.load(Ordering::SeqCst));
+        assert!(daemon.process.lock().await.try_wait().unwrap().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test_case(Some("0.0.0-other"); "other version")]
+    #[test_case(None; "no version")]
+    #[tokio::test]
+    async fn replaces_outdated_daemon(version: Option<&str>) {
+        let daemon = mock_daemon(version).await;
+        let reused = reuse_or_replace(daemon.context.clone(), Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        // The outdated daemon is drained and has exited, so a new one can be
+        // started in its place.
+        pretty_assert_eq!(reused, None);
+        assert!(daemon.shutdown_requested.load(Ordering::SeqCst));
+        assert!(daemon.process.lock().await.try_wait().unwrap().is_some());
+    }
+
+    #[test]
+    fn startup_backoff_doubles_until_deadline() {
+        let mut now = Instant::now();
+        let timeout = Duration::from_secs(5);
+        let mut backoff = StartupBackoff::new(now, timeout);
+        let mut delays = Vec::new();
+        while let Some(delay) = backoff.next_delay(now) {
+            delays.push(delay);
+            now += delay;
+        }
+
+        let expected = [25, 50, 100, 200, 400]
+            .into_iter()
+            .chain([500; 8])
+            .chain([225])
+            .map(Duration::from_millis)
+            .collect::<Vec<_>>();
+        pretty_assert_eq!(delays, expected);
+        pretty_assert_eq!(delays.iter().sum::<Duration>(), timeout);
+
+        // Once the deadline has passed, the backoff stays exhausted.
+        pretty_assert_eq!(backoff.next_delay(now), None);
+        pretty_assert_eq!(backoff.next_delay(now + timeout), None);
+    }
+
+    /// Open the cache of the current workspace against Courier at `url`.
+    async fn open_at(url: &str) -> color_eyre::Result<CargoCache> {
+        let workspace = Workspace::from_argv(CargoBuildArguments::empty())
+            .await
+            .expect("open current workspace");
+        CargoCache::open(
+            Url::parse(url).unwrap(),
+            Token::from("test-token"),
+            workspace,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn refused_connection_is_unreachable() {
+        // Nothing listens on the port once the listener is dropped.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+
+        let err = open_at(&url).await.unwrap_err();
+        assert!(CargoCache::is_unreachable(&err), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn unanswered_ping_is_unreachable() {
+        // The listener accepts connections but never answers, so the ping
+        // times out.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let err = open_at(&url).await.unwrap_err();
+        assert!(CargoCache::is_unreachable(&err), "{err:?}");
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn rejected_ping_is_not_unreachable() {
+        let app = Router::new().route(
+            "/api/v1/health",
+            get(|| async { StatusCode::SERVICE_UNAVAILABLE }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // Courier answered, so the problem is with Courier rather than the
+        // network, and the build reports it instead of going offline.
+        let err = open_at(&url).await.unwrap_err();
+        assert!(!CargoCache::is_unreachable(&err), "{err:?}");
+    }
+
+    #[test]
+    fn unreachable_warning_is_shown_once() {
+        let warning = WarnOnce::new();
+        let shown = [warning.take(), warning.take(), warning.take()];
+        pretty_assert_eq!(shown, [true, false, false]);
+
+        let url = Url::parse("https://hurry.example.com/").unwrap();
+        pretty_assert_eq!(
+            unreachable_warning(&url),
+            "Warning: couldn't reach the Hurry API at https://hurry.example.com/, building without the cache"
+        );
+    }
+}
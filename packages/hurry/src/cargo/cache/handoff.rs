@@ -0,0 +1,671 @@
+//! Handing restored units to a Cargo build that's already running.
+//!
+//! With `--hurry-overlap-restore`, Cargo starts building while the cache is
+//! still being restored. Cargo decides which units are fresh once, before it
+//! compiles anything, so units restored after that can't be picked up through
+//! their fingerprints. Instead, Cargo runs Hurry as its rustc wrapper, and the
+//! wrapper asks this process's [`RestoreHandoff`] for each unit that Cargo is
+//! about to compile:
+//!
+//! - If the unit has been restored (waiting for its files if they're still
+//!   being written), the wrapper exits without running rustc. Cargo then
+//!   treats the restored outputs as what rustc produced, and writes the unit's
+//!   fingerprint itself.
+//! - If restore doesn't restore the unit, e.g. because the cache doesn't have
+//!   it or the restore deadline passed before restore got to it, Cargo claims
+//!   it and the wrapper runs rustc. Restore leaves claimed units (and units
+//!   that depend on them) alone.
+//!
+//! Only library crates are handed off: Cargo runs build scripts itself rather
+//! than through rustc, so it only skips rerunning them if they're fresh when
+//! it starts. Build script executions are therefore restored, with the units
+//! they depend on, before Cargo starts compiling (see [`split_for_handoff`]).
+
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    Router,
+    extract::{Json, Path, State},
+    routing::post,
+};
+use color_eyre::{Result, eyre::Context as _};
+use dashmap::{DashMap, mapref::entry::Entry};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, sync::watch, task::JoinHandle};
+use tracing::{debug, instrument, trace};
+
+use crate::{
+    cargo::{RustcArguments, UnitHash, UnitPlan, Workspace, config},
+    daemon::local_client,
+    fs,
+    path::{AbsDirPath, AbsFilePath, JoinWith as _, TryJoinWith as _},
+};
+
+/// The environment variable that tells Hurry it's running as Cargo's rustc
+/// wrapper, set to the address of the hand-off server to ask for units.
+pub const HANDOFF_ENV: &str = "HURRY_RESTORE_HANDOFF";
+
+/// The environment variable holding the rustc wrapper that Cargo would have
+/// run if Hurry weren't its wrapper, which Hurry runs in its place.
+const INNER_WRAPPER_ENV: &str = "HURRY_INNER_RUSTC_WRAPPER";
+
+/// Where a unit is in the hand-off between restore and Cargo.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum UnitState {
+    /// Restore hasn't decided whether it restores the unit yet.
+    Pending,
+
+    /// Restore is writing the unit's files.
+    Restoring,
+
+    /// Restore wrote all of the unit's files.
+    Restored,
+
+    /// Cargo compiles the unit itself.
+    Compiling,
+}
+
+#[derive(Debug)]
+struct HandoffUnit {
+    state: watch::Sender<UnitState>,
+
+    /// The directory rustc writes the unit's outputs to, unless Cargo claimed
+    /// the unit before restore expected it.
+    out_dir: Option<AbsDirPath>,
+    deps: Vec<UnitHash>,
+}
+
+impl HandoffUnit {
+    fn compiling() -> Self {
+        Self {
+            state: watch::Sender::new(UnitState::Compiling),
+            out_dir: None,
+            deps: Vec::new(),
+        }
+    }
+}
+
+/// Hands units that are restored while Cargo is building to Cargo.
+///
+/// Units that may be handed off are registered with [`RestoreHandoff::expect`]
+/// before Cargo starts. Restore calls [`RestoreHandoff::start`] before
+/// restoring each unit, [`RestoreHandoff::queued`] once it has started every
+/// unit it's going to restore, and reports how each unit went with
+/// [`RestoreHandoff::restored`] or [`RestoreHandoff::abandoned`]. Cargo's
+/// rustc wrapper takes units with [`RestoreHandoff::take`] through the server
+/// started by [`RestoreHandoff::serve`].
+#[derive(Debug, Clone, Default)]
+pub struct RestoreHandoff {
+    units: Arc<DashMap<UnitHash, HandoffUnit>>,
+}
+
+impl RestoreHandoff {
+    /// Register the library crates among `units` as pending, so that Cargo
+    /// waits for restore to decide whether it restores them rather than
+    /// compiling them straight away.
+    pub fn expect(&self, ws: &Workspace, units: &[UnitPlan]) -> Result<()> {
+        for unit in units {
+            if matches!(unit, UnitPlan::LibraryCrate(_)) {
+                self.units.insert(
+                    unit.info().unit_hash.clone(),
+                    Self::entry(ws, unit, UnitState::Pending)?,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn entry(ws: &Workspace, unit: &UnitPlan, state: UnitState) -> Result<HandoffUnit> {
+        let info = unit.info();
+        Ok(HandoffUnit {
+            state: watch::Sender::new(state),
+            out_dir: Some(ws.unit_profile_dir(info).join(&info.deps_dir()?)),
+            deps: info.deps.clone(),
+        })
+    }
+
+    /// Start restoring a unit, returning whether restore may go ahead: it
+    /// can't once Cargo has started compiling the unit itself.
+    pub fn start(&self, ws: &Workspace, unit: &UnitPlan) -> Result<bool> {
+        match self.units.entry(unit.info().unit_hash.clone()) {
+            Entry::Occupied(entry) => Ok(entry.get().state.send_if_modified(|state| {
+                let pending = *state == UnitState::Pending;
+                if pending {
+                    *state = UnitState::Restoring;
+                }
+                pending
+            })),
+            Entry::Vacant(entry) => {
+                entry.insert(Self::entry(ws, unit, UnitState::Restoring)?);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Record that restore has started every unit it's going to restore,
+    /// leaving the units that are still pending for Cargo.
+    pub fn queued(&self) {
+        self.replace(&[UnitState::Pending], UnitState::Compiling);
+    }
+
+    /// Record that restore wrote all of a unit's files.
+    pub fn restored(&self, unit: &UnitHash) {
+        self.set(unit, UnitState::Restored);
+    }
+
+    /// Record that restore couldn't finish a unit, leaving it for Cargo.
+    pub fn abandoned(&self, unit: &UnitHash) {
+        self.set(unit, UnitState::Compiling);
+    }
+
+    /// Stop restoring: units that are still being restored are left for
+    /// Cargo, and so is every unit that restore didn't get to.
+    pub fn close(&self) {
+        self.replace(
+            &[UnitState::Pending, UnitState::Restoring],
+            UnitState::Compiling,
+        );
+    }
+
+    /// Move every unit in one of the states `from` to the state `to`.
+    fn replace(&self, from: &[UnitState], to: UnitState) {
+        for unit in self.units.iter() {
+            unit.state.send_if_modified(|state| {
+                let matched = from.contains(state);
+                if matched {
+                    *state = to;
+                }
+                matched
+            });
+        }
+    }
+
+    fn set(&self, unit: &UnitHash, state: UnitState) {
+        if let Some(unit) = self.units.get(unit) {
+            unit.state.send_replace(state);
+        }
+    }
+
+    /// Take a unit that Cargo is about to compile into `out_dir`, returning
+    /// whether it was restored.
+    ///
+    /// Units that are pending or being restored are waited for. Units that
+    /// restore won't restore are claimed for Cargo, and so are restored units
+    /// that depend on a unit Cargo compiled: they were compiled against the
+    /// cached build of that unit rather than Cargo's.
+    #[instrument(skip(self))]
+    pub async fn take(&self, unit: &UnitHash, out_dir: &AbsDirPath) -> bool {
+        let (mut state, expected, deps) = match self.units.entry(unit.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(HandoffUnit::compiling());
+                debug!("claimed unit that restore doesn't expect for cargo");
+                return false;
+            }
+            Entry::Occupied(entry) => {
+                let unit = entry.get();
+                (
+                    unit.state.subscribe(),
+                    unit.out_dir.clone(),
+                    unit.deps.clone(),
+                )
+            }
+        };
+
+        // Builds that build scripts run compile the same units into other
+        // build directories.
+        if expected.as_ref() != Some(out_dir) {
+            debug!(?expected, "unit is compiled into another directory");
+            return false;
+        }
+
+        let restored = matches!(
+            state
+                .wait_for(|state| !matches!(state, UnitState::Pending | UnitState::Restoring))
+                .await
+                .as_deref(),
+            Ok(UnitState::Restored)
+        );
+        if !restored {
+            debug!("restore abandoned unit");
+            return false;
+        }
+
+        let compiled_dep = deps.iter().find(|dep| {
+            self.units
+                .get(*dep)
+                .is_some_and(|dep| *dep.state.borrow() == UnitState::Compiling)
+        });
+        if let Some(dep) = compiled_dep {
+            debug!(?dep, "dependency was compiled by cargo");
+            self.abandoned(unit);
+            return false;
+        }
+        true
+    }
+
+    /// Serve the hand-off to Cargo's rustc wrapper on the loopback interface.
+    ///
+    /// The server stops when the returned handle is dropped.
+    pub async fn serve(&self) -> Result<HandoffServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .context("bind hand-off server")?;
+        let addr = listener.local_addr().context("read hand-off address")?;
+        let app = Router::new()
+            .route("/units/{unit_hash}", post(take_unit))
+            .with_state(self.clone());
+        let task = tokio::spawn(async move {
+            if let Err(err) = axum::serve(listener, app).await {
+                debug!(?err, "hand-off server stopped");
+            }
+        });
+        Ok(HandoffServer { addr, task })
+    }
+}
+
+/// Request from Cargo's rustc wrapper for a unit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TakeUnitRequest {
+    /// The directory that rustc would write the unit's outputs to.
+    out_dir: AbsDirPath,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TakeUnitResponse {
+    restored: bool,
+}
+
+async fn take_unit(
+    State(handoff): State<RestoreHandoff>,
+    Path(unit_hash): Path<UnitHash>,
+    Json(request): Json<TakeUnitRequest>,
+) -> Json<TakeUnitResponse> {
+    let restored = handoff.take(&unit_hash, &request.out_dir).await;
+    Json(TakeUnitResponse { restored })
+}
+
+/// The server that Cargo's rustc wrapper asks for units, which stops when
+/// dropped.
+#[derive(Debug)]
+pub struct HandoffServer {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl HandoffServer {
+    /// The environment variables that make Cargo, invoked in `cwd`, run
+    /// Hurry as its rustc wrapper and ask this server for units.
+    ///
+    /// A rustc wrapper that the user configured for Cargo is run by Hurry
+    /// for the units it compiles.
+    pub async fn cargo_env(&self, cwd: &AbsDirPath) -> Result<Vec<(String, OsString)>> {
+        let exe = std::env::current_exe().context("locate hurry executable")?;
+        let mut env = vec![
+            (String::from("RUSTC_WRAPPER"), exe.into_os_string()),
+            (String::from(HANDOFF_ENV), self.addr.to_string().into()),
+        ];
+        if let Some(wrapper) = configured_rustc_wrapper(cwd).await? {
+            debug!(
+                ?wrapper,
+                "running configured rustc wrapper for compiled units"
+            );
+            env.push((String::from(INNER_WRAPPER_ENV), wrapper));
+        }
+        Ok(env)
+    }
+}
+
+impl Drop for HandoffServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RustcWrapperConfig {
+    #[serde(default)]
+    build: Option<RustcWrapperBuildConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct RustcWrapperBuildConfig {
+    rustc_wrapper: Option<String>,
+}
+
+/// The rustc wrapper configured for Cargo invoked in `cwd`, the same way
+/// Cargo resolves it: the environment, then `build.rustc-wrapper` in
+/// configuration. An empty value disables the wrapper.
+async fn configured_rustc_wrapper(cwd: &AbsDirPath) -> Result<Option<OsString>> {
+    let from_env = ["RUSTC_WRAPPER", "CARGO_BUILD_RUSTC_WRAPPER"]
+        .into_iter()
+        .find_map(std::env::var_os);
+    if let Some(wrapper) = from_env {
+        return Ok(Some(wrapper).filter(|wrapper| !wrapper.is_empty()));
+    }
+
+    for file in config::read::<RustcWrapperConfig>(cwd, config::cargo_home(cwd).as_ref()).await? {
+        let Some(wrapper) = file.config.build.and_then(|build| build.rustc_wrapper) else {
+            continue;
+        };
+        if wrapper.is_empty() {
+            return Ok(None);
+        }
+        // Like other configured programs, wrappers given as paths are
+        // relative to the configuration file, and others are looked up in
+        // `PATH`.
+        let wrapper = match wrapper.contains(['/', '\\']) {
+            true => file.base.as_std_path().join(wrapper).into_os_string(),
+            false => OsString::from(wrapper),
+        };
+        return Ok(Some(wrapper));
+    }
+    Ok(None)
+}
+
+/// Split units into those that are restored before Cargo starts compiling and
+/// those that are handed to Cargo as they're restored.
+///
+/// Build script executions and the units they depend on, transitively, are
+/// restored first, since Cargo only skips rerunning a build script if it and
+/// its dependencies are fresh when Cargo starts. Both halves stay in
+/// dependency order.
+pub fn split_for_handoff(units: &[UnitPlan]) -> (Vec<UnitPlan>, Vec<UnitPlan>) {
+    let mut before_build = HashSet::new();
+    for unit in units.iter().rev() {
+        let info = unit.info();
+        if matches!(unit, UnitPlan::BuildScriptExecution(_))
+            || before_build.contains(&info.unit_hash)
+        {
+            before_build.insert(info.unit_hash.clone());
+            before_build.extend(info.deps.iter().cloned());
+        }
+    }
+    units
+        .iter()
+        .cloned()
+        .partition(|unit| before_build.contains(&unit.info().unit_hash))
+}
+
+/// A rustc invocation that Cargo runs through Hurry.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct WrappedUnit {
+    unit_hash: UnitHash,
+    out_dir: AbsDirPath,
+
+    /// The metadata file that rustc announces to Cargo once it's written, if
+    /// Cargo asked for artifact notifications.
+    metadata: Option<AbsFilePath>,
+}
+
+impl WrappedUnit {
+    /// Parse the unit that rustc is run for, or `None` if the invocation
+    /// doesn't compile a unit that could have been restored (e.g. Cargo
+    /// asking rustc for target information).
+    fn parse(rustc_args: &[OsString]) -> Option<Self> {
+        let args = rustc_args
+            .iter()
+            .map(|arg| arg.to_str().map(String::from))
+            .collect::<Option<Vec<_>>>()?;
+        let args = RustcArguments::from_iter(args);
+        let extra_filename = args.extra_filename()?;
+        let unit_hash = UnitHash::from(extra_filename.strip_prefix('-')?);
+        let out_dir = AbsDirPath::try_from(args.out_dir()?).ok()?;
+        let metadata = match (args.json(), args.crate_name()) {
+            (Some(json), Some(crate_name)) if json.split(',').any(|opt| opt == "artifacts") => {
+                out_dir
+                    .try_join_file(format!("lib{crate_name}{extra_filename}.rmeta"))
+                    .ok()
+            }
+            _ => None,
+        };
+        Some(Self {
+            unit_hash,
+            out_dir,
+            metadata,
+        })
+    }
+
+    /// Ask the hand-off server at `addr` for the unit, returning whether it
+    /// was restored.
+    async fn take(&self, addr: &str) -> Result<bool> {
+        let endpoint = format!("http://{addr}/units/{}", self.unit_hash);
+        let response = local_client()?
+            .post(&endpoint)
+            .json(&TakeUnitRequest {
+                out_dir: self.out_dir.clone(),
+            })
+            .send()
+            .await
+            .with_context(|| format!("send hand-off request to: {endpoint}"))?
+            .error_for_status()
+            .context("take unit")?
+            .json::<TakeUnitResponse>()
+            .await
+            .context("parse hand-off response")?;
+        Ok(response.restored)
+    }
+}
+
+/// Run as Cargo's rustc wrapper, asking the hand-off server at `addr` for the
+/// unit that rustc would compile. `args` are the wrapper's arguments: the
+/// rustc executable followed by its arguments.
+///
+/// Returns the exit code for the wrapper to exit with. Nothing can be logged
+/// here, since Cargo parses rustc's output.
+pub async fn wrap_rustc(addr: &str, args: Vec<OsString>) -> i32 {
+    if let Some(unit) = WrappedUnit::parse(args.get(1..).unwrap_or_default())
+        && unit.take(addr).await.unwrap_or(false)
+    {
+        // Cargo starts compiling dependents once their dependencies'
+        // metadata is written, which rustc announces on stderr.
+        if let Some(metadata) = unit.metadata
+            && fs::exists(&metadata).await
+        {
+            let notification = serde_json::json!({
+                "artifact": metadata.as_std_path(),
+                "emit": "metadata",
+            });
+            eprintln!("{notification}");
+        }
+        return 0;
+    }
+
+    let mut args = args.into_iter();
+    let program = match std::env::var_os(INNER_WRAPPER_ENV) {
+        Some(wrapper) => wrapper,
+        None => match args.next() {
+            Some(rustc) => rustc,
+            None => return 1,
+        },
+    };
+    trace!(?program, "running rustc");
+    match tokio::process::Command::new(&program)
+        .args(args)
+        .status()
+        .await
+    {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(err) => {
+            eprintln!("error: could not run {program:?}: {err}");
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, time::Duration};
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use crate::{
+        cargo::UnitHash,
+        path::{AbsDirPath, AbsFilePath},
+    };
+
+    use super::{RestoreHandoff, UnitState, WrappedUnit};
+
+    const OUT_DIR: &str = "/Users/jess/projects/hurry/target/debug/deps";
+
+    fn out_dir() -> AbsDirPath {
+        AbsDirPath::try_from(OUT_DIR).unwrap()
+    }
+
+    /// Start restoring a unit with the dependencies `deps`, without a
+    /// workspace to compute its output directory from.
+    fn start(handoff: &RestoreHandoff, unit: &str, deps: &[&str]) {
+        handoff.units.insert(
+            UnitHash::from(unit),
+            super::HandoffUnit {
+                state: tokio::sync::watch::Sender::new(UnitState::Restoring),
+                out_dir: Some(out_dir()),
+                deps: deps.iter().copied().map(UnitHash::from).collect(),
+            },
+        );
+    }
+
+    fn rustc_args(fixture: &str) -> Vec<OsString> {
+        serde_json::from_str::<Vec<String>>(fixture)
+            .unwrap()
+            .into_iter()
+            .map(OsString::from)
+            .collect()
+    }
+
+    #[test]
+    fn parses_wrapped_unit() {
+        let args = rustc_args(include_str!("../rustc/fixtures/lib_build.json"));
+        let expected = WrappedUnit {
+            unit_hash: UnitHash::from("ac0e04d584580346"),
+            out_dir: out_dir(),
+            metadata: Some(
+                AbsFilePath::try_from(format!("{OUT_DIR}/libbase64-ac0e04d584580346.rmeta"))
+                    .unwrap(),
+            ),
+        };
+        pretty_assert_eq!(WrappedUnit::parse(&args), Some(expected));
+    }
+
+    #[test]
+    fn target_info_queries_are_not_units() {
+        let args = ["-", "--crate-name", "___", "--print=file-names", "-vV"]
+            .map(OsString::from)
+            .to_vec();
+        pretty_assert_eq!(WrappedUnit::parse(&args), None);
+    }
+
+    #[tokio::test]
+    async fn unexpected_units_are_claimed_for_cargo() {
+        let handoff = RestoreHandoff::default();
+        let unit = UnitHash::from("a");
+        pretty_assert_eq!(handoff.take(&unit, &out_dir()).await, false);
+
+        // Restore leaves the unit alone once Cargo has claimed it.
+        let claimed = handoff.units.get(&unit).map(|unit| *unit.state.borrow());
+        pretty_assert_eq!(claimed, Some(UnitState::Compiling));
+    }
+
+    #[tokio::test]
+    async fn take_waits_for_restore() {
+        let handoff = RestoreHandoff::default();
+        start(&handoff, "a", &[]);
+
+        let restore = {
+            let handoff = handoff.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                handoff.restored(&UnitHash::from("a"));
+            })
+        };
+        let taken = handoff.take(&UnitHash::from("a"), &out_dir()).await;
+        restore.await.unwrap();
+        pretty_assert_eq!(taken, true);
+    }
+
+    #[tokio::test]
+    async fn pending_units_wait_for_restore_to_start_them() {
+        let handoff = RestoreHandoff::default();
+        for unit in ["started", "skipped"] {
+            handoff.units.insert(
+                UnitHash::from(unit),
+                super::HandoffUnit {
+                    state: tokio::sync::watch::Sender::new(UnitState::Pending),
+                    out_dir: Some(out_dir()),
+                    deps: Vec::new(),
+                },
+            );
+        }
+
+        let restore = {
+            let handoff = handoff.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                handoff.set(&UnitHash::from("started"), UnitState::Restoring);
+                handoff.queued();
+                handoff.restored(&UnitHash::from("started"));
+            })
+        };
+        let taken = [
+            handoff.take(&UnitHash::from("started"), &out_dir()).await,
+            handoff.take(&UnitHash::from("skipped"), &out_dir()).await,
+        ];
+        restore.await.unwrap();
+        pretty_assert_eq!(taken, [true, false]);
+    }
+
+    #[tokio::test]
+    async fn abandoned_and_unfinished_units_are_compiled() {
+        let handoff = RestoreHandoff::default();
+        start(&handoff, "abandoned", &[]);
+        start(&handoff, "unfinished", &[]);
+        handoff.abandoned(&UnitHash::from("abandoned"));
+        handoff.close();
+
+        let taken = [
+            handoff.take(&UnitHash::from("abandoned"), &out_dir()).await,
+            handoff
+                .take(&UnitHash::from("unfinished"), &out_dir())
+                .await,
+        ];
+        pretty_assert_eq!(taken, [false, false]);
+    }
+
+    #[tokio::test]
+    async fn dependents_of_compiled_units_are_compiled() {
+        let handoff = RestoreHandoff::default();
+        start(&handoff, "dep", &[]);
+        start(&handoff, "dependent", &["dep"]);
+        start(&handoff, "transitive", &["dependent"]);
+        handoff.abandoned(&UnitHash::from("dep"));
+        handoff.restored(&UnitHash::from("dependent"));
+        handoff.restored(&UnitHash::from("transitive"));
+
+        let taken = [
+            handoff.take(&UnitHash::from("dependent"), &out_dir()).await,
+            handoff
+                .take(&UnitHash::from("transitive"), &out_dir())
+                .await,
+        ];
+        pretty_assert_eq!(taken, [false, false]);
+    }
+
+    #[tokio::test]
+    async fn units_compiled_elsewhere_are_not_taken() {
+        let handoff = RestoreHandoff::default();
+        start(&handoff, "a", &[]);
+        handoff.restored(&UnitHash::from("a"));
+
+        let elsewhere = AbsDirPath::try_from("/tmp/nested/target/debug/deps").unwrap();
+        let taken = [
+            handoff.take(&UnitHash::from("a"), &elsewhere).await,
+            handoff.take(&UnitHash::from("a"), &out_dir()).await,
+        ];
+        pretty_assert_eq!(taken, [false, true]);
+    }
+}
@@ -0,0 +1,180 @@
+//! Metadata about the environment that saved units were built in.
+//!
+//! None of this affects whether a unit can be restored; Courier stores it
+//! alongside saved units so that operators can tell where a unit came from
+//! when debugging a bad restore, and so that retention policies can target
+//! units from specific toolchains or pipelines.
+
+use clients::courier::v1::{
+    HashAlgorithm, KeyHasher, cache::SavedUnitMetadata, traffic::TrafficClass,
+};
+use tracing::{debug, instrument};
+
+use crate::{cargo::Workspace, path::AbsDirPath};
+
+/// Environment variables that identify a CI provider, along with the variable
+/// that provider sets to the commit being built.
+///
+/// Checked in order; the first provider whose variable is set wins.
+const CI_PROVIDERS: [(&str, &str, &str); 4] = [
+    ("github-actions", "GITHUB_ACTIONS", "GITHUB_SHA"),
+    ("gitlab-ci", "GITLAB_CI", "CI_COMMIT_SHA"),
+    ("buildkite", "BUILDKITE", "BUILDKITE_COMMIT"),
+    ("circleci", "CIRCLECI", "CIRCLE_SHA1"),
+];
+
+/// Environment variables that CI providers set to the branch being built.
+///
+/// Checked in order; the first one that's set and non-empty wins. Pull
+/// requests check out a merge commit, so the variables naming the source
+/// branch of a pull request come before the ones naming the ref being built.
+const CI_BRANCHES: [&str; 6] = [
+    "GITHUB_HEAD_REF",
+    "GITHUB_REF_NAME",
+    "CI_MERGE_REQUEST_SOURCE_BRANCH_NAME",
+    "CI_COMMIT_REF_NAME",
+    "BUILDKITE_BRANCH",
+    "CIRCLE_BRANCH",
+];
+
+/// Distinguishes builder hostname hashes from any other hash of a hostname.
+const HOSTNAME_HASH_CONTEXT: &str = "hurry 2025-10-01 builder hostname hash";
+
+/// Whether Hurry is running in CI.
+///
+/// Most providers set `CI`, but the provider-specific variables are checked
+/// too in case it's been unset.
+pub fn in_ci() -> bool {
+    std::env::var_os("CI").is_some()
+        || CI_PROVIDERS
+            .iter()
+            .any(|(_, marker, _)| std::env::var_os(marker).is_some())
+}
+
+/// The class of traffic Hurry's requests to Courier belong to: CI builds are
+/// declared as such so that Courier can serve developers waiting on a build
+/// first.
+pub fn traffic_class() -> TrafficClass {
+    if in_ci() {
+        TrafficClass::Ci
+    } else {
+        TrafficClass::Interactive
+    }
+}
+
+/// Collect metadata about the environment that the workspace is built in.
+///
+/// Each field is best-effort: anything that can't be determined is left
+/// unset rather than failing the save.
+#[instrument(skip_all)]
+pub async fn collect(ws: &Workspace) -> SavedUnitMetadata {
+    let ci = CI_PROVIDERS
+        .iter()
+        .find(|(_, marker, _)| std::env::var_os(marker).is_some());
+    let ci_provider = ci.map(|(provider, _, _)| *provider);
+    let commit_sha = ci.and_then(|(_, _, commit)| std::env::var(commit).ok());
+
+    SavedUnitMetadata::builder()
+        .maybe_rustc_version(rustc_version(&ws.root).await)
+        .hurry_version(env!("HURRY_VERSION"))
+        .maybe_ci_provider(ci_provider)
+        .maybe_commit_sha(commit_sha)
+        .maybe_builder_hostname_hash(sysinfo::System::host_name().map(|host| hash_hostname(&host)))
+        .maybe_branch(current_branch(&ws.root).await)
+        .build()
+}
+
+/// The branch that the workspace at `root` is built from.
+///
+/// CI providers often check out a detached commit, so the branch they report
+/// is preferred over the one Git has checked out.
+pub async fn current_branch(root: &AbsDirPath) -> Option<String> {
+    let ci = CI_BRANCHES
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|branch| !branch.is_empty()));
+    if ci.is_some() {
+        return ci;
+    }
+
+    let output = tokio::process::Command::new("git")
+        .args(["rev-parse", "--abbrev-ref", "HEAD"])
+        .current_dir(root.as_std_path())
+        .output()
+        .await;
+    match output {
+        // Git prints `HEAD` when no branch is checked out.
+        Ok(output) if output.status.success() => String::from_utf8(output.stdout)
+            .ok()
+            .map(|branch| branch.trim().to_string())
+            .filter(|branch| !branch.is_empty() && branch != "HEAD"),
+        Ok(output) => {
+            debug!(status = ?output.status, "git rev-parse failed");
+            None
+        }
+        Err(err) => {
+            debug!(?err, "run git rev-parse");
+            None
+        }
+    }
+}
+
+/// The version string of the compiler that Cargo uses in the workspace at
+/// `root`.
+///
+/// This is run in the workspace root so that `rust-toolchain.toml` overrides
+/// are respected, and honors `$RUSTC` the same way Cargo does.
+pub async fn rustc_version(root: &AbsDirPath) -> Option<String> {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let output = tokio::process::Command::new(rustc)
+        .arg("-V")
+        .current_dir(root.as_std_path())
+        .output()
+        .await;
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8(output.stdout)
+            .ok()
+            .map(|version| version.trim().to_string()),
+        Ok(output) => {
+            debug!(status = ?output.status, "rustc -V failed");
+            None
+        }
+        Err(err) => {
+            debug!(?err, "run rustc -V");
+            None
+        }
+    }
+}
+
+/// Hash the hostname so that units can be correlated by builder without
+/// revealing machine names to everyone with access to the cache.
+fn hash_hostname(host: &str) -> String {
+    let mut hasher = KeyHasher::derive(HashAlgorithm::Blake3, HOSTNAME_HASH_CONTEXT);
+    hasher.update(host);
+    hasher.finalize().to_hex()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::{HOSTNAME_HASH_CONTEXT, hash_hostname};
+
+    #[test]
+    fn hostname_hash_is_stable_and_opaque() {
+        let hash = hash_hostname("builder-01.internal");
+        pretty_assert_eq!(hash, hash_hostname("builder-01.internal"));
+        assert_ne!(hash, hash_hostname("builder-02.internal"));
+        assert!(!hash.contains("builder"));
+    }
+
+    #[test]
+    fn hostname_hash_is_blake3_derived_key() {
+        // Units saved by earlier versions are correlated by this hash, so it
+        // can't change.
+        let expected = blake3::derive_key(HOSTNAME_HASH_CONTEXT, b"builder-01.internal");
+        pretty_assert_eq!(
+            hash_hostname("builder-01.internal"),
+            blake3::Hash::from(expected).to_hex().to_string()
+        );
+    }
+}
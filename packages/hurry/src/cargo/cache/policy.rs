@@ -0,0 +1,502 @@
+//! Cost/benefit policy for deciding which units are worth uploading.
+//!
+//! Restoring a unit from the cache only pays off if downloading its artifacts
+//! is faster than rebuilding it. Some units compile in a few seconds but
+//! produce hundreds of megabytes of artifacts (large `cdylib`s and debug-heavy
+//! proc macros are the usual suspects); uploading them costs bandwidth and
+//! storage, and restoring them is often slower than just building them.
+//!
+//! The policy compares the unit's artifact size with an estimate of how long
+//! the unit took to build, and skips uploading units whose rebuild time is too
+//! small for their size.
+//!
+//! The policy also decides what happens when a unit's content differs from
+//! what the cache already stores for the same unit hash; see
+//! [`DeterminismCheck`].
+//!
+//! Crates can override the policy for their own units in their `Cargo.toml`;
+//! see [`CratePolicy`].
+
+use std::{collections::HashSet, time::Duration};
+
+use bon::Builder;
+use clap::ValueEnum;
+use color_eyre::{Result, eyre::Context as _};
+use derive_more::Display;
+use futures::TryStreamExt as _;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    cargo::{Restored, UnitHash, UnitPlan, UnitPlanInfo, Workspace},
+    fs, mk_rel_file,
+    path::{AbsDirPath, JoinWith as _},
+};
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+/// Thresholds for deciding whether a unit is worth uploading.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Builder)]
+pub struct UploadPolicy {
+    /// Units whose artifacts total at most this many bytes are always
+    /// uploaded, since they're cheap to store and restore regardless of how
+    /// quickly they build.
+    #[builder(default = UploadPolicy::DEFAULT_SIZE_FLOOR)]
+    pub size_floor: u64,
+
+    /// The minimum rebuild time, in seconds per GiB of artifacts, for a unit
+    /// above the size floor to be uploaded. Zero uploads every unit.
+    #[builder(default = UploadPolicy::DEFAULT_MIN_REBUILD_PER_GIB)]
+    pub min_rebuild_per_gib: u64,
+
+    /// Units that other units in the build depend on. These are always
+    /// uploaded: restore discards units whose dependencies aren't in the
+    /// cache, so skipping one would make its dependents useless too.
+    ///
+    /// This must be set from the units as returned by the build plan (see
+    /// [`UploadPolicy::require_dependencies`]): unit dependencies aren't
+    /// serialized, so they're not available once the units are sent to the
+    /// daemon.
+    #[builder(default)]
+    #[serde(default)]
+    pub required: HashSet<UnitHash>,
+
+    /// Whether to compare units with what the cache already stores for the
+    /// same unit hash before uploading them.
+    #[builder(default)]
+    #[serde(default)]
+    pub determinism: DeterminismCheck,
+}
+
+impl UploadPolicy {
+    /// The default size floor: 16 MiB.
+    pub const DEFAULT_SIZE_FLOOR: u64 = 16 * 1024 * 1024;
+
+    /// The default minimum rebuild time: 10 seconds per GiB.
+    ///
+    /// This roughly corresponds to restoring over a ~100 MB/s connection, at
+    /// which point downloading the artifacts takes about as long as building
+    /// them.
+    pub const DEFAULT_MIN_REBUILD_PER_GIB: u64 = 10;
+
+    /// Decide whether to upload a unit.
+    ///
+    /// `required` is whether other units depend on the unit; see
+    /// [`UploadPolicy::required`]. `big_artifacts` is what the unit's crate
+    /// asked for; see [`CratePolicy::big_artifacts`].
+    pub fn decide(
+        &self,
+        bytes: u64,
+        estimated_rebuild: Option<Duration>,
+        required: bool,
+        big_artifacts: BigArtifacts,
+    ) -> UploadReason {
+        if required {
+            return UploadReason::Required;
+        }
+        if bytes <= self.size_floor {
+            return UploadReason::Small;
+        }
+        match big_artifacts {
+            BigArtifacts::Auto => {}
+            BigArtifacts::Skip => return UploadReason::CrateSkipped,
+            BigArtifacts::Upload => return UploadReason::CrateRequested,
+        }
+        let Some(estimated_rebuild) = estimated_rebuild else {
+            return UploadReason::Unknown;
+        };
+
+        // Compare in milliseconds to avoid losing precision for fast units.
+        let rebuild = estimated_rebuild.as_millis();
+        let threshold =
+            u128::from(self.min_rebuild_per_gib) * 1000 * u128::from(bytes) / u128::from(GIB);
+        if rebuild >= threshold {
+            UploadReason::Worthwhile
+        } else {
+            UploadReason::CheaperToRebuild
+        }
+    }
+
+    /// Mark every unit that another of the provided units depends on as
+    /// required.
+    pub fn require_dependencies(mut self, units: &[UnitPlan]) -> Self {
+        self.required.extend(
+            units
+                .iter()
+                .flat_map(|unit| unit.info().deps.iter().cloned()),
+        );
+        self
+    }
+}
+
+impl Default for UploadPolicy {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// The reason a unit was or was not uploaded.
+#[derive(Debug, Display, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum UploadReason {
+    /// Other units depend on this unit.
+    #[display("required by other units")]
+    Required,
+
+    /// The unit's artifacts are under the size floor.
+    #[display("small artifacts")]
+    Small,
+
+    /// The unit's rebuild time couldn't be estimated, so it's uploaded to be
+    /// safe.
+    #[display("unknown rebuild time")]
+    Unknown,
+
+    /// The unit takes long enough to rebuild to justify its size.
+    #[display("worth caching")]
+    Worthwhile,
+
+    /// The unit rebuilds faster than it's likely to download.
+    #[display("cheaper to rebuild than download")]
+    CheaperToRebuild,
+
+    /// The unit's crate asked for its large units to always be uploaded.
+    #[display("requested by crate")]
+    CrateRequested,
+
+    /// The unit's crate asked for its large units not to be uploaded.
+    #[display("skipped by crate")]
+    CrateSkipped,
+}
+
+impl UploadReason {
+    /// Whether the unit should be uploaded.
+    pub fn upload(self) -> bool {
+        !matches!(
+            self,
+            UploadReason::CheaperToRebuild | UploadReason::CrateSkipped
+        )
+    }
+}
+
+/// Cache settings that a crate declares for its own units.
+///
+/// Crate authors know things about their crates that Hurry can't infer: that
+/// a build script embeds the build time, or that a crate's artifacts are huge
+/// but slow to build. They declare this in the crate's `Cargo.toml`, which
+/// applies wherever the crate is built without any central configuration:
+///
+/// ```toml
+/// [package.metadata.hurry]
+/// cache = false
+/// nondeterministic = true
+/// big-artifacts = "skip"
+/// ```
+///
+/// A workspace can override the policy of any crate it builds, including
+/// third-party crates, under `[crates.<name>]` in its `hurry.toml`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct CratePolicy {
+    /// Whether the crate's units are saved to and restored from the cache at
+    /// all.
+    ///
+    /// Units that depend on a crate that isn't cached can't be restored
+    /// either, unless the crate is already built locally: restore only
+    /// restores units whose dependencies are available.
+    pub cache: bool,
+
+    /// Whether the crate's builds are known to be nondeterministic.
+    ///
+    /// Such units are expected to differ from the cached copy, so they're
+    /// exempt from [`DeterminismCheck`].
+    pub nondeterministic: bool,
+
+    /// How to upload the crate's units whose artifacts are above the
+    /// upload size floor.
+    pub big_artifacts: BigArtifacts,
+}
+
+impl Default for CratePolicy {
+    fn default() -> Self {
+        Self {
+            cache: true,
+            nondeterministic: false,
+            big_artifacts: BigArtifacts::default(),
+        }
+    }
+}
+
+impl CratePolicy {
+    /// Parse the policy from the content of a crate's `Cargo.toml`.
+    ///
+    /// Crates without `[package.metadata.hurry]` get the default policy.
+    pub fn from_manifest(manifest: &str) -> Result<Self> {
+        #[derive(Default, Deserialize)]
+        #[serde(default)]
+        struct Manifest {
+            package: Package,
+        }
+
+        #[derive(Default, Deserialize)]
+        #[serde(default)]
+        struct Package {
+            metadata: Metadata,
+        }
+
+        #[derive(Default, Deserialize)]
+        #[serde(default)]
+        struct Metadata {
+            hurry: CratePolicy,
+        }
+
+        toml::from_str::<Manifest>(manifest)
+            .context("parse [package.metadata.hurry]")
+            .map(|manifest| manifest.package.metadata.hurry)
+    }
+
+    /// Read the policy of the crate in `package_dir`.
+    ///
+    /// A crate whose manifest can't be read or parsed gets the default policy,
+    /// since Cargo reports broken manifests itself.
+    #[instrument]
+    pub async fn read(package_dir: &AbsDirPath) -> Self {
+        let read = async {
+            let manifest = package_dir.join(mk_rel_file!("Cargo.toml"));
+            match fs::read_buffered_utf8(&manifest).await? {
+                Some(content) => Self::from_manifest(&content),
+                None => Ok(Self::default()),
+            }
+        };
+        match read.await {
+            Ok(policy) => policy,
+            Err(err) => {
+                warn!(?err, ?package_dir, "read crate cache policy");
+                Self::default()
+            }
+        }
+    }
+}
+
+/// How to upload a crate's units whose artifacts are above the upload size
+/// floor.
+#[derive(Debug, Display, Clone, Copy, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BigArtifacts {
+    /// Let the upload policy decide based on how long the unit takes to
+    /// rebuild.
+    #[default]
+    #[display("auto")]
+    Auto,
+
+    /// Don't upload them.
+    #[display("skip")]
+    Skip,
+
+    /// Always upload them.
+    #[display("upload")]
+    Upload,
+}
+
+/// What to do when a unit's content differs from what the cache already
+/// stores for the same unit hash.
+///
+/// The same unit hash should always produce the same artifacts; when it
+/// doesn't, the build is nondeterministic (for example, a build script embeds
+/// a timestamp or absolute path). Such units never match what's stored, so
+/// uploading them only churns storage: the cache keeps the first upload of
+/// each unit hash.
+#[derive(
+    Debug, Display, Clone, Copy, Eq, PartialEq, Hash, Default, Serialize, Deserialize, ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum DeterminismCheck {
+    /// Don't compare units with the cache.
+    #[default]
+    #[display("off")]
+    Off,
+
+    /// Report units whose content differs, but upload them anyway so that
+    /// their origins are recorded.
+    #[display("warn")]
+    Warn,
+
+    /// Report units whose content differs and don't upload them.
+    #[display("refuse")]
+    Refuse,
+}
+
+impl DeterminismCheck {
+    /// Whether units are compared with the cache at all.
+    pub fn enabled(self) -> bool {
+        self != DeterminismCheck::Off
+    }
+}
+
+/// The policy decision for a single unit.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct UploadDecision {
+    pub unit_hash: UnitHash,
+    pub package_name: String,
+    pub bytes: u64,
+    pub estimated_rebuild: Option<Duration>,
+    pub reason: UploadReason,
+}
+
+/// Estimate how long the unit took to build.
+///
+/// Cargo writes an `invoked.timestamp` file to the unit's fingerprint
+/// directory when it starts building the unit, and writes the fingerprint
+/// files once the unit has finished. The difference between the two is
+/// roughly the unit's build time, not counting time spent waiting on the
+/// jobserver.
+///
+/// Returns `None` if the timestamps are missing or inconsistent, which is the
+/// case for units that Cargo hasn't built itself (for example, units restored
+/// by hurry, whose fingerprints are all written at once).
+#[instrument(skip(ws))]
+pub async fn estimate_rebuild(ws: &Workspace, info: &UnitPlanInfo) -> Option<Duration> {
+    let fingerprint_dir = ws
+        .unit_profile_dir(info)
+        .join(&info.fingerprint_dir().ok()?);
+    let invoked = fingerprint_dir.join(mk_rel_file!("invoked.timestamp"));
+    let started = fs::metadata(&invoked).await.ok()??.modified().ok()?;
+
+    let files = fs::walk_files(&fingerprint_dir)
+        .try_collect::<Vec<_>>()
+        .await
+        .ok()?;
+    let mut finished = None;
+    for file in files.iter().filter(|file| **file != invoked) {
+        let modified = fs::metadata(file).await.ok()??.modified().ok()?;
+        finished = finished.max(Some(modified));
+    }
+
+    let estimate = finished?.duration_since(started).ok();
+    debug!(?estimate, "estimated rebuild time");
+    estimate
+}
+
+/// Estimate how much build time restoring the `restored` units saved.
+///
+/// Restored units don't have build timestamps (see [`estimate_rebuild`]), so
+/// this extrapolates from the units that Cargo did build: each restored unit
+/// is assumed to have taken the average time of those units.
+///
+/// Returns `None` if nothing was restored, or if Cargo didn't build any units
+/// whose rebuild time could be estimated.
+#[instrument(skip_all)]
+pub async fn estimate_time_saved(
+    ws: &Workspace,
+    units: &[UnitPlan],
+    restored: &Restored,
+) -> Option<Duration> {
+    if restored.units.is_empty() {
+        return None;
+    }
+
+    let mut built = Vec::new();
+    for unit in units {
+        let info = unit.info();
+        if restored.units.contains(&info.unit_hash) {
+            continue;
+        }
+        if let Some(estimate) = estimate_rebuild(ws, info).await {
+            built.push(estimate);
+        }
+    }
+    if built.is_empty() {
+        return None;
+    }
+
+    let average = built.iter().sum::<Duration>() / built.len() as u32;
+    let saved = average * restored.units.len() as u32;
+    debug!(?average, ?saved, "estimated time saved");
+    Some(saved)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+
+    use super::{BigArtifacts, CratePolicy, GIB, UploadPolicy, UploadReason};
+
+    #[test_case(GIB, Some(Duration::from_secs(60)), false, UploadReason::Worthwhile; "slow large unit")]
+    #[test_case(2 * GIB, Some(Duration::from_secs(3)), false, UploadReason::CheaperToRebuild; "fast large unit")]
+    #[test_case(2 * GIB, Some(Duration::from_secs(3)), true, UploadReason::Required; "required unit")]
+    #[test_case(1024, Some(Duration::ZERO), false, UploadReason::Small; "small unit")]
+    #[test_case(2 * GIB, None, false, UploadReason::Unknown; "unknown rebuild time")]
+    #[test_case(GIB, Some(Duration::from_secs(10)), false, UploadReason::Worthwhile; "at threshold")]
+    #[test]
+    fn decide(bytes: u64, rebuild: Option<Duration>, required: bool, expected: UploadReason) {
+        let policy = UploadPolicy::default();
+        pretty_assert_eq!(
+            policy.decide(bytes, rebuild, required, BigArtifacts::Auto),
+            expected
+        );
+    }
+
+    #[test]
+    fn zero_threshold_uploads_everything() {
+        let policy = UploadPolicy::builder().min_rebuild_per_gib(0).build();
+        let reason = policy.decide(2 * GIB, Some(Duration::ZERO), false, BigArtifacts::Auto);
+        assert!(reason.upload(), "{reason:?}");
+    }
+
+    #[test_case(GIB, false, BigArtifacts::Skip, UploadReason::CrateSkipped; "skip slow large unit")]
+    #[test_case(1024, false, BigArtifacts::Skip, UploadReason::Small; "skip small unit")]
+    #[test_case(GIB, true, BigArtifacts::Skip, UploadReason::Required; "skip required unit")]
+    #[test_case(2 * GIB, false, BigArtifacts::Upload, UploadReason::CrateRequested; "upload fast large unit")]
+    #[test]
+    fn decide_with_crate_policy(
+        bytes: u64,
+        required: bool,
+        big_artifacts: BigArtifacts,
+        expected: UploadReason,
+    ) {
+        let policy = UploadPolicy::default();
+        let rebuild = Some(Duration::from_secs(60));
+        pretty_assert_eq!(
+            policy.decide(bytes, rebuild, required, big_artifacts),
+            expected
+        );
+    }
+
+    #[test]
+    fn crate_policy_from_manifest() {
+        let manifest = r#"
+[package]
+name = "example"
+version = "0.1.0"
+
+[package.metadata.hurry]
+cache = false
+nondeterministic = true
+big-artifacts = "skip"
+
+[package.metadata.docs.rs]
+all-features = true
+"#;
+        pretty_assert_eq!(
+            CratePolicy::from_manifest(manifest).unwrap(),
+            CratePolicy {
+                cache: false,
+                nondeterministic: true,
+                big_artifacts: BigArtifacts::Skip,
+            }
+        );
+    }
+
+    #[test_case("[package]\nname = \"example\""; "no metadata")]
+    #[test_case("[package.metadata.hurry]\nnondeterministic = false"; "defaults")]
+    #[test_case("[workspace]\nmembers = []"; "virtual manifest")]
+    #[test]
+    fn crate_policy_defaults(manifest: &str) {
+        pretty_assert_eq!(
+            CratePolicy::from_manifest(manifest).unwrap(),
+            CratePolicy::default()
+        );
+    }
+}
@@ -1,7 +1,11 @@
 use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
-    time::{Duration, SystemTime},
+    collections::{BTreeSet, HashMap, HashSet},
+    io::IsTerminal as _,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Instant, SystemTime},
 };
 
 use color_eyre::{
@@ -16,15 +20,27 @@ use tokio::task::JoinSet;
 use tracing::{Instrument, debug, info, instrument, trace, warn};
 
 use crate::{
-    cargo::{self, Fingerprint, QualifiedPath, UnitHash, UnitPlan, Workspace, host_glibc_version},
-    cas::CourierCas,
+    cargo::{
+        self, CheckPlan, Fingerprint, QualifiedPath, UnitHash, UnitPlan, Workspace,
+        cache::{
+            handoff::RestoreHandoff,
+            timings::{self, RestoreDecision, UnitTimings},
+        },
+        host_glibc_version, near_match,
+    },
+    cas::{CourierCas, DownloadClaim, LocalCas},
+    config::{OverwritePolicy, RestoreConfig},
     fs,
-    path::JoinWith as _,
-    progress::TransferBar,
+    path::{AbsDirPath, AbsFilePath, JoinWith as _, RelativeTo as _, TryJoinWith as _},
+    progress::{TransferBar, format_size},
 };
 use clients::{
     Courier,
-    courier::v1::{Key, SavedUnit, cache::CargoRestoreRequest, cache::CargoRestoreResponse},
+    courier::v1::{
+        GlibcVersion, Key, SavedUnit, SavedUnitHash, UnitHashVersion,
+        UnitPlanInfo as SavedUnitPlanInfo,
+        cache::{CacheAsOf, CargoRestoreRequest, CargoRestoreResponse},
+    },
 };
 
 /// Tracks items that were restored from the cache.
@@ -33,6 +49,91 @@ pub struct Restored {
     /// Stores the unit hashes of restored units.
     pub units: DashSet<UnitHash>,
     pub files: DashSet<Key>,
+
+    /// Units that were left for Cargo to build because adaptive restore
+    /// estimated that they build faster than they download.
+    #[serde(default)]
+    pub skipped_by_policy: Vec<RestoreDecision>,
+
+    /// Units that couldn't be restored because the cache is missing objects
+    /// they reference. They're left for Cargo to build, and saving them
+    /// afterwards replaces the broken units in the cache.
+    #[serde(default)]
+    pub repairs: Vec<CacheRepair>,
+}
+
+/// A unit whose saved copy references objects that are missing from the
+/// cache, e.g. because they were garbage collected or lost.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CacheRepair {
+    pub unit_hash: UnitHash,
+    pub package_name: String,
+
+    /// The number of the unit's objects that are missing.
+    pub missing_objects: usize,
+}
+
+impl Restored {
+    /// Add the items restored by another restore to these.
+    pub fn extend(&mut self, other: Restored) {
+        self.units.extend(other.units);
+        self.files.extend(other.files);
+        self.skipped_by_policy.extend(other.skipped_by_policy);
+        self.repairs.extend(other.repairs);
+    }
+
+    /// Summarize the units that adaptive restore left for Cargo for the build
+    /// summary.
+    ///
+    /// Returns `None` if no units were left for Cargo.
+    pub fn policy_summary(&self) -> Option<String> {
+        if self.skipped_by_policy.is_empty() {
+            return None;
+        }
+
+        let bytes = self
+            .skipped_by_policy
+            .iter()
+            .map(|decision| decision.bytes)
+            .sum::<u64>();
+        let mut summary = format!(
+            "[hurry] Left {} units ({}) for Cargo to build, since they build faster than they download:",
+            self.skipped_by_policy.len(),
+            format_size(bytes),
+        );
+        for decision in &self.skipped_by_policy {
+            summary.push_str(&format!(
+                "\n[hurry]   {} ({}, builds in {:.1}s, downloads in ~{:.1}s)",
+                decision.package_name,
+                format_size(decision.bytes),
+                decision.build.as_secs_f64(),
+                decision.download.as_secs_f64(),
+            ));
+        }
+        Some(summary)
+    }
+
+    /// Summarize the units that couldn't be restored because the cache is
+    /// missing their objects for the build summary.
+    ///
+    /// Returns `None` if every unit's objects were found.
+    pub fn repair_summary(&self) -> Option<String> {
+        if self.repairs.is_empty() {
+            return None;
+        }
+
+        let mut summary = format!(
+            "[hurry] {} units are missing objects in the cache; Cargo builds them, and saving them repairs the cache:",
+            self.repairs.len(),
+        );
+        for repair in &self.repairs {
+            summary.push_str(&format!(
+                "\n[hurry]   {} ({} missing objects)",
+                repair.package_name, repair.missing_objects,
+            ));
+        }
+        Some(summary)
+    }
 }
 
 #[derive(Debug)]
@@ -56,19 +157,180 @@ struct FileRestoreKey {
 #[derive(Debug, Clone, Default)]
 struct RestoreProgress {
     units: Arc<DashMap<UnitHash, DashSet<Key>>>,
+
+    /// Objects that Courier reported missing.
+    missing: Arc<DashSet<Key>>,
+
+    /// Where units are handed to Cargo once they're fully restored, if Cargo
+    /// is already building.
+    handoff: Option<RestoreHandoff>,
+}
+
+/// Options for restoring units.
+#[derive(Debug, Clone, Copy)]
+pub struct RestoreOptions<'a> {
+    pub config: &'a RestoreConfig,
+
+    /// When to stop restoring, leaving the remaining units for Cargo to build.
+    pub deadline: Option<Instant>,
+
+    /// Hands units to a Cargo build that's already running as they're
+    /// restored, instead of writing their fingerprints for Cargo to find
+    /// before it starts (see the `handoff` module).
+    pub handoff: Option<&'a RestoreHandoff>,
+
+    /// Whether the build directory is on a case-insensitive file system, if
+    /// that's already known; otherwise it's probed.
+    pub case_insensitive: Option<bool>,
 }
 
+impl<'a> RestoreOptions<'a> {
+    /// Restore every unit before Cargo starts, using `config`.
+    pub fn new(config: &'a RestoreConfig) -> Self {
+        Self {
+            config,
+            deadline: None,
+            handoff: None,
+            case_insensitive: None,
+        }
+    }
+
+    /// Stop restoring at `deadline`, if any.
+    pub fn with_deadline(self, deadline: Option<Instant>) -> Self {
+        Self { deadline, ..self }
+    }
+
+    /// Hand units to Cargo through `handoff` as they're restored.
+    pub fn with_handoff(self, handoff: &'a RestoreHandoff) -> Self {
+        Self {
+            handoff: Some(handoff),
+            ..self
+        }
+    }
+
+    /// Treat the build directory as case-insensitive (or not) instead of
+    /// probing its file system.
+    pub fn with_case_insensitive(self, case_insensitive: bool) -> Self {
+        Self {
+            case_insensitive: Some(case_insensitive),
+            ..self
+        }
+    }
+}
+
+/// Restores the OUT_DIR of a build script execution all at once.
+///
+/// Build scripts often generate code or copy data files into OUT_DIR, and the
+/// crates that use them expect every file at a specific path relative to it.
+/// An OUT_DIR that's only partially restored (because the restore deadline
+/// passed or a file couldn't be downloaded) would be reused by Cargo when it
+/// reruns the build script, mixing stale files with new ones. Files are
+/// therefore restored into a staging directory next to OUT_DIR, which replaces
+/// OUT_DIR once all of them are written; if the unit isn't completely
+/// restored, the staging directory is removed and OUT_DIR is left untouched.
+#[derive(Debug, Clone)]
+struct OutDirRestore {
+    out_dir: AbsDirPath,
+    staging: AbsDirPath,
+    remaining: Arc<AtomicUsize>,
+}
+
+impl OutDirRestore {
+    /// The name of the staging directory, next to OUT_DIR in the unit's build
+    /// directory.
+    const STAGING_DIR: &str = "out.hurry-restore";
+
+    /// Start restoring `files` files into `out_dir`.
+    ///
+    /// An OUT_DIR without files is restored immediately, so that build script
+    /// executions that don't write any files still have an empty OUT_DIR.
+    async fn new(out_dir: AbsDirPath, files: usize) -> Result<Self> {
+        let staging = out_dir
+            .parent()
+            .ok_or_eyre("OUT_DIR should have parent")?
+            .try_join_dir(Self::STAGING_DIR)?;
+
+        // A previous restore may have been interrupted before cleaning up.
+        fs::remove_dir_all(&staging).await?;
+        fs::create_dir_all(&staging).await?;
+
+        let restore = Self {
+            out_dir,
+            staging,
+            remaining: Arc::new(AtomicUsize::new(files)),
+        };
+        if files == 0 {
+            restore.commit().await?;
+        }
+        Ok(restore)
+    }
+
+    /// The path in the staging directory to write the OUT_DIR file at `path`
+    /// to.
+    fn staged(&self, path: &AbsFilePath) -> Result<AbsFilePath> {
+        let relative = path
+            .relative_to(&self.out_dir)
+            .context("build script output file should be in OUT_DIR")?;
+        Ok(self.staging.join(relative))
+    }
+
+    /// Record that a file was written to the staging directory, replacing
+    /// OUT_DIR with it once it's the last one.
+    async fn file_written(&self) -> Result<()> {
+        if self.remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.commit().await?;
+        }
+        Ok(())
+    }
+
+    /// Replace OUT_DIR with the staging directory.
+    async fn commit(&self) -> Result<()> {
+        fs::remove_dir_all(&self.out_dir).await?;
+        fs::rename(&self.staging, &self.out_dir).await
+    }
+
+    /// Discard the files written so far, leaving OUT_DIR as it was.
+    async fn abandon(&self) -> Result<()> {
+        fs::remove_dir_all(&self.staging).await
+    }
+}
+
+/// Restore units from the cache.
+///
+/// If a `deadline` is provided and restore has not finished by then, the
+/// remaining units are left for Cargo to build: no further units or files are
+/// queued for restore, and units whose files were not all restored have their
+/// fingerprints removed so that Cargo doesn't trust their incomplete outputs.
+/// Because units are restored in dependency order, the units that are restored
+/// are the ones Cargo would otherwise need to build first.
+///
+/// Units are restored from an exact match if the cache has one, and otherwise
+/// from a compatible near match if the config allows any (see the
+/// `near_match` module for details).
+///
+/// With a hand-off, only library crates are restored, and instead of having
+/// their fingerprints written they're handed to the running Cargo build as
+/// their files are written; units that are incomplete are left to it rather
+/// than having their fingerprints removed.
 #[instrument(skip(units, progress))]
 pub async fn restore_units(
     courier: &Courier,
     cas: &CourierCas,
+    local: &LocalCas,
     ws: &Workspace,
     units: &Vec<UnitPlan>,
     progress: &TransferBar,
+    options: RestoreOptions<'_>,
 ) -> Result<Restored> {
     trace!(?units, "units");
+    let RestoreOptions {
+        config,
+        deadline,
+        handoff,
+        case_insensitive,
+    } = options;
 
-    let restored = Restored::default();
+    let mut restored = Restored::default();
 
     // Check which units are already on disk, and don't attempt to restore them.
     // Note that this does not attempt to check actual _freshness_, since that
@@ -113,6 +375,36 @@ pub async fn restore_units(
         }
     }
 
+    // Crates can opt out of the cache in their manifest. Units that are
+    // already on disk are kept, since their dependents can still be restored.
+    let planned_count = units.len();
+    let units = &units
+        .iter()
+        .filter(|unit| {
+            let info = unit.info();
+            info.policy.cache || units_to_skip.contains(&info.unit_hash)
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    let opted_out = planned_count - units.len();
+    if opted_out > 0 {
+        debug!(
+            opted_out,
+            "skipping units whose crates opted out of the cache"
+        );
+        progress.dec_length(opted_out as u64);
+    }
+
+    // Units are saved under hashes that include the organization's cache
+    // generation, so that bumping it invalidates every unit saved before, and
+    // the build's cache scope, so that scopes don't restore each other's units.
+    let generation = courier
+        .cargo_cache_generation()
+        .await
+        .context("get cache generation")?
+        .generation;
+    let scope = courier.cache_scope();
+
     // If this build is against glibc, we need to know the glibc version so we
     // don't restore objects that link to missing symbols.
     let host_glibc_symbol_version = host_glibc_version()?;
@@ -133,13 +425,40 @@ pub async fn restore_units(
     // on disk from the disk, which would avoid making the network request
     // larger. This would require reading the fingerprint JSON files for skipped
     // units and merging them with the network response.
+    //
+    // Each unit is requested under every restorable unit hash version, so that
+    // units saved by older versions of Hurry can still be restored while their
+    // hash version is being deprecated.
     let requested_count = units.len();
+    let versioned_hashes = units
+        .iter()
+        .map(|unit| {
+            let info = SavedUnitPlanInfo::from(unit.info().clone());
+            let hashes = UnitHashVersion::restorable_scoped(&info, generation, scope)
+                .map(|version| version.derive_scoped(&info, generation, scope))
+                .collect::<Vec<_>>();
+            (unit.info().unit_hash.clone(), hashes)
+        })
+        .collect::<Vec<_>>();
     let bulk_req = CargoRestoreRequest::new(
-        units.iter().map(|unit| unit.info().unit_hash.clone()),
-        host_glibc_symbol_version,
-    );
+        versioned_hashes
+            .iter()
+            .flat_map(|(_, hashes)| hashes.iter().cloned()),
+        host_glibc_symbol_version.clone(),
+    )
+    .with_as_of(config.as_of.clone());
     info!(requested_count, "requesting units from cache");
-    let mut saved_units = courier.cargo_cache_restore(bulk_req).await?;
+    let mut response = courier.cargo_cache_restore(bulk_req).await?;
+
+    // Key the saved units by their Cargo unit hash, preferring the most recent
+    // hash version if a unit was saved under more than one.
+    let mut saved_units = versioned_hashes
+        .iter()
+        .filter_map(|(unit_hash, hashes)| {
+            let unit = hashes.iter().find_map(|hash| response.take(hash))?;
+            Some((SavedUnitHash::from(unit_hash), unit))
+        })
+        .collect::<CargoRestoreResponse>();
     info!(
         requested_count,
         returned_count = saved_units.len(),
@@ -163,8 +482,53 @@ pub async fn restore_units(
         );
     }
 
+    // Units that aren't cached exactly fall back to near matches, if the user
+    // has opted in to any.
+    let mut near_matches = if config.near_match_features.is_empty() {
+        CargoRestoreResponse::default()
+    } else {
+        request_near_matches(
+            courier,
+            units,
+            &saved_units,
+            &units_to_skip,
+            &units_with_incomplete_deps,
+            host_glibc_symbol_version,
+            config.as_of.clone(),
+        )
+        .await?
+    };
+
+    // Units that are estimated to build faster than they download are left
+    // for Cargo, if adaptive restore is enabled.
+    let timings = match config.adaptive.enabled {
+        true => Some(UnitTimings::load(ws).await),
+        false => None,
+    };
+    let mut cheaper_to_build = match &timings {
+        Some(timings) => {
+            let restorable = saved_units
+                .iter()
+                .map(|(hash, _)| UnitHash::from(hash.as_str()))
+                .filter(|hash| {
+                    !units_with_incomplete_deps.contains(hash) && !units_to_skip.contains(hash)
+                })
+                .collect::<HashSet<_>>();
+            let available = restorable
+                .iter()
+                .chain(&units_to_skip)
+                .cloned()
+                .collect::<HashSet<_>>();
+            timings::cheaper_to_build(ws, units, &restorable, &available, timings, config.adaptive)
+        }
+        None => HashMap::new(),
+    };
+
     // Track restore progress.
-    let restore_progress = RestoreProgress::default();
+    let restore_progress = RestoreProgress {
+        handoff: handoff.cloned(),
+        ..RestoreProgress::default()
+    };
 
     // Spawn concurrent workers for doing parallel downloads.
     let (tx, mut workers) = {
@@ -181,12 +545,22 @@ pub async fn restore_units(
         for worker_id in 0..worker_count {
             let rx = rx.clone();
             let cas = cas.clone();
+            let local = local.clone();
             let progress = progress.clone();
             let restored = restored.clone();
             let restore_progress = restore_progress.clone();
             let span = tracing::info_span!("restore_worker", worker_id);
             workers.spawn(
-                restore_worker(rx, cas, progress, restored, restore_progress).instrument(span),
+                restore_worker(
+                    rx,
+                    cas,
+                    local,
+                    progress,
+                    restored,
+                    restore_progress,
+                    deadline,
+                )
+                .instrument(span),
             );
         }
         // Dropping the `rx` causes it to close, so we cannot drop it until all
@@ -196,6 +570,7 @@ pub async fn restore_units(
 
     let mut dep_fingerprints = HashMap::new();
     let mut files_to_restore = Vec::<FileRestoreKey>::new();
+    let mut out_dirs = HashMap::<UnitHash, OutDirRestore>::new();
     // We anchor the starting mtime to the Unix epoch to avoid dirtying
     // first-party package builds in multi-package workspaces when we restore
     // dependencies.
@@ -222,11 +597,59 @@ pub async fn restore_units(
     // workspace packages as well, all of this logic needs to change (either by
     // setting all mtimes or by building some sort of constrained-graph mtime
     // solver).
-    let starting_mtime = SystemTime::UNIX_EPOCH;
+    //
+    // File systems that can't store 1970 (e.g. FAT, which starts at 1980)
+    // clamp every restored mtime to the same value, so we start at the
+    // earliest mtime the build directory's file system can store instead.
+    fs::create_dir_all(&ws.build_dir).await?;
+    let mtimes = fs::mtime_support(&ws.build_dir).await?;
+    let starting_mtime = mtimes.normalize(SystemTime::UNIX_EPOCH);
+
+    // First-party units are the exception: Cargo compares their outputs'
+    // mtimes against their source files' mtimes, which are set whenever the
+    // workspace is checked out. Restored first-party units therefore get the
+    // current time, which is after both their sources and every restored
+    // third-party unit. They all get the same time so that they stay fresh
+    // with respect to each other, since Cargo only considers a unit stale if
+    // a dependency is strictly newer than it.
+    //
+    // "Now" is by the file system's clock: on a network file system whose
+    // server is ahead of this machine, sources edited there would otherwise
+    // look newer than the units we restore, and Cargo would rebuild them.
+    let first_party_mtime = mtimes.normalize(mtimes.now());
+
+    // On case-insensitive file systems, files whose paths differ only in case
+    // are the same file, so restoring both would silently overwrite one with
+    // the other. We track the case-folded paths of restored files so that we
+    // can detect this and leave colliding units for Cargo to build instead.
+    // Units are likewise declined if restoring them would overwrite local
+    // files that the overwrite policy protects.
+    let case_insensitive = match case_insensitive {
+        Some(case_insensitive) => case_insensitive,
+        None => fs::is_case_insensitive(&ws.build_dir).await?,
+    };
+    let mut restored_paths = if case_insensitive {
+        debug!("build directory is on a case-insensitive file system");
+        Some(HashMap::new())
+    } else {
+        None
+    };
+    let mut declined_units = HashSet::<UnitHash>::new();
+
     // Shared references to clone once here instead of cloning once per unit.
     let ws = Arc::new(ws.clone());
 
     for (i, unit) in units.iter().enumerate() {
+        if deadline_passed(deadline) {
+            let remaining = units.len() - i;
+            warn!(
+                remaining,
+                "restore deadline passed, leaving remaining units to cargo"
+            );
+            progress.dec_length(remaining as u64);
+            break;
+        }
+
         debug!(?unit, "queuing unit restore");
         let unit_hash = &unit.info().unit_hash;
 
@@ -237,20 +660,49 @@ pub async fn restore_units(
         // increment this mtime for every unit we see (since units are in
         // dependency order).
         //
-        // We use a 1s increment here so that mtimes are still correctly set on
-        // filesystems with low timestamp precision. For reference, see Cargo's
-        // timestamp comparison logic.[^1]
+        // We use an increment of at least 1s (2s on FAT) so that mtimes are
+        // still correctly set on filesystems with low timestamp precision. For
+        // reference, see Cargo's timestamp comparison logic.[^1]
         //
         // [^1]: https://github.com/rust-lang/cargo/blob/c24e1064277fe51ab72011e2612e556ac56addf7/src/cargo/core/compiler/fingerprint/mod.rs#L1229-L1235
-        let mtime = starting_mtime + Duration::from_secs(i as u64);
+        let first_party = unit.info().source_hash.is_some();
+        let mtime = if first_party {
+            first_party_mtime
+        } else {
+            starting_mtime + mtimes.step() * i as u32
+        };
 
         if units_with_incomplete_deps.contains(unit_hash) {
             progress.dec_length(1);
             continue;
         }
 
-        // Load the saved file info from the response.
-        let Some(saved) = saved_units.take(&unit_hash.into()) else {
+        // Nothing being restored depends on units that are cheaper to build,
+        // but units restored from near matches might; they're declined so
+        // that those are left for Cargo too.
+        if let Some(decision) = cheaper_to_build.remove(unit_hash) {
+            declined_units.insert(unit_hash.clone());
+            restored.skipped_by_policy.push(decision);
+            progress.dec_length(1);
+            continue;
+        }
+
+        // Load the saved file info from the response, falling back to a near
+        // match if the unit isn't cached exactly. Near matches are restored
+        // under the local unit hash, so we track the hash they were saved
+        // under in order to rename their files.
+        let exact = saved_units.take(&unit_hash.into());
+        let near_match = match exact {
+            Some(_) => None,
+            None => take_near_match(
+                unit,
+                &mut near_matches,
+                &config.near_match_features,
+                &dep_fingerprints,
+            ),
+        };
+        let near_match_hash = near_match.as_ref().map(|saved| saved.unit_hash().clone());
+        let Some(saved) = exact.or(near_match) else {
             // Units may be missing from the cache response for various reasons:
             // - The unit was never uploaded (cache miss)
             // - The unit was evicted from the cache
@@ -266,8 +718,10 @@ pub async fn restore_units(
             // Even when skipped, unit mtimes must be updated to maintain the
             // invariant that dependencies always have older mtimes than their
             // dependents. Otherwise, units that are skipped may have mtimes
-            // that are out of sync with units that are restored.
+            // that are out of sync with units that are restored. First-party
+            // units are left as Cargo built them.
             if units_to_skip.contains(unit_hash)
+                && !first_party
                 && let Err(err) = unit.touch(&ws, starting_mtime).await
             {
                 warn!(?unit_hash, ?err, "could not set mtime for skipped unit");
@@ -275,12 +729,23 @@ pub async fn restore_units(
             progress.dec_length(1);
             continue;
         };
+        let adopted = adopt_fingerprint_hash(unit, &saved);
+        let unit = adopted.as_ref().unwrap_or(unit);
 
         // Parse the cached fingerprint from the saved unit. This is needed for
         // both skipped units (to record the mapping) and restored units (to
         // rewrite dependencies).
         let cached_fingerprint = saved.fingerprint().as_str();
-        let cached_fingerprint = serde_json::from_str::<Fingerprint>(cached_fingerprint)?;
+        let mut cached_fingerprint = serde_json::from_str::<Fingerprint>(cached_fingerprint)?;
+        if let (Some(from), UnitPlan::LibraryCrate(plan)) = (&near_match_hash, unit) {
+            debug!(
+                ?unit_hash,
+                %from,
+                pkg_name = %unit.info().package_name,
+                "restoring near match"
+            );
+            cached_fingerprint.set_features(&plan.features);
+        }
 
         // Handle skipped units that have been uploaded to cache.
         //
@@ -323,13 +788,58 @@ pub async fn restore_units(
 
             dep_fingerprints.insert(cached_hash, local);
 
-            if let Err(err) = unit.touch(&ws, mtime).await {
+            if !first_party && let Err(err) = unit.touch(&ws, mtime).await {
                 warn!(?unit_hash, ?err, "could not set mtime for skipped unit");
             }
             progress.dec_length(1);
             continue;
         }
 
+        // Units that depend on a unit we declined to restore can't have their
+        // fingerprints rewritten, so they're left for Cargo to build too.
+        if unit
+            .info()
+            .deps
+            .iter()
+            .any(|dep| declined_units.contains(dep))
+        {
+            debug!(?unit_hash, "skipping unit: depends on a declined unit");
+            declined_units.insert(unit_hash.clone());
+            progress.dec_length(1);
+            continue;
+        }
+        if let Some(restored_paths) = restored_paths.as_mut() {
+            let paths = saved_file_paths(&saved, &ws, unit.info())?;
+            if let Some((path, existing)) = record_case_folded_paths(restored_paths, &paths) {
+                warn!(
+                    ?unit_hash,
+                    pkg_name = %unit.info().package_name,
+                    ?path,
+                    ?existing,
+                    "skipping unit: file path collides with another path on case-insensitive file system"
+                );
+                declined_units.insert(unit_hash.clone());
+                progress.dec_length(1);
+                continue;
+            }
+        }
+        if !overwrite_allowed(config.overwrite, unit, &saved, &ws, mtime, progress).await? {
+            declined_units.insert(unit_hash.clone());
+            progress.dec_length(1);
+            continue;
+        }
+
+        // Only library crates can be handed to Cargo, and only until Cargo
+        // starts compiling them itself.
+        if let Some(handoff) = handoff
+            && !(matches!(unit, UnitPlan::LibraryCrate(_)) && handoff.start(&ws, unit)?)
+        {
+            debug!(?unit_hash, "skipping unit: left for cargo to compile");
+            declined_units.insert(unit_hash.clone());
+            progress.dec_length(1);
+            continue;
+        }
+
         // Handle restored unit fingerprints. These are written synchronously
         // during the loop because they need to be processed in dependency
         // order, since a unit's fingerprint depends on its dependencies'
@@ -343,23 +853,32 @@ pub async fn restore_units(
         // TODO: Maybe instead of this whole fingerprint-rewriting song and
         // dance, we should just fork and/or upstream relocatable fingerprints
         // into Cargo.
-        let info = unit.info();
-        let src_path = unit.src_path().map(|p| p.into());
-        let rewritten_fingerprint = cached_fingerprint.rewrite(src_path, &mut dep_fingerprints)?;
-        let fingerprint_hash = rewritten_fingerprint.fingerprint_hash();
-
-        // Write the rewritten fingerprint.
-        let profile_dir = ws.unit_profile_dir(info);
-        fs::write(
-            &profile_dir.join(&unit.fingerprint_hash_file()?),
-            fingerprint_hash,
-        )
-        .await?;
-        fs::write(
-            &profile_dir.join(&unit.fingerprint_json_file()?),
-            serde_json::to_vec(&rewritten_fingerprint)?,
-        )
-        .await?;
+        //
+        // Units handed off to Cargo have their fingerprints written by Cargo
+        // once it has taken them, like the units it compiles.
+        if handoff.is_none() {
+            let info = unit.info();
+            // Cargo fingerprints first-party sources by their path relative
+            // to the workspace root, so their paths don't need to be
+            // rewritten.
+            let src_path = unit.src_path().filter(|_| !first_party).map(|p| p.into());
+            let rewritten_fingerprint =
+                cached_fingerprint.rewrite(src_path, &mut dep_fingerprints)?;
+            let fingerprint_hash = rewritten_fingerprint.fingerprint_hash();
+
+            // Write the rewritten fingerprint.
+            let profile_dir = ws.unit_profile_dir(info);
+            fs::write(
+                &profile_dir.join(&unit.fingerprint_hash_file()?),
+                fingerprint_hash,
+            )
+            .await?;
+            fs::write(
+                &profile_dir.join(&unit.fingerprint_json_file()?),
+                serde_json::to_vec(&rewritten_fingerprint)?,
+            )
+            .await?;
+        }
 
         // Mark the unit's restore as pending.
         restore_progress
@@ -378,7 +897,7 @@ pub async fn restore_units(
                     pkg_name = %unit_plan.info.package_name,
                     unit_hash = %unit_plan.info.unit_hash,
                     deps_dir = %unit_plan.info.deps_dir()?,
-                    fingerprint_dir = %unit_plan.info.fingerprint_dir()?,
+                    fingerprint_dir = %unit_plan.fingerprint_dir()?,
                     num_output_files = saved_library_files.output_files.len(),
                     "restoring library crate unit"
                 );
@@ -387,6 +906,10 @@ pub async fn restore_units(
                 for file in saved_library_files.output_files {
                     let path: QualifiedPath = serde_json::from_str(file.path.as_str())?;
                     let path = path.reconstruct(&ws, &unit_plan.info).try_into()?;
+                    let path = match &near_match_hash {
+                        Some(from) => rename_near_match_file(path, from, unit_hash)?,
+                        None => path,
+                    };
                     let executable = file.executable;
 
                     restore_progress
@@ -560,15 +1083,20 @@ pub async fn restore_units(
                     "restoring build script execution unit"
                 );
 
-                // Create the OUT_DIR directory explicitly. This way, build
-                // script execution units that have no OUT_DIR files will still
-                // correctly have an empty OUT_DIR folder.
-                fs::create_dir_all(&out_dir_absolute).await?;
+                // OUT_DIR is restored all at once (see `OutDirRestore`).
+                let out_dir_restore = OutDirRestore::new(
+                    out_dir_absolute.clone(),
+                    build_script_output_files.out_dir_files.len(),
+                )
+                .await?;
+                out_dirs.insert(unit_hash.clone(), out_dir_restore.clone());
 
                 // Queue all OUT_DIR files with executable flag handling.
                 for file in build_script_output_files.out_dir_files {
                     let path: QualifiedPath = serde_json::from_str(file.path.as_str())?;
                     let path = path.reconstruct(&ws, &unit_plan.info).try_into()?;
+                    let staged = out_dir_restore.staged(&path)?;
+                    let out_dir_restore = out_dir_restore.clone();
                     let executable = file.executable;
 
                     // Log each OUT_DIR file being restored (helpful for debugging
@@ -593,10 +1121,10 @@ pub async fn restore_units(
                         write: Box::new(move |data| {
                             let data = data.clone();
                             Box::pin(async move {
-                                fs::write(&path, data).await?;
-                                fs::set_executable(&path, executable).await?;
-                                fs::set_mtime(&path, mtime).await?;
-                                Ok(())
+                                fs::write(&staged, data).await?;
+                                fs::set_executable(&staged, executable).await?;
+                                fs::set_mtime(&staged, mtime).await?;
+                                out_dir_restore.file_written().await
                             })
                         }),
                     });
@@ -672,9 +1200,22 @@ pub async fn restore_units(
         // the restore doesn't succeed.
         debug!(?unit, "marking unit as restored after restoring");
         restored.units.insert(unit_hash.clone());
+
+        // Cargo may be waiting for units that are handed off, so their files
+        // are restored as soon as they're queued.
+        if handoff.is_some() {
+            for file in files_to_restore.drain(..) {
+                tx.send_async(file).await?;
+            }
+        }
+    }
+
+    if let Some(handoff) = handoff {
+        handoff.queued();
     }
 
     debug!("start sending files to restore workers");
+    let download_started = (Instant::now(), progress.bytes());
     for file in files_to_restore {
         tx.send_async(file).await?;
     }
@@ -689,19 +1230,333 @@ pub async fn restore_units(
     }
     debug!("done joining restore workers");
 
+    if let Some(mut timings) = timings {
+        let (started, bytes) = download_started;
+        timings.record_throughput(progress.bytes().saturating_sub(bytes), started.elapsed());
+        if let Err(err) = timings.save(&ws).await {
+            warn!(?err, "failed to save unit timings");
+        }
+    }
+
+    // If the deadline passed while files were still being restored, some units
+    // are only partially restored. Remove their fingerprints so that Cargo
+    // rebuilds them instead of treating them as fresh.
+    let incomplete = units
+        .iter()
+        .filter(|unit| {
+            restore_progress
+                .units
+                .get(&unit.info().unit_hash)
+                .is_some_and(|pending| !pending.is_empty())
+        })
+        .collect::<Vec<_>>();
+    // Units are also incomplete if Courier is missing some of their objects.
+    // They're built by Cargo like units that the deadline cut off, and
+    // recorded so that saving them replaces the broken units in the cache.
+    for unit in &incomplete {
+        let info = unit.info();
+        let missing_objects = restore_progress
+            .units
+            .get(&info.unit_hash)
+            .map(|pending| {
+                pending
+                    .iter()
+                    .filter(|key| restore_progress.missing.contains(key.key()))
+                    .count()
+            })
+            .unwrap_or_default();
+        if missing_objects > 0 {
+            warn!(
+                unit_hash = %info.unit_hash,
+                pkg_name = %info.package_name,
+                missing_objects,
+                "cache is missing objects of unit, leaving it for cargo to build"
+            );
+            restored.repairs.push(CacheRepair {
+                unit_hash: info.unit_hash.clone(),
+                package_name: info.package_name.clone(),
+                missing_objects,
+            });
+        }
+    }
+    let cut_off = incomplete.len() - restored.repairs.len();
+    if cut_off > 0 {
+        warn!(
+            incomplete_count = cut_off,
+            "restore deadline passed, removing fingerprints of partially restored units"
+        );
+    }
+    for unit in incomplete {
+        if let Some(out_dir) = out_dirs.get(&unit.info().unit_hash) {
+            out_dir.abandon().await?;
+        }
+        match handoff {
+            Some(handoff) => handoff.abandoned(&unit.info().unit_hash),
+            None => {
+                let profile_dir = ws.unit_profile_dir(unit.info());
+                fs::remove_file(&profile_dir.join(&unit.fingerprint_hash_file()?)).await?;
+                fs::remove_file(&profile_dir.join(&unit.fingerprint_json_file()?)).await?;
+            }
+        }
+        restored.units.remove(&unit.info().unit_hash);
+        progress.dec_length(1);
+    }
+
     Ok(restored)
 }
 
+/// Download the objects of any saved units among `units` into the local CAS
+/// without restoring them, returning the number of objects fetched.
+///
+/// Restoring checks the local CAS before downloading, so this lets several
+/// restores that share units (e.g. of different profiles, or of a package
+/// that's about to be built) download each object only once.
+#[instrument(skip_all)]
+pub async fn prefetch_units(
+    courier: &Courier,
+    cas: &CourierCas,
+    local: &LocalCas,
+    units: impl IntoIterator<Item = &UnitPlan>,
+) -> Result<usize> {
+    let generation = courier
+        .cargo_cache_generation()
+        .await
+        .context("get cache generation")?
+        .generation;
+    let scope = courier.cache_scope();
+    let hashes = units
+        .into_iter()
+        .flat_map(|unit| {
+            let info = SavedUnitPlanInfo::from(unit.info().clone());
+            UnitHashVersion::restorable_scoped(&info, generation, scope)
+                .map(|version| version.derive_scoped(&info, generation, scope))
+                .collect::<Vec<_>>()
+        })
+        .collect::<HashSet<_>>();
+    if hashes.is_empty() {
+        return Ok(0);
+    }
+    let request = CargoRestoreRequest::new(hashes, host_glibc_version()?);
+    let response = courier.cargo_cache_restore(request).await?;
+
+    let mut keys = HashSet::<Key>::new();
+    for (_, unit) in response.iter() {
+        for key in unit.object_keys() {
+            if !local.exists(key).await? {
+                keys.insert(key.clone());
+            }
+        }
+    }
+    debug!(
+        missing = keys.len(),
+        "fetching objects missing from local CAS"
+    );
+    if keys.is_empty() {
+        return Ok(0);
+    }
+
+    let mut count = 0;
+    fetch_shared(cas, local, keys, async |_, _| {
+        count += 1;
+        Ok(())
+    })
+    .await?;
+    local.trim().await;
+    Ok(count)
+}
+
+/// Find which of the units the remote cache can restore, returning their unit
+/// hashes.
+#[instrument(skip_all)]
+pub async fn restorable_units(
+    courier: &Courier,
+    units: impl IntoIterator<Item = &UnitPlan>,
+) -> Result<HashSet<UnitHash>> {
+    let generation = courier
+        .cargo_cache_generation()
+        .await
+        .context("get cache generation")?
+        .generation;
+    let scope = courier.cache_scope();
+    let mut hashes = HashMap::new();
+    for unit in units {
+        let info = SavedUnitPlanInfo::from(unit.info().clone());
+        for version in UnitHashVersion::restorable_scoped(&info, generation, scope) {
+            let saved = version.derive_scoped(&info, generation, scope);
+            hashes.insert(saved, unit.info().unit_hash.clone());
+        }
+    }
+    if hashes.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let request = CargoRestoreRequest::new(hashes.keys().cloned(), host_glibc_version()?);
+    let response = courier.cargo_cache_restore(request).await?;
+    Ok(response
+        .iter()
+        .filter_map(|(hash, _)| hashes.get(hash).cloned())
+        .collect())
+}
+
+/// Look up the unit hashes that the checked units of a check plan were saved
+/// under, keyed by their build-mode unit hashes.
+///
+/// Checked units are saved under their check key in place of a near-match
+/// key (see `CheckPlan`), so this looks them up the same way near matches are
+/// looked up.
+#[instrument(skip_all)]
+pub async fn resolve_checked_units(
+    courier: &Courier,
+    plan: &CheckPlan,
+) -> Result<HashMap<UnitHash, UnitHash>> {
+    let keys = plan.check_keys();
+    if keys.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let request = CargoRestoreRequest::new(Vec::<SavedUnitHash>::new(), host_glibc_version()?)
+        .with_near_match_keys(keys.keys().cloned())
+        .with_cache_scope(courier.cache_scope().cloned());
+    let mut response = courier.cargo_cache_restore(request).await?;
+    let hashes = keys
+        .into_iter()
+        .filter_map(|(key, unit_hash)| {
+            let saved = response.take_near_matches(&key).into_iter().next()?;
+            Some((unit_hash, UnitHash::from(saved.unit_hash().as_str())))
+        })
+        .collect::<HashMap<_, _>>();
+    info!(
+        checked_count = plan.checked.len(),
+        resolved_count = hashes.len(),
+        "resolved checked units"
+    );
+    Ok(hashes)
+}
+
+/// What to do with a local file that restoring a unit would overwrite.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum OverwriteDecision {
+    Overwrite,
+    Keep,
+    Ask,
+}
+
+impl OverwriteDecision {
+    /// Decide what to do with a local file last modified at `local`, which
+    /// restore would replace with a file modified at `restored`.
+    fn new(policy: OverwritePolicy, local: SystemTime, restored: SystemTime) -> Self {
+        let newer = local > restored;
+        match policy {
+            OverwritePolicy::Always => Self::Overwrite,
+            OverwritePolicy::Never => Self::Keep,
+            OverwritePolicy::IfOlder if newer => Self::Keep,
+            OverwritePolicy::Prompt if newer => Self::Ask,
+            OverwritePolicy::IfOlder | OverwritePolicy::Prompt => Self::Overwrite,
+        }
+    }
+}
+
+/// Whether the overwrite policy allows restoring `saved` over the local files
+/// of `unit`, which would be restored with the modification time `mtime`.
+///
+/// The decision for each local file is logged. A unit is only restored if all
+/// of its local files may be overwritten, since its cached files are only
+/// restored together.
+async fn overwrite_allowed(
+    policy: OverwritePolicy,
+    unit: &UnitPlan,
+    saved: &SavedUnit,
+    ws: &Workspace,
+    mtime: SystemTime,
+    progress: &TransferBar,
+) -> Result<bool> {
+    if policy == OverwritePolicy::Always {
+        return Ok(true);
+    }
+
+    let info = unit.info();
+    let mut paths = saved_file_paths(saved, ws, info)?;
+    if let UnitPlan::BuildScriptCompilation(plan) = unit {
+        paths.push(ws.unit_profile_dir(info).join(plan.program_file()?));
+    }
+
+    let mut kept = 0usize;
+    let mut ask = 0usize;
+    for path in paths {
+        let Some(metadata) = fs::metadata(path.as_std_path()).await? else {
+            continue;
+        };
+        let local = metadata.modified().context("read mtime")?;
+        let decision = OverwriteDecision::new(policy, local, mtime);
+        match decision {
+            OverwriteDecision::Overwrite => debug!(?path, ?decision, "overwriting local file"),
+            OverwriteDecision::Keep => {
+                info!(?path, ?decision, "keeping newer local file");
+                kept += 1;
+            }
+            OverwriteDecision::Ask => ask += 1,
+        }
+    }
+
+    if kept > 0 {
+        warn!(
+            unit_hash = %info.unit_hash,
+            pkg_name = %info.package_name,
+            kept,
+            "skipping unit: restoring it would overwrite local files"
+        );
+        return Ok(false);
+    }
+    if ask == 0 {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        warn!(
+            unit_hash = %info.unit_hash,
+            pkg_name = %info.package_name,
+            ask,
+            "skipping unit: can't ask whether to overwrite newer local files"
+        );
+        return Ok(false);
+    }
+    let message = format!(
+        "Restore {} {} from the cache, overwriting {ask} newer local file(s)?",
+        info.package_name, info.package_version
+    );
+    let confirmed =
+        progress.suspend(|| inquire::Confirm::new(&message).with_default(false).prompt())?;
+    info!(
+        unit_hash = %info.unit_hash,
+        pkg_name = %info.package_name,
+        confirmed,
+        "asked whether to overwrite newer local files"
+    );
+    Ok(confirmed)
+}
+
+fn deadline_passed(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
 async fn restore_worker(
     rx: flume::Receiver<FileRestoreKey>,
     cas: CourierCas,
+    local: LocalCas,
     progress: TransferBar,
     restored: Restored,
     restore_progress: RestoreProgress,
+    deadline: Option<Instant>,
 ) -> Result<()> {
     const BATCH_SIZE: usize = 50;
     let mut batch = Vec::new();
     while let Ok(file) = rx.recv_async().await {
+        // Files left in the channel once the deadline has passed are dropped
+        // along with the receiver; their units are cleaned up after the
+        // workers are joined.
+        if deadline_passed(deadline) {
+            debug!("restore deadline passed, worker stopping");
+            return Ok(());
+        }
+
         debug!(?file, "worker got file");
 
         // Add the file to the batch.
@@ -719,6 +1574,7 @@ async fn restore_worker(
         restore_batch(
             batch_to_restore,
             &cas,
+            &local,
             &progress,
             &restored,
             &restore_progress,
@@ -731,7 +1587,7 @@ async fn restore_worker(
     // remaining. Restore the remaining files in the batch.
     if !batch.is_empty() {
         debug!(?batch, "restoring remaining batch");
-        restore_batch(batch, &cas, &progress, &restored, &restore_progress).await?;
+        restore_batch(batch, &cas, &local, &progress, &restored, &restore_progress).await?;
         debug!("done restoring remaining batch");
     }
 
@@ -742,6 +1598,7 @@ async fn restore_worker(
 async fn restore_batch(
     batch: Vec<FileRestoreKey>,
     cas: &CourierCas,
+    local: &LocalCas,
     progress: &TransferBar,
     restored: &Restored,
     restore_progress: &RestoreProgress,
@@ -758,58 +1615,382 @@ async fn restore_batch(
             .push(file);
     }
 
+    // Restore files whose content is already in the local CAS (e.g. because it
+    // was prefetched) without downloading it again.
+    let mut local_hits = Vec::new();
+    for key in key_to_files.keys() {
+        match local.get(key).await {
+            Ok(Some(data)) => local_hits.push((key.clone(), data)),
+            Ok(None) => {}
+            Err(error) => warn!(?key, ?error, "failed to read file from local CAS"),
+        }
+    }
+    debug!(
+        local_hits = local_hits.len(),
+        "restoring files from local CAS"
+    );
+    for (key, data) in local_hits {
+        let files = key_to_files
+            .remove(&key)
+            .ok_or_eyre("unrecognized key from local CAS")?;
+        restore_files(files, &key, &data, progress, restored, restore_progress).await?;
+    }
+    if key_to_files.is_empty() {
+        return Ok(());
+    }
+
     // Now that keys are deduplicated, we can send them to the CAS; this way we
     // avoid making the server send multiple copies of the same file content.
     let keys = key_to_files.keys().cloned().collect::<Vec<_>>();
 
-    // For each streamed CAS key, restore the file to the local filesystem.
+    // For each fetched CAS key, restore the file to the local filesystem.
     debug!(?keys, "start fetching files from CAS");
-    let mut res = cas.get_bulk(keys).await?;
-    debug!("start streaming response from CAS");
-    while let Some(result) = res.next().await {
+    let missing = fetch_shared(cas, local, keys, async |key, data| {
+        let files = key_to_files
+            .remove(&key)
+            .ok_or_eyre("unrecognized key from CAS bulk response")?;
+        restore_files(files, &key, &data, progress, restored, restore_progress).await
+    })
+    .await?;
+    debug!("done fetching files from CAS");
+    for key in missing {
+        warn!(?key, "object missing from CAS");
+        restore_progress.missing.insert(key);
+    }
+
+    Ok(())
+}
+
+/// Download objects into the local CAS, calling `on_object` with each one.
+///
+/// Downloads are shared with other processes on this machine: objects that
+/// another process is already downloading are waited for and then read from
+/// the local CAS instead of downloaded again. This process finishes its own
+/// downloads before waiting on anyone else's, so that two processes can't end
+/// up waiting on each other.
+///
+/// Returns the keys of the objects that Courier doesn't have.
+#[instrument(skip_all)]
+async fn fetch_shared(
+    cas: &CourierCas,
+    local: &LocalCas,
+    keys: impl IntoIterator<Item = Key>,
+    mut on_object: impl AsyncFnMut(Key, Vec<u8>) -> Result<()>,
+) -> Result<HashSet<Key>> {
+    let mut owned = Vec::new();
+    let mut locks = HashMap::new();
+    let mut busy = Vec::new();
+    for key in keys {
+        match local.claim_download(&key).await {
+            DownloadClaim::Owned(lock) => {
+                if let Some(lock) = lock {
+                    locks.insert(key.clone(), lock);
+                }
+                owned.push(key);
+            }
+            DownloadClaim::Busy(lock) => busy.push((key, lock)),
+        }
+    }
+    debug!(owned = owned.len(), busy = busy.len(), "claimed downloads");
+    let mut missing = fetch(cas, local, owned, &mut locks, &mut on_object).await?;
+
+    // Objects that failed to download are left unclaimed for other processes
+    // to try.
+    drop(locks);
+
+    let mut unfinished = Vec::new();
+    for (key, lock) in busy {
+        match local.wait_for_download(&key, lock).await {
+            Some(data) => on_object(key, data).await?,
+            None => unfinished.push(key),
+        }
+    }
+    missing.extend(fetch(cas, local, unfinished, &mut HashMap::new(), &mut on_object).await?);
+    Ok(missing)
+}
+
+/// Download objects into the local CAS, releasing each object's claim once
+/// it's stored.
+///
+/// Courier leaves objects it doesn't have out of bulk responses, so if the
+/// response completes without errors, the objects that weren't in it are
+/// missing from the CAS; their keys are returned. If any object fails to
+/// download, nothing is reported missing, since the failure may be why the
+/// others weren't received.
+async fn fetch(
+    cas: &CourierCas,
+    local: &LocalCas,
+    keys: Vec<Key>,
+    locks: &mut HashMap<Key, fs::LockFile<fs::Locked>>,
+    on_object: &mut impl AsyncFnMut(Key, Vec<u8>) -> Result<()>,
+) -> Result<HashSet<Key>> {
+    if keys.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let mut pending = keys.iter().cloned().collect::<HashSet<_>>();
+    let mut failed = false;
+    let mut stream = cas.get_bulk(keys).await?;
+    while let Some(result) = stream.next().await {
         match result {
             Ok((key, data)) => {
                 debug!(?key, "CAS stream entry");
-                let files = key_to_files
-                    .remove(&key)
-                    .ok_or_eyre("unrecognized key from CAS bulk response")?;
-                for file in files {
-                    restored.files.insert(file.key);
-
-                    progress.add_files(1);
-                    progress.add_bytes(data.len() as u64);
-
-                    // Call the write callback to handle all file operations.
-                    debug!(?key, "calling write callback");
-                    (file.write)(&data).await?;
-                    debug!(?key, "done calling write callback");
-
-                    // Remove the key from the unit's pending keys.
-                    let pending_keys = restore_progress
-                        .units
-                        .get_mut(&file.unit_hash)
-                        .ok_or_eyre("unit hash restore progress not initialized")?;
-                    // We ignore whether the key is actually present, because
-                    // keys might be double-removed if they are present multiple
-                    // times in the same unit, which can occur if a unit has two
-                    // files that have the same contents (e.g. are both empty).
-                    pending_keys.remove(&key);
-                    if pending_keys.is_empty() {
-                        debug!(?file.unit_hash, "unit has been fully restored");
-                        progress.inc(1);
-                    }
+                if let Err(error) = local.store(&key, &data).await {
+                    warn!(?key, ?error, "failed to store file in local CAS");
                 }
+                locks.remove(&key);
+                pending.remove(&key);
+                on_object(key, data).await?;
             }
             Err(error) => {
                 warn!(?error, "failed to fetch file from CAS");
+                failed = true;
             }
         }
     }
-    debug!("done streaming response from CAS");
+    if failed {
+        return Ok(HashSet::new());
+    }
+    Ok(pending)
+}
+
+/// Write the content of a CAS object to each file that references it.
+async fn restore_files(
+    files: Vec<FileRestoreKey>,
+    key: &Key,
+    data: &Vec<u8>,
+    progress: &TransferBar,
+    restored: &Restored,
+    restore_progress: &RestoreProgress,
+) -> Result<()> {
+    for file in files {
+        restored.files.insert(file.key);
 
+        progress.add_files(1);
+        progress.add_bytes(data.len() as u64);
+
+        // Call the write callback to handle all file operations.
+        debug!(?key, "calling write callback");
+        (file.write)(data).await?;
+        debug!(?key, "done calling write callback");
+
+        // Remove the key from the unit's pending keys.
+        let pending_keys = restore_progress
+            .units
+            .get_mut(&file.unit_hash)
+            .ok_or_eyre("unit hash restore progress not initialized")?;
+        // We ignore whether the key is actually present, because
+        // keys might be double-removed if they are present multiple
+        // times in the same unit, which can occur if a unit has two
+        // files that have the same contents (e.g. are both empty).
+        pending_keys.remove(key);
+        if pending_keys.is_empty() {
+            debug!(?file.unit_hash, "unit has been fully restored");
+            progress.inc(1);
+            if let Some(handoff) = &restore_progress.handoff {
+                handoff.restored(&file.unit_hash);
+            }
+        }
+    }
     Ok(())
 }
 
+/// The unit with the fingerprint directory it was saved with, if its outputs
+/// are unhashed and its own fingerprint directory isn't known yet.
+///
+/// Cargo names these directories after a hash of the package that the build
+/// plan doesn't report, so a clean build directory can't tell what it is.
+/// It's the same wherever the workspace is checked out, so the saved unit's
+/// is used.
+fn adopt_fingerprint_hash(unit: &UnitPlan, saved: &SavedUnit) -> Option<UnitPlan> {
+    let (UnitPlan::LibraryCrate(plan), SavedUnit::LibraryCrate(_, saved)) = (unit, saved) else {
+        return None;
+    };
+    if !plan.unhashed_outputs || plan.fingerprint_hash.is_some() {
+        return None;
+    }
+    let mut plan = plan.clone();
+    plan.fingerprint_hash = Some(saved.fingerprint_hash.clone()?);
+    Some(UnitPlan::LibraryCrate(plan))
+}
+
+/// Collect the paths that a saved unit's arbitrarily-named files are restored
+/// to.
+///
+/// Other files are named by Cargo based on the unit hash, so they can't
+/// collide.
+fn saved_file_paths(
+    saved: &SavedUnit,
+    ws: &Workspace,
+    info: &cargo::UnitPlanInfo,
+) -> Result<Vec<AbsFilePath>> {
+    let files = match saved {
+        SavedUnit::LibraryCrate(files, _) => &files.output_files,
+        SavedUnit::BuildScriptExecution(files, _) => &files.out_dir_files,
+        SavedUnit::BuildScriptCompilation(..) => return Ok(Vec::new()),
+    };
+    files
+        .iter()
+        .map(|file| -> Result<AbsFilePath> {
+            let path = serde_json::from_str::<QualifiedPath>(file.path.as_str())?;
+            path.reconstruct(ws, info).try_into()
+        })
+        .collect()
+}
+
+/// Record the case-folded form of each path, returning the first path that
+/// collides with a different path (along with that path) if there is one.
+///
+/// If any path collides, none of the paths are recorded.
+fn record_case_folded_paths(
+    seen: &mut HashMap<String, AbsFilePath>,
+    paths: &[AbsFilePath],
+) -> Option<(AbsFilePath, AbsFilePath)> {
+    let mut folded = HashMap::<String, &AbsFilePath>::new();
+    for path in paths {
+        let key = path.as_str_lossy().to_lowercase();
+        let existing = seen.get(&key).or_else(|| folded.get(&key).copied());
+        if let Some(existing) = existing
+            && existing != path
+        {
+            return Some((path.clone(), existing.clone()));
+        }
+        folded.insert(key, path);
+    }
+    seen.extend(folded.into_iter().map(|(key, path)| (key, path.clone())));
+    None
+}
+
+/// Request near-match candidates for the library crates that aren't cached
+/// exactly.
+///
+/// Only units whose dependencies are all available are considered: others
+/// couldn't have their fingerprints rewritten even if a candidate were found.
+/// Dependents of near matches are never restored themselves, since the cached
+/// dependents were compiled against the exact unit; they're left for Cargo to
+/// build by the same incomplete dependency filtering as any other cache miss.
+#[instrument(skip_all)]
+async fn request_near_matches(
+    courier: &Courier,
+    units: &[UnitPlan],
+    saved_units: &CargoRestoreResponse,
+    units_to_skip: &HashSet<UnitHash>,
+    units_with_incomplete_deps: &HashSet<UnitHash>,
+    host_glibc_version: Option<GlibcVersion>,
+    as_of: Option<CacheAsOf>,
+) -> Result<CargoRestoreResponse> {
+    let available = |hash: &UnitHash| {
+        units_to_skip.contains(hash)
+            || (saved_units.get(&hash.into()).is_some()
+                && !units_with_incomplete_deps.contains(hash))
+    };
+    let keys = units
+        .iter()
+        .filter_map(|unit| match unit {
+            UnitPlan::LibraryCrate(plan) => Some(plan),
+            _ => None,
+        })
+        .filter(|plan| !available(&plan.info.unit_hash))
+        .filter(|plan| plan.info.deps.iter().all(available))
+        .filter_map(|plan| plan.near_match_key.clone())
+        .collect::<HashSet<_>>();
+    if keys.is_empty() {
+        return Ok(CargoRestoreResponse::default());
+    }
+
+    let requested_count = keys.len();
+    info!(requested_count, "requesting near matches from cache");
+    let request = CargoRestoreRequest::new(Vec::<SavedUnitHash>::new(), host_glibc_version)
+        .with_near_match_keys(keys)
+        .with_as_of(as_of)
+        .with_cache_scope(courier.cache_scope().cloned());
+    let response = courier.cargo_cache_restore(request).await?;
+    info!(
+        requested_count,
+        candidate_count = response.near_match_count(),
+        "cache near match response"
+    );
+    Ok(response)
+}
+
+/// Take the first near-match candidate that can stand in for the unit.
+///
+/// A candidate is compatible if its features differ from the unit's only in
+/// the `allowed` features, and if it was compiled against the same
+/// dependencies, which we check by making sure that its fingerprint can be
+/// rewritten. Anything else that differs (e.g. the compiler version) is caught
+/// by Cargo's own freshness check, which rebuilds the unit.
+fn take_near_match(
+    unit: &UnitPlan,
+    near_matches: &mut CargoRestoreResponse,
+    allowed: &BTreeSet<String>,
+    dep_fingerprints: &HashMap<u64, Fingerprint>,
+) -> Option<SavedUnit> {
+    let UnitPlan::LibraryCrate(plan) = unit else {
+        return None;
+    };
+    let key = plan.near_match_key.as_deref()?;
+    near_matches
+        .take_near_matches(key)
+        .into_iter()
+        .find(|candidate| {
+            let SavedUnit::LibraryCrate(..) = candidate else {
+                return false;
+            };
+            let fingerprint = match serde_json::from_str::<Fingerprint>(
+                candidate.fingerprint().as_str(),
+            ) {
+                Ok(fingerprint) => fingerprint,
+                Err(err) => {
+                    debug!(?err, candidate = %candidate.unit_hash(), "skipping near match: invalid fingerprint");
+                    return false;
+                }
+            };
+            let features = match fingerprint.features() {
+                Ok(features) => features,
+                Err(err) => {
+                    debug!(?err, candidate = %candidate.unit_hash(), "skipping near match: invalid features");
+                    return false;
+                }
+            };
+            if !near_match::compatible(&plan.features, &features, allowed) {
+                debug!(
+                    candidate = %candidate.unit_hash(),
+                    ?features,
+                    local = ?plan.features,
+                    "skipping near match: incompatible features"
+                );
+                return false;
+            }
+            if !fingerprint.deps_resolvable(dep_fingerprints) {
+                debug!(
+                    candidate = %candidate.unit_hash(),
+                    "skipping near match: compiled against different dependencies"
+                );
+                return false;
+            }
+            true
+        })
+}
+
+/// Rename a file restored from a near match so that it has the name Cargo
+/// expects for the local unit.
+///
+/// Cargo includes the unit hash in the names of library outputs (e.g.
+/// `libserde-<hash>.rlib`), and the near match was saved under its own hash.
+fn rename_near_match_file(
+    path: AbsFilePath,
+    from: &SavedUnitHash,
+    to: &UnitHash,
+) -> Result<AbsFilePath> {
+    let name = path
+        .file_name_str_lossy()
+        .ok_or_eyre("restored file has no name")?
+        .replacen(&format!("-{from}"), &format!("-{to}"), 1);
+    path.parent()
+        .ok_or_eyre("restored file has no parent directory")?
+        .try_join_file(name)
+}
+
 fn unit_type_name(unit: &UnitPlan) -> &'static str {
     match unit {
         UnitPlan::LibraryCrate(_) => "LibraryCrate",
@@ -895,14 +2076,278 @@ fn filter_units_with_incomplete_deps(
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
-    use crate::cargo::{LibraryCrateUnitPlan, RustcTarget, UnitPlanInfo};
-    use crate::path::AbsFilePath;
-    use clients::courier::v1::{
-        Fingerprint as SavedFingerprint, Key, LibraryCrateUnitPlan as SavedLibraryCratePlan,
-        LibraryFiles, SavedUnit, UnitPlanInfo as SavedUnitPlanInfo,
+    use crate::cargo::{
+        CargoBuildArguments, CratePolicy, LibraryCrateUnitPlan, RustcTarget, UnitPlanInfo,
+    };
+    use crate::path::{AbsFilePath, RelFilePath};
+    use axum::{Json, Router, routing::post};
+    use clients::{
+        Token,
+        courier::v1::{
+            Fingerprint as SavedFingerprint, Key, LibraryCrateUnitPlan as SavedLibraryCratePlan,
+            LibraryFiles, SavedFile, SavedUnit, UnitPlanInfo as SavedUnitPlanInfo,
+        },
     };
     use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn case_folded_path_collisions() {
+        let path = |p: &str| AbsFilePath::try_from(p).unwrap();
+        let mut seen = HashMap::new();
+
+        let first = [path("/t/out/Bindings.rs"), path("/t/out/lib.rs")];
+        pretty_assert_eq!(record_case_folded_paths(&mut seen, &first), None);
+
+        // Restoring the same path again (e.g. a unit restored twice) is fine.
+        let same = [path("/t/out/lib.rs")];
+        pretty_assert_eq!(record_case_folded_paths(&mut seen, &same), None);
+
+        let colliding = [path("/t/out/other.rs"), path("/t/out/bindings.rs")];
+        pretty_assert_eq!(
+            record_case_folded_paths(&mut seen, &colliding),
+            Some((path("/t/out/bindings.rs"), path("/t/out/Bindings.rs")))
+        );
+
+        let within_unit = [path("/t/a/Foo.rs"), path("/t/a/foo.rs")];
+        pretty_assert_eq!(
+            record_case_folded_paths(&mut seen, &within_unit),
+            Some((path("/t/a/foo.rs"), path("/t/a/Foo.rs")))
+        );
+
+        // Paths from units with collisions are not recorded.
+        let expected = [
+            ("/t/out/bindings.rs", "/t/out/Bindings.rs"),
+            ("/t/out/lib.rs", "/t/out/lib.rs"),
+        ]
+        .into_iter()
+        .map(|(key, p)| (String::from(key), path(p)))
+        .collect::<HashMap<_, _>>();
+        pretty_assert_eq!(seen, expected);
+    }
+
+    /// A fingerprint that can be rewritten on restore.
+    const FINGERPRINT: &str = r#"{"rustc":1,"features":"[]","declared_features":"[]","target":1,"profile":1,"path":1,"deps":[],"local":[],"rustflags":[],"config":1,"compile_kind":0}"#;
+
+    /// A saved library crate whose output files are `outputs`, relative to the
+    /// profile directory, along with the content of each of its objects.
+    fn make_saved_library(hash: &str, outputs: &[(&str, &str)]) -> (SavedUnit, Vec<Vec<u8>>) {
+        let info = SavedUnitPlanInfo::builder()
+            .unit_hash(hash)
+            .package_name(hash)
+            .crate_name(hash)
+            .maybe_target_arch(Some("x86_64-unknown-linux-gnu"))
+            .build();
+
+        let mut objects = vec![b"[]".to_vec(), b"encoded-dep-info".to_vec()];
+        let output_files = outputs
+            .iter()
+            .map(|(path, content)| {
+                let path =
+                    QualifiedPath::RelativeTargetProfile(RelFilePath::try_from(*path).unwrap());
+                objects.push(content.as_bytes().to_vec());
+                SavedFile::builder()
+                    .executable(false)
+                    .object_key(test_key(content.as_bytes()))
+                    .path(serde_json::to_string(&path).unwrap())
+                    .build()
+            })
+            .collect();
+        let files = LibraryFiles::builder()
+            .output_files(output_files)
+            .fingerprint(SavedFingerprint::from(String::from(FINGERPRINT)))
+            .dep_info_file(test_key(&objects[0]))
+            .encoded_dep_info_file(test_key(&objects[1]))
+            .build();
+
+        let plan = SavedLibraryCratePlan::builder()
+            .info(info)
+            .src_path("test.rs")
+            .outputs(vec![] as Vec<clients::courier::v1::DiskPath>)
+            .build();
+
+        (SavedUnit::LibraryCrate(files, plan), objects)
+    }
+
+    /// Start a mock Courier that serves `units` to restores, returning a client
+    /// for it.
+    async fn mock_courier(units: Vec<(&str, SavedUnit)>) -> Courier {
+        let response = CargoRestoreResponse::new(units);
+        let restore = move || {
+            let response = response.clone();
+            async move { Json(response) }
+        };
+        let app = Router::new().route("/api/v1/cache/cargo/restore", post(restore));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Courier::new(url.parse().unwrap(), Token::from("test-token")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn case_folded_collisions_are_not_restored() {
+        let temp = TempDir::new().unwrap();
+        let args = CargoBuildArguments::from_iter([
+            "--target-dir",
+            temp.path().join("target").to_str().unwrap(),
+        ]);
+        let ws = Workspace::from_argv_in_dir(&AbsDirPath::current().unwrap(), &args)
+            .await
+            .unwrap();
+
+        // The units are independent, but `b` is restored after `a` and its
+        // output differs from `a`'s only in case.
+        let (a, a_objects) = make_saved_library("aaaa", &[("out/Bindings.rs", "from a")]);
+        let (b, b_objects) = make_saved_library("bbbb", &[("out/bindings.rs", "from b")]);
+        let local = LocalCas::new(AbsDirPath::try_from(temp.path().join("cas")).unwrap());
+        for object in a_objects.iter().chain(&b_objects) {
+            local.store(&test_key(object), object).await.unwrap();
+        }
+        let courier = mock_courier(vec![("aaaa", a), ("bbbb", b)]).await;
+        let cas = CourierCas::new(courier.clone());
+
+        let units = vec![
+            make_unit_plan("aaaa", "aaaa", vec![]),
+            make_unit_plan("bbbb", "bbbb", vec![]),
+        ];
+        let config = RestoreConfig::default();
+        let options = RestoreOptions::new(&config).with_case_insensitive(true);
+        let progress = TransferBar::hidden(units.len() as u64);
+        let restored = restore_units(&courier, &cas, &local, &ws, &units, &progress, options)
+            .await
+            .unwrap();
+
+        // `b` is left for Cargo to build rather than overwriting `a`'s output,
+        // which it would on a case-insensitive file system.
+        let restored_units = restored.units.into_iter().collect::<Vec<_>>();
+        pretty_assert_eq!(restored_units, vec![UnitHash::from("aaaa")]);
+        let profile_dir = ws.arch_profile_dir(&RustcTarget::ImplicitHost);
+        let output = |path: &str| profile_dir.try_join_file(path).unwrap();
+        let content = fs::read_buffered_utf8(&output("out/Bindings.rs"))
+            .await
+            .unwrap();
+        pretty_assert_eq!(content.as_deref(), Some("from a"));
+        assert!(!fs::exists(&output("out/bindings.rs")).await);
+    }
+
+    #[test]
+    fn near_match_files_are_renamed() {
+        let path = |p: &str| AbsFilePath::try_from(p).unwrap();
+        let renamed = rename_near_match_file(
+            path("/t/debug/deps/libserde-1111aaaa.rlib"),
+            &SavedUnitHash::from("1111aaaa"),
+            &UnitHash::from("2222bbbb"),
+        )
+        .unwrap();
+        pretty_assert_eq!(renamed, path("/t/debug/deps/libserde-2222bbbb.rlib"));
+    }
+
+    /// The OUT_DIR of a fixture crate whose build script generates bindings
+    /// and copies a data file, with a stale file left by an earlier run.
+    async fn fixture_out_dir(temp: &tempfile::TempDir) -> AbsDirPath {
+        let root = AbsDirPath::try_from(temp.path().to_path_buf()).unwrap();
+        let out_dir = root
+            .try_join_dirs(["build", "fixture-0123456789abcdef", "out"])
+            .unwrap();
+        fs::write(&out_dir.try_join_file("stale.rs").unwrap(), "// stale")
+            .await
+            .unwrap();
+        out_dir
+    }
+
+    async fn out_dir_files(out_dir: &AbsDirPath) -> Vec<String> {
+        let mut files = fs::walk_files(out_dir)
+            .map(|file| {
+                let file = file.unwrap();
+                String::from(file.relative_to(out_dir).unwrap().as_str_lossy())
+            })
+            .collect::<Vec<_>>()
+            .await;
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn out_dir_replaced_once_complete() {
+        let temp = tempfile::tempdir().unwrap();
+        let out_dir = fixture_out_dir(&temp).await;
+        let restore = OutDirRestore::new(out_dir.clone(), 2).await.unwrap();
+
+        for (file, content) in [
+            ("bindings.rs", "pub const N: u8 = 1;"),
+            ("data/table.bin", "\x01"),
+        ] {
+            let staged = restore
+                .staged(&out_dir.try_join_file(file).unwrap())
+                .unwrap();
+            fs::write(&staged, content).await.unwrap();
+            pretty_assert_eq!(out_dir_files(&out_dir).await, vec!["stale.rs"]);
+            restore.file_written().await.unwrap();
+        }
+
+        pretty_assert_eq!(
+            out_dir_files(&out_dir).await,
+            vec!["bindings.rs", "data/table.bin"]
+        );
+        assert!(!fs::exists(restore.staging.as_std_path()).await);
+    }
+
+    #[tokio::test]
+    async fn out_dir_untouched_when_abandoned() {
+        let temp = tempfile::tempdir().unwrap();
+        let out_dir = fixture_out_dir(&temp).await;
+        let restore = OutDirRestore::new(out_dir.clone(), 2).await.unwrap();
+
+        let staged = restore
+            .staged(&out_dir.try_join_file("bindings.rs").unwrap())
+            .unwrap();
+        fs::write(&staged, "pub const N: u8 = 1;").await.unwrap();
+        restore.file_written().await.unwrap();
+        restore.abandon().await.unwrap();
+
+        pretty_assert_eq!(out_dir_files(&out_dir).await, vec!["stale.rs"]);
+        assert!(!fs::exists(restore.staging.as_std_path()).await);
+    }
+
+    #[tokio::test]
+    async fn empty_out_dir_restored_immediately() {
+        let temp = tempfile::tempdir().unwrap();
+        let out_dir = fixture_out_dir(&temp).await;
+        OutDirRestore::new(out_dir.clone(), 0).await.unwrap();
+
+        pretty_assert_eq!(out_dir_files(&out_dir).await, Vec::<String>::new());
+        assert!(fs::is_dir(out_dir.as_std_path()).await);
+    }
+
+    #[test]
+    fn out_dir_files_must_be_in_out_dir() {
+        let restore = OutDirRestore {
+            out_dir: AbsDirPath::try_from("/t/build/fixture-0123456789abcdef/out").unwrap(),
+            staging: AbsDirPath::try_from("/t/build/fixture-0123456789abcdef/out.hurry-restore")
+                .unwrap(),
+            remaining: Arc::new(AtomicUsize::new(1)),
+        };
+        pretty_assert_eq!(
+            restore
+                .staged(
+                    &AbsFilePath::try_from("/t/build/fixture-0123456789abcdef/out/gen/lib.rs")
+                        .unwrap()
+                )
+                .unwrap(),
+            AbsFilePath::try_from("/t/build/fixture-0123456789abcdef/out.hurry-restore/gen/lib.rs")
+                .unwrap()
+        );
+        assert!(
+            restore
+                .staged(&AbsFilePath::try_from("/t/build/fixture-0123456789abcdef/output").unwrap())
+                .is_err()
+        );
+    }
 
     fn make_unit_plan(hash: &str, package: &str, deps: Vec<&str>) -> UnitPlan {
         UnitPlan::LibraryCrate(LibraryCrateUnitPlan {
@@ -913,9 +2358,15 @@ mod tests {
                 crate_name: String::from(package),
                 target_arch: RustcTarget::ImplicitHost,
                 deps: deps.into_iter().map(UnitHash::from).collect(),
+                policy: CratePolicy::default(),
+                source_hash: None,
             },
             src_path: AbsFilePath::try_from("/test/src/lib.rs").unwrap(),
             outputs: vec![],
+            features: BTreeSet::new(),
+            near_match_key: None,
+            unhashed_outputs: false,
+            fingerprint_hash: None,
         })
     }
 
@@ -1082,4 +2533,17 @@ mod tests {
         );
         pretty_assert_eq!(count, 2);
     }
+
+    #[test_case(OverwritePolicy::Always, 20, OverwriteDecision::Overwrite; "always")]
+    #[test_case(OverwritePolicy::Never, 5, OverwriteDecision::Keep; "never")]
+    #[test_case(OverwritePolicy::IfOlder, 5, OverwriteDecision::Overwrite; "if older, older")]
+    #[test_case(OverwritePolicy::IfOlder, 10, OverwriteDecision::Overwrite; "if older, same")]
+    #[test_case(OverwritePolicy::IfOlder, 20, OverwriteDecision::Keep; "if older, newer")]
+    #[test_case(OverwritePolicy::Prompt, 5, OverwriteDecision::Overwrite; "prompt, older")]
+    #[test_case(OverwritePolicy::Prompt, 20, OverwriteDecision::Ask; "prompt, newer")]
+    #[test]
+    fn overwrite_decisions(policy: OverwritePolicy, local: u64, expected: OverwriteDecision) {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        pretty_assert_eq!(OverwriteDecision::new(policy, at(local), at(10)), expected);
+    }
 }
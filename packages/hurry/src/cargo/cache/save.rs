@@ -1,43 +1,326 @@
 use std::{collections::HashMap, path::PathBuf};
 
-use color_eyre::{Result, eyre::bail};
+use color_eyre::{
+    Report, Result,
+    eyre::{Context as _, bail},
+};
 use futures::stream;
 use serde::{Deserialize, Serialize};
 use tap::{Conv as _, Pipe as _};
-use tracing::{debug, error, instrument, trace};
+use tokio::task::JoinSet;
+use tracing::{Instrument as _, Span, debug, error, info, instrument, trace, warn};
+use uuid::Uuid;
 
 use crate::{
     cargo::{
-        Fingerprint, QualifiedPath, Restored, RustcTarget, UnitPlan, Workspace, host_glibc_version,
+        CacheRepair, Fingerprint, QualifiedPath, Restored, RustcTarget, UnitHash, UnitPlan,
+        UnitPlanInfo, Workspace,
+        cache::{
+            policy::{
+                DeterminismCheck, UploadDecision, UploadPolicy, UploadReason, estimate_rebuild,
+            },
+            validate::{InvalidUnit, UnitProblem, validate_unit},
+        },
+        host_glibc_version,
     },
-    cas::CourierCas,
+    cas::{CourierCas, LocalCas},
     path::{AbsDirPath, AbsFilePath, JoinWith as _},
+    progress::format_size,
 };
 use clients::{
     Courier,
     courier::v1::{
-        self as courier, Key,
-        cache::{CargoSaveRequest, CargoSaveUnitRequest},
+        self as courier, CacheScope, Key, SavedUnitHash, UnitHashVersion,
+        UnitPlanInfo as SavedUnitPlanInfo,
+        cache::{
+            CacheReadOnly, CargoRestoreRequest, CargoRestoreResponse, CargoSaveRequest,
+            CargoSaveUnitRequest, SavedUnitMetadata,
+        },
+        cas::RESUMABLE_UPLOAD_THRESHOLD,
     },
 };
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
 pub struct SaveProgress {
     pub uploaded_units: u64,
     pub total_units: u64,
     pub uploaded_files: u64,
     pub uploaded_bytes: u64,
+
+    /// Bytes that have been read from units and are waiting to be uploaded.
+    #[serde(default)]
+    pub queued_bytes: u64,
+
+    /// Units that weren't uploaded because the upload policy decided they're
+    /// cheaper to rebuild than to restore.
+    pub skipped_by_policy: Vec<UploadDecision>,
+
+    /// Units that weren't uploaded because their files on disk are missing,
+    /// unreadable, or can't be restored.
+    #[serde(default)]
+    pub skipped_invalid: Vec<InvalidUnit>,
+
+    /// Units whose content differs from what the cache already stores for
+    /// the same unit hash.
+    #[serde(default)]
+    pub nondeterministic: Vec<NondeterministicUnit>,
+
+    /// Whether nothing was uploaded because the organization's write policy
+    /// doesn't let this branch save units.
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// Why the upload stopped early because Courier is read-only for
+    /// maintenance, if it did. Anything uploaded before is kept, and the
+    /// next build uploads the rest.
+    #[serde(default)]
+    pub maintenance: Option<String>,
+
+    /// Units that were uploaded to replace units in the cache whose objects
+    /// were missing when they were restored.
+    #[serde(default)]
+    pub repaired: Vec<CacheRepair>,
+
+    /// The state of each unit that the upload considered, in the order they
+    /// were planned. Units that were restored or opted out of the cache
+    /// aren't listed.
+    #[serde(default)]
+    pub units: Vec<UnitUploadProgress>,
+}
+
+/// The progress of a single unit's upload.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct UnitUploadProgress {
+    pub unit_hash: UnitHash,
+    pub package_name: String,
+    pub state: UnitUploadState,
+
+    /// The bytes of the unit's objects that are sent to Courier, once they're
+    /// known.
+    pub bytes: u64,
+}
+
+/// Where a unit is in its upload.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnitUploadState {
+    /// Waiting for earlier units to be read.
+    Pending,
+
+    /// Reading the unit's files and hashing them into CAS objects.
+    Hashing,
+
+    /// Sending the unit's objects to Courier.
+    Uploading,
+
+    /// The unit's objects are in Courier.
+    Done,
+
+    /// The unit wasn't uploaded, either because its files are invalid or
+    /// because its upload failed.
+    Failed,
+
+    /// The unit wasn't uploaded because a policy decided against it.
+    Skipped,
+}
+
+/// A unit whose content differs from what the cache already stores for the
+/// same unit hash.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct NondeterministicUnit {
+    pub unit_hash: UnitHash,
+    pub package_name: String,
+
+    /// Whether the unit wasn't uploaded because of the difference.
+    pub refused: bool,
+}
+
+impl SaveProgress {
+    /// How many of the listed units are in the provided state.
+    pub fn count_units(&self, state: UnitUploadState) -> usize {
+        self.units.iter().filter(|unit| unit.state == state).count()
+    }
+
+    /// How many bytes have actually been sent to Courier, as opposed to
+    /// read and waiting to be sent.
+    pub fn transferred_bytes(&self) -> u64 {
+        self.uploaded_bytes.saturating_sub(self.queued_bytes)
+    }
+
+    /// Mark the units that were still being read or uploaded as failed, for
+    /// an upload that stopped with an error.
+    pub fn fail_unfinished(&mut self) {
+        for unit in &mut self.units {
+            if matches!(
+                unit.state,
+                UnitUploadState::Hashing | UnitUploadState::Uploading
+            ) {
+                unit.state = UnitUploadState::Failed;
+            }
+        }
+    }
+
+    fn set_unit_state(&mut self, unit_hash: &UnitHash, state: UnitUploadState) {
+        if let Some(unit) = self
+            .units
+            .iter_mut()
+            .find(|unit| &unit.unit_hash == unit_hash)
+        {
+            unit.state = state;
+        }
+    }
+
+    /// Record a unit whose content differs from the cache. Refused units are
+    /// skipped, so the files and bytes counted for their uploads are taken
+    /// back out of the totals.
+    fn record_nondeterministic(&mut self, unit: NondeterministicUnit, uploads: &[(Key, Vec<u8>)]) {
+        if unit.refused {
+            self.total_units -= 1;
+            self.set_unit_state(&unit.unit_hash, UnitUploadState::Skipped);
+            self.uploaded_files -= uploads.len() as u64;
+            self.uploaded_bytes -= uploads
+                .iter()
+                .map(|(_, contents)| contents.len() as u64)
+                .sum::<u64>();
+        }
+        self.nondeterministic.push(unit);
+    }
+
+    /// Explain why nothing was uploaded for the build summary, if the
+    /// organization's write policy doesn't let this branch save units.
+    pub fn write_policy_summary(&self) -> Option<String> {
+        self.read_only.then(|| {
+            String::from(
+                "[hurry] Skipped uploading: this branch can only read from the cache (see `hurry cache write-policy`)",
+            )
+        })
+    }
+
+    /// Explain why the upload stopped early for the build summary, if Courier
+    /// is read-only for maintenance.
+    pub fn maintenance_summary(&self) -> Option<String> {
+        self.maintenance.as_ref().map(|reason| {
+            format!("[hurry] Skipped uploading: the cache is read-only for maintenance ({reason})")
+        })
+    }
+
+    /// Summarize the units skipped by the upload policy for the build summary.
+    ///
+    /// Returns `None` if the policy didn't skip any units.
+    pub fn policy_summary(&self) -> Option<String> {
+        if self.skipped_by_policy.is_empty() {
+            return None;
+        }
+
+        let bytes = self
+            .skipped_by_policy
+            .iter()
+            .map(|decision| decision.bytes)
+            .sum::<u64>();
+        let mut summary = format!(
+            "[hurry] Skipped uploading {} units ({}) that aren't worth caching:",
+            self.skipped_by_policy.len(),
+            format_size(bytes),
+        );
+        for decision in &self.skipped_by_policy {
+            let rebuild = decision
+                .estimated_rebuild
+                .map(|rebuild| format!("{:.1}s", rebuild.as_secs_f64()))
+                .unwrap_or_else(|| String::from("unknown"));
+            summary.push_str(&format!(
+                "\n[hurry]   {} ({}, rebuilds in {rebuild}, {})",
+                decision.package_name,
+                format_size(decision.bytes),
+                decision.reason,
+            ));
+        }
+        Some(summary)
+    }
+
+    /// Summarize the units uploaded to repair the cache for the build summary.
+    ///
+    /// Returns `None` if no units were repaired.
+    pub fn repair_summary(&self) -> Option<String> {
+        if self.repaired.is_empty() {
+            return None;
+        }
+
+        let mut summary = format!(
+            "[hurry] Repaired {} units in the cache whose objects were missing:",
+            self.repaired.len(),
+        );
+        for repair in &self.repaired {
+            summary.push_str(&format!(
+                "\n[hurry]   {} ({} missing objects re-uploaded)",
+                repair.package_name, repair.missing_objects,
+            ));
+        }
+        Some(summary)
+    }
+
+    /// Summarize the units skipped because of their files on disk for the
+    /// build summary.
+    ///
+    /// Returns `None` if no units were skipped for this reason.
+    pub fn validation_summary(&self) -> Option<String> {
+        if self.skipped_invalid.is_empty() {
+            return None;
+        }
+
+        let mut summary = format!(
+            "[hurry] Skipped uploading {} units whose files couldn't be read:",
+            self.skipped_invalid.len(),
+        );
+        for unit in &self.skipped_invalid {
+            summary.push_str(&format!(
+                "\n[hurry]   {} ({})",
+                unit.package_name, unit.problem,
+            ));
+        }
+        Some(summary)
+    }
+
+    /// Summarize the units whose content differs from the cache for the build
+    /// summary.
+    ///
+    /// Returns `None` if no such units were found.
+    pub fn determinism_summary(&self) -> Option<String> {
+        if self.nondeterministic.is_empty() {
+            return None;
+        }
+
+        let mut summary = format!(
+            "[hurry] {} units built differently than the cached copy of the same unit (nondeterministic build?):",
+            self.nondeterministic.len(),
+        );
+        for unit in &self.nondeterministic {
+            let action = if unit.refused {
+                "not uploaded"
+            } else {
+                "uploaded"
+            };
+            summary.push_str(&format!(
+                "\n[hurry]   {} ({}, {action})",
+                unit.package_name, unit.unit_hash,
+            ));
+        }
+        Some(summary)
+    }
 }
 
 #[instrument(skip_all)]
 pub async fn save_units(
     courier: &Courier,
     cas: &CourierCas,
+    local: &LocalCas,
     ws: Workspace,
-    units: Vec<UnitPlan>,
+    mut units: Vec<UnitPlan>,
     skip: Restored,
+    policy: UploadPolicy,
+    mut metadata: SavedUnitMetadata,
+    parallelism: usize,
     mut on_progress: impl FnMut(&SaveProgress),
-) -> Result<()> {
+) -> Result<SaveProgress> {
     trace!(?units, ?skip, "saving units");
 
     let mut progress = SaveProgress {
@@ -45,19 +328,105 @@ pub async fn save_units(
         total_units: units.len() as u64,
         uploaded_files: 0,
         uploaded_bytes: 0,
+        queued_bytes: 0,
+        skipped_by_policy: Vec::new(),
+        skipped_invalid: Vec::new(),
+        nondeterministic: Vec::new(),
+        read_only: false,
+        maintenance: None,
+        repaired: Vec::new(),
+        units: Vec::new(),
     };
 
-    // TODO: This algorithm currently uploads units one at a time. Instead, we
-    // should batch units together up to around 10MB in file size for optimal
-    // upload speed. One way we could do this is have units present their
-    // CAS-able contents, batch those contents up, and then issue save requests
-    // for batches of units as their CAS contents are finished uploading.
+    // Organizations can limit saves to protected branches, in which case
+    // builds of other branches only read from the cache.
+    let write_policy = courier
+        .cargo_cache_write_policy()
+        .await
+        .context("get cache write policy")?;
+    if !write_policy.allows(metadata.branch.as_deref()) {
+        info!(branch = ?metadata.branch, "branch can't save to the cache, skipping upload");
+        progress.total_units = 0;
+        progress.read_only = true;
+        on_progress(&progress);
+        return Ok(progress);
+    }
+
+    // Courier is read-only during maintenance, in which case the upload is
+    // skipped instead of failing. Writes are also checked as they're made,
+    // since maintenance can start partway through the upload.
+    let read_only = courier
+        .cargo_cache_read_only()
+        .await
+        .context("get cache read-only status")?;
+    if let Some(reason) = read_only.reason {
+        return Ok(stop_for_maintenance(progress, reason, &mut on_progress));
+    }
+
+    progress.units = units
+        .iter()
+        .map(UnitPlan::info)
+        .filter(|info| info.policy.cache && !skip.units.contains(&info.unit_hash))
+        .map(|info| UnitUploadProgress {
+            unit_hash: info.unit_hash.clone(),
+            package_name: info.package_name.clone(),
+            state: UnitUploadState::Pending,
+            bytes: 0,
+        })
+        .collect();
+    on_progress(&progress);
+
+    // Check every unit's files before reading any of them, so that a missing
+    // or unreadable file only skips its own unit instead of failing the
+    // upload partway through.
+    let mut invalid = HashMap::new();
+    for unit in &mut units {
+        // Units planned before Cargo first built them don't know their
+        // fingerprint directory yet if their outputs are unhashed.
+        if let UnitPlan::LibraryCrate(plan) = unit {
+            plan.locate_fingerprint_dir(&ws).await;
+        }
+        let info = unit.info();
+        if !info.policy.cache || skip.units.contains(&info.unit_hash) {
+            continue;
+        }
+        let unit_hash = info.unit_hash.clone();
+        if let Some(problem) = validate_unit(&ws, unit).await {
+            invalid.insert(unit_hash, problem);
+        }
+    }
+
+    // Units are saved under hashes that include the organization's cache
+    // generation, so that bumping it invalidates every unit saved before, and
+    // the build's cache scope, so that scopes don't restore each other's units.
+    let generation = courier
+        .cargo_cache_generation()
+        .await
+        .context("get cache generation")?
+        .generation;
+    let scope = courier.cache_scope().cloned();
+
+    let saved = if policy.determinism.enabled() {
+        load_saved(courier, &units, &skip, generation).await
+    } else {
+        CargoRestoreResponse::default()
+    };
+
+    // Units are read in order, since rewriting a unit's fingerprint needs the
+    // rewritten fingerprints of its dependencies, but their uploads run
+    // concurrently.
+    let mut uploads = UploadQueue::new(parallelism);
     let mut save_requests = Vec::new();
     let mut dep_fingerprints = HashMap::new();
     for unit in units {
         debug!(?unit, "saving unit");
-        if skip.units.contains(&unit.info().unit_hash) {
-            debug!(?unit, "skipping unit backup: unit was restored from cache");
+        let opted_out = !unit.info().policy.cache;
+        if opted_out || skip.units.contains(&unit.info().unit_hash) {
+            if opted_out {
+                debug!(?unit, "skipping unit backup: crate opted out of the cache");
+            } else {
+                debug!(?unit, "skipping unit backup: unit was restored from cache");
+            }
             progress.total_units -= 1;
             on_progress(&progress);
 
@@ -76,6 +445,30 @@ pub async fn save_units(
             continue;
         }
 
+        if let Some(problem) = invalid.remove(&unit.info().unit_hash) {
+            // Downstream units need this unit's rewritten fingerprint, so
+            // calculate it if the fingerprint itself can still be read.
+            if let Ok(fingerprint) = unit.read_fingerprint(&ws).await
+                && let Err(err) = rewrite_fingerprint(
+                    &ws,
+                    &unit.info().target_arch,
+                    unit.src_path(),
+                    &mut dep_fingerprints,
+                    fingerprint,
+                )
+                .await
+            {
+                debug!(?err, "rewrite fingerprint of invalid unit");
+            }
+            skip_invalid(&mut progress, unit.info(), problem);
+            on_progress(&progress);
+            continue;
+        }
+
+        let unit_hash = unit.info().unit_hash.clone();
+        progress.set_unit_state(&unit_hash, UnitUploadState::Hashing);
+        on_progress(&progress);
+
         // For units compiled against glibc, we need to know the glibc version
         // so we don't later restore the unit on a host machine that does not
         // have the needed glibc symbols.
@@ -105,6 +498,7 @@ pub async fn save_units(
                 // that linker accepts to query the libc file?
                 error!("backing up cross-compiled units is not yet supported");
                 progress.total_units -= 1;
+                progress.set_unit_state(&unit_hash, UnitUploadState::Skipped);
                 on_progress(&progress);
                 continue;
             }
@@ -120,18 +514,67 @@ pub async fn save_units(
             None
         };
 
-        // Upload unit to CAS and cache.
-        match unit {
+        // Units whose saved copies are missing objects are uploaded regardless
+        // of the upload policy, since otherwise the broken copy stays in the
+        // cache.
+        let repair = skip
+            .repairs
+            .iter()
+            .find(|repair| repair.unit_hash == unit.info().unit_hash)
+            .cloned();
+
+        // Prepare the unit's CAS objects and save request.
+        let nondeterministic = unit.info().policy.nondeterministic;
+        let (save_request, cas_uploads) = match unit {
             UnitPlan::LibraryCrate(plan) => {
                 // Read unit files.
                 let files = plan.read(&ws).await?;
 
+                // Rewrite the fingerprint before anything else, so that it's
+                // available to downstream units even if this unit is skipped.
+                let fingerprint = match rewrite_fingerprint(
+                    &ws,
+                    &plan.info.target_arch,
+                    Some(plan.src_path.clone()),
+                    &mut dep_fingerprints,
+                    files.fingerprint,
+                )
+                .await
+                {
+                    Ok(fingerprint) => fingerprint,
+                    Err(err) => {
+                        let error = format!("{err:#}");
+                        skip_invalid(
+                            &mut progress,
+                            &plan.info,
+                            UnitProblem::Fingerprint { error },
+                        );
+                        on_progress(&progress);
+                        continue;
+                    }
+                };
+
+                let bytes = files
+                    .output_files
+                    .iter()
+                    .map(|file| file.contents.len() as u64)
+                    .sum();
+                if repair.is_none()
+                    && let Some(decision) = skip_by_policy(&ws, &policy, &plan.info, bytes).await
+                {
+                    progress.total_units -= 1;
+                    progress.skipped_by_policy.push(decision);
+                    progress.set_unit_state(&unit_hash, UnitUploadState::Skipped);
+                    on_progress(&progress);
+                    continue;
+                }
+
                 // Prepare CAS objects.
                 let mut cas_uploads = Vec::new();
 
                 let mut output_files = Vec::new();
                 for output_file in files.output_files {
-                    let object_key = Key::from_buffer(&output_file.contents);
+                    let object_key = cas.key(&output_file.contents);
                     output_files.push(
                         courier::SavedFile::builder()
                             .object_key(object_key.clone())
@@ -148,34 +591,22 @@ pub async fn save_units(
                 }
 
                 let dep_info_file_contents = serde_json::to_vec(&files.dep_info_file)?;
-                let dep_info_file = Key::from_buffer(&dep_info_file_contents);
+                let dep_info_file = cas.key(&dep_info_file_contents);
                 if !skip.files.contains(&dep_info_file) {
                     progress.uploaded_files += 1;
                     progress.uploaded_bytes += dep_info_file_contents.len() as u64;
                     cas_uploads.push((dep_info_file.clone(), dep_info_file_contents));
                 }
 
-                let encoded_dep_info_file = Key::from_buffer(&files.encoded_dep_info_file);
+                let encoded_dep_info_file = cas.key(&files.encoded_dep_info_file);
                 if !skip.files.contains(&encoded_dep_info_file) {
                     progress.uploaded_files += 1;
                     progress.uploaded_bytes += files.encoded_dep_info_file.len() as u64;
                     cas_uploads.push((encoded_dep_info_file.clone(), files.encoded_dep_info_file));
                 }
 
-                // Save CAS objects.
-                if !cas_uploads.is_empty() {
-                    cas.store_bulk(stream::iter(cas_uploads)).await?;
-                }
-
                 // Prepare save request.
-                let fingerprint = rewrite_fingerprint(
-                    &ws,
-                    &plan.info.target_arch,
-                    Some(plan.src_path.clone()),
-                    &mut dep_fingerprints,
-                    files.fingerprint,
-                )
-                .await?;
+                let near_match_key = plan.near_match_key.clone();
                 let save_request = CargoSaveUnitRequest::builder()
                     .unit(courier::SavedUnit::LibraryCrate(
                         courier::LibraryFiles::builder()
@@ -188,18 +619,58 @@ pub async fn save_units(
                     ))
                     .resolved_target(unit_arch.as_str().to_string())
                     .maybe_linux_glibc_version(glibc_version)
+                    .unit_hash_version(UnitHashVersion::CURRENT)
+                    .cache_generation(generation)
+                    .maybe_cache_scope(scope.clone())
+                    .maybe_near_match_key(near_match_key)
+                    .repair(repair.is_some())
                     .build();
 
-                save_requests.push(save_request);
+                (save_request, cas_uploads)
             }
             UnitPlan::BuildScriptCompilation(plan) => {
                 // Read unit files.
                 let files = plan.read(&ws).await?;
 
+                // Rewrite the fingerprint before anything else, so that it's
+                // available to downstream units even if this unit is skipped.
+                let fingerprint = match rewrite_fingerprint(
+                    &ws,
+                    &plan.info.target_arch,
+                    Some(plan.src_path.clone()),
+                    &mut dep_fingerprints,
+                    files.fingerprint,
+                )
+                .await
+                {
+                    Ok(fingerprint) => fingerprint,
+                    Err(err) => {
+                        let error = format!("{err:#}");
+                        skip_invalid(
+                            &mut progress,
+                            &plan.info,
+                            UnitProblem::Fingerprint { error },
+                        );
+                        on_progress(&progress);
+                        continue;
+                    }
+                };
+
+                let bytes = files.compiled_program.len() as u64;
+                if repair.is_none()
+                    && let Some(decision) = skip_by_policy(&ws, &policy, &plan.info, bytes).await
+                {
+                    progress.total_units -= 1;
+                    progress.skipped_by_policy.push(decision);
+                    progress.set_unit_state(&unit_hash, UnitUploadState::Skipped);
+                    on_progress(&progress);
+                    continue;
+                }
+
                 // Prepare CAS objects.
                 let mut cas_uploads = Vec::new();
 
-                let compiled_program = Key::from_buffer(&files.compiled_program);
+                let compiled_program = cas.key(&files.compiled_program);
                 if !skip.files.contains(&compiled_program) {
                     progress.uploaded_files += 1;
                     progress.uploaded_bytes += files.compiled_program.len() as u64;
@@ -207,34 +678,21 @@ pub async fn save_units(
                 }
 
                 let dep_info_file_contents = serde_json::to_vec(&files.dep_info_file)?;
-                let dep_info_file = Key::from_buffer(&dep_info_file_contents);
+                let dep_info_file = cas.key(&dep_info_file_contents);
                 if !skip.files.contains(&dep_info_file) {
                     progress.uploaded_files += 1;
                     progress.uploaded_bytes += dep_info_file_contents.len() as u64;
                     cas_uploads.push((dep_info_file.clone(), dep_info_file_contents));
                 }
 
-                let encoded_dep_info_file = Key::from_buffer(&files.encoded_dep_info_file);
+                let encoded_dep_info_file = cas.key(&files.encoded_dep_info_file);
                 if !skip.files.contains(&encoded_dep_info_file) {
                     progress.uploaded_files += 1;
                     progress.uploaded_bytes += files.encoded_dep_info_file.len() as u64;
                     cas_uploads.push((encoded_dep_info_file.clone(), files.encoded_dep_info_file));
                 }
 
-                // Save CAS objects.
-                if !cas_uploads.is_empty() {
-                    cas.store_bulk(stream::iter(cas_uploads)).await?;
-                }
-
                 // Prepare save request.
-                let fingerprint = rewrite_fingerprint(
-                    &ws,
-                    &plan.info.target_arch,
-                    Some(plan.src_path.clone()),
-                    &mut dep_fingerprints,
-                    files.fingerprint,
-                )
-                .await?;
                 let save_request = CargoSaveUnitRequest::builder()
                     .unit(courier::SavedUnit::BuildScriptCompilation(
                         courier::BuildScriptCompiledFiles::builder()
@@ -247,20 +705,63 @@ pub async fn save_units(
                     ))
                     .resolved_target(unit_arch.as_str().to_string())
                     .maybe_linux_glibc_version(glibc_version)
+                    .unit_hash_version(UnitHashVersion::CURRENT)
+                    .cache_generation(generation)
+                    .maybe_cache_scope(scope.clone())
+                    .repair(repair.is_some())
                     .build();
 
-                save_requests.push(save_request);
+                (save_request, cas_uploads)
             }
             UnitPlan::BuildScriptExecution(plan) => {
                 // Read unit files.
                 let files = plan.read(&ws).await?;
 
+                // Rewrite the fingerprint before anything else, so that it's
+                // available to downstream units even if this unit is skipped.
+                let fingerprint = match rewrite_fingerprint(
+                    &ws,
+                    &plan.info.target_arch,
+                    None,
+                    &mut dep_fingerprints,
+                    files.fingerprint,
+                )
+                .await
+                {
+                    Ok(fingerprint) => fingerprint,
+                    Err(err) => {
+                        let error = format!("{err:#}");
+                        skip_invalid(
+                            &mut progress,
+                            &plan.info,
+                            UnitProblem::Fingerprint { error },
+                        );
+                        on_progress(&progress);
+                        continue;
+                    }
+                };
+
+                let bytes = files
+                    .out_dir_files
+                    .iter()
+                    .map(|file| file.contents.len() as u64)
+                    .sum();
+                if repair.is_none()
+                    && let Some(decision) = skip_by_policy(&ws, &policy, &plan.info, bytes).await
+                {
+                    progress.total_units -= 1;
+                    progress.skipped_by_policy.push(decision);
+                    progress.set_unit_state(&unit_hash, UnitUploadState::Skipped);
+                    on_progress(&progress);
+                    continue;
+                }
+
                 // Prepare CAS objects.
                 let mut cas_uploads = Vec::new();
 
                 let mut out_dir_files = Vec::new();
                 for out_dir_file in files.out_dir_files {
-                    let object_key = Key::from_buffer(&out_dir_file.contents);
+                    let object_key = cas.key(&out_dir_file.contents);
                     out_dir_files.push(
                         courier::SavedFile::builder()
                             .object_key(object_key.clone())
@@ -277,34 +778,21 @@ pub async fn save_units(
                 }
 
                 let stdout_contents = serde_json::to_vec(&files.stdout)?;
-                let stdout = Key::from_buffer(&stdout_contents);
+                let stdout = cas.key(&stdout_contents);
                 if !skip.files.contains(&stdout) {
                     progress.uploaded_files += 1;
                     progress.uploaded_bytes += stdout_contents.len() as u64;
                     cas_uploads.push((stdout.clone(), stdout_contents));
                 }
 
-                let stderr = Key::from_buffer(&files.stderr);
+                let stderr = cas.key(&files.stderr);
                 if !skip.files.contains(&stderr) {
                     progress.uploaded_files += 1;
                     progress.uploaded_bytes += files.stderr.len() as u64;
                     cas_uploads.push((stderr.clone(), files.stderr));
                 }
 
-                // Save CAS objects.
-                if !cas_uploads.is_empty() {
-                    cas.store_bulk(stream::iter(cas_uploads)).await?;
-                }
-
                 // Prepare save request.
-                let fingerprint = rewrite_fingerprint(
-                    &ws,
-                    &plan.info.target_arch,
-                    None,
-                    &mut dep_fingerprints,
-                    files.fingerprint,
-                )
-                .await?;
                 let save_request = CargoSaveUnitRequest::builder()
                     .unit(courier::SavedUnit::BuildScriptExecution(
                         courier::BuildScriptOutputFiles::builder()
@@ -317,21 +805,357 @@ pub async fn save_units(
                     ))
                     .resolved_target(unit_arch.as_str().to_string())
                     .maybe_linux_glibc_version(glibc_version)
+                    .unit_hash_version(UnitHashVersion::CURRENT)
+                    .cache_generation(generation)
+                    .maybe_cache_scope(scope.clone())
+                    .repair(repair.is_some())
                     .build();
 
-                save_requests.push(save_request);
+                (save_request, cas_uploads)
+            }
+        };
+
+        // Compare the unit with what's already saved before uploading it, so
+        // that nondeterministic units don't churn storage. Crates that are
+        // known to be nondeterministic are exempt, since they'd always differ,
+        // as are repairs, since the saved copy is broken anyway.
+        if !nondeterministic
+            && repair.is_none()
+            && let Some(saved) = saved.get(&save_request.saved_unit_hash())
+            && !same_content(saved, &save_request.unit)
+        {
+            let info = save_request.unit.info();
+            let refused = policy.determinism == DeterminismCheck::Refuse;
+            warn!(
+                unit_hash = %info.unit_hash,
+                package_name = %info.package_name,
+                refused,
+                "unit content differs from the cached copy"
+            );
+            let unit = NondeterministicUnit {
+                unit_hash: UnitHash::from(info.unit_hash.as_str()),
+                package_name: info.package_name.clone(),
+                refused,
+            };
+            progress.record_nondeterministic(unit, &cas_uploads);
+            if refused {
+                on_progress(&progress);
+                continue;
             }
         }
-        progress.uploaded_units += 1;
+
+        // Upload the unit in the background while the next units are read,
+        // keeping at most `parallelism` uploads in flight so that only that
+        // many units' contents are held in memory at once.
+        while uploads.is_full() {
+            let joined = join_upload(
+                &mut uploads,
+                &mut save_requests,
+                &mut progress,
+                &mut on_progress,
+            )
+            .await;
+            if let Err(err) = joined {
+                return skip_if_read_only(err, progress, &mut on_progress);
+            }
+        }
+        if let Some(repair) = repair {
+            progress.repaired.push(repair);
+        }
+        let bytes = cas_uploads
+            .iter()
+            .map(|(_, contents)| contents.len() as u64)
+            .sum::<u64>();
+        progress.queued_bytes += bytes;
+        if let Some(unit) = progress
+            .units
+            .iter_mut()
+            .find(|unit| unit.unit_hash == unit_hash)
+        {
+            unit.state = UnitUploadState::Uploading;
+            unit.bytes = bytes;
+        }
         on_progress(&progress);
+        uploads.spawn(
+            upload_unit(cas.clone(), local.clone(), save_request, cas_uploads)
+                .instrument(Span::current()),
+        );
+    }
+    while !uploads.is_empty() {
+        let joined = join_upload(
+            &mut uploads,
+            &mut save_requests,
+            &mut progress,
+            &mut on_progress,
+        )
+        .await;
+        if let Err(err) = joined {
+            return skip_if_read_only(err, progress, &mut on_progress);
+        }
     }
 
-    // Save units to remote cache.
-    courier
-        .cargo_cache_save(CargoSaveRequest::new(save_requests))
-        .await?;
+    // Save units to remote cache. The units are saved under a build ID and
+    // only restorable once the save is finalized, so that if the save is
+    // interrupted, none of its units are restored without their dependencies.
+    let build_id = metadata
+        .build_id
+        .get_or_insert_with(|| Uuid::new_v4().to_string())
+        .clone();
+    let saved = courier
+        .cargo_cache_save(CargoSaveRequest::new(save_requests).with_metadata(metadata))
+        .await;
+    if let Err(err) = saved {
+        return skip_if_read_only(err, progress, &mut on_progress);
+    }
+    if let Err(err) = courier.cargo_cache_finalize(&build_id).await {
+        return skip_if_read_only(err, progress, &mut on_progress);
+    }
 
-    Result::<_>::Ok(())
+    Ok(progress)
+}
+
+/// Stop the upload without failing if Courier rejected a write because it's
+/// read-only for maintenance, and return the error otherwise.
+fn skip_if_read_only(
+    err: Report,
+    progress: SaveProgress,
+    on_progress: &mut impl FnMut(&SaveProgress),
+) -> Result<SaveProgress> {
+    let read_only = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<CacheReadOnly>());
+    match read_only {
+        Some(read_only) => Ok(stop_for_maintenance(
+            progress,
+            read_only.reason.clone(),
+            on_progress,
+        )),
+        None => Err(err),
+    }
+}
+
+/// Record that the upload stopped because Courier is read-only.
+fn stop_for_maintenance(
+    mut progress: SaveProgress,
+    reason: String,
+    on_progress: &mut impl FnMut(&SaveProgress),
+) -> SaveProgress {
+    info!(?reason, "cache is read-only, skipping upload");
+    progress.total_units = progress.uploaded_units;
+    progress.queued_bytes = 0;
+    progress.maintenance = Some(reason);
+    on_progress(&progress);
+    progress
+}
+
+/// Upload a unit's objects to the CAS, keeping a copy in the local CAS so that
+/// restoring the unit elsewhere on this machine doesn't download it.
+///
+/// Returns the unit's save request and the number of bytes uploaded once its
+/// objects are uploaded.
+async fn upload_unit(
+    cas: CourierCas,
+    local: LocalCas,
+    save_request: CargoSaveUnitRequest,
+    cas_uploads: Vec<(Key, Vec<u8>)>,
+) -> Result<(CargoSaveUnitRequest, u64)> {
+    let bytes = cas_uploads
+        .iter()
+        .map(|(_, contents)| contents.len() as u64)
+        .sum();
+    for (key, contents) in &cas_uploads {
+        if let Err(error) = local.store(key, contents).await {
+            warn!(?key, ?error, "failed to store file in local CAS");
+        }
+    }
+
+    // Large objects are the likeliest to be cut off by a dropped connection,
+    // so they're uploaded in resumable chunks instead of in the bulk request.
+    let (large, small) = cas_uploads
+        .into_iter()
+        .partition::<Vec<_>, _>(|(_, contents)| contents.len() as u64 > RESUMABLE_UPLOAD_THRESHOLD);
+    for (key, contents) in large {
+        cas.store_resumable(&key, contents).await?;
+    }
+    if !small.is_empty() {
+        cas.store_bulk(stream::iter(small)).await?;
+    }
+    Ok((save_request, bytes))
+}
+
+/// Uploads running in the background, at most `parallelism` at once.
+///
+/// Dropping the queue aborts the uploads still running, so returning early
+/// with the error of one upload cancels the rest.
+#[derive(Debug)]
+struct UploadQueue<T> {
+    uploads: JoinSet<Result<T>>,
+    parallelism: usize,
+}
+
+impl<T: Send + 'static> UploadQueue<T> {
+    fn new(parallelism: usize) -> Self {
+        Self {
+            uploads: JoinSet::new(),
+            parallelism: parallelism.max(1),
+        }
+    }
+
+    /// Whether an upload has to finish before another one is started.
+    fn is_full(&self) -> bool {
+        self.uploads.len() >= self.parallelism
+    }
+
+    fn is_empty(&self) -> bool {
+        self.uploads.is_empty()
+    }
+
+    fn spawn(&mut self, upload: impl Future<Output = Result<T>> + Send + 'static) {
+        self.uploads.spawn(upload);
+    }
+
+    /// Wait for the next upload to finish, or return `None` if none are
+    /// running.
+    async fn join_next(&mut self) -> Option<Result<T>> {
+        let upload = self.uploads.join_next().await?;
+        Some(
+            upload
+                .context("join unit upload")
+                .and_then(|upload| upload.context("upload unit")),
+        )
+    }
+}
+
+/// Wait for the next unit upload to finish, recording its save request.
+async fn join_upload(
+    uploads: &mut UploadQueue<(CargoSaveUnitRequest, u64)>,
+    save_requests: &mut Vec<CargoSaveUnitRequest>,
+    progress: &mut SaveProgress,
+    on_progress: &mut impl FnMut(&SaveProgress),
+) -> Result<()> {
+    let Some(upload) = uploads.join_next().await else {
+        return Ok(());
+    };
+    let (save_request, bytes) = upload?;
+    let unit_hash = UnitHash::from(save_request.unit.info().unit_hash.as_str());
+    save_requests.push(save_request);
+    progress.set_unit_state(&unit_hash, UnitUploadState::Done);
+    progress.uploaded_units += 1;
+    progress.queued_bytes -= bytes;
+    on_progress(progress);
+    Ok(())
+}
+
+/// Record that a unit isn't uploaded because of the state of its files.
+fn skip_invalid(progress: &mut SaveProgress, info: &UnitPlanInfo, problem: UnitProblem) {
+    warn!(
+        unit_hash = %info.unit_hash,
+        package_name = %info.package_name,
+        %problem,
+        "skipping unit backup: invalid files"
+    );
+    progress.total_units -= 1;
+    progress.set_unit_state(&info.unit_hash, UnitUploadState::Failed);
+    progress.skipped_invalid.push(InvalidUnit {
+        unit_hash: info.unit_hash.clone(),
+        package_name: info.package_name.clone(),
+        problem,
+    });
+}
+
+/// Load what the cache already stores for the units about to be saved.
+///
+/// This is best-effort: if the cache can't be queried, units are saved
+/// without being compared.
+#[instrument(skip_all)]
+async fn load_saved(
+    courier: &Courier,
+    units: &[UnitPlan],
+    skip: &Restored,
+    generation: u64,
+) -> CargoRestoreResponse {
+    let hashes = saved_hashes(units, skip, generation, courier.cache_scope());
+    if hashes.is_empty() {
+        return CargoRestoreResponse::default();
+    }
+
+    // Units are compared regardless of the glibc they were built against, so
+    // the host glibc version isn't sent.
+    match courier
+        .cargo_cache_restore(CargoRestoreRequest::new(hashes, None))
+        .await
+    {
+        Ok(saved) => saved,
+        Err(err) => {
+            warn!(?err, "load saved units to check determinism");
+            CargoRestoreResponse::default()
+        }
+    }
+}
+
+/// The hashes under which the units that weren't restored are saved, which
+/// are the same hashes their save requests use.
+fn saved_hashes(
+    units: &[UnitPlan],
+    skip: &Restored,
+    generation: u64,
+    scope: Option<&CacheScope>,
+) -> Vec<SavedUnitHash> {
+    units
+        .iter()
+        .filter(|unit| !skip.units.contains(&unit.info().unit_hash))
+        .map(|unit| {
+            let info = SavedUnitPlanInfo::from(unit.info().clone());
+            UnitHashVersion::CURRENT.derive_scoped(&info, generation, scope)
+        })
+        .collect()
+}
+
+/// Whether two saves of the same unit have the same content.
+///
+/// Files hashed with different algorithms can't be compared, so units saved
+/// with different algorithms are assumed to have the same content.
+fn same_content(saved: &courier::SavedUnit, unit: &courier::SavedUnit) -> bool {
+    let (saved, unit) = (saved.object_keys(), unit.object_keys());
+    let mut algorithms = saved.iter().chain(&unit).map(|key| key.algorithm());
+    if let Some(first) = algorithms.next()
+        && algorithms.any(|algorithm| algorithm != first)
+    {
+        return true;
+    }
+    saved == unit
+}
+
+/// Apply the upload policy to a unit, returning the decision if the unit
+/// should be skipped.
+///
+/// The rebuild time is only estimated when the decision depends on it, since
+/// doing so walks the unit's fingerprint directory.
+#[instrument(skip(ws, policy))]
+async fn skip_by_policy(
+    ws: &Workspace,
+    policy: &UploadPolicy,
+    info: &UnitPlanInfo,
+    bytes: u64,
+) -> Option<UploadDecision> {
+    let required = policy.required.contains(&info.unit_hash);
+    let mut estimated_rebuild = None;
+    let big_artifacts = info.policy.big_artifacts;
+    let mut reason = policy.decide(bytes, None, required, big_artifacts);
+    if reason == UploadReason::Unknown {
+        estimated_rebuild = estimate_rebuild(ws, info).await;
+        reason = policy.decide(bytes, estimated_rebuild, required, big_artifacts);
+    }
+
+    let decision = UploadDecision {
+        unit_hash: info.unit_hash.clone(),
+        package_name: info.package_name.clone(),
+        bytes,
+        estimated_rebuild,
+        reason,
+    };
+    debug!(?decision, "upload policy decision");
+    (!reason.upload()).then_some(decision)
 }
 
 /// Rewrite fingerprint `src_path`s to be rooted at a static `$CARGO_HOME`.
@@ -363,6 +1187,10 @@ async fn rewrite_fingerprint(
                     .join(p)
                     .conv::<PathBuf>()
                     .pipe(Some),
+                // Cargo already fingerprints the paths of sources in the
+                // workspace relative to its root, so they're the same on
+                // every machine.
+                QualifiedPath::RelativeWorkspace(_) => None,
             }
         }
         None => None,
@@ -372,3 +1200,284 @@ async fn rewrite_fingerprint(
         .conv::<courier::Fingerprint>()
         .pipe(Ok)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeSet,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use clients::courier::v1::{
+        CacheScope, Fingerprint as SavedFingerprint, HashAlgorithm, Key,
+        LibraryCrateUnitPlan as SavedLibraryCratePlan, LibraryFiles, SavedUnit, UnitHashVersion,
+        UnitPlanInfo as SavedUnitPlanInfo, cache::CargoSaveUnitRequest,
+    };
+    use color_eyre::eyre::eyre;
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+
+    use super::{
+        NondeterministicUnit, SaveProgress, UnitUploadProgress, UnitUploadState, UploadQueue,
+        same_content, saved_hashes,
+    };
+    use crate::{
+        cargo::{
+            CratePolicy, LibraryCrateUnitPlan, Restored, RustcTarget, UnitHash, UnitPlan,
+            UnitPlanInfo,
+        },
+        path::AbsFilePath,
+    };
+
+    fn make_unit_plan(hash: &str, package: &str) -> UnitPlan {
+        UnitPlan::LibraryCrate(LibraryCrateUnitPlan {
+            info: UnitPlanInfo {
+                unit_hash: hash.into(),
+                package_name: String::from(package),
+                package_version: String::from("1.0.0"),
+                crate_name: String::from(package),
+                target_arch: RustcTarget::ImplicitHost,
+                deps: vec![],
+                policy: CratePolicy::default(),
+                source_hash: None,
+            },
+            src_path: AbsFilePath::try_from("/test/src/lib.rs").unwrap(),
+            outputs: vec![],
+            features: BTreeSet::new(),
+            near_match_key: None,
+            unhashed_outputs: false,
+            fingerprint_hash: None,
+        })
+    }
+
+    fn make_saved_unit(info: SavedUnitPlanInfo, dep_info: Key) -> SavedUnit {
+        let files = LibraryFiles::builder()
+            .output_files(vec![])
+            .fingerprint(SavedFingerprint::from(String::from("test-fingerprint")))
+            .dep_info_file(dep_info)
+            .encoded_dep_info_file(Key::from_buffer(b"encoded-dep-info"))
+            .build();
+        let plan = SavedLibraryCratePlan::builder()
+            .info(info)
+            .src_path("test.rs")
+            .outputs(vec![] as Vec<clients::courier::v1::DiskPath>)
+            .build();
+        SavedUnit::LibraryCrate(files, plan)
+    }
+
+    fn saved_with_dep_info(dep_info: Key) -> SavedUnit {
+        let info = SavedUnitPlanInfo::builder()
+            .unit_hash("a")
+            .package_name("pkg-a")
+            .crate_name("pkg-a")
+            .build();
+        make_saved_unit(info, dep_info)
+    }
+
+    fn unit_progress(hash: &str, package: &str) -> UnitUploadProgress {
+        UnitUploadProgress {
+            unit_hash: UnitHash::from(hash),
+            package_name: String::from(package),
+            state: UnitUploadState::Hashing,
+            bytes: 0,
+        }
+    }
+
+    fn nondeterministic(hash: &str, package: &str, refused: bool) -> NondeterministicUnit {
+        NondeterministicUnit {
+            unit_hash: UnitHash::from(hash),
+            package_name: String::from(package),
+            refused,
+        }
+    }
+
+    #[test_case(Key::from_buffer(b"dep-info"), Key::from_buffer(b"dep-info"), true; "identical")]
+    #[test_case(Key::from_buffer(b"dep-info"), Key::from_buffer(b"other"), false; "differing")]
+    #[test_case(Key::from_buffer_with(HashAlgorithm::Sha256, b"dep-info"), Key::from_buffer(b"other"), true; "mixed algorithms")]
+    #[test]
+    fn compares_content(saved: Key, unit: Key, expected: bool) {
+        let saved = saved_with_dep_info(saved);
+        let unit = saved_with_dep_info(unit);
+        pretty_assert_eq!(same_content(&saved, &unit), expected);
+    }
+
+    #[test]
+    fn refused_unit_is_skipped() {
+        let mut progress = SaveProgress {
+            total_units: 2,
+            uploaded_files: 3,
+            uploaded_bytes: 10,
+            units: vec![unit_progress("a", "pkg-a"), unit_progress("b", "pkg-b")],
+            ..SaveProgress::default()
+        };
+        let uploads = vec![
+            (Key::from_buffer(b"abc"), b"abc".to_vec()),
+            (Key::from_buffer(b"de"), b"de".to_vec()),
+        ];
+        progress.record_nondeterministic(nondeterministic("a", "pkg-a", true), &uploads);
+
+        let expected = SaveProgress {
+            total_units: 1,
+            uploaded_files: 1,
+            uploaded_bytes: 5,
+            nondeterministic: vec![nondeterministic("a", "pkg-a", true)],
+            units: vec![
+                UnitUploadProgress {
+                    state: UnitUploadState::Skipped,
+                    ..unit_progress("a", "pkg-a")
+                },
+                unit_progress("b", "pkg-b"),
+            ],
+            ..SaveProgress::default()
+        };
+        pretty_assert_eq!(progress, expected);
+    }
+
+    #[test]
+    fn warned_unit_is_uploaded() {
+        let mut progress = SaveProgress {
+            total_units: 1,
+            uploaded_files: 1,
+            uploaded_bytes: 3,
+            units: vec![unit_progress("a", "pkg-a")],
+            ..SaveProgress::default()
+        };
+        let uploads = vec![(Key::from_buffer(b"abc"), b"abc".to_vec())];
+        progress.record_nondeterministic(nondeterministic("a", "pkg-a", false), &uploads);
+
+        let expected = SaveProgress {
+            total_units: 1,
+            uploaded_files: 1,
+            uploaded_bytes: 3,
+            nondeterministic: vec![nondeterministic("a", "pkg-a", false)],
+            units: vec![unit_progress("a", "pkg-a")],
+            ..SaveProgress::default()
+        };
+        pretty_assert_eq!(progress, expected);
+    }
+
+    #[test]
+    fn determinism_summary() {
+        pretty_assert_eq!(SaveProgress::default().determinism_summary(), None);
+
+        let progress = SaveProgress {
+            nondeterministic: vec![
+                nondeterministic("a", "pkg-a", true),
+                nondeterministic("b", "pkg-b", false),
+            ],
+            ..SaveProgress::default()
+        };
+        pretty_assert_eq!(
+            progress.determinism_summary().as_deref(),
+            Some(
+                "[hurry] 2 units built differently than the cached copy of the same unit (nondeterministic build?):\n\
+                 [hurry]   pkg-a (a, not uploaded)\n\
+                 [hurry]   pkg-b (b, uploaded)"
+            )
+        );
+    }
+
+    #[test]
+    fn saved_hashes_match_save_requests() {
+        let unit = make_unit_plan("a", "pkg-a");
+        let units = vec![unit.clone(), make_unit_plan("b", "pkg-b")];
+        let skip = Restored::default();
+        skip.units.insert(UnitHash::from("b"));
+        let scope = CacheScope::new("org/repo").unwrap();
+
+        let hashes = saved_hashes(&units, &skip, 3, Some(&scope));
+
+        let info = SavedUnitPlanInfo::from(unit.info().clone());
+        let request = CargoSaveUnitRequest::builder()
+            .unit(make_saved_unit(info, Key::from_buffer(b"dep-info")))
+            .resolved_target(String::from("x86_64-unknown-linux-gnu"))
+            .unit_hash_version(UnitHashVersion::CURRENT)
+            .cache_generation(3)
+            .cache_scope(scope.clone())
+            .build();
+        pretty_assert_eq!(hashes, vec![request.saved_unit_hash()]);
+        assert_ne!(
+            saved_hashes(&units, &skip, 4, Some(&scope)),
+            hashes,
+            "the generation is part of the hash"
+        );
+    }
+
+    #[test_case(1; "one at a time")]
+    #[test_case(3; "several at once")]
+    #[test_case(0; "zero runs one at a time")]
+    #[tokio::test]
+    async fn uploads_are_bounded_by_parallelism(parallelism: usize) {
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let mut queue = UploadQueue::new(parallelism);
+        let mut finished = Vec::new();
+        for unit in 0..10 {
+            while queue.is_full() {
+                finished.push(queue.join_next().await.unwrap().unwrap());
+            }
+            let running = running.clone();
+            let max_running = max_running.clone();
+            queue.spawn(async move {
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now_running, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(unit)
+            });
+        }
+        while let Some(upload) = queue.join_next().await {
+            finished.push(upload.unwrap());
+        }
+
+        finished.sort();
+        pretty_assert_eq!(finished, (0..10).collect::<Vec<_>>());
+        pretty_assert_eq!(
+            max_running.load(Ordering::SeqCst),
+            parallelism.max(1),
+            "uploads in flight should reach but never exceed the parallelism"
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_upload_is_returned_and_cancels_the_rest() {
+        let slow_finished = Arc::new(AtomicBool::new(false));
+        let mut queue = UploadQueue::<u32>::new(4);
+        queue.spawn({
+            let slow_finished = slow_finished.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                slow_finished.store(true, Ordering::SeqCst);
+                Ok(1)
+            }
+        });
+        queue.spawn(async { Err(eyre!("courier rejected the upload")) });
+
+        let err = queue.join_next().await.unwrap().unwrap_err();
+        let causes = err.chain().map(ToString::to_string).collect::<Vec<_>>();
+        pretty_assert_eq!(causes, vec!["upload unit", "courier rejected the upload"]);
+
+        // Returning the error drops the queue, which aborts the uploads that
+        // are still running.
+        drop(queue);
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(!slow_finished.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn panicked_upload_is_an_error() {
+        async fn upload() -> color_eyre::Result<u32> {
+            panic!("upload panicked")
+        }
+
+        let mut queue = UploadQueue::new(1);
+        queue.spawn(upload());
+        let err = queue.join_next().await.unwrap().unwrap_err();
+        pretty_assert_eq!(err.to_string(), "join unit upload");
+        assert!(queue.join_next().await.is_none());
+    }
+}
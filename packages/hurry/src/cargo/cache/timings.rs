@@ -0,0 +1,286 @@
+//! Build timings for adaptive restore.
+//!
+//! For small crates on fast machines, downloading a unit's artifacts can take
+//! longer than compiling it. With adaptive restore enabled (see
+//! [`AdaptiveRestoreConfig`]), Hurry records how long Cargo took to build each
+//! library unit and how large its outputs were, along with how quickly
+//! restores download, in the workspace's state directory. Restore then leaves
+//! units whose recorded build time is below their estimated download time for
+//! Cargo to build.
+//!
+//! Build times are estimated from Cargo's fingerprint timestamps (see
+//! [`estimate_rebuild`]), so they're only recorded for units that Cargo built
+//! itself. They're recorded per package version, crate, target, and profile
+//! rather than per unit hash, so that a unit's timing carries over to other
+//! builds of it with different features or dependencies.
+//!
+//! Only units that nothing else being restored depends on are left for Cargo:
+//! restore can't rewrite the fingerprints of units whose dependencies Cargo
+//! builds, so leaving a unit for Cargo would make Cargo build its dependents
+//! too.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::Duration,
+};
+
+use color_eyre::{Result, eyre::Context as _};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    cargo::{
+        Restored, UnitHash, UnitPlan, UnitPlanInfo, Workspace, cache::policy::estimate_rebuild,
+    },
+    config::AdaptiveRestoreConfig,
+    fs, mk_rel_file,
+    path::{AbsFilePath, JoinWith as _},
+    state,
+};
+
+/// The most units whose timings are kept. Past this, the timings recorded
+/// longest ago are dropped.
+const MAX_RECORDED_UNITS: usize = 5000;
+
+/// Restores that download less than this aren't used to measure throughput,
+/// since their time is dominated by request latency rather than bandwidth.
+const MIN_THROUGHPUT_SAMPLE: u64 = 4 * 1024 * 1024;
+
+/// How long it took Cargo to build a unit, and how large its outputs were.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UnitTiming {
+    /// The estimated build time, in milliseconds.
+    pub build_ms: u64,
+
+    /// The total size of the unit's output files.
+    pub bytes: u64,
+
+    /// When the timing was recorded, in seconds since the Unix epoch.
+    pub recorded_at: i64,
+}
+
+/// The build timings and restore throughput recorded for a workspace.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UnitTimings {
+    #[serde(default)]
+    units: BTreeMap<String, UnitTiming>,
+
+    /// The measured restore throughput, in bytes per second.
+    #[serde(default)]
+    throughput: Option<u64>,
+}
+
+impl UnitTimings {
+    /// The path of the timings file in the workspace's state directory.
+    async fn path(ws: &Workspace) -> Result<AbsFilePath> {
+        Ok(state::dir(ws)
+            .await?
+            .join(mk_rel_file!("unit-timings.json")))
+    }
+
+    /// Load the timings recorded for the workspace.
+    ///
+    /// Timings that can't be read are treated as missing, since they're only
+    /// an optimization and are recorded again by later builds.
+    #[instrument(name = "UnitTimings::load", skip(ws))]
+    pub async fn load(ws: &Workspace) -> Self {
+        let load = async {
+            let path = Self::path(ws).await?;
+            match fs::read_buffered(&path).await? {
+                Some(content) => serde_json::from_slice(&content).context("parse unit timings"),
+                None => Ok(Self::default()),
+            }
+        };
+        match load.await {
+            Ok(timings) => timings,
+            Err(err) => {
+                warn!(?err, "failed to load unit timings");
+                Self::default()
+            }
+        }
+    }
+
+    /// Save the timings for the workspace, dropping the oldest beyond
+    /// [`MAX_RECORDED_UNITS`].
+    #[instrument(name = "UnitTimings::save", skip_all)]
+    pub async fn save(mut self, ws: &Workspace) -> Result<()> {
+        if self.units.len() > MAX_RECORDED_UNITS {
+            let mut recorded = self
+                .units
+                .values()
+                .map(|timing| timing.recorded_at)
+                .collect::<Vec<_>>();
+            recorded.sort_unstable_by(|a, b| b.cmp(a));
+            let cutoff = recorded[MAX_RECORDED_UNITS - 1];
+            self.units.retain(|_, timing| timing.recorded_at >= cutoff);
+        }
+        let content = serde_json::to_vec(&self).context("serialize unit timings")?;
+        fs::write(&Self::path(ws).await?, content).await
+    }
+
+    /// The key a unit's timing is recorded under.
+    fn key(ws: &Workspace, info: &UnitPlanInfo) -> String {
+        format!(
+            "{}@{} {} {} {}",
+            info.package_name,
+            info.package_version,
+            info.crate_name,
+            info.target_arch.as_str().unwrap_or("host"),
+            ws.profile.as_str(),
+        )
+    }
+
+    /// The timing recorded for the unit, if any.
+    pub fn get(&self, ws: &Workspace, info: &UnitPlanInfo) -> Option<&UnitTiming> {
+        self.units.get(&Self::key(ws, info))
+    }
+
+    /// The measured restore throughput, in bytes per second.
+    pub fn throughput(&self) -> Option<u64> {
+        self.throughput
+    }
+
+    /// Record the build times of the library units that Cargo built.
+    ///
+    /// Units in `restored` weren't built by Cargo, so they're skipped.
+    #[instrument(name = "UnitTimings::record_builds", skip_all)]
+    pub async fn record_builds(&mut self, ws: &Workspace, units: &[UnitPlan], restored: &Restored) {
+        let now = jiff::Timestamp::now().as_second();
+        for unit in units {
+            let UnitPlan::LibraryCrate(plan) = unit else {
+                continue;
+            };
+            if restored.units.contains(&plan.info.unit_hash) {
+                continue;
+            }
+            let Some(build) = estimate_rebuild(ws, &plan.info).await else {
+                continue;
+            };
+            let mut bytes = 0;
+            for output in &plan.outputs {
+                if let Ok(Some(metadata)) = fs::metadata(output).await {
+                    bytes += metadata.len();
+                }
+            }
+            let timing = UnitTiming {
+                build_ms: build.as_millis() as u64,
+                bytes,
+                recorded_at: now,
+            };
+            self.units.insert(Self::key(ws, &plan.info), timing);
+        }
+    }
+
+    /// Record the throughput of a restore that downloaded `bytes` in
+    /// `elapsed`.
+    ///
+    /// Each measurement is averaged with the previous throughput, so that a
+    /// single slow or fast restore doesn't swing decisions on its own.
+    pub fn record_throughput(&mut self, bytes: u64, elapsed: Duration) {
+        if bytes < MIN_THROUGHPUT_SAMPLE || elapsed.is_zero() {
+            return;
+        }
+        let measured = (u128::from(bytes) * 1000 / elapsed.as_millis().max(1)) as u64;
+        let throughput = match self.throughput {
+            Some(previous) => (previous + measured) / 2,
+            None => measured,
+        };
+        debug!(measured, throughput, "recorded restore throughput");
+        self.throughput = Some(throughput);
+    }
+}
+
+/// A unit that was left for Cargo to build because it's estimated to build
+/// faster than it downloads.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct RestoreDecision {
+    pub unit_hash: UnitHash,
+    pub package_name: String,
+    pub bytes: u64,
+    pub build: Duration,
+    pub download: Duration,
+}
+
+/// Choose which units to leave for Cargo to build because they build faster
+/// than they download.
+///
+/// `restorable` are the units that restore would otherwise restore, and
+/// `available` are those along with the units that are already on disk.
+/// Units must be in dependency order.
+pub fn cheaper_to_build(
+    ws: &Workspace,
+    units: &[UnitPlan],
+    restorable: &HashSet<UnitHash>,
+    available: &HashSet<UnitHash>,
+    timings: &UnitTimings,
+    config: AdaptiveRestoreConfig,
+) -> HashMap<UnitHash, RestoreDecision> {
+    let mut decisions = HashMap::new();
+    let Some(throughput) = timings.throughput().filter(|_| config.enabled) else {
+        return decisions;
+    };
+
+    // Visit dependents before their dependencies, so that by the time a unit
+    // is visited we know whether anything that's kept depends on it.
+    let mut depended_on = HashSet::new();
+    for unit in units.iter().rev() {
+        let info = unit.info();
+        if !available.contains(&info.unit_hash) {
+            continue;
+        }
+        if restorable.contains(&info.unit_hash)
+            && !depended_on.contains(&info.unit_hash)
+            && matches!(unit, UnitPlan::LibraryCrate(_))
+            && let Some(timing) = timings.get(ws, info)
+        {
+            let build = Duration::from_millis(timing.build_ms);
+            let download =
+                Duration::from_millis(timing.bytes.saturating_mul(1000) / throughput.max(1));
+            if config.cheaper_to_build(build, download) {
+                debug!(
+                    unit_hash = %info.unit_hash,
+                    pkg_name = %info.package_name,
+                    ?build,
+                    ?download,
+                    "leaving unit for cargo: cheaper to build than download"
+                );
+                let decision = RestoreDecision {
+                    unit_hash: info.unit_hash.clone(),
+                    package_name: info.package_name.clone(),
+                    bytes: timing.bytes,
+                    build,
+                    download,
+                };
+                decisions.insert(info.unit_hash.clone(), decision);
+                continue;
+            }
+        }
+        depended_on.extend(info.deps.iter().cloned());
+    }
+    decisions
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::{MIN_THROUGHPUT_SAMPLE, UnitTimings};
+
+    #[test]
+    fn records_throughput() {
+        let mut timings = UnitTimings::default();
+        timings.record_throughput(1024, Duration::from_millis(1));
+        pretty_assert_eq!(timings.throughput(), None);
+
+        timings.record_throughput(100 * 1024 * 1024, Duration::from_secs(1));
+        pretty_assert_eq!(timings.throughput(), Some(100 * 1024 * 1024));
+
+        timings.record_throughput(100 * 1024 * 1024, Duration::from_secs(2));
+        pretty_assert_eq!(timings.throughput(), Some(75 * 1024 * 1024));
+
+        timings.record_throughput(MIN_THROUGHPUT_SAMPLE, Duration::ZERO);
+        pretty_assert_eq!(timings.throughput(), Some(75 * 1024 * 1024));
+    }
+}
@@ -0,0 +1,193 @@
+//! Validation of the files planned for upload.
+//!
+//! Units are read from disk one at a time during the upload, so a file that's
+//! missing or unreadable used to surface as an error partway through, after
+//! earlier units had already been uploaded. Validating every unit up front
+//! lets the upload skip just the affected units and report why.
+
+use derive_more::Display;
+use futures::TryStreamExt as _;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument};
+
+use crate::{
+    cargo::{QualifiedPath, UnitHash, UnitPlan, Workspace},
+    fs,
+    path::{AbsDirPath, AbsFilePath, JoinWith as _},
+};
+
+/// File extensions of outputs that Cargo only produces with some platforms
+/// and configurations (split debuginfo), so it's expected for them to be
+/// missing.
+const OPTIONAL_OUTPUT_EXTENSIONS: [&str; 3] = ["dwp", "dSYM", "pdb"];
+
+/// A unit that isn't uploaded because of the state of its files on disk.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct InvalidUnit {
+    pub unit_hash: UnitHash,
+    pub package_name: String,
+    pub problem: UnitProblem,
+}
+
+/// Why a unit can't be uploaded.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Display, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum UnitProblem {
+    /// A file the unit needs doesn't exist.
+    #[display("missing {path}")]
+    Missing { path: String },
+
+    /// A file the unit needs exists but can't be read.
+    #[display("can't read {path}: {error}")]
+    Unreadable { path: String, error: String },
+
+    /// A file's path isn't valid UTF-8, so restoring it would write it to the
+    /// wrong path.
+    #[display("path is not valid UTF-8: {path}")]
+    NonUtf8Path { path: String },
+
+    /// The unit's fingerprint can't be rewritten, usually because a
+    /// dependency's fingerprint couldn't be read.
+    #[display("can't rewrite fingerprint: {error}")]
+    Fingerprint { error: String },
+}
+
+/// Check that the files a unit uploads exist and are readable, without
+/// reading them.
+///
+/// Outputs that are expected to be missing (see
+/// [`OPTIONAL_OUTPUT_EXTENSIONS`]) are removed from the unit's plan instead of
+/// failing validation.
+#[instrument(skip_all, fields(unit_hash = %unit.info().unit_hash))]
+pub async fn validate_unit(ws: &Workspace, unit: &mut UnitPlan) -> Option<UnitProblem> {
+    let profile_dir = ws.unit_profile_dir(unit.info());
+    let fingerprint = [unit.fingerprint_json_file(), unit.fingerprint_hash_file()];
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    let paths = match unit {
+        UnitPlan::LibraryCrate(plan) => {
+            let mut outputs = Vec::with_capacity(plan.outputs.len());
+            for output in std::mem::take(&mut plan.outputs) {
+                if is_optional_output(&output) && !fs::exists(&output).await {
+                    debug!(?output, "dropping optional output that wasn't produced");
+                    continue;
+                }
+                files.push(output.clone());
+                outputs.push(output);
+            }
+            plan.outputs = outputs;
+            vec![plan.dep_info_file(), plan.encoded_dep_info_file()]
+        }
+        UnitPlan::BuildScriptCompilation(plan) => vec![
+            plan.program_file(),
+            plan.dep_info_file(),
+            plan.encoded_dep_info_file(),
+        ],
+        UnitPlan::BuildScriptExecution(plan) => {
+            match plan.out_dir() {
+                Ok(out_dir) => dirs.push(profile_dir.join(out_dir)),
+                Err(err) => return Some(unknown_path(err)),
+            }
+            vec![plan.stdout_file(), plan.stderr_file()]
+        }
+    };
+    for path in paths.into_iter().chain(fingerprint) {
+        match path {
+            Ok(path) => files.push(profile_dir.join(path)),
+            Err(err) => return Some(unknown_path(err)),
+        }
+    }
+
+    for dir in dirs {
+        match walk_dir(&dir).await {
+            Ok(walked) => files.extend(walked),
+            Err(problem) => return Some(problem),
+        }
+    }
+    for file in &files {
+        if let Some(problem) = check_file(ws, unit, file).await {
+            return Some(problem);
+        }
+    }
+    None
+}
+
+/// Check that a file exists, is readable, and has a path that can be
+/// restored.
+async fn check_file(ws: &Workspace, unit: &UnitPlan, file: &AbsFilePath) -> Option<UnitProblem> {
+    let path = file.to_string();
+    if !fs::exists(file).await {
+        return Some(UnitProblem::Missing { path });
+    }
+    if let Err(err) = fs::open_file(file).await {
+        return Some(UnitProblem::Unreadable {
+            path,
+            error: format!("{err:#}"),
+        });
+    }
+    let qualified = QualifiedPath::parse_abs(ws, &unit.info().target_arch, file);
+    (!qualified.is_utf8()).then_some(UnitProblem::NonUtf8Path { path })
+}
+
+/// List the files in a directory the unit uploads.
+async fn walk_dir(dir: &AbsDirPath) -> Result<Vec<AbsFilePath>, UnitProblem> {
+    if !fs::exists(dir).await {
+        return Err(UnitProblem::Missing {
+            path: dir.to_string(),
+        });
+    }
+    fs::walk_files(dir)
+        .try_collect::<Vec<_>>()
+        .await
+        .map_err(|err| UnitProblem::Unreadable {
+            path: dir.to_string(),
+            error: format!("{err:#}"),
+        })
+}
+
+/// Report a file whose path couldn't be computed from the unit's plan.
+fn unknown_path(err: color_eyre::Report) -> UnitProblem {
+    UnitProblem::Unreadable {
+        path: String::from("<unknown>"),
+        error: format!("{err:#}"),
+    }
+}
+
+fn is_optional_output(path: &AbsFilePath) -> bool {
+    path.as_std_path()
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| OPTIONAL_OUTPUT_EXTENSIONS.contains(&extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+
+    use super::{UnitProblem, is_optional_output};
+    use crate::path::AbsFilePath;
+
+    #[test_case("/target/debug/deps/libfoo-0123.so.dwp", true; "dwp")]
+    #[test_case("/target/debug/deps/libfoo-0123.dylib.dSYM", true; "dsym")]
+    #[test_case("/target/debug/deps/foo.pdb", true; "pdb")]
+    #[test_case("/target/debug/deps/libfoo-0123.rlib", false; "rlib")]
+    #[test_case("/target/debug/deps/libfoo-0123.rmeta", false; "rmeta")]
+    #[test]
+    fn optional_outputs(path: &str, expected: bool) {
+        let path = AbsFilePath::try_from(path).unwrap();
+        pretty_assert_eq!(is_optional_output(&path), expected);
+    }
+
+    #[test]
+    fn problem_display() {
+        let problem = UnitProblem::Unreadable {
+            path: String::from("/target/debug/deps/libfoo.rlib"),
+            error: String::from("permission denied"),
+        };
+        pretty_assert_eq!(
+            problem.to_string(),
+            "can't read /target/debug/deps/libfoo.rlib: permission denied"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! Cargo's configuration files.
+//!
+//! Cargo reads `.cargo/config.toml` in the directory it's invoked in and in
+//! each of its ancestors, then `config.toml` in `$CARGO_HOME`; files closer to
+//! the working directory take precedence[^1]. Hurry reads the few keys it
+//! needs directly rather than asking Cargo, since Cargo has no cheap way to
+//! report them.
+//!
+//! [^1]: https://doc.rust-lang.org/cargo/reference/config.html#hierarchical-structure
+
+use color_eyre::Result;
+use serde::de::DeserializeOwned;
+use tracing::trace;
+
+use crate::{
+    fs,
+    path::{AbsDirPath, AbsFilePath, TryJoinWith as _},
+};
+
+/// A Cargo configuration file.
+#[derive(Clone, Debug)]
+pub struct ConfigFile<T> {
+    /// The path of the file.
+    pub path: AbsFilePath,
+
+    /// The directory that relative paths in the file are relative to, which
+    /// is the parent of the directory containing the file.
+    pub base: AbsDirPath,
+
+    /// The parsed content of the file.
+    pub config: T,
+}
+
+/// Read the configuration files that apply to Cargo invoked in `cwd`, in
+/// order of precedence.
+///
+/// Files that can't be parsed as `T` are skipped; Cargo reports them properly
+/// when it runs.
+pub async fn read<T: DeserializeOwned>(
+    cwd: &AbsDirPath,
+    cargo_home: Option<&AbsDirPath>,
+) -> Result<Vec<ConfigFile<T>>> {
+    let mut dirs = std::iter::successors(Some(cwd.clone()), |dir| dir.parent())
+        .map(|dir| {
+            dir.try_join_dir(".cargo")
+                .map(|config_dir| (config_dir, dir))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(home) = cargo_home
+        && let Some(parent) = home.parent()
+        && !dirs.iter().any(|(config_dir, _)| config_dir == home)
+    {
+        dirs.push((home.clone(), parent));
+    }
+
+    let mut files = Vec::new();
+    for (config_dir, base) in dirs {
+        // Cargo reads the legacy extensionless name when `config.toml`
+        // doesn't exist.
+        for name in ["config.toml", "config"] {
+            let path = config_dir.try_join_file(name)?;
+            let Some(content) = fs::read_buffered_utf8(&path).await? else {
+                continue;
+            };
+            match toml::from_str::<T>(&content) {
+                Ok(config) => files.push(ConfigFile { path, base, config }),
+                Err(err) => trace!(?path, ?err, "parse cargo config"),
+            }
+            break;
+        }
+    }
+    Ok(files)
+}
+
+/// The `$CARGO_HOME` for Cargo invoked in `cwd`, if it can be determined.
+pub fn cargo_home(cwd: &AbsDirPath) -> Option<AbsDirPath> {
+    home::cargo_home_with_cwd(cwd.as_std_path())
+        .ok()
+        .and_then(|home| AbsDirPath::try_from(home).ok())
+}
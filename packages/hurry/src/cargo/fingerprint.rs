@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     hash::{Hash, Hasher},
     path::PathBuf,
     sync::{Arc, Mutex},
@@ -7,7 +7,7 @@ use std::{
 
 use color_eyre::{
     Result,
-    eyre::{OptionExt as _, bail},
+    eyre::{Context as _, OptionExt as _, bail},
 };
 use derive_more::Debug;
 use rustc_stable_hash::StableSipHasher128;
@@ -149,6 +149,31 @@ impl Fingerprint {
         Ok(fingerprint)
     }
 
+    /// The features the unit was compiled with.
+    pub fn features(&self) -> Result<BTreeSet<String>> {
+        // Cargo renders the sorted feature list with `Debug`, which for
+        // feature names is the same as rendering it as JSON.
+        serde_json::from_str::<BTreeSet<String>>(&self.features)
+            .with_context(|| format!("parse fingerprint features: {}", self.features))
+    }
+
+    /// Replace the features the unit was compiled with.
+    ///
+    /// This is used when restoring a near match, whose fingerprint otherwise
+    /// records features that differ from the ones Cargo expects.
+    pub fn set_features(&mut self, features: &BTreeSet<String>) {
+        self.features = format!("{:?}", features.iter().collect::<Vec<_>>());
+        self.clear_memoized();
+    }
+
+    /// Whether every dependency of the fingerprint has a known replacement,
+    /// i.e. whether [`Fingerprint::rewrite`] will succeed.
+    pub fn deps_resolvable(&self, dep_fingerprints: &HashMap<u64, Fingerprint>) -> bool {
+        self.deps
+            .iter()
+            .all(|dep| dep_fingerprints.contains_key(&dep.fingerprint.hash_u64()))
+    }
+
     /// Create a new Fingerprint with rewritten path and dependencies.
     #[instrument(skip(self, dep_fingerprints))]
     pub fn rewrite(
@@ -0,0 +1,302 @@
+//! Garbage collection for the build directory.
+//!
+//! Cargo never removes the artifacts of units it no longer builds, so build
+//! directories grow without bound as dependencies are upgraded and features
+//! change. Every unit's artifacts are named after its unit hash (e.g.
+//! `deps/libserde-0123456789abcdef.rlib` or `.fingerprint/serde-0123456789abcdef`),
+//! so anything named after a hash that the current build plan doesn't use is
+//! stale.
+
+use std::{collections::HashSet, fmt::Debug};
+
+use color_eyre::Result;
+use derive_more::Display;
+use futures::TryStreamExt as _;
+use tracing::{debug, instrument, trace};
+
+use crate::{
+    cargo::{BuildPlan, CargoBuildArguments, RustcTarget, UnitHash, UnitPlan, Workspace},
+    fs,
+    path::{AbsDirPath, AbsFilePath, TryJoinWith as _},
+};
+
+/// The directories of a profile directory that hold per-unit artifacts.
+///
+/// Other directories (e.g. `incremental`) and the artifacts Cargo uplifts into
+/// the profile directory itself aren't named after unit hashes, so they're
+/// left alone.
+const UNIT_DIRS: [&str; 3] = [".fingerprint", "build", "deps"];
+
+/// An artifact that no unit of the build plan uses.
+#[derive(Clone, Debug, Display)]
+pub enum StaleArtifact {
+    #[display("{_0}")]
+    File(AbsFilePath, u64),
+
+    #[display("{_0}")]
+    Dir(AbsDirPath, u64),
+}
+
+impl StaleArtifact {
+    /// The size of the artifact in bytes, including everything in it if it's
+    /// a directory.
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::File(_, size) | Self::Dir(_, size) => *size,
+        }
+    }
+
+    /// Remove the artifact.
+    pub async fn remove(&self) -> Result<()> {
+        match self {
+            Self::File(path, _) => fs::remove_file(path).await,
+            Self::Dir(path, _) => fs::remove_dir_all(path).await,
+        }
+    }
+}
+
+/// Compute the build plan for `args`, returning the unit hashes that its
+/// artifacts are named after along with its (cacheable) units.
+///
+/// The hashes include first-party units, which Hurry usually doesn't cache,
+/// since their artifacts are just as much in use.
+#[instrument]
+pub async fn referenced_units(
+    ws: &Workspace,
+    args: impl AsRef<CargoBuildArguments> + Debug,
+) -> Result<(HashSet<UnitHash>, Vec<UnitPlan>)> {
+    let build_plan = ws.build_plan(args).await?;
+    let hashes = referenced_hashes(&build_plan);
+    let units = ws.units_from_build_plan(build_plan).await?;
+    Ok((hashes, units))
+}
+
+/// The unit hashes that the build plan's invocations name artifacts after.
+fn referenced_hashes(build_plan: &BuildPlan) -> HashSet<UnitHash> {
+    let mut hashes = HashSet::new();
+    for invocation in &build_plan.invocations {
+        let outputs = invocation
+            .outputs
+            .iter()
+            .filter_map(|output| output.rsplit(['/', '\\']).next());
+        let flags = invocation.args.iter().filter_map(|arg| {
+            arg.strip_prefix("extra-filename=-")
+                .or_else(|| arg.strip_prefix("metadata="))
+        });
+        let out_dir = invocation
+            .env
+            .get("OUT_DIR")
+            .and_then(|dir| dir.rsplit(['/', '\\']).nth(1));
+        hashes.extend(
+            outputs
+                .chain(out_dir)
+                .filter_map(artifact_hash)
+                .chain(flags.filter(|hash| is_unit_hash(hash)))
+                .map(|hash| UnitHash::from(hash.to_string())),
+        );
+    }
+    trace!(?hashes, "referenced unit hashes");
+    hashes
+}
+
+/// Find the artifacts in the workspace's profile directories that are named
+/// after unit hashes other than `keep`.
+#[instrument(skip(keep))]
+pub async fn stale_artifacts(
+    ws: &Workspace,
+    keep: &HashSet<UnitHash>,
+) -> Result<Vec<StaleArtifact>> {
+    let mut profile_dirs = vec![ws.arch_profile_dir(&RustcTarget::ImplicitHost)];
+    let target_dir = ws.arch_profile_dir(&ws.target_arch);
+    if !profile_dirs.contains(&target_dir) {
+        profile_dirs.push(target_dir);
+    }
+
+    let mut stale = Vec::new();
+    for profile_dir in profile_dirs {
+        for unit_dir in UNIT_DIRS {
+            let dir = profile_dir.try_join_dir(unit_dir)?;
+            if !fs::is_dir(dir.as_std_path()).await {
+                continue;
+            }
+            let mut entries = fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name();
+                let Some(hash) = name.to_str().and_then(artifact_hash) else {
+                    continue;
+                };
+                if keep.contains(&UnitHash::from(hash.to_string())) {
+                    continue;
+                }
+                let path = entry.path();
+                let artifact = if entry.file_type().await?.is_dir() {
+                    let path = AbsDirPath::try_from(path)?;
+                    let size = fs::walk_files(&path)
+                        .try_fold(0, |size, file| async move {
+                            let len = fs::metadata(file.as_std_path())
+                                .await?
+                                .map(|metadata| metadata.len())
+                                .unwrap_or_default();
+                            Result::<_>::Ok(size + len)
+                        })
+                        .await?;
+                    StaleArtifact::Dir(path, size)
+                } else {
+                    let size = entry.metadata().await?.len();
+                    StaleArtifact::File(AbsFilePath::try_from(path)?, size)
+                };
+                debug!(%artifact, "stale artifact");
+                stale.push(artifact);
+            }
+        }
+    }
+    Ok(stale)
+}
+
+/// Parse the unit hash out of an artifact name like `libserde-<hash>.rlib`,
+/// `serde-<hash>.d`, or `serde-<hash>`.
+fn artifact_hash(name: &str) -> Option<&str> {
+    let stem = name.split_once('.').map_or(name, |(stem, _)| stem);
+    let (_, hash) = stem.rsplit_once('-')?;
+    is_unit_hash(hash).then_some(hash)
+}
+
+/// Cargo's unit hashes are 16 lowercase hex digits.
+fn is_unit_hash(hash: &str) -> bool {
+    hash.len() == 16
+        && hash
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+    use tempfile::TempDir;
+
+    use super::{artifact_hash, stale_artifacts};
+    use crate::{
+        cargo::{CargoBuildArguments, UnitHash, Workspace},
+        fs,
+        path::{AbsDirPath, AbsFilePath},
+    };
+
+    const KEPT: &str = "0123456789abcdef";
+    const STALE: &str = "fedcba9876543210";
+
+    /// Files in a build directory: the artifacts of a kept and a stale unit,
+    /// along with files that aren't named after units.
+    fn build_dir_files() -> Vec<String> {
+        [
+            format!("debug/deps/libkept-{KEPT}.rlib"),
+            format!("debug/deps/kept-{KEPT}.d"),
+            format!("debug/deps/libstale-{STALE}.rlib"),
+            format!("debug/deps/stale-{STALE}.d"),
+            format!("debug/build/kept-{KEPT}/out/generated.rs"),
+            format!("debug/build/stale-{STALE}/out/generated.rs"),
+            format!("debug/.fingerprint/kept-{KEPT}/lib-kept"),
+            format!("debug/.fingerprint/stale-{STALE}/lib-stale"),
+            format!("debug/incremental/stale-{STALE}/s-abc/query-cache.bin"),
+            String::from("debug/libstale.rlib"),
+            String::from("debug/stale"),
+        ]
+        .into()
+    }
+
+    /// A workspace whose build directory holds [`build_dir_files`].
+    async fn workspace_with_artifacts(temp: &TempDir) -> Workspace {
+        for file in build_dir_files() {
+            let path = AbsFilePath::try_from(temp.path().join(file)).unwrap();
+            fs::write(&path, b"artifact").await.unwrap();
+        }
+        let args = CargoBuildArguments::from_iter(["--target-dir", temp.path().to_str().unwrap()]);
+        Workspace::from_argv_in_dir(&AbsDirPath::current().unwrap(), &args)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn finds_unreferenced_artifacts() {
+        let temp = TempDir::new().unwrap();
+        let ws = workspace_with_artifacts(&temp).await;
+        let keep = HashSet::from([UnitHash::from(KEPT)]);
+
+        let mut stale = stale_artifacts(&ws, &keep)
+            .await
+            .unwrap()
+            .iter()
+            .map(|artifact| artifact.to_string())
+            .collect::<Vec<_>>();
+        stale.sort();
+        let mut expected = [
+            format!("debug/.fingerprint/stale-{STALE}"),
+            format!("debug/build/stale-{STALE}"),
+            format!("debug/deps/libstale-{STALE}.rlib"),
+            format!("debug/deps/stale-{STALE}.d"),
+        ]
+        .map(|path| temp.path().join(path).display().to_string());
+        expected.sort();
+        pretty_assert_eq!(stale, expected);
+    }
+
+    #[tokio::test]
+    async fn removes_only_unreferenced_artifacts() {
+        let temp = TempDir::new().unwrap();
+        let ws = workspace_with_artifacts(&temp).await;
+        let keep = HashSet::from([UnitHash::from(KEPT)]);
+
+        for artifact in stale_artifacts(&ws, &keep).await.unwrap() {
+            artifact.remove().await.unwrap();
+        }
+
+        // Referenced units, incremental compilation state, and uplifted
+        // artifacts survive.
+        let mut remaining = Vec::new();
+        for file in build_dir_files() {
+            if fs::exists(temp.path().join(&file)).await {
+                remaining.push(file);
+            }
+        }
+        pretty_assert_eq!(
+            remaining,
+            vec![
+                format!("debug/deps/libkept-{KEPT}.rlib"),
+                format!("debug/deps/kept-{KEPT}.d"),
+                format!("debug/build/kept-{KEPT}/out/generated.rs"),
+                format!("debug/.fingerprint/kept-{KEPT}/lib-kept"),
+                format!("debug/incremental/stale-{STALE}/s-abc/query-cache.bin"),
+                String::from("debug/libstale.rlib"),
+                String::from("debug/stale"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn finding_artifacts_removes_nothing() {
+        // `hurry gc-target --dry-run` only lists the stale artifacts.
+        let temp = TempDir::new().unwrap();
+        let ws = workspace_with_artifacts(&temp).await;
+
+        let stale = stale_artifacts(&ws, &HashSet::new()).await.unwrap();
+        pretty_assert_eq!(stale.len(), 8);
+        for file in build_dir_files() {
+            assert!(fs::exists(temp.path().join(&file)).await, "{file} removed");
+        }
+    }
+
+    #[test_case("libserde-0123456789abcdef.rlib", Some("0123456789abcdef"); "library")]
+    #[test_case("serde_json-0123456789abcdef.d", Some("0123456789abcdef"); "dep info")]
+    #[test_case("build_script_build-0123456789abcdef", Some("0123456789abcdef"); "program")]
+    #[test_case("proc-macro2-0123456789abcdef", Some("0123456789abcdef"); "dashed name")]
+    #[test_case("serde-0123456789abcdef.dSYM", Some("0123456789abcdef"); "debug symbols")]
+    #[test_case("serde-0123456789ABCDEF", None; "uppercase")]
+    #[test_case("serde-0123", None; "short")]
+    #[test_case("proc-macro2", None; "no hash")]
+    #[test]
+    fn parses_artifact_hash(name: &str, expected: Option<&str>) {
+        pretty_assert_eq!(artifact_hash(name), expected);
+    }
+}
@@ -0,0 +1,248 @@
+//! Verifying that builds are hermetic.
+//!
+//! Units are only safe to share across machines if building the same unit
+//! twice produces the same artifacts, once the paths that Hurry rewrites on
+//! restore are normalized. Builds that embed a timestamp, a random seed, or an
+//! absolute path that Hurry doesn't know about produce different artifacts in
+//! every build directory, and restoring them elsewhere is at best useless and
+//! at worst wrong.
+//!
+//! Verification builds the same units in two clean build directories at
+//! different paths, reads each unit the way it would be saved to the cache,
+//! and compares the results. Units that differ are reported along with where
+//! their artifacts differ, and their crates can be excluded from the cache
+//! with `[crates.<name>]` in `hurry.toml` (see [`exclusions`]).
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use color_eyre::{Result, eyre::Context as _};
+use tracing::{instrument, warn};
+
+use crate::cargo::{Difference, QualifiedPath, UnitHash, UnitPlan, Workspace, diff_artifacts};
+
+/// A unit whose artifacts differed between two builds.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NonHermeticUnit {
+    pub unit_hash: UnitHash,
+    pub package_name: String,
+    pub package_version: String,
+
+    /// The unit's files that differed, by their normalized path.
+    pub files: Vec<NonHermeticFile>,
+}
+
+/// A file of a unit whose content differed between two builds.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NonHermeticFile {
+    /// The normalized path of the file, e.g. `<profile>/deps/libfoo.rlib`.
+    pub name: String,
+
+    /// Where the file's content differed, if it could be narrowed down; see
+    /// [`diff_artifacts`].
+    pub differences: Vec<Difference>,
+}
+
+/// Compare the units of two builds of the same workspace in different build
+/// directories, returning the units whose normalized artifacts differ.
+///
+/// Units are matched by unit hash. Units that are only in one of the builds,
+/// or that couldn't be read from either build directory (e.g. because Cargo
+/// didn't build them), aren't compared.
+#[instrument(skip_all)]
+pub async fn compare_builds(
+    first: (&Workspace, &[UnitPlan]),
+    second: (&Workspace, &[UnitPlan]),
+) -> Result<Vec<NonHermeticUnit>> {
+    let (first_ws, first_units) = first;
+    let (second_ws, second_units) = second;
+    let second_units = second_units
+        .iter()
+        .map(|unit| (&unit.info().unit_hash, unit))
+        .collect::<HashMap<_, _>>();
+
+    let mut differing = Vec::new();
+    for first_unit in first_units {
+        let info = first_unit.info();
+        let Some(second_unit) = second_units.get(&info.unit_hash) else {
+            warn!(unit_hash = %info.unit_hash, "unit is only in the first build");
+            continue;
+        };
+        let first_files = match unit_files(first_ws, first_unit).await {
+            Ok(files) => files,
+            Err(err) => {
+                warn!(?err, unit_hash = %info.unit_hash, "read unit from first build");
+                continue;
+            }
+        };
+        let second_files = match unit_files(second_ws, second_unit).await {
+            Ok(files) => files,
+            Err(err) => {
+                warn!(?err, unit_hash = %info.unit_hash, "read unit from second build");
+                continue;
+            }
+        };
+
+        let files = compare_files(&first_files, &second_files)
+            .with_context(|| format!("compare unit {}", info.unit_hash))?;
+        if !files.is_empty() {
+            differing.push(NonHermeticUnit {
+                unit_hash: info.unit_hash.clone(),
+                package_name: info.package_name.clone(),
+                package_version: info.package_version.clone(),
+                files,
+            });
+        }
+    }
+    Ok(differing)
+}
+
+/// Render the `hurry.toml` entries that exclude the crates of the units from
+/// the cache.
+pub fn exclusions(units: &[NonHermeticUnit]) -> String {
+    units
+        .iter()
+        .map(|unit| unit.package_name.as_str())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|name| format!("[crates.{name}]\ncache = false\n"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Compare the files of a unit from two builds, returning the files that
+/// differ. A file that's only in one of the builds differs too.
+fn compare_files(
+    first: &BTreeMap<String, Vec<u8>>,
+    second: &BTreeMap<String, Vec<u8>>,
+) -> Result<Vec<NonHermeticFile>> {
+    let names = first.keys().chain(second.keys()).collect::<BTreeSet<_>>();
+    let mut differing = Vec::new();
+    for name in names {
+        let (a, b) = match (first.get(name), second.get(name)) {
+            (Some(a), Some(b)) if a == b => continue,
+            (Some(a), Some(b)) => (a.as_slice(), b.as_slice()),
+            (a, b) => (
+                a.map(Vec::as_slice).unwrap_or_default(),
+                b.map(Vec::as_slice).unwrap_or_default(),
+            ),
+        };
+        let differences = diff_artifacts(a, b).with_context(|| format!("diff {name}"))?;
+        differing.push(NonHermeticFile {
+            name: name.clone(),
+            differences,
+        });
+    }
+    Ok(differing)
+}
+
+/// Read the files of a unit as they would be saved to the cache, by their
+/// normalized path.
+///
+/// Fingerprints aren't included, since they're rewritten on restore and
+/// always contain the time the unit was built.
+async fn unit_files(ws: &Workspace, unit: &UnitPlan) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+    match unit {
+        UnitPlan::LibraryCrate(plan) => {
+            let read = plan.read(ws).await?;
+            for file in read.output_files {
+                files.insert(display_path(&file.path), file.contents);
+            }
+            files.insert(
+                String::from("<dep-info>"),
+                serde_json::to_vec(&read.dep_info_file)?,
+            );
+            files.insert(
+                String::from("<encoded-dep-info>"),
+                read.encoded_dep_info_file,
+            );
+        }
+        UnitPlan::BuildScriptCompilation(plan) => {
+            let read = plan.read(ws).await?;
+            files.insert(String::from("<build-script>"), read.compiled_program);
+            files.insert(
+                String::from("<dep-info>"),
+                serde_json::to_vec(&read.dep_info_file)?,
+            );
+            files.insert(
+                String::from("<encoded-dep-info>"),
+                read.encoded_dep_info_file,
+            );
+        }
+        UnitPlan::BuildScriptExecution(plan) => {
+            let read = plan.read(ws).await?;
+            for file in read.out_dir_files {
+                files.insert(display_path(&file.path), file.contents);
+            }
+            files.insert(String::from("<stdout>"), serde_json::to_vec(&read.stdout)?);
+            files.insert(String::from("<stderr>"), read.stderr);
+        }
+    }
+    Ok(files)
+}
+
+/// Render a normalized path for display, with its root as a placeholder.
+fn display_path(path: &QualifiedPath) -> String {
+    match path {
+        QualifiedPath::Rootless(path) => path.to_string(),
+        QualifiedPath::RelativeTargetProfile(path) => format!("<profile>/{path}"),
+        QualifiedPath::RelativeCargoHome(path) => format!("$CARGO_HOME/{path}"),
+        QualifiedPath::RelativeWorkspace(path) => format!("<workspace>/{path}"),
+        QualifiedPath::Absolute(path) => path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::{NonHermeticUnit, compare_files, exclusions};
+    use crate::cargo::UnitHash;
+
+    #[test]
+    fn compares_files() {
+        let first = BTreeMap::from([
+            (String::from("<profile>/deps/libfoo.rlib"), b"same".to_vec()),
+            (String::from("<stderr>"), b"built at 12:00".to_vec()),
+            (String::from("<profile>/build/out/first"), b"only".to_vec()),
+        ]);
+        let second = BTreeMap::from([
+            (String::from("<profile>/deps/libfoo.rlib"), b"same".to_vec()),
+            (String::from("<stderr>"), b"built at 12:01".to_vec()),
+        ]);
+
+        let names = compare_files(&first, &second)
+            .unwrap()
+            .into_iter()
+            .map(|file| file.name)
+            .collect::<Vec<_>>();
+        pretty_assert_eq!(
+            names,
+            vec![
+                String::from("<profile>/build/out/first"),
+                String::from("<stderr>"),
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_exclusions() {
+        let unit = |name: &str, hash: &str| NonHermeticUnit {
+            unit_hash: UnitHash::from(hash),
+            package_name: String::from(name),
+            package_version: String::from("1.0.0"),
+            files: Vec::new(),
+        };
+        let units = [
+            unit("ring", "a"),
+            unit("openssl-sys", "b"),
+            unit("ring", "c"),
+        ];
+        pretty_assert_eq!(
+            exclusions(&units),
+            "[crates.openssl-sys]\ncache = false\n\n[crates.ring]\ncache = false\n"
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Near-match restore.
+//!
+//! Units are normally only restored when the cache has a unit with exactly the
+//! same unit hash. But the unit hash changes with anything that could possibly
+//! affect compilation, including features that many crates don't actually
+//! compile differently with (or whose differences don't matter to the user).
+//! So when a library crate isn't cached exactly, Hurry can fall back to a
+//! cached unit that differs only in such features.
+//!
+//! This is opt-in: only the features listed in `near-match-features` in
+//! `hurry.toml` may differ. Courier groups candidates under a near-match key,
+//! which covers everything about the compilation except the enabled features
+//! and machine-specific paths; Hurry then validates candidates itself before
+//! restoring one in place of the exact unit.
+//!
+//! The key also covers where the crate comes from, so that crates with the
+//! same name and version from different registries (e.g. crates.io and a
+//! private registry) are never near matches of each other.
+
+use std::collections::BTreeSet;
+
+use tap::Conv as _;
+
+use crate::{
+    cargo::{RustcArgument, RustcArguments, UnitPlanInfo, rustc::RustcCodegenOption},
+    path::{AbsDirPath, RelativeTo as _},
+};
+
+/// Distinguishes near-match keys from any other hash of the same fields.
+///
+/// Bump this when the fields in the key change, so that units saved with the
+/// old key aren't offered as candidates for units they may not match.
+const KEY_DOMAIN: &str = "hurry-near-match-key-v2";
+
+/// Derive the near-match key of a library crate unit.
+///
+/// Units with the same key differ at most in their enabled features and in
+/// arguments that vary with where or how Cargo was run rather than with how
+/// the crate is compiled. `source` identifies where the crate comes from; see
+/// [`source`].
+pub fn key(info: &UnitPlanInfo, source: &str, args: &RustcArguments) -> String {
+    let target_arch = info.target_arch.clone().conv::<Option<String>>();
+    let arguments = args
+        .iter()
+        .filter(|arg| !varies_between_near_matches(arg))
+        .map(|arg| format!("{arg:?}"));
+    let fields = [
+        String::from(KEY_DOMAIN),
+        info.package_name.clone(),
+        info.package_version.clone(),
+        info.crate_name.clone(),
+        target_arch.unwrap_or_default(),
+        String::from(source),
+    ]
+    .into_iter()
+    .chain(arguments);
+
+    // Length-prefix each field so that moving bytes between adjacent fields
+    // can't produce the same key.
+    let mut hasher = blake3::Hasher::new();
+    for field in fields {
+        hasher.update(&(field.len() as u64).to_le_bytes());
+        hasher.update(field.as_bytes());
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+/// Identify where the package in `package_dir` comes from, by the directory
+/// that Cargo unpacked it into relative to `$CARGO_HOME`.
+///
+/// For registry packages this is the registry's source directory (e.g.
+/// `registry/src/index.crates.io-1949cf8c6b5b557f`), which Cargo names after
+/// the registry's index URL, and for git dependencies it's the checkout of
+/// the dependency's revision. Packages outside of `$CARGO_HOME` have no
+/// source.
+pub fn source(package_dir: &AbsDirPath, cargo_home: &AbsDirPath) -> Option<String> {
+    let relative = package_dir.relative_to(cargo_home).ok()?;
+    let relative = relative.as_str_lossy();
+    let (source, _) = relative.rsplit_once(['/', '\\'])?;
+    Some(source.replace('\\', "/"))
+}
+
+/// Whether the argument may differ between near matches.
+fn varies_between_near_matches(arg: &RustcArgument) -> bool {
+    match arg {
+        // The features are what near matches are allowed to differ in, and
+        // the metadata and file name are derived from them.
+        RustcArgument::Cfg(spec) => spec.feature().is_some(),
+        RustcArgument::Codegen(
+            RustcCodegenOption::Metadata(_) | RustcCodegenOption::ExtraFilename(_),
+        ) => true,
+        // Dependencies are identified by paths that include their own unit
+        // hashes. Candidates are checked against the actual dependencies
+        // through their fingerprints instead.
+        RustcArgument::Extern(_) => true,
+        // These are paths on the machine that built the unit, or only affect
+        // how diagnostics are reported.
+        RustcArgument::OutDir(_)
+        | RustcArgument::Output(_)
+        | RustcArgument::LibrarySearchPath(_)
+        | RustcArgument::Positional(_)
+        | RustcArgument::ErrorFormat(_)
+        | RustcArgument::Json(_) => true,
+        _ => false,
+    }
+}
+
+/// Whether a candidate compiled with `candidate` features can stand in for a
+/// unit compiled with `local` features, given the features that are `allowed`
+/// to differ.
+///
+/// Features may differ in either direction: the allowlist is the user's
+/// assertion that the features don't matter, whether they're enabled or not.
+pub fn compatible(
+    local: &BTreeSet<String>,
+    candidate: &BTreeSet<String>,
+    allowed: &BTreeSet<String>,
+) -> bool {
+    local
+        .symmetric_difference(candidate)
+        .all(|feature| allowed.contains(feature))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+
+    use super::{compatible, key, source};
+    use crate::{
+        cargo::{CratePolicy, RustcArguments, RustcTarget, UnitPlanInfo},
+        path::AbsDirPath,
+    };
+
+    const CRATES_IO: &str = "registry/src/index.crates.io-1949cf8c6b5b557f";
+
+    fn info() -> UnitPlanInfo {
+        UnitPlanInfo {
+            unit_hash: "ac0e04d584580346".into(),
+            package_name: String::from("base64"),
+            package_version: String::from("0.22.1"),
+            crate_name: String::from("base64"),
+            target_arch: RustcTarget::ImplicitHost,
+            deps: vec![],
+            policy: CratePolicy::default(),
+            source_hash: None,
+        }
+    }
+
+    fn args(opt_level: &str, features: &[&str], hash: &str, target: &str) -> RustcArguments {
+        let features = features
+            .iter()
+            .flat_map(|feature| [String::from("--cfg"), format!("feature=\"{feature}\"")]);
+        [
+            "--crate-name",
+            "base64",
+            "--edition",
+            "2018",
+            "/home/user/.cargo/registry/src/base64-0.22.1/src/lib.rs",
+            "--crate-type",
+            "lib",
+            "-C",
+        ]
+        .map(String::from)
+        .into_iter()
+        .chain([format!("opt-level={opt_level}")])
+        .chain(features)
+        .chain([
+            String::from("-C"),
+            format!("metadata={hash}"),
+            String::from("-C"),
+            format!("extra-filename=-{hash}"),
+            String::from("--out-dir"),
+            format!("{target}/debug/deps"),
+            String::from("-L"),
+            format!("dependency={target}/debug/deps"),
+        ])
+        .collect()
+    }
+
+    #[test]
+    fn key_ignores_features_and_paths() {
+        let a = args("0", &["std"], "1111", "/a/target");
+        let b = args("0", &["alloc", "std"], "2222", "/b/target");
+        pretty_assert_eq!(key(&info(), CRATES_IO, &a), key(&info(), CRATES_IO, &b));
+    }
+
+    #[test]
+    fn key_depends_on_compilation() {
+        let debug = args("0", &["std"], "1111", "/a/target");
+        let release = args("3", &["std"], "1111", "/a/target");
+        assert_ne!(
+            key(&info(), CRATES_IO, &debug),
+            key(&info(), CRATES_IO, &release)
+        );
+
+        let mut other = info();
+        other.package_version = String::from("0.22.0");
+        assert_ne!(
+            key(&info(), CRATES_IO, &debug),
+            key(&other, CRATES_IO, &debug)
+        );
+    }
+
+    #[test]
+    fn key_depends_on_source() {
+        let a = args("0", &["std"], "1111", "/a/target");
+        let private = "registry/src/kellnr.internal-0123456789abcdef";
+        assert_ne!(key(&info(), CRATES_IO, &a), key(&info(), private, &a));
+    }
+
+    #[test_case(
+        "/home/user/.cargo/registry/src/index.crates.io-1949cf8c6b5b557f/base64-0.22.1",
+        Some(CRATES_IO);
+        "registry"
+    )]
+    #[test_case(
+        "/home/user/.cargo/git/checkouts/hurry-0123456789abcdef/1a2b3c4/packages/clients",
+        Some("git/checkouts/hurry-0123456789abcdef/1a2b3c4/packages");
+        "git"
+    )]
+    #[test_case("/home/user/projects/base64", None; "outside cargo home")]
+    #[test]
+    fn parses_source(package_dir: &str, expected: Option<&str>) {
+        let package_dir = AbsDirPath::try_from(package_dir).unwrap();
+        let cargo_home = AbsDirPath::try_from("/home/user/.cargo").unwrap();
+        pretty_assert_eq!(source(&package_dir, &cargo_home).as_deref(), expected);
+    }
+
+    #[test_case(&["std"], &["std"], &[], true; "identical")]
+    #[test_case(&["std"], &["std", "docs"], &["docs"], true; "extra allowed")]
+    #[test_case(&["std", "docs"], &["std"], &["docs"], true; "missing allowed")]
+    #[test_case(&["std"], &["std", "serde"], &["docs"], false; "extra not allowed")]
+    #[test_case(&["std"], &[], &["docs"], false; "missing not allowed")]
+    #[test]
+    fn compatibility(local: &[&str], candidate: &[&str], allowed: &[&str], expected: bool) {
+        let set = |features: &[&str]| {
+            features
+                .iter()
+                .copied()
+                .map(String::from)
+                .collect::<BTreeSet<_>>()
+        };
+        pretty_assert_eq!(
+            compatible(&set(local), &set(candidate), &set(allowed)),
+            expected
+        );
+    }
+}
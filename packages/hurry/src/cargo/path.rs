@@ -27,9 +27,18 @@ pub enum QualifiedPath {
     /// The absolute path is relative to the workspace target profile directory.
     RelativeTargetProfile(RelFilePath),
 
-    /// The absolute path is relative to `$CARGO_HOME` for the user.
+    /// The absolute path is relative to `$CARGO_HOME` for the user, or to
+    /// another `$CARGO_HOME` registered for the workspace (see
+    /// [`Workspace::cargo_homes`]). Such paths are always restored into the
+    /// workspace's own `$CARGO_HOME`.
     RelativeCargoHome(RelFilePath),
 
+    /// The absolute path is relative to the workspace root.
+    ///
+    /// In practice, these are the paths of first-party source files, which
+    /// only appear in first-party units.
+    RelativeWorkspace(RelFilePath),
+
     /// The absolute path is not relative to any known root.
     ///
     /// In practice, these are paths to SDK headers, system libraries, etc.
@@ -67,20 +76,16 @@ impl QualifiedPath {
         let profile_dir = ws.arch_profile_dir(target);
         Ok(if let Ok(rel) = RelFilePath::try_from(path) {
             if fs::exists(profile_dir.join(&rel).as_std_path()).await {
-                Self::RelativeTargetProfile(rel)
-            } else if fs::exists(ws.cargo_home.join(&rel).as_std_path()).await {
-                Self::RelativeCargoHome(rel)
-            } else {
-                Self::Rootless(rel)
+                return Ok(Self::RelativeTargetProfile(rel));
             }
-        } else if let Ok(abs) = AbsFilePath::try_from(path) {
-            if let Ok(rel) = abs.relative_to(&profile_dir) {
-                Self::RelativeTargetProfile(rel)
-            } else if let Ok(rel) = abs.relative_to(&ws.cargo_home) {
-                Self::RelativeCargoHome(rel)
-            } else {
-                Self::Absolute(abs)
+            for home in ws.cargo_homes() {
+                if fs::exists(home.join(&rel).as_std_path()).await {
+                    return Ok(Self::RelativeCargoHome(rel));
+                }
             }
+            Self::Rootless(rel)
+        } else if let Ok(abs) = AbsFilePath::try_from(path) {
+            Self::parse_abs(ws, target, &abs)
         } else {
             bail!("unknown kind of path: {path:?}")
         })
@@ -95,13 +100,33 @@ impl QualifiedPath {
         let profile_dir = ws.arch_profile_dir(target);
         if let Ok(rel) = path.relative_to(&profile_dir) {
             Self::RelativeTargetProfile(rel)
-        } else if let Ok(rel) = path.relative_to(&ws.cargo_home) {
+        } else if let Some(rel) = ws
+            .cargo_homes()
+            .into_iter()
+            .find_map(|home| path.relative_to(home).ok())
+        {
             Self::RelativeCargoHome(rel)
+        } else if let Ok(rel) = path.relative_to(&ws.root) {
+            Self::RelativeWorkspace(rel)
         } else {
             Self::Absolute(path.clone())
         }
     }
 
+    /// Report whether the path is valid UTF-8.
+    ///
+    /// Paths are serialized lossily, so paths that aren't valid UTF-8 are not
+    /// restored to the same location that they were saved from.
+    pub fn is_utf8(&self) -> bool {
+        match self {
+            QualifiedPath::Rootless(rel)
+            | QualifiedPath::RelativeTargetProfile(rel)
+            | QualifiedPath::RelativeCargoHome(rel)
+            | QualifiedPath::RelativeWorkspace(rel) => rel.is_utf8(),
+            QualifiedPath::Absolute(abs) => abs.is_utf8(),
+        }
+    }
+
     #[instrument(name = "QualifiedPath::reconstruct_string")]
     pub fn reconstruct_string(self, ws: &Workspace, target: &RustcTarget) -> String {
         self.reconstruct_inner(ws, target).to_string()
@@ -118,7 +143,94 @@ impl QualifiedPath {
             QualifiedPath::Rootless(rel) => rel.into(),
             QualifiedPath::RelativeTargetProfile(rel) => profile_dir.join(rel).into(),
             QualifiedPath::RelativeCargoHome(rel) => ws.cargo_home.join(rel).into(),
+            QualifiedPath::RelativeWorkspace(rel) => ws.root.join(rel).into(),
             QualifiedPath::Absolute(abs) => abs.into(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+
+    use super::QualifiedPath;
+    use crate::{
+        cargo::{Profile, RustcTarget, RustcTargetPlatform, Workspace},
+        path::{AbsDirPath, AbsFilePath, GenericPath, RelFilePath, TryJoinWith as _},
+    };
+
+    fn workspace(cargo_home: &str, roots: &[&str]) -> Workspace {
+        let root = AbsDirPath::try_from("/work/project").unwrap();
+        Workspace {
+            build_dir: root.try_join_dir("target").unwrap(),
+            root,
+            cargo_home: AbsDirPath::try_from(cargo_home).unwrap(),
+            cargo_home_roots: roots
+                .iter()
+                .map(|root| AbsDirPath::try_from(*root).unwrap())
+                .collect(),
+            profile: Profile::Debug,
+            target_arch: RustcTarget::ImplicitHost,
+            host_arch: RustcTargetPlatform::try_from("x86_64-unknown-linux-gnu").unwrap(),
+            cache_first_party: false,
+            crate_policies: Default::default(),
+            invoked_in: None,
+        }
+    }
+
+    #[test]
+    fn orders_cargo_homes_deterministically() {
+        let homes = |ws: &Workspace| {
+            ws.cargo_homes()
+                .into_iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+        let forward = workspace("/home/user/.cargo", &["/opt/cargo", "/opt/cargo/nested"]);
+        let reverse = workspace(
+            "/home/user/.cargo",
+            &["/opt/cargo/nested", "/opt/cargo", "/home/user/.cargo"],
+        );
+        pretty_assert_eq!(homes(&forward), homes(&reverse));
+        pretty_assert_eq!(
+            homes(&forward),
+            vec!["/home/user/.cargo", "/opt/cargo/nested", "/opt/cargo"]
+        );
+    }
+
+    #[test_case("/home/user/.cargo/registry/src/index/serde-1.0.0/src/lib.rs"; "own home")]
+    #[test_case("/opt/cargo/registry/src/index/serde-1.0.0/src/lib.rs"; "registered root")]
+    #[test_case("/opt/cargo/nested/registry/src/index/serde-1.0.0/src/lib.rs"; "nested root")]
+    #[test]
+    fn round_trips_across_cargo_homes(path: &str) {
+        let saved = workspace("/home/user/.cargo", &["/opt/cargo", "/opt/cargo/nested"]);
+        let restored = workspace("/usr/local/cargo", &[]);
+
+        let path = AbsFilePath::try_from(path).unwrap();
+        let qualified = QualifiedPath::parse_abs(&saved, &RustcTarget::ImplicitHost, &path);
+        pretty_assert_eq!(
+            qualified,
+            QualifiedPath::RelativeCargoHome(
+                RelFilePath::try_from("registry/src/index/serde-1.0.0/src/lib.rs").unwrap()
+            )
+        );
+        pretty_assert_eq!(
+            qualified.reconstruct_string(&restored, &RustcTarget::ImplicitHost),
+            "/usr/local/cargo/registry/src/index/serde-1.0.0/src/lib.rs"
+        );
+    }
+
+    #[tokio::test]
+    async fn parses_outside_cargo_homes() {
+        let ws = workspace("/home/user/.cargo", &["/opt/cargo"]);
+        let path = GenericPath::try_from("/usr/include/stdio.h").unwrap();
+        let qualified = QualifiedPath::parse(&ws, &RustcTarget::ImplicitHost, &path)
+            .await
+            .unwrap();
+        pretty_assert_eq!(
+            qualified,
+            QualifiedPath::Absolute(AbsFilePath::try_from("/usr/include/stdio.h").unwrap())
+        );
+    }
+}
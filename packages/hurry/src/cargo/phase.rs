@@ -0,0 +1,207 @@
+//! The phases of a build, and how long each of them took.
+//!
+//! Every build through the cache goes through the same phases, in order:
+//!
+//! | Phase     | What happens                                                 |
+//! | --------- | ------------------------------------------------------------ |
+//! | `plan`    | Opening the workspace and the cache, and computing the units |
+//! | `restore` | Restoring cached units into the target directory             |
+//! | `build`   | Cargo compiling whatever wasn't restored                     |
+//! | `test`    | Running tests, for `cargo test`                              |
+//! | `save`    | Uploading the compiled units, if the upload is waited for    |
+//!
+//! Each phase runs in a `phase` span whose `name` field is the phase, so
+//! traces can be filtered to a phase, e.g. with
+//! `RUST_LOG='[phase{name=restore}]=debug'`, and span timings line up with the
+//! breakdown reported after the build.
+//!
+//! With `--hurry-overlap-restore`, `restore` and `build` overlap, so the
+//! phases can add up to more than the total.
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use derive_more::Display;
+use serde::{Serialize, Serializer};
+use tracing::{Instrument as _, Span, info_span};
+
+/// A phase of a build.
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    #[display("plan")]
+    Plan,
+
+    #[display("restore")]
+    Restore,
+
+    #[display("build")]
+    Build,
+
+    #[display("test")]
+    Test,
+
+    #[display("save")]
+    Save,
+}
+
+impl Phase {
+    /// The span that the phase runs in.
+    pub fn span(self) -> Span {
+        info_span!("phase", name = %self)
+    }
+}
+
+/// How long each phase of a build took.
+#[derive(Clone, Debug, Serialize)]
+pub struct PhaseTimings {
+    /// The phases that ran, in the order they first ran.
+    phases: Vec<PhaseTiming>,
+
+    /// The wall-clock time of the whole build.
+    #[serde(rename = "total_secs", serialize_with = "serialize_secs")]
+    total: Duration,
+
+    #[serde(skip)]
+    started: Instant,
+}
+
+/// How long a phase of a build took.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PhaseTiming {
+    pub phase: Phase,
+
+    #[serde(rename = "secs", serialize_with = "serialize_secs")]
+    pub elapsed: Duration,
+}
+
+impl PartialEq for PhaseTimings {
+    fn eq(&self, other: &Self) -> bool {
+        self.phases == other.phases && self.total == other.total
+    }
+}
+
+impl Default for PhaseTimings {
+    fn default() -> Self {
+        Self::start()
+    }
+}
+
+impl PhaseTimings {
+    /// Start timing a build.
+    pub fn start() -> Self {
+        Self {
+            phases: Vec::new(),
+            total: Duration::ZERO,
+            started: Instant::now(),
+        }
+    }
+
+    /// Run the future as the phase, recording how long it took.
+    pub async fn time<F: Future>(&mut self, phase: Phase, future: F) -> F::Output {
+        let start = Instant::now();
+        let output = future.instrument(phase.span()).await;
+        self.record(phase, start.elapsed());
+        output
+    }
+
+    /// Record that the phase took `elapsed`, in addition to any time it
+    /// already took.
+    pub fn record(&mut self, phase: Phase, elapsed: Duration) {
+        match self.phases.iter_mut().find(|timing| timing.phase == phase) {
+            Some(timing) => timing.elapsed += elapsed,
+            None => self.phases.push(PhaseTiming { phase, elapsed }),
+        }
+        self.total = self.started.elapsed();
+    }
+
+    /// Record the end of the build.
+    pub fn finish(&mut self) {
+        self.total = self.started.elapsed();
+    }
+
+    /// The phases that ran, in the order they first ran.
+    pub fn phases(&self) -> &[PhaseTiming] {
+        &self.phases
+    }
+
+    /// How long the phase took, if it ran.
+    pub fn get(&self, phase: Phase) -> Option<Duration> {
+        self.phases
+            .iter()
+            .find(|timing| timing.phase == phase)
+            .map(|timing| timing.elapsed)
+    }
+
+    /// The wall-clock time of the build.
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Render the timings as a one-line breakdown, or nothing if no phase ran.
+    pub fn to_text(&self) -> Option<String> {
+        if self.phases.is_empty() {
+            return None;
+        }
+        let phases = self
+            .phases
+            .iter()
+            .map(|timing| format!("{} {:.1}s", timing.phase, timing.elapsed.as_secs_f64()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Some(format!("{phases} (total {:.1}s)", self.total.as_secs_f64()))
+    }
+}
+
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    duration.as_secs_f64().serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use serde_json::json;
+
+    use super::{Phase, PhaseTimings};
+
+    fn timings() -> PhaseTimings {
+        let mut timings = PhaseTimings::start();
+        timings.record(Phase::Plan, Duration::from_millis(400));
+        timings.record(Phase::Restore, Duration::from_millis(2500));
+        timings.record(Phase::Build, Duration::from_secs(30));
+        timings.record(Phase::Restore, Duration::from_millis(500));
+        timings.total = Duration::from_millis(33_400);
+        timings
+    }
+
+    #[test]
+    fn accumulates_phases_in_order() {
+        let timings = timings();
+        pretty_assert_eq!(timings.get(Phase::Restore), Some(Duration::from_secs(3)));
+        pretty_assert_eq!(timings.get(Phase::Save), None);
+        pretty_assert_eq!(
+            timings.to_text().as_deref(),
+            Some("plan 0.4s, restore 3.0s, build 30.0s (total 33.4s)")
+        );
+        pretty_assert_eq!(PhaseTimings::start().to_text(), None);
+    }
+
+    #[test]
+    fn encodes_json() {
+        pretty_assert_eq!(
+            serde_json::to_value(timings()).unwrap(),
+            json!({
+                "phases": [
+                    { "phase": "plan", "secs": 0.4 },
+                    { "phase": "restore", "secs": 3.0 },
+                    { "phase": "build", "secs": 30.0 },
+                ],
+                "total_secs": 33.4,
+            })
+        );
+    }
+}
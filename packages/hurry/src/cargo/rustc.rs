@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeSet,
     fmt::{Debug, Display},
     str::FromStr,
 };
@@ -246,6 +247,43 @@ impl RustcArguments {
             _ => None,
         })
     }
+
+    /// Find the `-C metadata` flag value if specified.
+    pub fn metadata(&self) -> Option<&str> {
+        self.0.iter().find_map(|arg| match arg {
+            RustcArgument::Codegen(RustcCodegenOption::Metadata(s)) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Find the `--out-dir` flag value if specified.
+    pub fn out_dir(&self) -> Option<&str> {
+        self.0.iter().find_map(|arg| match arg {
+            RustcArgument::OutDir(dir) => Some(dir.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Find the `--json` flag value if specified.
+    pub fn json(&self) -> Option<&str> {
+        self.0.iter().find_map(|arg| match arg {
+            RustcArgument::Json(options) => Some(options.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The Cargo features enabled for the crate, parsed from
+    /// `--cfg feature="<name>"` flags.
+    pub fn features(&self) -> BTreeSet<String> {
+        self.0
+            .iter()
+            .filter_map(|arg| match arg {
+                RustcArgument::Cfg(spec) => spec.feature(),
+                _ => None,
+            })
+            .map(String::from)
+            .collect()
+    }
 }
 
 impl IntoIterator for RustcArguments {
@@ -659,6 +697,16 @@ pub struct RustcCheckCfgSpec(String);
 #[display("{0}={1}")]
 pub struct RustcCfgSpec(RustcCfgSpecKey, RustcCfgSpecValue);
 
+impl RustcCfgSpec {
+    /// The feature name, if this enables a Cargo feature.
+    pub fn feature(&self) -> Option<&str> {
+        match self.0 {
+            RustcCfgSpecKey::Feature => Some(self.1.0.as_str()),
+            RustcCfgSpecKey::Other(_) => None,
+        }
+    }
+}
+
 /// The key used to configure the compilation environment.
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, ParseDisplay, ParseFromStr)]
 pub enum RustcCfgSpecKey {
@@ -679,6 +727,18 @@ pub struct RustcCfgSpecValue(String);
 #[display("{_0}={_1}")]
 pub struct RustcLibrarySearchPath(RustcLibrarySearchPathKind, String);
 
+impl RustcLibrarySearchPath {
+    /// The kind of libraries searched for in the directory.
+    pub fn kind(&self) -> &RustcLibrarySearchPathKind {
+        &self.0
+    }
+
+    /// The directory searched.
+    pub fn path(&self) -> &str {
+        &self.1
+    }
+}
+
 impl FromStr for RustcLibrarySearchPath {
     type Err = Report;
 
@@ -1211,6 +1271,17 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn lib_build_features() -> Result<()> {
+        let json = include_str!("rustc/fixtures/lib_build.json");
+        let args = serde_json::from_str::<RustcArguments>(json).context("parse lib build args")?;
+
+        let expected = ["alloc", "default", "std"].map(String::from);
+        pretty_assert_eq!(args.features(), BTreeSet::from(expected));
+
+        Ok(())
+    }
+
     #[test]
     fn parse_build_script_args() -> Result<()> {
         let json = include_str!("rustc/fixtures/build_script.json");
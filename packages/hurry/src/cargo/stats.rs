@@ -0,0 +1,202 @@
+//! Cache statistics for a build.
+//!
+//! After each build, Hurry reports what the cache did for it: how many units
+//! were restored and how many Cargo compiled, how much was downloaded and
+//! uploaded, roughly how much build time restoring saved, and how long each
+//! phase of the build took. The same statistics back the GitHub Actions job
+//! summary.
+
+use std::time::Duration;
+
+use clap::ValueEnum;
+use derive_more::Display;
+use serde::{Deserialize, Serialize, Serializer};
+use tracing::warn;
+
+use crate::{cargo::PhaseTimings, progress::format_size};
+
+/// How the cache statistics of a build are reported.
+#[derive(
+    Debug, Display, Clone, Copy, Eq, PartialEq, Hash, Default, Serialize, Deserialize, ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsFormat {
+    /// A one-line summary on stderr.
+    #[default]
+    #[display("text")]
+    Text,
+
+    /// A single line of JSON on stdout, after Cargo's own output.
+    #[display("json")]
+    Json,
+
+    /// Don't report statistics.
+    #[display("off")]
+    Off,
+}
+
+/// What the cache did for a build.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BuildStats {
+    /// The Cargo subcommand that was run, e.g. `build`.
+    pub command: String,
+
+    /// The number of units that the build could restore from the cache.
+    pub units: u64,
+
+    /// The number of units that were restored from the cache.
+    pub restored: u64,
+
+    /// The number of units that weren't restored, so Cargo compiled them (or
+    /// found them already up to date).
+    pub compiled: u64,
+
+    /// The number of bytes downloaded while restoring.
+    pub downloaded_bytes: u64,
+
+    /// The number of units uploaded after the build, if the upload was
+    /// waited for.
+    pub uploaded_units: Option<u64>,
+
+    /// The number of bytes uploaded after the build, if the upload was
+    /// waited for.
+    pub uploaded_bytes: Option<u64>,
+
+    /// An estimate of the build time that restoring saved.
+    #[serde(rename = "time_saved_secs", serialize_with = "serialize_secs")]
+    pub time_saved: Option<Duration>,
+
+    /// How long each phase of the build took.
+    pub timings: PhaseTimings,
+}
+
+impl BuildStats {
+    /// The fraction of units that were restored from the cache.
+    ///
+    /// This is zero for builds without any cacheable units.
+    pub fn hit_ratio(&self) -> f64 {
+        if self.units == 0 {
+            0.0
+        } else {
+            self.restored as f64 / self.units as f64
+        }
+    }
+
+    /// Render the statistics as a one-line summary.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "Cache: restored {}/{} units ({:.1}%), compiled {}, downloaded {}",
+            self.restored,
+            self.units,
+            self.hit_ratio() * 100.0,
+            self.compiled,
+            format_size(self.downloaded_bytes),
+        );
+        if let (Some(units), Some(bytes)) = (self.uploaded_units, self.uploaded_bytes) {
+            text.push_str(&format!(
+                ", uploaded {units} units ({})",
+                format_size(bytes)
+            ));
+        }
+        if let Some(saved) = self.time_saved {
+            text.push_str(&format!(", saved ~{:.1}s", saved.as_secs_f64()));
+        }
+        text
+    }
+
+    /// Report the statistics in the format.
+    pub fn report(&self, format: StatsFormat) {
+        match format {
+            StatsFormat::Text => {
+                eprintln!("{}", self.to_text());
+                if let Some(timings) = self.timings.to_text() {
+                    eprintln!("Time: {timings}");
+                }
+            }
+            StatsFormat::Json => match serde_json::to_string(self) {
+                Ok(json) => println!("{json}"),
+                Err(err) => warn!(?err, "failed to encode build stats"),
+            },
+            StatsFormat::Off => {}
+        }
+    }
+}
+
+fn serialize_secs<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    duration.map(|d| d.as_secs_f64()).serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use serde_json::json;
+    use simple_test_case::test_case;
+
+    use super::BuildStats;
+    use crate::progress::format_size;
+
+    #[test_case(0, 0, 0.0; "no units")]
+    #[test_case(4, 0, 0.0; "no hits")]
+    #[test_case(4, 3, 0.75; "some hits")]
+    #[test]
+    fn computes_hit_ratio(units: u64, restored: u64, expected: f64) {
+        let stats = BuildStats {
+            units,
+            restored,
+            ..Default::default()
+        };
+        pretty_assert_eq!(stats.hit_ratio(), expected);
+    }
+
+    #[test]
+    fn renders_text() {
+        let stats = BuildStats {
+            command: String::from("build"),
+            units: 4,
+            restored: 3,
+            compiled: 1,
+            downloaded_bytes: 2048,
+            uploaded_units: Some(1),
+            uploaded_bytes: Some(1024),
+            time_saved: Some(Duration::from_millis(1500)),
+        };
+        pretty_assert_eq!(
+            stats.to_text(),
+            format!(
+                "Cache: restored 3/4 units (75.0%), compiled 1, downloaded {}, uploaded 1 units ({}), saved ~1.5s",
+                format_size(2048),
+                format_size(1024),
+            )
+        );
+    }
+
+    #[test]
+    fn encodes_json() {
+        let stats = BuildStats {
+            command: String::from("test"),
+            units: 2,
+            restored: 2,
+            time_saved: Some(Duration::from_secs(3)),
+            ..Default::default()
+        };
+        pretty_assert_eq!(
+            serde_json::to_value(&stats).unwrap(),
+            json!({
+                "command": "test",
+                "units": 2,
+                "restored": 2,
+                "compiled": 0,
+                "downloaded_bytes": 0,
+                "uploaded_units": null,
+                "uploaded_bytes": null,
+                "time_saved_secs": 3.0,
+                "timings": { "phases": [], "total_secs": 0.0 },
+            })
+        );
+    }
+}
@@ -0,0 +1,479 @@
+//! Compute unit hashes from a snapshot of a build.
+//!
+//! Tooling sometimes needs the unit hashes of a commit without a checkout of
+//! it, for example to check how much of a commit's build is already cached.
+//! Cargo computes unit hashes internally from inputs that aren't practical to
+//! reproduce outside of Cargo, but it reports them in its build plan; this
+//! module reads them from a build plan captured earlier together with the
+//! commit's lockfile and toolchain, without touching the filesystem.
+//!
+//! ```ignore
+//! let input = serde_json::from_str::<UnitHashInput>(&snapshot)?;
+//! for unit in unit_hashes(&input)? {
+//!     println!("{} {}", unit.package_name, unit.saved_unit_hash);
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+
+use bon::Builder;
+use cargo_metadata::TargetKind;
+use clients::courier::v1::{self as courier, CacheScope, SavedUnitHash, UnitHashVersion};
+use color_eyre::{
+    Result,
+    eyre::{Context as _, OptionExt as _, bail},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cargo::{BuildPlan, CargoCompileMode, RustcArguments, RustcTarget, UnitHash},
+    path::AbsFilePath,
+};
+
+/// The inputs for computing the unit hashes of a build.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize, Builder)]
+pub struct UnitHashInput {
+    /// The build plan of the build, as output by
+    /// `cargo build --build-plan -Z unstable-options`.
+    pub build_plan: BuildPlan,
+
+    /// The content of the `Cargo.lock` the build plan was captured with.
+    ///
+    /// Packages with a source in the lockfile (registry and git
+    /// dependencies) are third-party, which are the units Hurry caches.
+    #[builder(into)]
+    pub lockfile: String,
+
+    /// The toolchain the build plan was captured with.
+    pub toolchain: ToolchainDescriptor,
+
+    /// The organization's cache generation, which is part of the hash under
+    /// which units are saved.
+    ///
+    /// Snapshots that don't specify one are hashed for the initial generation.
+    #[serde(default)]
+    #[builder(default)]
+    pub cache_generation: u64,
+
+    /// The cache scope the build saves units in, which is also part of the
+    /// hash under which units are saved.
+    ///
+    /// Snapshots that don't specify one are hashed for the unscoped cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_scope: Option<CacheScope>,
+}
+
+/// The toolchain a build plan was captured with.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize, Builder)]
+pub struct ToolchainDescriptor {
+    /// The host target triple (e.g. `x86_64-unknown-linux-gnu`), which units
+    /// without an explicit `--target` are built for.
+    #[builder(into)]
+    pub host: String,
+}
+
+/// The unit hash of a single unit.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct UnitHashEntry {
+    /// The hash Cargo uses for the unit.
+    pub unit_hash: UnitHash,
+
+    /// The hash the unit is saved under in the cache.
+    pub saved_unit_hash: SavedUnitHash,
+
+    pub kind: UnitKind,
+    pub package_name: String,
+    pub package_version: String,
+    pub crate_name: String,
+
+    /// The target triple the unit is built for.
+    pub target: String,
+}
+
+/// The kinds of units Hurry caches.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitKind {
+    LibraryCrate,
+    BuildScriptCompilation,
+    BuildScriptExecution,
+}
+
+#[derive(Debug, Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockfilePackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockfilePackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+}
+
+/// Compute the hashes of the third-party units in a build, which are the
+/// units Hurry saves and restores.
+///
+/// Units are returned in build plan order. Fails if the build plan contains a
+/// package that isn't in the lockfile, since that means the build plan was
+/// captured with a different lockfile.
+pub fn unit_hashes(input: &UnitHashInput) -> Result<Vec<UnitHashEntry>> {
+    let lockfile = toml::from_str::<Lockfile>(&input.lockfile).context("parse lockfile")?;
+    let third_party = lockfile
+        .package
+        .into_iter()
+        .map(|package| {
+            let key = (package.name, package.version);
+            (key, package.source.is_some())
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut seen = HashSet::new();
+    let mut units = Vec::new();
+    for invocation in &input.build_plan.invocations {
+        let key = (
+            invocation.package_name.clone(),
+            invocation.package_version.clone(),
+        );
+        let Some(&is_third_party) = third_party.get(&key) else {
+            bail!(
+                "package {} {} is not in the lockfile",
+                invocation.package_name,
+                invocation.package_version
+            );
+        };
+        let primary = invocation
+            .env
+            .get("CARGO_PRIMARY_PACKAGE")
+            .is_some_and(|v| v == "1");
+        if !is_third_party || primary {
+            continue;
+        }
+
+        let Some(unit_hash) = invocation.unit_hash()? else {
+            continue;
+        };
+        if !seen.insert(unit_hash.clone()) {
+            continue;
+        }
+
+        let (kind, crate_name) = if invocation.target_kind == [TargetKind::CustomBuild] {
+            match invocation.compile_mode {
+                CargoCompileMode::Build => {
+                    let args = RustcArguments::from_iter(invocation.args.iter().cloned());
+                    let crate_name = args
+                        .crate_name()
+                        .ok_or_eyre("build script compilation should have a crate name")?;
+                    (UnitKind::BuildScriptCompilation, String::from(crate_name))
+                }
+                CargoCompileMode::RunCustomBuild => {
+                    // Cargo defines the crate name of a build script execution
+                    // as the crate name of the build script being executed;
+                    // see `Workspace::units_from_build_plan`.
+                    let program = AbsFilePath::try_from(invocation.program.as_str())?;
+                    let crate_name = program
+                        .file_name_str_lossy()
+                        .ok_or_eyre("build script program should have name")?
+                        .replace("-", "_");
+                    (UnitKind::BuildScriptExecution, crate_name)
+                }
+                mode => bail!("unknown compile mode for build script: {mode:?}"),
+            }
+        } else {
+            let args = RustcArguments::from_iter(invocation.args.iter().cloned());
+            let crate_name = args.crate_name().ok_or_eyre("no crate name")?;
+            (UnitKind::LibraryCrate, String::from(crate_name))
+        };
+
+        let target = match &invocation.target_arch {
+            RustcTarget::Specified(target) => String::from(target.as_str()),
+            RustcTarget::ImplicitHost => input.toolchain.host.clone(),
+        };
+        let info = courier::UnitPlanInfo::builder()
+            .unit_hash(String::from(&unit_hash))
+            .package_name(invocation.package_name.as_str())
+            .crate_name(crate_name.as_str())
+            .maybe_target_arch(invocation.target_arch.as_str().map(String::from))
+            .build();
+        units.push(UnitHashEntry {
+            saved_unit_hash: UnitHashVersion::CURRENT.derive_scoped(
+                &info,
+                input.cache_generation,
+                input.cache_scope.as_ref(),
+            ),
+            unit_hash,
+            kind,
+            package_name: invocation.package_name.clone(),
+            package_version: invocation.package_version.clone(),
+            crate_name,
+            target,
+        });
+    }
+
+    Ok(units)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        cargo::{CargoBuildArguments, UnitPlan, Workspace},
+        fs,
+        path::TryJoinWith as _,
+    };
+
+    const LOCKFILE: &str = r#"
+version = 4
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = ["libc"]
+
+[[package]]
+name = "libc"
+version = "0.2.170"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "875b3680cb2f8f71bdcf9a30f38d48282f5d3c95cbf9b3fa57269bb5d5c06828"
+"#;
+
+    fn invocation(
+        package_name: &str,
+        target_kind: &str,
+        compile_mode: &str,
+        outputs: &[&str],
+        env: serde_json::Value,
+        args: &[&str],
+        program: &str,
+    ) -> serde_json::Value {
+        json!({
+            "package_name": package_name,
+            "package_version": if package_name == "app" { "0.1.0" } else { "0.2.170" },
+            "target_kind": [target_kind],
+            "kind": null,
+            "compile_mode": compile_mode,
+            "deps": [],
+            "outputs": outputs,
+            "links": {},
+            "program": program,
+            "args": args,
+            "env": env,
+            "cwd": "/home/user/.cargo/registry/src/index.crates.io-1949cf8c6b5b557f/libc-0.2.170",
+        })
+    }
+
+    fn build_plan() -> BuildPlan {
+        let plan = json!({
+            "invocations": [
+                invocation(
+                    "libc",
+                    "custom-build",
+                    "build",
+                    &["/work/target/debug/build/libc-1111/build_script_build-1111"],
+                    json!({}),
+                    &["--crate-name", "build_script_build", "build.rs"],
+                    "rustc",
+                ),
+                invocation(
+                    "libc",
+                    "custom-build",
+                    "run-custom-build",
+                    &[],
+                    json!({ "OUT_DIR": "/work/target/debug/build/libc-2222/out" }),
+                    &[],
+                    "/work/target/debug/build/libc-1111/build-script-build",
+                ),
+                invocation(
+                    "libc",
+                    "lib",
+                    "build",
+                    &["/work/target/debug/deps/liblibc-3333.rlib", "/work/target/debug/deps/liblibc-3333.rmeta"],
+                    json!({}),
+                    &["--crate-name", "libc", "src/lib.rs"],
+                    "rustc",
+                ),
+                invocation(
+                    "app",
+                    "lib",
+                    "build",
+                    &["/work/target/debug/deps/libapp-4444.rlib"],
+                    json!({ "CARGO_PRIMARY_PACKAGE": "1" }),
+                    &["--crate-name", "app", "src/lib.rs"],
+                    "rustc",
+                ),
+            ],
+            "inputs": [],
+        });
+        serde_json::from_value(plan).expect("valid build plan")
+    }
+
+    #[test]
+    fn third_party_units() -> Result<()> {
+        let input = UnitHashInput::builder()
+            .build_plan(build_plan())
+            .lockfile(LOCKFILE)
+            .toolchain(
+                ToolchainDescriptor::builder()
+                    .host("x86_64-unknown-linux-gnu")
+                    .build(),
+            )
+            .build();
+
+        let units = unit_hashes(&input)?
+            .into_iter()
+            .map(|unit| {
+                (
+                    unit.kind,
+                    unit.unit_hash.to_string(),
+                    unit.crate_name,
+                    unit.target,
+                )
+            })
+            .collect::<Vec<_>>();
+        let host = String::from("x86_64-unknown-linux-gnu");
+        pretty_assert_eq!(
+            units,
+            vec![
+                (
+                    UnitKind::BuildScriptCompilation,
+                    String::from("1111"),
+                    String::from("build_script_build"),
+                    host.clone()
+                ),
+                (
+                    UnitKind::BuildScriptExecution,
+                    String::from("2222"),
+                    String::from("build_script_build"),
+                    host.clone()
+                ),
+                (
+                    UnitKind::LibraryCrate,
+                    String::from("3333"),
+                    String::from("libc"),
+                    host
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn input_round_trips() -> Result<()> {
+        let input = UnitHashInput::builder()
+            .build_plan(build_plan())
+            .lockfile(LOCKFILE)
+            .toolchain(
+                ToolchainDescriptor::builder()
+                    .host("aarch64-apple-darwin")
+                    .build(),
+            )
+            .build();
+        let serialized = serde_json::to_string(&input)?;
+        let parsed = serde_json::from_str::<UnitHashInput>(&serialized)?;
+        pretty_assert_eq!(parsed, input);
+        pretty_assert_eq!(unit_hashes(&parsed)?, unit_hashes(&input)?);
+        Ok(())
+    }
+
+    #[test]
+    fn cache_generation_changes_saved_hashes() -> Result<()> {
+        let input = |generation| {
+            UnitHashInput::builder()
+                .build_plan(build_plan())
+                .lockfile(LOCKFILE)
+                .toolchain(
+                    ToolchainDescriptor::builder()
+                        .host("x86_64-unknown-linux-gnu")
+                        .build(),
+                )
+                .cache_generation(generation)
+                .build()
+        };
+        let initial = unit_hashes(&input(0))?;
+        let bumped = unit_hashes(&input(1))?;
+        for (initial, bumped) in initial.iter().zip(&bumped) {
+            pretty_assert_eq!(initial.unit_hash, bumped.unit_hash);
+            assert_ne!(initial.saved_unit_hash, bumped.saved_unit_hash);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn cache_scope_changes_saved_hashes() -> Result<()> {
+        let input = |scope: Option<&str>| -> Result<UnitHashInput> {
+            Ok(UnitHashInput::builder()
+                .build_plan(build_plan())
+                .lockfile(LOCKFILE)
+                .toolchain(
+                    ToolchainDescriptor::builder()
+                        .host("x86_64-unknown-linux-gnu")
+                        .build(),
+                )
+                .maybe_cache_scope(scope.map(CacheScope::new).transpose()?)
+                .build())
+        };
+        let unscoped = unit_hashes(&input(None)?)?;
+        let api = unit_hashes(&input(Some("acme/api"))?)?;
+        let web = unit_hashes(&input(Some("acme/web"))?)?;
+        for ((unscoped, api), web) in unscoped.iter().zip(&api).zip(&web) {
+            pretty_assert_eq!(unscoped.unit_hash, api.unit_hash);
+            assert_ne!(unscoped.saved_unit_hash, api.saved_unit_hash);
+            assert_ne!(api.saved_unit_hash, web.saved_unit_hash);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn stale_lockfile_fails() {
+        let lockfile = LOCKFILE.replace("0.2.170", "0.2.171");
+        let input = UnitHashInput::builder()
+            .build_plan(build_plan())
+            .lockfile(lockfile)
+            .toolchain(
+                ToolchainDescriptor::builder()
+                    .host("x86_64-unknown-linux-gnu")
+                    .build(),
+            )
+            .build();
+        assert!(unit_hashes(&input).is_err());
+    }
+
+    #[tokio::test]
+    async fn matches_workspace_units() -> Result<()> {
+        let args = CargoBuildArguments::from_iter(Vec::<String>::new());
+        let workspace = Workspace::from_argv(&args).await?;
+        let build_plan = workspace.build_plan(&args).await?;
+        let lockfile = fs::read_buffered_utf8(&workspace.root.try_join_file("Cargo.lock")?)
+            .await?
+            .expect("workspace should have a lockfile");
+
+        let input = UnitHashInput::builder()
+            .build_plan(build_plan.clone())
+            .lockfile(lockfile)
+            .toolchain(
+                ToolchainDescriptor::builder()
+                    .host(workspace.host_arch.as_str())
+                    .build(),
+            )
+            .build();
+        let computed = unit_hashes(&input)?
+            .into_iter()
+            .map(|unit| unit.unit_hash)
+            .collect::<HashSet<_>>();
+        let expected = workspace
+            .units_from_build_plan(build_plan)
+            .await?
+            .iter()
+            .map(UnitPlan::info)
+            .map(|info| info.unit_hash.clone())
+            .collect::<HashSet<_>>();
+
+        assert!(!expected.is_empty(), "workspace should have units");
+        pretty_assert_eq!(computed, expected);
+        Ok(())
+    }
+}
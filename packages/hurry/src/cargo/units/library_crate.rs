@@ -1,4 +1,8 @@
-use std::{collections::HashMap, path::PathBuf, time::SystemTime};
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+    time::SystemTime,
+};
 
 use clients::courier::v1 as courier;
 use color_eyre::{Result, eyre};
@@ -6,12 +10,12 @@ use derive_more::Debug;
 use itertools::Itertools as _;
 use serde::{Deserialize, Serialize};
 use tap::Pipe as _;
-use tracing::instrument;
+use tracing::{debug, instrument};
 
 use crate::{
     cargo::{DepInfo, Fingerprint, QualifiedPath, SavedFile, UnitPlanInfo, Workspace},
-    fs,
-    path::{AbsFilePath, JoinWith as _, RelFilePath, TryJoinWith as _},
+    fs, mk_rel_dir,
+    path::{AbsFilePath, JoinWith as _, RelDirPath, RelFilePath, TryJoinWith as _},
 };
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -19,34 +23,102 @@ pub struct LibraryCrateUnitPlan {
     pub info: UnitPlanInfo,
     pub src_path: AbsFilePath,
     pub outputs: Vec<AbsFilePath>,
+
+    /// The Cargo features enabled for the crate.
+    #[serde(default)]
+    pub features: BTreeSet<String>,
+
+    /// The key under which near matches for this unit are saved and
+    /// restored. See the `near_match` module for details.
+    #[serde(default)]
+    pub near_match_key: Option<String>,
+
+    /// Whether Cargo leaves the unit hash out of the unit's file names, which
+    /// it does for `cdylib` and `dylib` workspace members.
+    #[serde(default)]
+    pub unhashed_outputs: bool,
+
+    /// The hash in the name of the unit's fingerprint directory, for units
+    /// with unhashed outputs; see [`LibraryCrateUnitPlan::locate_fingerprint_dir`].
+    #[serde(default)]
+    pub fingerprint_hash: Option<String>,
 }
 
 impl LibraryCrateUnitPlan {
     pub fn dep_info_file(&self) -> Result<RelFilePath> {
-        self.info.deps_dir()?.try_join_file(format!(
-            "{}-{}.d",
-            self.info.crate_name, self.info.unit_hash
-        ))
+        let deps_dir = self.info.deps_dir()?;
+        if self.unhashed_outputs {
+            deps_dir.try_join_file(format!("{}.d", self.info.crate_name))
+        } else {
+            deps_dir.try_join_file(format!(
+                "{}-{}.d",
+                self.info.crate_name, self.info.unit_hash
+            ))
+        }
+    }
+
+    /// The fingerprint directory, relative to the unit's profile directory.
+    pub fn fingerprint_dir(&self) -> Result<RelDirPath> {
+        match &self.fingerprint_hash {
+            Some(hash) => mk_rel_dir!(".fingerprint")
+                .try_join_dir(format!("{}-{hash}", self.info.package_name)),
+            None => self.info.fingerprint_dir(),
+        }
     }
 
     pub fn encoded_dep_info_file(&self) -> Result<RelFilePath> {
-        self.info
-            .fingerprint_dir()?
+        self.fingerprint_dir()?
             .try_join_file(format!("dep-lib-{}", self.info.crate_name))
     }
 
     pub fn fingerprint_json_file(&self) -> Result<RelFilePath> {
-        self.info
-            .fingerprint_dir()?
+        self.fingerprint_dir()?
             .try_join_file(format!("lib-{}.json", self.info.crate_name))
     }
 
     pub fn fingerprint_hash_file(&self) -> Result<RelFilePath> {
-        self.info
-            .fingerprint_dir()?
+        self.fingerprint_dir()?
             .try_join_file(format!("lib-{}", self.info.crate_name))
     }
 
+    /// Find the fingerprint directory of a unit with unhashed outputs.
+    ///
+    /// Cargo names these directories after a hash of the package alone,
+    /// which the build plan doesn't report. Since it doesn't depend on the
+    /// profile, features, or where the workspace is checked out, it's found
+    /// on disk once Cargo has built the unit, and saved with the unit so that
+    /// restoring into a clean build directory can use it.
+    #[instrument(skip(ws))]
+    pub async fn locate_fingerprint_dir(&mut self, ws: &Workspace) {
+        if !self.unhashed_outputs || self.fingerprint_hash.is_some() {
+            return;
+        }
+        let fingerprints = ws
+            .unit_profile_dir(&self.info)
+            .join(&mk_rel_dir!(".fingerprint"));
+        let Ok(mut entries) = fs::read_dir(&fingerprints).await else {
+            return;
+        };
+        let prefix = format!("{}-", self.info.package_name);
+        let fingerprint = format!("lib-{}.json", self.info.crate_name);
+        let mut found = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name();
+            let Some(hash) = name.to_str().and_then(|name| name.strip_prefix(&prefix)) else {
+                continue;
+            };
+            if !hash.contains('-') && fs::exists(entry.path().join(&fingerprint)).await {
+                found.push(hash.to_string());
+            }
+        }
+        // Packages with the same name in different directories are
+        // indistinguishable here, so neither is resolved.
+        if let [hash] = found.as_slice() {
+            debug!(?hash, "located fingerprint directory");
+            self.fingerprint_hash = Some(hash.clone());
+        }
+    }
+
     pub async fn read(&self, ws: &Workspace) -> Result<LibraryFiles> {
         let profile_dir = ws.unit_profile_dir(&self.info);
 
@@ -136,6 +208,7 @@ impl TryFrom<LibraryCrateUnitPlan> for courier::LibraryCrateUnitPlan {
                     .map(|p| Result::<_>::Ok(serde_json::to_string(&p)?.into()))
                     .try_collect::<_, Vec<_>, _>()?,
             )
+            .maybe_fingerprint_hash(value.fingerprint_hash)
             .build()
             .pipe(Ok)
     }
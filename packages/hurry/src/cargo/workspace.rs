@@ -1,4 +1,9 @@
-use std::{collections::HashMap, fmt::Debug, time::SystemTime};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    iter::once,
+    time::{Duration, Instant, SystemTime},
+};
 
 use cargo_metadata::TargetKind;
 use color_eyre::{
@@ -6,24 +11,61 @@ use color_eyre::{
     eyre::{Context, OptionExt as _, bail, eyre},
 };
 use derive_more::{Debug as DebugExt, Display};
+use futures::{Stream, TryStreamExt as _};
 use itertools::Itertools as _;
+use rayon::iter::{
+    IndexedParallelIterator as _, IntoParallelRefIterator as _, ParallelIterator as _,
+};
 use serde::{Deserialize, Serialize};
+use sysinfo::{Pid, ProcessRefreshKind, RefreshKind, System, UpdateKind};
 use tap::{Conv as _, Tap as _, TapFallible as _, TryConv as _};
 use tokio::task::spawn_blocking;
-use tracing::{debug, instrument, trace};
+use tracing::{debug, instrument, trace, warn};
 use uuid::Uuid;
 
 use crate::{
     cargo::{
-        self, BuildPlan, BuildScriptCompilationUnitPlan, BuildScriptExecutionUnitPlan,
-        CargoBuildArguments, CargoCompileMode, Fingerprint, LibraryCrateUnitPlan, Profile,
-        RustcArguments, RustcTarget, RustcTargetPlatform,
+        self, BuildPlan, BuildPlanIndex, BuildPlanInvocation, BuildScriptCompilationUnitPlan,
+        BuildScriptExecutionUnitPlan, CargoBuildArguments, CargoCompileMode, CratePolicy,
+        Fingerprint, LibraryCrateUnitPlan, Profile, RustcArguments, RustcTarget,
+        RustcTargetPlatform, build_plan::is_library_kind, near_match,
     },
+    config::HurryConfig,
     fs, mk_rel_dir,
     path::{AbsDirPath, AbsFilePath, RelDirPath, RelFilePath, RelativeTo as _, TryJoinWith as _},
 };
 use clients::courier::v1 as courier;
 
+mod check;
+mod discover;
+mod identity;
+mod link;
+
+pub use check::{CheckMode, CheckPlan};
+pub use identity::WorkspaceIdentity;
+
+/// How often to check whether another process has released a profile
+/// directory lock.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often to remind the user that we're still waiting on a profile
+/// directory lock.
+const LOCK_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait for a profile directory lock that another process holds.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum LockWait {
+    /// Wait until the lock is released, like Cargo does.
+    #[default]
+    Forever,
+
+    /// Wait at most this long before failing.
+    Timeout(Duration),
+
+    /// Fail immediately.
+    NoWait,
+}
+
 /// The Cargo workspace of a build.
 ///
 /// Workspaces contain all of the information needed to unambiguously specify
@@ -49,6 +91,13 @@ pub struct Workspace {
     /// The $CARGO_HOME value.
     pub cargo_home: AbsDirPath,
 
+    /// Other `$CARGO_HOME`s on this machine, whose paths are qualified like
+    /// paths in `cargo_home` (see [`Workspace::cargo_homes`]).
+    ///
+    /// Set by `[cargo-home] roots` in `hurry.toml`.
+    #[serde(default)]
+    pub cargo_home_roots: Vec<AbsDirPath>,
+
     /// The build profile of this workspace invocation.
     pub profile: Profile,
 
@@ -57,10 +106,37 @@ pub struct Workspace {
 
     /// The architecture of the host machine.
     pub host_arch: RustcTargetPlatform,
+
+    /// Whether the libraries and build scripts of workspace members are
+    /// cached, keyed by the hash of their source files.
+    ///
+    /// Set by `[first-party] cache` in `hurry.toml`.
+    #[serde(default)]
+    pub cache_first_party: bool,
+
+    /// Cache policies for crates by package name, which take precedence over
+    /// the policies the crates declare in their manifests.
+    ///
+    /// Set by `[crates.<name>]` in `hurry.toml`.
+    #[serde(default)]
+    pub crate_policies: BTreeMap<String, CratePolicy>,
+
+    /// The directory the build was invoked in, if known.
+    ///
+    /// This isn't necessarily inside the workspace (e.g. with
+    /// `--manifest-path`). Cargo resolves relative paths in the build
+    /// arguments and finds its configuration from here, so the build plan is
+    /// computed here too; otherwise it's computed in the current directory.
+    #[serde(default)]
+    pub invoked_in: Option<AbsDirPath>,
 }
 
 impl Workspace {
-    /// Create a workspace by parsing `cargo metadata` from the given directory.
+    /// Create a workspace for a build invoked in the given directory.
+    ///
+    /// The workspace is discovered by reading Cargo's manifests and
+    /// configuration directly when possible, falling back to `cargo metadata`
+    /// otherwise.
     #[instrument(name = "Workspace::from_argv_in_dir")]
     pub async fn from_argv_in_dir(
         path: &AbsDirPath,
@@ -68,36 +144,16 @@ impl Workspace {
     ) -> Result<Self> {
         let args = args.as_ref();
 
-        let (root, build_dir) = {
-            // TODO: Maybe we should just replicate this logic and perform it
-            // statically using filesystem operations instead of shelling out?
-            // This costs something on the order of 200ms, which is not
-            // _terrible_ but feels much slower than if we just did our own
-            // filesystem reads, especially since we don't actually use any of
-            // the logic except the paths.
-            let manifest_path = args.manifest_path().map(String::from);
-            let cmd_current_dir = path.as_std_path().to_path_buf();
-            let metadata = spawn_blocking(move || -> Result<_> {
-                cargo_metadata::MetadataCommand::new()
-                    .tap_mut(|cmd| {
-                        if let Some(p) = manifest_path {
-                            cmd.manifest_path(p);
-                        }
-                    })
-                    .current_dir(cmd_current_dir)
-                    .exec()
-                    .context("exec and parse cargo metadata")
-            })
-            .await
-            .context("join task")?
-            .tap_ok(|metadata| trace!(?metadata, "cargo metadata"))
-            .context("get cargo metadata")?;
-            (
-                AbsDirPath::try_from(&metadata.workspace_root)
-                    .context("parse workspace root as absolute directory")?,
-                AbsDirPath::try_from(&metadata.target_directory)
-                    .context("parse workspace target as absolute directory")?,
-            )
+        let (root, build_dir) = match discover::discover(path, args).await {
+            Ok(Some(discovered)) => {
+                trace!(?discovered, "discovered workspace");
+                (discovered.root, discovered.target_dir)
+            }
+            Ok(None) => Self::paths_from_metadata(path, args).await?,
+            Err(err) => {
+                debug!(?err, "discover workspace, falling back to cargo metadata");
+                Self::paths_from_metadata(path, args).await?
+            }
         };
 
         let cargo_home = spawn_blocking({
@@ -113,6 +169,12 @@ impl Workspace {
         let host_arch = {
             let mut cmd = tokio::process::Command::new("cargo");
             cmd.args(["-Z", "unstable-options", "rustc", "--print", "host-tuple"]);
+            // `cargo rustc` needs a package, which isn't necessarily in the
+            // directory the build was invoked in.
+            if let Some(manifest) = args.manifest_path() {
+                cmd.args(["--manifest-path", manifest]);
+            }
+            cmd.current_dir(path.as_std_path());
             // This is apparently still unstable[^1] when invoked as `cargo
             // rustc`.
             //
@@ -141,14 +203,34 @@ impl Workspace {
 
         let profile = args.profile().map(Profile::from).unwrap_or(Profile::Debug);
         let target_arch = args.target();
+        let config = HurryConfig::load(&root)
+            .await
+            .context("load hurry config")?;
+
+        let cargo_home_roots = config
+            .cargo_home
+            .roots
+            .iter()
+            .filter_map(|root| match AbsDirPath::try_from(root.as_str()) {
+                Ok(root) => Some(root),
+                Err(err) => {
+                    warn!(?err, ?root, "ignoring invalid cargo home root");
+                    None
+                }
+            })
+            .collect();
 
         Ok(Self {
             root,
             build_dir,
             cargo_home,
+            cargo_home_roots,
             profile,
             target_arch,
             host_arch,
+            cache_first_party: config.first_party.cache,
+            crate_policies: config.crates,
+            invoked_in: Some(path.clone()),
         })
     }
 
@@ -162,6 +244,50 @@ impl Workspace {
         Self::from_argv_in_dir(&pwd, args).await
     }
 
+    /// Read the workspace root and build directory from `cargo metadata`.
+    ///
+    /// This is the fallback for workspaces that [`discover::discover`]
+    /// doesn't handle.
+    #[instrument(name = "Workspace::paths_from_metadata")]
+    async fn paths_from_metadata(
+        path: &AbsDirPath,
+        args: &CargoBuildArguments,
+    ) -> Result<(AbsDirPath, AbsDirPath)> {
+        let manifest_path = args.manifest_path().map(String::from);
+        let target_dir = args.target_dir().map(String::from);
+        let cmd_current_dir = path.as_std_path().to_path_buf();
+        let metadata = spawn_blocking(move || -> Result<_> {
+            cargo_metadata::MetadataCommand::new()
+                .tap_mut(|cmd| {
+                    if let Some(p) = manifest_path {
+                        cmd.manifest_path(p);
+                    }
+                    // `cargo metadata` doesn't accept `--target-dir`, but
+                    // `--target-dir` takes precedence over everything
+                    // `CARGO_TARGET_DIR` does (including the config files),
+                    // and Cargo resolves both relative to the directory it's
+                    // invoked in. So setting the variable makes Cargo report
+                    // the same target directory that the build will use.
+                    if let Some(dir) = target_dir {
+                        cmd.env("CARGO_TARGET_DIR", dir);
+                    }
+                })
+                .current_dir(cmd_current_dir)
+                .exec()
+                .context("exec and parse cargo metadata")
+        })
+        .await
+        .context("join task")?
+        .tap_ok(|metadata| trace!(?metadata, "cargo metadata"))
+        .context("get cargo metadata")?;
+        Ok((
+            AbsDirPath::try_from(&metadata.workspace_root)
+                .context("parse workspace root as absolute directory")?,
+            AbsDirPath::try_from(&metadata.target_directory)
+                .context("parse workspace target as absolute directory")?,
+        ))
+    }
+
     /// Get the intermediate build artifacts directory for a specific unit.
     ///
     /// ## Cross-Compilation Directory Structure
@@ -232,10 +358,177 @@ impl Workspace {
         }
     }
 
+    /// The `$CARGO_HOME`s that paths are qualified against, in the order
+    /// they're tried.
+    ///
+    /// These are the build's `$CARGO_HOME` and the registered
+    /// `cargo_home_roots`, most specific first (so that a path in a home
+    /// nested in another is qualified against the nested home), then in
+    /// lexical order. The order doesn't depend on the order the roots were
+    /// registered in, so every machine qualifies a path the same way.
+    pub fn cargo_homes(&self) -> Vec<&AbsDirPath> {
+        let mut homes = once(&self.cargo_home)
+            .chain(&self.cargo_home_roots)
+            .collect::<Vec<_>>();
+        homes.sort_by(|a, b| {
+            let depth = |home: &AbsDirPath| home.components().count();
+            depth(b)
+                .cmp(&depth(a))
+                .then_with(|| a.as_std_path().cmp(b.as_std_path()))
+        });
+        homes.dedup();
+        homes
+    }
+
+    /// A path to temporarily move the build directory to.
+    ///
+    /// This is a sibling of the build directory rather than a child of the
+    /// workspace root, since the build directory may be elsewhere (e.g. when
+    /// set with `--target-dir`) and renaming across devices fails.
+    pub fn build_dir_backup(&self) -> Result<AbsDirPath> {
+        let parent = self
+            .build_dir
+            .parent()
+            .ok_or_eyre("build directory has no parent")?;
+        let name = self
+            .build_dir
+            .as_std_path()
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("target");
+        parent.try_join_dir(format!("{name}.backup.{}", Uuid::new_v4()))
+    }
+
+    /// Walk the first-party source files in the workspace.
+    ///
+    /// Files ignored by `.gitignore` or `.hurryignore` files are skipped, as
+    /// is the build directory (even if it isn't ignored).
+    pub fn source_files(&self) -> impl Stream<Item = Result<AbsFilePath>> + Unpin {
+        fs::walk_source_files(&self.root, std::slice::from_ref(&self.build_dir))
+    }
+
+    /// Lock the profile directories that Cargo locks during a build.
+    ///
+    /// Cargo holds an exclusive lock on the `.cargo-lock` file in each profile
+    /// directory it builds into (see `Layout::at` in Cargo's
+    /// `core/compiler/layout.rs`), and blocks until it can acquire it. Holding
+    /// these locks lets Cargo start resolving and downloading dependencies
+    /// while we're still restoring into the profile directories, without
+    /// Cargo reading fingerprints that we haven't finished restoring.
+    ///
+    /// Cargo always locks the host profile directory, even when
+    /// cross-compiling, so that is always locked here too.
+    ///
+    /// If another process (usually another Cargo build) holds a lock, this
+    /// reports who we're waiting on and waits according to `wait`.
+    #[instrument(name = "Workspace::lock_profile_dirs")]
+    pub async fn lock_profile_dirs(&self, wait: LockWait) -> Result<Vec<fs::LockFile<fs::Locked>>> {
+        let dirs = [
+            self.arch_profile_dir(&RustcTarget::ImplicitHost),
+            self.arch_profile_dir(&self.target_arch),
+        ]
+        .into_iter()
+        .unique()
+        .collect::<Vec<_>>();
+
+        let mut locks = Vec::with_capacity(dirs.len());
+        for dir in dirs {
+            fs::create_dir_all(&dir).await?;
+            let path = dir.try_join_file(".cargo-lock")?;
+            let lock = match fs::LockFile::open(path).await?.try_lock().await? {
+                Ok(lock) => lock,
+                Err(lock) => self.wait_for_lock(lock, wait).await?,
+            };
+            locks.push(lock);
+        }
+        Ok(locks)
+    }
+
+    /// Wait for another process to release a profile directory lock,
+    /// reporting progress on stderr while we wait.
+    #[instrument(name = "Workspace::wait_for_lock", skip(self))]
+    async fn wait_for_lock(
+        &self,
+        mut lock: fs::LockFile<fs::Unlocked>,
+        wait: LockWait,
+    ) -> Result<fs::LockFile<fs::Locked>> {
+        let holder = lock_holders(&self.root);
+        let timeout = match wait {
+            LockWait::Forever => None,
+            LockWait::Timeout(timeout) => Some(timeout),
+            LockWait::NoWait => {
+                return Err(eyre!("{holder} holds the build directory lock"))
+                    .with_section(|| lock.to_string().header("Lock file:"))
+                    .suggestion("Wait for the other build to finish, or omit `--hurry-no-wait`");
+            }
+        };
+
+        let start = Instant::now();
+        let mut reported = start;
+        eprintln!("Waiting for {holder} to release the build directory lock...");
+        loop {
+            tokio::time::sleep(LOCK_POLL_INTERVAL).await;
+            lock = match lock.try_lock().await? {
+                Ok(lock) => {
+                    debug!(elapsed = ?start.elapsed(), "acquired lock");
+                    return Ok(lock);
+                }
+                Err(lock) => lock,
+            };
+
+            let elapsed = start.elapsed();
+            if let Some(timeout) = timeout
+                && elapsed >= timeout
+            {
+                return Err(eyre!(
+                    "timed out after {}s waiting for {holder} to release the build directory lock",
+                    timeout.as_secs()
+                ))
+                .with_section(|| lock.to_string().header("Lock file:"))
+                .suggestion("Increase the timeout with `--hurry-lock-timeout`");
+            }
+            if reported.elapsed() >= LOCK_REPORT_INTERVAL {
+                reported = Instant::now();
+                eprintln!(
+                    "Still waiting for {holder} to release the build directory lock ({}s)...",
+                    elapsed.as_secs()
+                );
+            }
+        }
+    }
+
     /// Get the build plan by running `cargo build --build-plan` with the
     /// provided arguments.
+    ///
+    /// Plans are saved in the workspace's state directory, so Cargo is only
+    /// asked again when the plan's inputs change; see [`BuildPlanIndex`].
     #[instrument(name = "Workspace::build_plan")]
-    async fn build_plan(
+    pub(crate) async fn build_plan(
+        &self,
+        args: impl AsRef<CargoBuildArguments> + std::fmt::Debug,
+    ) -> Result<BuildPlan> {
+        let index = BuildPlanIndex::open(self, args.as_ref())
+            .await
+            .tap_err(|err| warn!(?err, "failed to open build plan index"))
+            .ok();
+        if let Some(index) = &index
+            && let Some(plan) = index.load().await
+        {
+            return Ok(plan);
+        }
+
+        let plan = self.compute_build_plan(args).await?;
+        if let Some(index) = &index
+            && let Err(err) = index.save(&plan).await
+        {
+            warn!(?err, "failed to save build plan");
+        }
+        Ok(plan)
+    }
+
+    /// Compute the build plan by running `cargo build --build-plan`.
+    #[instrument(name = "Workspace::compute_build_plan")]
+    async fn compute_build_plan(
         &self,
         args: impl AsRef<CargoBuildArguments> + std::fmt::Debug,
     ) -> Result<BuildPlan> {
@@ -246,9 +539,7 @@ impl Workspace {
         // have the original issue but at least won't break the build.
         let renamed = if fs::exists(&self.build_dir).await {
             debug!("target exists before running build plan, renaming");
-            let temp = self
-                .root
-                .try_join_dir(format!("target.backup.{}", Uuid::new_v4()))?;
+            let temp = self.build_dir_backup()?;
 
             let renamed = fs::rename(&self.build_dir, &temp).await.is_ok();
             debug!(?renamed, ?temp, "renamed temp target");
@@ -289,9 +580,12 @@ impl Workspace {
             String::from("-Z"),
             String::from("unstable-options"),
         ]);
-        let output = cargo::invoke_output("build", build_args, [("RUSTC_BOOTSTRAP", "1")])
-            .await
-            .context("run cargo command")?;
+        let env = [("RUSTC_BOOTSTRAP", "1")];
+        let output = match &self.invoked_in {
+            Some(dir) => cargo::invoke_output_in(dir, "build", build_args, env).await,
+            None => cargo::invoke_output("build", build_args, env).await,
+        }
+        .context("run cargo command")?;
 
         // When users pass flags like
         // `--message-format=json`, cargo outputs NDJSON (newline-delimited JSON)
@@ -343,6 +637,33 @@ impl Workspace {
         self.units_from_build_plan(build_plan).await
     }
 
+    /// Plan the units of `cargo check` (or `cargo clippy`) with the provided
+    /// arguments.
+    ///
+    /// See the `check` module for how this differs from [`Workspace::units`].
+    #[instrument(name = "Workspace::check_plan")]
+    pub async fn check_plan(
+        &self,
+        mode: CheckMode,
+        args: impl AsRef<CargoBuildArguments> + std::fmt::Debug,
+    ) -> Result<CheckPlan> {
+        // `cargo check` doesn't support `--build-plan`, so this plans the
+        // equivalent `cargo build` instead.
+        let units = self.units(args).await?;
+        Ok(CheckPlan::new(mode, units))
+    }
+
+    /// The version of `clippy-driver` that `cargo clippy` uses, e.g. `clippy
+    /// 0.1.90 (1159e78c47 2025-09-14)`.
+    #[instrument(name = "Workspace::clippy_version")]
+    pub async fn clippy_version(&self) -> Result<String> {
+        let output = cargo::invoke_output("clippy", ["-V"], [] as [(&str, &str); 0])
+            .await
+            .context("run cargo clippy -V")?;
+        let version = String::from_utf8(output.stdout).context("parse clippy version")?;
+        Ok(version.trim().to_string())
+    }
+
     /// Parse unit plans from a build plan.
     ///
     /// This is the core parsing logic shared by both `units()` and
@@ -364,21 +685,29 @@ impl Workspace {
         // the original invocations array) to UnitHash values. Some invocations
         // will be skipped (first-party, binaries, etc.) but we still need their
         // hashes so that units depending on them can resolve their deps
-        // correctly.
-        let mut index_to_hash = HashMap::new();
-        for (idx, invocation) in build_plan.invocations.iter().enumerate() {
-            if let Some(hash) = invocation.unit_hash()? {
-                index_to_hash.insert(idx, hash);
-            }
-        }
+        // correctly. Invocations are parsed independently of each other, so
+        // this is spread across threads.
+        let index_to_hash = build_plan
+            .invocations
+            .par_iter()
+            .enumerate()
+            .filter_map(|(idx, invocation)| {
+                invocation
+                    .unit_hash()
+                    .transpose()
+                    .map(|hash| hash.map(|hash| (idx, hash)))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
 
         // Phase 2: Create units with deps resolved to hashes.
         let mut units: Vec<UnitPlan> = Vec::new();
-        for mut invocation in build_plan.invocations {
+        let mut crate_policies = HashMap::<String, CratePolicy>::new();
+        let mut source_hashes = HashMap::<String, String>::new();
+        for (idx, mut invocation) in build_plan.invocations.into_iter().enumerate() {
             trace!(?invocation, "build plan invocation");
 
-            // Skip first-party workspace members. We only cache third-party
-            // dependencies. `CARGO_PRIMARY_PACKAGE` is set if the user
+            // Find first-party workspace members, which are only cached when
+            // the workspace opts in. `CARGO_PRIMARY_PACKAGE` is set if the user
             // specifically requested the item to be built[^1]; while it's
             // technically possible for the user to do so for a third-party
             // dependency that's relatively rare (and arguably if they're asking
@@ -386,27 +715,76 @@ impl Workspace {
             // cache).
             //
             // [^1]: https://doc.rust-lang.org/cargo/reference/environment-variables.html#:~:text=CARGO_PRIMARY_PACKAGE
-            if let Some(v) = invocation.env.get("CARGO_PRIMARY_PACKAGE")
-                && v == "1"
-            {
-                trace!("skipping: first party workspace member");
-                continue;
-            }
+            let primary = invocation
+                .env
+                .get("CARGO_PRIMARY_PACKAGE")
+                .is_some_and(|v| v == "1");
 
             // But also, `CARGO_PRIMARY_PACKAGE` is not set for execution (as
             // opposed to compilation) units[^1]! So we use this heuristic as a
             // fallback.
             //
             // [^1]: https://doc.rust-lang.org/cargo/reference/environment-variables.html#:~:text=This%20is%20only%20set%20when%20compiling%20the%20package%20(not%20when%20running%20binaries%20or%20tests).
-            if invocation
-                .cwd
-                .try_conv::<AbsFilePath>()?
-                .relative_to(&self.cargo_home)
-                .is_err()
-            {
-                trace!("skipping: package outside of $CARGO_HOME");
-                continue;
-            }
+            let package_dir = AbsDirPath::try_from(invocation.cwd.as_str())?;
+            let outside_cargo_home = package_dir.relative_to(&self.cargo_home).is_err();
+
+            // First-party units are keyed by the hash of their package's
+            // source files, since their version doesn't change when their
+            // source does. Only libraries and build scripts are cached:
+            // binaries and test harnesses are what the user is iterating on.
+            let source_hash = if primary || outside_cargo_home {
+                if !self.cache_first_party {
+                    trace!("skipping: first party workspace member");
+                    continue;
+                }
+                if package_dir.relative_to(&self.root).is_err() {
+                    trace!("skipping: package outside of workspace");
+                    continue;
+                }
+                if !is_first_party_cacheable(&invocation) {
+                    trace!("skipping: first party binary or test");
+                    continue;
+                }
+                let hash = match source_hashes.get(&invocation.cwd) {
+                    Some(hash) => hash.clone(),
+                    None => {
+                        let hash = self
+                            .source_hash(&package_dir)
+                            .await
+                            .with_context(|| format!("hash sources of {package_dir}"))?;
+                        source_hashes.insert(invocation.cwd.clone(), hash.clone());
+                        hash
+                    }
+                };
+                if link::is_linked(&invocation.target_kind) {
+                    let args = RustcArguments::from_iter(invocation.args.iter().cloned());
+                    let hash = link::fold_link_inputs(&hash, &args)
+                        .await
+                        .with_context(|| format!("hash link inputs of {package_dir}"))?;
+                    Some(hash)
+                } else {
+                    Some(hash)
+                }
+            } else {
+                None
+            };
+
+            // Crates can override the cache policy for their own units in
+            // their manifest. Cargo runs each unit in its package's directory,
+            // which is where the manifest is. The workspace's `hurry.toml`
+            // can override it again, e.g. for third-party crates that are
+            // known not to build hermetically.
+            let policy = match self.crate_policies.get(&invocation.package_name) {
+                Some(policy) => *policy,
+                None => match crate_policies.get(&invocation.cwd) {
+                    Some(policy) => *policy,
+                    None => {
+                        let policy = CratePolicy::read(&package_dir).await;
+                        crate_policies.insert(invocation.cwd.clone(), policy);
+                        policy
+                    }
+                },
+            };
 
             // Figure out what kind of unit this invocation is.
             let package_name = invocation.package_name;
@@ -506,6 +884,8 @@ impl Workspace {
                                 crate_name,
                                 target_arch,
                                 deps,
+                                policy,
+                                source_hash: source_hash.clone(),
                             },
                             src_path,
                         };
@@ -562,6 +942,8 @@ impl Workspace {
                                 crate_name,
                                 target_arch,
                                 deps,
+                                policy,
+                                source_hash: source_hash.clone(),
                             },
                             build_script_program_name,
                         };
@@ -580,11 +962,7 @@ impl Workspace {
             } else if invocation.target_kind == [TargetKind::Bin] {
                 // Binaries are _always_ first-party code. Do nothing for now.
                 continue;
-            } else if invocation.target_kind.contains(&TargetKind::Lib)
-                || invocation.target_kind.contains(&TargetKind::RLib)
-                || invocation.target_kind.contains(&TargetKind::CDyLib)
-                || invocation.target_kind.contains(&TargetKind::ProcMacro)
-            {
+            } else if invocation.target_kind.iter().any(is_library_kind) {
                 // Sanity check: everything here should be a dependency being
                 // compiled.
                 if invocation.compile_mode != CargoCompileMode::Build {
@@ -599,8 +977,12 @@ impl Workspace {
                 // are `.so.dwp` files).
                 //
                 // Note there is no need to resolve `links` for library crates.
-                // They are never linked unless they are first-party, and we are
-                // skipping first-party crates for now anyway.
+                // They are never linked unless they are first-party, and Cargo
+                // uplifts first-party libraries again even when they're fresh.
+                let unit_hash = index_to_hash
+                    .get(&idx)
+                    .cloned()
+                    .ok_or_eyre("library crate has no unit hash")?;
                 let outputs = invocation
                     .outputs
                     .into_iter()
@@ -610,36 +992,36 @@ impl Workspace {
                 let args = RustcArguments::from_iter(invocation.args);
                 let crate_name = args.crate_name().ok_or_eyre("no crate name")?.to_owned();
                 let src_path = args.src_path().try_into()?;
-                // We could also parse this from `-C extra-filename`.
-                let unit_hash = {
-                    let compiled_file = outputs.first().ok_or_eyre("no compiled files")?;
-                    let filename = compiled_file
-                        .file_name()
-                        .ok_or_eyre("no filename")?
-                        .to_string_lossy();
-                    let filename = filename.split_once('.').ok_or_eyre("no extension")?.0;
-
-                    filename
-                        .rsplit_once('-')
-                        .ok_or_eyre(format!(
-                            "no unit hash suffix in filename: {filename} (all files: {outputs:?})"
-                        ))?
-                        .1
-                        .to_string()
+                let unhashed_outputs = args.extra_filename().is_none();
+
+                let info = UnitPlanInfo {
+                    unit_hash,
+                    package_name,
+                    package_version,
+                    crate_name,
+                    target_arch,
+                    deps,
+                    policy,
+                    source_hash,
                 };
-
-                UnitPlan::LibraryCrate(LibraryCrateUnitPlan {
-                    info: UnitPlanInfo {
-                        unit_hash: unit_hash.into(),
-                        package_name,
-                        package_version,
-                        crate_name,
-                        target_arch,
-                        deps,
-                    },
+                // Near matches are found by crate version, which says nothing
+                // about first-party source.
+                let near_match_key = match info.source_hash {
+                    Some(_) => None,
+                    None => near_match::source(&package_dir, &self.cargo_home)
+                        .map(|source| near_match::key(&info, &source, &args)),
+                };
+                let mut plan = LibraryCrateUnitPlan {
+                    info,
                     src_path,
                     outputs,
-                })
+                    features: args.features(),
+                    near_match_key,
+                    unhashed_outputs,
+                    fingerprint_hash: None,
+                };
+                plan.locate_fingerprint_dir(self).await;
+                UnitPlan::LibraryCrate(plan)
             } else {
                 bail!("unsupported target kind: {:?}", invocation.target_kind);
             };
@@ -648,6 +1030,45 @@ impl Workspace {
 
         Ok(units)
     }
+
+    /// Hash the source files of the package in `package_dir`.
+    ///
+    /// Each file contributes its path relative to the package along with its
+    /// content, so renaming a file changes the hash too. Files ignored by
+    /// `.gitignore` or `.hurryignore` don't contribute.
+    #[instrument(name = "Workspace::source_hash")]
+    async fn source_hash(&self, package_dir: &AbsDirPath) -> Result<String> {
+        let mut files = fs::walk_source_files(package_dir, std::slice::from_ref(&self.build_dir))
+            .try_collect::<Vec<_>>()
+            .await?;
+        files.sort_by(|a, b| a.as_str_lossy().cmp(&b.as_str_lossy()));
+
+        let contents = fs::hash_files(files.clone()).await?;
+
+        let mut hasher = blake3::Hasher::new();
+        for (file, content) in files.iter().zip(contents) {
+            let path = file.relative_to(package_dir)?;
+            let path = path.as_str_lossy();
+            hasher.update(&(path.len() as u64).to_le_bytes());
+            hasher.update(path.as_bytes());
+            hasher.update(content.as_bytes());
+        }
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+}
+
+/// Whether a first-party invocation is one that Hurry caches: library
+/// compilations (including `cdylib` and `staticlib` artifacts for FFI
+/// consumers) and build script compilations and executions.
+fn is_first_party_cacheable(invocation: &BuildPlanInvocation) -> bool {
+    match invocation.compile_mode {
+        CargoCompileMode::Build => {
+            invocation.target_kind == [TargetKind::CustomBuild]
+                || invocation.target_kind.iter().any(is_library_kind)
+        }
+        CargoCompileMode::RunCustomBuild => true,
+        _ => false,
+    }
 }
 
 /// This is a newtype for unit hash strings.
@@ -753,6 +1174,18 @@ pub struct UnitPlanInfo {
     // [^1]: https://github.com/attunehq/cargo/blob/c24e1064277fe51ab72011e2612e556ac56addf7/src/cargo/core/compiler/build_runner/compilation_files.rs#L721-L737
     #[serde(skip)]
     pub deps: Vec<UnitHash>,
+
+    /// The cache policy the unit's crate declares in its manifest.
+    #[serde(default)]
+    pub policy: CratePolicy,
+
+    /// The hash of the source files of the unit's package, for first-party
+    /// units.
+    ///
+    /// Third-party units are identified by their version, so this is `None`
+    /// for them.
+    #[serde(default)]
+    pub source_hash: Option<String>,
 }
 
 impl UnitPlanInfo {
@@ -783,6 +1216,7 @@ impl From<UnitPlanInfo> for courier::UnitPlanInfo {
             .package_name(value.package_name)
             .crate_name(value.crate_name)
             .maybe_target_arch(value.target_arch.conv::<Option<String>>())
+            .maybe_source_hash(value.source_hash)
             .build()
     }
 }
@@ -855,11 +1289,119 @@ impl UnitPlan {
     }
 }
 
+/// Describe the processes that may be holding a profile directory lock in
+/// the workspace, for telling the user what we're waiting on.
+///
+/// Cargo doesn't record which process holds its lock, so this looks for other
+/// Cargo and Hurry processes running in the workspace. If there aren't any
+/// (for example, because the lock is held from another workspace through a
+/// shared target directory), the holder is described generically.
+fn lock_holders(root: &AbsDirPath) -> String {
+    let system = System::new_with_specifics(
+        RefreshKind::nothing()
+            .with_processes(ProcessRefreshKind::nothing().with_cwd(UpdateKind::Always)),
+    );
+    let current = Pid::from_u32(std::process::id());
+    let holders = system
+        .processes()
+        .iter()
+        .filter(|(pid, _)| **pid != current)
+        .filter_map(|(pid, process)| {
+            let name = process.name().to_string_lossy();
+            let name = ["cargo", "hurry"]
+                .into_iter()
+                .find(|candidate| name.starts_with(candidate))?;
+            let cwd = process.cwd()?;
+            cwd.starts_with(root.as_std_path())
+                .then(|| format!("{name} (pid {pid})"))
+        })
+        .sorted()
+        .collect::<Vec<_>>();
+    if holders.is_empty() {
+        String::from("another process")
+    } else {
+        holders.join(", ")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use pretty_assertions::assert_eq as pretty_assert_eq;
 
+    /// A workspace whose build directory is `temp`, along with a lock on its
+    /// debug profile directory held by another handle, like another build
+    /// would hold it.
+    async fn workspace_with_held_lock(
+        temp: &tempfile::TempDir,
+    ) -> (Workspace, fs::LockFile<fs::Locked>) {
+        let args = CargoBuildArguments::from_iter(["--target-dir", temp.path().to_str().unwrap()]);
+        let workspace = Workspace::from_argv_in_dir(&AbsDirPath::current().unwrap(), &args)
+            .await
+            .expect("should open workspace");
+        let profile_dir = workspace.arch_profile_dir(&RustcTarget::ImplicitHost);
+        fs::create_dir_all(&profile_dir).await.unwrap();
+        let held = fs::LockFile::open(profile_dir.try_join_file(".cargo-lock").unwrap())
+            .await
+            .unwrap()
+            .lock()
+            .await
+            .unwrap();
+        (workspace, held)
+    }
+
+    #[tokio::test]
+    async fn lock_no_wait_fails_while_held() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let (workspace, _held) = workspace_with_held_lock(&temp).await;
+
+        let start = Instant::now();
+        let locked = workspace.lock_profile_dirs(LockWait::NoWait).await;
+        assert!(locked.is_err(), "lock is held");
+        // Describing the holder scans running processes, so this allows for
+        // that but not for waiting on the lock.
+        assert!(
+            start.elapsed() < Duration::from_secs(1),
+            "failed immediately"
+        );
+    }
+
+    #[tokio::test]
+    async fn lock_timeout_fails_after_timeout() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let (workspace, _held) = workspace_with_held_lock(&temp).await;
+
+        let timeout = Duration::from_secs(1);
+        let start = Instant::now();
+        let locked = workspace
+            .lock_profile_dirs(LockWait::Timeout(timeout))
+            .await;
+        let elapsed = start.elapsed();
+        assert!(locked.is_err(), "lock is held");
+        assert!(elapsed >= timeout, "waited {elapsed:?}");
+        assert!(
+            elapsed < timeout + LOCK_POLL_INTERVAL * 4,
+            "waited {elapsed:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn lock_waits_for_holder_to_unlock() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let (workspace, held) = workspace_with_held_lock(&temp).await;
+
+        let released = tokio::spawn(async move {
+            tokio::time::sleep(LOCK_POLL_INTERVAL * 2).await;
+            held.unlock().await.unwrap();
+        });
+        let locks = workspace
+            .lock_profile_dirs(LockWait::Forever)
+            .await
+            .expect("lock is released");
+        pretty_assert_eq!(locks.len(), 1);
+        released.await.unwrap();
+    }
+
     #[tokio::test]
     async fn build_plan_flag_order_does_not_matter() {
         // This is a relatively basic test to start with; if we find other edge
@@ -909,4 +1451,113 @@ mod tests {
         assert!(!plan.invocations.is_empty(), "should have invocations");
         assert!(!plan.inputs.is_empty(), "should have inputs");
     }
+
+    #[tokio::test]
+    async fn target_dir_flag_sets_build_dir() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let target = temp.path().join("out");
+        let args = CargoBuildArguments::from_iter(["--target-dir", target.to_str().unwrap()]);
+        let workspace = Workspace::from_argv(&args)
+            .await
+            .expect("should open workspace");
+        pretty_assert_eq!(workspace.build_dir.as_std_path(), target.as_path());
+    }
+
+    #[tokio::test]
+    async fn relative_target_dir_flag_is_relative_to_invocation() {
+        // Cargo resolves `--target-dir` relative to the directory it's invoked
+        // in, not the workspace root.
+        let pwd = AbsDirPath::current().unwrap();
+        let invoked_in = pwd.try_join_dir("src").unwrap();
+        let args = CargoBuildArguments::from_iter(["--target-dir", "relative-target"]);
+        let workspace = Workspace::from_argv_in_dir(&invoked_in, &args)
+            .await
+            .expect("should open workspace");
+        pretty_assert_eq!(
+            workspace.build_dir,
+            invoked_in.try_join_dir("relative-target").unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn manifest_path_from_outside_workspace() {
+        // Building a member by manifest path from another directory plans
+        // against the member's workspace, and only the member's closure.
+        let pwd = AbsDirPath::current().unwrap();
+        let manifest = pwd.try_join_file("Cargo.toml").unwrap();
+        let temp = tempfile::TempDir::new().unwrap();
+        let invoked_in = AbsDirPath::try_from(temp.path().to_path_buf()).unwrap();
+        let target = temp.path().join("out");
+        let args = CargoBuildArguments::from_iter([
+            "--manifest-path",
+            manifest.as_std_path().to_str().unwrap(),
+            "--target-dir",
+            "out",
+        ]);
+        let workspace = Workspace::from_argv_in_dir(&invoked_in, &args)
+            .await
+            .expect("should open workspace");
+
+        let metadata = cargo_metadata::MetadataCommand::new()
+            .manifest_path(manifest.as_std_path())
+            .no_deps()
+            .exec()
+            .unwrap();
+        pretty_assert_eq!(
+            workspace.root.as_std_path(),
+            metadata.workspace_root.as_std_path()
+        );
+        pretty_assert_eq!(workspace.build_dir.as_std_path(), target.as_path());
+
+        let plan = workspace
+            .build_plan(&args)
+            .await
+            .expect("should get build plan");
+        let primary = plan
+            .invocations
+            .iter()
+            .filter(|invocation| {
+                invocation
+                    .env
+                    .get("CARGO_PRIMARY_PACKAGE")
+                    .is_some_and(|v| v == "1")
+            })
+            .map(|invocation| invocation.package_name.as_str())
+            .collect::<std::collections::BTreeSet<_>>();
+        pretty_assert_eq!(primary, std::collections::BTreeSet::from(["hurry"]));
+    }
+
+    #[tokio::test]
+    async fn build_plan_matches_cargo_with_target_dir() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let target = temp.path().join("out");
+        let args = CargoBuildArguments::from_iter(["--target-dir", target.to_str().unwrap()]);
+        let workspace = Workspace::from_argv(&args)
+            .await
+            .expect("should open workspace");
+        let plan = workspace
+            .build_plan(&args)
+            .await
+            .expect("should get build plan");
+
+        // Every output Cargo plans to write should be in the build directory
+        // Hurry restores into and saves from.
+        let outputs = plan
+            .invocations
+            .iter()
+            .flat_map(|invocation| &invocation.outputs)
+            .collect::<Vec<_>>();
+        assert!(!outputs.is_empty(), "should have outputs");
+        for output in outputs {
+            assert!(
+                std::path::Path::new(output).starts_with(workspace.build_dir.as_std_path()),
+                "{output} should be in {}",
+                workspace.build_dir
+            );
+        }
+        assert!(
+            !fs::exists(workspace.build_dir.as_std_path()).await,
+            "build plan should clean up the build directory it created"
+        );
+    }
 }
@@ -0,0 +1,554 @@
+//! The artifact plan for `cargo check`.
+//!
+//! `cargo check` compiles libraries in check mode, which only emits their
+//! metadata (`.rmeta`) and not their code (`.rlib`). Cargo hashes the compile
+//! mode into the unit hash, so checked units have different unit hashes than
+//! the same units built by `cargo build`, and `cargo check` has no
+//! `--build-plan` to learn them from before the build.
+//!
+//! The check plan therefore starts from the build plan of `cargo build` with
+//! the same arguments:
+//! - Units that `cargo check` still builds in full (build scripts, proc
+//!   macros, and the libraries they depend on) are planned as usual, since
+//!   they have the same unit hashes either way.
+//! - Every other library is checked. A checked unit's hash is derived from
+//!   the same inputs as its build-mode unit hash, so the build-mode unit hash
+//!   identifies it. After a check build, Hurry finds each checked unit's
+//!   artifacts in the build directory and saves the unit under a check key
+//!   derived from its build-mode unit hash; later check builds look the key
+//!   up in the cache to learn the checked unit's hash before restoring it.
+//!
+//! Check keys are stored where near-match keys normally are. Checked units
+//! don't have near-match keys of their own, and the only candidate under a
+//! check key is the checked unit itself.
+//!
+//! `cargo clippy` is `cargo check` with `clippy-driver` wrapping `rustc` for
+//! workspace members, so it plans the same way. Cargo doesn't account for the
+//! driver in the unit hashes of the units it checks, so the check keys of
+//! Clippy builds also include the `clippy-driver` version: units checked by
+//! one version of Clippy are never looked up by another, or by `cargo
+//! check`.
+
+use std::collections::{HashMap, HashSet};
+
+use color_eyre::Result;
+use tracing::{debug, instrument, trace};
+
+use crate::{
+    cargo::{LibraryCrateUnitPlan, UnitHash, UnitPlan},
+    fs,
+    path::{AbsDirPath, AbsFilePath, TryJoinWith as _},
+};
+
+/// Distinguishes check keys from near-match keys and any other hash of the
+/// same unit hash.
+const CHECK_KEY_DOMAIN: &str = "hurry-check-key-v1";
+
+/// The Cargo subcommand that checks the units of a [`CheckPlan`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum CheckMode {
+    /// `cargo check`.
+    #[default]
+    Check,
+
+    /// `cargo clippy`, with the version reported by `clippy-driver`.
+    Clippy(String),
+}
+
+/// The units of a `cargo check` build.
+#[derive(Debug, Clone, Default)]
+pub struct CheckPlan {
+    /// The subcommand that checks the units.
+    pub mode: CheckMode,
+
+    /// Units that `cargo check` builds the same way as `cargo build`, in
+    /// dependency order.
+    pub built: Vec<UnitPlan>,
+
+    /// The library units that `cargo check` compiles in check mode, as
+    /// planned by `cargo build` (i.e. under their build-mode unit hashes), in
+    /// dependency order.
+    pub checked: Vec<LibraryCrateUnitPlan>,
+}
+
+impl CheckPlan {
+    /// Split the units of the equivalent `cargo build` into the units that
+    /// `cargo check` builds and the units it checks.
+    ///
+    /// A library that both a build script (or proc macro) and a checked
+    /// library depend on is in both: Cargo builds it for the former and
+    /// checks it for the latter.
+    pub fn new(mode: CheckMode, units: Vec<UnitPlan>) -> Self {
+        let built = {
+            let by_hash = units
+                .iter()
+                .map(|unit| (&unit.info().unit_hash, unit))
+                .collect::<HashMap<_, _>>();
+            let mut built = HashSet::new();
+            let mut pending = units
+                .iter()
+                .filter(|unit| !is_checkable(unit))
+                .map(|unit| &unit.info().unit_hash)
+                .collect::<Vec<_>>();
+            while let Some(hash) = pending.pop() {
+                if !built.insert(hash.clone()) {
+                    continue;
+                }
+                if let Some(unit) = by_hash.get(hash) {
+                    pending.extend(&unit.info().deps);
+                }
+            }
+            built
+        };
+
+        let checked = units
+            .iter()
+            .filter_map(|unit| match unit {
+                UnitPlan::LibraryCrate(plan) if rmeta_output(plan).is_some() => Some(plan.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let built = units
+            .into_iter()
+            .filter(|unit| built.contains(&unit.info().unit_hash))
+            .collect::<Vec<_>>();
+        trace!(?mode, ?built, ?checked, "check plan");
+        Self {
+            mode,
+            built,
+            checked,
+        }
+    }
+
+    /// The check keys of the checked units, mapped to their build-mode unit
+    /// hashes.
+    pub fn check_keys(&self) -> HashMap<String, UnitHash> {
+        self.checked
+            .iter()
+            .map(|plan| {
+                let key = check_key(&self.mode, &plan.info.unit_hash);
+                (key, plan.info.unit_hash.clone())
+            })
+            .collect()
+    }
+
+    /// The units to restore or save, given the unit hashes that the checked
+    /// units (keyed by their build-mode unit hash) are compiled under.
+    ///
+    /// Checked units whose hash isn't known, or that depend on such a unit,
+    /// are left out: Cargo checks them without Hurry's help.
+    pub fn units(&self, hashes: &HashMap<UnitHash, UnitHash>) -> Vec<UnitPlan> {
+        let checkable = self
+            .checked
+            .iter()
+            .map(|plan| &plan.info.unit_hash)
+            .collect::<HashSet<_>>();
+        let checked = self
+            .checked
+            .iter()
+            .filter_map(|plan| checked_unit(&self.mode, plan, hashes, &checkable))
+            .map(UnitPlan::LibraryCrate);
+        self.built.iter().cloned().chain(checked).collect()
+    }
+
+    /// Find the unit hashes that Cargo checked the checked units under, by
+    /// looking for their artifacts in the build directory.
+    ///
+    /// Every copy of a crate names its artifacts after the crate (other
+    /// versions, other features, and any copy Cargo built in full), so
+    /// candidates are narrowed down to metadata-only artifacts whose dep-info
+    /// lists the unit's source file. Units with no candidate or more than one
+    /// are left out.
+    #[instrument(name = "CheckPlan::discover", skip_all)]
+    pub async fn discover(&self) -> Result<HashMap<UnitHash, UnitHash>> {
+        let mut listings = HashMap::<AbsDirPath, HashMap<String, Vec<String>>>::new();
+        let mut hashes = HashMap::new();
+        for plan in &self.checked {
+            let Some(deps_dir) = rmeta_output(plan).and_then(|rmeta| rmeta.parent()) else {
+                continue;
+            };
+            if !listings.contains_key(&deps_dir) {
+                let listing = metadata_only_artifacts(&deps_dir).await?;
+                listings.insert(deps_dir.clone(), listing);
+            }
+            let unit_hashes = listings[&deps_dir]
+                .get(&plan.info.crate_name)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+
+            let mut candidates = Vec::new();
+            for hash in unit_hashes {
+                let dep_info =
+                    deps_dir.try_join_file(format!("{}-{hash}.d", plan.info.crate_name))?;
+                let lists_source = fs::read_buffered_utf8(&dep_info)
+                    .await?
+                    .is_some_and(|dep_info| dep_info.contains(&*plan.src_path.as_str_lossy()));
+                if lists_source {
+                    candidates.push(UnitHash::from(hash.as_str()));
+                }
+            }
+
+            match candidates.as_slice() {
+                [hash] => {
+                    trace!(unit_hash = %plan.info.unit_hash, checked = %hash, "discovered checked unit");
+                    hashes.insert(plan.info.unit_hash.clone(), hash.clone());
+                }
+                [] => trace!(unit_hash = %plan.info.unit_hash, "checked unit not found"),
+                _ => debug!(
+                    unit_hash = %plan.info.unit_hash,
+                    ?candidates,
+                    "skipping checked unit: ambiguous artifacts"
+                ),
+            }
+        }
+        Ok(hashes)
+    }
+}
+
+/// List the metadata-only artifacts in a deps directory, indexed by crate
+/// name.
+///
+/// The directory is listed once for all of the units whose artifacts are in
+/// it. Metadata is named `lib<crate>-<hash>.rmeta`; artifacts that also have
+/// an `.rlib` were built in full and aren't candidates. Each crate's unit
+/// hashes are sorted so that discovery doesn't depend on listing order.
+async fn metadata_only_artifacts(deps_dir: &AbsDirPath) -> Result<HashMap<String, Vec<String>>> {
+    let mut names = HashSet::new();
+    if fs::is_dir(deps_dir.as_std_path()).await {
+        let mut entries = fs::read_dir(deps_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(name) = entry.file_name().to_str() {
+                names.insert(name.to_string());
+            }
+        }
+    }
+
+    let mut listing = HashMap::<String, Vec<String>>::new();
+    for name in &names {
+        let Some((crate_name, hash)) = name
+            .strip_prefix("lib")
+            .and_then(|name| name.strip_suffix(".rmeta"))
+            .and_then(|stem| stem.rsplit_once('-'))
+        else {
+            continue;
+        };
+        if names.contains(&format!("lib{crate_name}-{hash}.rlib")) {
+            continue;
+        }
+        listing
+            .entry(crate_name.to_string())
+            .or_default()
+            .push(hash.to_string());
+    }
+    for hashes in listing.values_mut() {
+        hashes.sort();
+    }
+    Ok(listing)
+}
+
+/// Derive the check key under which the checked copy of the unit with the
+/// build-mode `unit_hash` is saved.
+///
+/// Keys for `cargo check` predate Clippy support and are left unchanged.
+fn check_key(mode: &CheckMode, unit_hash: &UnitHash) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(CHECK_KEY_DOMAIN.as_bytes());
+    hasher.update(b"\0");
+    if let CheckMode::Clippy(version) = mode {
+        hasher.update(b"clippy\0");
+        hasher.update(version.as_bytes());
+        hasher.update(b"\0");
+    }
+    hasher.update(String::from(unit_hash).as_bytes());
+    hasher.finalize().to_hex().to_string()
+}
+
+/// Whether `cargo check` compiles the unit in check mode.
+///
+/// Only libraries with metadata outputs are checked; proc macros (whose only
+/// output is a dynamic library) and build scripts are built in full.
+fn is_checkable(unit: &UnitPlan) -> bool {
+    match unit {
+        UnitPlan::LibraryCrate(plan) => rmeta_output(plan).is_some(),
+        _ => false,
+    }
+}
+
+/// The metadata output of a library unit, if it has one.
+fn rmeta_output(plan: &LibraryCrateUnitPlan) -> Option<&AbsFilePath> {
+    plan.outputs.iter().find(|output| {
+        output
+            .file_name_str_lossy()
+            .is_some_and(|name| name.ends_with(".rmeta"))
+    })
+}
+
+/// The checked copy of a library unit, if its unit hash and the unit hashes
+/// of the checked libraries it depends on are known.
+fn checked_unit(
+    mode: &CheckMode,
+    plan: &LibraryCrateUnitPlan,
+    hashes: &HashMap<UnitHash, UnitHash>,
+    checkable: &HashSet<&UnitHash>,
+) -> Option<LibraryCrateUnitPlan> {
+    let unit_hash = hashes.get(&plan.info.unit_hash)?;
+
+    // Checked libraries depend on the checked copies of the libraries they
+    // use. Their other dependencies (proc macros and build script
+    // executions) are built in full, so they're the same units.
+    let deps = plan
+        .info
+        .deps
+        .iter()
+        .map(|dep| {
+            if checkable.contains(dep) {
+                hashes.get(dep).cloned()
+            } else {
+                Some(dep.clone())
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let output = rmeta_output(plan)?
+        .parent()?
+        .try_join_file(format!("lib{}-{unit_hash}.rmeta", plan.info.crate_name))
+        .ok()?;
+    let mut info = plan.info.clone();
+    info.unit_hash = unit_hash.clone();
+    info.deps = deps;
+    Some(LibraryCrateUnitPlan {
+        info,
+        src_path: plan.src_path.clone(),
+        outputs: vec![output],
+        features: plan.features.clone(),
+        near_match_key: Some(check_key(mode, &plan.info.unit_hash)),
+        unhashed_outputs: false,
+        fingerprint_hash: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::{CheckMode, CheckPlan, check_key, metadata_only_artifacts};
+    use crate::{
+        cargo::{
+            BuildScriptExecutionUnitPlan, CratePolicy, LibraryCrateUnitPlan, RustcTarget, UnitHash,
+            UnitPlan, UnitPlanInfo,
+        },
+        fs,
+        path::{AbsDirPath, AbsFilePath, TryJoinWith as _},
+    };
+
+    fn info(hash: &str, name: &str, deps: &[&str]) -> UnitPlanInfo {
+        UnitPlanInfo {
+            unit_hash: hash.into(),
+            package_name: String::from(name),
+            package_version: String::from("1.0.0"),
+            crate_name: String::from(name),
+            target_arch: RustcTarget::ImplicitHost,
+            deps: deps.iter().copied().map(UnitHash::from).collect(),
+            policy: CratePolicy::default(),
+            source_hash: None,
+        }
+    }
+
+    fn library(hash: &str, name: &str, outputs: &[&str], deps: &[&str]) -> UnitPlan {
+        UnitPlan::LibraryCrate(LibraryCrateUnitPlan {
+            info: info(hash, name, deps),
+            src_path: AbsFilePath::try_from(format!("/registry/{name}/src/lib.rs")).unwrap(),
+            outputs: outputs
+                .iter()
+                .map(|output| AbsFilePath::try_from(format!("/t/debug/deps/{output}")).unwrap())
+                .collect(),
+            features: BTreeSet::new(),
+            near_match_key: None,
+            unhashed_outputs: false,
+            fingerprint_hash: None,
+        })
+    }
+
+    fn hashes(units: &[UnitPlan]) -> Vec<String> {
+        units
+            .iter()
+            .map(|unit| String::from(&unit.info().unit_hash))
+            .collect()
+    }
+
+    /// `app` (first-party, so not planned) uses `serde` and the `derive`
+    /// proc macro; `derive` uses `syn`, and `serde` runs a build script.
+    fn plan() -> CheckPlan {
+        CheckPlan::new(
+            CheckMode::Check,
+            vec![
+                UnitPlan::BuildScriptExecution(BuildScriptExecutionUnitPlan {
+                    info: info("0000000000000001", "serde", &[]),
+                    build_script_program_name: String::from("build-script-build"),
+                }),
+                library(
+                    "0000000000000002",
+                    "syn",
+                    &[
+                        "libsyn-0000000000000002.rlib",
+                        "libsyn-0000000000000002.rmeta",
+                    ],
+                    &[],
+                ),
+                library(
+                    "0000000000000003",
+                    "derive",
+                    &["libderive-0000000000000003.so"],
+                    &["0000000000000002"],
+                ),
+                library(
+                    "0000000000000004",
+                    "serde",
+                    &[
+                        "libserde-0000000000000004.rlib",
+                        "libserde-0000000000000004.rmeta",
+                    ],
+                    &["0000000000000001", "0000000000000003"],
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn splits_built_and_checked_units() {
+        let plan = plan();
+        pretty_assert_eq!(
+            hashes(&plan.built),
+            vec!["0000000000000001", "0000000000000002", "0000000000000003"]
+        );
+        let checked = plan
+            .checked
+            .iter()
+            .map(|plan| String::from(&plan.info.unit_hash))
+            .collect::<Vec<_>>();
+        pretty_assert_eq!(checked, vec!["0000000000000002", "0000000000000004"]);
+    }
+
+    #[test]
+    fn resolves_checked_units() {
+        let plan = plan();
+        let resolved = HashMap::from([
+            (
+                UnitHash::from("0000000000000002"),
+                UnitHash::from("000000000000000b"),
+            ),
+            (
+                UnitHash::from("0000000000000004"),
+                UnitHash::from("000000000000000d"),
+            ),
+        ]);
+        let units = plan.units(&resolved);
+        pretty_assert_eq!(
+            hashes(&units),
+            vec![
+                "0000000000000001",
+                "0000000000000002",
+                "0000000000000003",
+                "000000000000000b",
+                "000000000000000d",
+            ]
+        );
+
+        let UnitPlan::LibraryCrate(serde) = &units[4] else {
+            panic!("checked unit should be a library: {:?}", units[4]);
+        };
+        pretty_assert_eq!(
+            serde.info.deps,
+            vec![
+                UnitHash::from("0000000000000001"),
+                UnitHash::from("0000000000000003"),
+            ]
+        );
+        pretty_assert_eq!(
+            serde.outputs,
+            vec![AbsFilePath::try_from("/t/debug/deps/libserde-000000000000000d.rmeta").unwrap()]
+        );
+        pretty_assert_eq!(
+            serde.near_match_key,
+            Some(check_key(
+                &CheckMode::Check,
+                &UnitHash::from("0000000000000004")
+            ))
+        );
+    }
+
+    #[test]
+    fn leaves_out_units_with_unknown_dependencies() {
+        let plan = CheckPlan::new(vec![
+            library(
+                "0000000000000002",
+                "itoa",
+                &["libitoa-0000000000000002.rmeta"],
+                &[],
+            ),
+            library(
+                "0000000000000004",
+                "serde_json",
+                &["libserde_json-0000000000000004.rmeta"],
+                &["0000000000000002"],
+            ),
+        ]);
+        let resolved = HashMap::from([(
+            UnitHash::from("0000000000000004"),
+            UnitHash::from("000000000000000d"),
+        )]);
+        pretty_assert_eq!(hashes(&plan.units(&resolved)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn separates_check_keys_by_mode() {
+        let hash = UnitHash::from("0000000000000004");
+        let clippy = CheckMode::Clippy(String::from("clippy 0.1.90 (1159e78c47 2025-09-14)"));
+        let newer = CheckMode::Clippy(String::from("clippy 0.1.91 (f8297e351a 2025-10-28)"));
+        assert_ne!(
+            check_key(&CheckMode::Check, &hash),
+            check_key(&clippy, &hash)
+        );
+        assert_ne!(check_key(&clippy, &hash), check_key(&newer, &hash));
+        pretty_assert_eq!(check_key(&clippy, &hash), check_key(&clippy, &hash));
+    }
+
+    #[tokio::test]
+    async fn indexes_metadata_only_artifacts() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let deps_dir = AbsDirPath::try_from(temp.path()).expect("temp dir is absolute");
+        for name in [
+            "libserde-00000000000000b2.rmeta",
+            "libserde-00000000000000a1.rmeta",
+            "libserde-00000000000000c3.rmeta",
+            "libserde-00000000000000c3.rlib",
+            "libserde_json-00000000000000d4.rmeta",
+            "serde-00000000000000a1.d",
+        ] {
+            let path = deps_dir.try_join_file(name).expect("join file");
+            fs::write(&path, "").await.expect("write file");
+        }
+
+        let listing = metadata_only_artifacts(&deps_dir)
+            .await
+            .expect("list artifacts");
+        pretty_assert_eq!(
+            listing,
+            HashMap::from([
+                (
+                    String::from("serde"),
+                    vec![
+                        String::from("00000000000000a1"),
+                        String::from("00000000000000b2"),
+                    ],
+                ),
+                (
+                    String::from("serde_json"),
+                    vec![String::from("00000000000000d4")],
+                ),
+            ])
+        );
+    }
+}
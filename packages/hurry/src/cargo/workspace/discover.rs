@@ -0,0 +1,685 @@
+//! Workspace discovery without `cargo metadata`.
+//!
+//! Running `cargo metadata` costs something on the order of 200ms per
+//! invocation, but Hurry only needs a handful of paths from it. This module
+//! finds them by reading manifests, the lockfile, and Cargo configuration
+//! directly, following the same rules Cargo does.
+//!
+//! Only the common cases are handled. When a workspace relies on anything that
+//! isn't parsed here (e.g. glob `members`, configuration `include`s, or
+//! `--config` overrides), discovery returns `None` and the caller falls back
+//! to `cargo metadata`.
+
+use std::path::{Component, Path, PathBuf};
+
+use color_eyre::{Result, eyre::Context as _};
+use serde::Deserialize;
+use tracing::{debug, instrument, trace};
+
+use crate::{
+    cargo::{CargoBuildArguments, config},
+    fs,
+    path::{AbsDirPath, AbsFilePath, TryJoinWith as _},
+};
+
+/// The parts of a workspace that Hurry reads from `cargo metadata`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Discovered {
+    /// The root directory of the workspace.
+    pub root: AbsDirPath,
+
+    /// The manifest of the package Cargo builds, which is in the workspace at
+    /// `root` but isn't necessarily its root manifest.
+    pub manifest: AbsFilePath,
+
+    /// The target directory of the workspace.
+    pub target_dir: AbsDirPath,
+
+    /// The directories of the packages in the workspace.
+    pub members: Vec<AbsDirPath>,
+
+    /// The packages in the lockfile that come from a registry.
+    pub registry_packages: Vec<LockedPackage>,
+}
+
+/// A package pinned in `Cargo.lock`.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    package: Option<ManifestPackage>,
+    workspace: Option<ManifestWorkspace>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ManifestPackage {
+    workspace: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ManifestWorkspace {
+    #[serde(default)]
+    members: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockfilePackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockfilePackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    build: Option<BuildConfig>,
+    include: Option<toml::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct BuildConfig {
+    target_dir: Option<String>,
+}
+
+/// How a package relates to the workspace in an ancestor directory.
+enum Membership {
+    Member,
+    Excluded,
+    Unknown,
+}
+
+/// Discover the workspace that Cargo would build when invoked in `cwd` with
+/// `args`, or `None` if it can't be determined without `cargo metadata`.
+#[instrument(name = "discover")]
+pub async fn discover(cwd: &AbsDirPath, args: &CargoBuildArguments) -> Result<Option<Discovered>> {
+    let cargo_home = config::cargo_home(cwd);
+    let env_target_dir = ["CARGO_TARGET_DIR", "CARGO_BUILD_TARGET_DIR"]
+        .into_iter()
+        .find_map(|key| std::env::var(key).ok());
+    discover_with(cwd, args, cargo_home.as_ref(), env_target_dir.as_deref()).await
+}
+
+/// Discover the workspace with the provided `$CARGO_HOME` and target
+/// directory environment variable, rather than reading them from the process.
+async fn discover_with(
+    cwd: &AbsDirPath,
+    args: &CargoBuildArguments,
+    cargo_home: Option<&AbsDirPath>,
+    env_target_dir: Option<&str>,
+) -> Result<Option<Discovered>> {
+    if !args.config_overrides().is_empty() {
+        debug!("config overrides are not supported");
+        return Ok(None);
+    }
+
+    let manifest = match args.manifest_path() {
+        Some(path) => AbsFilePath::try_from(normalize(&cwd.as_std_path().join(path)))
+            .context("parse manifest path")?,
+        None => {
+            let Some(manifest) = find_manifest(cwd).await? else {
+                debug!("no manifest found");
+                return Ok(None);
+            };
+            manifest
+        }
+    };
+    let Some(package_dir) = manifest.parent() else {
+        return Ok(None);
+    };
+    let Some(package) = read_toml::<Manifest>(&manifest).await? else {
+        return Ok(None);
+    };
+
+    let (root, workspace) = match package {
+        Manifest {
+            workspace: Some(workspace),
+            ..
+        } => (package_dir.clone(), Some(workspace)),
+        Manifest {
+            package: Some(ManifestPackage {
+                workspace: Some(path),
+            }),
+            ..
+        } => {
+            // Cargo requires the workspace that the package points to to list
+            // it as a member, rather than searching the ancestors.
+            let Ok(root) = AbsDirPath::try_from(normalize(&package_dir.as_std_path().join(path)))
+            else {
+                return Ok(None);
+            };
+            let Some(Manifest {
+                workspace: Some(workspace),
+                ..
+            }) = read_toml::<Manifest>(&root.try_join_file("Cargo.toml")?).await?
+            else {
+                debug!(?root, "package.workspace doesn't point to a workspace");
+                return Ok(None);
+            };
+            match membership(&root, &workspace, &package_dir) {
+                Membership::Member => (root, Some(workspace)),
+                Membership::Excluded | Membership::Unknown => {
+                    debug!(?root, "membership can't be determined");
+                    return Ok(None);
+                }
+            }
+        }
+        Manifest {
+            package: Some(_), ..
+        } => match find_workspace(&package_dir).await? {
+            Some((root, workspace)) => match membership(&root, &workspace, &package_dir) {
+                Membership::Member => (root, Some(workspace)),
+                Membership::Excluded => (package_dir.clone(), None),
+                Membership::Unknown => {
+                    debug!(?root, "membership can't be determined");
+                    return Ok(None);
+                }
+            },
+            None => (package_dir.clone(), None),
+        },
+        Manifest { package: None, .. } => return Ok(None),
+    };
+
+    let members = match &workspace {
+        Some(workspace) => {
+            let Some(mut members) = members(&root, workspace) else {
+                debug!("glob members are not supported");
+                return Ok(None);
+            };
+            let root_manifest = root.try_join_file("Cargo.toml")?;
+            if read_toml::<Manifest>(&root_manifest)
+                .await?
+                .is_some_and(|manifest| manifest.package.is_some())
+            {
+                members.insert(0, root.clone());
+            }
+            members
+        }
+        None => vec![package_dir.clone()],
+    };
+
+    let Some(target_dir) = target_dir(cwd, &root, args, cargo_home, env_target_dir).await? else {
+        return Ok(None);
+    };
+
+    let lockfile = match args.lockfile_path() {
+        Some(path) => {
+            AbsFilePath::try_from(cwd.as_std_path().join(path)).context("parse lockfile path")?
+        }
+        None => root.try_join_file("Cargo.lock")?,
+    };
+    let registry_packages = read_toml::<Lockfile>(&lockfile)
+        .await?
+        .unwrap_or_default()
+        .package
+        .into_iter()
+        .filter(|package| {
+            package.source.as_deref().is_some_and(|source| {
+                source.starts_with("registry+") || source.starts_with("sparse+")
+            })
+        })
+        .map(|package| LockedPackage {
+            name: package.name,
+            version: package.version,
+        })
+        .collect();
+
+    Ok(Some(Discovered {
+        root,
+        manifest,
+        target_dir,
+        members,
+        registry_packages,
+    }))
+}
+
+/// Find the manifest of the package in or above `cwd`.
+async fn find_manifest(cwd: &AbsDirPath) -> Result<Option<AbsFilePath>> {
+    for dir in ancestors(cwd) {
+        let manifest = dir.try_join_file("Cargo.toml")?;
+        if fs::exists(manifest.as_std_path()).await {
+            return Ok(Some(manifest));
+        }
+    }
+    Ok(None)
+}
+
+/// Find the closest workspace above the package in `package_dir`.
+///
+/// Like Cargo, this stops at the first manifest with a `[workspace]` table,
+/// whether or not the package is a member of it.
+async fn find_workspace(
+    package_dir: &AbsDirPath,
+) -> Result<Option<(AbsDirPath, ManifestWorkspace)>> {
+    for dir in ancestors(package_dir).skip(1) {
+        let manifest = dir.try_join_file("Cargo.toml")?;
+        if let Some(Manifest {
+            workspace: Some(workspace),
+            ..
+        }) = read_toml::<Manifest>(&manifest).await?
+        {
+            return Ok(Some((dir, workspace)));
+        }
+    }
+    Ok(None)
+}
+
+/// Determine whether the package in `package_dir` is a member of the
+/// workspace at `root`.
+///
+/// Packages can also become members by being path dependencies of the root
+/// package; that isn't checked here, so those packages are `Unknown`.
+fn membership(
+    root: &AbsDirPath,
+    workspace: &ManifestWorkspace,
+    package_dir: &AbsDirPath,
+) -> Membership {
+    let package_dir = package_dir.as_std_path();
+    let excluded = workspace
+        .exclude
+        .iter()
+        .any(|path| package_dir.starts_with(normalize(&root.as_std_path().join(path))));
+    if excluded {
+        return Membership::Excluded;
+    }
+    match members(root, workspace) {
+        Some(members)
+            if members
+                .iter()
+                .any(|member| member.as_std_path() == package_dir) =>
+        {
+            Membership::Member
+        }
+        _ => Membership::Unknown,
+    }
+}
+
+/// The directories of the members listed in the workspace, or `None` if any
+/// are globs.
+fn members(root: &AbsDirPath, workspace: &ManifestWorkspace) -> Option<Vec<AbsDirPath>> {
+    workspace
+        .members
+        .iter()
+        .map(|member| {
+            if member.contains(['*', '?', '[']) {
+                return None;
+            }
+            AbsDirPath::try_from(normalize(&root.as_std_path().join(member))).ok()
+        })
+        .collect()
+}
+
+/// Resolve the target directory the same way Cargo does: `--target-dir`,
+/// then the environment, then `build.target-dir` in configuration, then
+/// `target` in the workspace root.
+async fn target_dir(
+    cwd: &AbsDirPath,
+    root: &AbsDirPath,
+    args: &CargoBuildArguments,
+    cargo_home: Option<&AbsDirPath>,
+    env_target_dir: Option<&str>,
+) -> Result<Option<AbsDirPath>> {
+    // Cargo joins these onto the working directory without normalizing them,
+    // and reports build plan paths the same way.
+    if let Some(dir) = args.target_dir().or(env_target_dir) {
+        return AbsDirPath::try_from(cwd.as_std_path().join(dir))
+            .context("parse target directory")
+            .map(Some);
+    }
+
+    // The first configuration file that sets the key wins.
+    let mut configured = None;
+    for file in config::read::<Config>(cwd, cargo_home).await? {
+        if file.config.include.is_some() {
+            debug!(path = ?file.path, "config includes are not supported");
+            return Ok(None);
+        }
+        if configured.is_none()
+            && let Some(target_dir) = file.config.build.and_then(|build| build.target_dir)
+        {
+            configured = Some(file.base.as_std_path().join(target_dir));
+        }
+    }
+
+    match configured {
+        Some(dir) => AbsDirPath::try_from(dir)
+            .context("parse configured target directory")
+            .map(Some),
+        None => root.try_join_dir("target").map(Some),
+    }
+}
+
+/// Read and parse a TOML file, returning `None` if it doesn't exist or can't
+/// be parsed.
+///
+/// Files that can't be parsed are left to Cargo, which reports them properly.
+async fn read_toml<T: for<'de> Deserialize<'de>>(path: &AbsFilePath) -> Result<Option<T>> {
+    let Some(content) = fs::read_buffered_utf8(path).await? else {
+        return Ok(None);
+    };
+    match toml::from_str(&content) {
+        Ok(parsed) => Ok(Some(parsed)),
+        Err(err) => {
+            trace!(?path, ?err, "parse toml");
+            Ok(None)
+        }
+    }
+}
+
+/// Iterate over `dir` and its ancestors.
+fn ancestors(dir: &AbsDirPath) -> impl Iterator<Item = AbsDirPath> {
+    std::iter::successors(Some(dir.clone()), |dir| dir.parent())
+}
+
+/// Lexically normalize the path, the same way Cargo normalizes manifest
+/// paths.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use tempfile::TempDir;
+
+    use super::{discover_with, normalize};
+    use crate::{
+        cargo::{CargoBuildArguments, config},
+        fs,
+        path::{AbsDirPath, TryJoinWith as _},
+    };
+
+    const PACKAGE: &str = "[package]\nname = \"pkg\"\nversion = \"0.1.0\"\n";
+
+    async fn write(root: &AbsDirPath, path: &str, content: &str) {
+        fs::write(&root.try_join_file(path).unwrap(), content)
+            .await
+            .unwrap();
+    }
+
+    fn temp() -> (TempDir, AbsDirPath) {
+        let temp = TempDir::new().unwrap();
+        let root = AbsDirPath::try_from(temp.path().to_path_buf()).unwrap();
+        (temp, root)
+    }
+
+    #[tokio::test]
+    async fn single_package() {
+        let (_temp, root) = temp();
+        write(&root, "Cargo.toml", PACKAGE).await;
+        let src = root.try_join_dir("src").unwrap();
+
+        let discovered = discover_with(&src, &CargoBuildArguments::empty(), None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        pretty_assert_eq!(discovered.root, root);
+        pretty_assert_eq!(discovered.target_dir, root.try_join_dir("target").unwrap());
+        pretty_assert_eq!(discovered.members, vec![root]);
+    }
+
+    #[tokio::test]
+    async fn workspace_member_with_configured_target_dir() {
+        let (_temp, root) = temp();
+        write(
+            &root,
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/a\"]\n",
+        )
+        .await;
+        write(&root, "crates/a/Cargo.toml", PACKAGE).await;
+        write(
+            &root,
+            ".cargo/config.toml",
+            "[build]\ntarget-dir = \"out\"\n",
+        )
+        .await;
+        write(
+            &root,
+            "Cargo.lock",
+            "version = 4\n\n\
+             [[package]]\nname = \"a\"\nversion = \"0.1.0\"\n\n\
+             [[package]]\nname = \"serde\"\nversion = \"1.0.0\"\n\
+             source = \"registry+https://github.com/rust-lang/crates.io-index\"\n",
+        )
+        .await;
+        let member = root.try_join_dir("crates/a").unwrap();
+
+        let discovered = discover_with(&member, &CargoBuildArguments::empty(), None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        pretty_assert_eq!(discovered.root, root);
+        pretty_assert_eq!(discovered.target_dir, root.try_join_dir("out").unwrap());
+        pretty_assert_eq!(discovered.members, vec![member]);
+        pretty_assert_eq!(
+            discovered
+                .registry_packages
+                .iter()
+                .map(|package| package.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["serde"]
+        );
+    }
+
+    #[tokio::test]
+    async fn target_dir_precedence() {
+        let (_temp, root) = temp();
+        write(&root, "Cargo.toml", PACKAGE).await;
+        write(
+            &root,
+            ".cargo/config.toml",
+            "[build]\ntarget-dir = \"config\"\n",
+        )
+        .await;
+
+        let discovered = discover_with(&root, &CargoBuildArguments::empty(), None, Some("env"))
+            .await
+            .unwrap()
+            .unwrap();
+        pretty_assert_eq!(discovered.target_dir, root.try_join_dir("env").unwrap());
+
+        let args = CargoBuildArguments::from_iter(["--target-dir", "flag"]);
+        let discovered = discover_with(&root, &args, None, Some("env"))
+            .await
+            .unwrap()
+            .unwrap();
+        pretty_assert_eq!(discovered.target_dir, root.try_join_dir("flag").unwrap());
+    }
+
+    #[tokio::test]
+    async fn excluded_package_is_its_own_workspace() {
+        let (_temp, root) = temp();
+        write(&root, "Cargo.toml", "[workspace]\nexclude = [\"vendor\"]\n").await;
+        write(&root, "vendor/pkg/Cargo.toml", PACKAGE).await;
+        let package = root.try_join_dir("vendor/pkg").unwrap();
+
+        let discovered = discover_with(&package, &CargoBuildArguments::empty(), None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        pretty_assert_eq!(discovered.root, package);
+    }
+
+    #[tokio::test]
+    async fn nested_workspace_member_from_manifest_path() {
+        let (_temp, root) = temp();
+        write(
+            &root,
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"tools\"]\nexclude = [\"inner\"]\n",
+        )
+        .await;
+        write(&root, "tools/Cargo.toml", PACKAGE).await;
+        write(
+            &root,
+            "inner/Cargo.toml",
+            "[workspace]\nmembers = [\"crates/a\"]\n",
+        )
+        .await;
+        write(&root, "inner/crates/a/Cargo.toml", PACKAGE).await;
+
+        // The member belongs to the closest workspace, not the one Cargo is
+        // invoked in.
+        let args = CargoBuildArguments::from_iter(["--manifest-path", "inner/crates/a/Cargo.toml"]);
+        let discovered = discover_with(&root, &args, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        let inner = root.try_join_dir("inner").unwrap();
+        pretty_assert_eq!(discovered.root, inner);
+        pretty_assert_eq!(
+            discovered.manifest,
+            root.try_join_file("inner/crates/a/Cargo.toml").unwrap()
+        );
+        pretty_assert_eq!(discovered.target_dir, inner.try_join_dir("target").unwrap());
+        pretty_assert_eq!(
+            discovered.members,
+            vec![inner.try_join_dir("crates/a").unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn virtual_workspace_from_relative_manifest_path() {
+        let (_temp, root) = temp();
+        write(
+            &root,
+            "ws/Cargo.toml",
+            "[workspace]\nmembers = [\"crates/a\", \"crates/b\"]\n",
+        )
+        .await;
+        write(&root, "ws/crates/a/Cargo.toml", PACKAGE).await;
+        write(&root, "ws/crates/b/Cargo.toml", PACKAGE).await;
+        write(&root, "elsewhere/README", "").await;
+        let cwd = root.try_join_dir("elsewhere").unwrap();
+        let ws = root.try_join_dir("ws").unwrap();
+
+        let args = CargoBuildArguments::from_iter(["--manifest-path", "../ws/crates/b/Cargo.toml"]);
+        let discovered = discover_with(&cwd, &args, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        pretty_assert_eq!(discovered.root, ws);
+        pretty_assert_eq!(
+            discovered.manifest,
+            ws.try_join_file("crates/b/Cargo.toml").unwrap()
+        );
+        pretty_assert_eq!(discovered.target_dir, ws.try_join_dir("target").unwrap());
+        pretty_assert_eq!(
+            discovered.members,
+            vec![
+                ws.try_join_dir("crates/a").unwrap(),
+                ws.try_join_dir("crates/b").unwrap(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn explicit_package_workspace() {
+        let (_temp, root) = temp();
+        write(
+            &root,
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/a\"]\n",
+        )
+        .await;
+        // A closer workspace that the package would otherwise belong to.
+        write(&root, "crates/Cargo.toml", "[workspace]\n").await;
+        write(
+            &root,
+            "crates/a/Cargo.toml",
+            "[package]\nname = \"a\"\nversion = \"0.1.0\"\nworkspace = \"../..\"\n",
+        )
+        .await;
+
+        let args = CargoBuildArguments::from_iter(["--manifest-path", "crates/a/Cargo.toml"]);
+        let discovered = discover_with(&root, &args, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        pretty_assert_eq!(discovered.root, root);
+
+        // Packages that point to a workspace that doesn't list them are left
+        // to Cargo, which rejects them.
+        write(&root, "Cargo.toml", "[workspace]\n").await;
+        let discovered = discover_with(&root, &args, None, None).await.unwrap();
+        pretty_assert_eq!(discovered, None);
+    }
+
+    #[tokio::test]
+    async fn unsupported_workspaces_fall_back() {
+        let (_temp, root) = temp();
+        write(
+            &root,
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\"]\n",
+        )
+        .await;
+        write(&root, "crates/a/Cargo.toml", PACKAGE).await;
+        let member = root.try_join_dir("crates/a").unwrap();
+        let discovered = discover_with(&member, &CargoBuildArguments::empty(), None, None)
+            .await
+            .unwrap();
+        pretty_assert_eq!(discovered, None);
+
+        let args = CargoBuildArguments::from_iter(["--config", "build.target-dir=\"out\""]);
+        let discovered = discover_with(&root, &args, None, None).await.unwrap();
+        pretty_assert_eq!(discovered, None);
+    }
+
+    #[tokio::test]
+    async fn matches_cargo_metadata() {
+        let cwd = AbsDirPath::current().unwrap();
+        let Some(discovered) = super::discover(&cwd, &CargoBuildArguments::empty())
+            .await
+            .unwrap()
+        else {
+            // The fast path doesn't have to handle every workspace, but when
+            // it does it has to agree with Cargo.
+            return;
+        };
+
+        let metadata = cargo_metadata::MetadataCommand::new()
+            .current_dir(cwd.as_std_path())
+            .no_deps()
+            .exec()
+            .unwrap();
+        pretty_assert_eq!(
+            discovered.root.as_std_path(),
+            metadata.workspace_root.as_std_path()
+        );
+        pretty_assert_eq!(
+            discovered.target_dir.as_std_path(),
+            metadata.target_directory.as_std_path()
+        );
+    }
+
+    #[test]
+    fn normalizes_lexically() {
+        pretty_assert_eq!(
+            normalize(std::path::Path::new("/a/./b/../c/Cargo.toml")),
+            std::path::PathBuf::from("/a/c/Cargo.toml")
+        );
+    }
+}
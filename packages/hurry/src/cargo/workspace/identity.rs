@@ -0,0 +1,187 @@
+//! Stable workspace identity shared between checkouts.
+//!
+//! Git worktrees, submodule checkouts, and separate clones of the same
+//! repository live at different paths, so anything keyed by the workspace
+//! root treats them as unrelated workspaces. The identity of a workspace is
+//! instead derived from where its repository comes from:
+//! - The `id` set under `[workspace]` in `hurry.toml`, if any.
+//! - Otherwise, the URL of the repository's `origin` remote, so that every
+//!   checkout of the repository shares an identity.
+//! - Otherwise, the repository's common Git directory, which all worktrees of
+//!   a repository share.
+//! - Outside of Git, the workspace root.
+//!
+//! Cargo workspaces in different directories of the same repository get
+//! different identities, since the workspace's path within the repository is
+//! part of the identity.
+
+use std::path::{Path, PathBuf};
+
+use derive_more::Display;
+use tracing::{debug, instrument};
+
+use crate::{config::WorkspaceConfig, path::AbsDirPath};
+
+/// Distinguishes workspace identities from other hashes.
+const KEY_DOMAIN: &str = "hurry-workspace-identity-v1";
+
+/// The stable identity of a workspace.
+#[derive(Clone, Debug, Display, PartialEq, Eq, Hash)]
+#[display("{_0}")]
+pub struct WorkspaceIdentity(String);
+
+impl WorkspaceIdentity {
+    /// Derive the identity of the workspace at `root`.
+    #[instrument(name = "WorkspaceIdentity::resolve")]
+    pub async fn resolve(root: &AbsDirPath, config: &WorkspaceConfig) -> Self {
+        if let Some(id) = config.id.as_deref().filter(|id| !id.is_empty()) {
+            return Self::from_parts("config", id, "");
+        }
+        let Some(repo) = GitRepo::discover(root).await else {
+            debug!("workspace isn't in a git repository");
+            return Self::from_parts("path", &root.to_string(), "");
+        };
+
+        // Git resolves symbolic links in the paths it prints.
+        let root = canonicalize(root.as_std_path()).await;
+        let subdir = root
+            .strip_prefix(&repo.toplevel)
+            .map(|subdir| {
+                subdir
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .unwrap_or_default();
+        let identity = match &repo.remote {
+            Some(remote) => Self::from_parts("remote", &normalize_remote(remote), &subdir),
+            None => Self::from_parts("git", &repo.common_dir, &subdir),
+        };
+        debug!(?repo, %subdir, %identity, "resolved workspace identity");
+        identity
+    }
+
+    /// The identity of the workspace at `subdir` of the repository that
+    /// `source` identifies.
+    fn from_parts(kind: &str, source: &str, subdir: &str) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        for part in [KEY_DOMAIN, kind, source, subdir] {
+            hasher.update(&(part.len() as u64).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+        Self(hasher.finalize().to_hex()[..32].to_string())
+    }
+
+    /// View the identity as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The Git repository that contains a workspace.
+#[derive(Clone, Debug)]
+struct GitRepo {
+    /// The root of the checkout.
+    toplevel: String,
+
+    /// The Git directory shared by every worktree of the repository.
+    common_dir: String,
+
+    /// The URL of the `origin` remote.
+    remote: Option<String>,
+}
+
+impl GitRepo {
+    async fn discover(root: &AbsDirPath) -> Option<Self> {
+        let output = git(root, &["rev-parse", "--show-toplevel", "--git-common-dir"]).await?;
+        let mut lines = output.lines();
+        let toplevel = lines.next()?.to_string();
+
+        // Git prints the common directory relative to the working directory
+        // when it's inside of it.
+        let common_dir = root.as_std_path().join(lines.next()?);
+        let common_dir = canonicalize(&common_dir)
+            .await
+            .to_string_lossy()
+            .into_owned();
+
+        let remote = git(root, &["config", "--get", "remote.origin.url"]).await;
+        Some(Self {
+            toplevel,
+            common_dir,
+            remote,
+        })
+    }
+}
+
+/// Resolve symbolic links in `path`, or return it as-is if that fails.
+async fn canonicalize(path: &Path) -> PathBuf {
+    tokio::fs::canonicalize(path)
+        .await
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Run `git` in `dir`, returning its trimmed output if it succeeds.
+async fn git(dir: &AbsDirPath, args: &[&str]) -> Option<String> {
+    let output = tokio::process::Command::new("git")
+        .args(args)
+        .current_dir(dir.as_std_path())
+        .output()
+        .await;
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8(output.stdout)
+            .ok()
+            .map(|output| output.trim().to_string())
+            .filter(|output| !output.is_empty()),
+        Ok(output) => {
+            debug!(?args, status = ?output.status, "git failed");
+            None
+        }
+        Err(err) => {
+            debug!(?args, ?err, "run git");
+            None
+        }
+    }
+}
+
+/// Normalize a remote URL so that the spellings Git accepts for the same
+/// repository compare equal.
+fn normalize_remote(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    let url = url.strip_suffix(".git").unwrap_or(url);
+    url.to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+
+    use super::{WorkspaceIdentity, normalize_remote};
+
+    #[test_case("https://github.com/attunehq/hurry", "https://github.com/attunehq/hurry"; "plain")]
+    #[test_case("https://github.com/attunehq/hurry.git", "https://github.com/attunehq/hurry"; "git suffix")]
+    #[test_case("https://github.com/AttuneHQ/hurry/", "https://github.com/attunehq/hurry"; "case and slash")]
+    #[test]
+    fn normalizes_remote(url: &str, expected: &str) {
+        pretty_assert_eq!(normalize_remote(url), expected);
+    }
+
+    #[test]
+    fn identity_depends_on_subdir() {
+        let remote = "https://github.com/attunehq/hurry";
+        pretty_assert_eq!(
+            WorkspaceIdentity::from_parts("remote", remote, "packages"),
+            WorkspaceIdentity::from_parts("remote", remote, "packages"),
+        );
+        assert_ne!(
+            WorkspaceIdentity::from_parts("remote", remote, ""),
+            WorkspaceIdentity::from_parts("remote", remote, "packages"),
+        );
+        assert_ne!(
+            WorkspaceIdentity::from_parts("remote", remote, ""),
+            WorkspaceIdentity::from_parts("git", remote, ""),
+        );
+    }
+}
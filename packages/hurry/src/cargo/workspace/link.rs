@@ -0,0 +1,222 @@
+//! Link-time inputs of linked workspace members.
+//!
+//! `cdylib`, `staticlib`, and `dylib` targets are final artifacts: FFI
+//! consumers like Python wheels (via pyo3) and Android apps load them
+//! directly, and Cargo doesn't build them again for anything that depends on
+//! them. Unlike `rlib`s, their content also depends on what's linked into
+//! them, which isn't part of the package's source: the linker and its flags,
+//! and native static libraries found on the library search path.
+//!
+//! The source hash that first-party units are keyed by therefore has these
+//! inputs folded in for linked units, so that changing them doesn't restore
+//! an artifact linked against the old ones. Native libraries that build
+//! scripts produce are already accounted for, since the build script
+//! execution is a dependency of the unit.
+
+use std::collections::BTreeSet;
+
+use cargo_metadata::TargetKind;
+use color_eyre::{Result, eyre::Context as _};
+use tracing::{instrument, trace};
+
+use crate::{
+    cargo::{
+        RustcArgument, RustcArguments,
+        rustc::{RustcCodegenOption, RustcLibrarySearchPathKind, RustcLinkKind},
+    },
+    fs,
+    path::{AbsDirPath, AbsFilePath, TryJoinWith as _},
+};
+
+/// Distinguishes folded source hashes from plain source hashes.
+const KEY_DOMAIN: &str = "hurry-link-inputs-v1";
+
+/// Codegen options that change how the artifact is linked.
+const LINK_CODEGEN_OPTIONS: [&str; 7] = [
+    "linker",
+    "linker-flavor",
+    "link-arg",
+    "link-args",
+    "link-self-contained",
+    "relocation-model",
+    "target-feature",
+];
+
+/// Whether a unit of the target kinds is linked into a final artifact.
+pub fn is_linked(target_kind: &[TargetKind]) -> bool {
+    target_kind.iter().any(|kind| {
+        matches!(
+            kind,
+            TargetKind::CDyLib | TargetKind::StaticLib | TargetKind::DyLib
+        )
+    })
+}
+
+/// Fold the link-time inputs of a linked unit into the unit's source hash.
+#[instrument(skip(args))]
+pub async fn fold_link_inputs(source_hash: &str, args: &RustcArguments) -> Result<String> {
+    let flags = link_flags(args);
+    let archives = static_archives(args).await;
+
+    let mut hasher = blake3::Hasher::new();
+    for field in [KEY_DOMAIN, source_hash] {
+        hasher.update(&(field.len() as u64).to_le_bytes());
+        hasher.update(field.as_bytes());
+    }
+    for flag in &flags {
+        hasher.update(&(flag.len() as u64).to_le_bytes());
+        hasher.update(flag.as_bytes());
+    }
+    let contents = fs::hash_files(archives.clone())
+        .await
+        .context("hash native libraries")?;
+    for (archive, content) in archives.iter().zip(contents) {
+        trace!(?archive, "folding native library");
+        let name = archive.file_name_str_lossy().unwrap_or_default();
+        hasher.update(&(name.len() as u64).to_le_bytes());
+        hasher.update(name.as_bytes());
+        hasher.update(content.as_bytes());
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// The flags of the invocation that change how the artifact is linked, in
+/// the order they're passed.
+fn link_flags(args: &RustcArguments) -> Vec<String> {
+    args.iter()
+        .filter_map(|arg| match arg {
+            RustcArgument::Link(spec) => Some(format!("-l {spec}")),
+            RustcArgument::Codegen(RustcCodegenOption::PreferDynamic) => {
+                Some(String::from("-C prefer-dynamic"))
+            }
+            RustcArgument::Codegen(option @ RustcCodegenOption::Other(key, _))
+                if LINK_CODEGEN_OPTIONS.contains(&key.as_str()) =>
+            {
+                Some(format!("-C {option}"))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The native static libraries linked by `-l static=<name>` that are found
+/// in the invocation's native library search paths.
+///
+/// Build directories are searched too but are left out, since whatever Cargo
+/// puts there is already a dependency of the unit.
+async fn static_archives(args: &RustcArguments) -> Vec<AbsFilePath> {
+    let names = args
+        .iter()
+        .filter_map(|arg| match arg {
+            RustcArgument::Link(spec) if spec.kind == Some(RustcLinkKind::Static) => {
+                Some(spec.name.as_str())
+            }
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    let dirs = args
+        .iter()
+        .filter_map(|arg| match arg {
+            RustcArgument::LibrarySearchPath(path)
+                if matches!(
+                    path.kind(),
+                    RustcLibrarySearchPathKind::All | RustcLibrarySearchPathKind::Native
+                ) =>
+            {
+                AbsDirPath::try_from(path.path()).ok()
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut archives = Vec::new();
+    for name in names {
+        // The first directory with the library is the one the linker uses.
+        for dir in &dirs {
+            let found = [format!("lib{name}.a"), format!("{name}.lib")]
+                .into_iter()
+                .filter_map(|file| dir.try_join_file(file).ok())
+                .find(|file| file.as_std_path().is_file());
+            if let Some(archive) = found {
+                archives.push(archive);
+                break;
+            }
+        }
+    }
+    archives
+}
+
+#[cfg(test)]
+mod tests {
+    use cargo_metadata::TargetKind;
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::{fold_link_inputs, is_linked, link_flags};
+    use crate::cargo::RustcArguments;
+
+    fn args(args: &[&str]) -> RustcArguments {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn linked_kinds() {
+        assert!(is_linked(&[TargetKind::CDyLib, TargetKind::RLib]));
+        assert!(is_linked(&[TargetKind::StaticLib]));
+        assert!(!is_linked(&[TargetKind::Lib]));
+        assert!(!is_linked(&[TargetKind::ProcMacro]));
+    }
+
+    #[test]
+    fn collects_link_flags() {
+        let args = args(&[
+            "--crate-name",
+            "ffi",
+            "src/lib.rs",
+            "-C",
+            "opt-level=3",
+            "-C",
+            "link-arg=-Wl,--no-undefined",
+            "-l",
+            "static=z",
+            "-C",
+            "prefer-dynamic",
+        ]);
+        pretty_assert_eq!(
+            link_flags(&args),
+            vec![
+                String::from("-C link-arg=-Wl,--no-undefined"),
+                String::from("-l static=z"),
+                String::from("-C prefer-dynamic"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn folds_native_libraries() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let lib_dir = temp.path().display().to_string();
+        let archive = temp.path().join("libz.a");
+        std::fs::write(&archive, "one").expect("write archive");
+
+        let args = args(&[
+            "--crate-name",
+            "ffi",
+            "src/lib.rs",
+            "-L",
+            &format!("native={lib_dir}"),
+            "-l",
+            "static=z",
+        ]);
+        let before = fold_link_inputs("source", &args).await.unwrap();
+        pretty_assert_eq!(fold_link_inputs("source", &args).await.unwrap(), before);
+
+        std::fs::write(&archive, "two").expect("write archive");
+        let after = fold_link_inputs("source", &args).await.unwrap();
+        assert_ne!(before, after, "changing a native library changes the hash");
+        assert_ne!(
+            fold_link_inputs("other", &args).await.unwrap(),
+            after,
+            "changing the source changes the hash"
+        );
+    }
+}
@@ -0,0 +1,393 @@
+//! Build tools that wrap Cargo.
+//!
+//! Tools like `maturin` and `wasm-pack` run `cargo build` on the user's
+//! behalf. Hurry can cache their builds as long as it knows which `cargo
+//! build` invocation the tool runs, since that determines the units it
+//! compiles and where Cargo puts them. A [`CargoWrapper`] declares this for a
+//! tool; `hurry wrap <tool>` looks the tool up in [`CargoWrappers`] and runs
+//! it through the same pipeline as `hurry cargo build`.
+//!
+//! Wrappers for `maturin` and `wasm-pack` are built in. Other tools can be
+//! registered without changing Hurry in the `[wrappers.<name>]` sections of
+//! `hurry.toml` (see [`WrapperConfig`]).
+
+use std::{collections::BTreeMap, ffi::OsStr, fmt, process::Stdio, sync::Arc};
+
+use color_eyre::{
+    Result,
+    eyre::{Context as _, bail},
+};
+use tracing::{instrument, trace};
+
+use crate::{cargo::CargoBuildArguments, config::WrapperConfig};
+
+/// A build tool that runs `cargo build` on the user's behalf.
+pub trait CargoWrapper: fmt::Debug + Send + Sync {
+    /// The name the tool is run as, e.g. `hurry wrap maturin`.
+    fn name(&self) -> &str;
+
+    /// The program to run. Defaults to the name.
+    fn program(&self) -> &str {
+        self.name()
+    }
+
+    /// The arguments of the `cargo build` invocation that the tool runs for
+    /// `subcommand` with `argv`, the arguments after the subcommand.
+    ///
+    /// Returns `None` if the subcommand doesn't compile through Cargo (or
+    /// does so in a way Hurry can't cache), in which case the tool is run
+    /// as-is.
+    fn build_args(&self, subcommand: &str, argv: &[String]) -> Option<CargoBuildArguments>;
+}
+
+/// The flags that wrappers commonly accept with the same meaning as `cargo
+/// build`, and whether each takes a value.
+const CARGO_FLAGS: &[(&str, bool)] = &[
+    ("-r", false),
+    ("--release", false),
+    ("--profile", true),
+    ("--target", true),
+    ("--target-dir", true),
+    ("--manifest-path", true),
+    ("-F", true),
+    ("--features", true),
+    ("--all-features", false),
+    ("--no-default-features", false),
+    ("-p", true),
+    ("--package", true),
+    ("--locked", false),
+    ("--frozen", false),
+    ("--offline", false),
+    ("--config", true),
+    ("-Z", true),
+];
+
+/// Keep the flags in `argv` that are in `flags`, along with their values.
+///
+/// `aliases` renames flags before they're looked up, for tools whose short
+/// flags differ from Cargo's. Everything else is dropped, including
+/// positional arguments, since wrappers' positional arguments aren't Cargo's.
+fn forward(argv: &[String], flags: &[(&str, bool)], aliases: &[(&str, &str)]) -> Vec<String> {
+    let rename = |flag: &str| -> String {
+        aliases
+            .iter()
+            .find(|(alias, _)| *alias == flag)
+            .map(|(_, name)| String::from(*name))
+            .unwrap_or_else(|| String::from(flag))
+    };
+    let takes_value = |flag: &str| flags.iter().find(|(name, _)| *name == flag).map(|f| f.1);
+
+    let mut forwarded = Vec::new();
+    let mut argv = argv.iter().take_while(|arg| *arg != "--").peekable();
+    while let Some(arg) = argv.next() {
+        if let Some((flag, value)) = arg.split_once('=')
+            && flag.starts_with('-')
+        {
+            let flag = rename(flag);
+            if takes_value(&flag) == Some(true) {
+                forwarded.push(format!("{flag}={value}"));
+            }
+            continue;
+        }
+        let flag = rename(arg);
+        match takes_value(&flag) {
+            Some(true) => {
+                if let Some(value) = argv.next_if(|value| !value.starts_with('-')) {
+                    forwarded.push(flag);
+                    forwarded.push(value.clone());
+                }
+            }
+            Some(false) => forwarded.push(flag),
+            None => {}
+        }
+    }
+    forwarded
+}
+
+/// [`maturin`](https://www.maturin.rs), which builds Python extension
+/// modules.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Maturin;
+
+impl CargoWrapper for Maturin {
+    fn name(&self) -> &str {
+        "maturin"
+    }
+
+    fn build_args(&self, subcommand: &str, argv: &[String]) -> Option<CargoBuildArguments> {
+        // `maturin publish` also builds, but uploads the result too; running
+        // it through Hurry gains little.
+        if !matches!(subcommand, "build" | "develop") {
+            return None;
+        }
+        let argv = forward(argv, CARGO_FLAGS, &[("-m", "--manifest-path")]);
+        Some(CargoBuildArguments::from_iter(argv))
+    }
+}
+
+/// [`wasm-pack`](https://rustwasm.github.io/wasm-pack), which builds
+/// WebAssembly packages.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WasmPack;
+
+impl WasmPack {
+    /// The target `wasm-pack` compiles for.
+    const TARGET: &str = "wasm32-unknown-unknown";
+
+    /// The `wasm-pack build` flags that take a value. Its `--target` is the
+    /// JavaScript target, not the Rust one.
+    const VALUE_FLAGS: &[&str] = &[
+        "-t",
+        "--target",
+        "-d",
+        "--out-dir",
+        "--out-name",
+        "-s",
+        "--scope",
+        "-m",
+        "--mode",
+        "--profile",
+    ];
+}
+
+impl CargoWrapper for WasmPack {
+    fn name(&self) -> &str {
+        "wasm-pack"
+    }
+
+    fn build_args(&self, subcommand: &str, argv: &[String]) -> Option<CargoBuildArguments> {
+        if subcommand != "build" {
+            return None;
+        }
+
+        // Arguments after `--` are passed to `cargo build` as-is.
+        let (own, extra) = match argv.iter().position(|arg| arg == "--") {
+            Some(split) => (&argv[..split], &argv[split + 1..]),
+            None => (argv, &[][..]),
+        };
+
+        let mut profile = vec![String::from("--release")];
+        let mut path = None;
+        let mut own = own.iter();
+        while let Some(arg) = own.next() {
+            match arg.as_str() {
+                "--dev" => profile.clear(),
+                "--release" | "--profiling" => profile = vec![String::from("--release")],
+                "--profile" => {
+                    if let Some(name) = own.next() {
+                        profile = vec![String::from("--profile"), name.clone()];
+                    }
+                }
+                flag if Self::VALUE_FLAGS.contains(&flag) => {
+                    own.next();
+                }
+                flag if flag.starts_with('-') => {}
+                positional => path = Some(positional),
+            }
+        }
+
+        let mut cargo = vec![
+            String::from("--lib"),
+            String::from("--target"),
+            String::from(Self::TARGET),
+        ];
+        cargo.extend(profile);
+        if let Some(path) = path {
+            cargo.push(String::from("--manifest-path"));
+            cargo.push(format!("{}/Cargo.toml", path.trim_end_matches('/')));
+        }
+        cargo.extend(extra.iter().cloned());
+        Some(CargoBuildArguments::from_iter(cargo))
+    }
+}
+
+/// A wrapper registered in `hurry.toml`.
+///
+/// Its build subcommands forward the flags that mean the same thing to Cargo
+/// (`--release`, `--target`, `--features`, and so on), along with the
+/// `cargo-args` the tool always passes.
+#[derive(Clone, Debug)]
+pub struct ConfiguredWrapper {
+    name: String,
+    config: WrapperConfig,
+}
+
+impl ConfiguredWrapper {
+    pub fn new(name: impl Into<String>, config: WrapperConfig) -> Self {
+        Self {
+            name: name.into(),
+            config,
+        }
+    }
+}
+
+impl CargoWrapper for ConfiguredWrapper {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn program(&self) -> &str {
+        self.config.program.as_deref().unwrap_or(&self.name)
+    }
+
+    fn build_args(&self, subcommand: &str, argv: &[String]) -> Option<CargoBuildArguments> {
+        if !self.config.subcommands().any(|s| s == subcommand) {
+            return None;
+        }
+        let mut cargo = self.config.cargo_args.clone();
+        cargo.extend(forward(argv, CARGO_FLAGS, &[]));
+        Some(CargoBuildArguments::from_iter(cargo))
+    }
+}
+
+/// The wrappers `hurry wrap` knows about, by name.
+#[derive(Clone, Debug)]
+pub struct CargoWrappers(BTreeMap<String, Arc<dyn CargoWrapper>>);
+
+impl CargoWrappers {
+    /// The built-in wrappers.
+    pub fn builtin() -> Self {
+        Self(BTreeMap::new()).with(Maturin).with(WasmPack)
+    }
+
+    /// Register a wrapper, replacing any with the same name.
+    pub fn with(mut self, wrapper: impl CargoWrapper + 'static) -> Self {
+        self.0
+            .insert(String::from(wrapper.name()), Arc::new(wrapper));
+        self
+    }
+
+    /// Register the wrappers set in `hurry.toml`. These replace built-in
+    /// wrappers with the same name.
+    pub fn with_config(self, config: &BTreeMap<String, WrapperConfig>) -> Self {
+        config.iter().fold(self, |wrappers, (name, config)| {
+            wrappers.with(ConfiguredWrapper::new(name, config.clone()))
+        })
+    }
+
+    /// The wrapper with the name.
+    pub fn get(&self, name: &str) -> Option<Arc<dyn CargoWrapper>> {
+        self.0.get(name).cloned()
+    }
+
+    /// The names of the registered wrappers.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+}
+
+/// Run the wrapper's program with the arguments.
+#[instrument]
+pub async fn invoke(
+    wrapper: &dyn CargoWrapper,
+    args: impl IntoIterator<Item = impl AsRef<str>> + fmt::Debug,
+) -> Result<()> {
+    invoke_env(wrapper, args, [] as [(&OsStr, &OsStr); 0]).await
+}
+
+/// Run the wrapper's program with the arguments and additional environment
+/// variables, which the program passes on to Cargo.
+#[instrument]
+pub async fn invoke_env(
+    wrapper: &dyn CargoWrapper,
+    args: impl IntoIterator<Item = impl AsRef<str>> + fmt::Debug,
+    env: impl IntoIterator<Item = (impl AsRef<OsStr>, impl AsRef<OsStr>)> + fmt::Debug,
+) -> Result<()> {
+    let program = wrapper.program();
+    let args = args
+        .into_iter()
+        .map(|arg| String::from(arg.as_ref()))
+        .collect::<Vec<_>>();
+
+    trace!(?program, ?args, "invoke wrapper");
+    let status = tokio::process::Command::new(program)
+        .args(&args)
+        .envs(env)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("could not spawn {program}; is it installed and in your PATH?"))?
+        .wait()
+        .await
+        .with_context(|| format!("could not complete {program} execution"))?;
+    if status.success() {
+        Ok(())
+    } else {
+        bail!("{program} exited with status: {status}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+
+    use super::{CargoWrapper, CargoWrappers, Maturin, WasmPack};
+    use crate::config::WrapperConfig;
+
+    fn argv(args: &str) -> Vec<String> {
+        args.split_whitespace().map(String::from).collect()
+    }
+
+    fn build_args(wrapper: &dyn CargoWrapper, subcommand: &str, args: &str) -> Option<String> {
+        wrapper
+            .build_args(subcommand, &argv(args))
+            .map(|args| args.to_argv().join(" "))
+    }
+
+    #[test_case("build", "", Some(""); "debug by default")]
+    #[test_case("build", "--release -m py/Cargo.toml", Some("--release --manifest-path py/Cargo.toml"); "manifest alias")]
+    #[test_case("develop", "-r -F pyo3/abi3 --target=aarch64-apple-darwin", Some("--release --features pyo3/abi3 --target aarch64-apple-darwin"); "develop")]
+    #[test_case("build", "-i python3.11 python3.12 --strip --out dist", Some(""); "own flags dropped")]
+    #[test_case("publish", "--release", None; "publish")]
+    #[test]
+    fn maturin(subcommand: &str, args: &str, expected: Option<&str>) {
+        pretty_assert_eq!(build_args(&Maturin, subcommand, args).as_deref(), expected);
+    }
+
+    #[test_case("", Some("--lib --target wasm32-unknown-unknown --release"); "release by default")]
+    #[test_case("--dev --target web", Some("--lib --target wasm32-unknown-unknown"); "dev")]
+    #[test_case("crates/app --out-dir pkg", Some("--lib --target wasm32-unknown-unknown --release --manifest-path crates/app/Cargo.toml"); "path")]
+    #[test_case("--profile wasm -- --features simd", Some("--lib --target wasm32-unknown-unknown --profile wasm --features simd"); "extra cargo args")]
+    #[test]
+    fn wasm_pack(args: &str, expected: Option<&str>) {
+        pretty_assert_eq!(build_args(&WasmPack, "build", args).as_deref(), expected);
+    }
+
+    #[test]
+    fn wasm_pack_test_passes_through() {
+        pretty_assert_eq!(build_args(&WasmPack, "test", "--node"), None);
+    }
+
+    #[test]
+    fn configured_wrappers() {
+        let config = BTreeMap::from([(
+            String::from("cargo-lambda"),
+            WrapperConfig {
+                program: Some(String::from("cargo-lambda")),
+                subcommands: vec![String::from("build"), String::from("watch")],
+                cargo_args: argv("--target x86_64-unknown-linux-gnu"),
+            },
+        )]);
+        let wrappers = CargoWrappers::builtin().with_config(&config);
+        pretty_assert_eq!(
+            wrappers.names().collect::<Vec<_>>(),
+            vec!["cargo-lambda", "maturin", "wasm-pack"]
+        );
+
+        let lambda = wrappers.get("cargo-lambda").unwrap();
+        pretty_assert_eq!(lambda.program(), "cargo-lambda");
+        pretty_assert_eq!(
+            build_args(
+                lambda.as_ref(),
+                "build",
+                "--release --arm64 --output-format zip"
+            )
+            .as_deref(),
+            Some("--target x86_64-unknown-linux-gnu --release")
+        );
+        pretty_assert_eq!(build_args(lambda.as_ref(), "deploy", ""), None);
+    }
+}
@@ -1,30 +1,199 @@
-use std::{collections::BTreeSet, convert::identity, fmt::Debug};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    convert::identity,
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
-use clients::{Courier, Token, courier::v1::Key};
-use color_eyre::{Result, eyre::OptionExt};
+use clients::{
+    Courier, Token,
+    courier::v1::{
+        ChunkList, HashAlgorithm, Key,
+        cas::{Dictionary, RESUMABLE_UPLOAD_THRESHOLD},
+    },
+};
+use color_eyre::{
+    Result,
+    eyre::{Context as _, OptionExt, eyre},
+};
 use derive_more::Display;
-use futures::Stream;
-use tracing::{debug, instrument};
+use futures::{Stream, StreamExt as _, TryStreamExt as _, future, stream};
+use tokio::sync::OnceCell;
+use tracing::{debug, instrument, warn};
 use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    config::LocalCacheConfig,
+    fs::{self, LockFile, Locked, Unlocked},
+    path::{AbsDirPath, AbsFilePath, TryJoinWith as _},
+};
+
+pub mod chunk;
+
+/// How many chunks of an object are checked for in Courier at once before
+/// uploading the object.
+const CHUNK_EXISTS_CONCURRENCY: usize = 16;
 
 /// The remote content-addressed storage area backed by Courier.
+///
+/// Bulk transfers compress small objects with the organization's global
+/// compression dictionary if Courier has trained one. The dictionary is
+/// downloaded the first time it's needed and shared between clones.
+///
+/// With chunking enabled, large objects are stored as a list of their chunks
+/// (see [`chunk`]) so that objects that are mostly the same share storage and
+/// only upload the chunks Courier doesn't already have. Keys returned by
+/// [`CourierCas::key`] are then the keys of these lists. Chunked objects are
+/// read back as their content whether or not chunking is enabled.
 #[derive(Clone, Debug, Display)]
 #[display("{client}")]
 pub struct CourierCas {
     client: Courier,
+    dictionary: Arc<OnceCell<Option<Dictionary>>>,
+
+    /// The algorithm used to hash new content. Content is read under whatever
+    /// algorithm its key was hashed with.
+    algorithm: HashAlgorithm,
+
+    /// Whether large objects are stored as lists of their chunks.
+    chunking: bool,
 }
 
 impl CourierCas {
     /// Create a new instance with the given client.
     pub fn new(client: Courier) -> Self {
-        Self { client }
+        Self {
+            client,
+            dictionary: Default::default(),
+            algorithm: HashAlgorithm::default(),
+            chunking: false,
+        }
+    }
+
+    /// Hash new content with the algorithm.
+    ///
+    /// The algorithm must already have been negotiated with Courier, for
+    /// example with [`Courier::negotiate_hash_algorithm`].
+    pub fn with_hash_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Store large objects as lists of their chunks.
+    pub fn with_chunking(mut self, chunking: bool) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    /// The key of the content, hashed with the algorithm used for new
+    /// content.
+    ///
+    /// If the content is stored in chunks, this is the key of its chunk list.
+    pub fn key(&self, content: impl AsRef<[u8]>) -> Key {
+        let content = content.as_ref();
+        if self.chunking && chunk::is_chunkable(content.len()) {
+            chunk::split(self.algorithm, content).list.key()
+        } else {
+            Key::from_buffer_with(self.algorithm, content)
+        }
     }
 
     /// Create a new instance with the provided base url and token.
     /// Instantiates a new [`Courier`] instance.
     pub fn new_client(base: Url, token: Token) -> Result<Self> {
         let client = Courier::new(base, token)?;
-        Ok(Self { client })
+        Ok(Self::new(client))
+    }
+
+    /// The organization's global compression dictionary, if any.
+    ///
+    /// Dictionaries are an optimization, so failing to load one is logged and
+    /// treated as if there is no dictionary.
+    async fn dictionary(&self) -> Option<Dictionary> {
+        self.dictionary
+            .get_or_init(|| async {
+                let load = async || -> Result<Option<Dictionary>> {
+                    let listing = self.client.cas_dictionaries().await?;
+                    match listing.global() {
+                        Some(dictionary) => self.client.cas_dictionary(dictionary).await,
+                        None => Ok(None),
+                    }
+                };
+                load()
+                    .await
+                    .inspect_err(|error| debug!(?error, "failed to load compression dictionary"))
+                    .ok()
+                    .flatten()
+            })
+            .await
+            .clone()
+    }
+
+    /// The objects to upload to store the content under the key: the content
+    /// itself, or if the key is of a chunk list, the list along with whichever
+    /// of its chunks Courier doesn't already have.
+    ///
+    /// Chunks come before the list, so that a list is never stored without
+    /// its chunks.
+    async fn objects(&self, key: Key, content: Vec<u8>) -> Vec<(Key, Vec<u8>)> {
+        let chunked = self
+            .chunking
+            .then(|| chunk::split_for(&key, &content))
+            .flatten();
+        let Some(chunked) = chunked else {
+            return vec![(key, content)];
+        };
+
+        let client = &self.client;
+        let mut objects = stream::iter(chunked.chunks.into_iter().collect::<HashMap<_, _>>())
+            .map(|(chunk, data)| async move {
+                let exists = client.cas_exists(&chunk).await.is_ok_and(identity);
+                (!exists).then(|| (chunk, data.to_vec()))
+            })
+            .buffer_unordered(CHUNK_EXISTS_CONCURRENCY)
+            .filter_map(future::ready)
+            .collect::<Vec<_>>()
+            .await;
+        debug!(?key, new_chunks = objects.len(), "split object into chunks");
+        objects.push((key, chunked.list.encode()));
+        objects
+    }
+
+    /// Read the object with the key as its content, assembling it from its
+    /// chunks if it's a chunk list.
+    async fn assemble(&self, key: Key, content: Vec<u8>) -> Result<(Key, Vec<u8>)> {
+        let Some(list) = ChunkList::decode(&content) else {
+            return Ok((key, content));
+        };
+
+        let dictionary = self.dictionary().await;
+        let unique = list.chunks.iter().cloned().collect::<HashSet<_>>();
+        let mut chunks = HashMap::new();
+        let mut entries = self
+            .client
+            .cas_read_bulk_with_dictionary(unique, dictionary)
+            .await?;
+        while let Some((chunk, data)) = entries.try_next().await? {
+            chunks.insert(chunk, data);
+        }
+        let chunks = list
+            .chunks
+            .iter()
+            .map(|chunk| {
+                chunks
+                    .get(chunk)
+                    .ok_or_else(|| eyre!("chunk {chunk} of {key} does not exist"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let content = list.assemble(chunks)?;
+        debug!(
+            ?key,
+            chunks = list.chunks.len(),
+            "assembled object from chunks"
+        );
+        Ok((key, content))
     }
 
     /// Store the entry in the CAS.
@@ -32,40 +201,85 @@ impl CourierCas {
     /// already existed (false).
     #[instrument(name = "CourierCas::store", skip(content))]
     pub async fn store(&self, content: &[u8]) -> Result<(Key, bool)> {
-        let key = Key::from_buffer(content);
+        let key = self.key(content);
         if self.client.cas_exists(&key).await.is_ok_and(identity) {
             return Ok((key, false));
         }
 
-        self.client.cas_write_bytes(&key, content.to_vec()).await?;
+        if self.chunking && chunk::is_chunkable(content.len()) {
+            self.store_bulk(stream::iter([(key.clone(), content.to_vec())]))
+                .await?;
+        } else if content.len() as u64 > RESUMABLE_UPLOAD_THRESHOLD {
+            self.client
+                .cas_write_resumable(&key, content.to_vec())
+                .await?;
+        } else {
+            self.client.cas_write_bytes(&key, content.to_vec()).await?;
+        }
         debug!(?key, bytes = ?content.len(), "stored content");
         Ok((key, true))
     }
 
+    /// Store a large entry in the CAS in resumable chunks, so that a dropped
+    /// connection doesn't lose what was already uploaded.
+    /// Returns whether the content was actually uploaded (true) or already
+    /// existed (false).
+    ///
+    /// Content stored as a chunk list is uploaded in bulk instead, since its
+    /// chunks are small.
+    #[instrument(name = "CourierCas::store_resumable", skip(content))]
+    pub async fn store_resumable(&self, key: &Key, content: Vec<u8>) -> Result<bool> {
+        if self.client.cas_exists(key).await.is_ok_and(identity) {
+            return Ok(false);
+        }
+        if self.chunking && chunk::is_chunkable(content.len()) {
+            self.store_bulk(stream::iter([(key.clone(), content)]))
+                .await?;
+            return Ok(true);
+        }
+
+        let bytes = content.len();
+        self.client.cas_write_resumable(key, content).await?;
+        debug!(?key, ?bytes, "stored content");
+        Ok(true)
+    }
+
     /// Get the entry out of the CAS.
     #[instrument(name = "CourierCas::get")]
     pub async fn get(&self, key: &Key) -> Result<Option<Vec<u8>>> {
-        self.client.cas_read_bytes(key).await
+        match self.client.cas_read_bytes(key).await? {
+            Some(content) => Ok(Some(self.assemble(key.clone(), content).await?.1)),
+            None => Ok(None),
+        }
     }
 
     /// Get the entry out of the CAS.
     /// Errors if the entry is not available.
     #[instrument(name = "CourierCas::get")]
     pub async fn must_get(&self, key: &Key) -> Result<Vec<u8>> {
-        self.client
-            .cas_read_bytes(key)
-            .await?
-            .ok_or_eyre("key does not exist")
+        self.get(key).await?.ok_or_eyre("key does not exist")
     }
 
     /// Store multiple entries in the CAS via bulk write.
+    ///
+    /// Entries stored in chunks are written as their chunks and chunk lists,
+    /// so the result reports those keys instead.
     #[instrument(name = "CourierCas::store_bulk", skip(entries))]
     pub async fn store_bulk(
         &self,
         entries: impl Stream<Item = (Key, Vec<u8>)> + Unpin + Send + 'static,
     ) -> Result<BulkStoreResult> {
+        let dictionary = self.dictionary().await;
+        let cas = self.clone();
+        let entries = entries
+            .then(move |(key, content)| {
+                let cas = cas.clone();
+                async move { cas.objects(key, content).await }
+            })
+            .flat_map(stream::iter)
+            .boxed();
         self.client
-            .cas_write_bulk(entries)
+            .cas_write_bulk_with_dictionary(entries, dictionary)
             .await
             .map(|response| BulkStoreResult {
                 written: response.written,
@@ -82,12 +296,22 @@ impl CourierCas {
     }
 
     /// Get multiple entries from the CAS via bulk read.
+    ///
+    /// Entries stored in chunks are assembled from their chunks as they're
+    /// read.
     #[instrument(name = "CourierCas::get_bulk", skip(keys))]
     pub async fn get_bulk(
         &self,
         keys: impl IntoIterator<Item = impl Into<Key>>,
     ) -> Result<impl Stream<Item = Result<(Key, Vec<u8>)>> + Unpin> {
-        self.client.cas_read_bulk(keys).await
+        let dictionary = self.dictionary().await;
+        let entries = self
+            .client
+            .cas_read_bulk_with_dictionary(keys, dictionary)
+            .await?;
+        Ok(Box::pin(entries.and_then(move |(key, content)| {
+            self.assemble(key, content)
+        })))
     }
 }
 
@@ -103,3 +327,418 @@ pub struct BulkStoreError {
     pub key: Key,
     pub error: String,
 }
+
+/// How often to check whether another process has finished downloading an
+/// object into the local CAS.
+const DOWNLOAD_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for another process to download an object into the local
+/// CAS before downloading it anyway, in case that process is stuck.
+const DOWNLOAD_WAIT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A claim on downloading an object into the local CAS.
+#[derive(Debug)]
+pub enum DownloadClaim {
+    /// This process downloads the object. Other processes wait for it until
+    /// the lock is dropped, which should happen once the object is stored.
+    ///
+    /// There's no lock if the local CAS is disabled or the lock file couldn't
+    /// be opened, in which case the download isn't shared.
+    Owned(Option<LockFile<Locked>>),
+
+    /// Another process is already downloading the object; wait for it with
+    /// [`LocalCas::wait_for_download`].
+    Busy(LockFile<Unlocked>),
+}
+
+/// The local content-addressed storage area on disk.
+///
+/// This fronts [`CourierCas`]: objects that are restored, prefetched, or
+/// uploaded are kept here so that later restores can skip downloading them.
+/// Objects are stored uncompressed using the same two-level prefix layout as
+/// Courier's storage, which keeps directory sizes manageable.
+///
+/// The local CAS is kept within a size budget by evicting the least recently
+/// used objects; reading an object bumps its mtime, which is what eviction
+/// orders objects by.
+///
+/// Builds in different workspaces on the same machine often need the same
+/// objects at the same time, so downloads into the local CAS are claimed with
+/// lock files (see [`LocalCas::claim_download`]) and each object is only
+/// downloaded once.
+#[derive(Clone, Debug, Display)]
+#[display("{root}")]
+pub struct LocalCas {
+    root: AbsDirPath,
+    max_size: u64,
+}
+
+impl LocalCas {
+    /// The directory, relative to the root, that download lock files are kept
+    /// in.
+    const LOCKS_DIR: &str = "locks";
+
+    /// Create a new instance rooted at the provided directory.
+    pub fn new(root: AbsDirPath) -> Self {
+        Self {
+            root,
+            max_size: LocalCacheConfig::DEFAULT_MAX_SIZE,
+        }
+    }
+
+    /// Open the local CAS in the user's global cache directory.
+    #[instrument(name = "LocalCas::open_default")]
+    pub async fn open_default() -> Result<Self> {
+        let root = fs::user_global_cache_path().await?.try_join_dir("cas")?;
+        fs::create_dir_all(&root).await?;
+        Ok(Self::new(root))
+    }
+
+    /// Open the local CAS in the user's global cache directory, with the size
+    /// budget set in `hurry.toml`.
+    #[instrument(name = "LocalCas::open")]
+    pub async fn open(config: &LocalCacheConfig) -> Result<Self> {
+        Self::open_default()
+            .await
+            .map(|local| local.with_max_size(config.max_size()))
+    }
+
+    /// Keep the local CAS within `max_size` bytes when evicting. A budget of
+    /// zero disables the local CAS: nothing new is stored in it.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    fn key_path(&self, key: &Key) -> Result<AbsFilePath> {
+        let hex = key.to_hex();
+        let prefix1 = hex.chars().take(2).collect::<String>();
+        let prefix2 = hex.chars().skip(2).take(2).collect::<String>();
+        self.root.try_join_combined([prefix1, prefix2], hex)
+    }
+
+    /// Check whether the entry is in the local CAS.
+    #[instrument(name = "LocalCas::exists")]
+    pub async fn exists(&self, key: &Key) -> Result<bool> {
+        Ok(fs::exists(&self.key_path(key)?).await)
+    }
+
+    /// Get the entry out of the local CAS.
+    ///
+    /// Entries whose content doesn't match their key (e.g. because they were
+    /// truncated by a crash) are treated as missing. Chunk lists are read as
+    /// the content of their chunks, and are missing if any chunk is.
+    #[instrument(name = "LocalCas::get")]
+    pub async fn get(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        let Some(content) = self.get_object(key).await? else {
+            return Ok(None);
+        };
+        let Some(list) = ChunkList::decode(&content) else {
+            return Ok(Some(content));
+        };
+
+        let mut chunks = Vec::with_capacity(list.chunks.len());
+        for chunk in &list.chunks {
+            let Some(data) = self.get_object(chunk).await? else {
+                debug!(?key, ?chunk, "local CAS is missing chunk");
+                return Ok(None);
+            };
+            chunks.push(data);
+        }
+        match list.assemble(chunks) {
+            Ok(content) => Ok(Some(content)),
+            Err(error) => {
+                warn!(?key, ?error, "local CAS chunks do not assemble, ignoring");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Get the object stored under the key, without assembling chunk lists.
+    async fn get_object(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        let Some(content) = fs::read_buffered(&self.key_path(key)?).await? else {
+            return Ok(None);
+        };
+        if !key.verify(&content) {
+            warn!(?key, "local CAS entry does not match its key, ignoring");
+            return Ok(None);
+        }
+
+        // Mark the entry as recently used so that eviction keeps it.
+        if let Err(error) = fs::set_mtime(&self.key_path(key)?, SystemTime::now()).await {
+            debug!(?key, ?error, "failed to mark local CAS entry as used");
+        }
+        Ok(Some(content))
+    }
+
+    /// Store the entry in the local CAS.
+    ///
+    /// If the key is of the content's chunk list, the list and its chunks are
+    /// stored instead, so that chunks are shared with other entries.
+    #[instrument(name = "LocalCas::store", skip(content))]
+    pub async fn store(&self, key: &Key, content: &[u8]) -> Result<()> {
+        if self.max_size == 0 {
+            return Ok(());
+        }
+        let Some(chunked) = chunk::split_for(key, content) else {
+            return self.store_object(key, content).await;
+        };
+        for (chunk, data) in &chunked.chunks {
+            if !self.exists(chunk).await? {
+                self.store_object(chunk, data).await?;
+            }
+        }
+        self.store_object(key, &chunked.list.encode()).await
+    }
+
+    /// Store the object under the key.
+    ///
+    /// The content is written to a temporary file and then renamed into place,
+    /// so concurrent readers never see a partially written entry.
+    async fn store_object(&self, key: &Key, content: &[u8]) -> Result<()> {
+        let path = self.key_path(key)?;
+        let temp = self
+            .root
+            .try_join_file(format!("{}.{}.tmp", key.to_hex(), Uuid::new_v4()))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(&parent).await?;
+        }
+        fs::write(&temp, content).await?;
+        fs::rename(&temp, &path).await?;
+        debug!(?key, bytes = ?content.len(), "stored content locally");
+        Ok(())
+    }
+
+    /// Claim the download of the entry into the local CAS.
+    ///
+    /// Claims are shared by every process on this machine. The operating
+    /// system releases a claim when its process exits, so a download that
+    /// crashed partway through doesn't hold up anyone else.
+    #[instrument(name = "LocalCas::claim_download")]
+    pub async fn claim_download(&self, key: &Key) -> DownloadClaim {
+        if self.max_size == 0 {
+            return DownloadClaim::Owned(None);
+        }
+        let claim = async || -> Result<Result<LockFile<Locked>, LockFile<Unlocked>>> {
+            let dir = self.root.try_join_dir(Self::LOCKS_DIR)?;
+            fs::create_dir_all(&dir).await?;
+            let path = dir.try_join_file(format!("{}.lock", key.to_hex()))?;
+            LockFile::open(path).await?.try_lock().await
+        };
+        match claim().await {
+            Ok(Ok(lock)) => DownloadClaim::Owned(Some(lock)),
+            Ok(Err(lock)) => DownloadClaim::Busy(lock),
+            Err(error) => {
+                debug!(?key, ?error, "failed to claim download of local CAS entry");
+                DownloadClaim::Owned(None)
+            }
+        }
+    }
+
+    /// Wait for another process to finish downloading the entry, returning it
+    /// once it's in the local CAS.
+    ///
+    /// Returns `None` if the entry still isn't in the local CAS once the other
+    /// process is done (e.g. because its download failed), or if the other
+    /// process takes longer than [`DOWNLOAD_WAIT_TIMEOUT`]; the caller should
+    /// then download the entry itself.
+    #[instrument(name = "LocalCas::wait_for_download", skip(lock))]
+    pub async fn wait_for_download(
+        &self,
+        key: &Key,
+        mut lock: LockFile<Unlocked>,
+    ) -> Option<Vec<u8>> {
+        let deadline = Instant::now() + DOWNLOAD_WAIT_TIMEOUT;
+        loop {
+            // The lock is only taken to see whether the other process is
+            // done, so it's released again right away.
+            match lock.try_lock().await {
+                Ok(Ok(_)) => break,
+                Ok(Err(unlocked)) => lock = unlocked,
+                Err(error) => {
+                    debug!(
+                        ?key,
+                        ?error,
+                        "failed to wait for download of local CAS entry"
+                    );
+                    return None;
+                }
+            }
+            if Instant::now() >= deadline {
+                warn!(
+                    ?key,
+                    "timed out waiting for another process to download local CAS entry"
+                );
+                return None;
+            }
+            tokio::time::sleep(DOWNLOAD_POLL_INTERVAL).await;
+        }
+
+        match self.get(key).await {
+            Ok(content) => content,
+            Err(error) => {
+                debug!(?key, ?error, "failed to read downloaded local CAS entry");
+                None
+            }
+        }
+    }
+
+    /// Evict the least recently used entries until the local CAS fits in its
+    /// size budget, returning the number of bytes evicted.
+    #[instrument(name = "LocalCas::evict")]
+    pub async fn evict(&self) -> Result<u64> {
+        let mut entries = Vec::new();
+        let mut locks = Vec::new();
+        let mut files = fs::walk_files(&self.root);
+        while let Some(path) = files.try_next().await? {
+            // Temporary files belong to stores that are still in progress.
+            let name = path.file_name_str_lossy().unwrap_or_default();
+            if name.ends_with(".tmp") {
+                continue;
+            }
+            if name.ends_with(".lock") {
+                locks.push(path);
+                continue;
+            }
+
+            // Entries may be evicted concurrently by another process.
+            let Some(metadata) = fs::metadata(&path).await? else {
+                continue;
+            };
+            let used = metadata
+                .modified()
+                .with_context(|| format!("read mtime of {path:?}"))?;
+            entries.push((used, metadata.len(), path));
+        }
+
+        let mut evicted = 0;
+        for (path, size) in lru_victims(entries, self.max_size) {
+            match fs::remove_file(&path).await {
+                Ok(()) => evicted += size,
+                Err(error) => debug!(?path, ?error, "failed to evict local CAS entry"),
+            }
+        }
+        debug!(
+            evicted,
+            max_size = self.max_size,
+            "evicted local CAS entries"
+        );
+
+        // Lock files outlive their downloads, so remove the ones that no
+        // process holds anymore. A process that opened one just before it's
+        // removed may end up downloading an object another process is also
+        // downloading, which only costs the duplicate download.
+        for path in locks {
+            let unheld = match LockFile::open(path.clone()).await {
+                Ok(lock) => matches!(lock.try_lock().await, Ok(Ok(_))),
+                Err(_) => false,
+            };
+            if unheld && let Err(error) = fs::remove_file(&path).await {
+                debug!(?path, ?error, "failed to remove download lock file");
+            }
+        }
+        Ok(evicted)
+    }
+
+    /// Evict entries like [`LocalCas::evict`], logging failures instead of
+    /// returning them since the local CAS is only an optimization.
+    pub async fn trim(&self) {
+        if let Err(error) = self.evict().await {
+            warn!(?error, "failed to evict local CAS entries");
+        }
+    }
+}
+
+/// Select the least recently used entries to remove so that the remaining
+/// entries total at most `max_size` bytes, returning them with their sizes.
+///
+/// Each entry is the time it was last used, its size, and its path.
+fn lru_victims<T>(mut entries: Vec<(SystemTime, u64, T)>, max_size: u64) -> Vec<(T, u64)> {
+    let total = entries.iter().map(|(_, size, _)| size).sum::<u64>();
+    let mut excess = total.saturating_sub(max_size);
+    entries.sort_by_key(|(used, _, _)| *used);
+    entries
+        .into_iter()
+        .map_while(|(_, size, path)| {
+            (excess > 0).then(|| {
+                excess = excess.saturating_sub(size);
+                (path, size)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use clients::courier::v1::{HashAlgorithm, Key};
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::{DownloadClaim, LocalCas, chunk, lru_victims};
+    use crate::path::AbsDirPath;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let now = SystemTime::now();
+        let entries = vec![
+            (now, 10, "recent"),
+            (now - Duration::from_secs(60), 10, "older"),
+            (now - Duration::from_secs(120), 10, "oldest"),
+        ];
+        pretty_assert_eq!(
+            lru_victims(entries.clone(), 15),
+            vec![("oldest", 10), ("older", 10)]
+        );
+        pretty_assert_eq!(lru_victims(entries.clone(), 20), vec![("oldest", 10)]);
+        pretty_assert_eq!(lru_victims(entries, 30), vec![]);
+    }
+
+    #[tokio::test]
+    async fn concurrent_downloads_share_a_claim() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let root = AbsDirPath::try_from(temp.path()).expect("temp dir is absolute");
+        let local = LocalCas::new(root);
+        let content = b"shared object".to_vec();
+        let key = Key::from_buffer(&content);
+
+        let DownloadClaim::Owned(Some(lock)) = local.claim_download(&key).await else {
+            panic!("first claim should own the download");
+        };
+        let DownloadClaim::Busy(waiting) = local.claim_download(&key).await else {
+            panic!("second claim should wait for the first");
+        };
+
+        local.store(&key, &content).await.expect("store object");
+        drop(lock);
+        pretty_assert_eq!(local.wait_for_download(&key, waiting).await, Some(content));
+    }
+
+    #[tokio::test]
+    async fn chunked_objects_share_chunks() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let root = AbsDirPath::try_from(temp.path()).expect("temp dir is absolute");
+        let local = LocalCas::new(root);
+        let cas_key = |content: &[u8]| chunk::split(HashAlgorithm::Blake3, content).list.key();
+
+        let first = (0..4 * chunk::CHUNKING_THRESHOLD)
+            .map(|i| (i * 7919 % 251) as u8)
+            .collect::<Vec<_>>();
+        let mut second = first.clone();
+        second.extend_from_slice(b"appended");
+        let (first_key, second_key) = (cas_key(&first), cas_key(&second));
+
+        local.store(&first_key, &first).await.expect("store first");
+        local
+            .store(&second_key, &second)
+            .await
+            .expect("store second");
+        pretty_assert_eq!(local.get(&first_key).await.unwrap(), Some(first.clone()));
+        pretty_assert_eq!(local.get(&second_key).await.unwrap(), Some(second));
+
+        let chunks = chunk::split(HashAlgorithm::Blake3, &first).list.chunks;
+        let removed = local.key_path(&chunks[0]).unwrap();
+        tokio::fs::remove_file(removed.as_std_path()).await.unwrap();
+        pretty_assert_eq!(local.get(&first_key).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,126 @@
+//! Content-defined chunking of large objects.
+//!
+//! Large objects are split with FastCDC, so that an edit to part of an object
+//! only changes the chunks around it; see [`ChunkList`] for how chunked
+//! objects are stored. An object's chunk list is a function of its content,
+//! so the key of a chunked object can always be recomputed from the content.
+
+use clients::courier::v1::{ChunkList, HashAlgorithm, Key};
+use fastcdc::v2020::FastCDC;
+
+/// Objects of at least this many bytes are split into chunks when chunking
+/// is enabled. Smaller objects gain little from deduplication, and splitting
+/// them would mostly add requests.
+pub const CHUNKING_THRESHOLD: u64 = 1024 * 1024;
+
+/// The smallest chunk FastCDC cuts, other than the last chunk of an object.
+const MIN_CHUNK_SIZE: u32 = 64 * 1024;
+
+/// The chunk size FastCDC aims for.
+const AVG_CHUNK_SIZE: u32 = 256 * 1024;
+
+/// The largest chunk FastCDC cuts.
+const MAX_CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// An object split into chunks.
+#[derive(Clone, Debug)]
+pub struct Chunked<'a> {
+    /// The list that the object is stored as.
+    pub list: ChunkList,
+
+    /// The content of each chunk in the list, in order.
+    pub chunks: Vec<(Key, &'a [u8])>,
+}
+
+/// Whether an object of the given size is split into chunks when chunking is
+/// enabled.
+pub fn is_chunkable(size: usize) -> bool {
+    size as u64 >= CHUNKING_THRESHOLD
+}
+
+/// Split the content into chunks, hashing it and its chunks with the
+/// algorithm.
+pub fn split(algorithm: HashAlgorithm, content: &[u8]) -> Chunked<'_> {
+    let chunks = FastCDC::new(content, MIN_CHUNK_SIZE, AVG_CHUNK_SIZE, MAX_CHUNK_SIZE)
+        .map(|chunk| {
+            let data = &content[chunk.offset..chunk.offset + chunk.length];
+            (Key::from_buffer_with(algorithm, data), data)
+        })
+        .collect::<Vec<_>>();
+    let list = ChunkList::new(
+        Key::from_buffer_with(algorithm, content),
+        content.len() as u64,
+        chunks.iter().map(|(key, _)| key.clone()).collect(),
+    );
+    Chunked { list, chunks }
+}
+
+/// Split the content into chunks if the key is the key of its chunk list.
+///
+/// Returns `None` if the key is a key of the content itself, or if it isn't a
+/// key of the content at all.
+pub fn split_for<'a>(key: &Key, content: &'a [u8]) -> Option<Chunked<'a>> {
+    if !is_chunkable(content.len()) || key.verify(content) {
+        return None;
+    }
+    let chunked = split(key.algorithm(), content);
+    (chunked.list.key() == *key).then_some(chunked)
+}
+
+#[cfg(test)]
+mod tests {
+    use clients::courier::v1::{HashAlgorithm, Key};
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::{CHUNKING_THRESHOLD, split, split_for};
+
+    /// Deterministic content that doesn't repeat within a chunk.
+    fn content(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edits_only_change_nearby_chunks() {
+        let original = content(8 * CHUNKING_THRESHOLD as usize, 1);
+        let mut edited = original.clone();
+        edited.splice(1000..1000, *b"inserted");
+
+        let original = split(HashAlgorithm::Blake3, &original);
+        let edited = split(HashAlgorithm::Blake3, &edited);
+        let shared = edited
+            .list
+            .chunks
+            .iter()
+            .filter(|key| original.list.chunks.contains(key))
+            .count();
+        assert!(shared >= original.list.chunks.len() - 2);
+    }
+
+    #[test]
+    fn chunks_assemble_into_content() {
+        let content = content(3 * CHUNKING_THRESHOLD as usize, 2);
+        let chunked = split(HashAlgorithm::Sha256, &content);
+        let assembled = chunked
+            .list
+            .assemble(chunked.chunks.iter().map(|(_, data)| data))
+            .unwrap();
+        pretty_assert_eq!(assembled, content);
+    }
+
+    #[test]
+    fn split_for_recognizes_chunk_list_keys() {
+        let content = content(2 * CHUNKING_THRESHOLD as usize, 3);
+        let list_key = split(HashAlgorithm::Blake3, &content).list.key();
+        assert!(split_for(&list_key, &content).is_some());
+        assert!(split_for(&Key::from_buffer(&content), &content).is_none());
+        assert!(split_for(&Key::from_buffer(b"other"), &content).is_none());
+    }
+}
@@ -0,0 +1,7 @@
+//! Output for CI providers.
+//!
+//! Hurry's regular output is written for a terminal. CI providers can also
+//! show richer output (summaries on the job page, annotations on the diff) if
+//! it's written in their format; the submodules here produce it.
+
+pub mod github;
@@ -0,0 +1,235 @@
+//! GitHub Actions job summaries and workflow annotations.
+//!
+//! When Hurry runs in GitHub Actions, it appends a Markdown summary of the
+//! cache's effect on the build to the file named by `GITHUB_STEP_SUMMARY`,
+//! which GitHub shows on the job's page. Cache warnings (units that couldn't
+//! be uploaded, nondeterministic builds) are also emitted as workflow
+//! annotations[^1], so that they show up on the run without digging through
+//! the logs.
+//!
+//! [^1]: https://docs.github.com/en/actions/reference/workflow-commands-for-github-actions
+
+use color_eyre::{Result, eyre::Context as _};
+use derive_more::Display;
+use tokio::io::AsyncWriteExt as _;
+use tracing::{debug, instrument};
+
+use crate::{
+    cargo::{BuildStats, SaveProgress},
+    progress::format_size,
+};
+
+/// Whether Hurry is running in GitHub Actions.
+pub fn enabled() -> bool {
+    std::env::var("GITHUB_ACTIONS").is_ok_and(|value| value == "true")
+}
+
+/// What the cache did for a build, for the job summary.
+#[derive(Clone, Debug, Default)]
+pub struct JobSummary {
+    /// The cache statistics of the build.
+    pub stats: BuildStats,
+
+    /// What was uploaded after the build, if the upload was waited for.
+    pub saved: Option<SaveProgress>,
+}
+
+impl JobSummary {
+    /// Render the summary as Markdown.
+    pub fn to_markdown(&self) -> String {
+        let stats = &self.stats;
+        let time_saved = stats
+            .time_saved
+            .map(|saved| format!("~{:.1}s", saved.as_secs_f64()))
+            .unwrap_or_else(|| String::from("unknown"));
+        let mut markdown = format!(
+            "### Hurry: `cargo {}`\n\n\
+             | | |\n\
+             | --- | --- |\n\
+             | Cache hits | {}/{} units ({:.1}%) |\n\
+             | Time saved | {time_saved} |\n\
+             | Downloaded | {} |\n",
+            stats.command,
+            stats.restored,
+            stats.units,
+            stats.hit_ratio() * 100.0,
+            format_size(stats.downloaded_bytes),
+        );
+        if let Some(timings) = stats.timings.to_text() {
+            markdown.push_str(&format!("| Time | {timings} |\n"));
+        }
+        match &self.saved {
+            Some(saved) if saved.read_only => {
+                markdown.push_str("| Uploaded | skipped (read-only branch) |\n");
+            }
+            Some(saved) if saved.maintenance.is_some() => {
+                markdown.push_str("| Uploaded | skipped (cache under maintenance) |\n");
+            }
+            Some(saved) => markdown.push_str(&format!(
+                "| Uploaded | {} units ({}) |\n",
+                saved.uploaded_units,
+                format_size(saved.uploaded_bytes),
+            )),
+            None => {}
+        }
+        markdown
+    }
+
+    /// Append the summary to the job summary file.
+    ///
+    /// Does nothing if `GITHUB_STEP_SUMMARY` isn't set.
+    #[instrument(skip(self))]
+    pub async fn write(&self) -> Result<()> {
+        let Some(path) = std::env::var_os("GITHUB_STEP_SUMMARY") else {
+            debug!("GITHUB_STEP_SUMMARY not set, skipping job summary");
+            return Ok(());
+        };
+
+        // Other steps write to the same file, so it's appended to rather than
+        // overwritten.
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("open job summary: {path:?}"))?;
+        file.write_all(self.to_markdown().as_bytes())
+            .await
+            .with_context(|| format!("write job summary: {path:?}"))
+    }
+}
+
+/// The severity of a workflow annotation.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq)]
+pub enum Level {
+    #[display("notice")]
+    Notice,
+
+    #[display("warning")]
+    Warning,
+
+    #[display("error")]
+    Error,
+}
+
+/// A workflow annotation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Annotation {
+    pub level: Level,
+    pub title: String,
+    pub message: String,
+}
+
+impl Annotation {
+    /// Create a warning annotation.
+    pub fn warning(title: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            level: Level::Warning,
+            title: title.into(),
+            message: message.into(),
+        }
+    }
+
+    /// Render the annotation as a workflow command.
+    pub fn to_command(&self) -> String {
+        format!(
+            "::{} title={}::{}",
+            self.level,
+            escape_property(&self.title),
+            escape_data(&self.message),
+        )
+    }
+
+    /// Emit the annotation.
+    ///
+    /// The runner reads workflow commands from both output streams; this uses
+    /// stderr since stdout belongs to Cargo.
+    pub fn emit(&self) {
+        eprintln!("{}", self.to_command());
+    }
+}
+
+/// The annotations for the cache warnings of an upload.
+pub fn save_annotations(saved: &SaveProgress) -> Vec<Annotation> {
+    let mut annotations = Vec::new();
+    if saved.read_only {
+        annotations.push(Annotation::warning(
+            "Hurry cache is read-only",
+            "This branch can only read from the cache, so nothing was uploaded (see `hurry cache write-policy`).",
+        ));
+    }
+    if let Some(reason) = &saved.maintenance {
+        annotations.push(Annotation::warning(
+            "Hurry cache is under maintenance",
+            format!("The cache is read-only for maintenance, so the upload was skipped: {reason}"),
+        ));
+    }
+    for unit in &saved.skipped_invalid {
+        annotations.push(Annotation::warning(
+            "Hurry skipped uploading a unit",
+            format!(
+                "{} wasn't uploaded because its files couldn't be read: {}",
+                unit.package_name, unit.problem
+            ),
+        ));
+    }
+    for unit in &saved.nondeterministic {
+        let action = if unit.refused {
+            "not uploaded"
+        } else {
+            "uploaded"
+        };
+        annotations.push(Annotation::warning(
+            "Hurry found a nondeterministic build",
+            format!(
+                "{} ({}) built differently than the cached copy of the same unit ({action})",
+                unit.package_name, unit.unit_hash
+            ),
+        ));
+    }
+    annotations
+}
+
+/// Escape the message of a workflow command.
+fn escape_data(data: &str) -> String {
+    data.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escape a property value of a workflow command.
+fn escape_property(value: &str) -> String {
+    escape_data(value).replace(':', "%3A").replace(',', "%2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+
+    use super::{Annotation, escape_data, escape_property};
+
+    #[test_case("plain", "plain"; "plain")]
+    #[test_case("100%\nok", "100%25%0Aok"; "percent and newline")]
+    #[test_case("a\r\nb", "a%0D%0Ab"; "carriage return")]
+    #[test]
+    fn escapes_data(data: &str, expected: &str) {
+        pretty_assert_eq!(escape_data(data), expected);
+    }
+
+    #[test_case("a: b, c", "a%3A b%2C c"; "colon and comma")]
+    #[test_case("50%", "50%25"; "percent")]
+    #[test]
+    fn escapes_property(value: &str, expected: &str) {
+        pretty_assert_eq!(escape_property(value), expected);
+    }
+
+    #[test]
+    fn renders_annotation() {
+        let annotation = Annotation::warning("Cache: skipped", "line one\nline two");
+        pretty_assert_eq!(
+            annotation.to_command(),
+            "::warning title=Cache%3A skipped::line one%0Aline two"
+        );
+    }
+}
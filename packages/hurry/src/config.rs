@@ -0,0 +1,1153 @@
+//! Configuration from `hurry.toml`.
+//!
+//! Hurry reads `hurry.toml` from the root of the workspace, or if there isn't
+//! one there, from the user's configuration directory:
+//! - Linux: `$XDG_CONFIG_HOME/hurry/hurry.toml` or
+//!   `$HOME/.config/hurry/hurry.toml`
+//! - macOS: `$HOME/Library/Application Support/com.attunehq.hurry/hurry.toml`
+//! - Windows: `%APPDATA%\attunehq\hurry\config\hurry.toml`
+//!
+//! Files aren't merged: the workspace file replaces the user file entirely.
+//!
+//! `hurry setup` writes the `[api]` section of the user's file. Keep the API
+//! token out of workspace files, since those are usually committed.
+//!
+//! ```toml
+//! hash-algorithm = "sha256"
+//!
+//! [api]
+//! url = "https://app.hurry.build"
+//! token = "..."
+//!
+//! [buffers]
+//! network = 4194304
+//!
+//! [restore]
+//! near-match-features = ["nightly", "unstable-docs"]
+//! overwrite = "if-older"
+//!
+//! [restore.adaptive]
+//! enabled = true
+//! break-even-percent = 50
+//!
+//! [upload]
+//! defer-secs = 30
+//! parallelism = 16
+//! chunking = true
+//!
+//! [state]
+//! shared = true
+//!
+//! [workspace]
+//! id = "attunehq/hurry"
+//!
+//! [first-party]
+//! cache = true
+//!
+//! [local-cache]
+//! max-size = 21474836480
+//!
+//! [proxy]
+//! url = "socks5h://proxy.internal:1080"
+//! no-proxy = "localhost,.internal"
+//!
+//! [network]
+//! transfer-timeout-secs = 3600
+//!
+//! [crates.openssl-sys]
+//! cache = false
+//!
+//! [wrappers.cargo-lambda]
+//! subcommands = ["build"]
+//! cargo-args = ["--target", "x86_64-unknown-linux-gnu"]
+//! ```
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use clients::{
+    ProxyConfig, Token,
+    courier::v1::{HashAlgorithm, PoolOptions, Timeouts, cache::CacheAsOf},
+};
+use color_eyre::{
+    Result,
+    eyre::{Context as _, OptionExt as _},
+};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use tap::TryConv as _;
+use tokio::task::spawn_blocking;
+use tracing::{debug, instrument};
+use url::Url;
+
+use crate::{
+    cargo::{CratePolicy, in_ci},
+    fs, mk_rel_file,
+    path::{AbsDirPath, AbsFilePath, JoinWith as _},
+};
+
+/// The contents of `hurry.toml`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct HurryConfig {
+    /// How Hurry connects to the Hurry API.
+    pub api: ApiConfig,
+
+    /// The proxy used for requests to the Hurry API. If unset, the proxies
+    /// configured in the environment are used.
+    pub proxy: ProxyConfig,
+
+    /// Timeouts and connection pooling for requests to the Hurry API.
+    pub network: NetworkConfig,
+
+    /// The algorithm used to hash artifacts uploaded to the cache. Artifacts
+    /// uploaded with a different algorithm can still be restored, so this can
+    /// be changed without discarding the cache.
+    pub hash_algorithm: HashAlgorithm,
+
+    /// Buffer sizes, in bytes. Sizes that aren't set are tuned for the
+    /// machine the first time Hurry runs on it.
+    pub buffers: BufferSizeConfig,
+
+    /// How units are restored from the cache.
+    pub restore: RestoreConfig,
+
+    /// How units are uploaded to the cache.
+    pub upload: UploadConfig,
+
+    /// Where Hurry keeps its per-workspace state.
+    pub state: StateConfig,
+
+    /// How the workspace is identified across checkouts.
+    pub workspace: WorkspaceConfig,
+
+    /// How the workspace's own crates are cached.
+    pub first_party: FirstPartyConfig,
+
+    /// How objects are cached on this machine.
+    pub local_cache: LocalCacheConfig,
+
+    /// Other `$CARGO_HOME`s used on this machine.
+    pub cargo_home: CargoHomeConfig,
+
+    /// When the daemon prewarms the workspace's cache.
+    pub prewarm: PrewarmConfig,
+
+    /// Build tools that wrap Cargo, by the name `hurry wrap` runs them as.
+    pub wrappers: BTreeMap<String, WrapperConfig>,
+
+    /// Cache policies for crates by package name, with the same settings as
+    /// `[package.metadata.hurry]` in a crate's manifest; see
+    /// [`CratePolicy`]. These take precedence over the crate's own policy,
+    /// e.g. to stop caching a dependency that `hurry verify-hermetic` found
+    /// doesn't build the same way twice.
+    pub crates: BTreeMap<String, CratePolicy>,
+}
+
+/// API settings set in `hurry.toml`.
+///
+/// The `--hurry-api-url` and `--hurry-api-token` arguments, and the
+/// `HURRY_API_URL` and `HURRY_API_TOKEN` environment variables, take
+/// precedence over these.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ApiConfig {
+    /// The base URL of the Hurry API.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<Url>,
+
+    /// The token Hurry authenticates to the API with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<Token>,
+}
+
+impl ApiConfig {
+    /// The base URL of the Hurry API if none is set.
+    pub const DEFAULT_URL: &str = "https://app.hurry.build";
+
+    /// The base URL of the Hurry API, preferring `url` if it's given.
+    pub fn url(&self, url: Option<Url>) -> Url {
+        url.or_else(|| self.url.clone())
+            .unwrap_or_else(|| Url::parse(Self::DEFAULT_URL).expect("default API URL is valid"))
+    }
+
+    /// The token to authenticate to the API with, preferring `token` if it's
+    /// given.
+    pub fn token(&self, token: Option<Token>) -> Option<Token> {
+        token.or_else(|| self.token.clone())
+    }
+}
+
+/// Buffer sizes set in `hurry.toml`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct BufferSizeConfig {
+    /// The size of buffers used to stream content to and from the network.
+    pub network: Option<usize>,
+
+    /// The size of buffers used to process content locally, e.g. to hash it.
+    pub local: Option<usize>,
+}
+
+/// Restore settings set in `hurry.toml`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct RestoreConfig {
+    /// Features that units restored from the cache may differ in.
+    ///
+    /// When a unit isn't cached exactly, a cached unit of the same crate that
+    /// was compiled with the same settings may be restored in its place if
+    /// its features differ only in these. Leave this empty to only restore
+    /// exact matches.
+    pub near_match_features: BTreeSet<String>,
+
+    /// What to do when restoring a unit would overwrite files that already
+    /// exist locally.
+    pub overwrite: OverwritePolicy,
+
+    /// Only restore units that were saved at or before this point. This is
+    /// meant to be set for a single restore, with `hurry cache restore
+    /// --as-of`, rather than in `hurry.toml`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_of: Option<CacheAsOf>,
+
+    /// Whether to leave units that build faster than they download for Cargo
+    /// to build.
+    pub adaptive: AdaptiveRestoreConfig,
+}
+
+/// Adaptive restore settings, set under `[restore.adaptive]` in `hurry.toml`.
+///
+/// For small crates on fast machines, downloading a unit can take longer than
+/// compiling it. With adaptive restore enabled, Hurry records how long Cargo
+/// takes to build each unit and how quickly restores download, and leaves
+/// units that are estimated to build faster than they download for Cargo.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct AdaptiveRestoreConfig {
+    /// Whether adaptive restore is enabled.
+    pub enabled: bool,
+
+    /// The longest a unit may take to build, as a percentage of how long it's
+    /// estimated to take to download, for it to be left for Cargo. At 100,
+    /// every unit that's estimated to build faster is left for Cargo; lower
+    /// values leave room for the estimates being off.
+    pub break_even_percent: u64,
+}
+
+impl AdaptiveRestoreConfig {
+    /// The break-even percentage if it isn't configured.
+    pub const DEFAULT_BREAK_EVEN_PERCENT: u64 = 50;
+
+    /// Whether a unit that takes `build` to build and is estimated to take
+    /// `download` to download should be left for Cargo to build.
+    pub fn cheaper_to_build(&self, build: Duration, download: Duration) -> bool {
+        self.enabled
+            && build.as_millis() * 100 <= download.as_millis() * u128::from(self.break_even_percent)
+    }
+}
+
+impl Default for AdaptiveRestoreConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            break_even_percent: Self::DEFAULT_BREAK_EVEN_PERCENT,
+        }
+    }
+}
+
+/// What restore does when a unit's files already exist locally.
+///
+/// Units whose files are kept are left for Cargo to build, since a unit's
+/// cached files are only restored together.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverwritePolicy {
+    /// Never overwrite local files.
+    Never,
+
+    /// Overwrite local files that are older than the restored files would be,
+    /// keeping files that were built locally since.
+    #[default]
+    IfOlder,
+
+    /// Always overwrite local files.
+    Always,
+
+    /// Like `if-older`, but ask before overwriting newer local files. Newer
+    /// files are kept when there's no terminal to ask in.
+    Prompt,
+}
+
+/// Upload settings set in `hurry.toml`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct UploadConfig {
+    /// How long the daemon waits before starting a background upload.
+    ///
+    /// During active development every build uploads nearly the same units.
+    /// If another build of the same profile finishes while an upload is
+    /// waiting, the two uploads are merged and only the latest state is
+    /// uploaded. Uploads are never deferred in CI, or when the build waits
+    /// for its upload to finish.
+    pub defer_secs: Option<u64>,
+
+    /// How many units are uploaded at once. Each unit being uploaded holds
+    /// its contents in memory, so this also bounds the memory an upload uses.
+    pub parallelism: Option<usize>,
+
+    /// Whether large objects are uploaded as lists of content-defined
+    /// chunks, so that objects that are mostly the same (e.g. a dependency's
+    /// rlib before and after a version bump) only upload the chunks that
+    /// changed.
+    pub chunking: bool,
+}
+
+impl UploadConfig {
+    /// The number of units uploaded at once if it isn't configured.
+    pub const DEFAULT_PARALLELISM: usize = 8;
+
+    /// How many units to upload at once.
+    pub fn parallelism(&self) -> usize {
+        self.parallelism
+            .filter(|&parallelism| parallelism > 0)
+            .unwrap_or(Self::DEFAULT_PARALLELISM)
+    }
+
+    /// How long to defer background uploads in this environment, if at all.
+    pub fn defer(&self) -> Option<Duration> {
+        self.defer_in(in_ci())
+    }
+
+    /// How long to defer background uploads, if at all, depending on whether
+    /// the build runs in CI.
+    fn defer_in(&self, ci: bool) -> Option<Duration> {
+        if ci {
+            return None;
+        }
+        self.defer_secs
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs)
+    }
+}
+
+/// Network settings set in `hurry.toml`.
+///
+/// Settings that aren't set use the client's defaults; see [`Timeouts`] and
+/// [`PoolOptions`].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct NetworkConfig {
+    /// How long opening a connection can take.
+    pub connect_timeout_secs: Option<u64>,
+
+    /// How long small API requests, like cache lookups, can take.
+    pub api_timeout_secs: Option<u64>,
+
+    /// How long requests that transfer artifacts can take. Raise this for
+    /// slow links that upload or download large artifacts.
+    pub transfer_timeout_secs: Option<u64>,
+
+    /// The most idle connections kept open to the Hurry API.
+    pub max_idle_connections: Option<usize>,
+}
+
+impl NetworkConfig {
+    /// The options for the connection pool.
+    pub fn pool_options(&self) -> PoolOptions {
+        PoolOptions::builder()
+            .maybe_connect_timeout(self.connect_timeout_secs.map(Duration::from_secs))
+            .maybe_max_idle_per_host(self.max_idle_connections)
+            .build()
+    }
+
+    /// The timeouts for each class of request.
+    pub fn timeouts(&self) -> Timeouts {
+        Timeouts::builder()
+            .maybe_api(self.api_timeout_secs.map(Duration::from_secs))
+            .maybe_transfer(self.transfer_timeout_secs.map(Duration::from_secs))
+            .build()
+    }
+}
+
+/// State settings set in `hurry.toml`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct StateConfig {
+    /// The directory to keep per-workspace state in, relative to the
+    /// workspace root. If unset, state is kept in the build directory, where
+    /// it's lost whenever the build directory is removed.
+    pub dir: Option<String>,
+
+    /// Keep state in the user's cache directory under the workspace's
+    /// identity, sharing it between worktrees and other checkouts of the
+    /// repository. This takes precedence over `dir`.
+    pub shared: bool,
+}
+
+/// Workspace settings set in `hurry.toml`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct WorkspaceConfig {
+    /// The identity of the workspace. If unset, it's derived from the
+    /// workspace's Git repository, so that worktrees and other checkouts of
+    /// the repository share it.
+    pub id: Option<String>,
+}
+
+/// First-party settings set in `hurry.toml`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct FirstPartyConfig {
+    /// Cache the libraries and build scripts of packages in the workspace,
+    /// not just third-party dependencies.
+    ///
+    /// Cargo doesn't hash first-party sources into unit hashes, so these
+    /// units are cached under a hash of their package's source files as well.
+    /// Binaries, tests, and examples are always built by Cargo.
+    pub cache: bool,
+}
+
+/// Local cache settings set in `hurry.toml`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct LocalCacheConfig {
+    /// The most disk space, in bytes, that objects cached on this machine may
+    /// take up; the least recently used objects are evicted beyond it.
+    ///
+    /// Objects that are restored or uploaded are kept in the local cache so
+    /// that later builds don't download them again. Set this to zero to
+    /// disable the local cache.
+    pub max_size: Option<u64>,
+}
+
+/// `$CARGO_HOME` settings set in `hurry.toml`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct CargoHomeConfig {
+    /// Absolute paths of other `$CARGO_HOME`s on this machine, e.g. the homes
+    /// of containers or of projects with their own home.
+    ///
+    /// Paths under any of these are cached relative to the home they're in,
+    /// and restored into the build's own `$CARGO_HOME`, like paths under the
+    /// build's `$CARGO_HOME` are.
+    pub roots: Vec<String>,
+}
+
+/// Prewarm settings set in `hurry.toml`.
+///
+/// The daemon remembers workspaces that were recently built, and prefetches
+/// the artifacts of their last build into the local CAS when they're due, so
+/// that e.g. the first build of the morning doesn't wait on downloads.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PrewarmConfig {
+    /// The local time to prewarm at, as `HH:MM`.
+    pub at: Option<String>,
+
+    /// The days to prewarm on at `at`, e.g. `["mon", "wed"]`. If empty,
+    /// Monday to Friday.
+    pub days: Vec<String>,
+
+    /// Prewarm once the daemon has been idle for this many minutes.
+    pub idle_minutes: Option<u64>,
+}
+
+/// A build tool that wraps Cargo, set in `hurry.toml` as
+/// `[wrappers.<name>]`.
+///
+/// `hurry wrap <name>` caches the tool's builds like `hurry cargo build`
+/// caches Cargo's. The tool's flags that mean the same thing to Cargo, like
+/// `--release` and `--features`, are assumed to be passed through to `cargo
+/// build`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct WrapperConfig {
+    /// The program to run. Defaults to the wrapper's name.
+    pub program: Option<String>,
+
+    /// The tool's subcommands that build with Cargo. If empty, `build`.
+    pub subcommands: Vec<String>,
+
+    /// Arguments the tool always passes to `cargo build`, e.g. `["--lib",
+    /// "--target", "wasm32-wasip1"]`.
+    pub cargo_args: Vec<String>,
+}
+
+impl WrapperConfig {
+    /// The tool's subcommands that build with Cargo.
+    pub fn subcommands(&self) -> impl Iterator<Item = &str> {
+        let default = self.subcommands.is_empty().then_some("build");
+        self.subcommands.iter().map(String::as_str).chain(default)
+    }
+}
+
+impl LocalCacheConfig {
+    /// The size budget of the local cache if none is set: 10 GiB.
+    pub const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024 * 1024;
+
+    /// The size budget of the local cache, in bytes.
+    pub fn max_size(&self) -> u64 {
+        self.max_size.unwrap_or(Self::DEFAULT_MAX_SIZE)
+    }
+}
+
+impl HurryConfig {
+    /// Load the configuration for the workspace at `root`.
+    ///
+    /// Returns the default configuration if there's no configuration file.
+    #[instrument(name = "HurryConfig::load")]
+    pub async fn load(root: &AbsDirPath) -> Result<Self> {
+        let workspace = root.join(mk_rel_file!("hurry.toml"));
+        if let Some(config) = Self::load_file(&workspace).await? {
+            return Ok(config);
+        }
+        let Some(user) = user_config_path().await? else {
+            return Ok(Self::default());
+        };
+        Ok(Self::load_file(&user).await?.unwrap_or_default())
+    }
+
+    /// Load the configuration for the workspace at `root` like
+    /// [`HurryConfig::load`], reporting where each effective setting came
+    /// from and which keys in the file weren't recognized.
+    #[instrument(name = "HurryConfig::resolve")]
+    pub async fn resolve(root: &AbsDirPath) -> Result<ResolvedConfig> {
+        let workspace = root.join(mk_rel_file!("hurry.toml"));
+        let user = user_config_path().await?;
+
+        let mut file = None;
+        let mut ignored = Vec::new();
+        for path in std::iter::once(workspace).chain(user) {
+            if !fs::exists(&path).await {
+                continue;
+            }
+            match file {
+                None => file = Some(path),
+                Some(_) => ignored.push(path),
+            }
+        }
+
+        let (config, raw) = match &file {
+            Some(path) => {
+                let content = fs::read_buffered_utf8(path)
+                    .await?
+                    .ok_or_eyre("config file was removed while reading it")?;
+                let raw = toml::from_str::<toml::Table>(&content)
+                    .with_context(|| format!("parse config file: {path}"))?;
+                let config = toml::from_str::<Self>(&content)
+                    .with_context(|| format!("parse config file: {path}"))?;
+                (config, raw)
+            }
+            None => (Self::default(), toml::Table::new()),
+        };
+        let known = toml::Value::try_from(&config)
+            .context("serialize config")?
+            .as_table()
+            .cloned()
+            .unwrap_or_default();
+
+        let mut unknown_keys = Vec::new();
+        collect_unknown_keys(&raw, &known, "", &mut unknown_keys);
+        let settings =
+            effective_settings(&raw, &known, file.as_ref(), |name| std::env::var(name).ok());
+        Ok(ResolvedConfig {
+            config,
+            file,
+            ignored,
+            settings,
+            unknown_keys,
+        })
+    }
+
+    /// Load the user's configuration file, if there is one.
+    ///
+    /// Unlike [`HurryConfig::load`], this ignores workspace files, so it's
+    /// what to modify and write back with [`HurryConfig::save_user`].
+    #[instrument(name = "HurryConfig::load_user")]
+    pub async fn load_user() -> Result<Option<Self>> {
+        match user_config_path().await? {
+            Some(path) => Self::load_file(&path).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Write the configuration to the user's configuration file, returning
+    /// its path.
+    #[instrument(name = "HurryConfig::save_user")]
+    pub async fn save_user(&self) -> Result<AbsFilePath> {
+        let path = user_config_path()
+            .await?
+            .ok_or_eyre("no configuration directory for the current user")?;
+        let content = toml::to_string(self).context("serialize config")?;
+        fs::write(&path, content).await?;
+        Ok(path)
+    }
+
+    async fn load_file(path: &AbsFilePath) -> Result<Option<Self>> {
+        let Some(content) = fs::read_buffered_utf8(path).await? else {
+            return Ok(None);
+        };
+        let config = toml::from_str::<Self>(&content)
+            .with_context(|| format!("parse config file: {path}"))?;
+        debug!(?path, ?config, "loaded config");
+        Ok(Some(config))
+    }
+}
+
+/// Environment variables that override settings in `hurry.toml`, by the key
+/// of the setting they override.
+///
+/// These are read by the commands the settings apply to; they're listed here
+/// so that [`HurryConfig::resolve`] can report them.
+const ENV_OVERRIDES: [(&str, &str); 3] = [
+    ("api.token", "HURRY_API_TOKEN"),
+    ("api.url", "HURRY_API_URL"),
+    ("upload.parallelism", "HURRY_UPLOAD_PARALLELISM"),
+];
+
+/// Where an effective setting came from.
+#[derive(Debug, Clone, Eq, PartialEq, Display)]
+pub enum ConfigOrigin {
+    /// The setting isn't set anywhere, so its default applies.
+    #[display("default")]
+    Default,
+
+    /// The setting is set in this configuration file.
+    #[display("{_0}")]
+    File(AbsFilePath),
+
+    /// The setting is overridden by this environment variable.
+    #[display("${_0}")]
+    Env(&'static str),
+}
+
+/// An effective setting, by its dotted key (e.g. `restore.overwrite`).
+///
+/// Secrets, like the API token, are redacted from the value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigSetting {
+    pub key: String,
+    pub value: toml::Value,
+    pub origin: ConfigOrigin,
+}
+
+/// The effective configuration for a workspace and where each of its
+/// settings came from; see [`HurryConfig::resolve`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedConfig {
+    pub config: HurryConfig,
+
+    /// The file the configuration was loaded from, if there is one.
+    pub file: Option<AbsFilePath>,
+
+    /// Configuration files that exist but were ignored, because files aren't
+    /// merged and another file takes precedence.
+    pub ignored: Vec<AbsFilePath>,
+
+    /// Every effective setting, sorted by key. Settings that aren't set and
+    /// have no default are left out.
+    pub settings: Vec<ConfigSetting>,
+
+    /// Keys in the file that Hurry doesn't know, e.g. misspelled settings.
+    /// They're ignored when the configuration is loaded.
+    pub unknown_keys: Vec<String>,
+}
+
+/// Collect the keys of `raw` that aren't in `known`, the same configuration
+/// as Hurry understands it.
+fn collect_unknown_keys(
+    raw: &toml::Table,
+    known: &toml::Table,
+    prefix: &str,
+    unknown: &mut Vec<String>,
+) {
+    for (key, value) in raw {
+        let path = format!("{prefix}{key}");
+        match (value, known.get(key)) {
+            (_, None) => unknown.push(path),
+            (toml::Value::Table(raw), Some(toml::Value::Table(known))) => {
+                collect_unknown_keys(raw, known, &format!("{path}."), unknown);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Flatten the effective configuration into its settings, noting which ones
+/// are set in `file` and applying the environment variables in
+/// [`ENV_OVERRIDES`] that `env` finds.
+fn effective_settings(
+    raw: &toml::Table,
+    known: &toml::Table,
+    file: Option<&AbsFilePath>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Vec<ConfigSetting> {
+    fn flatten(
+        raw: Option<&toml::Table>,
+        known: &toml::Table,
+        prefix: &str,
+        file: Option<&AbsFilePath>,
+        settings: &mut Vec<ConfigSetting>,
+    ) {
+        for (key, value) in known {
+            let path = format!("{prefix}{key}");
+            let raw_value = raw.and_then(|raw| raw.get(key));
+            if let toml::Value::Table(known) = value {
+                let raw = raw_value.and_then(toml::Value::as_table);
+                flatten(raw, known, &format!("{path}."), file, settings);
+                continue;
+            }
+            let origin = match (raw_value, file) {
+                (Some(_), Some(file)) => ConfigOrigin::File(file.clone()),
+                _ => ConfigOrigin::Default,
+            };
+            settings.push(ConfigSetting {
+                value: redact_setting(&path, value.clone()),
+                key: path,
+                origin,
+            });
+        }
+    }
+
+    let mut settings = Vec::new();
+    flatten(Some(raw), known, "", file, &mut settings);
+    for (key, name) in ENV_OVERRIDES {
+        let Some(value) = env(name).filter(|value| !value.is_empty()) else {
+            continue;
+        };
+        let value = match value.parse::<i64>() {
+            Ok(number) => toml::Value::Integer(number),
+            Err(_) => toml::Value::String(value),
+        };
+        let setting = ConfigSetting {
+            key: String::from(key),
+            value: redact_setting(key, value),
+            origin: ConfigOrigin::Env(name),
+        };
+        match settings.iter_mut().find(|setting| setting.key == key) {
+            Some(existing) => *existing = setting,
+            None => settings.push(setting),
+        }
+    }
+    settings.sort_by(|a, b| a.key.cmp(&b.key));
+    settings
+}
+
+/// Redact secrets from the value of a setting so that it can be shown.
+fn redact_setting(key: &str, value: toml::Value) -> toml::Value {
+    match key {
+        "api.token" => toml::Value::String(String::from("[redacted]")),
+        "proxy.url" => match value.as_str().map(Url::parse) {
+            Some(Ok(mut url)) if url.password().is_some() => {
+                let _ = url.set_password(Some("[redacted]"));
+                toml::Value::String(url.to_string())
+            }
+            _ => value,
+        },
+        _ => value,
+    }
+}
+
+/// The path of the user's `hurry.toml`, if the user has a configuration
+/// directory.
+async fn user_config_path() -> Result<Option<AbsFilePath>> {
+    let dirs = spawn_blocking(|| directories::ProjectDirs::from("com", "attunehq", "hurry"))
+        .await
+        .expect("join task");
+    let Some(dirs) = dirs else {
+        return Ok(None);
+    };
+    dirs.config_dir()
+        .join("hurry.toml")
+        .try_conv::<AbsFilePath>()
+        .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use clients::{
+        ProxyConfig,
+        courier::v1::{HashAlgorithm, PoolOptions, Timeouts, cache::CacheAsOf},
+    };
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+    use url::Url;
+
+    use super::{
+        AdaptiveRestoreConfig, ApiConfig, BufferSizeConfig, ConfigOrigin, FirstPartyConfig,
+        HurryConfig, LocalCacheConfig, OverwritePolicy, RestoreConfig, StateConfig, UploadConfig,
+        WorkspaceConfig, collect_unknown_keys, effective_settings,
+    };
+    use crate::{cargo::CratePolicy, path::AbsFilePath};
+
+    #[test]
+    fn parse_proxy() {
+        let config = toml::from_str::<HurryConfig>(
+            r#"
+            [proxy]
+            url = "socks5h://proxy.internal:1080"
+            no-proxy = "localhost,.internal"
+            "#,
+        )
+        .unwrap();
+        let expected = ProxyConfig::builder()
+            .url(Url::parse("socks5h://proxy.internal:1080").unwrap())
+            .no_proxy("localhost,.internal")
+            .build();
+        pretty_assert_eq!(config.proxy, expected);
+    }
+
+    #[test]
+    fn parse_network() {
+        let config = toml::from_str::<HurryConfig>(
+            r#"
+            [network]
+            connect-timeout-secs = 5
+            transfer-timeout-secs = 3600
+            max-idle-connections = 4
+            "#,
+        )
+        .unwrap();
+        pretty_assert_eq!(
+            config.network.pool_options(),
+            PoolOptions::builder()
+                .connect_timeout(Duration::from_secs(5))
+                .max_idle_per_host(4)
+                .build()
+        );
+        pretty_assert_eq!(
+            config.network.timeouts(),
+            Timeouts::builder()
+                .transfer(Duration::from_secs(3600))
+                .build()
+        );
+        pretty_assert_eq!(
+            HurryConfig::default().network.timeouts(),
+            Timeouts::default()
+        );
+    }
+
+    #[test]
+    fn parse_api() {
+        let config = toml::from_str::<HurryConfig>(
+            r#"
+            [api]
+            url = "https://courier.internal"
+            token = "secret"
+            "#,
+        )
+        .unwrap();
+        pretty_assert_eq!(
+            config.api.url(None),
+            Url::parse("https://courier.internal").unwrap()
+        );
+        pretty_assert_eq!(config.api.token(None), Some("secret".into()));
+        pretty_assert_eq!(
+            config.api.token(Some("override".into())),
+            Some("override".into())
+        );
+        pretty_assert_eq!(
+            ApiConfig::default().url(None).as_str(),
+            "https://app.hurry.build/"
+        );
+
+        let written = toml::to_string(&config).unwrap();
+        pretty_assert_eq!(toml::from_str::<HurryConfig>(&written).unwrap(), config);
+    }
+
+    #[test]
+    fn parse_hash_algorithm() {
+        let config = toml::from_str::<HurryConfig>(r#"hash-algorithm = "sha256""#).unwrap();
+        pretty_assert_eq!(config.hash_algorithm, HashAlgorithm::Sha256);
+    }
+
+    #[test]
+    fn parse_buffers() {
+        let config = toml::from_str::<HurryConfig>(
+            r#"
+            [buffers]
+            network = 4194304
+            "#,
+        )
+        .unwrap();
+        let expected = BufferSizeConfig {
+            network: Some(4 * 1024 * 1024),
+            local: None,
+        };
+        pretty_assert_eq!(config.buffers, expected);
+    }
+
+    #[test]
+    fn parse_restore() {
+        let config = toml::from_str::<HurryConfig>(
+            r#"
+            [restore]
+            near-match-features = ["nightly"]
+            "#,
+        )
+        .unwrap();
+        let expected = RestoreConfig {
+            near_match_features: [String::from("nightly")].into(),
+            overwrite: OverwritePolicy::IfOlder,
+            as_of: None,
+            adaptive: AdaptiveRestoreConfig::default(),
+        };
+        pretty_assert_eq!(config.restore, expected);
+    }
+
+    #[test]
+    fn parse_restore_adaptive() {
+        let config = toml::from_str::<HurryConfig>(
+            r#"
+            [restore.adaptive]
+            enabled = true
+            break-even-percent = 80
+            "#,
+        )
+        .unwrap();
+        let expected = AdaptiveRestoreConfig {
+            enabled: true,
+            break_even_percent: 80,
+        };
+        pretty_assert_eq!(config.restore.adaptive, expected);
+    }
+
+    #[test_case(1000, 3000, true; "much faster to build")]
+    #[test_case(1500, 3000, true; "at break even")]
+    #[test_case(2000, 3000, false; "slightly faster to build")]
+    #[test_case(5000, 3000, false; "faster to download")]
+    #[test]
+    fn adaptive_restore_break_even(build_ms: u64, download_ms: u64, expected: bool) {
+        let config = AdaptiveRestoreConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        let build = Duration::from_millis(build_ms);
+        let download = Duration::from_millis(download_ms);
+        pretty_assert_eq!(config.cheaper_to_build(build, download), expected);
+    }
+
+    #[test]
+    fn parse_restore_overwrite() {
+        let config = toml::from_str::<HurryConfig>(
+            r#"
+            [restore]
+            overwrite = "prompt"
+            "#,
+        )
+        .unwrap();
+        pretty_assert_eq!(config.restore.overwrite, OverwritePolicy::Prompt);
+    }
+
+    #[test]
+    fn parse_upload() {
+        let config = toml::from_str::<HurryConfig>(
+            r#"
+            [upload]
+            defer-secs = 30
+            parallelism = 16
+            chunking = true
+            "#,
+        )
+        .unwrap();
+        let expected = UploadConfig {
+            defer_secs: Some(30),
+            parallelism: Some(16),
+            chunking: true,
+        };
+        pretty_assert_eq!(config.upload, expected);
+    }
+
+    #[test_case(Some(30), false, Some(Duration::from_secs(30)); "deferred")]
+    #[test_case(Some(30), true, None; "in ci")]
+    #[test_case(Some(0), false, None; "zero")]
+    #[test_case(None, false, None; "unset")]
+    #[test]
+    fn upload_defer(defer_secs: Option<u64>, ci: bool, expected: Option<Duration>) {
+        let config = UploadConfig {
+            defer_secs,
+            ..Default::default()
+        };
+        pretty_assert_eq!(config.defer_in(ci), expected);
+    }
+
+    #[test]
+    fn parse_state() {
+        let config = toml::from_str::<HurryConfig>(
+            r#"
+            [state]
+            dir = ".hurry/state"
+            "#,
+        )
+        .unwrap();
+        let expected = StateConfig {
+            dir: Some(String::from(".hurry/state")),
+            shared: false,
+        };
+        pretty_assert_eq!(config.state, expected);
+    }
+
+    #[test]
+    fn parse_workspace() {
+        let config = toml::from_str::<HurryConfig>(
+            r#"
+            [state]
+            shared = true
+
+            [workspace]
+            id = "attunehq/hurry"
+            "#,
+        )
+        .unwrap();
+        assert!(config.state.shared);
+        let expected = WorkspaceConfig {
+            id: Some(String::from("attunehq/hurry")),
+        };
+        pretty_assert_eq!(config.workspace, expected);
+    }
+
+    #[test]
+    fn parse_first_party() {
+        let config = toml::from_str::<HurryConfig>(
+            r#"
+            [first-party]
+            cache = true
+            "#,
+        )
+        .unwrap();
+        pretty_assert_eq!(config.first_party, FirstPartyConfig { cache: true });
+    }
+
+    #[test]
+    fn parse_local_cache() {
+        let config = toml::from_str::<HurryConfig>(
+            r#"
+            [local-cache]
+            max-size = 1073741824
+            "#,
+        )
+        .unwrap();
+        pretty_assert_eq!(config.local_cache.max_size(), 1024 * 1024 * 1024);
+        pretty_assert_eq!(
+            LocalCacheConfig::default().max_size(),
+            LocalCacheConfig::DEFAULT_MAX_SIZE
+        );
+    }
+
+    #[test]
+    fn parse_crates() {
+        let config = toml::from_str::<HurryConfig>(
+            r#"
+            [crates.openssl-sys]
+            cache = false
+            "#,
+        )
+        .unwrap();
+        pretty_assert_eq!(
+            config.crates.get("openssl-sys"),
+            Some(&CratePolicy {
+                cache: false,
+                ..CratePolicy::default()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_empty() {
+        let config = toml::from_str::<HurryConfig>("").unwrap();
+        pretty_assert_eq!(config, HurryConfig::default());
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let content = r#"
+            hash-algoritm = "sha256"
+
+            [restore]
+            overwrite = "always"
+            near-match = ["nightly"]
+
+            [wrappers.maturin]
+            subcommands = ["develop"]
+            cargo-arg = ["--lib"]
+            "#;
+        let raw = toml::from_str::<toml::Table>(content).unwrap();
+        let config = toml::from_str::<HurryConfig>(content).unwrap();
+        let known = toml::Value::try_from(&config).unwrap();
+
+        let mut unknown = Vec::new();
+        collect_unknown_keys(&raw, known.as_table().unwrap(), "", &mut unknown);
+        pretty_assert_eq!(
+            unknown,
+            vec![
+                String::from("hash-algoritm"),
+                String::from("restore.near-match"),
+                String::from("wrappers.maturin.cargo-arg"),
+            ]
+        );
+    }
+
+    #[test]
+    fn setting_origins() {
+        let content = r#"
+            [api]
+            url = "https://hurry.internal"
+            token = "secret"
+
+            [restore]
+            overwrite = "always"
+            "#;
+        let raw = toml::from_str::<toml::Table>(content).unwrap();
+        let config = toml::from_str::<HurryConfig>(content).unwrap();
+        let known = toml::Value::try_from(&config).unwrap();
+        let file = AbsFilePath::try_from("/workspace/hurry.toml").unwrap();
+
+        let settings = effective_settings(&raw, known.as_table().unwrap(), Some(&file), |name| {
+            (name == "HURRY_UPLOAD_PARALLELISM").then(|| String::from("4"))
+        });
+        let origin = |key: &str| {
+            settings
+                .iter()
+                .find(|setting| setting.key == key)
+                .map(|setting| (setting.value.clone(), setting.origin.clone()))
+        };
+        pretty_assert_eq!(
+            origin("api.url"),
+            Some((
+                toml::Value::from("https://hurry.internal/"),
+                ConfigOrigin::File(file.clone())
+            ))
+        );
+        pretty_assert_eq!(
+            origin("api.token"),
+            Some((
+                toml::Value::from("[redacted]"),
+                ConfigOrigin::File(file.clone())
+            ))
+        );
+        pretty_assert_eq!(
+            origin("restore.overwrite"),
+            Some((toml::Value::from("always"), ConfigOrigin::File(file)))
+        );
+        pretty_assert_eq!(
+            origin("upload.chunking"),
+            Some((toml::Value::from(false), ConfigOrigin::Default))
+        );
+        pretty_assert_eq!(
+            origin("upload.parallelism"),
+            Some((
+                toml::Value::from(4),
+                ConfigOrigin::Env("HURRY_UPLOAD_PARALLELISM")
+            ))
+        );
+        assert!(
+            settings.is_sorted_by(|a, b| a.key <= b.key),
+            "settings are sorted by key: {settings:?}"
+        );
+    }
+}
@@ -15,7 +15,6 @@ use color_eyre::{
     eyre::{Context as _, eyre},
 };
 use tracing::{debug, instrument, trace};
-use uuid::Uuid;
 
 use crate::{
     cargo::{BuildPlan, CargoBuildArguments, RustcTargetPlatform, UnitPlan, Workspace},
@@ -173,9 +172,7 @@ impl Workspace {
         // directory, just like cargo. We use the same rename workaround.
         let renamed = if fs::exists(&self.build_dir).await {
             debug!("target exists before running build plan, renaming");
-            let temp = self
-                .root
-                .try_join_dir(format!("target.backup.{}", Uuid::new_v4()))?;
+            let temp = self.build_dir_backup()?;
 
             let renamed = fs::rename(&self.build_dir, &temp).await.is_ok();
             debug!(?renamed, ?temp, "renamed temp target");
@@ -270,10 +267,14 @@ mod tests {
             root,
             build_dir,
             cargo_home,
+            cargo_home_roots: Vec::new(),
             profile: crate::cargo::Profile::Debug,
             target_arch: crate::cargo::RustcTarget::ImplicitHost,
             host_arch: crate::cargo::RustcTargetPlatform::try_from("x86_64-unknown-linux-gnu")
                 .unwrap(),
+            cache_first_party: false,
+            crate_policies: Default::default(),
+            invoked_in: None,
         }
     }
 
@@ -1,22 +1,52 @@
 mod cargo;
+mod metrics;
+mod prewarm;
 
 pub use cargo::{
-    CargoDaemonState, CargoUploadRequest, CargoUploadResponse, CargoUploadStatus,
-    CargoUploadStatusAllResponse, CargoUploadStatusRequest, CargoUploadStatusResponse,
-    cargo_router,
+    CargoCancelRequest, CargoCancelResponse, CargoConnectionsResponse, CargoDaemonState,
+    CargoPrefetchRequest, CargoPrefetchResponse, CargoPrewarmRequest, CargoPrewarmResponse,
+    CargoRestoreEvent, CargoRestoreProgress, CargoRestoreRequest, CargoSessionEndRequest,
+    CargoSessionEndResponse, CargoUploadProgressResponse, CargoUploadRequest, CargoUploadResponse,
+    CargoUploadStatus, CargoUploadStatusAllResponse, CargoUploadStatusRequest,
+    CargoUploadStatusResponse, CargoWarmRequest, CargoWarmResponse, CargoWatchRequest,
+    CargoWatchResponse, TransferRate, cargo_router,
 };
+pub use metrics::{DaemonMetrics, InvocationReport};
+pub use prewarm::{Day, PrewarmReason, PrewarmSchedule, TimeOfDay};
+
+use std::time::Duration;
 
 use crate::{
-    fs, mk_rel_file,
-    path::{AbsFilePath, JoinWith as _},
+    fs,
+    path::{AbsDirPath, AbsFilePath, TryJoinWith as _},
 };
 use color_eyre::{
-    Result,
-    eyre::{Context as _, OptionExt as _, bail},
+    Result, Section as _, SectionExt as _,
+    eyre::{Context as _, OptionExt as _, eyre},
 };
 use serde::{Deserialize, Serialize};
-use sysinfo::{Pid, ProcessRefreshKind, RefreshKind, System};
-use tap::Pipe as _;
+use sysinfo::{Pid, ProcessRefreshKind, RefreshKind, System, UpdateKind};
+use tracing::{info, warn};
+
+/// Create an HTTP client for sending requests to the daemon.
+///
+/// The daemon only listens on the loopback interface, so requests to it must
+/// never go through a proxy configured in the environment: the proxy wouldn't
+/// be able to reach it.
+pub fn local_client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .no_proxy()
+        .build()
+        .context("build daemon client")
+}
+
+/// How long to wait for the daemon to respond to a health check. The daemon
+/// answers health checks without doing any work, so this only needs to cover
+/// a round trip over the loopback interface.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often to check whether a daemon that was asked to stop has exited.
+const DAEMON_EXIT_POLL: Duration = Duration::from_millis(100);
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct DaemonContext {
@@ -25,45 +55,438 @@ pub struct DaemonContext {
     pub log_file_path: AbsFilePath,
 }
 
+impl DaemonContext {
+    /// Check that the daemon is serving requests, returning what it reports
+    /// about itself.
+    pub async fn health(&self) -> Result<DaemonHealth> {
+        let endpoint = format!("http://{}/api/v0/health", self.url);
+        let response = local_client()?
+            .get(&endpoint)
+            .timeout(HEALTH_CHECK_TIMEOUT)
+            .send()
+            .await
+            .with_context(|| format!("send health check to daemon at: {endpoint}"))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(eyre!("daemon does not support health checks"))
+                .note("The daemon is likely running an older version of Hurry.")
+                .suggestion("Stop it with `hurry daemon stop`.");
+        }
+        response
+            .error_for_status()
+            .with_context(|| format!("check health of daemon at: {endpoint}"))?
+            .json::<DaemonHealth>()
+            .await
+            .context("parse daemon health")
+    }
+
+    /// Fetch what the daemon reports about itself and its work.
+    pub async fn status(&self) -> Result<DaemonStatus> {
+        let endpoint = format!("http://{}/api/v0/status", self.url);
+        let response = local_client()?
+            .get(&endpoint)
+            .timeout(HEALTH_CHECK_TIMEOUT)
+            .send()
+            .await
+            .with_context(|| format!("send status request to daemon at: {endpoint}"))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(eyre!("daemon does not support status requests"))
+                .note("The daemon is likely running an older version of Hurry.")
+                .suggestion("Stop it with `hurry daemon stop`.");
+        }
+        response
+            .error_for_status()
+            .with_context(|| format!("get status of daemon at: {endpoint}"))?
+            .json::<DaemonStatus>()
+            .await
+            .context("parse daemon status")
+    }
+
+    /// Report an invocation of `hurry` to the daemon, to be included in its
+    /// metrics.
+    pub async fn report_invocation(&self, report: &InvocationReport) -> Result<()> {
+        let endpoint = format!("http://{}/api/v0/metrics/invocations", self.url);
+        local_client()?
+            .post(&endpoint)
+            .timeout(HEALTH_CHECK_TIMEOUT)
+            .json(report)
+            .send()
+            .await
+            .with_context(|| format!("send invocation report to daemon at: {endpoint}"))?
+            .error_for_status()
+            .context("report invocation")?;
+        Ok(())
+    }
+
+    /// Find out whether the daemon is running the same version of Hurry as
+    /// this process.
+    ///
+    /// Daemons from before health checks were added don't report a version,
+    /// so they count as outdated. Errors are only returned if the daemon
+    /// couldn't be reached or isn't the process described by this context.
+    pub async fn handshake(&self) -> Result<DaemonHandshake> {
+        let endpoint = format!("http://{}/api/v0/health", self.url);
+        let response = local_client()?
+            .get(&endpoint)
+            .timeout(HEALTH_CHECK_TIMEOUT)
+            .send()
+            .await
+            .with_context(|| format!("send health check to daemon at: {endpoint}"))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(DaemonHandshake::Outdated { version: None });
+        }
+        let health = response
+            .error_for_status()
+            .with_context(|| format!("check health of daemon at: {endpoint}"))?
+            .json::<DaemonHealth>()
+            .await
+            .context("parse daemon health")?;
+        if health.pid != self.pid {
+            return Err(eyre!("daemon at {} is not process {}", self.url, self.pid))
+                .with_section(|| format!("{health:?}").header("Health:"));
+        }
+        if health.version != DAEMON_VERSION {
+            return Ok(DaemonHandshake::Outdated {
+                version: Some(health.version),
+            });
+        }
+        Ok(DaemonHandshake::Current(health))
+    }
+
+    /// Ask the daemon to stop, waiting up to `timeout` for it to exit.
+    ///
+    /// The daemon waits for uploads that have started to finish before it
+    /// exits, and anything it doesn't finish stays in the upload journal for
+    /// the next daemon to resume.
+    pub async fn stop(&self, timeout: Duration) -> Result<()> {
+        let endpoint = format!("http://{}/api/v0/shutdown", self.url);
+        local_client()?
+            .post(&endpoint)
+            .send()
+            .await
+            .with_context(|| format!("send shutdown request to daemon at: {endpoint}"))?;
+
+        let pid = Pid::from_u32(self.pid);
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let system = System::new_with_specifics(
+                RefreshKind::nothing().with_processes(ProcessRefreshKind::nothing()),
+            );
+            if system.process(pid).is_none() {
+                return Ok(());
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(eyre!(
+                    "daemon did not exit within {}s; it may still be finishing uploads",
+                    timeout.as_secs()
+                ));
+            }
+            tokio::time::sleep(DAEMON_EXIT_POLL).await;
+        }
+    }
+
+    /// Check that the daemon is healthy and that it's the process described
+    /// by this context, running the same version of Hurry as this process.
+    ///
+    /// Requests and responses between the CLI and the daemon aren't
+    /// versioned, so sending work to a daemon of another version can fail in
+    /// confusing ways.
+    pub async fn verify(&self) -> Result<DaemonHealth> {
+        let health = self.health().await?;
+        self.check(&health)?;
+        Ok(health)
+    }
+
+    /// Check that the health reported by the daemon is from the process
+    /// described by this context, running the same version of Hurry as this
+    /// process.
+    pub fn check(&self, health: &DaemonHealth) -> Result<()> {
+        if health.pid != self.pid {
+            return Err(eyre!("daemon at {} is not process {}", self.url, self.pid))
+                .with_section(|| format!("{health:?}").header("Health:"));
+        }
+        if health.version != DAEMON_VERSION {
+            return Err(eyre!(
+                "daemon is running Hurry {}, but this is Hurry {DAEMON_VERSION}",
+                health.version
+            ))
+            .suggestion(
+                "Stop the daemon with `hurry daemon stop`; the next build starts a new one.",
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The version of Hurry, as reported by the daemon in health checks.
+pub const DAEMON_VERSION: &str = env!("HURRY_VERSION");
+
+/// The result of a version handshake with the daemon; see
+/// [`DaemonContext::handshake`].
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum DaemonHandshake {
+    /// The daemon is running the same version of Hurry as this process.
+    Current(DaemonHealth),
+
+    /// The daemon is running another version of Hurry, which is `None` if
+    /// the daemon is too old to report it.
+    Outdated { version: Option<String> },
+}
+
+/// What the daemon reports about itself in response to a health check.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct DaemonHealth {
+    pub pid: u32,
+    pub version: String,
+}
+
+impl DaemonHealth {
+    /// The health of the current process.
+    pub fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            version: String::from(DAEMON_VERSION),
+        }
+    }
+}
+
+/// What the daemon reports about itself and its work.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub version: String,
+
+    /// How long the daemon has been running.
+    pub uptime: Duration,
+
+    /// The number of uploads that haven't finished, including deferred
+    /// uploads.
+    pub uploads_in_flight: u64,
+
+    /// The number of uploads that are deferred and haven't started yet.
+    pub uploads_deferred: u64,
+
+    /// The number of units that running uploads haven't uploaded yet.
+    pub queued_units: u64,
+
+    /// The number of bytes that running uploads have read from units and
+    /// haven't uploaded yet.
+    pub queued_bytes: u64,
+
+    /// The number of workspaces the daemon is watching.
+    pub watched_workspaces: u64,
+
+    /// The most recent error from a restore, upload, or prefetch.
+    pub last_error: Option<DaemonError>,
+}
+
+/// An error from work the daemon ran in the background.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct DaemonError {
+    pub message: String,
+
+    /// When the error happened, in seconds since the Unix epoch.
+    pub at: i64,
+}
+
+/// The environment variable that runs separate daemons for the same user.
+///
+/// Daemons are per user by default. Setting this (for example, to the path of
+/// a workspace) gives the daemons started with each value their own files, so
+/// that they don't share uploads, sessions, or connections.
+pub const DAEMON_NAMESPACE_ENV: &str = "HURRY_DAEMON_NAMESPACE";
+
+/// The pid-file and context file of daemons started by versions of Hurry from
+/// before daemon files were namespaced; see [`DaemonPaths::stop_legacy_daemon`].
+const LEGACY_PID_FILE: &str = "hurryd.pid";
+const LEGACY_CONTEXT_FILE: &str = "hurryd.json";
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct DaemonPaths {
     pub pid_file_path: AbsFilePath,
     pub context_path: AbsFilePath,
+
+    /// The directory the daemon's files are in.
+    dir: AbsDirPath,
+
+    /// The namespace that keeps this daemon's files apart from other daemons'
+    /// in the same directory; see [`DaemonPaths::namespace`].
+    namespace: String,
 }
 
 impl DaemonPaths {
     pub async fn initialize() -> Result<DaemonPaths> {
         let hurry_cache_dir = fs::user_global_cache_path().await?;
-        let pid_file_path = hurry_cache_dir.join(mk_rel_file!("hurryd.pid"));
-        let context_path = hurry_cache_dir.join(mk_rel_file!("hurryd.json"));
+        let namespace = Self::namespace(std::env::var(DAEMON_NAMESPACE_ENV).ok().as_deref());
+        let pid_file_path = hurry_cache_dir.try_join_file(format!("hurryd-{namespace}.pid"))?;
+        let context_path = hurry_cache_dir.try_join_file(format!("hurryd-{namespace}.json"))?;
         Ok(DaemonPaths {
             pid_file_path,
             context_path,
+            dir: hurry_cache_dir,
+            namespace,
         })
     }
 
+    /// The namespace for the daemon's files.
+    ///
+    /// The cache directory can be shared between users (for example, on build
+    /// servers where `$HOME` or `$XDG_CACHE_HOME` is shared), so daemon files
+    /// are always namespaced by user. Daemons started with
+    /// [`DAEMON_NAMESPACE_ENV`] set are further namespaced by a hash of its
+    /// value.
+    fn namespace(extra: Option<&str>) -> String {
+        let user = current_user_id();
+        match extra.filter(|extra| !extra.is_empty()) {
+            Some(extra) => {
+                let mut hash = [0; 8];
+                blake3::Hasher::new()
+                    .update(extra.as_bytes())
+                    .finalize_xof()
+                    .fill(&mut hash);
+                format!("{user}-{}", hex::encode(hash))
+            }
+            None => user,
+        }
+    }
+
+    /// The directory the daemon keeps unfinished uploads in, so that the
+    /// next daemon can resume them.
+    pub fn uploads_dir(&self) -> Result<AbsDirPath> {
+        self.dir
+            .try_join_dir(format!("hurryd-{}-uploads", self.namespace))
+    }
+
+    /// The log file for the daemon with the given process ID.
+    pub fn log_file_path(&self, pid: u32) -> Result<AbsFilePath> {
+        self.dir
+            .try_join_file(format!("hurryd-{}.{pid}.log", self.namespace))
+    }
+
+    /// The log files of the daemons that have run in this namespace, most
+    /// recently modified first.
+    pub async fn log_files(&self) -> Result<Vec<AbsFilePath>> {
+        let prefix = format!("hurryd-{}.", self.namespace);
+        let mut entries = fs::read_dir(&self.dir).await?;
+        let mut logs = Vec::new();
+        while let Some(entry) = entries.next_entry().await.context("read cache directory")? {
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if !name.starts_with(&prefix) || !name.ends_with(".log") {
+                continue;
+            }
+            let modified = entry.metadata().await.and_then(|m| m.modified()).ok();
+            logs.push((modified, self.dir.try_join_file(name)?));
+        }
+        logs.sort_by(|(a, _), (b, _)| b.cmp(a));
+        Ok(logs.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Read the context of the running daemon, if there is one.
+    ///
+    /// If the pid-file was left behind by a daemon that crashed, it and the
+    /// context file are removed so that a new daemon can start. A pid-file
+    /// counts as stale if its process has exited or if the process ID has
+    /// since been reused by a process that isn't this user's daemon.
     pub async fn daemon_running(&self) -> Result<Option<DaemonContext>> {
-        if self.pid_file_path.exists().await {
-            let pid = fs::must_read_buffered_utf8(&self.pid_file_path).await?;
-            match pid.trim().parse::<u32>() {
-                Ok(pid) => {
-                    let system = System::new_with_specifics(
-                        RefreshKind::nothing().with_processes(ProcessRefreshKind::nothing()),
-                    );
-                    let process = system.process(Pid::from_u32(pid));
-                    match process {
-                        Some(_) => self.read_context().await?,
-                        None => None,
-                    }
-                }
-                Err(err) => {
-                    bail!("could not parse pid-file: {err}")
-                }
+        let Some(pid) = fs::read_buffered_utf8(&self.pid_file_path).await? else {
+            return Ok(None);
+        };
+        let running = match pid.trim().parse::<u32>() {
+            Ok(pid) => is_own_daemon(pid),
+            Err(err) => {
+                warn!(?err, path = ?self.pid_file_path, "could not parse pid-file");
+                false
+            }
+        };
+        if running {
+            return self.read_context().await;
+        }
+
+        self.remove_stale_files().await;
+        Ok(None)
+    }
+
+    /// Remove the files left behind by a daemon that is no longer running.
+    ///
+    /// Failures are logged and ignored: if the files can't be removed, the
+    /// next daemon reports why when it tries to replace them.
+    async fn remove_stale_files(&self) {
+        info!(path = ?self.pid_file_path, "removing stale daemon files");
+        for path in [&self.pid_file_path, &self.context_path] {
+            if fs::exists(path).await
+                && let Err(err) = fs::remove_file(path).await
+            {
+                warn!(?err, ?path, "failed to remove stale daemon file");
             }
-        } else {
-            None
         }
-        .pipe(Ok)
+    }
+
+    /// Stop a daemon started by a version of Hurry from before daemon files
+    /// were namespaced, and remove its files.
+    ///
+    /// Such a daemon doesn't know about the namespaced files, so it would
+    /// otherwise keep running next to the daemon started in its place. Its
+    /// files aren't namespaced by user either, so they're left alone if they
+    /// belong to another user's daemon. If the daemon they describe doesn't
+    /// answer, they're stale and are removed.
+    pub async fn stop_legacy_daemon(&self, timeout: Duration) -> Result<()> {
+        let pid_file_path = self.dir.try_join_file(LEGACY_PID_FILE)?;
+        let context_path = self.dir.try_join_file(LEGACY_CONTEXT_FILE)?;
+        let Some(pid) = fs::read_buffered_utf8(&pid_file_path).await? else {
+            return Ok(());
+        };
+        let pid = pid.trim().parse::<u32>().ok();
+        if pid.and_then(daemon_owner) == Some(DaemonOwner::OtherUser) {
+            return Ok(());
+        }
+
+        let context = fs::read_buffered_utf8(&context_path)
+            .await?
+            .and_then(|context| serde_json::from_str::<DaemonContext>(&context).ok())
+            .filter(|context| Some(context.pid) == pid);
+        if let Some(context) = context
+            && context.handshake().await.is_ok()
+        {
+            info!(
+                pid = context.pid,
+                "stopping daemon started by an older version of Hurry"
+            );
+            context
+                .stop(timeout)
+                .await
+                .context("stop daemon started by an older version of Hurry")
+                .with_section(|| format!("{context:?}").header("Daemon context:"))?;
+        }
+
+        for path in [&pid_file_path, &context_path] {
+            if fs::exists(path).await
+                && let Err(err) = fs::remove_file(path).await
+            {
+                warn!(?err, ?path, "failed to remove legacy daemon file");
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the context of the running daemon, which tells clients where to
+    /// send requests.
+    ///
+    /// The context is written to a temporary file that then replaces the
+    /// context file, so that clients never read a partially written context.
+    pub async fn write_context(&self, context: &DaemonContext) -> Result<()> {
+        let encoded = serde_json::to_string(context)
+            .context("encode daemon context")
+            .with_section(|| format!("{context:?}").header("Context:"))?;
+        let temp = self.dir.try_join_file(format!(
+            "hurryd-{}.{}.json.tmp",
+            self.namespace, context.pid
+        ))?;
+        fs::write(&temp, &encoded).await?;
+        fs::rename(&temp, &self.context_path)
+            .await
+            .with_context(|| format!("write daemon context to {:?}", self.context_path))
     }
 
     pub async fn read_context(&self) -> Result<Option<DaemonContext>> {
@@ -82,3 +505,282 @@ impl DaemonPaths {
         Ok(Some(daemon_context))
     }
 }
+
+/// Whether the process is a Hurry daemon owned by the current user.
+///
+/// Checking only that the process exists isn't enough: after a daemon
+/// crashes, its process ID can be reused by any other process, including one
+/// owned by another user.
+fn is_own_daemon(pid: u32) -> bool {
+    daemon_owner(pid) == Some(DaemonOwner::CurrentUser)
+}
+
+/// Who owns a Hurry daemon process.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum DaemonOwner {
+    CurrentUser,
+    OtherUser,
+}
+
+/// The owner of the process, or `None` if the process doesn't exist or isn't
+/// a Hurry daemon.
+fn daemon_owner(pid: u32) -> Option<DaemonOwner> {
+    let system = System::new_with_specifics(
+        RefreshKind::nothing()
+            .with_processes(ProcessRefreshKind::nothing().with_user(UpdateKind::OnlyIfNotSet)),
+    );
+    let process = system.process(Pid::from_u32(pid))?;
+    if !process.name().to_string_lossy().contains("hurry") {
+        return None;
+    }
+    #[cfg(unix)]
+    let is_own = process.user_id().is_some_and(|uid| **uid == current_uid());
+    #[cfg(not(unix))]
+    let is_own = true;
+    Some(if is_own {
+        DaemonOwner::CurrentUser
+    } else {
+        DaemonOwner::OtherUser
+    })
+}
+
+/// The ID of the user running this process.
+#[cfg(unix)]
+fn current_user_id() -> String {
+    current_uid().to_string()
+}
+
+#[cfg(unix)]
+fn current_uid() -> libc::uid_t {
+    // SAFETY: `getuid` has no preconditions and always succeeds.
+    unsafe { libc::getuid() }
+}
+
+/// The name of the user running this process.
+#[cfg(not(unix))]
+fn current_user_id() -> String {
+    std::env::var("USERNAME").unwrap_or_else(|_| String::from("default"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
+        time::Duration,
+    };
+
+    use axum::{
+        Router,
+        http::StatusCode,
+        response::Response,
+        routing::{get, post},
+    };
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use tempfile::TempDir;
+    use tokio::{net::TcpListener, sync::Mutex};
+
+    use super::{
+        DAEMON_VERSION, DaemonContext, DaemonHealth, DaemonPaths, LEGACY_CONTEXT_FILE,
+        LEGACY_PID_FILE, current_user_id,
+    };
+    use crate::{
+        fs,
+        path::{AbsDirPath, AbsFilePath, TryJoinWith as _},
+    };
+
+    fn paths(temp: &TempDir) -> DaemonPaths {
+        let dir = AbsDirPath::try_from(temp.path().to_path_buf()).unwrap();
+        DaemonPaths {
+            pid_file_path: dir.try_join_file("hurryd-test.pid").unwrap(),
+            context_path: dir.try_join_file("hurryd-test.json").unwrap(),
+            dir,
+            namespace: String::from("test"),
+        }
+    }
+
+    fn context(pid: u32) -> DaemonContext {
+        DaemonContext {
+            pid,
+            url: String::from("127.0.0.1:4567"),
+            log_file_path: AbsFilePath::try_from("/cache/hurryd-test.1.log").unwrap(),
+        }
+    }
+
+    #[test]
+    fn namespace_includes_user() {
+        let user = current_user_id();
+        pretty_assert_eq!(DaemonPaths::namespace(None), user);
+        pretty_assert_eq!(DaemonPaths::namespace(Some("")), user);
+
+        let a = DaemonPaths::namespace(Some("/work/a"));
+        let b = DaemonPaths::namespace(Some("/work/b"));
+        let suffix = a.strip_prefix(&format!("{user}-")).unwrap_or_default();
+        pretty_assert_eq!(suffix.len(), 16, "{a}");
+        assert!(suffix.chars().all(|c| c.is_ascii_hexdigit()), "{a}");
+        assert_ne!(a, b);
+        pretty_assert_eq!(a, DaemonPaths::namespace(Some("/work/a")));
+    }
+
+    #[tokio::test]
+    async fn context_round_trips() {
+        let temp = TempDir::new().unwrap();
+        let paths = paths(&temp);
+        pretty_assert_eq!(paths.read_context().await.unwrap(), None);
+
+        let context = context(1234);
+        paths.write_context(&context).await.unwrap();
+        pretty_assert_eq!(paths.read_context().await.unwrap(), Some(context));
+
+        // The context is written through a temporary file, which is renamed
+        // into place rather than left behind.
+        let temp_file = paths
+            .dir
+            .try_join_file("hurryd-test.1234.json.tmp")
+            .unwrap();
+        assert!(!fs::exists(&temp_file).await);
+    }
+
+    #[tokio::test]
+    async fn malformed_context_is_an_error() {
+        let temp = TempDir::new().unwrap();
+        let paths = paths(&temp);
+        fs::write(&paths.context_path, "{\"pid\": 12")
+            .await
+            .unwrap();
+        assert!(paths.read_context().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn running_daemon_is_read_from_context() {
+        let temp = TempDir::new().unwrap();
+        let paths = paths(&temp);
+
+        // Test binaries are named after the crate, so this process passes for
+        // a daemon.
+        let context = context(std::process::id());
+        fs::write(&paths.pid_file_path, context.pid.to_string())
+            .await
+            .unwrap();
+        paths.write_context(&context).await.unwrap();
+        pretty_assert_eq!(paths.daemon_running().await.unwrap(), Some(context));
+    }
+
+    #[tokio::test]
+    async fn stale_files_are_removed() {
+        let temp = TempDir::new().unwrap();
+        let paths = paths(&temp);
+        fs::write(&paths.pid_file_path, "not a pid").await.unwrap();
+        paths.write_context(&context(1234)).await.unwrap();
+
+        pretty_assert_eq!(paths.daemon_running().await.unwrap(), None);
+        assert!(!fs::exists(&paths.pid_file_path).await);
+        assert!(!fs::exists(&paths.context_path).await);
+    }
+
+    #[tokio::test]
+    async fn stale_legacy_files_are_removed() {
+        let temp = TempDir::new().unwrap();
+        let paths = paths(&temp);
+        let pid_file = paths.dir.try_join_file(LEGACY_PID_FILE).unwrap();
+        let context_file = paths.dir.try_join_file(LEGACY_CONTEXT_FILE).unwrap();
+        fs::write(&pid_file, "not a pid").await.unwrap();
+        fs::write(&context_file, "{\"pid\": 12").await.unwrap();
+
+        paths
+            .stop_legacy_daemon(Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert!(!fs::exists(&pid_file).await);
+        assert!(!fs::exists(&context_file).await);
+
+        // Without legacy files there's nothing to do.
+        paths
+            .stop_legacy_daemon(Duration::from_secs(10))
+            .await
+            .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn legacy_daemon_is_stopped() {
+        let temp = TempDir::new().unwrap();
+        let paths = paths(&temp);
+
+        // The legacy daemon predates health checks, and its process exits
+        // when it's asked to shut down.
+        let process = tokio::process::Command::new("sleep")
+            .arg("60")
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let pid = process.id().unwrap();
+        let process = Arc::new(Mutex::new(process));
+        let shutdown_requested = Arc::new(AtomicBool::new(false));
+        let shutdown = {
+            let process = process.clone();
+            let shutdown_requested = shutdown_requested.clone();
+            move || async move {
+                shutdown_requested.store(true, Ordering::SeqCst);
+                process.lock().await.kill().await.unwrap();
+                Response::default()
+            }
+        };
+        let app = Router::new()
+            .route("/api/v0/health", get(|| async { StatusCode::NOT_FOUND }))
+            .route("/api/v0/shutdown", post(shutdown));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let pid_file = paths.dir.try_join_file(LEGACY_PID_FILE).unwrap();
+        let context_file = paths.dir.try_join_file(LEGACY_CONTEXT_FILE).unwrap();
+        let context = DaemonContext {
+            url,
+            ..context(pid)
+        };
+        fs::write(&pid_file, pid.to_string()).await.unwrap();
+        fs::write(&context_file, serde_json::to_string(&context).unwrap())
+            .await
+            .unwrap();
+
+        paths
+            .stop_legacy_daemon(Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert!(shutdown_requested.load(Ordering::SeqCst));
+        assert!(process.lock().await.try_wait().unwrap().is_some());
+        assert!(!fs::exists(&pid_file).await);
+        assert!(!fs::exists(&context_file).await);
+    }
+
+    #[test]
+    fn check_rejects_other_versions_and_processes() {
+        let context = context(1234);
+        let health = DaemonHealth {
+            pid: 1234,
+            version: String::from(DAEMON_VERSION),
+        };
+        context.check(&health).unwrap();
+
+        let other_version = DaemonHealth {
+            version: String::from("0.0.0-other"),
+            ..health.clone()
+        };
+        let err = context.check(&other_version).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("daemon is running Hurry 0.0.0-other"),
+            "{err:?}"
+        );
+
+        let other_process = DaemonHealth {
+            pid: 5678,
+            ..health
+        };
+        let err = context.check(&other_process).unwrap_err();
+        assert!(err.to_string().contains("is not process 1234"), "{err:?}");
+    }
+}
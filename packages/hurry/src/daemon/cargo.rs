@@ -1,42 +1,752 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use axum::{
     Router,
-    extract::{Json, State},
+    body::Body,
+    extract::{Json, Path, State},
+    http::header,
+    response::IntoResponse,
     routing::{get, post},
 };
-use color_eyre::Result;
+use color_eyre::{
+    Result,
+    eyre::{Context as _, OptionExt as _},
+};
 use dashmap::DashMap;
 use derive_more::Debug;
+use futures::StreamExt as _;
 use serde::{Deserialize, Serialize};
-use tracing::{Instrument, error, info, instrument};
+use tokio::sync::mpsc;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{Instrument, debug, error, info, instrument, warn};
 use url::Url;
 use uuid::Uuid;
 
 use crate::{
-    cargo::{Restored, SaveProgress, UnitPlan, Workspace, save_units},
-    cas::CourierCas,
+    cargo::{
+        CargoBuildArguments, LockWait, RestoreOptions, Restored, SaveProgress, UnitHash, UnitPlan,
+        UnitUploadState, UploadPolicy, Workspace, prefetch_units, restore_units, rustc_version,
+        save_units, traffic_class,
+    },
+    cas::{CourierCas, LocalCas},
+    config::{HurryConfig, LocalCacheConfig, NetworkConfig, RestoreConfig, UploadConfig},
+    daemon::{
+        DaemonError, DaemonHealth, DaemonMetrics, DaemonStatus, InvocationReport, PrewarmSchedule,
+        metrics::Outcome,
+    },
+    fs, mk_rel_file,
+    path::{AbsDirPath, AbsFilePath, JoinWith as _, TryJoinWith as _},
+    progress::{TransferBar, format_size},
 };
-use clients::{Courier, Token};
+use clients::{
+    BufferSizes, Courier, ProxyConfig, Token,
+    courier::v1::{
+        CacheScope, ConnectionPool, ConnectionStats, HashAlgorithm, Key, PoolOptions,
+        cache::SavedUnitMetadata,
+    },
+};
+
+/// How often the daemon checks watched workspaces for drift.
+const WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often the daemon checks whether registered workspaces are due to be
+/// prewarmed.
+const PREWARM_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the daemon checks whether uploads have finished while it drains
+/// them on shutdown.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many times an upload can fail before the daemon stops retrying it.
+const MAX_UPLOAD_ATTEMPTS: u64 = 3;
+
+/// The window over which the recent transfer rate of an upload is measured.
+const RECENT_RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// How long the daemon keeps prewarming a workspace after its last build.
+const PREWARM_FORGET_AFTER: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+/// How often the daemon reports restore progress to the client.
+const RESTORE_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
 
 #[derive(Debug, Clone)]
 pub struct CargoDaemonState {
     uploads: Arc<DashMap<Uuid, CargoUploadStatus>>,
+
+    /// How fast each upload that has started is sending bytes to Courier.
+    upload_rates: Arc<DashMap<Uuid, TransferRate>>,
+
+    /// Connections to Courier, shared by every request the daemon handles so
+    /// that consecutive uploads don't each repeat the TLS handshake.
+    connections: Connections,
+
+    /// Every background task the daemon runs, so that shutdown can wait for
+    /// them to stop instead of abandoning them mid-request.
+    tasks: TaskTracker,
+
+    /// Cancelled when the daemon shuts down. Every task's cancellation token
+    /// is a child of this one.
+    shutdown: CancellationToken,
+
+    /// The current session of each profile directory, keyed by the profile
+    /// directory like `deferred`.
+    ///
+    /// A session lasts from the start of one build in the profile directory to
+    /// the start of the next one (or until its workspace's sessions are ended
+    /// explicitly, e.g. by `hurry cargo clean`); ending it cancels the work it
+    /// started. Builds in other profiles of the same workspace write other
+    /// directories, so they don't end it.
+    sessions: Arc<DashMap<AbsDirPath, Session>>,
+
+    /// The cancellation token of each upload that's still running. Each is a
+    /// child of its profile directory's session token, except while the
+    /// upload is deferred.
+    running: Arc<DashMap<Uuid, CancellationToken>>,
+
+    /// Deferred uploads that haven't started yet, keyed by the profile
+    /// directory they upload from.
+    deferred: Arc<DashMap<AbsDirPath, CargoUploadRequest>>,
+
+    /// The cancellation token of the watch task of each watched workspace.
+    watched: Arc<DashMap<AbsDirPath, CancellationToken>>,
+
+    /// Uploads that haven't finished, persisted so that a restarted daemon
+    /// can resume them.
+    journal: Option<UploadJournal>,
+
+    /// Recently built workspaces that are prewarmed on a schedule.
+    prewarmed: Arc<DashMap<AbsDirPath, PrewarmEntry>>,
+
+    /// When the daemon last restored or uploaded anything.
+    last_activity: Arc<Mutex<jiff::Timestamp>>,
+
+    /// When the daemon started.
+    started: Instant,
+
+    /// The most recent error from work the daemon ran in the background.
+    last_error: Arc<Mutex<Option<DaemonError>>>,
+
+    /// Counters and latencies of the daemon's work, served as Prometheus
+    /// metrics.
+    metrics: DaemonMetrics,
 }
 
-impl Default for CargoDaemonState {
-    fn default() -> Self {
-        Self {
+impl CargoDaemonState {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
             uploads: Arc::new(DashMap::new()),
+            upload_rates: Arc::new(DashMap::new()),
+            connections: Connections::default(),
+            tasks: TaskTracker::new(),
+            shutdown: CancellationToken::new(),
+            sessions: Arc::new(DashMap::new()),
+            running: Arc::new(DashMap::new()),
+            deferred: Arc::new(DashMap::new()),
+            watched: Arc::new(DashMap::new()),
+            journal: None,
+            prewarmed: Arc::new(DashMap::new()),
+            last_activity: Arc::new(Mutex::new(jiff::Timestamp::now())),
+            started: Instant::now(),
+            last_error: Arc::new(Mutex::new(None)),
+            metrics: DaemonMetrics::default(),
+        })
+    }
+
+    /// What the daemon reports about itself and its work.
+    pub fn status(&self) -> DaemonStatus {
+        let health = DaemonHealth::current();
+        let mut status = DaemonStatus {
+            pid: health.pid,
+            version: health.version,
+            uptime: self.started.elapsed(),
+            uploads_in_flight: self.running.len() as u64,
+            uploads_deferred: self.deferred.len() as u64,
+            queued_units: 0,
+            queued_bytes: 0,
+            watched_workspaces: self.watched.len() as u64,
+            last_error: self.last_error.lock().expect("mutex is poisoned").clone(),
+        };
+        for upload in self.uploads.iter() {
+            if let CargoUploadStatus::InProgress(progress) = upload.value() {
+                status.queued_units += progress.total_units.saturating_sub(progress.uploaded_units);
+                status.queued_bytes += progress.queued_bytes;
+            }
+        }
+        status
+    }
+
+    /// The daemon's metrics in the Prometheus text format.
+    pub fn metrics(&self) -> String {
+        self.metrics
+            .render(&self.status(), &self.connections.stats())
+    }
+
+    /// Record an invocation of `hurry` in the daemon's metrics.
+    pub fn record_invocation(&self, report: &InvocationReport) {
+        self.metrics.record_invocation(report);
+    }
+
+    /// Record that the daemon did some work, so that it isn't considered
+    /// idle.
+    fn touch(&self) {
+        *self.last_activity.lock().expect("mutex is poisoned") = jiff::Timestamp::now();
+    }
+
+    /// When the daemon last did any work, or `None` if it's uploading.
+    fn idle_since(&self) -> Option<jiff::Timestamp> {
+        if !self.running.is_empty() {
+            return None;
+        }
+        Some(*self.last_activity.lock().expect("mutex is poisoned"))
+    }
+
+    /// Prewarm registered workspaces whenever they're due, until the daemon
+    /// shuts down.
+    pub fn start_prewarming(&self) {
+        let state = self.clone();
+        let span = tracing::info_span!("prewarm_worker");
+        self.tasks.spawn(
+            async move {
+                loop {
+                    tokio::select! {
+                        _ = tokio::time::sleep(PREWARM_INTERVAL) => {}
+                        _ = state.shutdown.cancelled() => return,
+                    }
+                    state.prewarm_due().await;
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    /// Prewarm the registered workspaces that are due, and forget workspaces
+    /// that haven't been built in a while.
+    async fn prewarm_due(&self) {
+        let now = jiff::Zoned::now();
+        self.prewarmed.retain(|root, entry| {
+            let unused = now
+                .timestamp()
+                .duration_since(entry.last_used)
+                .unsigned_abs();
+            let keep = unused < PREWARM_FORGET_AFTER;
+            if !keep {
+                info!(
+                    ?root,
+                    "forgetting workspace that hasn't been built recently"
+                );
+            }
+            keep
+        });
+
+        let idle_since = self.idle_since();
+        let due = self
+            .prewarmed
+            .iter()
+            .filter_map(|entry| {
+                let reason = entry.schedule.due(&now, entry.last_prewarm, idle_since)?;
+                Some((entry.key().clone(), reason, entry.request.clone()))
+            })
+            .collect::<Vec<_>>();
+        for (root, reason, request) in due {
+            info!(?root, %reason, "prewarming workspace");
+            match prefetch_workspace(&self.connections, &request).await {
+                Ok(Some(count)) => info!(?root, count, "prewarmed workspace"),
+                Ok(None) => {
+                    // Try again once the build finishes.
+                    debug!(?root, "workspace is building, retrying later");
+                    continue;
+                }
+                Err(err) => {
+                    warn!(?err, ?root, "failed to prewarm workspace");
+                    self.record_error("prewarm", &err);
+                }
+            }
+            if let Some(mut entry) = self.prewarmed.get_mut(&root) {
+                entry.last_prewarm = jiff::Timestamp::now();
+            }
+        }
+    }
+
+    /// Record an error from work the daemon ran in the background, so that
+    /// `hurry daemon status` can report it.
+    fn record_error(&self, operation: &'static str, err: &color_eyre::Report) {
+        let error = DaemonError {
+            message: format!("{operation} failed: {err:#}"),
+            at: jiff::Timestamp::now().as_second(),
+        };
+        *self.last_error.lock().expect("mutex is poisoned") = Some(error);
+        self.metrics.record_error(operation);
+    }
+
+    /// Persist uploads in the directory until they finish, so that they can
+    /// be resumed with [`CargoDaemonState::resume_uploads`] after a restart.
+    pub fn with_upload_journal(mut self, dir: AbsDirPath) -> Self {
+        self.journal = Some(UploadJournal { dir });
+        self
+    }
+
+    /// Resume the uploads that a previous daemon didn't finish.
+    ///
+    /// Objects that the previous daemon uploaded are already in Courier, and
+    /// large objects continue from wherever their upload stopped, so resuming
+    /// only uploads what's left. The artifacts are read from the workspace
+    /// again, and units whose artifacts have changed since are skipped like
+    /// any other invalid unit.
+    #[instrument(name = "CargoDaemonState::resume_uploads", skip(self))]
+    pub async fn resume_uploads(&self) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        for mut req in journal.pending().await {
+            let request_id = req.request_id;
+            info!(?request_id, "resuming upload");
+            req.defer = None;
+            self.uploads.insert(
+                request_id,
+                CargoUploadStatus::InProgress(SaveProgress {
+                    total_units: req.units.len() as u64,
+                    ..Default::default()
+                }),
+            );
+            let profile_dir = req.ws.arch_profile_dir(&req.ws.target_arch);
+            let cancel = self.session(&req.ws.root, &profile_dir).child_token();
+            self.running.insert(request_id, cancel.clone());
+            let span = tracing::info_span!("upload_worker", ?request_id);
+            self.tasks
+                .spawn(run_upload(self.clone(), req, cancel).instrument(span));
+        }
+    }
+
+    /// Record the upload in the journal, if there is one.
+    ///
+    /// The journal only lets uploads survive a restart, so failing to record
+    /// one is logged rather than failing the upload.
+    async fn journal_upload(&self, req: &CargoUploadRequest) {
+        if let Some(journal) = &self.journal
+            && let Err(err) = journal.record(req).await
+        {
+            warn!(?err, request_id = ?req.request_id, "failed to record upload in journal");
         }
     }
+
+    /// Keep the failed upload in the journal, if there is one, so that the
+    /// next daemon retries it, unless it has already failed
+    /// [`MAX_UPLOAD_ATTEMPTS`] times.
+    async fn retry_upload_later(&self, request_id: Uuid) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        match journal.record_failure(request_id).await {
+            Ok(Some(failures)) if failures < MAX_UPLOAD_ATTEMPTS => {
+                info!(
+                    ?request_id,
+                    failures, "upload will be retried on next start"
+                );
+            }
+            Ok(Some(failures)) => {
+                warn!(?request_id, failures, "giving up on upload");
+                self.forget_upload(request_id).await;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                warn!(
+                    ?err,
+                    ?request_id,
+                    "failed to record upload failure in journal"
+                );
+                self.forget_upload(request_id).await;
+            }
+        }
+    }
+
+    /// Remove the upload from the journal, if there is one.
+    async fn forget_upload(&self, request_id: Uuid) {
+        if let Some(journal) = &self.journal
+            && let Err(err) = journal.remove(request_id).await
+        {
+            warn!(?err, ?request_id, "failed to remove upload from journal");
+        }
+    }
+
+    /// Wait for uploads that have started to finish, for at most `timeout`,
+    /// returning how many are still running when it elapses.
+    ///
+    /// Deferred uploads haven't read anything yet, so they aren't waited for.
+    /// They stay in the journal, as do uploads that are still running once
+    /// the daemon shuts down, and the next daemon resumes them.
+    #[instrument(name = "CargoDaemonState::drain", skip(self))]
+    pub async fn drain(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let active = self.active_uploads();
+            if active == 0 {
+                info!("uploads drained");
+                return 0;
+            }
+            if Instant::now() >= deadline {
+                warn!(active, "uploads still running after drain timeout");
+                return active;
+            }
+            debug!(active, "waiting for uploads to finish");
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    }
+
+    /// The number of uploads that are running and not deferred.
+    fn active_uploads(&self) -> usize {
+        let deferred = self
+            .deferred
+            .iter()
+            .map(|entry| entry.value().request_id)
+            .collect::<HashSet<_>>();
+        self.running
+            .iter()
+            .filter(|entry| !deferred.contains(entry.key()))
+            .count()
+    }
+
+    /// Cancel all background tasks and wait for them to stop.
+    #[instrument(name = "CargoDaemonState::shutdown", skip(self))]
+    pub async fn shutdown(&self) {
+        self.shutdown.cancel();
+        self.tasks.close();
+        info!(tasks = self.tasks.len(), "waiting for background tasks");
+        self.tasks.wait().await;
+    }
+
+    /// Start a new session for the profile directory of the workspace at
+    /// `root`, cancelling the previous one.
+    fn begin_session(&self, root: &AbsDirPath, profile_dir: &AbsDirPath) -> CancellationToken {
+        let session = Session {
+            root: root.clone(),
+            token: self.shutdown.child_token(),
+        };
+        let token = session.token.clone();
+        if let Some(previous) = self.sessions.insert(profile_dir.clone(), session) {
+            info!(?profile_dir, "cancelling previous session");
+            previous.token.cancel();
+        }
+        token
+    }
+
+    /// The current session for the profile directory of the workspace at
+    /// `root`, starting one if there isn't one.
+    fn session(&self, root: &AbsDirPath, profile_dir: &AbsDirPath) -> CancellationToken {
+        self.sessions
+            .entry(profile_dir.clone())
+            .or_insert_with(|| Session {
+                root: root.clone(),
+                token: self.shutdown.child_token(),
+            })
+            .token
+            .clone()
+    }
+
+    /// Cancel the running upload with the provided ID, or every running upload
+    /// if there's no ID, returning the IDs of the cancelled uploads.
+    fn cancel_uploads(&self, request_id: Option<Uuid>) -> Vec<Uuid> {
+        let ids = match request_id {
+            Some(request_id) => vec![request_id],
+            None => self.running.iter().map(|entry| *entry.key()).collect(),
+        };
+        ids.into_iter()
+            .filter_map(|request_id| self.running.remove(&request_id))
+            .map(|(request_id, token)| {
+                info!(?request_id, "cancelling upload");
+                token.cancel();
+                request_id
+            })
+            .collect()
+    }
+
+    /// End the current session of every profile directory in the workspace,
+    /// cancelling the work they started.
+    fn end_session(&self, root: &AbsDirPath) -> bool {
+        let mut ended = false;
+        self.sessions.retain(|_, session| {
+            if &session.root != root {
+                return true;
+            }
+            session.token.cancel();
+            ended = true;
+            false
+        });
+        ended
+    }
+}
+
+/// A session of builds in one profile directory.
+#[derive(Debug, Clone)]
+struct Session {
+    /// The root of the workspace the profile directory belongs to.
+    root: AbsDirPath,
+    token: CancellationToken,
+}
+
+/// Connection pools to Courier, one for each proxy and pool configuration.
+///
+/// The daemon is shared by every workspace on the machine, and each workspace
+/// can configure its own proxy and network settings in `hurry.toml`, so
+/// requests that use different settings can't share connections.
+#[derive(Debug, Clone, Default)]
+struct Connections(Arc<DashMap<(ProxyConfig, PoolOptions), ConnectionPool>>);
+
+impl Connections {
+    /// Create a client for Courier that uses the pool for the proxy and
+    /// network configuration, creating the pool if needed.
+    ///
+    /// The daemon inherits the environment of the build that started it, so
+    /// it declares the same traffic class as that build.
+    fn client(
+        &self,
+        proxy: &ProxyConfig,
+        network: &NetworkConfig,
+        base: Url,
+        token: Token,
+    ) -> Result<Courier> {
+        let options = network.pool_options();
+        let pool = self
+            .0
+            .entry((proxy.clone(), options))
+            .or_try_insert_with(|| ConnectionPool::with_options(proxy, &options))?
+            .clone();
+        Ok(pool
+            .client(base, token)
+            .with_timeouts(network.timeouts())
+            .with_traffic_class(traffic_class()))
+    }
+
+    /// Statistics summed across every pool.
+    fn stats(&self) -> ConnectionStats {
+        self.0
+            .iter()
+            .map(|pool| pool.stats())
+            .fold(ConnectionStats::default(), |total, stats| ConnectionStats {
+                connections_opened: total.connections_opened + stats.connections_opened,
+                warmups: total.warmups + stats.warmups,
+            })
+    }
 }
 
 pub fn cargo_router() -> Router<CargoDaemonState> {
     Router::new()
+        .route("/restore", post(restore))
         .route("/upload", post(upload))
         .route("/status", post(status))
         .route("/status/all", get(status_all))
+        .route("/status/{request_id}", get(status_detail))
+        .route("/prefetch", post(prefetch))
+        .route("/watch", post(watch))
+        .route("/prewarm", post(prewarm))
+        .route("/warm", post(warm))
+        .route("/connections", get(connections))
+        .route("/session/end", post(end_session))
+        .route("/cancel", post(cancel))
+}
+
+/// Request to restore units from the cache into a workspace.
+///
+/// Restores run in the daemon, like uploads, so that both share the daemon's
+/// connections to Courier and so that restores from concurrent builds share
+/// the local CAS.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CargoRestoreRequest {
+    pub courier_url: Url,
+    pub courier_token: Token,
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    /// The algorithm to hash new content with, already negotiated with
+    /// Courier.
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+
+    /// The buffer sizes to download with, already resolved by the client.
+    #[serde(default)]
+    pub buffer_sizes: BufferSizes,
+    pub ws: Workspace,
+    #[debug(skip)]
+    pub units: Vec<UnitPlan>,
+    #[serde(default)]
+    pub config: RestoreConfig,
+    #[serde(default)]
+    pub local_cache: LocalCacheConfig,
+
+    /// How long restore may run before the units that haven't been restored
+    /// yet are left for Cargo to build.
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+
+    /// The cache scope to restore units from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_scope: Option<CacheScope>,
+}
+
+/// A message in the response to a restore request.
+///
+/// The response is streamed as newline-delimited JSON: any number of
+/// `Progress` messages followed by exactly one `Complete` or `Failed`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CargoRestoreEvent {
+    Progress(CargoRestoreProgress),
+    Complete(Restored),
+    Failed { error: String },
+}
+
+/// The progress of a restore so far.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CargoRestoreProgress {
+    pub restored_units: u64,
+
+    /// The number of units restore is still expected to restore, which
+    /// shrinks as units turn out to be fresh locally or missing from the
+    /// cache.
+    pub total_units: u64,
+    pub restored_files: u64,
+    pub restored_bytes: u64,
+}
+
+impl From<&TransferBar> for CargoRestoreProgress {
+    fn from(progress: &TransferBar) -> Self {
+        Self {
+            restored_units: progress.position(),
+            total_units: progress.length(),
+            restored_files: progress.files(),
+            restored_bytes: progress.bytes(),
+        }
+    }
+}
+
+/// Restore units, streaming progress to the client until restore finishes.
+///
+/// Restoring starts a new build in the profile, so this starts a new session
+/// for the profile directory: uploads from earlier builds of the profile would
+/// read artifacts that restore is now overwriting. Uploads from other profiles
+/// of the workspace keep running. The restore is cancelled if the client
+/// disconnects.
+#[instrument(skip(state, req), fields(root = ?req.ws.root, units = req.units.len()))]
+async fn restore(
+    State(state): State<CargoDaemonState>,
+    Json(req): Json<CargoRestoreRequest>,
+) -> impl IntoResponse {
+    state.touch();
+    let profile_dir = req.ws.arch_profile_dir(&req.ws.target_arch);
+    let cancel = state
+        .begin_session(&req.ws.root, &profile_dir)
+        .child_token();
+    let (tx, rx) = mpsc::channel(16);
+    let span = tracing::info_span!("restore_worker");
+    let worker = state.clone();
+    state.tasks.spawn(
+        async move {
+            let started = Instant::now();
+            let progress = TransferBar::hidden(req.units.len() as u64);
+            let record = |outcome| {
+                worker.metrics.record_restore(
+                    outcome,
+                    started.elapsed(),
+                    progress.position(),
+                    progress.bytes(),
+                );
+            };
+            let restore = restore_workspace(&worker.connections, &req, &progress);
+            tokio::pin!(restore);
+            let mut interval = tokio::time::interval(RESTORE_PROGRESS_INTERVAL);
+            let event = loop {
+                tokio::select! {
+                    restored = &mut restore => break match restored {
+                        Ok(restored) => {
+                            info!(units = restored.units.len(), "restore completed");
+                            record(Outcome::Complete);
+                            CargoRestoreEvent::Complete(restored)
+                        }
+                        Err(err) => {
+                            error!(?err, "restore failed");
+                            worker.record_error("restore", &err);
+                            record(Outcome::Failed);
+                            CargoRestoreEvent::Failed { error: format!("{err:#}") }
+                        }
+                    },
+                    _ = cancel.cancelled() => {
+                        info!("restore cancelled");
+                        record(Outcome::Cancelled);
+                        let error = String::from("restore cancelled");
+                        break CargoRestoreEvent::Failed { error };
+                    }
+                    _ = interval.tick() => {
+                        let update = CargoRestoreProgress::from(&progress);
+                        if tx.send(CargoRestoreEvent::Progress(update)).await.is_err() {
+                            info!("client disconnected, cancelling restore");
+                            record(Outcome::Cancelled);
+                            return;
+                        }
+                    }
+                }
+            };
+
+            worker.touch();
+
+            // The client may have disconnected by now, in which case there's
+            // nobody left to tell.
+            let update = CargoRestoreEvent::Progress(CargoRestoreProgress::from(&progress));
+            if tx.send(update).await.is_ok() {
+                let _ = tx.send(event).await;
+            }
+        }
+        .instrument(span),
+    );
+
+    let events = futures::stream::unfold(rx, |mut rx| async move {
+        let event = rx.recv().await?;
+        let line = serde_json::to_vec(&event).map(|mut line| {
+            line.push(b'\n');
+            line
+        });
+        Some((line, rx))
+    });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(events),
+    )
+}
+
+/// Restore the requested units into the workspace.
+#[instrument(skip_all)]
+async fn restore_workspace(
+    connections: &Connections,
+    req: &CargoRestoreRequest,
+    progress: &TransferBar,
+) -> Result<Restored> {
+    let courier = connections
+        .client(
+            &req.proxy,
+            &req.network,
+            req.courier_url.clone(),
+            req.courier_token.clone(),
+        )?
+        .with_buffer_sizes(req.buffer_sizes)
+        .with_cache_scope(req.cache_scope.clone());
+    let cas = CourierCas::new(courier.clone()).with_hash_algorithm(req.hash_algorithm);
+    let local = LocalCas::open(&req.local_cache).await?;
+    let deadline = req.timeout.map(|timeout| Instant::now() + timeout);
+    let restored = restore_units(
+        &courier,
+        &cas,
+        &local,
+        &req.ws,
+        &req.units,
+        progress,
+        RestoreOptions::new(&req.config).with_deadline(deadline),
+    )
+    .await;
+    local.trim().await;
+    restored
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,11 +754,57 @@ pub struct CargoUploadRequest {
     pub request_id: Uuid,
     pub courier_url: Url,
     pub courier_token: Token,
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    /// The algorithm to hash new content with, already negotiated with
+    /// Courier.
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+
+    /// The buffer sizes to upload with, already resolved by the client.
+    #[serde(default)]
+    pub buffer_sizes: BufferSizes,
     pub ws: Workspace,
     #[debug(skip)]
     pub units: Vec<UnitPlan>,
     #[debug(skip)]
     pub skip: Restored,
+    #[debug(skip)]
+    pub policy: UploadPolicy,
+
+    /// Metadata about the build environment, recorded with the saved units.
+    #[serde(default)]
+    pub metadata: SavedUnitMetadata,
+
+    /// The size budget of the local CAS, which uploaded objects are kept in.
+    #[serde(default)]
+    pub local_cache: LocalCacheConfig,
+
+    /// How long to wait before starting the upload.
+    ///
+    /// If another upload of the same profile arrives in the meantime, it
+    /// takes over the units of this one and this one is superseded.
+    #[serde(default)]
+    pub defer: Option<Duration>,
+
+    /// How many units to upload at once.
+    #[serde(default = "default_upload_parallelism")]
+    pub parallelism: usize,
+
+    /// Whether large objects are uploaded as lists of their chunks.
+    #[serde(default)]
+    pub chunking: bool,
+
+    /// The cache scope to save units in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_scope: Option<CacheScope>,
+}
+
+fn default_upload_parallelism() -> usize {
+    UploadConfig::DEFAULT_PARALLELISM
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -59,9 +815,26 @@ pub struct CargoUploadResponse {
 #[instrument(skip(state))]
 async fn upload(
     State(state): State<CargoDaemonState>,
-    Json(req): Json<CargoUploadRequest>,
+    Json(mut req): Json<CargoUploadRequest>,
 ) -> Json<CargoUploadResponse> {
     let request_id = req.request_id;
+    state.touch();
+
+    // Only the latest state of a profile needs to be uploaded, so a deferred
+    // upload that hasn't started yet is merged into this one.
+    let key = req.ws.arch_profile_dir(&req.ws.target_arch);
+    if let Some((_, superseded)) = state.deferred.remove(&key) {
+        info!(?request_id, superseded = ?superseded.request_id, "merging deferred upload");
+        state.cancel_uploads(Some(superseded.request_id));
+        state.uploads.insert(
+            superseded.request_id,
+            CargoUploadStatus::Superseded { by: request_id },
+        );
+        state.forget_upload(superseded.request_id).await;
+        merge_upload(&mut req, superseded);
+    }
+    state.journal_upload(&req).await;
+
     state.uploads.insert(
         request_id,
         CargoUploadStatus::InProgress(SaveProgress {
@@ -69,44 +842,340 @@ async fn upload(
             total_units: req.units.len() as u64,
             uploaded_files: 0,
             uploaded_bytes: 0,
+            queued_bytes: 0,
+            skipped_by_policy: Vec::new(),
+            skipped_invalid: Vec::new(),
+            nondeterministic: Vec::new(),
+            read_only: false,
+            maintenance: None,
+            repaired: Vec::new(),
         }),
     );
+
+    // A new build in the profile starts a new session: uploads from earlier
+    // builds would read artifacts that Cargo is now overwriting. Uploads from
+    // other profiles read other directories, so they keep running.
+    let session = state.begin_session(&req.ws.root, &key);
     let span = tracing::info_span!("upload_worker", ?request_id);
-    tokio::spawn(
+    let Some(defer) = req.defer else {
+        let cancel = session.child_token();
+        state.running.insert(request_id, cancel.clone());
+        state
+            .tasks
+            .spawn(run_upload(state.clone(), req, cancel).instrument(span));
+        return Json(CargoUploadResponse { ok: true });
+    };
+
+    // A deferred upload hasn't read anything yet, so later builds don't
+    // cancel it; they merge it into their own upload instead. It can still be
+    // cancelled explicitly while it waits.
+    let cancel = state.shutdown.child_token();
+    state.running.insert(request_id, cancel.clone());
+    state.deferred.insert(key.clone(), req);
+    debug!(?request_id, ?defer, "deferring upload");
+    let worker = state.clone();
+    state.tasks.spawn(
         async move {
-            let courier = Courier::new(req.courier_url, req.courier_token)?;
-            let cas = CourierCas::new(courier.clone());
-            let upload = save_units(&courier, &cas, req.ws, req.units, req.skip, |progress| {
+            let state = worker;
+            let cancelled = tokio::select! {
+                _ = tokio::time::sleep(defer) => false,
+                _ = cancel.cancelled() => true,
+            };
+
+            // If a later upload merged this one while it waited, that upload
+            // has already taken over its units and status.
+            let Some((_, req)) = state
+                .deferred
+                .remove_if(&key, |_, pending| pending.request_id == request_id)
+            else {
+                return;
+            };
+            if cancelled {
+                info!(?request_id, "deferred upload cancelled");
+                state.running.remove(&request_id);
+                if !state.shutdown.is_cancelled() {
+                    state.forget_upload(request_id).await;
+                }
+                state
+                    .uploads
+                    .insert(request_id, CargoUploadStatus::Cancelled(Default::default()));
+                return;
+            }
+
+            let cancel = state.session(&req.ws.root, &key).child_token();
+            state.running.insert(request_id, cancel.clone());
+            run_upload(state, req, cancel).await;
+        }
+        .instrument(span),
+    );
+    Json(CargoUploadResponse { ok: true })
+}
+
+/// Upload units, recording the upload's status as it progresses.
+async fn run_upload(state: CargoDaemonState, req: CargoUploadRequest, cancel: CancellationToken) {
+    let request_id = req.request_id;
+    let upload = async {
+        let courier = state
+            .connections
+            .client(&req.proxy, &req.network, req.courier_url, req.courier_token)?
+            .with_buffer_sizes(req.buffer_sizes)
+            .with_cache_scope(req.cache_scope);
+        let cas = CourierCas::new(courier.clone())
+            .with_hash_algorithm(req.hash_algorithm)
+            .with_chunking(req.chunking);
+        let local = LocalCas::open(&req.local_cache).await?;
+        let mut rate = RateTracker::new(Instant::now());
+        let saved = save_units(
+            &courier,
+            &cas,
+            &local,
+            req.ws,
+            req.units,
+            req.skip,
+            req.policy,
+            req.metadata,
+            req.parallelism,
+            |progress| {
+                let transferred = progress.transferred_bytes();
+                state
+                    .upload_rates
+                    .insert(request_id, rate.record(Instant::now(), transferred));
                 state
                     .uploads
                     .insert(request_id, CargoUploadStatus::InProgress(progress.clone()));
-            })
-            .await;
-            match upload {
-                Ok(()) => {
-                    info!(?request_id, "upload completed successfully");
-                    state
-                        .uploads
-                        .insert(request_id, CargoUploadStatus::Complete);
+            },
+        )
+        .await;
+        local.trim().await;
+        saved
+    };
+    let upload = tokio::select! {
+        upload = upload => Some(upload),
+        _ = cancel.cancelled() => None,
+    };
+    state.running.remove(&request_id);
+    state.touch();
+
+    // Report whatever progress was made before the upload stopped.
+    let last_progress = || match state.uploads.get(&request_id).as_deref() {
+        Some(CargoUploadStatus::InProgress(progress)) => progress.clone(),
+        _ => SaveProgress::default(),
+    };
+    let (outcome, status) = match upload {
+        Some(Ok(progress)) => {
+            info!(?request_id, "upload completed successfully");
+            (Outcome::Complete, CargoUploadStatus::Complete(progress))
+        }
+        Some(Err(err)) => {
+            error!(?err, ?request_id, "upload failed");
+            state.record_error("upload", &err);
+            let mut progress = last_progress();
+            progress.fail_unfinished();
+            let status = CargoUploadStatus::Failed {
+                progress,
+                error: format!("{err:#}"),
+            };
+            (Outcome::Failed, status)
+        }
+        None => {
+            info!(?request_id, "upload cancelled");
+            (
+                Outcome::Cancelled,
+                CargoUploadStatus::Cancelled(last_progress()),
+            )
+        }
+    };
+    if let CargoUploadStatus::Complete(progress)
+    | CargoUploadStatus::Failed { progress, .. }
+    | CargoUploadStatus::Cancelled(progress) = &status
+    {
+        state
+            .metrics
+            .record_upload(outcome, progress.uploaded_units, progress.uploaded_bytes);
+        state
+            .metrics
+            .record_nondeterministic(&progress.nondeterministic);
+    }
+
+    // Uploads cancelled because the daemon is shutting down are left in the
+    // journal for the next daemon to resume, and failed uploads for it to
+    // retry. An upload only leaves the journal once Courier has saved it or
+    // it's cancelled (or superseded) by a later build.
+    if !state.shutdown.is_cancelled() {
+        match &status {
+            CargoUploadStatus::Failed { .. } => state.retry_upload_later(request_id).await,
+            _ => state.forget_upload(request_id).await,
+        }
+    }
+    state.uploads.insert(request_id, status);
+}
+
+/// Upload requests persisted on disk until they finish.
+///
+/// Each request is stored as `<request id>.json` in the journal directory,
+/// along with how many times it has failed (as `failures`). Requests include
+/// the Courier token, so the files are only readable by the user running the
+/// daemon.
+#[derive(Debug, Clone)]
+struct UploadJournal {
+    dir: AbsDirPath,
+}
+
+impl UploadJournal {
+    /// The key that counts an entry's failures.
+    const FAILURES: &str = "failures";
+
+    fn path(&self, request_id: Uuid) -> Result<AbsFilePath> {
+        self.dir.try_join_file(format!("{request_id}.json"))
+    }
+
+    /// Persist the request.
+    async fn record(&self, req: &CargoUploadRequest) -> Result<()> {
+        let encoded = serde_json::to_vec(req).context("encode upload request")?;
+        self.write(req.request_id, &encoded).await
+    }
+
+    /// Count a failure of the persisted request, returning how many times
+    /// it has failed, or `None` if it isn't persisted.
+    async fn record_failure(&self, request_id: Uuid) -> Result<Option<u64>> {
+        let path = self.path(request_id)?;
+        let Some(content) = fs::read_buffered(&path).await? else {
+            return Ok(None);
+        };
+        let mut entry =
+            serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&content)
+                .context("parse upload journal entry")?;
+        let failures = entry
+            .get(Self::FAILURES)
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or_default()
+            + 1;
+        entry.insert(String::from(Self::FAILURES), failures.into());
+        let encoded = serde_json::to_vec(&entry).context("encode upload journal entry")?;
+        self.write(request_id, &encoded).await?;
+        Ok(Some(failures))
+    }
+
+    /// Atomically replace the entry for the request.
+    async fn write(&self, request_id: Uuid, content: &[u8]) -> Result<()> {
+        let path = self.path(request_id)?;
+        let temp = self.dir.try_join_file(format!("{request_id}.json.tmp"))?;
+        fs::write_private(&temp, content).await?;
+        fs::rename(&temp, &path)
+            .await
+            .with_context(|| format!("write upload request to {path:?}"))
+    }
+
+    /// Remove the request, if it's persisted.
+    async fn remove(&self, request_id: Uuid) -> Result<()> {
+        let path = self.path(request_id)?;
+        if fs::exists(&path).await {
+            fs::remove_file(&path).await?;
+        }
+        Ok(())
+    }
+
+    /// Read the persisted requests.
+    ///
+    /// Requests that can't be read are removed, since they'd never be
+    /// readable: e.g. they were written by a version of Hurry whose requests
+    /// have a different shape.
+    async fn pending(&self) -> Vec<CargoUploadRequest> {
+        if !fs::exists(&self.dir).await {
+            return Vec::new();
+        }
+        let mut files = fs::walk_files(&self.dir);
+        let mut requests = Vec::new();
+        while let Some(file) = files.next().await {
+            let file = match file {
+                Ok(file) => file,
+                Err(err) => {
+                    warn!(?err, dir = ?self.dir, "failed to read upload journal");
+                    break;
                 }
+            };
+            if file
+                .as_std_path()
+                .extension()
+                .is_none_or(|ext| ext != "json")
+            {
+                continue;
+            }
+            let request = fs::read_buffered(&file)
+                .await
+                .and_then(|content| content.ok_or_eyre("journal entry disappeared"))
+                .and_then(|content| {
+                    serde_json::from_slice::<CargoUploadRequest>(&content)
+                        .context("parse upload request")
+                });
+            match request {
+                Ok(request) => requests.push(request),
                 Err(err) => {
-                    error!(?err, ?request_id, "upload failed");
-                    state
-                        .uploads
-                        .insert(request_id, CargoUploadStatus::Complete);
+                    warn!(?err, ?file, "discarding unreadable upload journal entry");
+                    if let Err(err) = fs::remove_file(&file).await {
+                        warn!(?err, ?file, "failed to remove upload journal entry");
+                    }
                 }
             }
-            Result::<_>::Ok(())
         }
-        .instrument(span),
-    );
-    Json(CargoUploadResponse { ok: true })
+        requests
+    }
+}
+
+/// Merge the units of a superseded upload into `req`.
+///
+/// Units that `req` also uploads are taken from `req`, since they reflect
+/// the latest state of the profile directory. The artifacts of the other
+/// units are still validated before they're uploaded, in case a later build
+/// removed them.
+fn merge_upload(req: &mut CargoUploadRequest, superseded: CargoUploadRequest) {
+    let known = req
+        .units
+        .iter()
+        .map(|unit| unit.info().unit_hash.clone())
+        .collect::<HashSet<_>>();
+    let added = superseded
+        .units
+        .into_iter()
+        .filter(|unit| !known.contains(&unit.info().unit_hash))
+        .collect::<Vec<_>>();
+    for unit in &added {
+        let hash = &unit.info().unit_hash;
+        if superseded.skip.units.contains(hash) {
+            req.skip.units.insert(hash.clone());
+        }
+    }
+    for key in superseded.skip.files {
+        req.skip.files.insert(key);
+    }
+    req.policy = std::mem::take(&mut req.policy).require_dependencies(&added);
+    req.units.extend(added);
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum CargoUploadStatus {
     InProgress(SaveProgress),
-    Complete,
+
+    /// The upload has finished, with its final progress.
+    Complete(SaveProgress),
+
+    /// The upload failed, with the progress made before it stopped.
+    Failed {
+        progress: SaveProgress,
+        error: String,
+    },
+
+    /// The upload was cancelled, with the progress made before it stopped.
+    ///
+    /// Uploads are cancelled by `hurry cancel`, when the build waiting on them
+    /// is interrupted, or when a new build starts in the same workspace.
+    Cancelled(SaveProgress),
+
+    /// The upload was deferred, and merged into a later upload of the same
+    /// profile before it started.
+    Superseded {
+        by: Uuid,
+    },
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -131,6 +1200,130 @@ async fn status(
     Json(CargoUploadStatusResponse { status })
 }
 
+/// The status of an upload, with how fast it's sending bytes to Courier.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CargoUploadProgressResponse {
+    pub status: Option<CargoUploadStatus>,
+
+    /// The bytes that have been sent to Courier so far.
+    pub transferred_bytes: u64,
+
+    /// How fast the upload is sending bytes, or was when it finished.
+    pub rate: TransferRate,
+}
+
+impl CargoUploadProgressResponse {
+    /// Describe the upload's units and transfer rate for a progress bar, e.g.
+    /// `4 uploading, 2 hashing, 1 failed, 12 MB/s`.
+    pub fn detail(&self) -> Option<String> {
+        let Some(CargoUploadStatus::InProgress(progress)) = &self.status else {
+            return None;
+        };
+        let mut parts = [
+            (UnitUploadState::Uploading, "uploading"),
+            (UnitUploadState::Hashing, "hashing"),
+            (UnitUploadState::Failed, "failed"),
+        ]
+        .into_iter()
+        .filter_map(|(state, label)| match progress.count_units(state) {
+            0 => None,
+            count => Some(format!("{count} {label}")),
+        })
+        .collect::<Vec<_>>();
+        parts.push(format!("{}/s", format_size(self.rate.recent)));
+        Some(parts.join(", "))
+    }
+}
+
+/// How fast an upload is sending bytes to Courier.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TransferRate {
+    /// Bytes per second since the upload started.
+    pub average: u64,
+
+    /// Bytes per second over the last few seconds.
+    pub recent: u64,
+}
+
+/// Measures the transfer rate of an upload from samples of how many bytes it
+/// has sent.
+#[derive(Debug)]
+struct RateTracker {
+    start: Instant,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl RateTracker {
+    fn new(start: Instant) -> Self {
+        Self {
+            start,
+            samples: VecDeque::from([(start, 0)]),
+        }
+    }
+
+    /// Record that `bytes` have been sent in total as of `now`, returning the
+    /// resulting rate.
+    fn record(&mut self, now: Instant, bytes: u64) -> TransferRate {
+        self.samples.push_back((now, bytes));
+        // Keep the newest sample that's outside the window as its start, so
+        // that the window covers the whole period even with sparse samples.
+        while self.samples.len() > 2
+            && self
+                .samples
+                .get(1)
+                .is_some_and(|(at, _)| now.duration_since(*at) >= RECENT_RATE_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+
+        let per_second = |elapsed: Duration, bytes: u64| {
+            let secs = elapsed.as_secs_f64();
+            if secs > 0.0 {
+                (bytes as f64 / secs) as u64
+            } else {
+                0
+            }
+        };
+        let (oldest_at, oldest_bytes) = self.samples.front().copied().unwrap_or((now, bytes));
+        TransferRate {
+            average: per_second(now.duration_since(self.start), bytes),
+            recent: per_second(
+                now.duration_since(oldest_at),
+                bytes.saturating_sub(oldest_bytes),
+            ),
+        }
+    }
+}
+
+/// Report an upload's status along with its transfer rate, for rendering a
+/// live progress bar.
+#[instrument]
+async fn status_detail(
+    State(state): State<CargoDaemonState>,
+    Path(request_id): Path<Uuid>,
+) -> Json<CargoUploadProgressResponse> {
+    let status = state.uploads.get(&request_id).map(|r| r.value().to_owned());
+    let transferred_bytes = match &status {
+        Some(
+            CargoUploadStatus::InProgress(progress)
+            | CargoUploadStatus::Complete(progress)
+            | CargoUploadStatus::Cancelled(progress)
+            | CargoUploadStatus::Failed { progress, .. },
+        ) => progress.transferred_bytes(),
+        Some(CargoUploadStatus::Superseded { .. }) | None => 0,
+    };
+    let rate = state
+        .upload_rates
+        .get(&request_id)
+        .map(|r| *r.value())
+        .unwrap_or_default();
+    Json(CargoUploadProgressResponse {
+        status,
+        transferred_bytes,
+        rate,
+    })
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct CargoUploadStatusAllResponse {
     pub statuses: HashMap<Uuid, CargoUploadStatus>,
@@ -145,3 +1338,976 @@ async fn status_all(State(state): State<CargoDaemonState>) -> Json<CargoUploadSt
         .collect();
     Json(CargoUploadStatusAllResponse { statuses })
 }
+
+/// Request to download the cached artifacts for specific packages into the
+/// local CAS, so that a later restore doesn't need to fetch them.
+///
+/// Editors and CI helpers often know which crates will be built next; they can
+/// use this to warm the cache ahead of the actual build.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CargoPrefetchRequest {
+    pub request_id: Uuid,
+    pub courier_url: Url,
+    pub courier_token: Token,
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    /// The directory of the workspace to resolve units in.
+    pub root: AbsDirPath,
+
+    /// The directory the build is invoked in, which relative paths in `argv`
+    /// are resolved against; defaults to `root`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invoked_in: Option<AbsDirPath>,
+
+    /// The `cargo build` arguments that will be used for the build, which
+    /// determine the unit hashes of the packages.
+    pub argv: Vec<String>,
+
+    /// Package specs to prefetch, in the form `name` or `name@version`.
+    ///
+    /// The dependencies of each matching unit are prefetched as well.
+    pub packages: Vec<String>,
+
+    /// The cache scope to prefetch units from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_scope: Option<CacheScope>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CargoPrefetchResponse {
+    pub ok: bool,
+}
+
+/// Prefetch artifacts in the background.
+///
+/// Note that resolving unit hashes requires computing the build plan of the
+/// workspace, which (like `hurry cargo build`) briefly renames the workspace's
+/// target directory, so workspaces that are being built are skipped.
+#[instrument(skip(state))]
+async fn prefetch(
+    State(state): State<CargoDaemonState>,
+    Json(req): Json<CargoPrefetchRequest>,
+) -> Json<CargoPrefetchResponse> {
+    let request_id = req.request_id;
+
+    // Prefetching doesn't touch the target directory, so it doesn't belong to
+    // any profile's session; it gets one of its own, keyed by the workspace
+    // root, which builds don't replace but ending the workspace's sessions
+    // still cancels.
+    let cancel = state.session(&req.root, &req.root);
+    let span = tracing::info_span!("prefetch_worker", ?request_id);
+    let worker = state.clone();
+    state.tasks.spawn(
+        async move {
+            tokio::select! {
+                prefetched = prefetch_packages(&worker.connections, req) => match prefetched {
+                    Ok(Some(count)) => info!(?request_id, count, "prefetch completed successfully"),
+                    Ok(None) => info!(?request_id, "workspace is being built, skipping prefetch"),
+                    Err(err) => {
+                        error!(?err, ?request_id, "prefetch failed");
+                        worker.record_error("prefetch", &err);
+                    }
+                },
+                _ = cancel.cancelled() => info!(?request_id, "prefetch cancelled"),
+            }
+        }
+        .instrument(span),
+    );
+    Json(CargoPrefetchResponse { ok: true })
+}
+
+/// Download the objects for the requested packages into the local CAS,
+/// returning the number of objects downloaded, or `None` if the workspace is
+/// being built.
+#[instrument(skip(connections, req), fields(packages = ?req.packages))]
+async fn prefetch_packages(
+    connections: &Connections,
+    req: CargoPrefetchRequest,
+) -> Result<Option<usize>> {
+    let args = CargoBuildArguments::from_iter(&req.argv);
+    let invoked_in = req.invoked_in.as_ref().unwrap_or(&req.root);
+    let ws = Workspace::from_argv_in_dir(invoked_in, &args).await?;
+
+    // Computing the build plan briefly renames the build directory, which
+    // would break a build that's running, so skip workspaces that are being
+    // built.
+    let Ok(locks) = ws.lock_profile_dirs(LockWait::NoWait).await else {
+        return Ok(None);
+    };
+    let units = ws.units(&args).await?;
+    drop(locks);
+
+    // Select the units of the requested packages along with all of their
+    // transitive dependencies, since building a package needs those too.
+    let by_hash = units
+        .iter()
+        .map(|unit| (&unit.info().unit_hash, unit))
+        .collect::<HashMap<_, _>>();
+    let mut pending = units
+        .iter()
+        .filter(|unit| {
+            let info = unit.info();
+            req.packages
+                .iter()
+                .any(|spec| package_spec_matches(spec, &info.package_name, &info.package_version))
+        })
+        .collect::<Vec<_>>();
+    let mut selected = HashSet::<&UnitHash>::new();
+    while let Some(unit) = pending.pop() {
+        if !selected.insert(&unit.info().unit_hash) {
+            continue;
+        }
+        pending.extend(
+            unit.info()
+                .deps
+                .iter()
+                .filter_map(|dep| by_hash.get(dep).copied()),
+        );
+    }
+    debug!(selected = selected.len(), "selected units to prefetch");
+    if selected.is_empty() {
+        warn!("no units matched the requested packages");
+        return Ok(Some(0));
+    }
+
+    let config = HurryConfig::load(&ws.root).await?;
+    let courier = connections
+        .client(&req.proxy, &req.network, req.courier_url, req.courier_token)?
+        .with_cache_scope(req.cache_scope);
+    let cas = CourierCas::new(courier.clone());
+    let local = LocalCas::open(&config.local_cache).await?;
+    let units = selected
+        .iter()
+        .filter_map(|hash| by_hash.get(hash).copied());
+    prefetch_units(&courier, &cas, &local, units)
+        .await
+        .map(Some)
+}
+
+/// Request to watch a workspace for changes to its toolchain or lockfile,
+/// prefetching the cached artifacts of the new build plan whenever either
+/// changes.
+///
+/// This way the first build after e.g. `rustup update` or merging a lockfile
+/// change doesn't wait on downloads. Registering a workspace again replaces
+/// its previous registration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CargoWatchRequest {
+    pub courier_url: Url,
+    pub courier_token: Token,
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    /// The directory of the workspace to watch.
+    pub root: AbsDirPath,
+
+    /// The directory builds of the workspace are invoked in, which relative
+    /// paths in `argv` are resolved against; defaults to `root`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invoked_in: Option<AbsDirPath>,
+
+    /// The `cargo build` arguments that builds of the workspace use, which
+    /// determine the units to prefetch.
+    pub argv: Vec<String>,
+
+    /// The cache scope that builds of the workspace restore units from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_scope: Option<CacheScope>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CargoWatchResponse {
+    pub ok: bool,
+}
+
+/// Start watching a workspace in the background.
+#[instrument(skip(state, req), fields(root = ?req.root))]
+async fn watch(
+    State(state): State<CargoDaemonState>,
+    Json(req): Json<CargoWatchRequest>,
+) -> Json<CargoWatchResponse> {
+    let cancel = state.shutdown.child_token();
+    if let Some(previous) = state.watched.insert(req.root.clone(), cancel.clone()) {
+        info!("replacing previous watch");
+        previous.cancel();
+    }
+    let span = tracing::info_span!("watch_worker", root = ?req.root);
+    let connections = state.connections.clone();
+    state
+        .tasks
+        .spawn(watch_workspace(connections, req, cancel).instrument(span));
+    Json(CargoWatchResponse { ok: true })
+}
+
+/// The state of a workspace that determines its build plan, other than its
+/// sources.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct WorkspaceDrift {
+    rustc_version: Option<String>,
+    lockfile: Option<Key>,
+}
+
+impl WorkspaceDrift {
+    async fn read(root: &AbsDirPath) -> Self {
+        let lockfile = match fs::read_buffered(&root.join(mk_rel_file!("Cargo.lock"))).await {
+            Ok(content) => content.map(Key::from_buffer),
+            Err(err) => {
+                warn!(?err, "failed to read lockfile");
+                None
+            }
+        };
+        Self {
+            rustc_version: rustc_version(root).await,
+            lockfile,
+        }
+    }
+}
+
+/// Poll a workspace for drift until cancelled, prefetching its units each
+/// time it drifts.
+///
+/// Objects for the workspace's previous state are left in the local CAS:
+/// it's shared by every workspace on the machine, so they may still be used.
+async fn watch_workspace(
+    connections: Connections,
+    req: CargoWatchRequest,
+    cancel: CancellationToken,
+) {
+    let mut last = WorkspaceDrift::read(&req.root).await;
+    debug!(?last, "watching workspace");
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(WATCH_INTERVAL) => {}
+            _ = cancel.cancelled() => {
+                info!("stopped watching workspace");
+                return;
+            }
+        }
+
+        let current = WorkspaceDrift::read(&req.root).await;
+        if current == last {
+            continue;
+        }
+        info!(?last, ?current, "workspace drifted");
+        match prefetch_workspace(&connections, &req).await {
+            Ok(Some(count)) => info!(count, "prefetched drifted workspace"),
+            Ok(None) => {
+                // Try again once the build finishes.
+                debug!("workspace is building, retrying later");
+                continue;
+            }
+            Err(err) => warn!(?err, "failed to prefetch drifted workspace"),
+        }
+        last = current;
+    }
+}
+
+/// Download the objects for every unit of the workspace's build plan into the
+/// local CAS, returning the number of objects downloaded, or `None` if the
+/// workspace is being built.
+async fn prefetch_workspace(
+    connections: &Connections,
+    req: &CargoWatchRequest,
+) -> Result<Option<usize>> {
+    let args = CargoBuildArguments::from_iter(&req.argv);
+    let invoked_in = req.invoked_in.as_ref().unwrap_or(&req.root);
+    let ws = Workspace::from_argv_in_dir(invoked_in, &args).await?;
+
+    // Computing the build plan briefly renames the build directory, which
+    // would break a build that's running, so skip workspaces that are being
+    // built.
+    let Ok(locks) = ws.lock_profile_dirs(LockWait::NoWait).await else {
+        return Ok(None);
+    };
+    let units = ws.units(&args).await?;
+    drop(locks);
+
+    let courier = connections
+        .client(
+            &req.proxy,
+            &req.network,
+            req.courier_url.clone(),
+            req.courier_token.clone(),
+        )?
+        .with_cache_scope(req.cache_scope.clone());
+    let config = HurryConfig::load(&ws.root).await?;
+    let cas = CourierCas::new(courier.clone());
+    let local = LocalCas::open(&config.local_cache).await?;
+    prefetch_units(&courier, &cas, &local, &units)
+        .await
+        .map(Some)
+}
+
+/// Request to prewarm a workspace on a schedule.
+///
+/// Builds of workspaces with a prewarm schedule send this each time, so the
+/// daemon knows which workspaces were built recently and with which
+/// arguments. Registering a workspace again replaces its previous
+/// registration, but keeps when it was last prewarmed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CargoPrewarmRequest {
+    /// The workspace to prewarm, and how to reach Courier for it.
+    #[serde(flatten)]
+    pub workspace: CargoWatchRequest,
+
+    /// When to prewarm the workspace.
+    pub schedule: PrewarmSchedule,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CargoPrewarmResponse {
+    pub ok: bool,
+}
+
+/// A workspace registered for prewarming.
+#[derive(Debug, Clone)]
+struct PrewarmEntry {
+    request: CargoWatchRequest,
+    schedule: PrewarmSchedule,
+
+    /// When the workspace was last built.
+    last_used: jiff::Timestamp,
+
+    /// When the workspace was last prewarmed, or registered if it hasn't
+    /// been prewarmed yet.
+    last_prewarm: jiff::Timestamp,
+}
+
+/// Register a workspace for prewarming.
+#[instrument(skip(state, req), fields(root = ?req.workspace.root))]
+async fn prewarm(
+    State(state): State<CargoDaemonState>,
+    Json(req): Json<CargoPrewarmRequest>,
+) -> Json<CargoPrewarmResponse> {
+    let now = jiff::Timestamp::now();
+    let last_prewarm = state
+        .prewarmed
+        .get(&req.workspace.root)
+        .map(|entry| entry.last_prewarm)
+        .unwrap_or(now);
+    debug!(schedule = ?req.schedule, "registering workspace for prewarming");
+    state.prewarmed.insert(
+        req.workspace.root.clone(),
+        PrewarmEntry {
+            request: req.workspace,
+            schedule: req.schedule,
+            last_used: now,
+            last_prewarm,
+        },
+    );
+    Json(CargoPrewarmResponse { ok: true })
+}
+
+/// Request to open a connection to Courier ahead of an upload.
+///
+/// `hurry cargo build` sends this when the build starts, so that by the time
+/// the build finishes and the upload begins the daemon already has a warm
+/// connection to Courier.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CargoWarmRequest {
+    pub courier_url: Url,
+    pub courier_token: Token,
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CargoWarmResponse {
+    pub ok: bool,
+}
+
+/// Warm the connection pool in the background.
+#[instrument(skip(state))]
+async fn warm(
+    State(state): State<CargoDaemonState>,
+    Json(req): Json<CargoWarmRequest>,
+) -> Json<CargoWarmResponse> {
+    let client =
+        state
+            .connections
+            .client(&req.proxy, &req.network, req.courier_url, req.courier_token);
+    let courier = match client {
+        Ok(courier) => courier,
+        Err(err) => {
+            warn!(?err, "failed to create courier client");
+            return Json(CargoWarmResponse { ok: false });
+        }
+    };
+    let cancel = state.shutdown.child_token();
+    state.tasks.spawn(
+        async move {
+            if let Some(Err(err)) = cancel.run_until_cancelled(courier.warm()).await {
+                warn!(?err, "failed to warm courier connection");
+            }
+        }
+        .in_current_span(),
+    );
+    Json(CargoWarmResponse { ok: true })
+}
+
+/// Request to end a workspace's session, cancelling the uploads and prefetches
+/// it started.
+///
+/// `hurry cargo clean` sends this, since uploads that are still reading the
+/// workspace's target directory can't succeed once it's removed.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CargoSessionEndRequest {
+    pub root: AbsDirPath,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CargoSessionEndResponse {
+    /// Whether the workspace had a session to end.
+    pub ended: bool,
+}
+
+#[instrument(skip(state))]
+async fn end_session(
+    State(state): State<CargoDaemonState>,
+    Json(req): Json<CargoSessionEndRequest>,
+) -> Json<CargoSessionEndResponse> {
+    let ended = state.end_session(&req.root);
+    Json(CargoSessionEndResponse { ended })
+}
+
+/// Request to cancel running uploads.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CargoCancelRequest {
+    /// The upload to cancel. If unset, every running upload is cancelled.
+    pub request_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CargoCancelResponse {
+    /// The uploads that were cancelled. Uploads that had already finished
+    /// aren't included.
+    pub cancelled: Vec<Uuid>,
+}
+
+#[instrument(skip(state))]
+async fn cancel(
+    State(state): State<CargoDaemonState>,
+    Json(req): Json<CargoCancelRequest>,
+) -> Json<CargoCancelResponse> {
+    let cancelled = state.cancel_uploads(req.request_id);
+    Json(CargoCancelResponse { cancelled })
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CargoConnectionsResponse {
+    pub stats: ConnectionStats,
+}
+
+/// Report statistics for the daemon's connections to Courier.
+#[instrument(skip(state))]
+async fn connections(State(state): State<CargoDaemonState>) -> Json<CargoConnectionsResponse> {
+    Json(CargoConnectionsResponse {
+        stats: state.connections.stats(),
+    })
+}
+
+/// Check whether a package matches a spec of the form `name` or
+/// `name@version`.
+fn package_spec_matches(spec: &str, name: &str, version: &str) -> bool {
+    match spec.split_once('@') {
+        Some((spec_name, spec_version)) => spec_name == name && spec_version == version,
+        None => spec == name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, time::Duration};
+
+    use axum::{Json, extract::State};
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+    use uuid::Uuid;
+
+    use tempfile::TempDir;
+
+    use super::{
+        CargoDaemonState, CargoPrefetchRequest, CargoRestoreProgress, CargoUploadProgressResponse,
+        CargoUploadRequest, CargoUploadStatus, Connections, RateTracker, TransferRate,
+        UploadJournal, WorkspaceDrift, merge_upload, package_spec_matches, prefetch_packages,
+        upload,
+    };
+    use crate::{
+        cargo::{
+            CargoBuildArguments, CratePolicy, LibraryCrateUnitPlan, LockWait, Restored,
+            RustcTarget, SaveProgress, UnitHash, UnitPlan, UnitPlanInfo, UnitUploadProgress,
+            UnitUploadState, UploadPolicy, Workspace,
+        },
+        fs, mk_rel_file,
+        path::{AbsDirPath, AbsFilePath, JoinWith as _},
+        progress::TransferBar,
+    };
+
+    fn make_unit_plan(hash: &str) -> UnitPlan {
+        UnitPlan::LibraryCrate(LibraryCrateUnitPlan {
+            info: UnitPlanInfo {
+                unit_hash: hash.into(),
+                package_name: String::from("pkg"),
+                package_version: String::from("1.0.0"),
+                crate_name: String::from("pkg"),
+                target_arch: RustcTarget::ImplicitHost,
+                deps: Vec::new(),
+                policy: CratePolicy::default(),
+                source_hash: None,
+            },
+            src_path: AbsFilePath::try_from("/test/src/lib.rs").unwrap(),
+            outputs: vec![],
+            features: BTreeSet::new(),
+            near_match_key: None,
+            unhashed_outputs: false,
+            fingerprint_hash: None,
+        })
+    }
+
+    /// A deferred upload of `units` from the debug profile of a workspace
+    /// whose build directory is `build_dir`.
+    async fn deferred_upload(build_dir: &TempDir, units: &[&str]) -> CargoUploadRequest {
+        let args =
+            CargoBuildArguments::from_iter(["--target-dir", build_dir.path().to_str().unwrap()]);
+        let ws = Workspace::from_argv_in_dir(&AbsDirPath::current().unwrap(), &args)
+            .await
+            .unwrap();
+        CargoUploadRequest {
+            request_id: Uuid::new_v4(),
+            courier_url: "http://127.0.0.1:1".parse().unwrap(),
+            courier_token: clients::Token::from("unused"),
+            proxy: Default::default(),
+            network: Default::default(),
+            hash_algorithm: Default::default(),
+            buffer_sizes: Default::default(),
+            ws,
+            units: units.iter().map(|hash| make_unit_plan(hash)).collect(),
+            skip: Restored::default(),
+            policy: UploadPolicy::default(),
+            metadata: Default::default(),
+            local_cache: Default::default(),
+            defer: Some(Duration::from_secs(3600)),
+            parallelism: 1,
+            chunking: false,
+            cache_scope: None,
+        }
+    }
+
+    fn unit_hashes(req: &CargoUploadRequest) -> Vec<String> {
+        req.units
+            .iter()
+            .map(|unit| unit.info().unit_hash.to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn merge_upload_dedups_units_and_carries_skip() {
+        let temp = TempDir::new().unwrap();
+        let mut req = deferred_upload(&temp, &["a", "b"]).await;
+        let superseded = deferred_upload(&temp, &["b", "c", "d"]).await;
+        for hash in ["b", "c"] {
+            superseded.skip.units.insert(UnitHash::from(hash));
+        }
+        let key = clients::courier::v1::Key::from_buffer(b"restored");
+        superseded.skip.files.insert(key.clone());
+
+        merge_upload(&mut req, superseded);
+        pretty_assert_eq!(unit_hashes(&req), vec!["a", "b", "c", "d"]);
+
+        // Units that `req` also uploads keep its skip state; the others bring
+        // theirs along.
+        let mut skipped = req
+            .skip
+            .units
+            .iter()
+            .map(|hash| hash.to_string())
+            .collect::<Vec<_>>();
+        skipped.sort();
+        pretty_assert_eq!(skipped, vec!["c"]);
+        assert!(req.skip.files.contains(&key));
+    }
+
+    #[tokio::test]
+    async fn later_upload_supersedes_deferred_upload() {
+        let temp = TempDir::new().unwrap();
+        let state = CargoDaemonState::new().unwrap();
+        let first = deferred_upload(&temp, &["a"]).await;
+        let second = deferred_upload(&temp, &["b"]).await;
+        let (first_id, second_id) = (first.request_id, second.request_id);
+
+        upload(State(state.clone()), Json(first)).await;
+        upload(State(state.clone()), Json(second)).await;
+        pretty_assert_eq!(
+            state
+                .uploads
+                .get(&first_id)
+                .map(|status| status.value().clone()),
+            Some(CargoUploadStatus::Superseded { by: second_id })
+        );
+        assert!(!state.running.contains_key(&first_id));
+        let pending = state
+            .deferred
+            .iter()
+            .map(|entry| (entry.value().request_id, unit_hashes(entry.value())))
+            .collect::<Vec<_>>();
+        pretty_assert_eq!(
+            pending,
+            vec![(second_id, vec![String::from("b"), String::from("a")])]
+        );
+
+        // Once every task has stopped, the superseded upload still hasn't
+        // started: its status would have changed if it had.
+        state.shutdown().await;
+        pretty_assert_eq!(
+            state
+                .uploads
+                .get(&first_id)
+                .map(|status| status.value().clone()),
+            Some(CargoUploadStatus::Superseded { by: second_id })
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_deferred_upload_is_recorded() {
+        let temp = TempDir::new().unwrap();
+        let state = CargoDaemonState::new().unwrap();
+        let req = deferred_upload(&temp, &["a"]).await;
+        let request_id = req.request_id;
+
+        upload(State(state.clone()), Json(req)).await;
+        pretty_assert_eq!(state.cancel_uploads(Some(request_id)), vec![request_id]);
+        state.tasks.close();
+        state.tasks.wait().await;
+
+        pretty_assert_eq!(
+            state
+                .uploads
+                .get(&request_id)
+                .map(|status| status.value().clone()),
+            Some(CargoUploadStatus::Cancelled(Default::default()))
+        );
+        assert!(state.deferred.is_empty());
+        assert!(!state.running.contains_key(&request_id));
+    }
+
+    #[test_case("serde", true; "name")]
+    #[test_case("serde@1.0.228", true; "name and version")]
+    #[test_case("serde@1.0.0", false; "other version")]
+    #[test_case("serde_json", false; "other name")]
+    #[test]
+    fn matches_package_spec(spec: &str, expected: bool) {
+        pretty_assert_eq!(package_spec_matches(spec, "serde", "1.0.228"), expected);
+    }
+
+    #[tokio::test]
+    async fn prefetch_skips_workspace_being_built() {
+        let temp = TempDir::new().unwrap();
+        let root = AbsDirPath::current().unwrap();
+        let argv = vec![
+            String::from("--target-dir"),
+            temp.path().to_string_lossy().into_owned(),
+        ];
+        let ws = Workspace::from_argv_in_dir(&root, &CargoBuildArguments::from_iter(&argv))
+            .await
+            .unwrap();
+        let _locks = ws.lock_profile_dirs(LockWait::NoWait).await.unwrap();
+
+        // The lock is checked before anything is sent, so Courier doesn't need
+        // to be reachable.
+        let req = CargoPrefetchRequest {
+            request_id: Uuid::new_v4(),
+            courier_url: "http://127.0.0.1:1".parse().unwrap(),
+            courier_token: clients::Token::from("unused"),
+            proxy: Default::default(),
+            network: Default::default(),
+            root,
+            invoked_in: None,
+            argv,
+            packages: vec![String::from("serde")],
+            cache_scope: None,
+        };
+        let prefetched = prefetch_packages(&Connections::default(), req)
+            .await
+            .unwrap();
+        pretty_assert_eq!(prefetched, None);
+    }
+
+    #[test]
+    fn restore_progress_from_hidden_bar() {
+        let progress = TransferBar::hidden(10);
+        progress.inc(3);
+        progress.add_files(7);
+        progress.add_bytes(1024);
+        progress.dec_length(2);
+        pretty_assert_eq!(
+            CargoRestoreProgress::from(&progress),
+            CargoRestoreProgress {
+                restored_units: 3,
+                total_units: 8,
+                restored_files: 7,
+                restored_bytes: 1024,
+            }
+        );
+    }
+
+    #[test]
+    fn new_session_cancels_previous() {
+        let state = CargoDaemonState::new().unwrap();
+        let root = AbsDirPath::try_from("/workspace").unwrap();
+        let profile = AbsDirPath::try_from("/workspace/target/debug").unwrap();
+        let other = AbsDirPath::try_from("/other").unwrap();
+        let other_profile = AbsDirPath::try_from("/other/target/debug").unwrap();
+
+        let first = state.begin_session(&root, &profile);
+        let unrelated = state.begin_session(&other, &other_profile);
+        let joined = state.session(&root, &profile);
+        let second = state.begin_session(&root, &profile);
+        assert!(first.is_cancelled());
+        assert!(joined.is_cancelled());
+        assert!(!second.is_cancelled());
+        assert!(!unrelated.is_cancelled());
+
+        pretty_assert_eq!(state.end_session(&root), true);
+        pretty_assert_eq!(state.end_session(&root), false);
+        assert!(second.is_cancelled());
+        assert!(!unrelated.is_cancelled());
+    }
+
+    #[test]
+    fn session_is_per_profile() {
+        let state = CargoDaemonState::new().unwrap();
+        let root = AbsDirPath::try_from("/workspace").unwrap();
+        let debug = AbsDirPath::try_from("/workspace/target/debug").unwrap();
+        let release = AbsDirPath::try_from("/workspace/target/release").unwrap();
+
+        // An upload from a debug build keeps running through a release build.
+        let upload = state.begin_session(&root, &debug).child_token();
+        let release_session = state.begin_session(&root, &release);
+        assert!(!upload.is_cancelled());
+
+        // Another debug build cancels it.
+        let debug_session = state.begin_session(&root, &debug);
+        assert!(upload.is_cancelled());
+
+        // Ending the workspace's sessions ends every profile's.
+        pretty_assert_eq!(state.end_session(&root), true);
+        assert!(release_session.is_cancelled());
+        assert!(debug_session.is_cancelled());
+    }
+
+    #[test]
+    fn rate_tracks_recent_window() {
+        let start = std::time::Instant::now();
+        let mut rate = RateTracker::new(start);
+        pretty_assert_eq!(
+            rate.record(start + Duration::from_secs(2), 2000),
+            TransferRate {
+                average: 1000,
+                recent: 1000,
+            }
+        );
+        rate.record(start + Duration::from_secs(10), 2000);
+
+        // Only the last few seconds count towards the recent rate, so a
+        // stalled upload shows as stalled even if it was fast earlier.
+        pretty_assert_eq!(
+            rate.record(start + Duration::from_secs(20), 2000),
+            TransferRate {
+                average: 100,
+                recent: 0,
+            }
+        );
+        pretty_assert_eq!(
+            rate.record(start + Duration::from_secs(22), 6000),
+            TransferRate {
+                average: 272,
+                recent: 333,
+            }
+        );
+    }
+
+    #[test]
+    fn progress_detail_counts_units() {
+        let unit = |hash: &str, state| UnitUploadProgress {
+            unit_hash: UnitHash::from(hash),
+            package_name: String::from("serde"),
+            state,
+            bytes: 0,
+        };
+        let progress = SaveProgress {
+            units: vec![
+                unit("a", UnitUploadState::Uploading),
+                unit("b", UnitUploadState::Uploading),
+                unit("c", UnitUploadState::Hashing),
+                unit("d", UnitUploadState::Pending),
+                unit("e", UnitUploadState::Done),
+            ],
+            ..Default::default()
+        };
+        let response = CargoUploadProgressResponse {
+            status: Some(CargoUploadStatus::InProgress(progress.clone())),
+            transferred_bytes: 0,
+            rate: TransferRate {
+                average: 0,
+                recent: 2_000_000,
+            },
+        };
+        pretty_assert_eq!(
+            response.detail().as_deref(),
+            Some("2 uploading, 1 hashing, 2 MB/s")
+        );
+
+        let response = CargoUploadProgressResponse {
+            status: Some(CargoUploadStatus::Complete(progress)),
+            ..response
+        };
+        pretty_assert_eq!(response.detail(), None);
+    }
+
+    #[test]
+    fn status_sums_running_uploads() {
+        let state = CargoDaemonState::new().unwrap();
+        let in_progress = SaveProgress {
+            uploaded_units: 2,
+            total_units: 5,
+            queued_bytes: 1024,
+            ..Default::default()
+        };
+        let complete = SaveProgress {
+            uploaded_units: 1,
+            total_units: 4,
+            queued_bytes: 512,
+            ..Default::default()
+        };
+        let running = Uuid::new_v4();
+        state
+            .uploads
+            .insert(running, CargoUploadStatus::InProgress(in_progress.clone()));
+        state
+            .uploads
+            .insert(Uuid::new_v4(), CargoUploadStatus::InProgress(in_progress));
+        state
+            .uploads
+            .insert(Uuid::new_v4(), CargoUploadStatus::Complete(complete));
+        state.running.insert(running, state.shutdown.child_token());
+        state.record_error("upload", &color_eyre::eyre::eyre!("connection reset"));
+
+        let status = state.status();
+        pretty_assert_eq!(status.uploads_in_flight, 1);
+        pretty_assert_eq!(status.queued_units, 6);
+        pretty_assert_eq!(status.queued_bytes, 2048);
+        pretty_assert_eq!(
+            status.last_error.map(|error| error.message),
+            Some(String::from("upload failed: connection reset"))
+        );
+    }
+
+    #[test]
+    fn cancel_uploads_by_id() {
+        let state = CargoDaemonState::new().unwrap();
+        let root = AbsDirPath::try_from("/workspace").unwrap();
+        let profile = AbsDirPath::try_from("/workspace/target/debug").unwrap();
+        let session = state.begin_session(&root, &profile);
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let first_token = session.child_token();
+        let second_token = session.child_token();
+        state.running.insert(first, first_token.clone());
+        state.running.insert(second, second_token.clone());
+
+        pretty_assert_eq!(state.cancel_uploads(Some(first)), vec![first]);
+        pretty_assert_eq!(state.cancel_uploads(Some(first)), Vec::<Uuid>::new());
+        assert!(first_token.is_cancelled());
+        assert!(!second_token.is_cancelled());
+        assert!(!session.is_cancelled());
+
+        pretty_assert_eq!(state.cancel_uploads(None), vec![second]);
+        assert!(second_token.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn shutdown_cancels_sessions() {
+        let state = CargoDaemonState::new().unwrap();
+        let root = AbsDirPath::try_from("/workspace").unwrap();
+        let profile = AbsDirPath::try_from("/workspace/target/debug").unwrap();
+        let session = state.session(&root, &profile);
+        state.tasks.spawn({
+            let session = session.clone();
+            async move { session.cancelled().await }
+        });
+
+        state.shutdown().await;
+        assert!(session.is_cancelled());
+        assert!(state.tasks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn drain_waits_for_running_uploads() {
+        let state = CargoDaemonState::new().unwrap();
+        let request_id = Uuid::new_v4();
+        state
+            .running
+            .insert(request_id, state.shutdown.child_token());
+        pretty_assert_eq!(state.drain(Duration::ZERO).await, 1);
+
+        tokio::spawn({
+            let state = state.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                state.running.remove(&request_id);
+            }
+        });
+        pretty_assert_eq!(state.drain(Duration::from_secs(60)).await, 0);
+    }
+
+    #[tokio::test]
+    async fn journal_counts_failures() {
+        let temp = TempDir::new().unwrap();
+        let dir = AbsDirPath::try_from(temp.path().to_path_buf()).unwrap();
+        let journal = UploadJournal { dir };
+        let request_id = Uuid::new_v4();
+        pretty_assert_eq!(journal.record_failure(request_id).await.unwrap(), None);
+
+        let entry = serde_json::json!({ "request_id": request_id });
+        journal
+            .write(request_id, &serde_json::to_vec(&entry).unwrap())
+            .await
+            .unwrap();
+        pretty_assert_eq!(journal.record_failure(request_id).await.unwrap(), Some(1));
+        pretty_assert_eq!(journal.record_failure(request_id).await.unwrap(), Some(2));
+
+        let content = fs::read_buffered(&journal.path(request_id).unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        pretty_assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&content).unwrap(),
+            serde_json::json!({ "request_id": request_id, "failures": 2 })
+        );
+    }
+
+    #[tokio::test]
+    async fn lockfile_changes_are_drift() {
+        let temp = TempDir::new().unwrap();
+        let root = AbsDirPath::try_from(temp.path().to_path_buf()).unwrap();
+        let lockfile = root.join(mk_rel_file!("Cargo.lock"));
+
+        let missing = WorkspaceDrift::read(&root).await;
+        fs::write(&lockfile, "version = 4\n").await.unwrap();
+        let written = WorkspaceDrift::read(&root).await;
+        pretty_assert_eq!(WorkspaceDrift::read(&root).await, written);
+        fs::write(&lockfile, "version = 4\n\n[[package]]\nname = \"serde\"\n")
+            .await
+            .unwrap();
+        let changed = WorkspaceDrift::read(&root).await;
+
+        assert_ne!(missing, written);
+        assert_ne!(written, changed);
+    }
+}
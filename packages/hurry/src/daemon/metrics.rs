@@ -0,0 +1,493 @@
+//! Runtime metrics for the daemon, in the Prometheus text format.
+//!
+//! The daemon serves these at `/api/v0/metrics` so that local dashboards can
+//! scrape them while developing Hurry or tuning a machine's configuration.
+//! Counters start at zero when the daemon starts; gauges are read from the
+//! daemon's state when the metrics are rendered.
+//!
+//! `hurry` invocations can also report how long they took to the daemon (see
+//! [`InvocationReport`]), so that builds show up next to the restores and
+//! uploads they started.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{Display as FmtDisplay, Write as _},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use clients::courier::v1::ConnectionStats;
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+use crate::{cargo::NondeterministicUnit, daemon::DaemonStatus};
+
+/// The upper bounds of the latency histogram buckets, in seconds.
+///
+/// Restores range from a few milliseconds when everything is fresh to
+/// minutes for a cold cache, and builds from seconds to hours.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0, 1800.0,
+];
+
+/// The most distinct commands that invocation metrics are kept for. Cargo
+/// subcommands are arbitrary, so without a limit a script that runs many of
+/// them would grow the metrics without bound.
+const MAX_INVOCATION_COMMANDS: usize = 64;
+
+/// The command that invocations are counted under once
+/// [`MAX_INVOCATION_COMMANDS`] is reached.
+const OTHER_COMMAND: &str = "other";
+
+/// How a restore, upload, or invocation ended.
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Outcome {
+    #[display("complete")]
+    Complete,
+    #[display("failed")]
+    Failed,
+    #[display("cancelled")]
+    Cancelled,
+}
+
+/// A report of a `hurry` invocation, sent to the daemon when the CLI is run
+/// with `--push-metrics`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct InvocationReport {
+    /// The command that was run, e.g. `cargo build`.
+    pub command: String,
+
+    /// How long the invocation took.
+    pub duration: Duration,
+
+    /// Whether the invocation succeeded.
+    pub success: bool,
+}
+
+/// Metrics recorded by the daemon as it works.
+///
+/// ## Cloning
+///
+/// This type is cheaply cloneable, and clones record to the same metrics.
+#[derive(Debug, Clone, Default)]
+pub struct DaemonMetrics(Arc<Mutex<Recorded>>);
+
+#[derive(Debug, Default)]
+struct Recorded {
+    restores: BTreeMap<Outcome, u64>,
+    restore_durations: Histogram,
+    restored_units: u64,
+    restored_bytes: u64,
+    uploads: BTreeMap<Outcome, u64>,
+    uploaded_units: u64,
+    uploaded_bytes: u64,
+    nondeterministic_units: BTreeMap<&'static str, u64>,
+    errors: BTreeMap<&'static str, u64>,
+    invocations: BTreeMap<(String, Outcome), u64>,
+    invocation_durations: BTreeMap<String, Histogram>,
+}
+
+impl DaemonMetrics {
+    /// Record a restore that ended.
+    pub fn record_restore(&self, outcome: Outcome, duration: Duration, units: u64, bytes: u64) {
+        let mut recorded = self.0.lock().expect("mutex is poisoned");
+        *recorded.restores.entry(outcome).or_default() += 1;
+        recorded.restore_durations.observe(duration);
+        recorded.restored_units += units;
+        recorded.restored_bytes += bytes;
+    }
+
+    /// Record an upload that ended.
+    pub fn record_upload(&self, outcome: Outcome, units: u64, bytes: u64) {
+        let mut recorded = self.0.lock().expect("mutex is poisoned");
+        *recorded.uploads.entry(outcome).or_default() += 1;
+        recorded.uploaded_units += units;
+        recorded.uploaded_bytes += bytes;
+    }
+
+    /// Record the units an upload found to differ from the cache.
+    pub fn record_nondeterministic(&self, units: &[NondeterministicUnit]) {
+        let mut recorded = self.0.lock().expect("mutex is poisoned");
+        for unit in units {
+            let action = if unit.refused { "refused" } else { "uploaded" };
+            *recorded.nondeterministic_units.entry(action).or_default() += 1;
+        }
+    }
+
+    /// Record an error from work the daemon ran in the background.
+    pub fn record_error(&self, operation: &'static str) {
+        let mut recorded = self.0.lock().expect("mutex is poisoned");
+        *recorded.errors.entry(operation).or_default() += 1;
+    }
+
+    /// Record an invocation reported by the CLI.
+    pub fn record_invocation(&self, report: &InvocationReport) {
+        let outcome = if report.success {
+            Outcome::Complete
+        } else {
+            Outcome::Failed
+        };
+        let mut recorded = self.0.lock().expect("mutex is poisoned");
+        let command = if recorded.invocation_durations.contains_key(&report.command)
+            || recorded.invocation_durations.len() < MAX_INVOCATION_COMMANDS
+        {
+            report.command.clone()
+        } else {
+            String::from(OTHER_COMMAND)
+        };
+        *recorded
+            .invocations
+            .entry((command.clone(), outcome))
+            .or_default() += 1;
+        recorded
+            .invocation_durations
+            .entry(command)
+            .or_default()
+            .observe(report.duration);
+    }
+
+    /// Render the metrics in the Prometheus text format, along with gauges
+    /// read from the daemon's current status and connections.
+    pub fn render(&self, status: &DaemonStatus, connections: &ConnectionStats) -> String {
+        let recorded = self.0.lock().expect("mutex is poisoned");
+        let mut out = Exposition::default();
+
+        out.gauge(
+            "hurryd_uptime_seconds",
+            "How long the daemon has been running.",
+            status.uptime.as_secs_f64(),
+        );
+        out.gauge(
+            "hurryd_uploads_in_flight",
+            "Uploads that haven't finished, including deferred uploads.",
+            status.uploads_in_flight,
+        );
+        out.gauge(
+            "hurryd_uploads_deferred",
+            "Uploads that are deferred and haven't started yet.",
+            status.uploads_deferred,
+        );
+        out.gauge(
+            "hurryd_upload_queue_units",
+            "Units that running uploads haven't uploaded yet.",
+            status.queued_units,
+        );
+        out.gauge(
+            "hurryd_upload_queue_bytes",
+            "Bytes that running uploads have read and haven't uploaded yet.",
+            status.queued_bytes,
+        );
+        out.gauge(
+            "hurryd_watched_workspaces",
+            "Workspaces the daemon is watching.",
+            status.watched_workspaces,
+        );
+        out.counter(
+            "hurryd_courier_connections_opened_total",
+            "Connections opened to Courier.",
+            connections.connections_opened,
+        );
+
+        out.labeled_counter(
+            "hurryd_restores_total",
+            "Restores that ended, by outcome.",
+            recorded
+                .restores
+                .iter()
+                .map(|(outcome, count)| (vec![("outcome", outcome.to_string())], *count)),
+        );
+        out.histogram(
+            "hurryd_restore_duration_seconds",
+            "How long restores took.",
+            &[],
+            &recorded.restore_durations,
+        );
+        out.counter(
+            "hurryd_restored_units_total",
+            "Units restored from the cache.",
+            recorded.restored_units,
+        );
+        out.counter(
+            "hurryd_restored_bytes_total",
+            "Bytes restored from the cache.",
+            recorded.restored_bytes,
+        );
+
+        out.labeled_counter(
+            "hurryd_uploads_total",
+            "Uploads that ended, by outcome.",
+            recorded
+                .uploads
+                .iter()
+                .map(|(outcome, count)| (vec![("outcome", outcome.to_string())], *count)),
+        );
+        out.counter(
+            "hurryd_uploaded_units_total",
+            "Units uploaded to the cache.",
+            recorded.uploaded_units,
+        );
+        out.counter(
+            "hurryd_uploaded_bytes_total",
+            "Bytes uploaded to the cache.",
+            recorded.uploaded_bytes,
+        );
+        out.labeled_counter(
+            "hurryd_nondeterministic_units_total",
+            "Units whose content differed from the cached copy, by whether they were uploaded.",
+            recorded
+                .nondeterministic_units
+                .iter()
+                .map(|(action, count)| (vec![("action", action.to_string())], *count)),
+        );
+
+        out.labeled_counter(
+            "hurryd_errors_total",
+            "Errors from work the daemon ran in the background, by operation.",
+            recorded
+                .errors
+                .iter()
+                .map(|(operation, count)| (vec![("operation", operation.to_string())], *count)),
+        );
+
+        out.labeled_counter(
+            "hurry_invocations_total",
+            "Invocations of hurry reported to the daemon, by command and outcome.",
+            recorded
+                .invocations
+                .iter()
+                .map(|((command, outcome), count)| {
+                    let labels = vec![
+                        ("command", command.clone()),
+                        ("outcome", outcome.to_string()),
+                    ];
+                    (labels, *count)
+                }),
+        );
+        out.header(
+            "hurry_invocation_duration_seconds",
+            "How long invocations of hurry reported to the daemon took.",
+            "histogram",
+        );
+        for (command, durations) in &recorded.invocation_durations {
+            out.histogram_samples(
+                "hurry_invocation_duration_seconds",
+                &[("command", command.clone())],
+                durations,
+            );
+        }
+
+        out.0
+    }
+}
+
+/// A histogram of durations, with [`LATENCY_BUCKETS`].
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Histogram {
+    /// The number of observations in each bucket, not cumulative.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| secs <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+/// Metrics being rendered in the Prometheus text format.
+#[derive(Debug, Default)]
+struct Exposition(String);
+
+impl Exposition {
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        writeln!(self.0, "# HELP {name} {help}").expect("write to string");
+        writeln!(self.0, "# TYPE {name} {kind}").expect("write to string");
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, String)], value: impl FmtDisplay) {
+        write!(self.0, "{name}").expect("write to string");
+        if !labels.is_empty() {
+            let labels = labels
+                .iter()
+                .map(|(key, value)| format!("{key}=\"{}\"", escape_label(value)))
+                .collect::<Vec<_>>()
+                .join(",");
+            write!(self.0, "{{{labels}}}").expect("write to string");
+        }
+        writeln!(self.0, " {value}").expect("write to string");
+    }
+
+    fn gauge(&mut self, name: &str, help: &str, value: impl FmtDisplay) {
+        self.header(name, help, "gauge");
+        self.sample(name, &[], value);
+    }
+
+    fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.header(name, help, "counter");
+        self.sample(name, &[], value);
+    }
+
+    fn labeled_counter<'a>(
+        &mut self,
+        name: &str,
+        help: &str,
+        samples: impl IntoIterator<Item = (Vec<(&'a str, String)>, u64)>,
+    ) {
+        self.header(name, help, "counter");
+        for (labels, value) in samples {
+            self.sample(name, &labels, value);
+        }
+    }
+
+    fn histogram(
+        &mut self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, String)],
+        histogram: &Histogram,
+    ) {
+        self.header(name, help, "histogram");
+        self.histogram_samples(name, labels, histogram);
+    }
+
+    fn histogram_samples(&mut self, name: &str, labels: &[(&str, String)], histogram: &Histogram) {
+        let bucket = format!("{name}_bucket");
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let mut labels = labels.to_vec();
+            labels.push(("le", bound.to_string()));
+            self.sample(&bucket, &labels, cumulative);
+        }
+        let mut all = labels.to_vec();
+        all.push(("le", String::from("+Inf")));
+        self.sample(&bucket, &all, histogram.count);
+        self.sample(&format!("{name}_sum"), labels, histogram.sum);
+        self.sample(&format!("{name}_count"), labels, histogram.count);
+    }
+}
+
+/// Escape a label value for the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use clients::courier::v1::ConnectionStats;
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::{DaemonMetrics, Histogram, InvocationReport, Outcome, escape_label};
+    use crate::{
+        cargo::{NondeterministicUnit, UnitHash},
+        daemon::DaemonStatus,
+    };
+
+    fn status() -> DaemonStatus {
+        DaemonStatus {
+            pid: 1,
+            version: String::from("test"),
+            uptime: Duration::from_secs(60),
+            uploads_in_flight: 2,
+            uploads_deferred: 1,
+            queued_units: 10,
+            queued_bytes: 4096,
+            watched_workspaces: 0,
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_millis(10));
+        histogram.observe(Duration::from_millis(300));
+        histogram.observe(Duration::from_secs(3600));
+        pretty_assert_eq!(histogram.count, 3);
+        pretty_assert_eq!(histogram.buckets[0], 1);
+        pretty_assert_eq!(histogram.buckets[3], 1);
+        pretty_assert_eq!(histogram.buckets.iter().sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn renders_metrics() {
+        let metrics = DaemonMetrics::default();
+        metrics.record_restore(Outcome::Complete, Duration::from_millis(200), 3, 1024);
+        metrics.record_upload(Outcome::Failed, 1, 512);
+        metrics.record_nondeterministic(&[
+            NondeterministicUnit {
+                unit_hash: UnitHash::from("a"),
+                package_name: String::from("a"),
+                refused: true,
+            },
+            NondeterministicUnit {
+                unit_hash: UnitHash::from("b"),
+                package_name: String::from("b"),
+                refused: false,
+            },
+        ]);
+        metrics.record_error("upload");
+        metrics.record_invocation(&InvocationReport {
+            command: String::from("cargo build"),
+            duration: Duration::from_secs(20),
+            success: true,
+        });
+
+        let rendered = metrics.render(&status(), &ConnectionStats::default());
+        for line in [
+            "# TYPE hurryd_uploads_in_flight gauge",
+            "hurryd_uploads_in_flight 2",
+            "hurryd_upload_queue_bytes 4096",
+            "hurryd_restores_total{outcome=\"complete\"} 1",
+            "hurryd_restore_duration_seconds_bucket{le=\"0.1\"} 0",
+            "hurryd_restore_duration_seconds_bucket{le=\"0.25\"} 1",
+            "hurryd_restore_duration_seconds_bucket{le=\"+Inf\"} 1",
+            "hurryd_restore_duration_seconds_count 1",
+            "hurryd_restored_bytes_total 1024",
+            "hurryd_uploads_total{outcome=\"failed\"} 1",
+            "hurryd_nondeterministic_units_total{action=\"refused\"} 1",
+            "hurryd_nondeterministic_units_total{action=\"uploaded\"} 1",
+            "hurryd_errors_total{operation=\"upload\"} 1",
+            "hurry_invocations_total{command=\"cargo build\",outcome=\"complete\"} 1",
+            "hurry_invocation_duration_seconds_bucket{command=\"cargo build\",le=\"30\"} 1",
+            "hurry_invocation_duration_seconds_sum{command=\"cargo build\"} 20",
+        ] {
+            assert!(
+                rendered.lines().any(|rendered| rendered == line),
+                "missing {line:?} in:\n{rendered}"
+            );
+        }
+    }
+
+    #[test]
+    fn limits_invocation_commands() {
+        let metrics = DaemonMetrics::default();
+        for n in 0..100 {
+            metrics.record_invocation(&InvocationReport {
+                command: format!("cargo custom-{n}"),
+                duration: Duration::from_secs(1),
+                success: false,
+            });
+        }
+        let rendered = metrics.render(&status(), &ConnectionStats::default());
+        assert!(
+            rendered.contains("hurry_invocations_total{command=\"other\",outcome=\"failed\"} 36")
+        );
+    }
+
+    #[test]
+    fn escapes_labels() {
+        pretty_assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
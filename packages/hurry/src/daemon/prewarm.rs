@@ -0,0 +1,310 @@
+//! Scheduled cache prewarming.
+//!
+//! Developers tend to open the same workspaces every day. Builds of
+//! workspaces with a `[prewarm]` schedule in `hurry.toml` register the
+//! workspace with the daemon, which then prefetches the artifacts of the
+//! workspace's last build into the local CAS whenever the schedule is due:
+//! at a time of day on some days of the week, and/or once the daemon has been
+//! idle for a while.
+
+use std::time::Duration;
+
+use color_eyre::{
+    Result,
+    eyre::{Context as _, bail, eyre},
+};
+use derive_more::Display;
+use jiff::{Span, Timestamp, Zoned, civil::Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::config::PrewarmConfig;
+
+/// A day of the week.
+#[derive(Debug, Display, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Day {
+    #[display("mon")]
+    Mon,
+    #[display("tue")]
+    Tue,
+    #[display("wed")]
+    Wed,
+    #[display("thu")]
+    Thu,
+    #[display("fri")]
+    Fri,
+    #[display("sat")]
+    Sat,
+    #[display("sun")]
+    Sun,
+}
+
+impl Day {
+    /// Monday to Friday.
+    pub const WEEKDAYS: [Self; 5] = [Self::Mon, Self::Tue, Self::Wed, Self::Thu, Self::Fri];
+
+    fn parse(day: &str) -> Result<Self> {
+        Ok(match day.trim().to_ascii_lowercase().as_str() {
+            "mon" | "monday" => Self::Mon,
+            "tue" | "tuesday" => Self::Tue,
+            "wed" | "wednesday" => Self::Wed,
+            "thu" | "thursday" => Self::Thu,
+            "fri" | "friday" => Self::Fri,
+            "sat" | "saturday" => Self::Sat,
+            "sun" | "sunday" => Self::Sun,
+            other => bail!("unknown day: {other:?}"),
+        })
+    }
+
+    fn from_weekday(weekday: Weekday) -> Self {
+        match weekday {
+            Weekday::Monday => Self::Mon,
+            Weekday::Tuesday => Self::Tue,
+            Weekday::Wednesday => Self::Wed,
+            Weekday::Thursday => Self::Thu,
+            Weekday::Friday => Self::Fri,
+            Weekday::Saturday => Self::Sat,
+            Weekday::Sunday => Self::Sun,
+        }
+    }
+}
+
+/// A local time of day.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TimeOfDay {
+    pub hour: i8,
+    pub minute: i8,
+}
+
+impl TimeOfDay {
+    /// Parse a time of day written as `HH:MM`.
+    fn parse(time: &str) -> Result<Self> {
+        let (hour, minute) = time
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| eyre!("expected HH:MM, got {time:?}"))?;
+        let hour = hour.parse::<i8>().context("parse hour")?;
+        let minute = minute.parse::<i8>().context("parse minute")?;
+        if !(0..24).contains(&hour) || !(0..60).contains(&minute) {
+            bail!("invalid time of day: {time:?}");
+        }
+        Ok(Self { hour, minute })
+    }
+}
+
+/// Why a workspace is prewarmed.
+#[derive(Debug, Display, Clone, Copy, Eq, PartialEq)]
+pub enum PrewarmReason {
+    #[display("scheduled")]
+    Scheduled,
+
+    #[display("idle")]
+    Idle,
+}
+
+/// When a workspace is prewarmed.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct PrewarmSchedule {
+    /// The local time to prewarm at, if any.
+    pub at: Option<TimeOfDay>,
+
+    /// The days to prewarm on at `at`.
+    pub days: Vec<Day>,
+
+    /// How long the daemon must be idle before prewarming, if it prewarms
+    /// when idle.
+    pub idle: Option<Duration>,
+}
+
+impl PrewarmSchedule {
+    /// Parse the schedule set in `hurry.toml`, returning `None` if the
+    /// workspace isn't prewarmed.
+    pub fn from_config(config: &PrewarmConfig) -> Result<Option<Self>> {
+        let at = config
+            .at
+            .as_deref()
+            .map(TimeOfDay::parse)
+            .transpose()
+            .context("parse prewarm.at")?;
+        let idle = config
+            .idle_minutes
+            .filter(|&minutes| minutes > 0)
+            .map(|minutes| Duration::from_secs(minutes * 60));
+        if at.is_none() && idle.is_none() {
+            return Ok(None);
+        }
+        let mut days = config
+            .days
+            .iter()
+            .map(|day| Day::parse(day))
+            .collect::<Result<Vec<_>>>()
+            .context("parse prewarm.days")?;
+        if days.is_empty() {
+            days = Day::WEEKDAYS.to_vec();
+        }
+        Ok(Some(Self { at, days, idle }))
+    }
+
+    /// The most recent scheduled time at or before `now`, if prewarming is
+    /// scheduled at a time of day.
+    pub fn last_slot(&self, now: &Zoned) -> Option<Timestamp> {
+        let at = self.at?;
+        (0..=7).find_map(|days_ago| {
+            let date = now.date().checked_sub(Span::new().days(days_ago)).ok()?;
+            if !self.days.contains(&Day::from_weekday(date.weekday())) {
+                return None;
+            }
+            let slot = date
+                .at(at.hour, at.minute, 0, 0)
+                .to_zoned(now.time_zone().clone())
+                .ok()?
+                .timestamp();
+            (slot <= now.timestamp()).then_some(slot)
+        })
+    }
+
+    /// Whether a workspace that was last prewarmed (or registered) at
+    /// `last_prewarm` is due to be prewarmed at `now`.
+    ///
+    /// `idle_since` is when the daemon last did any work, or `None` if it's
+    /// busy. Idle prewarming runs at most once per idle period.
+    pub fn due(
+        &self,
+        now: &Zoned,
+        last_prewarm: Timestamp,
+        idle_since: Option<Timestamp>,
+    ) -> Option<PrewarmReason> {
+        if let Some(slot) = self.last_slot(now)
+            && last_prewarm < slot
+        {
+            return Some(PrewarmReason::Scheduled);
+        }
+        if let (Some(idle), Some(idle_since)) = (self.idle, idle_since)
+            && last_prewarm < idle_since
+            && now.timestamp().duration_since(idle_since).unsigned_abs() >= idle
+        {
+            return Some(PrewarmReason::Idle);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use jiff::{Timestamp, Zoned, civil::date, tz::TimeZone};
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
+    use super::{Day, PrewarmReason, PrewarmSchedule, TimeOfDay};
+    use crate::config::PrewarmConfig;
+
+    fn at(year: i16, month: i8, day: i8, hour: i8, minute: i8) -> Zoned {
+        date(year, month, day)
+            .at(hour, minute, 0, 0)
+            .to_zoned(TimeZone::UTC)
+            .unwrap()
+    }
+
+    fn morning() -> PrewarmSchedule {
+        PrewarmSchedule {
+            at: Some(TimeOfDay { hour: 8, minute: 0 }),
+            days: Day::WEEKDAYS.to_vec(),
+            idle: None,
+        }
+    }
+
+    #[test]
+    fn parses_config() {
+        pretty_assert_eq!(
+            PrewarmSchedule::from_config(&PrewarmConfig::default()).unwrap(),
+            None
+        );
+        let config = PrewarmConfig {
+            at: Some(String::from("08:30")),
+            days: vec![String::from("mon"), String::from("Friday")],
+            idle_minutes: Some(15),
+        };
+        pretty_assert_eq!(
+            PrewarmSchedule::from_config(&config).unwrap(),
+            Some(PrewarmSchedule {
+                at: Some(TimeOfDay {
+                    hour: 8,
+                    minute: 30
+                }),
+                days: vec![Day::Mon, Day::Fri],
+                idle: Some(Duration::from_secs(15 * 60)),
+            })
+        );
+
+        let invalid = PrewarmConfig {
+            at: Some(String::from("25:00")),
+            ..Default::default()
+        };
+        assert!(PrewarmSchedule::from_config(&invalid).is_err());
+    }
+
+    #[test]
+    fn finds_last_slot() {
+        let schedule = morning();
+        // 2026-10-12 is a Monday.
+        pretty_assert_eq!(
+            schedule.last_slot(&at(2026, 10, 14, 9, 0)),
+            Some(at(2026, 10, 14, 8, 0).timestamp())
+        );
+        pretty_assert_eq!(
+            schedule.last_slot(&at(2026, 10, 14, 7, 0)),
+            Some(at(2026, 10, 13, 8, 0).timestamp())
+        );
+        pretty_assert_eq!(
+            schedule.last_slot(&at(2026, 10, 18, 12, 0)),
+            Some(at(2026, 10, 16, 8, 0).timestamp())
+        );
+    }
+
+    #[test]
+    fn due_once_per_slot() {
+        let schedule = morning();
+        let now = at(2026, 10, 14, 8, 5);
+        let yesterday = at(2026, 10, 13, 17, 0).timestamp();
+        pretty_assert_eq!(
+            schedule.due(&now, yesterday, None),
+            Some(PrewarmReason::Scheduled)
+        );
+        pretty_assert_eq!(schedule.due(&now, now.timestamp(), None), None);
+    }
+
+    #[test]
+    fn due_once_per_idle_period() {
+        let schedule = PrewarmSchedule {
+            at: None,
+            days: Day::WEEKDAYS.to_vec(),
+            idle: Some(Duration::from_secs(30 * 60)),
+        };
+        let now = at(2026, 10, 14, 12, 0);
+        let idle_since = at(2026, 10, 14, 11, 0).timestamp();
+        let before = at(2026, 10, 14, 10, 0).timestamp();
+        pretty_assert_eq!(
+            schedule.due(&now, before, Some(idle_since)),
+            Some(PrewarmReason::Idle)
+        );
+        pretty_assert_eq!(schedule.due(&now, now.timestamp(), Some(idle_since)), None);
+        pretty_assert_eq!(schedule.due(&now, before, None), None);
+
+        let recently = at(2026, 10, 14, 11, 50).timestamp();
+        pretty_assert_eq!(schedule.due(&now, before, Some(recently)), None);
+    }
+
+    #[test]
+    fn last_slot_needs_time() {
+        let schedule = PrewarmSchedule {
+            at: None,
+            days: Day::WEEKDAYS.to_vec(),
+            idle: None,
+        };
+        pretty_assert_eq!(
+            schedule.last_slot(&Timestamp::UNIX_EPOCH.to_zoned(TimeZone::UTC)),
+            None
+        );
+    }
+}
@@ -25,7 +25,13 @@
 )]
 
 use std::{
-    convert::identity, fmt::Debug as StdDebug, marker::PhantomData, sync::Arc, time::SystemTime,
+    borrow::Cow,
+    convert::identity,
+    fmt::Debug as StdDebug,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use bon::Builder;
@@ -38,14 +44,68 @@ use filetime::FileTime;
 use fslock::LockFile as FsLockFile;
 use futures::{Stream, TryStreamExt};
 use jiff::Timestamp;
+use rayon::iter::{IntoParallelRefIterator as _, ParallelIterator as _};
 use serde::{Deserialize, Serialize};
 use tap::{Pipe, TapFallible, TryConv as _};
 use tokio::{fs::ReadDir, io::AsyncReadExt, sync::Mutex, task::spawn_blocking};
 use tracing::{debug, error, instrument, trace};
+use uuid::Uuid;
 
 use clients::courier::v1::Key;
 
-use crate::path::{Abs, AbsDirPath, AbsFilePath, JoinWith, RelativeTo, TypedPath};
+use crate::path::{
+    Abs, AbsDirPath, AbsFilePath, JoinWith, RelativeTo, TryJoinWith as _, TypedPath,
+};
+
+/// Windows paths longer than this many characters must be in extended-length
+/// form.
+const WINDOWS_MAX_PATH: usize = 260;
+
+/// Convert the path into the form that is passed to the operating system.
+///
+/// On Windows, paths longer than `MAX_PATH` can only be used in extended-length
+/// (`\\?\`) form[^1], so long absolute paths are converted to that form. On
+/// other platforms, paths are passed through unchanged.
+///
+/// [^1]: https://learn.microsoft.com/en-us/windows/win32/fileio/maximum-file-path-limitation
+fn os_path(path: &Path) -> Cow<'_, Path> {
+    if cfg!(windows)
+        && path.as_os_str().len() >= WINDOWS_MAX_PATH
+        && let Some(extended) = path.to_str().and_then(windows_extended_length_path)
+    {
+        return Cow::Owned(PathBuf::from(extended));
+    }
+    Cow::Borrowed(path)
+}
+
+/// Convert an absolute Windows path into extended-length form.
+///
+/// Returns `None` if the path is already in extended-length or device form,
+/// is not absolute, or contains `.` or `..` components (which the OS does not
+/// resolve for extended-length paths).
+fn windows_extended_length_path(path: &str) -> Option<String> {
+    if path.starts_with(r"\\?\") || path.starts_with(r"\\.\") {
+        return None;
+    }
+    let path = path.replace('/', r"\");
+    if path
+        .split('\\')
+        .any(|component| component == "." || component == "..")
+    {
+        return None;
+    }
+
+    if let Some(unc) = path.strip_prefix(r"\\") {
+        return Some(format!(r"\\?\UNC\{unc}"));
+    }
+    let mut chars = path.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(drive), Some(':'), Some('\\')) if drive.is_ascii_alphabetic() => {
+            Some(format!(r"\\?\{path}"))
+        }
+        _ => None,
+    }
+}
 
 /// The default level of concurrency used in hurry `fs` operations.
 ///
@@ -79,11 +139,12 @@ impl LockFile<Unlocked> {
     /// Create a new instance at the provided path.
     pub async fn open(path: impl Into<AbsFilePath> + StdDebug) -> Result<Self> {
         let path = path.into();
-        let (file, path) =
-            spawn_blocking(move || FsLockFile::open(path.as_std_path()).map(|file| (file, path)))
-                .await
-                .context("join task")?
-                .context("open lock file")?;
+        let (file, path) = spawn_blocking(move || {
+            FsLockFile::open(&*os_path(path.as_std_path())).map(|file| (file, path))
+        })
+        .await
+        .context("join task")?
+        .context("open lock file")?;
         Ok(Self {
             state: PhantomData,
             inner: Arc::new(Mutex::new(file)),
@@ -112,6 +173,31 @@ impl LockFile<Unlocked> {
         .context("join task")?
         .tap_ok(|f| trace!(path = ?f.path, "locked file"))
     }
+
+    /// Lock the lockfile if no other process holds it, returning the unlocked
+    /// instance otherwise.
+    #[instrument(skip_all, fields(%self))]
+    pub async fn try_lock(self) -> Result<Result<LockFile<Locked>, Self>> {
+        spawn_blocking(move || {
+            let locked = {
+                let mut inner = self.inner.blocking_lock();
+                inner.try_lock().context("lock file")?
+            };
+            Ok(if locked {
+                trace!(path = ?self.path, "locked file");
+                Ok(LockFile {
+                    state: PhantomData,
+                    inner: self.inner,
+                    path: self.path,
+                })
+            } else {
+                trace!(path = ?self.path, "file is locked by another process");
+                Err(self)
+            })
+        })
+        .await
+        .context("join task")?
+    }
 }
 
 impl LockFile<Locked> {
@@ -181,7 +267,7 @@ pub async fn user_global_cache_path() -> Result<AbsDirPath> {
 /// Create the directory and all its parents, if they don't already exist.
 #[instrument]
 pub async fn create_dir_all(dir: &AbsDirPath) -> Result<()> {
-    tokio::fs::create_dir_all(dir.as_std_path())
+    tokio::fs::create_dir_all(os_path(dir.as_std_path()))
         .await
         .with_context(|| format!("create dir: {dir:?}"))
         .tap_ok(|_| trace!(?dir, "create directory"))
@@ -246,6 +332,79 @@ pub fn walk_files(root: &AbsDirPath) -> impl Stream<Item = Result<AbsFilePath>>
     rx.into_stream().pipe(Box::pin)
 }
 
+/// The name of the ignore file that overrides other ignore rules when walking
+/// source files with [`walk_source_files`].
+///
+/// It uses gitignore syntax and takes precedence over `.gitignore` and
+/// `.ignore` files, so it can both exclude files that Git tracks (e.g.
+/// generated files) and re-include files that Git ignores (with `!`).
+pub const HURRY_IGNORE_FILE: &str = ".hurryignore";
+
+/// Walk source files in a directory recursively, respecting ignore files.
+///
+/// Files excluded by `.gitignore` (whether or not the directory is actually a
+/// Git repository), `.ignore`, or [`HURRY_IGNORE_FILE`] files are skipped, as
+/// are the `.git` directory and any of the `exclude` directories. Like
+/// [`walk_files`], only regular files are emitted.
+#[instrument]
+pub fn walk_source_files(
+    root: &AbsDirPath,
+    exclude: &[AbsDirPath],
+) -> impl Stream<Item = Result<AbsFilePath>> + Unpin {
+    let (tx, rx) = flume::bounded::<Result<AbsFilePath>>(0);
+    let root = root.clone();
+    let exclude = exclude.to_vec();
+
+    spawn_blocking(move || {
+        let walker = ignore::WalkBuilder::new(root.as_std_path())
+            .hidden(false)
+            .require_git(false)
+            .add_custom_ignore_filename(HURRY_IGNORE_FILE)
+            .filter_entry(move |entry| {
+                entry.file_name() != ".git"
+                    && !exclude.iter().any(|dir| entry.path() == dir.as_std_path())
+            })
+            .build();
+        for entry in walker {
+            let entry = match entry.with_context(|| format!("walk source files in {root:?}")) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    if let Err(send) = tx.send(Err(err)) {
+                        let err = send.into_inner();
+                        error!(error = ?err, "unable to walk source files");
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            if !entry.file_type().is_some_and(|ty| ty.is_file()) {
+                continue;
+            }
+
+            let path = match AbsFilePath::try_from(entry.path()) {
+                Ok(path) => path,
+                Err(err) => {
+                    if let Err(send) = tx.send(Err(err)) {
+                        let err = send.into_inner();
+                        error!(error = ?err, "unable to walk source files");
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            if let Err(send) = tx.send(Ok(path)) {
+                let err = send.into_inner();
+                error!(error = ?err, "unable to walk source files");
+                return;
+            }
+        }
+    });
+
+    rx.into_stream().pipe(Box::pin)
+}
+
 /// Report whether the provided directory is empty.
 /// For the purpose of this function, the directory is empty
 /// if it has no regular files.
@@ -290,7 +449,7 @@ pub async fn copy_file(src: &AbsFilePath, dst: &AbsFilePath) -> Result<u64> {
             .await
             .context("create parent directory")?;
     }
-    let bytes = tokio::fs::copy(src.as_std_path(), dst.as_std_path())
+    let bytes = tokio::fs::copy(os_path(src.as_std_path()), os_path(dst.as_std_path()))
         .await
         .context("copy file")?;
     trace!(?src, ?dst, ?bytes, "copy file");
@@ -301,7 +460,7 @@ pub async fn copy_file(src: &AbsFilePath, dst: &AbsFilePath) -> Result<u64> {
 /// Buffer the file content from disk.
 #[instrument]
 pub async fn read_buffered(path: &AbsFilePath) -> Result<Option<Vec<u8>>> {
-    match tokio::fs::read(path.as_std_path()).await {
+    match tokio::fs::read(os_path(path.as_std_path())).await {
         Ok(buf) => {
             trace!(?path, bytes = buf.len(), "read file");
             Ok(Some(buf))
@@ -316,7 +475,7 @@ pub async fn read_buffered(path: &AbsFilePath) -> Result<Option<Vec<u8>>> {
 /// doesn't exist.
 #[instrument]
 pub async fn must_read_buffered(path: &AbsFilePath) -> Result<Vec<u8>> {
-    tokio::fs::read(path.as_std_path())
+    tokio::fs::read(os_path(path.as_std_path()))
         .await
         .with_context(|| format!("read file: {path:?}"))
 }
@@ -324,7 +483,7 @@ pub async fn must_read_buffered(path: &AbsFilePath) -> Result<Vec<u8>> {
 /// Buffer the file content from disk and parse it as UTF8.
 #[instrument]
 pub async fn read_buffered_utf8(path: &AbsFilePath) -> Result<Option<String>> {
-    match tokio::fs::read_to_string(path.as_std_path()).await {
+    match tokio::fs::read_to_string(os_path(path.as_std_path())).await {
         Ok(buf) => {
             trace!(?path, bytes = buf.len(), "read file as string");
             Ok(Some(buf))
@@ -339,7 +498,7 @@ pub async fn read_buffered_utf8(path: &AbsFilePath) -> Result<Option<String>> {
 /// doesn't exist.
 #[instrument]
 pub async fn must_read_buffered_utf8(path: &AbsFilePath) -> Result<String> {
-    tokio::fs::read_to_string(path.as_std_path())
+    tokio::fs::read_to_string(os_path(path.as_std_path()))
         .await
         .with_context(|| format!("read file: {path:?}"))
 }
@@ -353,16 +512,39 @@ pub async fn write(path: &AbsFilePath, content: impl AsRef<[u8]>) -> Result<()>
             .await
             .context("create parent directory")?;
     }
-    tokio::fs::write(path.as_std_path(), content)
+    tokio::fs::write(os_path(path.as_std_path()), content)
         .await
         .with_context(|| format!("write file: {path:?}"))
         .tap_ok(|_| trace!(?path, bytes = content.len(), "write file"))
 }
 
+/// Write the provided file content to disk, readable only by the current
+/// user.
+///
+/// ## Windows
+///
+/// This function does not restrict the file's permissions on Windows, where
+/// files in the user's profile are already private to the user.
+#[instrument(skip(content))]
+pub async fn write_private(path: &AbsFilePath, content: impl AsRef<[u8]>) -> Result<()> {
+    write(path, content).await?;
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let permissions = std::fs::Permissions::from_mode(0o600);
+        tokio::fs::set_permissions(os_path(path.as_std_path()), permissions)
+            .await
+            .with_context(|| format!("set permissions: {path:?}"))?;
+    }
+    Ok(())
+}
+
 /// Open a file for reading.
 #[instrument]
 pub async fn open_file(path: &AbsFilePath) -> Result<tokio::fs::File> {
-    tokio::fs::File::open(path.as_std_path())
+    tokio::fs::File::open(os_path(path.as_std_path()))
         .await
         .with_context(|| format!("open file: {path:?}"))
         .tap_ok(|_| trace!(?path, "open file"))
@@ -371,7 +553,7 @@ pub async fn open_file(path: &AbsFilePath) -> Result<tokio::fs::File> {
 /// Open a file for writing.
 #[instrument]
 pub async fn create_file(path: &AbsFilePath) -> Result<tokio::fs::File> {
-    tokio::fs::File::create(path.as_std_path())
+    tokio::fs::File::create(os_path(path.as_std_path()))
         .await
         .with_context(|| format!("create file: {path:?}"))
         .tap_ok(|_| trace!(?path, "create file"))
@@ -380,7 +562,7 @@ pub async fn create_file(path: &AbsFilePath) -> Result<tokio::fs::File> {
 /// Remove a file.
 #[instrument]
 pub async fn remove_file(path: &AbsFilePath) -> Result<()> {
-    tokio::fs::remove_file(path.as_std_path())
+    tokio::fs::remove_file(os_path(path.as_std_path()))
         .await
         .with_context(|| format!("remove file: {path:?}"))
         .tap_ok(|_| trace!(?path, "remove file"))
@@ -389,7 +571,7 @@ pub async fn remove_file(path: &AbsFilePath) -> Result<()> {
 /// Rename a file or folder, overwriting the destination if it already exists.
 #[instrument]
 pub async fn rename<T>(src: &TypedPath<Abs, T>, dst: &TypedPath<Abs, T>) -> Result<()> {
-    tokio::fs::rename(src.as_std_path(), dst.as_std_path())
+    tokio::fs::rename(os_path(src.as_std_path()), os_path(dst.as_std_path()))
         .await
         .with_context(|| format!("rename file: {src:?} -> {dst:?}"))
         .tap_ok(|_| trace!(?src, ?dst, "rename file"))
@@ -398,7 +580,7 @@ pub async fn rename<T>(src: &TypedPath<Abs, T>, dst: &TypedPath<Abs, T>) -> Resu
 /// Read directory entries.
 #[instrument]
 pub async fn read_dir(path: &AbsDirPath) -> Result<ReadDir> {
-    tokio::fs::read_dir(path.as_std_path())
+    tokio::fs::read_dir(os_path(path.as_std_path()))
         .await
         .with_context(|| format!("read directory: {path:?}"))
         .tap_ok(|_| trace!(?path, "read directory"))
@@ -476,7 +658,7 @@ impl Metadata {
 
 /// Remove the directory and all its contents.
 pub async fn remove_dir_all(path: &AbsDirPath) -> Result<()> {
-    match tokio::fs::remove_dir_all(path.as_std_path()).await {
+    match tokio::fs::remove_dir_all(os_path(path.as_std_path())).await {
         Ok(()) => {
             trace!(?path, "removed directory");
             Ok(())
@@ -489,6 +671,164 @@ pub async fn remove_dir_all(path: &AbsDirPath) -> Result<()> {
     }
 }
 
+/// Report whether the file system containing `dir` treats paths that differ
+/// only in case as the same path.
+///
+/// This is checked by creating a probe file rather than by platform, since
+/// case sensitivity is configurable per volume on macOS and per directory on
+/// Windows.
+#[instrument]
+pub async fn is_case_insensitive(dir: &AbsDirPath) -> Result<bool> {
+    let name = format!(".hurry-case-probe-{}", Uuid::new_v4().simple());
+    let probe = dir.try_join_file(&name)?;
+    let folded = dir.try_join_file(name.to_uppercase())?;
+    write(&probe, b"").await?;
+    let insensitive = exists(folded.as_std_path()).await;
+    remove_file(&probe).await?;
+    Ok(insensitive)
+}
+
+/// How a file system stores mtimes, as probed by [`mtime_support`].
+///
+/// Cargo decides whether units are fresh by comparing mtimes, so restored
+/// files need mtimes that survive the file system they're written to. Some
+/// file systems store mtimes coarsely (FAT and exFAT in 2 second steps, some
+/// NFS servers in whole seconds), can't store mtimes before 1980 (FAT), or
+/// are served by a machine whose clock is ahead of this one (NFS, SMB), which
+/// makes files written there look newer than anything this machine restores.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MtimeSupport {
+    /// The file system truncates mtimes to a multiple of this.
+    pub granularity: Duration,
+
+    /// The earliest mtime the file system stores; earlier mtimes are clamped
+    /// to it.
+    pub earliest: SystemTime,
+
+    /// How far the file system's clock is ahead of this machine's, or zero if
+    /// it isn't.
+    pub clock_ahead: Duration,
+}
+
+impl Default for MtimeSupport {
+    fn default() -> Self {
+        Self {
+            granularity: Duration::from_nanos(1),
+            earliest: SystemTime::UNIX_EPOCH,
+            clock_ahead: Duration::ZERO,
+        }
+    }
+}
+
+impl MtimeSupport {
+    /// Mtimes that are this far apart stay distinct and ordered on every file
+    /// system; this is also the resolution Cargo is careful about.
+    pub const MIN_STEP: Duration = Duration::from_secs(1);
+
+    /// The smallest step between mtimes that keeps them distinct on the file
+    /// system.
+    pub fn step(&self) -> Duration {
+        self.granularity.max(Self::MIN_STEP)
+    }
+
+    /// The current time according to the file system's clock.
+    pub fn now(&self) -> SystemTime {
+        SystemTime::now() + self.clock_ahead
+    }
+
+    /// The mtime the file system stores for `mtime`, rounded up rather than
+    /// truncated so that it's never earlier than `mtime`.
+    pub fn normalize(&self, mtime: SystemTime) -> SystemTime {
+        let mtime = mtime.max(self.earliest);
+        let since_epoch = mtime
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let granularity = self.granularity.as_nanos().max(1);
+        let remainder = since_epoch.as_nanos() % granularity;
+        if remainder == 0 {
+            return mtime;
+        }
+        let padding = granularity - remainder;
+        mtime + Duration::from_nanos(padding as u64)
+    }
+}
+
+/// Probe how the file system containing `dir` stores mtimes.
+///
+/// Like [`is_case_insensitive`], this creates a probe file rather than going
+/// by platform, since the build directory can be on any file system.
+#[instrument]
+pub async fn mtime_support(dir: &AbsDirPath) -> Result<MtimeSupport> {
+    /// 1980-01-01T00:00:01.123456789Z, which every file system can store
+    /// the second of and none can store in the same step as 1980-01-01.
+    const PROBE: Duration = Duration::new(315_532_801, 123_456_789);
+    const GRANULARITIES: [Duration; 7] = [
+        Duration::from_nanos(1),
+        Duration::from_nanos(100),
+        Duration::from_micros(1),
+        Duration::from_millis(1),
+        Duration::from_millis(10),
+        Duration::from_secs(1),
+        Duration::from_secs(2),
+    ];
+
+    let name = format!(".hurry-mtime-probe-{}", Uuid::new_v4().simple());
+    let probe = dir.try_join_file(&name)?;
+    write(&probe, b"").await?;
+    let support = async {
+        // The file system sets the mtime of a new file with its own clock.
+        let created = read_mtime(&probe).await?;
+        let clock_ahead = created
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+
+        set_mtime(&probe, SystemTime::UNIX_EPOCH + PROBE).await?;
+        let stored = read_mtime(&probe)
+            .await?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let granularity = GRANULARITIES
+            .into_iter()
+            .find(|granularity| {
+                let granularity = granularity.as_nanos();
+                PROBE.as_nanos() / granularity * granularity == stored.as_nanos()
+            })
+            // A stored time that matches no granularity was rounded in some
+            // other way, so the coarsest granularity is the safe assumption.
+            .or_else(|| GRANULARITIES.last().copied())
+            .ok_or_eyre("no mtime granularities to probe")?;
+
+        // Some file systems clamp mtimes they can't store, others reject them;
+        // either way the probe time is the earliest known to work.
+        let earliest = match set_mtime(&probe, SystemTime::UNIX_EPOCH).await {
+            Ok(()) => read_mtime(&probe).await?.max(SystemTime::UNIX_EPOCH),
+            Err(_) => SystemTime::UNIX_EPOCH + Duration::from_secs(PROBE.as_secs() - 1),
+        };
+
+        Result::<_>::Ok(MtimeSupport {
+            granularity,
+            earliest,
+            clock_ahead,
+        })
+    }
+    .await;
+    remove_file(&probe).await?;
+    let support = support?;
+    if support != MtimeSupport::default() {
+        debug!(?dir, ?support, "file system stores mtimes coarsely");
+    }
+    Ok(support)
+}
+
+/// Read the mtime of the file.
+async fn read_mtime(path: &AbsFilePath) -> Result<SystemTime> {
+    metadata(path.as_std_path())
+        .await?
+        .ok_or_eyre("file does not exist")?
+        .modified()
+        .with_context(|| format!("read file {path:?} mtime"))
+}
+
 /// Get the standard metadata for the file.
 ///
 /// Note: you probably want [`Metadata::from_file`] instead,
@@ -499,7 +839,7 @@ pub async fn metadata(
     path: impl AsRef<std::path::Path> + StdDebug,
 ) -> Result<Option<std::fs::Metadata>> {
     let path = path.as_ref();
-    match tokio::fs::metadata(path).await {
+    match tokio::fs::metadata(os_path(path)).await {
         Ok(metadata) => {
             trace!(?path, ?metadata, "stat metadata");
             Ok(Some(metadata))
@@ -517,7 +857,9 @@ pub async fn metadata(
 /// just try to do the operation and handle the case of the file not existing.
 #[instrument]
 pub async fn exists(path: impl AsRef<std::path::Path> + StdDebug) -> bool {
-    tokio::fs::try_exists(path).await.is_ok_and(identity)
+    tokio::fs::try_exists(os_path(path.as_ref()))
+        .await
+        .is_ok_and(identity)
 }
 
 /// Check whether the file is executable.
@@ -528,7 +870,7 @@ pub async fn exists(path: impl AsRef<std::path::Path> + StdDebug) -> bool {
 /// just try to do the operation and handle the case of the file not existing.
 #[instrument]
 pub async fn is_executable(path: impl AsRef<std::path::Path> + StdDebug) -> bool {
-    let path = path.as_ref().to_path_buf();
+    let path = os_path(path.as_ref()).into_owned();
     spawn_blocking(move || is_executable::is_executable(path))
         .await
         .expect("join task")
@@ -538,7 +880,7 @@ pub async fn is_executable(path: impl AsRef<std::path::Path> + StdDebug) -> bool
 #[instrument]
 pub async fn set_mtime(path: &AbsFilePath, mtime: SystemTime) -> Result<()> {
     let mtime = FileTime::from_system_time(mtime);
-    let path = path.as_std_path().to_path_buf();
+    let path = os_path(path.as_std_path()).into_owned();
     spawn_blocking(move || {
         filetime::set_file_mtime(&path, mtime).tap_ok(|_| trace!(?path, ?mtime, "update mtime"))
     })
@@ -566,12 +908,12 @@ pub async fn set_executable(path: &AbsFilePath, executable: bool) -> Result<()>
     if executable {
         use std::os::unix::fs::PermissionsExt as _;
 
-        let metadata = tokio::fs::metadata(path.as_std_path())
+        let metadata = tokio::fs::metadata(os_path(path.as_std_path()))
             .await
             .context("get metadata")?;
         let mut permissions = metadata.permissions();
         permissions.set_mode(permissions.mode() | 0o111);
-        tokio::fs::set_permissions(path.as_std_path(), permissions.clone())
+        tokio::fs::set_permissions(os_path(path.as_std_path()), permissions.clone())
             .await
             .context("set permissions")
             .tap_ok(|_| trace!(?path, ?permissions, "set permissions"))?;
@@ -588,7 +930,7 @@ pub async fn hard_link(original: &AbsFilePath, link: &AbsFilePath) -> Result<()>
             .context("remove linked destination")?;
     }
 
-    tokio::fs::hard_link(original.as_std_path(), link.as_std_path())
+    tokio::fs::hard_link(os_path(original.as_std_path()), os_path(link.as_std_path()))
         .await
         .context(format!("hard link {original:?} -> {link:?}"))
 }
@@ -620,8 +962,8 @@ pub async fn is_file(path: impl AsRef<std::path::Path> + StdDebug) -> bool {
 /// Synchronously hash the contents of the file at the specified path.
 #[instrument]
 pub fn hash_file_sync(path: &AbsFilePath) -> Result<Key> {
-    let mut file =
-        std::fs::File::open(path.as_std_path()).with_context(|| format!("open file: {path}"))?;
+    let mut file = std::fs::File::open(os_path(path.as_std_path()))
+        .with_context(|| format!("open file: {path}"))?;
     let mut hasher = blake3::Hasher::new();
     let bytes = std::io::copy(&mut file, &mut hasher).context("hash file")?;
     let hash = hasher.finalize();
@@ -650,3 +992,187 @@ pub async fn hash_file(path: &AbsFilePath) -> Result<Key> {
     trace!(?path, hash = %key, ?bytes, "hash file");
     Ok(key)
 }
+
+/// Hash the contents of the files at the specified paths in parallel.
+///
+/// The hashes are returned in the same order as `paths`.
+#[instrument(skip(paths), fields(files = paths.len()))]
+pub async fn hash_files(paths: Vec<AbsFilePath>) -> Result<Vec<Key>> {
+    spawn_blocking(move || paths.par_iter().map(hash_file_sync).collect())
+        .await
+        .context("join background task")?
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt as _;
+    use itertools::Itertools as _;
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use simple_test_case::test_case;
+
+    use super::*;
+    use crate::path::TryJoinWith as _;
+
+    #[tokio::test]
+    async fn walk_source_files_respects_ignore_files() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let root = AbsDirPath::try_from(temp.path()).expect("temp dir is absolute");
+        let files = [
+            (".gitignore", "/generated\n*.log\n/vendored.rs\n"),
+            (".hurryignore", "/src/bindings.rs\n!/vendored.rs\n"),
+            ("Cargo.toml", ""),
+            ("build.log", ""),
+            ("vendored.rs", ""),
+            ("generated/out.rs", ""),
+            ("src/lib.rs", ""),
+            ("src/bindings.rs", ""),
+            ("target/debug/lib.rlib", ""),
+            (".git/HEAD", ""),
+        ];
+        for (path, content) in files {
+            let path = root.try_join_file(path).expect("join file");
+            write(&path, content).await.expect("write file");
+        }
+
+        let target = root.try_join_dir("target").expect("join dir");
+        let walked = walk_source_files(&root, &[target])
+            .try_collect::<Vec<_>>()
+            .await
+            .expect("walk source files")
+            .into_iter()
+            .map(|path| path.relative_to(&root).expect("relative path").to_string())
+            .sorted()
+            .collect::<Vec<_>>();
+        let expected = [
+            ".gitignore",
+            ".hurryignore",
+            "Cargo.toml",
+            "src/lib.rs",
+            "vendored.rs",
+        ]
+        .map(String::from)
+        .to_vec();
+        pretty_assert_eq!(walked, expected);
+    }
+
+    #[tokio::test]
+    async fn hash_files_preserves_order() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let root = AbsDirPath::try_from(temp.path()).expect("temp dir is absolute");
+        let mut paths = Vec::new();
+        for i in 0..64 {
+            let path = root.try_join_file(format!("{i}.txt")).expect("join file");
+            write(&path, i.to_string()).await.expect("write file");
+            paths.push(path);
+        }
+
+        let hashes = hash_files(paths.clone()).await.expect("hash files");
+        let mut expected = Vec::new();
+        for path in &paths {
+            expected.push(hash_file(path).await.expect("hash file"));
+        }
+        pretty_assert_eq!(hashes, expected);
+    }
+
+    #[tokio::test]
+    async fn try_lock_fails_while_held() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let root = AbsDirPath::try_from(temp.path()).expect("temp dir is absolute");
+        let path = root.try_join_file(".cargo-lock").expect("join file");
+
+        let held = LockFile::open(path.clone())
+            .await
+            .expect("open lock file")
+            .lock()
+            .await
+            .expect("lock file");
+        let waiting = LockFile::open(path)
+            .await
+            .expect("open lock file")
+            .try_lock()
+            .await
+            .expect("try to lock file")
+            .expect_err("lock is held");
+
+        held.unlock().await.expect("unlock file");
+        waiting
+            .try_lock()
+            .await
+            .expect("try to lock file")
+            .expect("lock is released");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn linux_is_case_sensitive() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = AbsDirPath::try_from(temp.path()).expect("temp dir is absolute");
+        let insensitive = is_case_insensitive(&dir)
+            .await
+            .expect("probe case sensitivity");
+        assert!(!insensitive, "linux file systems should be case sensitive");
+    }
+
+    #[tokio::test]
+    async fn probes_mtime_support() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = AbsDirPath::try_from(temp.path()).expect("temp dir is absolute");
+        let support = mtime_support(&dir).await.expect("probe mtime support");
+        assert!(support.granularity <= Duration::from_secs(2));
+        assert!(support.clock_ahead < Duration::from_secs(1));
+        pretty_assert_eq!(support.step(), MtimeSupport::MIN_STEP);
+    }
+
+    #[test]
+    fn normalizes_mtimes_for_coarse_file_systems() {
+        let fat_epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(315_532_800);
+        let fat = MtimeSupport {
+            granularity: Duration::from_secs(2),
+            earliest: fat_epoch,
+            clock_ahead: Duration::ZERO,
+        };
+        pretty_assert_eq!(fat.step(), Duration::from_secs(2));
+        pretty_assert_eq!(fat.normalize(SystemTime::UNIX_EPOCH), fat_epoch);
+        pretty_assert_eq!(
+            fat.normalize(fat_epoch + Duration::from_millis(2500)),
+            fat_epoch + Duration::from_secs(4)
+        );
+        pretty_assert_eq!(
+            fat.normalize(fat_epoch + Duration::from_secs(2)),
+            fat_epoch + Duration::from_secs(2)
+        );
+
+        let fine = MtimeSupport::default();
+        let now = SystemTime::now();
+        pretty_assert_eq!(fine.normalize(now), now);
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn windows_long_paths() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let root = AbsDirPath::try_from(temp.path()).expect("temp dir is absolute");
+        let dirs = std::iter::repeat_n("a".repeat(50), 6).collect::<Vec<_>>();
+        let path = root
+            .try_join_combined(&dirs, "file.txt")
+            .expect("join long path");
+        assert!(path.as_os_str().len() > WINDOWS_MAX_PATH);
+
+        write(&path, b"content").await.expect("write long path");
+        let content = must_read_buffered(&path).await.expect("read long path");
+        pretty_assert_eq!(content, b"content".to_vec());
+        remove_file(&path).await.expect("remove long path");
+    }
+
+    #[test_case(r"C:\foo\bar", Some(r"\\?\C:\foo\bar"); "drive")]
+    #[test_case("C:/foo/bar", Some(r"\\?\C:\foo\bar"); "drive with forward slashes")]
+    #[test_case(r"\\server\share\foo", Some(r"\\?\UNC\server\share\foo"); "unc")]
+    #[test_case(r"\\?\C:\foo", None; "already extended")]
+    #[test_case(r"\\.\pipe\foo", None; "device")]
+    #[test_case(r"C:\foo\..\bar", None; "parent component")]
+    #[test_case(r"foo\bar", None; "relative")]
+    #[test]
+    fn windows_extended_length(path: &str, expected: Option<&str>) {
+        pretty_assert_eq!(windows_extended_length_path(path).as_deref(), expected);
+    }
+}
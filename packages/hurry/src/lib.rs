@@ -4,11 +4,17 @@
 //! that configuration. It's only a library to enable sharing code in `hurry`
 //! with benchmarks and integration tests in the `hurry` repository.
 
+pub mod auth;
+pub mod buffers;
 pub mod cargo;
 pub mod cas;
+pub mod ci;
+pub mod config;
 pub mod cross;
 pub mod daemon;
 pub mod ext;
 pub mod fs;
 pub mod path;
 pub mod progress;
+pub mod state;
+pub mod support;
@@ -32,6 +32,14 @@
 //! This module supports both Unix and Windows paths. Paths are stored as-is
 //! without normalization, preserving the exact separators and format provided
 //! by the caller.
+//!
+//! ## Non-UTF-8 Paths
+//!
+//! Paths are not required to be valid UTF-8: some Linux systems and some
+//! third-party build scripts produce file names that aren't. Paths carry a
+//! `PathBuf` internally so that they round-trip losslessly through the file
+//! system, and are only converted lossily when explicitly requested (the
+//! `*_lossy` methods) or when serialized.
 
 use std::{
     any::type_name,
@@ -256,6 +264,17 @@ impl<B, T> TypedPath<B, T> {
         self.inner.as_os_str()
     }
 
+    /// View the path as a string, if it is valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        self.inner.to_str()
+    }
+
+    /// Report whether the path is valid UTF-8, and therefore survives
+    /// serialization unchanged.
+    pub fn is_utf8(&self) -> bool {
+        self.as_str().is_some()
+    }
+
     /// Get the parent of the provided path, if one exists.
     ///
     /// Unlike the standard library, this method returns `None`
@@ -413,12 +432,17 @@ impl<'de, B: Validator, T: Validator> Deserialize<'de> for TypedPath<B, T> {
     }
 }
 
+/// Paths are serialized as strings, lossily converting any non-UTF-8
+/// sequences to `U+FFFD REPLACEMENT CHARACTER` instead of failing.
+///
+/// Callers that need the deserialized path to match the original should check
+/// [`TypedPath::is_utf8`] first.
 impl<B, T> Serialize for TypedPath<B, T> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        self.inner.serialize(serializer)
+        serializer.serialize_str(&self.as_str_lossy())
     }
 }
 
@@ -973,6 +997,8 @@ impl Validator for ty_self {
 
 #[cfg(test)]
 mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+
     use super::*;
 
     #[test]
@@ -1092,4 +1118,29 @@ mod tests {
         let display = accepts_path_like(rel_file);
         assert!(!display.is_empty());
     }
+
+    #[test]
+    fn serialize_utf8() {
+        let rel_file = mk_rel_file!("src/main.rs");
+        assert!(rel_file.is_utf8());
+
+        let serialized = serde_json::to_string(&rel_file).expect("serialize");
+        pretty_assert_eq!(serialized, r#""src/main.rs""#);
+        let deserialized = serde_json::from_str::<RelFilePath>(&serialized).expect("deserialize");
+        pretty_assert_eq!(deserialized, rel_file);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serialize_non_utf8_lossily() {
+        use std::os::unix::ffi::OsStrExt as _;
+
+        let name = OsStr::from_bytes(b"out/gen\xff.rs");
+        let rel_file = RelFilePath::try_from(name).expect("relative path");
+        pretty_assert_eq!(rel_file.as_str(), None);
+        assert!(!rel_file.is_utf8());
+
+        let serialized = serde_json::to_string(&rel_file).expect("serialize");
+        pretty_assert_eq!(serialized, "\"out/gen\u{FFFD}.rs\"");
+    }
 }
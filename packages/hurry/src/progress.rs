@@ -12,6 +12,10 @@ use indicatif::{HumanDuration, ProgressBar, ProgressStyle};
 ///
 /// - In interactive terminals, displays a normal progress bar.
 /// - In non-interactive environments emits log lines every 5 seconds.
+///
+/// Progress is always written to stderr: stdout belongs to Cargo, and tools
+/// that run builds with `--message-format=json` parse it as a stream of JSON
+/// messages.
 #[derive(Clone, Debug, Display)]
 #[display("{}", self.inner)]
 #[debug("{}", self.inner)]
@@ -27,6 +31,16 @@ impl TransferBar {
         }
     }
 
+    /// Creates a transfer tracker that displays nothing.
+    ///
+    /// This is for progress that is reported somewhere else, like the daemon
+    /// reporting restore progress to the client that requested the restore.
+    pub fn hidden(items: u64) -> Self {
+        Self {
+            inner: Arc::new(TransferBarInner::hidden(items)),
+        }
+    }
+
     /// Increment the transferred file count and update the progress message.
     pub fn add_files(&self, count: u64) {
         self.inner.add_files(count);
@@ -47,6 +61,16 @@ impl TransferBar {
         self.inner.bytes()
     }
 
+    /// Get the current progress bar position.
+    pub fn position(&self) -> u64 {
+        self.inner.progress.position()
+    }
+
+    /// Get the current progress bar length.
+    pub fn length(&self) -> u64 {
+        self.inner.progress.length().unwrap_or(0)
+    }
+
     /// Increment the progress bar position.
     pub fn inc(&self, delta: u64) {
         self.inner.inc(delta);
@@ -57,6 +81,17 @@ impl TransferBar {
         self.inner.dec_length(delta);
     }
 
+    /// Show extra detail after the transfer statistics, e.g. the state of the
+    /// items in flight, or clear it with `None`.
+    pub fn set_detail(&self, detail: Option<String>) {
+        self.inner.set_detail(detail);
+    }
+
+    /// Hide the progress bar while running `f`, e.g. to prompt the user.
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.inner.progress.suspend(f)
+    }
+
     /// Finish the progress bar and display final statistics.
     ///
     /// This consumes the `TransferBar`, explicitly dropping it and triggering
@@ -74,8 +109,10 @@ struct TransferBarInner {
     operation: String,
     files: Arc<AtomicU64>,
     bytes: Arc<AtomicU64>,
+    detail: Mutex<Option<String>>,
     handle: Option<JoinHandle<()>>,
     signal: Option<Arc<StopSignal>>,
+    hidden: bool,
 }
 
 impl TransferBarInner {
@@ -100,8 +137,10 @@ impl TransferBarInner {
                 operation,
                 files: transferred_files,
                 bytes: transferred_bytes,
+                detail: Mutex::new(None),
                 handle: None,
                 signal: None,
+                hidden: false,
             }
         } else {
             let signal = StopSignal::new();
@@ -110,7 +149,7 @@ impl TransferBarInner {
                 let signal = signal.clone();
                 move || {
                     loop {
-                        println!("{}", Self::render_plain(start, &progress));
+                        eprintln!("{}", Self::render_plain(start, &progress));
                         if signal.wait_timeout(Duration::from_secs(5)) {
                             break;
                         }
@@ -126,12 +165,30 @@ impl TransferBarInner {
                 operation,
                 files: transferred_files,
                 bytes: transferred_bytes,
+                detail: Mutex::new(None),
                 handle: Some(handle),
                 signal: Some(signal),
+                hidden: false,
             }
         }
     }
 
+    fn hidden(items: u64) -> Self {
+        let progress = ProgressBar::hidden();
+        progress.set_length(items);
+        Self {
+            progress,
+            start: Instant::now(),
+            operation: String::new(),
+            files: Arc::new(AtomicU64::new(0)),
+            bytes: Arc::new(AtomicU64::new(0)),
+            detail: Mutex::new(None),
+            handle: None,
+            signal: None,
+            hidden: true,
+        }
+    }
+
     fn add_files(&self, count: u64) {
         self.files.fetch_add(count, Ordering::Relaxed);
         self.update_message();
@@ -150,16 +207,25 @@ impl TransferBarInner {
         self.bytes.load(Ordering::Relaxed)
     }
 
+    fn set_detail(&self, detail: Option<String>) {
+        *self.detail.lock().expect("mutex is poisoned") = detail;
+        self.update_message();
+    }
+
     fn update_message(&self) {
         let files = self.files.load(Ordering::Relaxed);
         let bytes = self.bytes.load(Ordering::Relaxed);
-        self.progress.set_message(format!(
+        let mut message = format!(
             "{} ({} files, {} at {})",
             self.operation,
             files,
             format_size(bytes),
             format_transfer_rate(bytes, self.start)
-        ));
+        );
+        if let Some(detail) = self.detail.lock().expect("mutex is poisoned").as_deref() {
+            message.push_str(&format!(" [{detail}]"));
+        }
+        self.progress.set_message(message);
     }
 
     fn render_plain(start: Instant, progress: &ProgressBar) -> String {
@@ -197,6 +263,10 @@ impl std::fmt::Display for TransferBarInner {
 
 impl Drop for TransferBarInner {
     fn drop(&mut self) {
+        if self.hidden {
+            return;
+        }
+
         let files = self.files();
         let bytes = self.bytes();
         let message = format!(
@@ -213,7 +283,7 @@ impl Drop for TransferBarInner {
             let elapsed = HumanDuration(self.start.elapsed());
             let pos = self.progress.position();
             let len = self.progress.length().unwrap_or(0);
-            println!("[{elapsed}] [{pos}/{len}] {message}");
+            eprintln!("[{elapsed}] [{pos}/{len}] {message}");
         }
 
         if let Some(signal) = &self.signal {
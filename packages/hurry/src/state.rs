@@ -0,0 +1,233 @@
+//! Hurry's per-workspace state.
+//!
+//! State that belongs to a workspace rather than to the machine (e.g. records
+//! of previous invocations) lives in `hurry` inside the workspace's build
+//! directory by default. That keeps it out of version control without any
+//! setup, but it also means it's lost whenever the build directory is wiped,
+//! which some teams do aggressively.
+//!
+//! Setting `dir` under `[state]` in `hurry.toml` moves the state elsewhere,
+//! so that only files Cargo owns are left in the build directory:
+//!
+//! ```toml
+//! [state]
+//! dir = ".hurry/state"
+//! ```
+//!
+//! Relative paths are relative to the workspace root. Setting `shared`
+//! instead keeps state in the user's cache directory under the workspace's
+//! [`WorkspaceIdentity`], so that worktrees and other checkouts of the same
+//! repository share it.
+//!
+//! When the state directory is moved, existing state is migrated from the
+//! build directory the first time it's used. If the configured directory
+//! can't be created (e.g. because it's on a read-only mount), Hurry falls
+//! back to the build directory rather than failing.
+
+use color_eyre::{Result, eyre::Context as _};
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    cargo::{Workspace, WorkspaceIdentity},
+    config::{HurryConfig, StateConfig},
+    fs, mk_rel_dir, mk_rel_file,
+    path::{AbsDirPath, AbsFilePath, JoinWith as _, TryJoinWith as _},
+};
+
+/// Ignores everything in a state directory that isn't in the build
+/// directory, the same way Cargo ignores its build directory.
+const GITIGNORE: &str = "# Created by hurry\n*\n";
+
+/// Resolve the state directory of the workspace, creating it if it doesn't
+/// exist.
+#[instrument(skip(ws), fields(root = %ws.root))]
+pub async fn dir(ws: &Workspace) -> Result<AbsDirPath> {
+    let config = HurryConfig::load(&ws.root)
+        .await
+        .context("load hurry config")?;
+    if !config.state.shared {
+        return resolve(&ws.root, &ws.build_dir, &config.state).await;
+    }
+    let identity = WorkspaceIdentity::resolve(&ws.root, &config.workspace).await;
+    let shared = fs::user_global_cache_path()
+        .await?
+        .try_join_dirs(["workspaces", identity.as_str()])?;
+    resolve_dir(&ws.build_dir, Some(shared)).await
+}
+
+/// Resolve the state directory for the workspace at `root` whose build
+/// directory is `build_dir`, creating it if it doesn't exist.
+///
+/// This migrates state from the build directory into the configured
+/// directory if there's any there.
+#[instrument]
+pub async fn resolve(
+    root: &AbsDirPath,
+    build_dir: &AbsDirPath,
+    config: &StateConfig,
+) -> Result<AbsDirPath> {
+    resolve_dir(build_dir, configured_dir(root, config)?).await
+}
+
+/// Resolve the state directory for a workspace whose build directory is
+/// `build_dir`, preferring the `configured` directory if there is one.
+async fn resolve_dir(build_dir: &AbsDirPath, configured: Option<AbsDirPath>) -> Result<AbsDirPath> {
+    let default = build_dir.join(mk_rel_dir!("hurry"));
+    let Some(configured) = configured else {
+        fs::create_dir_all(&default).await?;
+        return Ok(default);
+    };
+    if configured == default {
+        fs::create_dir_all(&default).await?;
+        return Ok(default);
+    }
+
+    if let Err(err) = prepare(&configured).await {
+        warn!(
+            ?err,
+            ?configured,
+            "failed to create configured state directory, using build directory"
+        );
+        fs::create_dir_all(&default).await?;
+        return Ok(default);
+    }
+    if let Err(err) = migrate(&default, &configured).await {
+        // The old state is only an optimization, so Hurry can continue
+        // without it; it stays where it is so that nothing is lost.
+        warn!(?err, from = ?default, to = ?configured, "failed to migrate state");
+    }
+    Ok(configured)
+}
+
+/// The state directory set in the configuration, if any.
+fn configured_dir(root: &AbsDirPath, config: &StateConfig) -> Result<Option<AbsDirPath>> {
+    let Some(dir) = &config.dir else {
+        return Ok(None);
+    };
+    let dir = root.as_std_path().join(dir);
+    AbsDirPath::try_from(dir)
+        .context("parse configured state directory")
+        .map(Some)
+}
+
+/// Create a state directory outside the build directory.
+async fn prepare(dir: &AbsDirPath) -> Result<()> {
+    fs::create_dir_all(dir).await?;
+    let gitignore = dir.join(mk_rel_file!(".gitignore"));
+    if !fs::exists(gitignore.as_std_path()).await {
+        fs::write(&gitignore, GITIGNORE).await?;
+    }
+    Ok(())
+}
+
+/// Move state from `from` into `to`.
+///
+/// Entries that already exist in `to` are newer than the ones in `from`,
+/// since `to` is only written after migrating, so they're kept.
+async fn migrate(from: &AbsDirPath, to: &AbsDirPath) -> Result<()> {
+    if !fs::is_dir(from.as_std_path()).await {
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(from).await?;
+    let mut migrated = 0usize;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let source = from.as_std_path().join(&name);
+        let destination = to.as_std_path().join(&name);
+        if fs::exists(&destination).await {
+            debug!(?source, "skipping migration of state that already exists");
+            continue;
+        }
+
+        // Renaming fails when the directories are on different devices, in
+        // which case the entry is copied instead.
+        if entry.file_type().await?.is_dir() {
+            let source = AbsDirPath::try_from(source)?;
+            let destination = AbsDirPath::try_from(destination)?;
+            if fs::rename(&source, &destination).await.is_err() {
+                fs::copy_dir(&source, &destination).await?;
+            }
+        } else {
+            let source = AbsFilePath::try_from(source)?;
+            let destination = AbsFilePath::try_from(destination)?;
+            if fs::rename(&source, &destination).await.is_err() {
+                fs::copy_file(&source, &destination).await?;
+            }
+        }
+        migrated += 1;
+    }
+
+    fs::remove_dir_all(from).await?;
+    debug!(?from, ?to, migrated, "migrated state");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq as pretty_assert_eq;
+    use tempfile::TempDir;
+
+    use super::resolve;
+    use crate::{
+        config::StateConfig,
+        fs, mk_rel_dir, mk_rel_file,
+        path::{AbsDirPath, JoinWith as _},
+    };
+
+    fn workspace() -> (TempDir, AbsDirPath, AbsDirPath) {
+        let temp = TempDir::new().unwrap();
+        let root = AbsDirPath::try_from(temp.path().to_path_buf()).unwrap();
+        let build_dir = root.join(mk_rel_dir!("target"));
+        (temp, root, build_dir)
+    }
+
+    #[tokio::test]
+    async fn defaults_to_build_dir() {
+        let (_temp, root, build_dir) = workspace();
+        let dir = resolve(&root, &build_dir, &StateConfig::default())
+            .await
+            .unwrap();
+        pretty_assert_eq!(dir, build_dir.join(mk_rel_dir!("hurry")));
+        assert!(fs::is_dir(dir.as_std_path()).await);
+    }
+
+    #[tokio::test]
+    async fn migrates_from_build_dir() {
+        let (_temp, root, build_dir) = workspace();
+        let old = build_dir.join(mk_rel_dir!("hurry"));
+        fs::write(&old.join(mk_rel_file!("record.json")), "old")
+            .await
+            .unwrap();
+        fs::write(&old.join(mk_rel_file!("kept.json")), "old")
+            .await
+            .unwrap();
+
+        let new = root.join(mk_rel_dir!(".hurry/state"));
+        fs::write(&new.join(mk_rel_file!("kept.json")), "new")
+            .await
+            .unwrap();
+
+        let config = StateConfig {
+            dir: Some(String::from(".hurry/state")),
+            shared: false,
+        };
+        let dir = resolve(&root, &build_dir, &config).await.unwrap();
+        pretty_assert_eq!(dir, new);
+
+        let read = |file| {
+            let path = dir.join(file);
+            async move { fs::read_buffered_utf8(&path).await.unwrap() }
+        };
+        pretty_assert_eq!(
+            read(mk_rel_file!("record.json")).await.as_deref(),
+            Some("old")
+        );
+        pretty_assert_eq!(
+            read(mk_rel_file!("kept.json")).await.as_deref(),
+            Some("new")
+        );
+        assert!(read(mk_rel_file!(".gitignore")).await.is_some());
+        assert!(!fs::exists(old.as_std_path()).await);
+    }
+}